@@ -0,0 +1,413 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, bail};
+use clap::{Parser, Subcommand};
+use tabletd::{
+    config::Config,
+    control::dbus::ControlProxy,
+    event_dispatcher::api::{ApiServer, protocol::ServerMessage},
+    event_model::tablet::TabletId,
+    input_devices::remote::RemoteClient,
+    mapping::{MappingConfig, MappingTarget, calibration::CALIBRATION_TARGETS},
+    profile::binding::{Action, Binding, ProfileAssignment},
+};
+use tokio::net::UnixStream;
+
+/// Control a running tabletd
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// 列出已连接的数位板
+    List,
+    /// 把数位板映射到一个显示器, `desktop` 表示整个桌面. 只在本次运行中有效
+    Map {
+        /// 数位板 ID, 见 `tabletctl list`
+        #[arg(value_parser = parse_tablet)]
+        tablet: u32,
+        output: String,
+    },
+    /// 绑定按键. 只在本次运行中有效
+    ///
+    /// 动作: close-window、toggle-hud、toggle-history、toggle-diagnostics、toggle-mapping、
+    /// keys:<组合键>、run:<命令>、volume:<百分比>、scroll:<格数>、profile:<设置名称>、
+    /// profiles:<ID>=<设置名称>,<ID>=<设置名称>...
+    Bind {
+        button: u8,
+        #[arg(value_parser = parse_action)]
+        action: Action,
+        /// 数位板 ID, 只连接了一块数位板时可以省略
+        #[arg(long, value_parser = parse_tablet)]
+        tablet: Option<u32>,
+        /// 只在这个模式组生效
+        #[arg(long)]
+        bank: Option<u8>,
+        /// 执行前需要在 HUD 上确认
+        #[arg(long)]
+        confirm: bool,
+    },
+    /// 同时切换多块数位板的设置, 例如 `tabletctl switch 1=副屏 2=按键`. 任何一块失败时都不切换.
+    /// 只在本次运行中有效
+    Switch {
+        #[arg(value_parser = parse_assignment, required = true)]
+        profiles: Vec<(u32, String)>,
+        /// 在 HUD 上显示的名称
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// 显示 overlay 连接的合成器, 或者在 tabletd 等待选择时指定一个
+    Display { name: Option<String> },
+    /// 显示驱动统计的报告率、抖动、断档和重连次数
+    Stats,
+    /// 显示事件从读到报告到离开路由器、再到写入虚拟数位板的延迟
+    Latency,
+    /// 校准数位屏的映射: HUD 依次显示目标点, 用笔点击它们. 数位板使用已保存的设置时
+    /// 结果同时保存到设置中
+    Calibrate {
+        /// 数位板 ID, 只连接了一块数位板时可以省略
+        #[arg(value_parser = parse_tablet)]
+        tablet: Option<u32>,
+        /// 取消正在进行的校准
+        #[arg(long, conflicts_with_all = ["tablet", "reset"])]
+        cancel: bool,
+        /// 清除校准, 只在本次运行中有效
+        #[arg(long)]
+        reset: bool,
+    },
+    /// 显示或修改 tabletd 的日志过滤规则(语法同 `RUST_LOG`), 只在本次运行中有效.
+    /// 例如 `tabletctl log info,tabletd::hid=trace` 输出原始 HID 报告
+    Log { filter: Option<String> },
+    /// 持续显示数位板事件, 按 Ctrl+C 退出
+    Monitor {
+        /// `tabletd API` 的 Unix socket, 默认使用配置文件中的 `[api] unix`
+        #[arg(long)]
+        socket: Option<PathBuf>,
+    },
+}
+
+/// 接受 `3` 和 `tablet-3`
+fn parse_tablet(text: &str) -> Result<u32, String> {
+    text.strip_prefix("tablet-")
+        .unwrap_or(text)
+        .parse()
+        .map_err(|_| format!("无效的数位板 ID: {text}"))
+}
+
+/// `<数位板 ID>=<设置名称>`
+fn parse_assignment(text: &str) -> Result<(u32, String), String> {
+    let Some((tablet, profile)) = text.split_once('=') else {
+        return Err(format!("需要 <数位板 ID>=<设置名称>: {text}"));
+    };
+    Ok((parse_tablet(tablet)?, profile.to_string()))
+}
+
+fn parse_action(text: &str) -> Result<Action, String> {
+    let (name, argument) = match text.split_once(':') {
+        Some((name, argument)) => (name, Some(argument)),
+        None => (text, None),
+    };
+    let number = |argument: &str| {
+        argument
+            .parse()
+            .map_err(|_| format!("{name} 需要一个数字: {argument}"))
+    };
+    let action = match (name, argument) {
+        ("close-window", None) => Action::CloseWindow,
+        ("toggle-hud", None) => Action::ToggleHud,
+        ("toggle-history", None) => Action::ToggleHistory,
+        ("toggle-diagnostics", None) => Action::ToggleDiagnostics,
+        ("toggle-mapping", None) => Action::ToggleMappingOverlay,
+        ("keys", Some(keys)) => Action::Keys {
+            keys: keys.to_string(),
+        },
+        ("run", Some(command)) => Action::RunCommand {
+            command: command.to_string(),
+        },
+        ("volume", Some(step)) => Action::Volume {
+            step: number(step)?,
+        },
+        ("scroll", Some(amount)) => Action::Scroll {
+            amount: number(amount)?,
+        },
+        ("profile", Some(profile)) => Action::SwitchProfile {
+            profile: profile.to_string(),
+        },
+        ("profiles", Some(profiles)) => Action::SwitchProfiles {
+            name: None,
+            profiles: profiles
+                .split(',')
+                .map(|text| {
+                    parse_assignment(text).map(|(tablet, profile)| ProfileAssignment {
+                        tablet: TabletId(tablet),
+                        profile,
+                    })
+                })
+                .collect::<Result<_, _>>()?,
+        },
+        _ => return Err(format!("无效的动作: {text}")),
+    };
+    Ok(action)
+}
+
+async fn control() -> anyhow::Result<ControlProxy<'static>> {
+    let connection = zbus::Connection::session()
+        .await
+        .context("无法连接会话总线")?;
+    Ok(ControlProxy::new(&connection).await?)
+}
+
+async fn list() -> anyhow::Result<()> {
+    let tablets = control()
+        .await?
+        .list_tablets()
+        .await
+        .context("tabletd 没有运行?")?;
+    if tablets.is_empty() {
+        println!("没有已连接的数位板");
+    }
+    for (id, name, transport, profile) in tablets {
+        let profile = if profile.is_empty() {
+            "默认设置".to_string()
+        } else {
+            profile
+        };
+        println!("tablet-{id}\t{name}\t{transport}\t{profile}");
+    }
+    Ok(())
+}
+
+async fn map(tablet: u32, output: String) -> anyhow::Result<()> {
+    let control = control().await?;
+    let mapping = control.get_mapping(tablet).await?;
+    let mut mapping: MappingConfig = toml::from_str(&mapping)?;
+    mapping.target = if output == "desktop" {
+        MappingTarget::Desktop
+    } else {
+        MappingTarget::Output { name: output }
+    };
+    control
+        .set_mapping(tablet, &toml::to_string(&mapping)?)
+        .await?;
+    Ok(())
+}
+
+/// 没有指定数位板时使用唯一连接的一块
+async fn only_tablet(control: &ControlProxy<'_>, tablet: Option<u32>) -> anyhow::Result<u32> {
+    Ok(match tablet {
+        Some(tablet) => tablet,
+        None => match control.list_tablets().await?.as_slice() {
+            [(id, ..)] => *id,
+            [] => bail!("没有已连接的数位板"),
+            _ => bail!("连接了多块数位板, 需要指定数位板 ID"),
+        },
+    })
+}
+
+async fn bind(tablet: Option<u32>, binding: Binding) -> anyhow::Result<()> {
+    let control = control().await?;
+    let tablet = only_tablet(&control, tablet).await?;
+    control
+        .set_binding(tablet, &toml::to_string(&binding)?)
+        .await?;
+    println!(
+        "tablet-{tablet} 的按键 {}: {}",
+        binding.button,
+        binding.action.describe()
+    );
+    Ok(())
+}
+
+async fn switch(name: Option<String>, profiles: Vec<(u32, String)>) -> anyhow::Result<()> {
+    let assignments: Vec<_> = profiles
+        .iter()
+        .map(|(tablet, profile)| (*tablet, profile.as_str()))
+        .collect();
+    control()
+        .await?
+        .switch_profiles(name.as_deref().unwrap_or_default(), &assignments)
+        .await?;
+    for (tablet, profile) in profiles {
+        println!("tablet-{tablet}: {profile}");
+    }
+    Ok(())
+}
+
+async fn display(name: Option<String>) -> anyhow::Result<()> {
+    let control = control().await?;
+    if let Some(name) = name {
+        control.select_display(&name).await?;
+        println!("overlay 将连接 {name}");
+        return Ok(());
+    }
+    let (current, pending) = control.list_displays().await?;
+    if !pending.is_empty() {
+        println!("等待选择合成器, 用 `tabletctl display <名称>` 选择:");
+        for (name, layer_shell) in pending {
+            let usable = if layer_shell {
+                ""
+            } else {
+                " (不支持 wlr-layer-shell)"
+            };
+            println!("  {name}{usable}");
+        }
+    } else if current.is_empty() {
+        println!("overlay 使用 WAYLAND_DISPLAY, 或者还没有连接合成器");
+    } else {
+        println!("overlay 已连接 {current}");
+    }
+    Ok(())
+}
+
+async fn stats() -> anyhow::Result<()> {
+    let stats = control()
+        .await?
+        .report_stats()
+        .await
+        .context("tabletd 没有运行?")?;
+    if stats.is_empty() {
+        println!("还没有连接过数位板");
+    }
+    for (id, name, reports, rate, jitter, gaps, resets) in stats {
+        println!(
+            "tablet-{id}\t{name}\t{reports} 个报告\t{rate:.0} Hz\t抖动 {jitter:.2} ms\t断档 {gaps}\t重连 {resets}"
+        );
+    }
+    Ok(())
+}
+
+async fn latency() -> anyhow::Result<()> {
+    let stages = control()
+        .await?
+        .latency()
+        .await
+        .context("tabletd 没有运行?")?;
+    for (stage, count, mean, p50, p99, max) in stages {
+        if count == 0 {
+            println!("{stage}\t还没有事件");
+            continue;
+        }
+        println!(
+            "{stage}\t{count} 个事件\t平均 {mean:.2} ms\t中位数 ≤{p50:.2} ms\t99% ≤{p99:.2} ms\t最大 {max:.2} ms"
+        );
+    }
+    Ok(())
+}
+
+async fn calibrate(tablet: Option<u32>, cancel: bool, reset: bool) -> anyhow::Result<()> {
+    let control = control().await?;
+    if cancel {
+        control.cancel_calibration().await?;
+        println!("已取消校准");
+        return Ok(());
+    }
+    let tablet = only_tablet(&control, tablet).await?;
+    if reset {
+        let mut mapping: MappingConfig = toml::from_str(&control.get_mapping(tablet).await?)?;
+        mapping.calibration = None;
+        control
+            .set_mapping(tablet, &toml::to_string(&mapping)?)
+            .await?;
+        println!("已清除 tablet-{tablet} 的校准");
+        return Ok(());
+    }
+    control.calibrate(tablet).await?;
+    println!(
+        "用笔依次点击 HUD 上显示的 {} 个目标点",
+        CALIBRATION_TARGETS.len()
+    );
+    Ok(())
+}
+
+async fn log(filter: Option<String>) -> anyhow::Result<()> {
+    let control = control().await?;
+    let filter = match filter {
+        Some(filter) => control.set_log_filter(&filter).await?,
+        None => control.log_filter().await.context("tabletd 没有运行?")?,
+    };
+    println!("{filter}");
+    Ok(())
+}
+
+async fn monitor(socket: Option<PathBuf>) -> anyhow::Result<()> {
+    let socket = match socket {
+        Some(socket) => socket,
+        None => Config::load(&Config::default_path())
+            .map(|config| config.api.unix)
+            .unwrap_or_default()
+            .unwrap_or_else(ApiServer::default_socket_path),
+    };
+    let stream = UnixStream::connect(&socket)
+        .await
+        .with_context(|| format!("无法连接 {}", socket.display()))?;
+    let mut client = RemoteClient::connect(stream).await?;
+    for tablet in &client.handshake().tablets {
+        println!("{} {}", tablet.id, tablet.name);
+    }
+    while let Some(message) = client.recv().await? {
+        match message {
+            ServerMessage::Event(event) => {
+                let position = event
+                    .position
+                    .map(|(x, y)| format!(" ({x:.4}, {y:.4})"))
+                    .unwrap_or_default();
+                let consumed = if event.consumed { " [已消费]" } else { "" };
+                println!(
+                    "{} #{} {:?}{position}{consumed}",
+                    event.tablet, event.stamp.sequence, event.event
+                );
+            }
+            ServerMessage::TabletAdded(tablet) => println!("接入 {} {}", tablet.id, tablet.name),
+            ServerMessage::TabletRemoved(tablet) => println!("断开 {tablet}"),
+            ServerMessage::Error(report) => {
+                let recoverable = if report.recoverable {
+                    ""
+                } else {
+                    " [无法自动恢复]"
+                };
+                println!("错误 {:?}{recoverable}: {}", report.kind, report.message);
+            }
+            _ => {}
+        }
+    }
+    println!("tabletd 关闭了连接");
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    match Cli::parse().command {
+        Command::List => list().await,
+        Command::Map { tablet, output } => map(tablet, output).await,
+        Command::Bind {
+            button,
+            action,
+            tablet,
+            bank,
+            confirm,
+        } => {
+            let binding = Binding {
+                button,
+                bank,
+                action,
+                confirm,
+            };
+            bind(tablet, binding).await
+        }
+        Command::Switch { profiles, name } => switch(name, profiles).await,
+        Command::Display { name } => display(name).await,
+        Command::Stats => stats().await,
+        Command::Latency => latency().await,
+        Command::Calibrate {
+            tablet,
+            cancel,
+            reset,
+        } => calibrate(tablet, cancel, reset).await,
+        Command::Log { filter } => log(filter).await,
+        Command::Monitor { socket } => monitor(socket).await,
+    }
+}
@@ -0,0 +1,415 @@
+//! 守护进程的配置文件
+//!
+//! 一个 TOML 文件描述所有数位板的设置(映射、压感曲线、光标颜色、绑定等)、按笔识别的用户、
+//! 按聚焦的应用叠加的设置、
+//! `tabletd API` 监听的地址、命令的执行规则和哪些显示器需要 overlay. 文件修改后由 [`watcher`]
+//! 重新加载，通过 [`ConfigBus`] 通知各子系统，子系统用 [`ConfigChange`] 找出和自己有关的变化
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+use transaction::ConfigStage;
+
+use crate::{
+    daemon::DaemonConfig,
+    event_dispatcher::exec::ExecPolicy,
+    event_model::{event::ToolId, tablet::TabletId},
+    event_router::{
+        bindings::{BindingTable, TabletBindings},
+        pressure::PressureCurves,
+    },
+    profile::{Profile, app::AppProfile, layers, storage::ProfileStorage},
+    screen_overlay::selection::OverlayConfig,
+    units::UnitConfig,
+};
+
+/// 事务式地应用配置
+pub mod transaction;
+/// 监视配置文件，修改后重新加载
+pub mod watcher;
+
+/// 配置文件的内容
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// 没有单独设置的数位板使用的设置
+    pub defaults: Profile,
+    /// 每块数位板的设置, 在文件中写作 `[[tablet]]`
+    #[serde(rename = "tablet")]
+    pub tablets: Vec<TabletConfig>,
+    /// 按笔识别的用户, 在文件中写作 `[[user]]`
+    #[serde(rename = "user")]
+    pub users: Vec<UserConfig>,
+    /// 应用获得焦点时叠加在所有数位板设置上的修改, 在文件中写作 `[[app]]`
+    #[serde(rename = "app")]
+    pub apps: Vec<AppProfile>,
+    /// 和数位板编为一组的按键设备, 在文件中写作 `[[keypad]]`
+    #[serde(rename = "keypad")]
+    pub keypads: Vec<KeypadConfig>,
+    /// `tabletd API` 监听的地址
+    pub api: ApiConfig,
+    /// 绑定中命令的执行规则
+    pub exec: ExecPolicy,
+    /// 哪些显示器需要 overlay
+    pub overlay: OverlayConfig,
+    /// 数值的单位和地区格式
+    pub units: UnitConfig,
+    /// 运行哪些子系统, 修改后需要重启
+    pub daemon: DaemonConfig,
+}
+
+/// 一块数位板的设置
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TabletConfig {
+    pub id: TabletId,
+    #[serde(flatten)]
+    pub profile: Profile,
+}
+
+/// 一位用户的设置
+///
+/// 多人共用数位板时，用户的笔进入感应范围后，当前数位板改用这里的压感曲线和绑定
+/// (按键、滚轮和滚轮预设), 其他设置仍然是数位板自己的
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserConfig {
+    /// 用户的笔的序列号, 例如 `pens = [0x0a1b2c3d]`. 未登记的笔接近时日志中会打印序列号
+    pub pens: Vec<ToolId>,
+    #[serde(flatten)]
+    pub profile: Profile,
+}
+
+/// 一个按键设备所在的组
+///
+/// 编组后按键设备使用数位板的按键和滚轮绑定: 数位板上编号从 `first_button` 开始的按键绑定
+/// 依次对应按键设备的按键 0、1、..., 避免和数位板自己的快捷键冲突
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeypadConfig {
+    /// 按键设备的 ID, 见 `tabletctl list`
+    pub id: TabletId,
+    /// 编入的数位板
+    pub tablet: TabletId,
+    #[serde(default = "default_first_button")]
+    pub first_button: u8,
+}
+
+fn default_first_button() -> u8 {
+    32
+}
+
+/// `tabletd API` 的监听地址
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApiConfig {
+    /// Unix socket 路径, 不设置时不监听
+    pub unix: Option<PathBuf>,
+    /// TCP 地址, 例如 `0.0.0.0:7520`. TCP 没有访问控制, 只应该在可信的网络上使用
+    pub tcp: Option<String>,
+    /// 设置后允许另一台 tabletd 同步设置([`crate::profile::sync`]), 对方要先发送这个口令
+    pub sync_token: Option<String>,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            unix: Some(crate::event_dispatcher::api::ApiServer::default_socket_path()),
+            tcp: None,
+            sync_token: None,
+        }
+    }
+}
+
+impl Config {
+    /// 默认路径: `$XDG_CONFIG_HOME/tabletd/config.toml`
+    pub fn default_path() -> PathBuf {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .unwrap_or_else(std::env::temp_dir)
+            .join("tabletd")
+            .join("config.toml")
+    }
+
+    /// 读取配置文件, 文件不存在时使用默认配置
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("无法读取 {}", path.display())),
+        };
+        toml::from_str(&text).with_context(|| format!("{}", path.display()))
+    }
+
+    /// 展开设置中的 `extends`, 被继承的设置从 `storage` 中读取
+    pub fn resolve<S>(&mut self, storage: &S) -> anyhow::Result<()>
+    where
+        S: ProfileStorage + ?Sized,
+    {
+        let profiles = std::iter::once(&mut self.defaults)
+            .chain(self.tablets.iter_mut().map(|tablet| &mut tablet.profile))
+            .chain(self.users.iter_mut().map(|user| &mut user.profile));
+        for profile in profiles {
+            let Some(base) = profile.extends.clone() else {
+                continue;
+            };
+            let mut table = layers::resolve_table(storage, &base)?
+                .with_context(|| format!("找不到继承的设置 {base}"))?;
+            layers::merge(&mut table, &toml::Table::try_from(&*profile)?);
+            *profile = table.try_into()?;
+        }
+        Ok(())
+    }
+
+    /// 某块数位板单独的设置
+    pub fn tablet(&self, tablet: TabletId) -> Option<&Profile> {
+        self.tablets
+            .iter()
+            .find(|config| config.id == tablet)
+            .map(|config| &config.profile)
+    }
+
+    /// 某块数位板使用的设置
+    pub fn profile(&self, tablet: TabletId) -> &Profile {
+        self.tablet(tablet).unwrap_or(&self.defaults)
+    }
+
+    /// 按键设备所在的组
+    pub fn keypad(&self, keypad: TabletId) -> Option<&KeypadConfig> {
+        self.keypads.iter().find(|config| config.id == keypad)
+    }
+
+    /// 登记了这支笔的用户
+    pub fn user(&self, pen: ToolId) -> Option<&UserConfig> {
+        self.users.iter().find(|user| user.pens.contains(&pen))
+    }
+
+    /// 检查用户: 一支笔只能属于一位用户
+    pub fn validate_users(&self) -> anyhow::Result<()> {
+        let mut owners = HashMap::new();
+        for user in &self.users {
+            for pen in &user.pens {
+                if let Some(owner) = owners.insert(*pen, &user.profile.name) {
+                    bail!("笔 {pen} 同时属于 {owner} 和 {}", user.profile.name);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 应用的设置叠加
+    pub fn app(&self, app_id: &str) -> Option<&AppProfile> {
+        self.apps.iter().find(|app| app.matches(app_id))
+    }
+
+    /// 把应用的设置叠加到默认设置和每块数位板的设置上, 用户的设置不变
+    pub fn with_app(&self, app: &AppProfile) -> anyhow::Result<Config> {
+        let mut config = self.clone();
+        let profiles = std::iter::once(&mut config.defaults)
+            .chain(config.tablets.iter_mut().map(|tablet| &mut tablet.profile));
+        for profile in profiles {
+            *profile = app
+                .apply(profile)
+                .with_context(|| format!("{} 的设置无效", app.app_id))?;
+        }
+        Ok(config)
+    }
+
+    /// 检查每个应用叠加后的设置
+    pub fn validate_apps(&self) -> anyhow::Result<()> {
+        for app in &self.apps {
+            self.with_app(app)?
+                .validate_pressure()
+                .with_context(|| format!("{} 的设置无效", app.app_id))?;
+        }
+        Ok(())
+    }
+
+    /// 替换所有绑定, 配置中没有的数位板改用默认绑定, 编组的按键设备使用所在数位板的绑定
+    pub fn apply_bindings(&self, bindings: &BindingTable) {
+        let keypads = self.keypads.iter().map(|keypad| {
+            let tablet = TabletBindings::from_profile(self.profile(keypad.tablet));
+            (keypad.id, tablet.for_keypad(keypad.first_button))
+        });
+        bindings.replace(
+            TabletBindings::from_profile(&self.defaults),
+            self.tablets
+                .iter()
+                .map(|tablet| (tablet.id, TabletBindings::from_profile(&tablet.profile)))
+                .chain(keypads)
+                .collect(),
+        );
+    }
+
+    /// 替换所有压感曲线, 有无效的曲线时什么也不修改
+    pub fn apply_pressure(&self, curves: &PressureCurves) -> anyhow::Result<()> {
+        curves.replace(
+            self.defaults.pressure_curve.clone(),
+            self.tablets
+                .iter()
+                .map(|tablet| (tablet.id, tablet.profile.pressure_curve.clone()))
+                .collect(),
+        )
+    }
+
+    /// 检查所有压感曲线
+    pub fn validate_pressure(&self) -> anyhow::Result<()> {
+        self.defaults.pressure_curve.validate()?;
+        for tablet in &self.tablets {
+            tablet
+                .profile
+                .pressure_curve
+                .validate()
+                .with_context(|| format!("{} 的压感曲线无效", tablet.id))?;
+        }
+        for user in &self.users {
+            user.profile
+                .pressure_curve
+                .validate()
+                .with_context(|| format!("用户 {} 的压感曲线无效", user.profile.name))?;
+        }
+        Ok(())
+    }
+}
+
+impl ConfigStage for BindingTable {
+    fn name(&self) -> &str {
+        "bindings"
+    }
+
+    fn apply(&mut self, config: &Config, _change: &ConfigChange) -> anyhow::Result<()> {
+        config.apply_bindings(self);
+        Ok(())
+    }
+}
+
+impl ConfigStage for PressureCurves {
+    fn name(&self) -> &str {
+        "pressure-curve"
+    }
+
+    fn validate(&self, config: &Config) -> anyhow::Result<()> {
+        config.validate_pressure()
+    }
+
+    fn apply(&mut self, config: &Config, _change: &ConfigChange) -> anyhow::Result<()> {
+        config.apply_pressure(self)
+    }
+}
+
+/// 两次配置之间的变化
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigChange {
+    /// 默认设置变化, 影响所有没有单独设置的数位板
+    pub defaults: bool,
+    /// 设置变化(包括新增和删除)的数位板
+    pub tablets: BTreeSet<TabletId>,
+    pub users: bool,
+    pub apps: bool,
+    pub api: bool,
+    pub exec: bool,
+    pub overlay: bool,
+    pub units: bool,
+    pub daemon: bool,
+}
+
+impl ConfigChange {
+    pub fn between(old: &Config, new: &Config) -> Self {
+        let ids = old
+            .tablets
+            .iter()
+            .chain(&new.tablets)
+            .map(|tablet| tablet.id);
+        let mut tablets: BTreeSet<_> = ids
+            .filter(|id| old.tablet(*id) != new.tablet(*id))
+            .collect();
+        // 按键设备的组变化, 或者所在的数位板设置变化时, 按键设备的绑定也变化
+        let keypads = old
+            .keypads
+            .iter()
+            .chain(&new.keypads)
+            .map(|keypad| keypad.id);
+        let changed: Vec<_> = keypads
+            .filter(|id| {
+                let grouped = |config: &Config| {
+                    config
+                        .keypad(*id)
+                        .map(|keypad| (keypad.clone(), config.profile(keypad.tablet).clone()))
+                };
+                grouped(old) != grouped(new)
+            })
+            .collect();
+        tablets.extend(changed);
+        Self {
+            defaults: old.defaults != new.defaults,
+            tablets,
+            users: old.users != new.users,
+            apps: old.apps != new.apps,
+            api: old.api != new.api,
+            exec: old.exec != new.exec,
+            overlay: old.overlay != new.overlay,
+            units: old.units != new.units,
+            daemon: old.daemon != new.daemon,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// 某块数位板的设置是否可能变化
+    pub fn affects(&self, tablet: TabletId) -> bool {
+        self.defaults || self.tablets.contains(&tablet)
+    }
+}
+
+/// 当前配置的广播通道
+///
+/// 订阅者总能拿到最新的配置，中间的变化可能被合并, 所以应该自己保留上一次的配置来比较.
+/// [`crate::event_router::Router`] 订阅后会在事件之间通过 [`transaction`] 应用配置
+#[derive(Clone)]
+pub struct ConfigBus {
+    tx: watch::Sender<Arc<Config>>,
+}
+
+impl ConfigBus {
+    pub fn new(config: Config) -> Self {
+        Self {
+            tx: watch::Sender::new(Arc::new(config)),
+        }
+    }
+
+    /// 发布新的配置，和当前配置相同时不会通知订阅者. 返回变化
+    pub fn publish(&self, config: Config) -> ConfigChange {
+        let mut change = ConfigChange::default();
+        self.tx.send_if_modified(|current| {
+            change = ConfigChange::between(current, &config);
+            if change.is_empty() {
+                return false;
+            }
+            *current = Arc::new(config);
+            true
+        });
+        change
+    }
+
+    pub fn current(&self) -> Arc<Config> {
+        Arc::clone(&self.tx.borrow())
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<Arc<Config>> {
+        self.tx.subscribe()
+    }
+}
+
+impl Default for ConfigBus {
+    fn default() -> Self {
+        Self::new(Config::default())
+    }
+}
@@ -0,0 +1,55 @@
+//! 事务式地应用配置
+//!
+//! 先让所有子系统检查新配置，全部通过后再依次应用; 某个子系统应用失败时，
+//! 已经应用的子系统按相反的顺序恢复旧配置. 这样重新加载只会全部生效或者全部不生效
+
+use anyhow::Context;
+use tracing::error;
+
+use super::{Config, ConfigChange};
+
+/// 需要跟随配置变化的子系统
+pub trait ConfigStage: Send {
+    /// 名称, 用于错误信息
+    fn name(&self) -> &str;
+
+    /// 检查新配置, 不应该修改任何状态
+    fn validate(&self, _config: &Config) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// 应用配置, `change` 是和当前配置的差异. 回滚时也通过这个方法恢复旧配置
+    fn apply(&mut self, config: &Config, change: &ConfigChange) -> anyhow::Result<()>;
+}
+
+/// 把 `new` 应用到所有 `stages`, 返回和 `old` 的差异
+///
+/// 任何子系统检查不通过时什么也不修改; 应用失败时已经应用的子系统恢复到 `old`
+pub fn apply(
+    stages: &mut [&mut dyn ConfigStage],
+    old: &Config,
+    new: &Config,
+) -> anyhow::Result<ConfigChange> {
+    let change = ConfigChange::between(old, new);
+    if change.is_empty() {
+        return Ok(change);
+    }
+    for stage in stages.iter() {
+        stage
+            .validate(new)
+            .with_context(|| format!("{} 不接受新配置", stage.name()))?;
+    }
+    for index in 0..stages.len() {
+        let Err(e) = stages[index].apply(new, &change) else {
+            continue;
+        };
+        let e = e.context(format!("{} 无法应用新配置", stages[index].name()));
+        for stage in stages[..index].iter_mut().rev() {
+            if let Err(rollback) = stage.apply(old, &change) {
+                error!("{} 无法恢复旧配置: {rollback:#}", stage.name());
+            }
+        }
+        return Err(e);
+    }
+    Ok(change)
+}
@@ -0,0 +1,122 @@
+use std::{
+    ffi::{OsStr, OsString},
+    os::fd::{AsFd, AsRawFd, RawFd},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Context;
+use nix::{
+    errno::Errno,
+    sys::inotify::{AddWatchFlags, InitFlags, Inotify},
+};
+use tokio::{io::unix::AsyncFd, task::JoinHandle};
+use tracing::{info, warn};
+
+use crate::profile::storage::ProfileStorage;
+
+use super::{Config, ConfigBus};
+
+/// 最后一次修改之后等待多久再重新加载
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// 让 [`Inotify`] 可以交给 [`AsyncFd`]
+struct InotifyFd(Inotify);
+
+impl AsRawFd for InotifyFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_fd().as_raw_fd()
+    }
+}
+
+/// 读取并展开配置
+fn load(path: &Path, storage: &dyn ProfileStorage) -> anyhow::Result<Config> {
+    let mut config = Config::load(path)?;
+    config.resolve(storage)?;
+    Ok(config)
+}
+
+/// 读取配置文件，之后每次文件变化时重新读取并发布到 `bus`
+///
+/// 监视的是配置文件所在的目录: 很多编辑器保存时会写一个新文件再改名覆盖,
+/// 直接监视文件会在第一次保存后失效. 新的配置有错误时保留旧的配置,
+/// 文件被删除时恢复默认配置
+pub fn spawn(
+    path: PathBuf,
+    storage: Arc<dyn ProfileStorage>,
+    bus: ConfigBus,
+) -> anyhow::Result<JoinHandle<()>> {
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+        .to_path_buf();
+    let name: OsString = path.file_name().context("配置文件路径没有文件名")?.into();
+    std::fs::create_dir_all(&dir).with_context(|| format!("无法创建 {}", dir.display()))?;
+
+    let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)
+        .context("无法初始化 inotify")?;
+    inotify
+        .add_watch(
+            &dir,
+            AddWatchFlags::IN_CLOSE_WRITE | AddWatchFlags::IN_MOVED_TO | AddWatchFlags::IN_DELETE,
+        )
+        .with_context(|| format!("无法监视 {}", dir.display()))?;
+    let inotify = AsyncFd::new(InotifyFd(inotify))?;
+
+    match load(&path, storage.as_ref()) {
+        Ok(config) => {
+            bus.publish(config);
+        }
+        Err(e) => warn!("配置文件有错误，使用默认配置: {e:#}"),
+    }
+
+    Ok(tokio::spawn(async move {
+        loop {
+            match touched(&inotify, &name).await {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(e) => {
+                    warn!("无法监视配置文件: {e}");
+                    break;
+                }
+            }
+            // 编辑器保存时可能连续产生好几个事件，等文件安静下来再读取
+            loop {
+                match tokio::time::timeout(DEBOUNCE, touched(&inotify, &name)).await {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => {
+                        warn!("无法监视配置文件: {e}");
+                        return;
+                    }
+                    Err(_) => break,
+                }
+            }
+            match load(&path, storage.as_ref()) {
+                Ok(config) => {
+                    let change = bus.publish(config);
+                    if !change.is_empty() {
+                        info!("已重新加载配置 {}", path.display());
+                    }
+                }
+                Err(e) => warn!("配置文件有错误，保留之前的配置: {e:#}"),
+            }
+        }
+    }))
+}
+
+/// 等待下一批 inotify 事件, 返回其中是否有配置文件的事件
+async fn touched(inotify: &AsyncFd<InotifyFd>, name: &OsStr) -> std::io::Result<bool> {
+    let mut guard = inotify.readable().await?;
+    match guard.get_inner().0.read_events() {
+        Ok(events) => Ok(events
+            .iter()
+            .any(|event| event.name.as_deref() == Some(name))),
+        Err(Errno::EAGAIN) => {
+            guard.clear_ready();
+            Ok(false)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,302 @@
+//! D-Bus 上的控制接口
+//!
+//! 在会话总线上注册 [`BUS_NAME`], 在 [`OBJECT_PATH`] 提供 [`INTERFACE`].
+//! 每个方法对应一个 [`ControlRequest`], GUI 和命令行工具都通过它管理运行中的 tabletd:
+//!
+//! ```sh
+//! busctl --user call io.github.sb_child.Tabletd /io/github/sb_child/Tabletd \
+//!     io.github.sb_child.Tabletd.Control1 ListTablets
+//! ```
+//!
+//! 映射用 TOML 文本传递, 格式和配置文件中的 `[defaults.mapping]` 相同
+
+use zbus::{fdo, interface, proxy};
+
+use crate::{
+    event_model::tablet::TabletId,
+    hud_interface::notification::NotificationLevel,
+    mapping::MappingConfig,
+    profile::binding::{Binding, ProfileAssignment},
+};
+
+use super::{ControlRequest, ControlResponse, ControlState};
+
+pub const BUS_NAME: &str = "io.github.sb_child.Tabletd";
+pub const OBJECT_PATH: &str = "/io/github/sb_child/Tabletd";
+pub const INTERFACE: &str = "io.github.sb_child.Tabletd.Control1";
+
+/// `ReportStats` 的一行: ID、名称、报告数、报告率(Hz)、抖动(毫秒)、断档次数和重新启动次数
+pub type StatsRow = (u32, String, u64, f64, f64, u64, u64);
+
+/// `Latency` 的一行: 阶段(`route`、`dispatch`、`total`)、事件数、平均值、中位数、
+/// 99% 分位数和最大值(毫秒)
+pub type LatencyRow = (String, u64, f64, f64, f64, f64);
+
+/// 把 D-Bus 方法转换成 [`ControlRequest`]
+pub struct ControlInterface {
+    state: ControlState,
+}
+
+impl ControlInterface {
+    fn call(&self, request: ControlRequest) -> fdo::Result<ControlResponse> {
+        match self.state.handle(request) {
+            ControlResponse::Error { message } => Err(fdo::Error::Failed(message)),
+            response => Ok(response),
+        }
+    }
+}
+
+fn unexpected(response: ControlResponse) -> fdo::Error {
+    fdo::Error::Failed(format!("意外的回应: {response:?}"))
+}
+
+#[interface(name = "io.github.sb_child.Tabletd.Control1")]
+impl ControlInterface {
+    /// 已连接的数位板: ID、名称、连接方式和使用的设置名称(没有时为空)
+    fn list_tablets(&self) -> fdo::Result<Vec<(u32, String, String, String)>> {
+        match self.call(ControlRequest::ListTablets)? {
+            ControlResponse::Tablets { tablets } => Ok(tablets
+                .into_iter()
+                .map(|tablet| {
+                    let profile = tablet.profile.unwrap_or_default();
+                    (tablet.id.0, tablet.name, tablet.transport, profile)
+                })
+                .collect()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// 数位板的映射, TOML 文本
+    fn get_mapping(&self, tablet: u32) -> fdo::Result<String> {
+        match self.call(ControlRequest::GetMapping {
+            tablet: TabletId(tablet),
+        })? {
+            ControlResponse::Mapping { mapping } => {
+                toml::to_string(&mapping).map_err(|e| fdo::Error::Failed(e.to_string()))
+            }
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// 修改数位板的映射, 只在本次运行中有效
+    fn set_mapping(&self, tablet: u32, mapping: &str) -> fdo::Result<()> {
+        let mapping: MappingConfig =
+            toml::from_str(mapping).map_err(|e| fdo::Error::InvalidArgs(e.to_string()))?;
+        self.call(ControlRequest::SetMapping {
+            tablet: TabletId(tablet),
+            mapping,
+        })?;
+        Ok(())
+    }
+
+    /// 设置数位板一个按键的绑定, TOML 文本, 格式和配置文件中的 `[[bindings]]` 相同.
+    /// 只在本次运行中有效
+    fn set_binding(&self, tablet: u32, binding: &str) -> fdo::Result<()> {
+        let binding: Binding =
+            toml::from_str(binding).map_err(|e| fdo::Error::InvalidArgs(e.to_string()))?;
+        self.call(ControlRequest::SetBinding {
+            tablet: TabletId(tablet),
+            binding,
+        })?;
+        Ok(())
+    }
+
+    /// 已保存的设置名称
+    fn list_profiles(&self) -> fdo::Result<Vec<String>> {
+        match self.call(ControlRequest::ListProfiles)? {
+            ControlResponse::Profiles { names } => Ok(names),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// 数位板改用一套已保存的设置, 只在本次运行中有效
+    fn switch_profile(&self, tablet: u32, profile: String) -> fdo::Result<()> {
+        self.call(ControlRequest::SwitchProfile {
+            tablet: TabletId(tablet),
+            profile,
+        })?;
+        Ok(())
+    }
+
+    /// 同时切换多块数位板的设置: (数位板 ID, 设置名称). 任何一块失败时都不切换.
+    /// `name` 显示在 HUD 上, 为空时不显示
+    fn switch_profiles(&self, name: String, profiles: Vec<(u32, String)>) -> fdo::Result<()> {
+        self.call(ControlRequest::SwitchProfiles {
+            name: Some(name).filter(|name| !name.is_empty()),
+            profiles: profiles
+                .into_iter()
+                .map(|(tablet, profile)| ProfileAssignment {
+                    tablet: TabletId(tablet),
+                    profile,
+                })
+                .collect(),
+        })?;
+        Ok(())
+    }
+
+    /// 在 HUD 上显示一条测试通知
+    fn test_notification(&self, text: String) -> fdo::Result<()> {
+        self.call(ControlRequest::TestNotification { text })?;
+        Ok(())
+    }
+
+    /// 最近的通知, 从新到旧: 级别(`info`、`warning`、`error`)和内容
+    fn notification_history(&self, limit: u32) -> fdo::Result<Vec<(String, String)>> {
+        match self.call(ControlRequest::NotificationHistory {
+            limit: limit as usize,
+        })? {
+            ControlResponse::Notifications { notifications } => Ok(notifications
+                .into_iter()
+                .map(|notification| {
+                    let level = match notification.level {
+                        NotificationLevel::Info => "info",
+                        NotificationLevel::Warning => "warning",
+                        NotificationLevel::Error => "error",
+                    };
+                    (level.to_string(), notification.text)
+                })
+                .collect()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// 黑匣子的文本记录
+    fn dump_black_box(&self) -> fdo::Result<String> {
+        match self.call(ControlRequest::DumpBlackBox)? {
+            ControlResponse::BlackBox { text } => Ok(text),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// overlay 连接的合成器(通过 `WAYLAND_DISPLAY` 连接时为空), 以及等待选择的合成器:
+    /// socket 名称和是否支持 `wlr-layer-shell`
+    fn list_displays(&self) -> fdo::Result<(String, Vec<(String, bool)>)> {
+        match self.call(ControlRequest::ListDisplays)? {
+            ControlResponse::Displays { current, pending } => Ok((
+                current.unwrap_or_default(),
+                pending
+                    .into_iter()
+                    .map(|candidate| (candidate.name, candidate.layer_shell))
+                    .collect(),
+            )),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// 选择 overlay 连接的合成器
+    fn select_display(&self, name: String) -> fdo::Result<()> {
+        self.call(ControlRequest::SelectDisplay { name })?;
+        Ok(())
+    }
+
+    /// 驱动的报告统计, 每块连接过的数位板一行, 见 [`StatsRow`]
+    fn report_stats(&self) -> fdo::Result<Vec<StatsRow>> {
+        match self.call(ControlRequest::ReportStats)? {
+            ControlResponse::ReportStats { stats } => Ok(stats
+                .into_iter()
+                .map(|stats| {
+                    (
+                        stats.tablet.0,
+                        stats.name,
+                        stats.reports,
+                        stats.rate_hz,
+                        stats.jitter_ms,
+                        stats.gaps,
+                        stats.resets,
+                    )
+                })
+                .collect()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// 事件各处理阶段的延迟, 每个阶段一行, 见 [`LatencyRow`]
+    fn latency(&self) -> fdo::Result<Vec<LatencyRow>> {
+        match self.call(ControlRequest::Latency)? {
+            ControlResponse::Latency { stages } => Ok(stages
+                .into_iter()
+                .map(|stage| {
+                    (
+                        stage.stage.name().to_string(),
+                        stage.count,
+                        stage.mean_ms,
+                        stage.p50_ms,
+                        stage.p99_ms,
+                        stage.max_ms,
+                    )
+                })
+                .collect()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// 当前的日志过滤规则
+    fn log_filter(&self) -> fdo::Result<String> {
+        match self.call(ControlRequest::GetLogFilter)? {
+            ControlResponse::LogFilter { filter } => Ok(filter),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// 修改日志过滤规则, 语法同 `RUST_LOG`. 返回修改后的规则
+    fn set_log_filter(&self, filter: &str) -> fdo::Result<String> {
+        match self.call(ControlRequest::SetLogFilter {
+            filter: filter.to_string(),
+        })? {
+            ControlResponse::LogFilter { filter } => Ok(filter),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// 在 HUD 上开始校准数位板的映射, 用笔依次点击显示的目标点
+    fn calibrate(&self, tablet: u32) -> fdo::Result<()> {
+        self.call(ControlRequest::Calibrate {
+            tablet: TabletId(tablet),
+        })?;
+        Ok(())
+    }
+
+    /// 取消正在进行的校准
+    fn cancel_calibration(&self) -> fdo::Result<()> {
+        self.call(ControlRequest::CancelCalibration)?;
+        Ok(())
+    }
+}
+
+/// 客户端使用的 [`ControlInterface`] 代理, 方法的含义见那里
+#[proxy(
+    interface = "io.github.sb_child.Tabletd.Control1",
+    default_service = "io.github.sb_child.Tabletd",
+    default_path = "/io/github/sb_child/Tabletd",
+    gen_blocking = false
+)]
+pub trait Control {
+    fn list_tablets(&self) -> zbus::Result<Vec<(u32, String, String, String)>>;
+    fn get_mapping(&self, tablet: u32) -> zbus::Result<String>;
+    fn set_mapping(&self, tablet: u32, mapping: &str) -> zbus::Result<()>;
+    fn set_binding(&self, tablet: u32, binding: &str) -> zbus::Result<()>;
+    fn list_profiles(&self) -> zbus::Result<Vec<String>>;
+    fn switch_profile(&self, tablet: u32, profile: &str) -> zbus::Result<()>;
+    fn switch_profiles(&self, name: &str, profiles: &[(u32, &str)]) -> zbus::Result<()>;
+    fn test_notification(&self, text: &str) -> zbus::Result<()>;
+    fn notification_history(&self, limit: u32) -> zbus::Result<Vec<(String, String)>>;
+    fn dump_black_box(&self) -> zbus::Result<String>;
+    fn list_displays(&self) -> zbus::Result<(String, Vec<(String, bool)>)>;
+    fn select_display(&self, name: &str) -> zbus::Result<()>;
+    fn report_stats(&self) -> zbus::Result<Vec<StatsRow>>;
+    fn latency(&self) -> zbus::Result<Vec<LatencyRow>>;
+    fn calibrate(&self, tablet: u32) -> zbus::Result<()>;
+    fn cancel_calibration(&self) -> zbus::Result<()>;
+    fn log_filter(&self) -> zbus::Result<String>;
+    fn set_log_filter(&self, filter: &str) -> zbus::Result<String>;
+}
+
+/// 在会话总线上提供控制接口, 返回的连接被丢弃时注销
+pub async fn serve(state: ControlState) -> anyhow::Result<zbus::Connection> {
+    let connection = zbus::connection::Builder::session()?
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, ControlInterface { state })?
+        .build()
+        .await?;
+    Ok(connection)
+}
@@ -0,0 +1,536 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    config::{ConfigBus, TabletConfig},
+    event_dispatcher::actions::ProfileSwitch,
+    event_model::{
+        capability::DeviceCapabilities,
+        latency::{LatencyStats, LatencySummary},
+        tablet::TabletId,
+    },
+    event_router::black_box::BlackBox,
+    hud_interface::{
+        HudEvent, HudSender,
+        notification::{Notification, NotificationHistory, NotificationLevel},
+    },
+    input_devices::transport::Transport,
+    logging,
+    mapping::{
+        Mapper, MappingConfig,
+        calibration::{Calibration, CalibrationSample, CalibrationView},
+        preview,
+    },
+    profile::{
+        Profile,
+        binding::{Binding, ProfileAssignment},
+        focus::FocusBus,
+        storage::ProfileStorage,
+    },
+    screen_overlay::backend_wayland::discovery::{DisplayCandidate, DisplayChooser},
+    tablet_driver::stats::{DeviceStats, ReportStats},
+    units,
+};
+
+/// D-Bus 上的控制接口
+pub mod dbus;
+
+/// 预览图的最大边长
+const MAX_PREVIEW_SIZE: u32 = 1024;
+
+/// 控制面板发来的请求
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "snake_case")]
+pub enum ControlRequest {
+    /// 查询最近的通知，从新到旧
+    NotificationHistory { limit: usize },
+    /// 当前映射设置的示意图
+    MappingPreview {
+        tablet: TabletId,
+        width: u32,
+        height: u32,
+    },
+    /// 报告聚焦的应用, 用于混成器不支持 `wlr-foreign-toplevel-management` 的桌面
+    SetFocus { app_id: Option<String> },
+    /// 导出黑匣子中最近的事件记录, 见 [`crate::event_router::black_box`]
+    DumpBlackBox,
+    /// 已连接的数位板和它们的连接方式
+    ListTablets,
+    /// 数位板当前使用的映射
+    GetMapping { tablet: TabletId },
+    /// 修改数位板的映射, 只在本次运行中有效
+    SetMapping {
+        tablet: TabletId,
+        mapping: MappingConfig,
+    },
+    /// 设置数位板一个按键的绑定, 替换同一按键和模式组上原有的绑定. 只在本次运行中有效
+    SetBinding { tablet: TabletId, binding: Binding },
+    /// 已保存的设置名称
+    ListProfiles,
+    /// 数位板改用一套已保存的设置, 只在本次运行中有效
+    SwitchProfile { tablet: TabletId, profile: String },
+    /// 同时切换多块数位板的设置, 只在本次运行中有效. 所有设置都读取并检查通过后才一起
+    /// 生效, 任何一块失败时什么也不修改. 结果在 HUD 上显示为一条通知
+    SwitchProfiles {
+        /// 在 HUD 上显示的名称
+        name: Option<String>,
+        profiles: Vec<ProfileAssignment>,
+    },
+    /// 在 HUD 上显示一条通知, 用于检查 HUD 是否正常
+    TestNotification { text: String },
+    /// overlay 连接的合成器和等待选择的合成器
+    ListDisplays,
+    /// 没有 `WAYLAND_DISPLAY` 且找到多个合成器时, 选择 overlay 连接哪一个
+    SelectDisplay { name: String },
+    /// 驱动统计的报告率、抖动和断档, 见 [`crate::tablet_driver::stats`]
+    ReportStats,
+    /// 事件各处理阶段的延迟, 见 [`crate::event_model::latency`]
+    Latency,
+    /// 在 HUD 上开始校准数位板的映射, 见 [`crate::mapping::calibration`]. 完成后结果写入
+    /// 数位板的设置, 数位板使用已保存的设置时同时保存
+    Calibrate { tablet: TabletId },
+    /// 取消正在进行的校准
+    CancelCalibration,
+    /// 当前的日志过滤规则
+    GetLogFilter,
+    /// 修改日志过滤规则, 语法同 `RUST_LOG`, 只在本次运行中有效. 见 [`crate::logging`]
+    SetLogFilter { filter: String },
+}
+
+/// 一块已连接的数位板
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TabletSummary {
+    pub id: TabletId,
+    pub name: String,
+    /// 连接方式, 如 `USB`
+    pub transport: String,
+    /// 使用的设置名称, 没有单独设置时为 `None`
+    pub profile: Option<String>,
+}
+
+/// 对控制请求的回应
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "response", rename_all = "snake_case")]
+pub enum ControlResponse {
+    Notifications {
+        notifications: Vec<Notification>,
+    },
+    /// PNG 图片
+    MappingPreview {
+        png: Vec<u8>,
+        /// 使用的数位板区域的物理尺寸, 按当前单位格式化. 设备没有提供分辨率时为 `None`
+        area: Option<String>,
+    },
+    /// 黑匣子的文本记录
+    BlackBox {
+        text: String,
+    },
+    Tablets {
+        tablets: Vec<TabletSummary>,
+    },
+    Mapping {
+        mapping: MappingConfig,
+    },
+    Profiles {
+        names: Vec<String>,
+    },
+    Displays {
+        /// 通过 `WAYLAND_DISPLAY` 连接或者还没有连接时为 `None`
+        current: Option<String>,
+        /// 等待选择的合成器, 不在等待时为空
+        pending: Vec<DisplayCandidate>,
+    },
+    /// 所有连接过的数位板, 包括已经断开的
+    ReportStats {
+        stats: Vec<ReportStats>,
+    },
+    /// 从 tabletd 启动开始统计
+    Latency {
+        stages: Vec<LatencySummary>,
+    },
+    /// 修改后的过滤规则
+    LogFilter {
+        filter: String,
+    },
+    /// 请求已处理, 没有需要返回的内容
+    Done,
+    Error {
+        message: String,
+    },
+}
+
+/// 控制接口看到的一块数位板
+#[derive(Debug, Clone)]
+pub struct TabletStatus {
+    pub name: String,
+    pub transport: Transport,
+    pub capabilities: DeviceCapabilities,
+    pub mapper: Mapper,
+}
+
+/// 控制接口需要访问的守护进程状态
+#[derive(Clone, Default)]
+pub struct ControlState {
+    pub notifications: Arc<Mutex<NotificationHistory>>,
+    /// 已连接的数位板
+    pub tablets: Arc<Mutex<HashMap<TabletId, TabletStatus>>>,
+    /// 聚焦的应用
+    pub focus: FocusBus,
+    /// 路由器的黑匣子, 见 [`crate::event_router::Router::black_box`]
+    pub black_box: BlackBox,
+    /// 修改映射和切换设置时发布新的配置
+    pub config: ConfigBus,
+    /// 已保存的设置, 为 `None` 时不能切换设置
+    pub profiles: Option<Arc<dyn ProfileStorage>>,
+    /// 测试通知发往这里, 为 `None` 时 HUD 没有运行
+    pub hud: Option<HudSender>,
+    /// overlay 连接哪个合成器
+    pub display: DisplayChooser,
+    /// 驱动的报告统计
+    pub stats: DeviceStats,
+    /// 路由器和出口统计的延迟
+    pub latency: LatencyStats,
+    /// 正在进行的校准, 和 HUD 共享
+    pub calibration: CalibrationView,
+}
+
+impl ControlState {
+    pub fn handle(&self, request: ControlRequest) -> ControlResponse {
+        match request {
+            ControlRequest::NotificationHistory { limit } => {
+                let history = self.notifications.lock().unwrap();
+                ControlResponse::Notifications {
+                    notifications: history.newest_first().take(limit).cloned().collect(),
+                }
+            }
+            ControlRequest::MappingPreview {
+                tablet,
+                width,
+                height,
+            } => {
+                let tablets = self.tablets.lock().unwrap();
+                let Some(status) = tablets.get(&tablet) else {
+                    return ControlResponse::Error {
+                        message: format!("找不到数位板 {tablet}"),
+                    };
+                };
+                let canvas = preview::render_preview(
+                    &status.mapper,
+                    &status.capabilities,
+                    width.clamp(1, MAX_PREVIEW_SIZE),
+                    height.clamp(1, MAX_PREVIEW_SIZE),
+                );
+                let area = status
+                    .mapper
+                    .active_area(&status.capabilities)
+                    .zip(status.capabilities.physical_size())
+                    .map(|(area, (width, height))| {
+                        units::current().area((area.width * width, area.height * height))
+                    });
+                ControlResponse::MappingPreview {
+                    png: canvas.to_png(),
+                    area,
+                }
+            }
+            ControlRequest::SetFocus { app_id } => {
+                self.focus.publish(app_id);
+                ControlResponse::Done
+            }
+            ControlRequest::DumpBlackBox => ControlResponse::BlackBox {
+                text: self.black_box.dump(),
+            },
+            ControlRequest::ListTablets => {
+                let config = self.config.current();
+                let mut tablets: Vec<_> = self
+                    .tablets
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|(id, status)| TabletSummary {
+                        id: *id,
+                        name: status.name.clone(),
+                        transport: status.transport.to_string(),
+                        profile: config.tablet(*id).map(|profile| profile.name.clone()),
+                    })
+                    .collect();
+                tablets.sort_by_key(|tablet| tablet.id.0);
+                ControlResponse::Tablets { tablets }
+            }
+            ControlRequest::GetMapping { tablet } => ControlResponse::Mapping {
+                mapping: self.config.current().profile(tablet).mapping.clone(),
+            },
+            ControlRequest::SetMapping { tablet, mapping } => {
+                self.update_profile(tablet, |profile| profile.mapping = mapping);
+                ControlResponse::Done
+            }
+            ControlRequest::SetBinding { tablet, binding } => {
+                self.update_profile(tablet, |profile| {
+                    profile
+                        .bindings
+                        .retain(|old| old.button != binding.button || old.bank != binding.bank);
+                    profile.bindings.push(binding);
+                });
+                ControlResponse::Done
+            }
+            ControlRequest::ListProfiles => match &self.profiles {
+                Some(storage) => match storage.list() {
+                    Ok(names) => ControlResponse::Profiles { names },
+                    Err(e) => error(format!("无法读取设置列表: {e:#}")),
+                },
+                None => error("没有设置存储".to_string()),
+            },
+            ControlRequest::SwitchProfile { tablet, profile } => {
+                let Some(storage) = &self.profiles else {
+                    return error("没有设置存储".to_string());
+                };
+                match storage.load(&profile) {
+                    Ok(Some(loaded)) => {
+                        self.update_profile(tablet, |profile| *profile = loaded);
+                        ControlResponse::Done
+                    }
+                    Ok(None) => error(format!("找不到设置 {profile}")),
+                    Err(e) => error(format!("无法读取设置 {profile}: {e:#}")),
+                }
+            }
+            ControlRequest::SwitchProfiles { name, profiles } => {
+                let result = self.switch_profiles(&profiles);
+                let (level, text) = match &result {
+                    Ok(()) => {
+                        let list = profiles
+                            .iter()
+                            .map(|assignment| {
+                                format!("{} → {}", assignment.tablet, assignment.profile)
+                            })
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        let text = match &name {
+                            Some(name) => format!("已切换到 {name}: {list}"),
+                            None => format!("已切换设置: {list}"),
+                        };
+                        (NotificationLevel::Info, text)
+                    }
+                    Err(e) => (NotificationLevel::Error, format!("没有切换设置: {e:#}")),
+                };
+                if let Some(hud) = &self.hud {
+                    let _ = hud.send(HudEvent::Notify(Notification::new(level, text)));
+                }
+                match result {
+                    Ok(()) => ControlResponse::Done,
+                    Err(e) => error(format!("{e:#}")),
+                }
+            }
+            ControlRequest::TestNotification { text } => match &self.hud {
+                Some(hud) => {
+                    let notification = Notification::new(NotificationLevel::Info, text);
+                    match hud.send(HudEvent::Notify(notification)) {
+                        Ok(()) => ControlResponse::Done,
+                        Err(_) => error("HUD 已停止".to_string()),
+                    }
+                }
+                None => error("HUD 没有运行".to_string()),
+            },
+            ControlRequest::ListDisplays => ControlResponse::Displays {
+                current: self.display.chosen(),
+                pending: self.display.pending(),
+            },
+            ControlRequest::SelectDisplay { name } => match self.display.choose(&name) {
+                Ok(()) => ControlResponse::Done,
+                Err(e) => error(format!("{e:#}")),
+            },
+            ControlRequest::ReportStats => ControlResponse::ReportStats {
+                stats: self.stats.snapshot(),
+            },
+            ControlRequest::Latency => ControlResponse::Latency {
+                stages: self.latency.snapshot(),
+            },
+            ControlRequest::Calibrate { tablet } => {
+                let target = match self.tablets.lock().unwrap().get(&tablet) {
+                    Some(status) => status.mapper.target_rect(),
+                    None => return error(format!("找不到数位板 {tablet}")),
+                };
+                let state = self.clone();
+                self.calibration.start(
+                    tablet,
+                    target,
+                    Box::new(move |tablet, result, samples| {
+                        state.finish_calibration(tablet, result, samples)
+                    }),
+                );
+                self.send_hud(HudEvent::CalibrationChanged);
+                ControlResponse::Done
+            }
+            ControlRequest::CancelCalibration => {
+                if self.calibration.cancel() {
+                    ControlResponse::Done
+                } else {
+                    error("没有在校准".to_string())
+                }
+            }
+            ControlRequest::GetLogFilter => match logging::filter() {
+                Some(filter) => ControlResponse::LogFilter { filter },
+                None => error("日志没有初始化".to_string()),
+            },
+            ControlRequest::SetLogFilter { filter } => match logging::set_filter(&filter) {
+                Ok(filter) => {
+                    info!("日志过滤规则改为 {filter}");
+                    ControlResponse::LogFilter { filter }
+                }
+                Err(e) => error(format!("{e:#}")),
+            },
+        }
+    }
+
+    fn send_hud(&self, event: HudEvent) {
+        if let Some(hud) = &self.hud {
+            let _ = hud.send(event);
+        }
+    }
+
+    /// 校准完成或取消, 成功时写入数位板的设置并在 HUD 上显示结果
+    fn finish_calibration(
+        &self,
+        tablet: TabletId,
+        result: anyhow::Result<Calibration>,
+        samples: &[CalibrationSample],
+    ) {
+        let (level, text) = match result {
+            Ok(calibration) => {
+                let residual = calibration.residual(samples);
+                self.update_profile(tablet, |profile| {
+                    profile.mapping.calibration = Some(calibration);
+                });
+                let saved = match self.save_calibration(tablet) {
+                    Ok(Some(name)) => format!(", 已保存到设置 {name}"),
+                    Ok(None) => String::new(),
+                    Err(e) => format!(", 但没有保存: {e:#}"),
+                };
+                (
+                    NotificationLevel::Info,
+                    format!("{tablet} 已校准, 最大误差 {:.1}%{saved}", residual * 100.0),
+                )
+            }
+            Err(e) => (
+                NotificationLevel::Warning,
+                format!("{tablet} 没有校准: {e:#}"),
+            ),
+        };
+        self.send_hud(HudEvent::CalibrationChanged);
+        self.send_hud(HudEvent::Notify(Notification::new(level, text)));
+    }
+
+    /// 数位板使用已保存的设置时把校准写回存储, 设置的其他部分不变. 返回设置名称
+    fn save_calibration(&self, tablet: TabletId) -> anyhow::Result<Option<String>> {
+        let Some(storage) = &self.profiles else {
+            return Ok(None);
+        };
+        let config = self.config.current();
+        let Some(profile) = config
+            .tablet(tablet)
+            .filter(|profile| !profile.name.is_empty())
+        else {
+            return Ok(None);
+        };
+        let Some(mut stored) = storage.load(&profile.name)? else {
+            return Ok(None);
+        };
+        stored.mapping.calibration = profile.mapping.calibration;
+        storage.save(&stored)?;
+        Ok(Some(profile.name.clone()))
+    }
+
+    /// 修改数位板的设置并发布新的配置, 没有单独设置的数位板先复制默认设置
+    ///
+    /// 只修改内存中的配置, 配置文件变化后会被文件中的内容覆盖
+    fn update_profile(&self, tablet: TabletId, update: impl FnOnce(&mut Profile)) {
+        let mut config = (*self.config.current()).clone();
+        let index = match config.tablets.iter().position(|config| config.id == tablet) {
+            Some(index) => index,
+            None => {
+                config.tablets.push(TabletConfig {
+                    id: tablet,
+                    profile: config.defaults.clone(),
+                });
+                config.tablets.len() - 1
+            }
+        };
+        update(&mut config.tablets[index].profile);
+        let mapping = config.tablets[index].profile.mapping.clone();
+        self.config.publish(config);
+        if let Some(status) = self.tablets.lock().unwrap().get_mut(&tablet) {
+            status.mapper.set_config(mapping);
+        }
+    }
+
+    /// 读取所有设置, 替换到配置中检查通过后一次发布. 出错时配置不变
+    fn switch_profiles(&self, profiles: &[ProfileAssignment]) -> anyhow::Result<()> {
+        let Some(storage) = &self.profiles else {
+            bail!("没有设置存储");
+        };
+        if profiles.is_empty() {
+            bail!("没有要切换的数位板");
+        }
+        let mut config = (*self.config.current()).clone();
+        let mut seen = HashSet::new();
+        for assignment in profiles {
+            let tablet = assignment.tablet;
+            if !seen.insert(tablet) {
+                bail!("数位板 {tablet} 出现了多次");
+            }
+            let profile = storage
+                .load(&assignment.profile)
+                .with_context(|| format!("无法读取设置 {}", assignment.profile))?
+                .with_context(|| format!("找不到设置 {}", assignment.profile))?;
+            match config.tablets.iter_mut().find(|config| config.id == tablet) {
+                Some(config) => config.profile = profile,
+                None => config.tablets.push(TabletConfig {
+                    id: tablet,
+                    profile,
+                }),
+            }
+        }
+        config.resolve(&**storage)?;
+        config.validate_pressure()?;
+        config.validate_apps()?;
+
+        let mappings: Vec<_> = profiles
+            .iter()
+            .map(|assignment| {
+                (
+                    assignment.tablet,
+                    config.profile(assignment.tablet).mapping.clone(),
+                )
+            })
+            .collect();
+        self.config.publish(config);
+        let mut tablets = self.tablets.lock().unwrap();
+        for (tablet, mapping) in mappings {
+            if let Some(status) = tablets.get_mut(&tablet) {
+                status.mapper.set_config(mapping);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<ProfileSwitch> for ControlRequest {
+    fn from(switch: ProfileSwitch) -> Self {
+        match switch {
+            ProfileSwitch::Tablet { tablet, profile } => {
+                ControlRequest::SwitchProfile { tablet, profile }
+            }
+            ProfileSwitch::Group { name, profiles } => {
+                ControlRequest::SwitchProfiles { name, profiles }
+            }
+        }
+    }
+}
+
+fn error(message: String) -> ControlResponse {
+    ControlResponse::Error { message }
+}
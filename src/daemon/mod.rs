@@ -0,0 +1,232 @@
+//! 守护进程的运行模式
+//!
+//! 默认所有子系统都运行. 也可以只运行其中一部分:
+//!
+//! - [`DaemonMode::OverlayOnly`]: 不读取本地设备、不创建虚拟设备, 只连接另一台 tabletd
+//!   (`remote`), 在本机的 overlay 和 HUD 上显示它的光标和墨迹
+//! - [`DaemonMode::InputOnly`]: 不创建 overlay 和 HUD, 读取本地设备并分发, 同时通过
+//!   `tabletd API` 导出事件. 适合没有显示器的服务器
+//!
+//! 模式在配置文件中设置, 也可以用 `tabletd --mode` 覆盖. 不需要的子系统会被跳过,
+//! 即使当前环境无法运行它们(比如没有 Wayland 混成器)也不算错误
+//!
+//! ```toml
+//! [daemon]
+//! mode = "overlay_only"
+//! remote = "192.168.1.20:7520"
+//! ```
+//!
+//! 子系统启动前探测它用到的后端, 探测的超时和重试见 [`startup`]
+
+use std::{fmt, str::FromStr};
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+use crate::{config::ApiConfig, input_devices::privilege::HelperConfig, logging::LogConfig};
+
+use startup::StartupConfig;
+
+/// 守护进程的路由器
+pub mod pipeline;
+/// 后端的启动探测
+pub mod startup;
+/// 在监督下启动各个子系统
+pub mod tasks;
+
+/// 运行哪些子系统
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DaemonMode {
+    /// 所有子系统
+    #[default]
+    Full,
+    /// 只显示 overlay 和 HUD, 事件来自远程的 tabletd
+    OverlayOnly,
+    /// 只处理输入, 不显示任何界面
+    InputOnly,
+}
+
+/// 守护进程的子系统
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Subsystem {
+    /// 读取本地的 USB 和蓝牙数位板
+    Devices,
+    /// 通过 uinput 或 tablet-v2 把事件交给系统
+    Dispatch,
+    /// 每个显示器上的 overlay
+    Overlay,
+    /// overlay 上的 HUD
+    Hud,
+    /// `tabletd API` 服务端
+    Api,
+    /// 连接远程 tabletd
+    Remote,
+    /// D-Bus 上的控制接口
+    Control,
+    /// Prometheus 格式的 metrics 端点
+    Metrics,
+}
+
+impl Subsystem {
+    pub const ALL: [Subsystem; 8] = [
+        Subsystem::Devices,
+        Subsystem::Dispatch,
+        Subsystem::Overlay,
+        Subsystem::Hud,
+        Subsystem::Api,
+        Subsystem::Remote,
+        Subsystem::Control,
+        Subsystem::Metrics,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Subsystem::Devices => "devices",
+            Subsystem::Dispatch => "dispatch",
+            Subsystem::Overlay => "overlay",
+            Subsystem::Hud => "hud",
+            Subsystem::Api => "api",
+            Subsystem::Remote => "remote",
+            Subsystem::Control => "control",
+            Subsystem::Metrics => "metrics",
+        }
+    }
+}
+
+impl DaemonMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            DaemonMode::Full => "full",
+            DaemonMode::OverlayOnly => "overlay_only",
+            DaemonMode::InputOnly => "input_only",
+        }
+    }
+
+    /// 这个模式是否运行 `subsystem`. 远程连接和 metrics 端点只在配置了地址时才运行
+    pub fn runs(self, subsystem: Subsystem) -> bool {
+        match self {
+            DaemonMode::Full => true,
+            DaemonMode::OverlayOnly => matches!(
+                subsystem,
+                Subsystem::Overlay | Subsystem::Hud | Subsystem::Remote | Subsystem::Control
+            ),
+            DaemonMode::InputOnly => matches!(
+                subsystem,
+                Subsystem::Devices
+                    | Subsystem::Dispatch
+                    | Subsystem::Api
+                    | Subsystem::Control
+                    | Subsystem::Metrics
+            ),
+        }
+    }
+}
+
+impl fmt::Display for DaemonMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for DaemonMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.replace('-', "_").as_str() {
+            "full" => Ok(DaemonMode::Full),
+            "overlay_only" => Ok(DaemonMode::OverlayOnly),
+            "input_only" => Ok(DaemonMode::InputOnly),
+            _ => bail!("未知的运行模式 {s}, 可选 full、overlay_only、input_only"),
+        }
+    }
+}
+
+/// 配置文件中的 `[daemon]`
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DaemonConfig {
+    pub mode: DaemonMode,
+    /// 远程 tabletd 的地址, 例如 `192.168.1.20:7520`. 只显示 overlay 时必须设置
+    pub remote: Option<String>,
+    /// 每个后端的启动探测
+    pub startup: StartupConfig,
+    /// Prometheus metrics 端点的地址, 例如 `127.0.0.1:9720`. 不设置时不监听,
+    /// 见 [`crate::event_dispatcher::metrics`]
+    pub metrics: Option<String>,
+    /// 没有设备权限时使用的特权辅助进程, 见 [`crate::input_devices::privilege`]
+    pub helper: HelperConfig,
+    /// 日志的过滤规则, 见 [`crate::logging`]
+    pub log: LogConfig,
+    /// 独占接管的数位板, libinput 不再为它们创建指针, 见 [`crate::input_devices::grab`]
+    pub grab: bool,
+}
+
+impl DaemonConfig {
+    /// 检查模式需要的设置是否齐全
+    pub fn validate(&self, api: &ApiConfig) -> anyhow::Result<()> {
+        match self.mode {
+            DaemonMode::Full => {}
+            DaemonMode::OverlayOnly => {
+                if self.remote.is_none() {
+                    bail!("overlay_only 模式需要设置 [daemon] remote");
+                }
+            }
+            DaemonMode::InputOnly => {
+                if api.unix.is_none() && api.tcp.is_none() {
+                    bail!("input_only 模式需要设置 [api] unix 或 tcp, 否则事件无法导出");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 按模式和设置决定启动哪些子系统
+    pub fn plan(&self) -> DaemonPlan {
+        DaemonPlan {
+            mode: self.mode,
+            subsystems: Subsystem::ALL
+                .into_iter()
+                .filter(|subsystem| self.mode.runs(*subsystem))
+                .filter(|subsystem| *subsystem != Subsystem::Remote || self.remote.is_some())
+                .filter(|subsystem| *subsystem != Subsystem::Metrics || self.metrics.is_some())
+                .collect(),
+        }
+    }
+}
+
+/// 要启动的子系统
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DaemonPlan {
+    pub mode: DaemonMode,
+    pub subsystems: Vec<Subsystem>,
+}
+
+impl DaemonPlan {
+    pub fn runs(&self, subsystem: Subsystem) -> bool {
+        self.subsystems.contains(&subsystem)
+    }
+
+    /// 所有子系统都运行
+    pub fn full() -> Self {
+        Self {
+            mode: DaemonMode::Full,
+            subsystems: Subsystem::ALL.to_vec(),
+        }
+    }
+}
+
+impl fmt::Display for DaemonPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "运行模式 {}:", self.mode)?;
+        for subsystem in Subsystem::ALL {
+            let state = if self.runs(subsystem) {
+                "启动"
+            } else {
+                "跳过"
+            };
+            write!(f, " {}={state}", subsystem.name())?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+//! 守护进程的路由器
+//!
+//! 按固定的顺序添加过滤器, 过滤器的设置都来自路由器跟随的配置. 路由器重启时用同一份
+//! [`PipelineParts`] 重新创建, 和 HUD、动作执行者共享的状态不会丢失:
+//!
+//! 1. 模式组: 后面的过滤器都能读到事件的模式组
+//! 2. 按笔识别用户: 替换绑定和压感曲线, 在用到它们的过滤器之前
+//! 3. 表达式、压感曲线、平滑和预测: 读写设备报告的值
+//! 4. 映射到屏幕(包括边缘阻力和校准)、演示模式: 激光笔轨迹需要屏幕位置
+//! 5. 笔杆按键手势、HUD 面板、快捷菜单、绑定(包括确认): 需要屏幕位置, 可能消费事件
+//! 6. 触控环转盘和反馈: 只读取事件
+
+use std::{
+    sync::{Arc, Mutex, atomic::AtomicBool},
+    time::Duration,
+};
+
+use crate::{
+    event_router::{
+        Router,
+        bindings::{ActionSender, BindingEngine, BindingTable},
+        capture::Capture,
+        feedback::{Feedback, FeedbackConfig},
+        gesture::Gestures,
+        hover_only::HoverOnly,
+        mode_bank::TabletBanks,
+        pressure::{PressureCurves, PressureMapper},
+        quick_menu::QuickMenu,
+        ring::RingFeedback,
+        screen::ScreenMapping,
+        script::{TransformScripts, Transforms},
+        smoothing::{Prediction, Smoothing, SmoothingSettings},
+        users::PenUsers,
+    },
+    hud_interface::{HudSender, mapping_overlay::MappingView, pointer::HudElement},
+    mapping::calibration::CalibrationView,
+    screen_overlay::trail::LaserTrail,
+};
+
+/// 演示模式的激光笔轨迹保留的时间
+const TRAIL_LIFETIME: Duration = Duration::from_millis(800);
+
+/// 路由器和其他子系统共享的状态
+#[derive(Clone)]
+pub struct PipelineParts {
+    /// 绑定触发的动作发往这里, 见 [`crate::event_dispatcher::actions::spawn`]
+    pub actions: ActionSender,
+    /// 不运行 HUD 时为 `None`
+    pub hud: Option<HudSender>,
+    /// HUD 面板打开时拦截事件的开关, 由 [`Action::ToggleHud`](crate::profile::binding::Action::ToggleHud) 切换
+    pub capture: Arc<AtomicBool>,
+    /// HUD 的映射区域显示读取
+    pub mapping_view: MappingView,
+    /// 控制接口和 HUD 共享的校准
+    pub calibration: CalibrationView,
+    /// 演示模式的激光笔轨迹, overlay 读取
+    pub trail: Arc<Mutex<LaserTrail>>,
+}
+
+impl PipelineParts {
+    pub fn new(actions: ActionSender) -> Self {
+        Self {
+            actions,
+            hud: None,
+            capture: Arc::default(),
+            mapping_view: MappingView::new(),
+            calibration: CalibrationView::default(),
+            trail: Arc::new(Mutex::new(LaserTrail::new(TRAIL_LIFETIME))),
+        }
+    }
+}
+
+/// 创建添加了所有过滤器的路由器
+pub fn router(parts: &PipelineParts) -> Router {
+    let mut router = Router::new();
+    if let Some(hud) = &parts.hud {
+        router.set_hud(hud.clone());
+    }
+
+    // 跟随配置的表, 过滤器在处理事件时读取
+    let bindings = BindingTable::new();
+    let curves = PressureCurves::new();
+    let smoothing = SmoothingSettings::new();
+    let scripts = TransformScripts::new();
+    router.add_stage(Box::new(bindings.clone()));
+    router.add_stage(Box::new(curves.clone()));
+    router.add_stage(Box::new(smoothing.clone()));
+    router.add_stage(Box::new(scripts.clone()));
+
+    let mut banks = TabletBanks::new();
+    let mut users = PenUsers::new(bindings.clone(), curves.clone());
+    if let Some(hud) = &parts.hud {
+        banks.set_hud(hud.clone());
+        users.set_hud(hud.clone());
+    }
+    router.add_filter(Box::new(banks));
+    router.add_filter(Box::new(users));
+
+    router.add_filter(Box::new(Transforms::new(scripts)));
+    router.add_filter(Box::new(PressureMapper::new(curves)));
+    router.add_filter(Box::new(Smoothing::new(smoothing.clone())));
+    router.add_filter(Box::new(Prediction::new(smoothing)));
+
+    let mut screen = ScreenMapping::new();
+    screen.set_mapping_view(parts.mapping_view.clone());
+    screen.set_calibration(parts.calibration.clone());
+    if let Some(hud) = &parts.hud {
+        screen.set_hud(hud.clone());
+    }
+    router.add_filter(Box::new(screen));
+    router.add_filter(Box::new(HoverOnly::new(parts.trail.clone())));
+
+    let gestures = Gestures::new();
+    let mut engine = BindingEngine::new(bindings.clone(), parts.actions.clone());
+    engine.set_gestures(gestures.subscribe());
+    let mut quick_menu = QuickMenu::new(bindings, parts.actions.clone());
+    let mut ring = RingFeedback::new();
+    if let Some(hud) = &parts.hud {
+        engine.set_hud(hud.clone());
+        quick_menu.set_hud(hud.clone());
+        ring.set_hud(hud.clone());
+    }
+    router.add_filter(Box::new(gestures));
+    router.add_filter(Box::new(
+        Capture::with_switch("hud", parts.capture.clone()).target(HudElement::Panel),
+    ));
+    router.add_filter(Box::new(quick_menu));
+    router.add_filter(Box::new(engine));
+
+    router.add_filter(Box::new(ring));
+    router.add_filter(Box::new(Feedback::new(FeedbackConfig::default())));
+
+    router
+}
@@ -0,0 +1,228 @@
+//! 后端的启动探测
+//!
+//! 子系统启动前先探测它依赖的后端是否可用. 每次探测有超时, 失败后按这个后端自己的次数和间隔
+//! 重试, 都在 `[daemon.startup]` 中设置. BlueZ 没有响应或者找不到 DRM 节点时只推迟用到它的
+//! 子系统, 其他子系统照常启动. 重试用完后这个子系统不再启动, 不算崩溃:
+//!
+//! ```toml
+//! [daemon.startup.wayland]
+//! timeout_ms = 2000
+//! retries = 10
+//! retry_delay_ms = 3000
+//! ```
+
+use std::{fmt, future::Future, os::unix::net::UnixStream, time::Duration};
+
+use anyhow::{Context, anyhow, bail};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::{
+    input_devices::hidraw,
+    screen_overlay::{
+        backend_drm::drm_util::device::Card, backend_wayland::discovery::DisplayChooser,
+    },
+};
+
+/// 需要探测的后端
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Usb,
+    Ble,
+    Wayland,
+    Drm,
+    X11,
+}
+
+impl Backend {
+    pub const ALL: [Backend; 5] = [
+        Backend::Usb,
+        Backend::Ble,
+        Backend::Wayland,
+        Backend::Drm,
+        Backend::X11,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Usb => "usb",
+            Backend::Ble => "ble",
+            Backend::Wayland => "wayland",
+            Backend::Drm => "drm",
+            Backend::X11 => "x11",
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// 一个后端的探测超时和重试
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProbePolicy {
+    /// 一次探测最多等待的时间
+    pub timeout_ms: u32,
+    /// 第一次失败后最多再试几次
+    pub retries: u32,
+    pub retry_delay_ms: u32,
+}
+
+impl Default for ProbePolicy {
+    fn default() -> Self {
+        Self {
+            timeout_ms: 5000,
+            retries: 3,
+            retry_delay_ms: 2000,
+        }
+    }
+}
+
+impl ProbePolicy {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms as u64)
+    }
+
+    pub fn retry_delay(&self) -> Duration {
+        Duration::from_millis(self.retry_delay_ms as u64)
+    }
+}
+
+/// 配置文件中的 `[daemon.startup]`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StartupConfig {
+    pub usb: ProbePolicy,
+    pub ble: ProbePolicy,
+    pub wayland: ProbePolicy,
+    pub drm: ProbePolicy,
+    pub x11: ProbePolicy,
+}
+
+impl StartupConfig {
+    pub fn policy(&self, backend: Backend) -> &ProbePolicy {
+        match backend {
+            Backend::Usb => &self.usb,
+            Backend::Ble => &self.ble,
+            Backend::Wayland => &self.wayland,
+            Backend::Drm => &self.drm,
+            Backend::X11 => &self.x11,
+        }
+    }
+}
+
+/// 探测一次, 成功时返回找到的内容. Wayland 按默认规则寻找合成器, 见 [`DisplayChooser::probe`]
+pub async fn probe(backend: Backend) -> anyhow::Result<String> {
+    match backend {
+        Backend::Usb => {
+            blocking(|| {
+                let nodes = hidraw::enumerate();
+                let tablets = nodes.iter().filter(|node| node.is_known_tablet()).count();
+                Ok(format!(
+                    "{} 个 hidraw 节点, {tablets} 块已知数位板",
+                    nodes.len()
+                ))
+            })
+            .await
+        }
+        Backend::Ble => {
+            let session = bluer::Session::new().await.context("无法连接 BlueZ")?;
+            let adapter = session.default_adapter().await.context("没有蓝牙适配器")?;
+            if !adapter.is_powered().await? {
+                bail!("蓝牙适配器 {} 没有打开", adapter.name());
+            }
+            Ok(format!("蓝牙适配器 {}", adapter.name()))
+        }
+        Backend::Wayland => blocking(|| DisplayChooser::default().probe()).await,
+        Backend::Drm => {
+            blocking(|| {
+                Card::find().context("找不到接了显示器的显卡")?;
+                Ok("找到接了显示器的显卡".to_string())
+            })
+            .await
+        }
+        Backend::X11 => blocking(probe_x11).await,
+    }
+}
+
+/// 阻塞的探测放到单独的线程, 超时后不再等待它
+async fn blocking<F>(probe: F) -> anyhow::Result<String>
+where
+    F: FnOnce() -> anyhow::Result<String> + Send + 'static,
+{
+    tokio::task::spawn_blocking(probe).await?
+}
+
+/// 连接 `DISPLAY` 对应的 X server socket
+fn probe_x11() -> anyhow::Result<String> {
+    let display = std::env::var("DISPLAY").context("未设置 DISPLAY")?;
+    let number = display
+        .strip_prefix(':')
+        .and_then(|rest| rest.split('.').next())
+        .with_context(|| format!("只支持本机的 DISPLAY: {display}"))?;
+    let path = format!("/tmp/.X11-unix/X{number}");
+    UnixStream::connect(&path).with_context(|| format!("无法连接 {path}"))?;
+    Ok(display)
+}
+
+/// 按 `policy` 反复调用 `probe`, 直到成功或者重试用完
+///
+/// 每次探测超过 `timeout_ms` 都算失败. 成功时返回探测到的内容, 否则返回最后一次失败的原因
+pub async fn wait_ready<P, Fut>(
+    backend: Backend,
+    policy: &ProbePolicy,
+    mut probe: P,
+) -> anyhow::Result<String>
+where
+    P: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<String>>,
+{
+    let attempts = policy.retries + 1;
+    let mut attempt = 1;
+    loop {
+        let result = match tokio::time::timeout(policy.timeout(), probe()).await {
+            Ok(result) => result,
+            Err(_) => Err(anyhow!("{} 毫秒内没有完成", policy.timeout_ms)),
+        };
+        let error = match result {
+            Ok(detail) => return Ok(detail),
+            Err(e) if attempt >= attempts => {
+                return Err(e.context(format!("{backend} 尝试 {attempts} 次后仍不可用")));
+            }
+            Err(e) => e,
+        };
+        warn!(
+            "{backend} 不可用 ({attempt}/{attempts}), {} 毫秒后重试: {error:#}",
+            policy.retry_delay_ms
+        );
+        tokio::time::sleep(policy.retry_delay()).await;
+        attempt += 1;
+    }
+}
+
+/// 等 `backend` 可用后运行 `run`. 重试用完时不运行, 返回 `Ok`, 子系统不会被当作崩溃重启
+pub async fn run_when_ready<P, Fut, R>(
+    backend: Backend,
+    policy: &ProbePolicy,
+    probe: P,
+    run: R,
+) -> anyhow::Result<()>
+where
+    P: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<String>>,
+    R: Future<Output = anyhow::Result<()>>,
+{
+    match wait_ready(backend, policy, probe).await {
+        Ok(detail) => {
+            info!("{backend} 可用: {detail}");
+            run.await
+        }
+        Err(e) => {
+            error!("{e:#}, 不再启动");
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,773 @@
+//! 守护进程的子系统
+//!
+//! 按 [`DaemonPlan`] 在 [`Supervisor`] 下启动各个子系统. 子系统之间通过这里创建的通道连接,
+//! 通道的两端由守护进程持有, 所以任何一个子系统重启后其他子系统不需要重新连接:
+//!
+//! - 驱动 → 路由器: 事件通道的接收端在路由器重启时交给新的路由器
+//! - 数位板接入和断开: 发往同一个广播通道, 路由器和虚拟数位板重启时按已接入的设备重建
+//! - HUD: 事件通道和界面状态都由守护进程持有, overlay 重启后重新设置重绘句柄
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context, anyhow, bail};
+use tokio::{
+    sync::{broadcast, mpsc},
+    task::JoinHandle,
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    config::{Config, ConfigBus, watcher},
+    control::{ControlResponse, ControlState, TabletStatus, dbus},
+    event_dispatcher::{
+        actions::{self, ActionRunner, ProfileSwitch},
+        api::ApiServer,
+        keyboard::VirtualKeyboard,
+        metrics,
+        sinks::Sinks,
+    },
+    event_model::latency::LatencyStats,
+    event_router::{EventSender, RoutedEvent, bindings::Triggered, black_box::BlackBox, lanes},
+    hud_interface::{
+        HudEvent, HudSender, HudState,
+        diagnostics::DiagnosticsPanel,
+        mapping_overlay::{MappingOverlay, MappingView},
+        notification::NotificationHistory,
+    },
+    input_devices::{
+        bluetooth::{BleBackend, BleWatcher},
+        grab::GrabManager,
+        hotplug::{ConnectedDevice, DeviceEvent, HotplugWatcher, TabletConnections},
+        identity::IdentityRegistry,
+        remote::{RemoteLink, RemoteMode, RemoteViewer},
+        usb::UsbBackend,
+    },
+    mapping::{Mapper, geometry::GeometryBus},
+    profile::{focus::FocusBus, storage::FileStorage, sync::ProfileSync},
+    screen_overlay::{
+        backend_wayland::{WaylandOverlay, discovery::DisplayChooser, frame::RedrawHandle},
+        builder::SurfaceOptions,
+        canvas::Color,
+        cursor_manager::CursorManager,
+        error::OverlayError,
+        ink::InkLayer,
+        layers::OverlayLayers,
+        selection::OutputSelection,
+        strategy::OverlayStrategy,
+        trail::LaserTrail,
+    },
+    shutdown::{ShutdownCoordinator, ShutdownReport, ShutdownStage},
+    supervisor::Supervisor,
+    tablet_driver::{
+        keypad::KeypadSpec, quirks::DeviceQuirk, spec::DeviceSpec, stats::DeviceStats,
+    },
+};
+
+use super::{
+    DaemonConfig, Subsystem,
+    pipeline::{self, PipelineParts},
+    startup::{self, Backend},
+};
+
+/// 退出时所有清理步骤一共最多等待的时间
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+/// 驱动发往路由器的事件队列长度
+const INPUT_QUEUE_LEN: usize = 1024;
+/// 路由器发往出口的事件队列长度
+const ROUTED_QUEUE_LEN: usize = 1024;
+/// 数位板接入和断开事件的队列长度
+const DEVICE_QUEUE_LEN: usize = 64;
+/// 出口发往 overlay 的笔事件队列长度, 落后时只丢掉中间的位置
+const PEN_QUEUE_LEN: usize = 64;
+
+/// 运行守护进程直到收到 SIGTERM 或 SIGINT, 返回退出时各清理步骤的结果
+pub async fn run(config: Config, daemon: &DaemonConfig) -> anyhow::Result<ShutdownReport> {
+    let plan = daemon.plan();
+    let config_bus = ConfigBus::new(config.clone());
+    if let Err(e) = watcher::spawn(
+        Config::default_path(),
+        Arc::new(FileStorage::default()),
+        config_bus.clone(),
+    ) {
+        warn!("无法监视配置文件, 修改后需要重启 tabletd: {e:#}");
+    }
+    let geometry = GeometryBus::new();
+    let black_box = BlackBox::new();
+    black_box.install_panic_hook();
+
+    let (hud, hud_rx) = mpsc::unbounded_channel::<HudEvent>();
+    let hud_rx = Arc::new(tokio::sync::Mutex::new(hud_rx));
+    let notifications = Arc::new(Mutex::new(NotificationHistory::default()));
+    let stats = DeviceStats::new();
+    let latency = LatencyStats::new();
+    let mut hud_state = HudState::new(notifications.clone());
+    hud_state.diagnostics = DiagnosticsPanel::new(stats.clone());
+    let mapping_view = MappingView::new();
+    hud_state.mapping_overlay = MappingOverlay::new(mapping_view.clone());
+    let calibration = hud_state.calibration.clone();
+    let hud_state = Arc::new(Mutex::new(hud_state));
+    // 演示模式的路由器记录, overlay 绘制
+    let trail = Arc::new(Mutex::new(LaserTrail::default()));
+    // 只看模式下远程数位板的墨迹
+    let remote_ink = Arc::new(Mutex::new(InkLayer::new(None)));
+    geometry.forward_to_hud(hud.clone());
+
+    let focus = FocusBus::new();
+    let display = DisplayChooser::new(config.overlay.display.clone());
+    let control = ControlState {
+        notifications,
+        tablets: Arc::default(),
+        focus: focus.clone(),
+        black_box: black_box.clone(),
+        config: config_bus.clone(),
+        profiles: Some(Arc::new(FileStorage::default())),
+        hud: plan.runs(Subsystem::Hud).then(|| hud.clone()),
+        display: display.clone(),
+        stats: stats.clone(),
+        latency: latency.clone(),
+        calibration: calibration.clone(),
+    };
+
+    let (events, input) = lanes::channel(INPUT_QUEUE_LEN);
+    let input = Arc::new(tokio::sync::Mutex::new(input));
+    let lifecycle = broadcast::channel::<DeviceEvent>(DEVICE_QUEUE_LEN).0;
+
+    let api = plan.runs(Subsystem::Api).then(|| {
+        let mut api = ApiServer::with_geometry(geometry.clone());
+        api.set_black_box(black_box.clone());
+        if let Some(token) = &config.api.sync_token {
+            enable_sync(&mut api, token, &config_bus);
+        }
+        api.follow_devices(lifecycle.subscribe());
+        api
+    });
+    let connected = track_devices(&lifecycle, &control, &geometry);
+    let (pens, _) = broadcast::channel(PEN_QUEUE_LEN);
+
+    let mut supervisor = Supervisor::new(ShutdownCoordinator::new(SHUTDOWN_TIMEOUT));
+    if plan.runs(Subsystem::Hud) {
+        supervisor.set_hud(hud.clone());
+    }
+    if let Some(api) = &api {
+        supervisor.set_api(api.clone());
+    }
+
+    if plan.runs(Subsystem::Devices) {
+        let identities = match IdentityRegistry::load(IdentityRegistry::default_path()) {
+            Ok(identities) => identities,
+            Err(e) => {
+                warn!("无法读取设备注册表, 本次运行不保存: {e:#}");
+                IdentityRegistry::new()
+            }
+        };
+        let identities = Arc::new(Mutex::new(identities));
+        // 每次变化时已经保存过, 这里重试那时写入失败的部分
+        let registry = identities.clone();
+        supervisor
+            .coordinator()
+            .register(ShutdownStage::Persist, "devices", move || async move {
+                registry.lock().unwrap().save()
+            });
+        let (specs, keypads, quirks) = device_specs();
+        let grabs = daemon.grab.then(GrabManager::new);
+        if let Some(grabs) = grabs.clone() {
+            // 驱动停止时已经逐个解除, 这里兜底
+            supervisor.coordinator().register(
+                ShutdownStage::ReleaseGrabs,
+                "grabs",
+                move || async move {
+                    grabs.release_all();
+                    Ok(())
+                },
+            );
+        }
+        let startup = daemon.startup.clone();
+        let (events, lifecycle, hud, api, stats) = (
+            events.clone(),
+            lifecycle.clone(),
+            hud.clone(),
+            api.clone(),
+            stats.clone(),
+        );
+        supervisor.supervise(
+            Subsystem::Devices,
+            ShutdownStage::StopInput,
+            move |mut stop| {
+                let mut backend = UsbBackend::new(specs.clone());
+                backend.set_keypads(keypads.clone());
+                backend.set_quirks(quirks.clone());
+                let mut watcher = HotplugWatcher::new(backend, identities.clone(), events.clone());
+                watcher.set_lifecycle(lifecycle.clone());
+                watcher.set_stats(stats.clone());
+                watcher.set_hud(hud.clone());
+                if let Some(api) = &api {
+                    watcher.set_api(api.clone());
+                }
+                if let Some(grabs) = &grabs {
+                    watcher.set_grabs(grabs.clone());
+                }
+                let ble = BleStart {
+                    specs: specs.clone(),
+                    identities: identities.clone(),
+                    events: events.clone(),
+                    lifecycle: lifecycle.clone(),
+                    hud: hud.clone(),
+                    api: api.clone(),
+                    stats: stats.clone(),
+                };
+                let startup = startup.clone();
+                // 停止时 watcher 被丢弃, 所有驱动随之停止
+                async move {
+                    let usb = startup::run_when_ready(
+                        Backend::Usb,
+                        &startup.usb,
+                        || startup::probe(Backend::Usb),
+                        watcher.run(),
+                    );
+                    // 没有蓝牙适配器时只有 USB 运行
+                    let ble = startup::run_when_ready(
+                        Backend::Ble,
+                        &startup.ble,
+                        || startup::probe(Backend::Ble),
+                        ble.run(),
+                    );
+                    tokio::select! {
+                        result = async { tokio::try_join!(usb, ble).map(|_| ()) } => result,
+                        _ = stop.wait() => Ok(()),
+                    }
+                }
+            },
+        );
+    }
+
+    if plan.runs(Subsystem::Remote)
+        && let Some(address) = daemon.remote.clone()
+    {
+        let (hud, mapping) = (hud.clone(), config.defaults.mapping.clone());
+        let (geometry, remote_ink) = (geometry.clone(), remote_ink.clone());
+        supervisor.supervise(
+            Subsystem::Remote,
+            ShutdownStage::StopInput,
+            move |mut stop| {
+                let mut viewer =
+                    RemoteViewer::new(Mapper::new(mapping.clone()), remote_ink.clone());
+                viewer.set_hud(hud.clone());
+                let mut link = RemoteLink::new(
+                    address.clone(),
+                    RemoteMode::ViewOnly(Arc::new(Mutex::new(viewer))),
+                );
+                link.set_hud(hud.clone());
+                link.follow_geometry(&geometry);
+                async move {
+                    tokio::select! {
+                        _ = link.run(None) => {}
+                        _ = stop.wait() => {}
+                    }
+                    Ok(())
+                }
+            },
+        );
+    }
+
+    if plan.runs(Subsystem::Dispatch) {
+        let (focus, connected, pens) = (focus.clone(), connected.clone(), pens.clone());
+        let (config_bus, geometry, black_box, lifecycle, api, latency) = (
+            config_bus.clone(),
+            geometry.clone(),
+            black_box.clone(),
+            lifecycle.clone(),
+            api.clone(),
+            latency.clone(),
+        );
+        let (actions, actions_rx) = mpsc::unbounded_channel();
+        let parts = PipelineParts {
+            hud: plan.runs(Subsystem::Hud).then(|| hud.clone()),
+            mapping_view,
+            calibration,
+            trail: trail.clone(),
+            ..PipelineParts::new(actions)
+        };
+        let runner = spawn_actions(&config, &parts, &control, actions_rx);
+        // 路由器停止后动作的发送端都已丢弃, 执行者随之退出并销毁虚拟键盘
+        supervisor.coordinator().register(
+            ShutdownStage::DestroyVirtualDevices,
+            "virtual-keyboard",
+            move || async move {
+                runner.await?;
+                Ok(())
+            },
+        );
+        supervisor.supervise(
+            Subsystem::Dispatch,
+            ShutdownStage::FlushDispatch,
+            move |mut stop| {
+                let mut router = pipeline::router(&parts);
+                router.set_black_box(black_box.clone());
+                router.set_latency(latency.clone());
+                router.follow_config(&config_bus);
+                router.follow_geometry(&geometry);
+                router.follow_focus(&focus);
+                router.follow_devices(lifecycle.subscribe());
+                let mut sinks = Sinks::new(black_box.clone());
+                sinks.set_latency(latency.clone());
+                sinks.set_pens(pens.clone());
+                if let Some(api) = &api {
+                    sinks.set_api(api.clone());
+                }
+                // 先应用配置, 已经接入的数位板按它创建映射
+                if let Err(e) = router.reconfigure(config_bus.current()) {
+                    error!("无法应用配置: {e:#}");
+                }
+                // 重启前已经接入的数位板不会再收到接入事件, USB 和蓝牙都接着时两个连接都交给仲裁
+                for device in connected.lock().unwrap().iter() {
+                    router.connect_device(device);
+                    sinks.connect(device);
+                }
+                let devices = lifecycle.subscribe();
+                let input = input.clone();
+                async move {
+                    let mut input = input.lock().await;
+                    let (output, routed) = mpsc::channel(ROUTED_QUEUE_LEN);
+                    let sinks = tokio::spawn(sinks.run(routed, devices));
+                    let stopped = tokio::select! {
+                        _ = router.run_with(&mut input, output) => false,
+                        _ = stop.wait() => true,
+                    };
+                    // 路由器已经丢弃, 出口发完剩下的事件后销毁虚拟数位板
+                    sinks.await?;
+                    if !stopped {
+                        bail!("路由器意外停止");
+                    }
+                    Ok(())
+                }
+            },
+        );
+    }
+
+    if let Some(api) = api {
+        let listen = config.api.clone();
+        supervisor.supervise(
+            Subsystem::Api,
+            ShutdownStage::FlushDispatch,
+            move |mut stop| {
+                let (api, listen) = (api.clone(), listen.clone());
+                async move {
+                    let mut listeners = Vec::new();
+                    if let Some(path) = &listen.unix {
+                        listeners.push(api.serve_unix(path)?);
+                        info!("tabletd API: 监听 {}", path.display());
+                    }
+                    if let Some(address) = &listen.tcp {
+                        listeners.push(api.serve_tcp(address.as_str())?);
+                        info!("tabletd API: 监听 {address}");
+                    }
+                    let result = tokio::select! {
+                        _ = futures::future::select_all(listeners.iter_mut()) => {
+                            Err(anyhow!("tabletd API 停止监听"))
+                        }
+                        _ = stop.wait() => Ok(()),
+                    };
+                    for listener in listeners {
+                        listener.abort();
+                    }
+                    if let Some(path) = &listen.unix {
+                        let _ = std::fs::remove_file(path);
+                    }
+                    result
+                }
+            },
+        );
+    }
+
+    if plan.runs(Subsystem::Metrics)
+        && let Some(address) = daemon.metrics.clone()
+    {
+        supervisor.supervise(
+            Subsystem::Metrics,
+            ShutdownStage::FlushDispatch,
+            move |mut stop| {
+                let (address, latency) = (address.clone(), latency.clone());
+                async move {
+                    let mut listener = metrics::serve(address.as_str(), latency).await?;
+                    info!("metrics: 监听 {address}");
+                    let result = tokio::select! {
+                        _ = &mut listener => Err(anyhow!("metrics 端点停止监听")),
+                        _ = stop.wait() => Ok(()),
+                    };
+                    listener.abort();
+                    result
+                }
+            },
+        );
+    }
+
+    if plan.runs(Subsystem::Control) {
+        supervisor.supervise(
+            Subsystem::Control,
+            ShutdownStage::StopInput,
+            move |mut stop| {
+                let control = control.clone();
+                async move {
+                    let connection = dbus::serve(control).await?;
+                    info!("控制接口: 已在会话总线上注册 {}", dbus::BUS_NAME);
+                    stop.wait().await;
+                    drop(connection);
+                    Ok(())
+                }
+            },
+        );
+    }
+
+    if plan.runs(Subsystem::Overlay) {
+        let (geometry, hud_state) = (geometry.clone(), hud_state.clone());
+        let (config_bus, lifecycle, connected, pens) = (
+            config_bus.clone(),
+            lifecycle.clone(),
+            connected.clone(),
+            pens.clone(),
+        );
+        let mut layers = OverlayLayers::new(hud_state.clone());
+        layers.set_trail(trail);
+        layers.set_ink(remote_ink);
+        let startup = daemon.startup.clone();
+        let stacking = config.overlay.stacking.clone();
+        supervisor.supervise(
+            Subsystem::Overlay,
+            ShutdownStage::DestroyOverlays,
+            move |mut stop| {
+                let (geometry, hud_state, layers, display, stacking) = (
+                    geometry.clone(),
+                    hud_state.clone(),
+                    layers.clone(),
+                    display.clone(),
+                    stacking.clone(),
+                );
+                // 重启前已经接入的数位板不会再收到接入事件
+                let (pens, devices) = (pens.subscribe(), lifecycle.subscribe());
+                let mut connections = TabletConnections::new();
+                {
+                    let config = config_bus.current();
+                    let mut cursors = layers.cursors().lock().unwrap();
+                    *cursors = CursorManager::new();
+                    for device in connected.lock().unwrap().iter() {
+                        if connections.connect(device.clone()) {
+                            add_cursor(&mut cursors, device, &config);
+                        }
+                    }
+                }
+                let config_bus = config_bus.clone();
+                let policy = startup.wayland.clone();
+                let probe = {
+                    let display = display.clone();
+                    move || {
+                        let display = display.clone();
+                        async move { tokio::task::spawn_blocking(move || display.probe()).await? }
+                    }
+                };
+                async move {
+                    let ready = startup::wait_ready(Backend::Wayland, &policy, probe);
+                    let detail = tokio::select! {
+                        ready = ready => match ready {
+                            Ok(detail) => detail,
+                            Err(e) => {
+                                error!("{e:#}, 不再启动");
+                                return Ok(());
+                            }
+                        },
+                        _ = stop.wait() => return Ok(()),
+                    };
+                    info!("wayland 可用: {detail}");
+                    let overlay = WaylandOverlay::with_surface(
+                        geometry,
+                        SurfaceOptions {
+                            layer: stacking.layer.into(),
+                            ..SurfaceOptions::default()
+                        },
+                        OutputSelection::all(),
+                        OverlayStrategy::default(),
+                        display,
+                    );
+                    overlay.set_stacking(stacking).await?;
+                    overlay.set_renderer(layers.renderer()).await?;
+                    overlay.set_cursor_renderer(layers.cursor_renderer()).await?;
+                    let redraw = overlay.redraw_handle();
+                    hud_state.lock().unwrap().set_redraw(redraw.clone());
+                    tokio::select! {
+                        _ = overlay.closed() => return Err(OverlayError::Stopped.into()),
+                        _ = follow_cursors(&layers, connections, (pens, devices), &config_bus, &redraw) => {}
+                        _ = stop.wait() => {}
+                    }
+                    overlay.destroy_surfaces().await.context("无法销毁 overlay")
+                }
+            },
+        );
+    }
+
+    if plan.runs(Subsystem::Hud) {
+        let (hud_rx, hud_state) = (hud_rx.clone(), hud_state.clone());
+        supervisor.supervise(
+            Subsystem::Hud,
+            ShutdownStage::DestroyOverlays,
+            move |mut stop| {
+                let (hud_rx, hud_state) = (hud_rx.clone(), hud_state.clone());
+                async move {
+                    let mut hud_rx = hud_rx.lock().await;
+                    loop {
+                        tokio::select! {
+                            event = hud_rx.recv() => match event {
+                                Some(event) => hud_state.lock().unwrap().apply(event),
+                                None => return Ok(()),
+                            },
+                            _ = stop.wait() => return Ok(()),
+                        }
+                    }
+                }
+            },
+        );
+    } else {
+        // 没有人接收时发往 HUD 的事件直接丢弃, 不会堆积
+        drop(hud_rx);
+    }
+
+    supervisor.run().await
+}
+
+/// 按出口发来的笔事件移动光标, 数位板接入和断开时增删光标. 不会返回
+async fn follow_cursors(
+    layers: &OverlayLayers,
+    mut connections: TabletConnections,
+    (pens, devices): (
+        broadcast::Receiver<RoutedEvent>,
+        broadcast::Receiver<DeviceEvent>,
+    ),
+    config_bus: &ConfigBus,
+    redraw: &RedrawHandle,
+) {
+    let (mut pens, mut devices) = (Some(pens), Some(devices));
+    loop {
+        tokio::select! {
+            Some(routed) = next_broadcast(&mut pens) => {
+                if layers.update_cursor(&routed) {
+                    redraw.request();
+                } else {
+                    redraw.request_cursor();
+                }
+            }
+            Some(device) = next_broadcast(&mut devices) => {
+                let mut cursors = layers.cursors().lock().unwrap();
+                match device {
+                    DeviceEvent::Connected(device) => {
+                        if connections.connect(device.clone()) {
+                            add_cursor(&mut cursors, &device, &config_bus.current());
+                        }
+                    }
+                    // 还有其他连接时光标继续使用
+                    DeviceEvent::Disconnected(device) => {
+                        if connections.disconnect(&device) {
+                            cursors.remove(device.tablet);
+                        }
+                    }
+                }
+                redraw.request();
+            }
+            // 发送端都已关闭, 光标不再变化
+            else => std::future::pending().await,
+        }
+    }
+}
+
+/// 为数位板添加光标, 颜色和标签来自它的设置
+fn add_cursor(cursors: &mut CursorManager, device: &ConnectedDevice, config: &Config) {
+    let profile = config.profile(device.tablet);
+    let color = profile.cursor_color.as_deref().and_then(|hex| {
+        let color = Color::from_hex(hex);
+        if color.is_none() {
+            warn!("{} 的光标颜色 {hex} 无效", device.tablet);
+        }
+        color
+    });
+    cursors.add(
+        device.tablet,
+        device.capabilities.clone(),
+        color,
+        profile.cursor_label.clone(),
+    );
+}
+
+/// 等待下一条广播, 落后时跳过丢失的部分. 发送端关闭后返回 `None`, 之后永远等待
+async fn next_broadcast<T: Clone>(rx: &mut Option<broadcast::Receiver<T>>) -> Option<T> {
+    let Some(receiver) = rx.as_mut() else {
+        return std::future::pending().await;
+    };
+    loop {
+        match receiver.recv().await {
+            Ok(value) => return Some(value),
+            Err(broadcast::error::RecvError::Lagged(_)) => {}
+            Err(broadcast::error::RecvError::Closed) => {
+                *rx = None;
+                return None;
+            }
+        }
+    }
+}
+
+/// 在路由器之外执行绑定触发的动作, 路由器重启时动作不会丢失
+///
+/// 返回的任务在动作的发送端都丢弃后结束, 结束时虚拟键盘已经销毁
+fn spawn_actions(
+    config: &Config,
+    parts: &PipelineParts,
+    control: &ControlState,
+    actions: mpsc::UnboundedReceiver<Triggered>,
+) -> JoinHandle<()> {
+    let mut runner = ActionRunner::new();
+    match VirtualKeyboard::new() {
+        Ok(keyboard) => runner.set_keyboard(keyboard),
+        Err(e) => warn!("无法创建虚拟键盘, 组合键和滚动不可用: {e:#}"),
+    }
+    if let Some(hud) = &parts.hud {
+        runner.set_hud(hud.clone());
+    }
+    runner.set_capture(parts.capture.clone());
+    runner.set_exec_policy(config.exec.clone());
+    let (profiles, mut switches) = mpsc::unbounded_channel::<ProfileSwitch>();
+    runner.set_profiles(profiles);
+    let runner = actions::spawn(runner, actions);
+
+    // 切换设置会读取设置文件, 不在执行动作的线程中等待
+    let control = control.clone();
+    tokio::spawn(async move {
+        while let Some(switch) = switches.recv().await {
+            if let ControlResponse::Error { message } = control.handle(switch.into()) {
+                warn!("无法切换设置: {message}");
+            }
+        }
+    });
+    runner
+}
+
+/// 读取设备描述文件中额外支持的数位板、按键设备和设备怪癖, 文件不存在时只使用内置的设备
+fn device_specs() -> (Vec<DeviceSpec>, Vec<KeypadSpec>, Vec<DeviceQuirk>) {
+    let path = DeviceSpec::default_path();
+    if !path.exists() {
+        return (Vec::new(), Vec::new(), Vec::new());
+    }
+    let load = || {
+        anyhow::Ok((
+            DeviceSpec::load(&path)?,
+            KeypadSpec::load(&path)?,
+            DeviceQuirk::load(&path)?,
+        ))
+    };
+    match load() {
+        Ok(specs) => specs,
+        Err(e) => {
+            warn!("无法读取设备描述文件 {}: {e:#}", path.display());
+            (Vec::new(), Vec::new(), Vec::new())
+        }
+    }
+}
+
+/// 允许发送了 `token` 的客户端同步设置, 收到的设置写入配置目录后重新加载配置
+fn enable_sync(api: &mut ApiServer, token: &str, config_bus: &ConfigBus) {
+    if token.is_empty() {
+        warn!("[api] sync_token 为空, 不开启设置同步");
+        return;
+    }
+    let storage = Arc::new(FileStorage::default());
+    let sync = match ProfileSync::load(storage, ProfileSync::default_path()) {
+        Ok(sync) => sync,
+        Err(e) => {
+            warn!("无法读取设置的同步记录, 不开启设置同步: {e:#}");
+            return;
+        }
+    };
+    sync.set_reload(Config::default_path(), config_bus.clone());
+    api.set_sync(sync, token);
+    info!("tabletd API: 已开启设置同步");
+}
+
+/// 启动蓝牙后端需要的东西, 蓝牙可用后才创建 [`BleBackend`]
+struct BleStart {
+    specs: Vec<DeviceSpec>,
+    identities: Arc<Mutex<IdentityRegistry>>,
+    events: EventSender,
+    lifecycle: broadcast::Sender<DeviceEvent>,
+    hud: HudSender,
+    api: Option<ApiServer>,
+    stats: DeviceStats,
+}
+
+impl BleStart {
+    async fn run(self) -> anyhow::Result<()> {
+        let backend = BleBackend::new(self.specs)
+            .await
+            .context("无法打开蓝牙适配器")?;
+        let mut watcher = BleWatcher::new(backend, self.identities, self.events);
+        watcher.set_lifecycle(self.lifecycle);
+        watcher.set_stats(self.stats);
+        watcher.set_hud(self.hud);
+        if let Some(api) = self.api {
+            watcher.set_api(api);
+        }
+        watcher.run().await
+    }
+}
+
+/// 记录当前接入的数位板
+///
+/// 同时更新控制接口看到的数位板. 它按数位板而不是按连接计数:
+/// 同一块数位板的第二个连接接入时不重复添加, 还有连接时断开一个也不移除
+fn track_devices(
+    lifecycle: &broadcast::Sender<DeviceEvent>,
+    control: &ControlState,
+    geometry: &GeometryBus,
+) -> Arc<Mutex<TabletConnections>> {
+    let connected = Arc::new(Mutex::new(TabletConnections::new()));
+    let mut rx = lifecycle.subscribe();
+    let devices = connected.clone();
+    let (control, geometry) = (control.clone(), geometry.clone());
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(DeviceEvent::Connected(device)) => {
+                    if !devices.lock().unwrap().connect(device.clone()) {
+                        continue;
+                    }
+                    let config = control.config.current();
+                    let mut mapper = Mapper::new(config.profile(device.tablet).mapping.clone());
+                    mapper.apply_geometry(&geometry.current());
+                    control.tablets.lock().unwrap().insert(
+                        device.tablet,
+                        TabletStatus {
+                            name: device.name.clone(),
+                            transport: device.transport,
+                            capabilities: device.capabilities.clone(),
+                            mapper,
+                        },
+                    );
+                }
+                Ok(DeviceEvent::Disconnected(device)) => {
+                    let mut devices = devices.lock().unwrap();
+                    if !devices.disconnect(&device) {
+                        // 路由器已经切换到剩下的连接
+                        if let Some(remaining) = devices.get(device.tablet)
+                            && let Some(status) =
+                                control.tablets.lock().unwrap().get_mut(&device.tablet)
+                        {
+                            status.transport = remaining.transport;
+                        }
+                        continue;
+                    }
+                    control.tablets.lock().unwrap().remove(&device.tablet);
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+    connected
+}
@@ -0,0 +1,126 @@
+//! 各模块的错误类型
+//!
+//! 模块内部仍然用 `anyhow` 添加上下文, 模块的边界上(驱动、分发、overlay)返回模块自己的错误类型,
+//! 它们都可以转换成 [`TabletdError`]. 守护进程按 [`TabletdError::is_recoverable`] 决定是否重启
+//! 出错的子系统, `tabletd API` 把错误转换成 [`ErrorReport`] 发给客户端
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    event_dispatcher::error::DispatchError, event_model::tablet::TabletId,
+    screen_overlay::error::OverlayError, tablet_driver::error::DriverError,
+};
+
+/// 守护进程的错误
+#[derive(Debug, Error)]
+pub enum TabletdError {
+    #[error(transparent)]
+    Overlay(#[from] OverlayError),
+    #[error(transparent)]
+    Driver(#[from] DriverError),
+    #[error(transparent)]
+    Dispatch(#[from] DispatchError),
+    /// 不属于以上模块的错误, 当作可以恢复
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+/// 错误的种类, 客户端按它决定怎样提示用户
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ErrorKind {
+    /// 数位板断开或者读取失败
+    Device,
+    /// 没有设备节点的权限, 需要安装 udev 规则
+    Permission,
+    /// 合成器或者后端不支持需要的功能, 需要修改配置
+    Unsupported,
+    /// overlay 出错
+    Overlay,
+    /// 写入虚拟设备失败
+    Dispatch,
+    Other,
+}
+
+impl TabletdError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            TabletdError::Overlay(e) => e.kind(),
+            TabletdError::Driver(e) => e.kind(),
+            TabletdError::Dispatch(e) => e.kind(),
+            TabletdError::Other(_) => ErrorKind::Other,
+        }
+    }
+
+    /// 重试(重新启动子系统、重新接入设备)有可能恢复. 权限和配置的问题重试也没有用
+    pub fn is_recoverable(&self) -> bool {
+        !matches!(self.kind(), ErrorKind::Permission | ErrorKind::Unsupported)
+    }
+}
+
+impl From<anyhow::Error> for TabletdError {
+    /// 取出 `anyhow` 错误中的模块错误类型. 外层的上下文会丢掉, 需要完整信息时先格式化原来的错误
+    fn from(error: anyhow::Error) -> Self {
+        let error = match error.downcast::<OverlayError>() {
+            Ok(e) => return e.into(),
+            Err(error) => error,
+        };
+        let error = match error.downcast::<DriverError>() {
+            Ok(e) => return e.into(),
+            Err(error) => error,
+        };
+        match error.downcast::<DispatchError>() {
+            Ok(e) => e.into(),
+            Err(error) => TabletdError::Other(error),
+        }
+    }
+}
+
+impl From<postcard::Error> for TabletdError {
+    /// 事件的编解码错误
+    fn from(error: postcard::Error) -> Self {
+        TabletdError::Other(error.into())
+    }
+}
+
+/// 发给 `tabletd API` 客户端的错误
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ErrorReport {
+    pub kind: ErrorKind,
+    pub recoverable: bool,
+    /// 出错的数位板, 和某块数位板无关时为 `None`
+    pub tablet: Option<TabletId>,
+    /// 出错的子系统, 见 [`crate::daemon::Subsystem::name`]
+    pub subsystem: Option<String>,
+    /// 包括所有上下文的说明
+    pub message: String,
+}
+
+impl ErrorReport {
+    /// `error` 只用来分类, 说明来自 `message`
+    pub fn new(error: &TabletdError, message: String) -> Self {
+        Self {
+            kind: error.kind(),
+            recoverable: error.is_recoverable(),
+            tablet: None,
+            subsystem: None,
+            message,
+        }
+    }
+
+    /// 保留 `anyhow` 错误的上下文
+    pub fn from_anyhow(error: anyhow::Error) -> Self {
+        let message = format!("{error:#}");
+        Self::new(&error.into(), message)
+    }
+
+    pub fn with_tablet(mut self, tablet: TabletId) -> Self {
+        self.tablet = Some(tablet);
+        self
+    }
+
+    pub fn with_subsystem(mut self, subsystem: &str) -> Self {
+        self.subsystem = Some(subsystem.to_string());
+        self
+    }
+}
@@ -0,0 +1,166 @@
+//! 执行绑定触发的动作
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+use anyhow::Context;
+use tokio::{sync::mpsc, task::JoinHandle};
+use tracing::warn;
+
+use crate::{
+    event_model::{event::WheelDirection, tablet::TabletId},
+    event_router::bindings::Triggered,
+    hud_interface::{
+        HudEvent, HudSender,
+        osd::{Osd, OsdIcon},
+        wheel_ring::WheelTurn,
+    },
+    profile::{
+        binding::{Action, ProfileAssignment},
+        wheel,
+    },
+};
+
+use super::{
+    exec::ExecPolicy,
+    keyboard::{KeyCombo, VirtualKeyboard},
+};
+
+/// 关闭窗口使用的组合键
+const CLOSE_WINDOW_KEYS: &str = "alt+f4";
+
+/// 请求切换设置
+#[derive(Debug, Clone)]
+pub enum ProfileSwitch {
+    /// 把触发绑定的数位板切换到另一套设置
+    Tablet { tablet: TabletId, profile: String },
+    /// 同时切换多块数位板, 见 [`Action::SwitchProfiles`]
+    Group {
+        name: Option<String>,
+        profiles: Vec<ProfileAssignment>,
+    },
+}
+
+/// 动作的执行者
+///
+/// 组合键通过虚拟键盘发送，HUD 相关的动作发给 HUD, 切换设置的请求发给设置的管理者
+#[derive(Default)]
+pub struct ActionRunner {
+    keyboard: Option<VirtualKeyboard>,
+    hud: Option<HudSender>,
+    /// HUD 打开时拦截事件的开关
+    capture: Option<Arc<AtomicBool>>,
+    profiles: Option<mpsc::UnboundedSender<ProfileSwitch>>,
+    exec: ExecPolicy,
+}
+
+impl ActionRunner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_keyboard(&mut self, keyboard: VirtualKeyboard) {
+        self.keyboard = Some(keyboard);
+    }
+
+    pub fn set_hud(&mut self, hud: HudSender) {
+        self.hud = Some(hud);
+    }
+
+    /// 打开 HUD 时同时打开 `capture`, 通常来自 [`crate::event_router::capture::Capture::switch`]
+    pub fn set_capture(&mut self, capture: Arc<AtomicBool>) {
+        self.capture = Some(capture);
+    }
+
+    pub fn set_profiles(&mut self, profiles: mpsc::UnboundedSender<ProfileSwitch>) {
+        self.profiles = Some(profiles);
+    }
+
+    /// 执行命令的规则
+    pub fn set_exec_policy(&mut self, policy: ExecPolicy) {
+        self.exec = policy;
+    }
+
+    fn send_hud(&self, event: HudEvent) {
+        if let Some(hud) = self.hud.as_ref() {
+            let _ = hud.send(event);
+        }
+    }
+
+    fn keys(&self, keys: &str) -> anyhow::Result<()> {
+        let combo = KeyCombo::parse(keys)?;
+        let keyboard = self.keyboard.as_ref().context("没有虚拟键盘")?;
+        keyboard
+            .press(&combo)
+            .with_context(|| format!("无法发送 {keys}"))
+    }
+
+    /// 执行一个动作, 可能阻塞(比如调整音量)
+    pub fn execute(&mut self, triggered: &Triggered) -> anyhow::Result<()> {
+        let mut volume = None;
+        match &triggered.action {
+            Action::CloseWindow => self.keys(CLOSE_WINDOW_KEYS)?,
+            Action::RunCommand { command } => {
+                self.exec.spawn(command)?;
+            }
+            Action::ToggleHistory => self.send_hud(HudEvent::ToggleHistory),
+            Action::ToggleDiagnostics => self.send_hud(HudEvent::ToggleDiagnostics),
+            Action::ToggleMappingOverlay => self.send_hud(HudEvent::ToggleMappingOverlay),
+            Action::Keys { keys } => self.keys(keys)?,
+            Action::Volume { step } => volume = Some(wheel::adjust_volume(*step)?),
+            Action::ToggleHud => {
+                if let Some(capture) = self.capture.as_ref() {
+                    capture.fetch_xor(true, Ordering::Relaxed);
+                }
+                self.send_hud(HudEvent::ToggleHud);
+            }
+            Action::Scroll { amount } => {
+                let keyboard = self.keyboard.as_ref().context("没有虚拟键盘")?;
+                keyboard.scroll(*amount).context("无法滚动")?;
+            }
+            Action::SwitchProfile { profile } => {
+                if let Some(profiles) = self.profiles.as_ref() {
+                    let _ = profiles.send(ProfileSwitch::Tablet {
+                        tablet: triggered.tablet,
+                        profile: profile.clone(),
+                    });
+                }
+            }
+            Action::SwitchProfiles { name, profiles } => {
+                if let Some(sender) = self.profiles.as_ref() {
+                    let _ = sender.send(ProfileSwitch::Group {
+                        name: name.clone(),
+                        profiles: profiles.clone(),
+                    });
+                }
+            }
+        }
+        if let Some(wheel) = &triggered.wheel {
+            let osd = match wheel.preset {
+                Some(preset) => preset.osd(&wheel.direction, volume),
+                None => Osd::new(OsdIcon::Wheel, triggered.action.describe(), volume),
+            };
+            self.send_hud(HudEvent::WheelTurned(WheelTurn {
+                osd,
+                clockwise: matches!(wheel.direction, WheelDirection::Clockwise),
+            }));
+        }
+        Ok(())
+    }
+}
+
+/// 在阻塞线程中执行 `actions` 中的动作, 单个动作失败不会停止
+pub fn spawn(
+    mut runner: ActionRunner,
+    mut actions: mpsc::UnboundedReceiver<Triggered>,
+) -> JoinHandle<()> {
+    tokio::task::spawn_blocking(move || {
+        while let Some(triggered) = actions.blocking_recv() {
+            if let Err(e) = runner.execute(&triggered) {
+                warn!("无法执行 {}: {e:#}", triggered.action.describe());
+            }
+        }
+    })
+}
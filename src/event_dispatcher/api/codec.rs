@@ -0,0 +1,61 @@
+use serde::{Serialize, de::DeserializeOwned};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// 协议版本, 消息格式不兼容时加一
+pub const PROTOCOL_VERSION: u16 = 17;
+
+/// 单个帧的最大长度
+pub const MAX_FRAME_LEN: usize = 64 * 1024;
+
+/// 编码一帧: 4 字节大端长度 + postcard 编码的消息
+pub fn encode_frame<T: Serialize>(message: &T) -> anyhow::Result<Vec<u8>> {
+    let payload = postcard::to_stdvec(message)?;
+    anyhow::ensure!(payload.len() <= MAX_FRAME_LEN, "帧过长: {}", payload.len());
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&payload);
+    Ok(frame)
+}
+
+/// 解码一个完整的帧, 长度前缀和实际长度不一致时返回错误
+pub fn decode_frame<T: DeserializeOwned>(frame: &[u8]) -> anyhow::Result<T> {
+    let (len, payload) = frame
+        .split_first_chunk::<4>()
+        .ok_or_else(|| anyhow::anyhow!("帧过短: {}", frame.len()))?;
+    let len = u32::from_be_bytes(*len) as usize;
+    anyhow::ensure!(len <= MAX_FRAME_LEN, "帧过长: {len}");
+    anyhow::ensure!(
+        len == payload.len(),
+        "帧长度不一致: 声明 {len}, 实际 {}",
+        payload.len()
+    );
+    Ok(postcard::from_bytes(payload)?)
+}
+
+/// 写入一帧, 格式见 [`encode_frame`]
+pub async fn write_frame<W, T>(writer: &mut W, message: &T) -> anyhow::Result<()>
+where
+    W: AsyncWrite + Unpin,
+    T: Serialize,
+{
+    writer.write_all(&encode_frame(message)?).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// 读取一帧, 对端正常关闭时返回 `None`
+pub async fn read_frame<R, T>(reader: &mut R) -> anyhow::Result<Option<T>>
+where
+    R: AsyncRead + Unpin,
+    T: DeserializeOwned,
+{
+    let len = match reader.read_u32().await {
+        Ok(len) => len as usize,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    anyhow::ensure!(len <= MAX_FRAME_LEN, "帧过长: {len}");
+    let mut payload = vec![0; len];
+    reader.read_exact(&mut payload).await?;
+    Ok(Some(postcard::from_bytes(&payload)?))
+}
@@ -0,0 +1,107 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    event_model::{
+        event::{PenLocation, TabletEvent},
+        tablet::TabletId,
+    },
+    event_router::RoutedEvent,
+};
+
+/// 事件的种类
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EventKind {
+    Pen,
+    AuxButton,
+    Wheel,
+    Ring,
+    /// 笔进入感应范围时的序列号
+    ToolIn,
+    /// 笔杆上的按键
+    PenButton,
+    /// 笔离开感应范围时的序列号
+    ToolOut,
+}
+
+impl EventKind {
+    fn of(event: &TabletEvent) -> Option<EventKind> {
+        match event {
+            TabletEvent::PenEvent(_) => Some(EventKind::Pen),
+            TabletEvent::AuxButton(_) => Some(EventKind::AuxButton),
+            TabletEvent::Wheel(_) => Some(EventKind::Wheel),
+            TabletEvent::Ring(_) => Some(EventKind::Ring),
+            TabletEvent::ToolIn(_) => Some(EventKind::ToolIn),
+            TabletEvent::PenButton(_) => Some(EventKind::PenButton),
+            TabletEvent::ToolOut(_) => Some(EventKind::ToolOut),
+            TabletEvent::Unknown => None,
+        }
+    }
+}
+
+/// 订阅时设置的过滤条件, 服务端在编码之前检查，不符合的事件不会发送
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EventFilter {
+    /// 只接收这些数位板的事件, 为空时接收所有数位板
+    pub tablets: Vec<TabletId>,
+    /// 只接收这些种类的事件, 为空时接收所有种类
+    pub kinds: Vec<EventKind>,
+    /// 只接收压力不低于这个值的笔事件(悬浮时压力为 0, 也会被过滤).
+    /// 笔的状态变化(落笔、抬笔、离开)总是发送
+    pub min_pressure: Option<u32>,
+    /// 每块数位板每秒最多发送的笔移动事件数. 笔的状态变化(落笔、抬笔、离开)总是发送
+    pub max_rate: Option<u32>,
+    /// 不接收被 tabletd 消费的事件
+    pub skip_consumed: bool,
+}
+
+/// 每个客户端的过滤状态
+#[derive(Debug, Default)]
+pub struct FilterState {
+    /// 每块数位板上次发送笔事件的时间和笔的状态
+    last_pen: HashMap<TabletId, (Instant, PenLocation)>,
+}
+
+impl EventFilter {
+    /// 检查事件是否应该发送给客户端, 会更新限速状态
+    pub fn accepts(&self, event: &RoutedEvent, state: &mut FilterState, now: Instant) -> bool {
+        if self.skip_consumed && event.is_consumed() {
+            return false;
+        }
+        if !self.tablets.is_empty() && !self.tablets.contains(&event.tablet) {
+            return false;
+        }
+        if !self.kinds.is_empty()
+            && !EventKind::of(&event.event).is_some_and(|kind| self.kinds.contains(&kind))
+        {
+            return false;
+        }
+        let TabletEvent::PenEvent(pen) = &event.event else {
+            return true;
+        };
+        // 和上次发送的笔事件状态相同时才是移动, 可以过滤
+        let last = state.last_pen.get(&event.tablet).filter(|(_, location)| {
+            std::mem::discriminant(location) == std::mem::discriminant(&pen.location)
+        });
+        let Some((sent_at, _)) = last else {
+            state.last_pen.insert(event.tablet, (now, pen.location));
+            return true;
+        };
+        if let Some(min) = self.min_pressure
+            && pen.pressure < min
+        {
+            return false;
+        }
+        if let Some(rate) = self.max_rate.filter(|rate| *rate > 0)
+            && now.saturating_duration_since(*sent_at) < Duration::from_secs(1) / rate
+        {
+            return false;
+        }
+        state.last_pen.insert(event.tablet, (now, pen.location));
+        true
+    }
+}
@@ -0,0 +1,489 @@
+use std::{
+    net::ToSocketAddrs,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Instant,
+};
+
+use anyhow::anyhow;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, UnixListener},
+    sync::{broadcast, mpsc, watch},
+    task::JoinHandle,
+};
+use tracing::{info, warn};
+
+use crate::{
+    error::ErrorReport,
+    event_model::{
+        capability::DeviceCapabilities, coordinate::ScreenMapping, event::TabletEvent,
+        tablet::TabletId,
+    },
+    event_router::{
+        RoutedEvent,
+        black_box::{BlackBox, RecordKind},
+    },
+    input_devices::hotplug::{DeviceEvent, TabletConnections},
+    mapping::{
+        ScreenPoint,
+        geometry::{GeometryBus, GeometryChanged},
+    },
+    profile::sync::ProfileSync,
+    units,
+};
+
+use filter::FilterState;
+use protocol::{ApiEvent, ClientMessage, Handshake, ServerMessage, Subscription, TabletInfo};
+
+/// 帧编解码
+pub mod codec;
+/// 订阅的过滤条件
+pub mod filter;
+/// 客户端与服务端之间的消息
+pub mod protocol;
+
+/// 事件广播队列的长度，客户端落后太多时会丢弃旧事件
+const EVENT_QUEUE_LEN: usize = 1024;
+/// 数位板接入和断开通知的队列长度
+const LIFECYCLE_QUEUE_LEN: usize = 64;
+
+/// 已接入的数位板, 握手时告诉客户端, 转换坐标时按 `event.tablet` 查找能力
+#[derive(Default)]
+struct ApiContext {
+    tablets: Vec<TabletInfo>,
+}
+
+impl ApiContext {
+    fn capabilities(&self, tablet: TabletId) -> Option<&DeviceCapabilities> {
+        self.tablets
+            .iter()
+            .find(|info| info.id == tablet)
+            .map(|info| &info.capabilities)
+    }
+}
+
+/// `tabletd API` 服务端
+///
+/// 把数位板事件转发给所有已订阅的客户端，每个客户端有自己的订阅设置
+#[derive(Clone)]
+pub struct ApiServer {
+    events: broadcast::Sender<RoutedEvent>,
+    context: Arc<RwLock<ApiContext>>,
+    geometry: GeometryBus,
+    /// [`ServerMessage::TabletAdded`]、[`ServerMessage::TabletRemoved`] 和 [`ServerMessage::Error`]
+    lifecycle: broadcast::Sender<ServerMessage>,
+    /// 开启时响应通过认证的客户端的 [`ClientMessage::Sync`], 附带口令
+    sync: Option<(ProfileSync, Arc<str>)>,
+    /// 记录事件是否发给了客户端
+    black_box: Option<BlackBox>,
+}
+
+impl ApiServer {
+    pub fn new() -> Self {
+        Self::with_geometry(GeometryBus::new())
+    }
+
+    /// 把 `geometry` 上的显示器布局变化转发给客户端
+    pub fn with_geometry(geometry: GeometryBus) -> Self {
+        let (events, _) = broadcast::channel(EVENT_QUEUE_LEN);
+        Self {
+            events,
+            context: Arc::new(RwLock::new(ApiContext::default())),
+            geometry,
+            lifecycle: broadcast::channel(LIFECYCLE_QUEUE_LEN).0,
+            sync: None,
+            black_box: None,
+        }
+    }
+
+    /// 和连接上来的另一台 tabletd 同步设置, 需要在开始监听之前设置
+    ///
+    /// 客户端要先用 [`ClientMessage::Auth`] 发送 `token`, 否则不能写入设置
+    pub fn set_sync(&mut self, sync: ProfileSync, token: impl Into<Arc<str>>) {
+        self.sync = Some((sync, token.into()));
+    }
+
+    /// 把每个事件是否发给了客户端记在 [`crate::event_router::Router::black_box`] 中
+    pub fn set_black_box(&mut self, black_box: BlackBox) {
+        self.black_box = Some(black_box);
+    }
+
+    /// 默认的 Unix socket 路径: `$XDG_RUNTIME_DIR/tabletd.sock`
+    pub fn default_socket_path() -> PathBuf {
+        std::env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir)
+            .join("tabletd.sock")
+    }
+
+    /// 按 `devices` 中的接入和断开增删数位板, 同一块数位板有多个连接时只算一次
+    pub fn follow_devices(&self, mut devices: broadcast::Receiver<DeviceEvent>) -> JoinHandle<()> {
+        let server = self.clone();
+        tokio::spawn(async move {
+            let mut connections = TabletConnections::new();
+            loop {
+                match devices.recv().await {
+                    Ok(DeviceEvent::Connected(device)) => {
+                        if connections.connect(device.clone()) {
+                            server.add_tablet(device.info());
+                        }
+                    }
+                    Ok(DeviceEvent::Disconnected(device)) => {
+                        if connections.disconnect(&device) {
+                            server.remove_tablet(device.tablet);
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("tabletd API: 错过了 {n} 个设备接入或断开事件");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+
+    /// 数位板接入后调用, 之后握手的客户端会在列表中看到它，已连接的客户端收到通知
+    pub fn add_tablet(&self, info: TabletInfo) {
+        {
+            let mut context = self.context.write().unwrap();
+            if context.tablets.iter().any(|tablet| tablet.id == info.id) {
+                return;
+            }
+            context.tablets.push(info.clone());
+        }
+        let _ = self.lifecycle.send(ServerMessage::TabletAdded(info));
+    }
+
+    /// 数位板断开后调用
+    pub fn remove_tablet(&self, id: TabletId) {
+        {
+            let mut context = self.context.write().unwrap();
+            let before = context.tablets.len();
+            context.tablets.retain(|tablet| tablet.id != id);
+            if context.tablets.len() == before {
+                return;
+            }
+        }
+        let _ = self.lifecycle.send(ServerMessage::TabletRemoved(id));
+    }
+
+    /// 把错误发给所有已连接的客户端
+    pub fn report_error(&self, report: ErrorReport) {
+        let _ = self.lifecycle.send(ServerMessage::Error(report));
+    }
+
+    /// 把事件发给所有客户端，包括被 tabletd 消费的事件
+    pub fn publish(&self, event: RoutedEvent) {
+        let Some(black_box) = &self.black_box else {
+            // 没有客户端时发送会失败，这没关系
+            let _ = self.events.send(event);
+            return;
+        };
+        let (tablet, stamp) = (event.tablet, event.stamp);
+        let kind = match self.events.send(event) {
+            Ok(_) => RecordKind::Delivered { sink: "api" },
+            Err(_) => RecordKind::Skipped {
+                sink: "api",
+                reason: "没有客户端".to_string(),
+            },
+        };
+        black_box.record(tablet, stamp, kind);
+    }
+
+    /// 在 Unix socket 上监听客户端连接
+    pub fn serve_unix(&self, path: impl AsRef<Path>) -> std::io::Result<JoinHandle<()>> {
+        let path = path.as_ref();
+        // 清理上次没删掉的 socket 文件
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        let server = self.clone();
+        Ok(tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        server.spawn_client(stream);
+                    }
+                    Err(e) => {
+                        warn!("tabletd API: 接受连接失败: {e}");
+                        break;
+                    }
+                }
+            }
+        }))
+    }
+
+    /// 在 TCP 端口上监听客户端连接
+    ///
+    /// TCP 没有访问控制, 只应该监听在可信的网络上
+    pub fn serve_tcp(&self, address: impl ToSocketAddrs) -> std::io::Result<JoinHandle<()>> {
+        let listener = std::net::TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        let listener = TcpListener::from_std(listener)?;
+        let server = self.clone();
+        Ok(tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, peer)) => {
+                        info!("tabletd API: {peer} 已连接");
+                        // 笔事件很小且需要低延迟
+                        if let Err(e) = stream.set_nodelay(true) {
+                            warn!("tabletd API: 无法设置 TCP_NODELAY: {e}");
+                        }
+                        server.spawn_client(stream);
+                    }
+                    Err(e) => {
+                        warn!("tabletd API: 接受连接失败: {e}");
+                        break;
+                    }
+                }
+            }
+        }))
+    }
+
+    /// 为一个已建立的连接启动处理任务
+    pub fn spawn_client<S>(&self, stream: S) -> JoinHandle<()>
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let events = self.events.subscribe();
+        let geometry = self.geometry.subscribe();
+        let lifecycle = self.lifecycle.subscribe();
+        let context = Arc::clone(&self.context);
+        let sync = self.sync.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_client(stream, events, geometry, lifecycle, context, sync).await {
+                warn!("tabletd API: 客户端连接出错: {e}");
+            }
+        })
+    }
+}
+
+impl Default for ApiServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+async fn serve_client<S>(
+    stream: S,
+    mut events: broadcast::Receiver<RoutedEvent>,
+    mut geometry: watch::Receiver<GeometryChanged>,
+    mut lifecycle: broadcast::Receiver<ServerMessage>,
+    context: Arc<RwLock<ApiContext>>,
+    sync: Option<(ProfileSync, Arc<str>)>,
+) -> anyhow::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (mut reader, mut writer) = tokio::io::split(stream);
+
+    let hello = ServerMessage::Hello(Handshake {
+        version: codec::PROTOCOL_VERSION,
+        tablets: context.read().unwrap().tablets.clone(),
+        units: units::current(),
+    });
+    codec::write_frame(&mut writer, &hello).await?;
+
+    // 读帧不能被 select! 打断，所以放到单独的任务里
+    let (message_tx, mut message_rx) = mpsc::channel(8);
+    let read_task = tokio::spawn(async move {
+        while let Ok(Some(message)) = codec::read_frame::<_, ClientMessage>(&mut reader).await {
+            if message_tx.send(message).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut subscription: Option<Subscription> = None;
+    let mut filter_state = FilterState::default();
+    let mut authenticated = false;
+    let result = loop {
+        tokio::select! {
+            message = message_rx.recv() => match message {
+                Some(ClientMessage::Subscribe(new)) => {
+                    let first = subscription.is_none();
+                    subscription = Some(new);
+                    if first {
+                        let mut messages: Vec<_> = context
+                            .read()
+                            .unwrap()
+                            .tablets
+                            .iter()
+                            .map(|info| ServerMessage::Capabilities(info.id, info.capabilities.clone()))
+                            .collect();
+                        messages.push(ServerMessage::Geometry(geometry.borrow_and_update().clone()));
+                        if let Err(e) = write_all(&mut writer, &messages).await {
+                            break Err(e);
+                        }
+                    }
+                }
+                Some(ClientMessage::Unsubscribe) => subscription = None,
+                Some(ClientMessage::Ping(seq)) => {
+                    if let Err(e) = codec::write_frame(&mut writer, &ServerMessage::Pong(seq)).await {
+                        break Err(e);
+                    }
+                }
+                Some(ClientMessage::Auth(token)) => {
+                    let Some((_, expected)) = sync.as_ref() else {
+                        continue;
+                    };
+                    if !token_matches(&token, expected) {
+                        break Err(anyhow!("同步口令错误"));
+                    }
+                    authenticated = true;
+                }
+                Some(ClientMessage::Sync(message)) => {
+                    let Some((sync, _)) = sync.as_ref() else {
+                        continue;
+                    };
+                    if !authenticated {
+                        warn!("tabletd API: 客户端没有认证, 忽略同步设置的请求");
+                        continue;
+                    }
+                    let replies = match sync.handle(message) {
+                        Ok(replies) => replies,
+                        // 同步失败不影响事件转发
+                        Err(e) => {
+                            warn!("tabletd API: 同步设置失败: {e:#}");
+                            continue;
+                        }
+                    };
+                    let replies: Vec<_> = replies.into_iter().map(ServerMessage::Sync).collect();
+                    if let Err(e) = write_all(&mut writer, &replies).await {
+                        break Err(e);
+                    }
+                }
+                // 客户端断开
+                None => break Ok(()),
+            },
+            changed = geometry.changed() => {
+                if changed.is_err() {
+                    break Ok(());
+                }
+                let message = ServerMessage::Geometry(geometry.borrow_and_update().clone());
+                if subscription.is_some()
+                    && let Err(e) = codec::write_frame(&mut writer, &message).await
+                {
+                    break Err(e);
+                }
+            }
+            // 接入和断开与订阅无关，所有客户端都需要知道
+            message = lifecycle.recv() => match message {
+                Ok(message) => {
+                    if let Err(e) = codec::write_frame(&mut writer, &message).await {
+                        break Err(e);
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("tabletd API: 客户端太慢，丢弃了 {skipped} 个数位板通知");
+                }
+                Err(broadcast::error::RecvError::Closed) => break Ok(()),
+            },
+            event = events.recv() => match event {
+                Ok(event) => {
+                    let Some(subscription) = subscription.as_ref() else {
+                        continue;
+                    };
+                    if !subscription.filter.accepts(&event, &mut filter_state, Instant::now()) {
+                        continue;
+                    }
+                    let event = convert(event, subscription, &context, &geometry.borrow());
+                    let message = ServerMessage::Event(event);
+                    if let Err(e) = codec::write_frame(&mut writer, &message).await {
+                        break Err(e);
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("tabletd API: 客户端太慢，丢弃了 {skipped} 个事件");
+                }
+                Err(broadcast::error::RecvError::Closed) => break Ok(()),
+            },
+        }
+    };
+
+    read_task.abort();
+    result
+}
+
+/// 比较口令, 耗时不随第一个不同字节的位置变化
+fn token_matches(token: &str, expected: &str) -> bool {
+    token.len() == expected.len()
+        && token
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+async fn write_all<W>(writer: &mut W, messages: &[ServerMessage]) -> anyhow::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    for message in messages {
+        codec::write_frame(writer, message).await?;
+    }
+    Ok(())
+}
+
+/// 按订阅要求转换坐标
+///
+/// 屏幕坐标使用路由器按数位板自己的映射算出的位置, 换算到订阅的显示器上
+fn convert(
+    RoutedEvent {
+        tablet,
+        event,
+        position: routed,
+        consumed_by,
+        stamp,
+        ..
+    }: RoutedEvent,
+    subscription: &Subscription,
+    context: &RwLock<ApiContext>,
+    geometry: &GeometryChanged,
+) -> ApiEvent {
+    let position = match &event {
+        TabletEvent::PenEvent(pen) => {
+            let context = context.read().unwrap();
+            let mapping = routed
+                .as_ref()
+                .map(|point| RoutedPosition { point, geometry });
+            context.capabilities(tablet).and_then(|caps| {
+                subscription.coordinates.convert(
+                    pen.x,
+                    pen.y,
+                    caps,
+                    mapping.as_ref().map(|m| m as &dyn ScreenMapping),
+                )
+            })
+        }
+        _ => None,
+    };
+    ApiEvent {
+        tablet,
+        event,
+        position,
+        consumed: consumed_by.is_some(),
+        stamp,
+    }
+}
+
+/// 路由器映射到屏幕上的点, 已经包含了校准和长宽比裁剪, 所以忽略传入的归一化坐标
+struct RoutedPosition<'a> {
+    point: &'a ScreenPoint,
+    geometry: &'a GeometryChanged,
+}
+
+impl ScreenMapping for RoutedPosition<'_> {
+    fn to_output(&self, output: &str, _x: f64, _y: f64) -> Option<(f64, f64)> {
+        let output = self.geometry.output(output)?;
+        Some(output.to_pixels(self.point.logical_x, self.point.logical_y))
+    }
+
+    fn output_size(&self, output: &str) -> Option<(f64, f64)> {
+        let output = self.geometry.output(output)?;
+        Some((output.width as f64, output.height as f64))
+    }
+}
@@ -0,0 +1,93 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::ErrorReport,
+    event_model::{
+        capability::DeviceCapabilities, coordinate::CoordinateFormat, event::TabletEvent,
+        stamp::EventStamp, tablet::TabletId,
+    },
+    mapping::geometry::GeometryChanged,
+    profile::sync::SyncMessage,
+    units::Units,
+};
+
+use super::filter::EventFilter;
+
+/// 客户端的订阅设置
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Subscription {
+    /// 客户端希望收到的坐标表示方式
+    pub coordinates: CoordinateFormat,
+    /// 过滤条件
+    pub filter: EventFilter,
+}
+
+/// 客户端发往服务端的消息
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ClientMessage {
+    /// 开始(或更新)订阅
+    Subscribe(Subscription),
+    /// 停止接收事件
+    Unsubscribe,
+    /// 心跳, 服务端用相同序号的 [`ServerMessage::Pong`] 回应
+    Ping(u32),
+    /// 同步设置, 服务端没有开启同步或者客户端还没有通过 [`ClientMessage::Auth`] 认证时忽略
+    Sync(SyncMessage),
+    /// 发送服务端 `[api] sync_token` 中的口令, 通过后才能同步设置. 口令错误时服务端断开连接
+    Auth(String),
+}
+
+/// 服务端提供的一块数位板
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TabletInfo {
+    pub id: TabletId,
+    pub name: String,
+    pub capabilities: DeviceCapabilities,
+}
+
+/// 连接建立后服务端发送的第一条消息
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Handshake {
+    /// 服务端的 [`PROTOCOL_VERSION`](super::codec::PROTOCOL_VERSION), 不一致时客户端应该断开
+    pub version: u16,
+    pub tablets: Vec<TabletInfo>,
+    /// 服务端使用的单位和地区, 客户端显示数值时可以沿用
+    pub units: Units,
+}
+
+/// 服务端发往客户端的事件
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiEvent {
+    pub tablet: TabletId,
+    pub event: TabletEvent,
+    /// 按订阅要求转换过的笔坐标, 无法转换时为 `None`
+    pub position: Option<(f64, f64)>,
+    /// 事件已被 tabletd 内部处理(比如 HUD 打开时), 客户端通常应该忽略它
+    pub consumed: bool,
+    /// 服务端读到这个事件的时间(服务端的 `CLOCK_MONOTONIC`)和序号,
+    /// 序号不连续说明中间有事件被过滤或者丢失了
+    pub stamp: EventStamp,
+}
+
+/// 服务端发往客户端的消息
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ServerMessage {
+    Event(ApiEvent),
+    /// 一块数位板的能力, 订阅时为每块已接入的数位板发送一次, 之后接入的数位板见
+    /// [`ServerMessage::TabletAdded`]. 客户端应该忽略设备不支持的字段(比如没有倾斜时的 `tilt`)
+    Capabilities(TabletId, DeviceCapabilities),
+    /// 显示器布局，订阅时发送一次，之后每次变化时发送
+    Geometry(GeometryChanged),
+    /// 握手, 连接建立后立刻发送
+    Hello(Handshake),
+    /// 对 [`ClientMessage::Ping`] 的回应
+    Pong(u32),
+    /// 握手之后接入的数位板
+    TabletAdded(TabletInfo),
+    /// 断开的数位板
+    TabletRemoved(TabletId),
+    /// 对 [`ClientMessage::Sync`] 的回应
+    Sync(SyncMessage),
+    /// 数位板或者子系统出错
+    Error(ErrorReport),
+}
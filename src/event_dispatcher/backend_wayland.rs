@@ -0,0 +1,335 @@
+//! 通过 Wayland tablet-v2 协议把笔输入交给原生 Wayland 程序
+//!
+//! tablet-v2 (`zwp_tablet_manager_v2` 等) 只能由混成器向客户端发送事件，客户端无法直接注入.
+//! 所以这个后端先创建 uinput 虚拟数位板，由混成器(通过 libinput)识别后再用 tablet-v2
+//! 转发给程序，程序收到的是完整的接近、落笔、压力和倾斜事件，而不是模拟的指针事件.
+//!
+//! 创建之前和之后都会检查混成器的 tablet-v2 支持，确认虚拟设备确实出现在 tablet seat 中.
+//!
+//! libinput 不把同时有笔的设备当作快捷键面板, 所以快捷键、滚轮和触控环写入另一个只有快捷键的
+//! 虚拟设备(`tabletd <名称> Pad`), 混成器把它作为 `zwp_tablet_pad_v2` 提供, 触控环成为 pad ring
+
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use tokio::{sync::mpsc, task::JoinHandle};
+use tracing::{info, info_span, warn};
+use wayland_client::{
+    Connection, Dispatch, QueueHandle, delegate_noop, event_created_child,
+    protocol::{wl_registry, wl_seat},
+};
+use wayland_protocols::wp::tablet::zv2::client::{
+    zwp_tablet_manager_v2, zwp_tablet_pad_group_v2, zwp_tablet_pad_ring_v2,
+    zwp_tablet_pad_strip_v2, zwp_tablet_pad_v2, zwp_tablet_seat_v2, zwp_tablet_tool_v2,
+    zwp_tablet_v2,
+};
+
+use crate::{
+    event_model::{
+        capability::DeviceCapabilities,
+        event::TabletEvent,
+        latency::{LatencyStage, LatencyStats},
+        stamp::monotonic_micros,
+    },
+    event_router::{RoutedEvent, black_box::BlackBox},
+};
+
+use super::{error::DispatchError, uinput::UinputTablet};
+
+/// 等待混成器识别虚拟设备的时间
+const APPEAR_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// 混成器的 tablet-v2 支持情况
+#[derive(Debug, Clone)]
+pub struct TabletV2Support {
+    pub manager_version: u32,
+    /// 混成器当前报告的数位板名称
+    pub tablets: Vec<String>,
+}
+
+#[derive(Default)]
+struct ProbeState {
+    manager: Option<(zwp_tablet_manager_v2::ZwpTabletManagerV2, u32)>,
+    seat: Option<wl_seat::WlSeat>,
+    tablets: Vec<String>,
+}
+
+/// 查询混成器的 tablet-v2 支持, 不支持时返回 `None`
+pub fn probe() -> anyhow::Result<Option<TabletV2Support>> {
+    let conn = Connection::connect_to_env().context("无法连接到 Wayland 混成器")?;
+    let mut queue = conn.new_event_queue();
+    let qhandle = queue.handle();
+    conn.display().get_registry(&qhandle, ());
+
+    let mut state = ProbeState::default();
+    queue
+        .roundtrip(&mut state)
+        .context("Wayland registry 查询失败")?;
+    let (Some((manager, version)), Some(seat)) = (state.manager.clone(), state.seat.clone()) else {
+        return Ok(None);
+    };
+
+    // tablet seat 在创建后立刻发送所有数位板
+    let tablet_seat = manager.get_tablet_seat(&seat, &qhandle, ());
+    queue
+        .roundtrip(&mut state)
+        .context("tablet seat 查询失败")?;
+    queue
+        .roundtrip(&mut state)
+        .context("tablet seat 查询失败")?;
+    tablet_seat.destroy();
+    manager.destroy();
+    conn.flush().ok();
+
+    Ok(Some(TabletV2Support {
+        manager_version: version,
+        tablets: state.tablets,
+    }))
+}
+
+/// 经过 tablet-v2 交给 Wayland 程序的虚拟数位板
+pub struct WaylandTablet {
+    device: UinputTablet,
+    /// 快捷键面板, 数位板本身就是按键设备时为 `None`
+    pad: Option<UinputTablet>,
+}
+
+impl WaylandTablet {
+    /// 创建虚拟数位板并等待混成器识别
+    ///
+    /// 混成器不支持 tablet-v2 时返回错误, 这时可以改用 [`UinputTablet`]
+    pub fn new(name: &str, capabilities: &DeviceCapabilities) -> anyhow::Result<Self> {
+        let Some(support) = probe()? else {
+            return Err(DispatchError::Unsupported("tablet-v2 (zwp_tablet_manager_v2)").into());
+        };
+        info!(
+            "混成器支持 tablet-v2 (版本 {}), 已有 {} 块数位板",
+            support.manager_version,
+            support.tablets.len()
+        );
+
+        let device = UinputTablet::new(name, capabilities)?;
+        let pad = if capabilities.is_keypad() {
+            None
+        } else {
+            Some(UinputTablet::new(
+                &format!("{name} Pad"),
+                &DeviceCapabilities::keypad(),
+            )?)
+        };
+        let expected = format!("tabletd {name}");
+        let started = Instant::now();
+        loop {
+            let tablets = probe()?.map(|support| support.tablets).unwrap_or_default();
+            if tablets.contains(&expected) {
+                info!("混成器已通过 tablet-v2 提供 {expected}");
+                break;
+            }
+            if started.elapsed() > APPEAR_TIMEOUT {
+                // 设备仍然可用，混成器可能只是没有报告名称
+                warn!("混成器没有在 tablet seat 中报告 {expected}, 程序可能只能收到指针事件");
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        Ok(Self { device, pad })
+    }
+
+    pub fn device(&mut self) -> &mut UinputTablet {
+        &mut self.device
+    }
+
+    /// 写入一个事件, 快捷键、滚轮和触控环写入快捷键面板
+    pub fn dispatch(&mut self, event: &TabletEvent) -> std::io::Result<()> {
+        match (event, self.pad.as_mut()) {
+            (
+                TabletEvent::AuxButton(_) | TabletEvent::Wheel(_) | TabletEvent::Ring(_),
+                Some(pad),
+            ) => pad.dispatch(event),
+            _ => self.device.dispatch(event),
+        }
+    }
+}
+
+/// 虚拟数位板, 混成器不支持 tablet-v2 时只有 uinput 设备
+enum Output {
+    TabletV2(WaylandTablet),
+    Uinput(UinputTablet),
+}
+
+impl Output {
+    /// 查询混成器后创建, 查询失败时同样只创建 uinput 设备
+    fn new(name: &str, capabilities: &DeviceCapabilities) -> anyhow::Result<Self> {
+        match probe() {
+            Ok(Some(_)) => Ok(Output::TabletV2(WaylandTablet::new(name, capabilities)?)),
+            Ok(None) => {
+                info!("混成器不支持 tablet-v2, 只创建 uinput 虚拟数位板");
+                Ok(Output::Uinput(UinputTablet::new(name, capabilities)?))
+            }
+            Err(e) => {
+                warn!("{e:#}, 只创建 uinput 虚拟数位板");
+                Ok(Output::Uinput(UinputTablet::new(name, capabilities)?))
+            }
+        }
+    }
+
+    /// 写在 black box 中的出口名称
+    fn sink(&self) -> &'static str {
+        match self {
+            Output::TabletV2(_) => "tablet-v2",
+            Output::Uinput(_) => "uinput",
+        }
+    }
+
+    fn dispatch(&mut self, event: &TabletEvent) -> std::io::Result<()> {
+        match self {
+            Output::TabletV2(tablet) => tablet.dispatch(event),
+            Output::Uinput(tablet) => tablet.dispatch(event),
+        }
+    }
+}
+
+/// 在阻塞线程中创建设备, 把 `events` 中未被 tabletd 消费的事件交给 Wayland 程序
+///
+/// 和 [`super::uinput::spawn`] 一样记录每个事件的去向和写入的延迟. 混成器不支持 tablet-v2 时
+/// 退回只有 uinput 虚拟数位板
+pub fn spawn(
+    name: String,
+    capabilities: DeviceCapabilities,
+    mut events: mpsc::Receiver<RoutedEvent>,
+    black_box: BlackBox,
+    latency: LatencyStats,
+) -> JoinHandle<Result<(), DispatchError>> {
+    tokio::task::spawn_blocking(move || {
+        let _span = info_span!("tablet-v2", %name).entered();
+        let mut tablet = Output::new(&name, &capabilities).map_err(DispatchError::Create)?;
+        let sink = tablet.sink();
+        while let Some(routed) = events.blocking_recv() {
+            if routed.is_consumed() {
+                black_box.skipped_consumed(&routed, sink);
+                continue;
+            }
+            if let Err(e) = tablet.dispatch(&routed.event) {
+                black_box.failed(&routed, sink, &e);
+                return Err(DispatchError::Write { sink, source: e });
+            }
+            let now = monotonic_micros();
+            latency.record(LatencyStage::Dispatch, routed.routed_at, now);
+            latency.record(LatencyStage::Total, routed.stamp.timestamp, now);
+            black_box.delivered(&routed, sink);
+        }
+        Ok(())
+    })
+}
+
+impl Dispatch<wl_registry::WlRegistry, ()> for ProbeState {
+    fn event(
+        state: &mut Self,
+        registry: &wl_registry::WlRegistry,
+        event: wl_registry::Event,
+        _: &(),
+        _: &Connection,
+        qhandle: &QueueHandle<Self>,
+    ) {
+        if let wl_registry::Event::Global {
+            name,
+            interface,
+            version,
+        } = event
+        {
+            match &interface[..] {
+                "zwp_tablet_manager_v2" => {
+                    let manager = registry.bind::<zwp_tablet_manager_v2::ZwpTabletManagerV2, _, _>(
+                        name,
+                        version.min(1),
+                        qhandle,
+                        (),
+                    );
+                    state.manager = Some((manager, version));
+                }
+                // 只使用第一个 seat
+                "wl_seat" if state.seat.is_none() => {
+                    state.seat = Some(registry.bind::<wl_seat::WlSeat, _, _>(
+                        name,
+                        version.min(1),
+                        qhandle,
+                        (),
+                    ));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Dispatch<zwp_tablet_seat_v2::ZwpTabletSeatV2, ()> for ProbeState {
+    fn event(
+        _: &mut Self,
+        _: &zwp_tablet_seat_v2::ZwpTabletSeatV2,
+        _: zwp_tablet_seat_v2::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+
+    event_created_child!(ProbeState, zwp_tablet_seat_v2::ZwpTabletSeatV2, [
+        zwp_tablet_seat_v2::EVT_TABLET_ADDED_OPCODE => (zwp_tablet_v2::ZwpTabletV2, ()),
+        zwp_tablet_seat_v2::EVT_TOOL_ADDED_OPCODE => (zwp_tablet_tool_v2::ZwpTabletToolV2, ()),
+        zwp_tablet_seat_v2::EVT_PAD_ADDED_OPCODE => (zwp_tablet_pad_v2::ZwpTabletPadV2, ()),
+    ]);
+}
+
+impl Dispatch<zwp_tablet_v2::ZwpTabletV2, ()> for ProbeState {
+    fn event(
+        state: &mut Self,
+        _: &zwp_tablet_v2::ZwpTabletV2,
+        event: zwp_tablet_v2::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let zwp_tablet_v2::Event::Name { name } = event {
+            state.tablets.push(name);
+        }
+    }
+}
+
+impl Dispatch<zwp_tablet_pad_v2::ZwpTabletPadV2, ()> for ProbeState {
+    fn event(
+        _: &mut Self,
+        _: &zwp_tablet_pad_v2::ZwpTabletPadV2,
+        _: zwp_tablet_pad_v2::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+
+    event_created_child!(ProbeState, zwp_tablet_pad_v2::ZwpTabletPadV2, [
+        zwp_tablet_pad_v2::EVT_GROUP_OPCODE => (zwp_tablet_pad_group_v2::ZwpTabletPadGroupV2, ()),
+    ]);
+}
+
+impl Dispatch<zwp_tablet_pad_group_v2::ZwpTabletPadGroupV2, ()> for ProbeState {
+    fn event(
+        _: &mut Self,
+        _: &zwp_tablet_pad_group_v2::ZwpTabletPadGroupV2,
+        _: zwp_tablet_pad_group_v2::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+
+    event_created_child!(ProbeState, zwp_tablet_pad_group_v2::ZwpTabletPadGroupV2, [
+        zwp_tablet_pad_group_v2::EVT_RING_OPCODE => (zwp_tablet_pad_ring_v2::ZwpTabletPadRingV2, ()),
+        zwp_tablet_pad_group_v2::EVT_STRIP_OPCODE => (zwp_tablet_pad_strip_v2::ZwpTabletPadStripV2, ()),
+    ]);
+}
+
+delegate_noop!(ProbeState: ignore wl_seat::WlSeat);
+delegate_noop!(ProbeState: ignore zwp_tablet_manager_v2::ZwpTabletManagerV2);
+delegate_noop!(ProbeState: ignore zwp_tablet_tool_v2::ZwpTabletToolV2);
+delegate_noop!(ProbeState: ignore zwp_tablet_pad_ring_v2::ZwpTabletPadRingV2);
+delegate_noop!(ProbeState: ignore zwp_tablet_pad_strip_v2::ZwpTabletPadStripV2);
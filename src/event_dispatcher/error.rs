@@ -0,0 +1,40 @@
+//! 分发的错误
+
+use std::io;
+
+use thiserror::Error;
+
+use crate::error::ErrorKind;
+
+#[derive(Debug, Error)]
+pub enum DispatchError {
+    /// 创建虚拟设备失败
+    #[error("无法创建虚拟设备")]
+    Create(#[source] anyhow::Error),
+    #[error("无法写入 {sink} 事件")]
+    Write {
+        sink: &'static str,
+        #[source]
+        source: io::Error,
+    },
+    /// 合成器不支持需要的协议
+    #[error("混成器不支持 {0}")]
+    Unsupported(&'static str),
+}
+
+impl DispatchError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            DispatchError::Create(e) if is_permission_denied(e) => ErrorKind::Permission,
+            DispatchError::Unsupported(_) => ErrorKind::Unsupported,
+            _ => ErrorKind::Dispatch,
+        }
+    }
+}
+
+fn is_permission_denied(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .filter_map(|e| e.downcast_ref::<io::Error>())
+        .any(|e| e.kind() == io::ErrorKind::PermissionDenied)
+}
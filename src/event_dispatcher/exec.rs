@@ -0,0 +1,217 @@
+//! 受控地执行绑定中的命令
+//!
+//! 默认不经过 shell, 命令按类似 shell 的规则拆成参数后直接执行;
+//! 只保留少量环境变量，超时后结束进程，输出逐行写入日志(在 systemd 下即 journal)
+
+use std::{process::Stdio, time::Duration};
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    process::Command,
+    task::JoinHandle,
+};
+use tracing::{Level, info, warn};
+
+/// 命令的执行规则
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExecPolicy {
+    /// 通过 `sh -c` 执行，可以使用管道、重定向等. 这会绕过 `allow`
+    pub shell: bool,
+    /// 允许执行的程序(名称或绝对路径), 不设置时允许所有程序
+    pub allow: Option<Vec<String>>,
+    /// 保留的环境变量, 其他变量都会被清除
+    pub keep_env: Vec<String>,
+    /// 超时(秒), 超时后结束进程. 0 表示不限制
+    pub timeout_secs: u64,
+}
+
+impl Default for ExecPolicy {
+    fn default() -> Self {
+        Self {
+            shell: false,
+            allow: None,
+            keep_env: [
+                "PATH",
+                "HOME",
+                "USER",
+                "LANG",
+                "XDG_RUNTIME_DIR",
+                "WAYLAND_DISPLAY",
+                "DISPLAY",
+                "DBUS_SESSION_BUS_ADDRESS",
+            ]
+            .map(String::from)
+            .to_vec(),
+            timeout_secs: 10,
+        }
+    }
+}
+
+impl ExecPolicy {
+    fn timeout(&self) -> Option<Duration> {
+        (self.timeout_secs > 0).then(|| Duration::from_secs(self.timeout_secs))
+    }
+
+    fn allows(&self, program: &str) -> bool {
+        let Some(allow) = self.allow.as_ref() else {
+            return true;
+        };
+        // 名称只匹配不带路径的程序，避免 `./firefox` 冒充 `firefox`
+        allow.iter().any(|allowed| allowed == program)
+    }
+
+    /// 按规则构造进程, 命令不被允许时返回错误
+    pub fn command(&self, command: &str) -> anyhow::Result<Command> {
+        let mut process = if self.shell {
+            let mut process = Command::new("sh");
+            process.args(["-c", command]);
+            process
+        } else {
+            let args = split_args(command)?;
+            let Some((program, args)) = args.split_first() else {
+                bail!("命令为空");
+            };
+            if !self.allows(program) {
+                bail!("`{program}` 不在允许执行的程序中");
+            }
+            let mut process = Command::new(program);
+            process.args(args);
+            process
+        };
+        process.env_clear();
+        for name in &self.keep_env {
+            if let Some(value) = std::env::var_os(name) {
+                process.env(name, value);
+            }
+        }
+        process
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        Ok(process)
+    }
+
+    /// 启动命令，在后台等待它结束, 需要在 tokio 运行时中调用
+    pub fn spawn(&self, command: &str) -> anyhow::Result<JoinHandle<()>> {
+        let mut child = self
+            .command(command)?
+            .spawn()
+            .with_context(|| format!("无法执行 `{command}`"))?;
+        let name = command.to_string();
+        let stdout = child
+            .stdout
+            .take()
+            .map(|out| log_lines(name.clone(), out, Level::INFO));
+        let stderr = child
+            .stderr
+            .take()
+            .map(|err| log_lines(name.clone(), err, Level::WARN));
+        let timeout = self.timeout();
+        Ok(tokio::spawn(async move {
+            let status = match timeout {
+                Some(timeout) => match tokio::time::timeout(timeout, child.wait()).await {
+                    Ok(status) => status,
+                    Err(_) => {
+                        warn!("`{name}` 超时 ({}s), 已结束", timeout.as_secs());
+                        let _ = child.kill().await;
+                        child.wait().await
+                    }
+                },
+                None => child.wait().await,
+            };
+            for task in [stdout, stderr].into_iter().flatten() {
+                let _ = task.await;
+            }
+            match status {
+                Ok(status) if status.success() => {}
+                Ok(status) => warn!("`{name}` 异常退出: {status}"),
+                Err(e) => warn!("无法等待 `{name}` 结束: {e}"),
+            }
+        }))
+    }
+}
+
+/// 把进程的输出逐行写入日志, 标准输出用 `INFO`, 标准错误用 `WARN`
+fn log_lines(
+    name: String,
+    output: impl AsyncRead + Unpin + Send + 'static,
+    level: Level,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(output).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if level == Level::WARN {
+                warn!("`{name}`: {line}");
+            } else {
+                info!("`{name}`: {line}");
+            }
+        }
+    })
+}
+
+/// 按类似 shell 的规则拆分参数: 空白分隔，支持单引号、双引号和反斜杠转义.
+/// 不支持变量、通配符、管道等
+pub fn split_args(command: &str) -> anyhow::Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    // 引号中的空字符串也是一个参数
+    let mut in_arg = false;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_arg = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => current.push(c),
+                        None => bail!("`{command}` 中的单引号没有闭合"),
+                    }
+                }
+            }
+            '"' => {
+                in_arg = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => current.push(c),
+                            Some(c) => {
+                                current.push('\\');
+                                current.push(c);
+                            }
+                            None => bail!("`{command}` 中的双引号没有闭合"),
+                        },
+                        Some(c) => current.push(c),
+                        None => bail!("`{command}` 中的双引号没有闭合"),
+                    }
+                }
+            }
+            '\\' => {
+                in_arg = true;
+                match chars.next() {
+                    Some(c) => current.push(c),
+                    None => bail!("`{command}` 以反斜杠结尾"),
+                }
+            }
+            c if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            c => {
+                in_arg = true;
+                current.push(c);
+            }
+        }
+    }
+    if in_arg {
+        args.push(current);
+    }
+    Ok(args)
+}
@@ -0,0 +1,112 @@
+//! 通过 uinput 创建虚拟键盘，用来发送绑定的组合键和滚动
+
+use std::io;
+
+use anyhow::bail;
+use evdev_rs::enums::{EV_KEY, EV_REL, EV_SYN, EventCode, int_to_ev_key};
+
+use crate::input_devices::privilege::{UinputSpec, VirtualDevice};
+
+/// 启用的最大键码, 覆盖普通键盘上的所有按键(`KEY_MICMUTE`)
+const MAX_KEYBOARD_CODE: u32 = 248;
+
+/// 组合键名称的别名, 其他名称对应 `KEY_` 加上大写的名称
+const ALIASES: [(&str, EV_KEY); 11] = [
+    ("ctrl", EV_KEY::KEY_LEFTCTRL),
+    ("control", EV_KEY::KEY_LEFTCTRL),
+    ("shift", EV_KEY::KEY_LEFTSHIFT),
+    ("alt", EV_KEY::KEY_LEFTALT),
+    ("super", EV_KEY::KEY_LEFTMETA),
+    ("meta", EV_KEY::KEY_LEFTMETA),
+    ("logo", EV_KEY::KEY_LEFTMETA),
+    ("bracketleft", EV_KEY::KEY_LEFTBRACE),
+    ("bracketright", EV_KEY::KEY_RIGHTBRACE),
+    ("return", EV_KEY::KEY_ENTER),
+    ("escape", EV_KEY::KEY_ESC),
+];
+
+/// 解析过的组合键, 按书写顺序按下，反序松开
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyCombo(Vec<EV_KEY>);
+
+impl KeyCombo {
+    /// 解析 `ctrl+shift+z` 形式的组合键, 不区分大小写
+    pub fn parse(keys: &str) -> anyhow::Result<Self> {
+        let combo = keys
+            .split('+')
+            .map(|name| {
+                let name = name.trim().to_lowercase();
+                if name.is_empty() {
+                    bail!("组合键 `{keys}` 中有空的按键");
+                }
+                if let Some((_, key)) = ALIASES.iter().find(|(alias, _)| *alias == name) {
+                    return Ok(*key);
+                }
+                format!("KEY_{}", name.to_uppercase())
+                    .parse::<EV_KEY>()
+                    .map_err(|_| anyhow::anyhow!("未知的按键 `{name}`"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self(combo))
+    }
+
+    pub fn keys(&self) -> &[EV_KEY] {
+        &self.0
+    }
+}
+
+/// 除键盘外还需要启用的指针事件, 有了 `REL_X`/`REL_Y` 和左键 udev 才会把设备标记为指针,
+/// libinput 才会处理其中的滚轮
+const POINTER_CODES: [EventCode; 4] = [
+    EventCode::EV_REL(EV_REL::REL_X),
+    EventCode::EV_REL(EV_REL::REL_Y),
+    EventCode::EV_REL(EV_REL::REL_WHEEL),
+    EventCode::EV_KEY(EV_KEY::BTN_LEFT),
+];
+
+/// uinput 虚拟键盘, 同时可以滚动滚轮
+pub struct VirtualKeyboard {
+    device: VirtualDevice,
+}
+
+impl VirtualKeyboard {
+    /// 创建虚拟键盘, 需要 `/dev/uinput` 的写权限, 特权辅助进程不创建键盘
+    pub fn new() -> anyhow::Result<Self> {
+        let mut spec = UinputSpec::new("tabletd keyboard");
+        let keys = (1..=MAX_KEYBOARD_CODE)
+            .filter_map(int_to_ev_key)
+            .map(EventCode::EV_KEY);
+        for code in keys.chain(POINTER_CODES) {
+            spec.enable(code, None);
+        }
+        let device = VirtualDevice::create(&spec)?;
+        Ok(Self { device })
+    }
+
+    /// 虚拟设备的节点, 如 `/dev/input/event21`
+    pub fn devnode(&self) -> Option<&str> {
+        self.device.devnode()
+    }
+
+    fn write(&self, code: EventCode, value: i32) -> io::Result<()> {
+        self.device.write(code, value)
+    }
+
+    /// 按下并松开组合键
+    pub fn press(&self, combo: &KeyCombo) -> io::Result<()> {
+        for key in combo.keys() {
+            self.write(EventCode::EV_KEY(*key), 1)?;
+        }
+        self.write(EventCode::EV_SYN(EV_SYN::SYN_REPORT), 0)?;
+        for key in combo.keys().iter().rev() {
+            self.write(EventCode::EV_KEY(*key), 0)?;
+        }
+        self.write(EventCode::EV_SYN(EV_SYN::SYN_REPORT), 0)
+    }
+
+    /// 滚动滚轮, 正数向上
+    pub fn scroll(&self, amount: i8) -> io::Result<()> {
+        self.write(EventCode::EV_REL(EV_REL::REL_WHEEL), amount as i32)?;
+        self.write(EventCode::EV_SYN(EV_SYN::SYN_REPORT), 0)
+    }
+}
@@ -0,0 +1,74 @@
+//! Prometheus 格式的 metrics 端点
+//!
+//! 在 `[daemon] metrics` 设置的地址上响应 `GET /metrics`, 内容是
+//! [`LatencyStats::prometheus`]. 只实现了 Prometheus 抓取需要的最少的 HTTP, 每个连接
+//! 只处理一个请求. 和 `tabletd API` 的 TCP 一样没有访问控制
+
+use std::io;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    task::JoinHandle,
+};
+use tracing::warn;
+
+use crate::event_model::latency::LatencyStats;
+
+/// 请求头的最大长度
+const MAX_REQUEST_LEN: usize = 8 * 1024;
+
+/// 监听 `address`, 返回的任务在监听出错时结束
+pub async fn serve(
+    address: impl ToSocketAddrs,
+    latency: LatencyStats,
+) -> io::Result<JoinHandle<()>> {
+    let listener = TcpListener::bind(address).await?;
+    Ok(tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let latency = latency.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = respond(stream, &latency).await {
+                            warn!("metrics: 无法响应请求: {e}");
+                        }
+                    });
+                }
+                Err(e) => {
+                    warn!("metrics: 接受连接失败: {e}");
+                    break;
+                }
+            }
+        }
+    }))
+}
+
+async fn respond(mut stream: TcpStream, latency: &LatencyStats) -> io::Result<()> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|end| end == b"\r\n\r\n") {
+        let n = stream.read(&mut buffer).await?;
+        if n == 0 || request.len() + n > MAX_REQUEST_LEN {
+            return Ok(());
+        }
+        request.extend_from_slice(&buffer[..n]);
+    }
+    let line = request
+        .split(|byte| *byte == b'\r')
+        .next()
+        .unwrap_or_default();
+    let mut parts = line.split(|byte| *byte == b' ');
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some(b"GET"), Some(b"/metrics")) => ("200 OK", latency.prometheus()),
+        (Some(b"GET"), _) => ("404 Not Found", "只提供 /metrics\n".to_string()),
+        _ => ("405 Method Not Allowed", String::new()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
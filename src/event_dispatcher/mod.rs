@@ -0,0 +1,18 @@
+/// 执行绑定触发的动作
+pub mod actions;
+/// `tabletd API` 服务端，向远程客户端转发数位板事件
+pub mod api;
+/// 经过 Wayland tablet-v2 协议输出
+pub mod backend_wayland;
+/// 分发的错误
+pub mod error;
+/// 受控地执行绑定中的命令
+pub mod exec;
+/// uinput 虚拟键盘
+pub mod keyboard;
+/// Prometheus 格式的 metrics 端点
+pub mod metrics;
+/// 路由后事件的出口: uinput 虚拟数位板和 `tabletd API`
+pub mod sinks;
+/// uinput 虚拟数位板
+pub mod uinput;
@@ -0,0 +1,202 @@
+//! 路由后事件的出口
+//!
+//! 每块接入的数位板有一个 uinput 虚拟数位板. 在 Wayland 会话中经过 [`backend_wayland`] 创建,
+//! 混成器支持 tablet-v2 时原生 Wayland 程序通过它收到笔输入. 所有事件(包括被 tabletd 消费的)
+//! 同时交给 `tabletd API`, 笔的事件还交给 overlay 绘制光标. 数位板断开时它的虚拟数位板随之
+//! 销毁, 销毁前松开所有按键
+
+use std::collections::HashMap;
+
+use tokio::{
+    sync::{broadcast, mpsc},
+    task::JoinHandle,
+};
+use tracing::{error, warn};
+
+use crate::{
+    error::ErrorReport,
+    event_model::{event::TabletEvent, latency::LatencyStats, tablet::TabletId},
+    event_router::{
+        RoutedEvent,
+        black_box::{BlackBox, RecordKind},
+    },
+    input_devices::hotplug::{ConnectedDevice, DeviceEvent, TabletConnections},
+};
+
+use super::{api::ApiServer, backend_wayland, error::DispatchError, uinput};
+
+/// 每个虚拟数位板等待写入的事件数量
+const DEVICE_QUEUE_LEN: usize = 256;
+
+struct VirtualTablet {
+    events: mpsc::Sender<RoutedEvent>,
+    task: JoinHandle<Result<(), DispatchError>>,
+}
+
+/// 把路由器输出的事件交给虚拟数位板和 `tabletd API`
+pub struct Sinks {
+    api: Option<ApiServer>,
+    pens: Option<broadcast::Sender<RoutedEvent>>,
+    black_box: BlackBox,
+    latency: LatencyStats,
+    tablets: HashMap<TabletId, VirtualTablet>,
+    connections: TabletConnections,
+    /// 创建虚拟数位板时查询混成器的 tablet-v2 支持
+    tablet_v2: bool,
+}
+
+impl Sinks {
+    /// 每个事件的去向记在 `black_box` 中
+    pub fn new(black_box: BlackBox) -> Self {
+        Self {
+            api: None,
+            pens: None,
+            black_box,
+            latency: LatencyStats::new(),
+            tablets: HashMap::new(),
+            connections: TabletConnections::new(),
+            tablet_v2: std::env::var_os("WAYLAND_DISPLAY").is_some(),
+        }
+    }
+
+    pub fn set_api(&mut self, api: ApiServer) {
+        self.api = Some(api);
+    }
+
+    /// 笔的事件同时发往 `pens`, overlay 用它移动光标
+    pub fn set_pens(&mut self, pens: broadcast::Sender<RoutedEvent>) {
+        self.pens = Some(pens);
+    }
+
+    /// 事件写入虚拟数位板时把分发和总的延迟记在 `latency` 中, 之后创建的虚拟数位板才会记录
+    pub fn set_latency(&mut self, latency: LatencyStats) {
+        self.latency = latency;
+    }
+
+    /// 为数位板创建虚拟数位板, 已经有的保持不变
+    pub fn connect(&mut self, device: &ConnectedDevice) {
+        self.connections.connect(device.clone());
+        if self.tablets.contains_key(&device.tablet) {
+            return;
+        }
+        let (events, rx) = mpsc::channel(DEVICE_QUEUE_LEN);
+        let spawn = if self.tablet_v2 {
+            backend_wayland::spawn
+        } else {
+            uinput::spawn
+        };
+        let task = spawn(
+            format!("tabletd {}", device.name),
+            device.capabilities.clone(),
+            rx,
+            self.black_box.clone(),
+            self.latency.clone(),
+        );
+        self.tablets
+            .insert(device.tablet, VirtualTablet { events, task });
+    }
+
+    /// 数位板的所有连接都断开后销毁它的虚拟数位板, 不等它完成
+    ///
+    /// 还有其他连接时路由器已经切换过去, 虚拟数位板继续使用
+    pub fn disconnect(&mut self, device: &ConnectedDevice) {
+        if self.connections.disconnect(device) {
+            self.tablets.remove(&device.tablet);
+        }
+    }
+
+    async fn deliver(&mut self, routed: RoutedEvent) {
+        if let Some(api) = &self.api {
+            api.publish(routed.clone());
+        }
+        if let Some(pens) = &self.pens
+            && matches!(routed.event, TabletEvent::PenEvent(_))
+        {
+            // 没有运行 overlay 时没有接收者
+            let _ = pens.send(routed.clone());
+        }
+        let tablet = routed.tablet;
+        let Some(device) = self.tablets.get(&tablet) else {
+            self.black_box.record(
+                routed.tablet,
+                routed.stamp,
+                RecordKind::Skipped {
+                    sink: "uinput",
+                    reason: "没有虚拟数位板".to_string(),
+                },
+            );
+            return;
+        };
+        // 虚拟数位板已经出错退出
+        if device.events.send(routed).await.is_err()
+            && let Some(device) = self.tablets.remove(&tablet)
+        {
+            match device.task.await {
+                Ok(Err(e)) => {
+                    let report = ErrorReport::from_anyhow(e.into()).with_tablet(tablet);
+                    error!("虚拟数位板已停止: {}", report.message);
+                    if let Some(api) = &self.api {
+                        api.report_error(report);
+                    }
+                }
+                Err(e) => error!("虚拟数位板已停止: {e}"),
+                Ok(Ok(())) => {}
+            }
+        }
+    }
+
+    /// 处理 `routed` 中的事件直到它关闭, 然后销毁所有虚拟数位板
+    ///
+    /// `devices` 中的接入和断开决定创建和销毁哪些虚拟数位板
+    pub async fn run(
+        mut self,
+        mut routed: mpsc::Receiver<RoutedEvent>,
+        devices: broadcast::Receiver<DeviceEvent>,
+    ) {
+        let mut devices = Some(devices);
+        loop {
+            tokio::select! {
+                event = routed.recv() => match event {
+                    Some(event) => self.deliver(event).await,
+                    None => break,
+                },
+                Some(device) = device_event(&mut devices) => match device {
+                    DeviceEvent::Connected(device) => self.connect(&device),
+                    DeviceEvent::Disconnected(device) => self.disconnect(&device),
+                },
+            }
+        }
+        self.close().await;
+    }
+
+    /// 销毁所有虚拟数位板并等待它们完成
+    pub async fn close(self) {
+        for (_, device) in self.tablets {
+            drop(device.events);
+            match device.task.await {
+                Ok(Err(e)) => error!("虚拟数位板出错: {e:#}"),
+                Err(e) => error!("虚拟数位板出错: {e}"),
+                Ok(Ok(())) => {}
+            }
+        }
+    }
+}
+
+/// 等待下一个接入或断开. 发送端关闭后不会再有新设备, 之后永远等待, 只继续处理事件
+async fn device_event(rx: &mut Option<broadcast::Receiver<DeviceEvent>>) -> Option<DeviceEvent> {
+    let Some(receiver) = rx.as_mut() else {
+        return std::future::pending().await;
+    };
+    loop {
+        match receiver.recv().await {
+            Ok(event) => return Some(event),
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                warn!("错过了 {n} 个设备接入或断开事件");
+            }
+            Err(broadcast::error::RecvError::Closed) => {
+                *rx = None;
+                return None;
+            }
+        }
+    }
+}
@@ -0,0 +1,324 @@
+//! 通过 uinput 创建虚拟数位板
+//!
+//! 把分发的事件重新写成 evdev 数位板事件，普通程序不需要混成器支持任何特殊协议,
+//! 就能像使用内核驱动的数位板一样收到笔输入.
+//!
+//! 虚拟设备的坐标范围、分辨率、压感范围、倾斜范围和工具都照搬 [`DeviceCapabilities`],
+//! 这样 libinput 的校准和程序的压感换算与使用内核驱动时相同
+
+use std::{collections::BTreeSet, io};
+
+use evdev_rs::enums::{EV_ABS, EV_KEY, EV_REL, EV_SYN, EventCode};
+use tokio::{sync::mpsc, task::JoinHandle};
+use tracing::{info, info_span};
+
+use crate::{
+    event_model::{
+        capability::DeviceCapabilities,
+        event::{PenButton, PenLocation, PenState, TabletEvent, ToolType},
+        latency::{LatencyStage, LatencyStats},
+        stamp::monotonic_micros,
+    },
+    event_router::{RoutedEvent, black_box::BlackBox},
+    input_devices::privilege::{AbsRange, UinputSpec, VirtualDevice},
+};
+
+use super::error::DispatchError;
+
+/// 倾斜的分辨率, 单位/弧度 (与内核 HID 驱动一致)
+const TILT_RESOLUTION: i32 = 57;
+
+/// 快捷键依次对应 `BTN_0` ~ `BTN_9`, 之后的按键依次对应 `BTN_A` 等
+const PAD_BUTTONS: [EV_KEY; 17] = [
+    EV_KEY::BTN_0,
+    EV_KEY::BTN_1,
+    EV_KEY::BTN_2,
+    EV_KEY::BTN_3,
+    EV_KEY::BTN_4,
+    EV_KEY::BTN_5,
+    EV_KEY::BTN_6,
+    EV_KEY::BTN_7,
+    EV_KEY::BTN_8,
+    EV_KEY::BTN_9,
+    EV_KEY::BTN_A,
+    EV_KEY::BTN_B,
+    EV_KEY::BTN_C,
+    EV_KEY::BTN_X,
+    EV_KEY::BTN_Y,
+    EV_KEY::BTN_Z,
+    EV_KEY::BTN_BASE,
+];
+
+/// 触控环位置在 `ABS_WHEEL` 上的最大值. 和内核的 wacom 驱动一样, 0 表示手指离开, 位置从 1 开始
+const RING_MAX: i32 = 72;
+
+/// uinput 虚拟数位板
+pub struct UinputTablet {
+    device: VirtualDevice,
+    capabilities: DeviceCapabilities,
+    /// 当前在感应范围内的工具
+    tool: Option<ToolType>,
+    touching: bool,
+    buttons: PenButton,
+    /// 按着的快捷键, 是 [`PAD_BUTTONS`] 中的下标
+    pad: BTreeSet<usize>,
+}
+
+fn abs_info(minimum: i32, maximum: i32, resolution: i32) -> Option<AbsRange> {
+    Some(AbsRange {
+        minimum,
+        maximum,
+        resolution,
+    })
+}
+
+/// 工具对应的按键, 设备没有橡皮擦时都当作笔
+fn tool_key(tool: ToolType, capabilities: &DeviceCapabilities) -> EV_KEY {
+    match tool {
+        ToolType::Eraser if capabilities.eraser => EV_KEY::BTN_TOOL_RUBBER,
+        _ => EV_KEY::BTN_TOOL_PEN,
+    }
+}
+
+impl UinputTablet {
+    /// 按数位板的能力创建虚拟设备, 没有 `/dev/uinput` 的写权限时由特权辅助进程创建
+    pub fn new(name: &str, capabilities: &DeviceCapabilities) -> anyhow::Result<Self> {
+        let mut spec = UinputSpec::new(format!("tabletd {name}"));
+
+        let clamp = |value: u32| value.min(i32::MAX as u32) as i32;
+        // 按键设备没有坐标, 只保留 0 ~ 1 的坐标轴, 和内核驱动的快捷键设备一样
+        let mut codes = vec![
+            (
+                EventCode::EV_ABS(EV_ABS::ABS_X),
+                abs_info(
+                    0,
+                    clamp(capabilities.max_x).max(1),
+                    clamp(capabilities.resolution_x),
+                ),
+            ),
+            (
+                EventCode::EV_ABS(EV_ABS::ABS_Y),
+                abs_info(
+                    0,
+                    clamp(capabilities.max_y).max(1),
+                    clamp(capabilities.resolution_y),
+                ),
+            ),
+            (EventCode::EV_REL(EV_REL::REL_WHEEL), None),
+            (
+                EventCode::EV_ABS(EV_ABS::ABS_WHEEL),
+                abs_info(0, RING_MAX, 0),
+            ),
+        ];
+        if !capabilities.is_keypad() {
+            codes.extend([
+                (EventCode::EV_KEY(EV_KEY::BTN_TOOL_PEN), None),
+                (EventCode::EV_KEY(EV_KEY::BTN_TOUCH), None),
+                (EventCode::EV_KEY(EV_KEY::BTN_STYLUS), None),
+                (EventCode::EV_KEY(EV_KEY::BTN_STYLUS2), None),
+            ]);
+        }
+        if capabilities.eraser {
+            codes.push((EventCode::EV_KEY(EV_KEY::BTN_TOOL_RUBBER), None));
+        }
+        if capabilities.has_pressure() {
+            codes.push((
+                EventCode::EV_ABS(EV_ABS::ABS_PRESSURE),
+                abs_info(0, clamp(capabilities.max_pressure), 0),
+            ));
+        }
+        if capabilities.tilt {
+            let range = capabilities.max_tilt as i32;
+            for axis in [EV_ABS::ABS_TILT_X, EV_ABS::ABS_TILT_Y] {
+                codes.push((
+                    EventCode::EV_ABS(axis),
+                    abs_info(-range, range, TILT_RESOLUTION),
+                ));
+            }
+        }
+        codes.extend(
+            PAD_BUTTONS
+                .iter()
+                .map(|button| (EventCode::EV_KEY(*button), None)),
+        );
+        for (code, range) in codes {
+            spec.enable(code, range);
+        }
+        spec.pointer = !capabilities.is_keypad();
+
+        let device = VirtualDevice::create(&spec)?;
+        Ok(Self {
+            device,
+            capabilities: capabilities.clone(),
+            tool: None,
+            touching: false,
+            buttons: PenButton::default(),
+            pad: BTreeSet::new(),
+        })
+    }
+
+    /// 虚拟设备的节点, 如 `/dev/input/event20`
+    pub fn devnode(&self) -> Option<&str> {
+        self.device.devnode()
+    }
+
+    fn write(&self, code: EventCode, value: i32) -> io::Result<()> {
+        self.device.write(code, value)
+    }
+
+    fn key(&self, key: EV_KEY, pressed: bool) -> io::Result<()> {
+        self.write(EventCode::EV_KEY(key), pressed as i32)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.write(EventCode::EV_SYN(EV_SYN::SYN_REPORT), 0)
+    }
+
+    /// 写入一个事件
+    pub fn dispatch(&mut self, event: &TabletEvent) -> io::Result<()> {
+        match event {
+            TabletEvent::PenEvent(pen) => self.pen(pen)?,
+            TabletEvent::PenButton(buttons) => self.pen_buttons(*buttons)?,
+            TabletEvent::AuxButton(button) => {
+                let index = button.button_id as usize;
+                let Some(key) = PAD_BUTTONS.get(index) else {
+                    return Ok(());
+                };
+                self.key(*key, button.pressed)?;
+                if button.pressed {
+                    self.pad.insert(index);
+                } else {
+                    self.pad.remove(&index);
+                }
+            }
+            TabletEvent::Wheel(wheel) => {
+                self.write(EventCode::EV_REL(EV_REL::REL_WHEEL), wheel.delta())?;
+            }
+            // 只有一个触控环的轴, 和内核驱动一样只报告第一个触控环
+            TabletEvent::Ring(ring) if ring.ring == 0 => {
+                let value = ring.position.map_or(0, |position| {
+                    1 + (position.clamp(0.0, 1.0) * (RING_MAX - 1) as f32).round() as i32
+                });
+                self.write(EventCode::EV_ABS(EV_ABS::ABS_WHEEL), value)?;
+            }
+            // 笔离开时已经有 `Leaved` 的笔事件
+            TabletEvent::Ring(_)
+            | TabletEvent::ToolIn(_)
+            | TabletEvent::ToolOut(_)
+            | TabletEvent::Unknown => {
+                return Ok(());
+            }
+        }
+        self.sync()
+    }
+
+    fn pen_buttons(&mut self, buttons: PenButton) -> io::Result<()> {
+        if buttons.lower != self.buttons.lower {
+            self.key(EV_KEY::BTN_STYLUS, buttons.lower)?;
+        }
+        if buttons.upper != self.buttons.upper {
+            self.key(EV_KEY::BTN_STYLUS2, buttons.upper)?;
+        }
+        self.buttons = buttons;
+        Ok(())
+    }
+
+    fn pen(&mut self, pen: &PenState) -> io::Result<()> {
+        let in_range = !matches!(pen.location, PenLocation::Leaved);
+        let touching = matches!(pen.location, PenLocation::Pressed);
+
+        // 切换工具(比如笔翻过来用橡皮擦)时，先让旧工具离开
+        if let Some(tool) = self.tool
+            && (!in_range
+                || tool_key(tool, &self.capabilities) != tool_key(pen.tool, &self.capabilities))
+        {
+            if self.touching {
+                self.key(EV_KEY::BTN_TOUCH, false)?;
+                self.touching = false;
+            }
+            self.key(tool_key(tool, &self.capabilities), false)?;
+            self.tool = None;
+            if in_range {
+                self.sync()?;
+            }
+        }
+        if !in_range {
+            return Ok(());
+        }
+
+        self.write(EventCode::EV_ABS(EV_ABS::ABS_X), pen.x as i32)?;
+        self.write(EventCode::EV_ABS(EV_ABS::ABS_Y), pen.y as i32)?;
+        if self.capabilities.has_pressure() {
+            let pressure = if touching { pen.pressure as i32 } else { 0 };
+            self.write(EventCode::EV_ABS(EV_ABS::ABS_PRESSURE), pressure)?;
+        }
+        if self.capabilities.tilt {
+            let range = self.capabilities.max_tilt as i32;
+            let tilt = |value: i16| (value as i32).clamp(-range, range);
+            self.write(EventCode::EV_ABS(EV_ABS::ABS_TILT_X), tilt(pen.tilt.x))?;
+            self.write(EventCode::EV_ABS(EV_ABS::ABS_TILT_Y), tilt(pen.tilt.y))?;
+        }
+        if self.tool.is_none() {
+            self.key(tool_key(pen.tool, &self.capabilities), true)?;
+            self.tool = Some(pen.tool);
+        }
+        if touching != self.touching {
+            self.key(EV_KEY::BTN_TOUCH, touching)?;
+            self.touching = touching;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for UinputTablet {
+    fn drop(&mut self) {
+        // 松开所有按键，避免程序收到卡住的按键
+        let _ = self.pen_buttons(PenButton::default());
+        if let Some(tool) = self.tool.take() {
+            let _ = self.key(EV_KEY::BTN_TOUCH, false);
+            let _ = self.key(tool_key(tool, &self.capabilities), false);
+        }
+        for index in std::mem::take(&mut self.pad) {
+            let _ = self.key(PAD_BUTTONS[index], false);
+        }
+        let _ = self.sync();
+    }
+}
+
+/// 在阻塞线程中创建虚拟设备, 把 `events` 中未被 tabletd 消费的事件写入
+///
+/// 每个事件的去向记在 `black_box` 中, 写入的延迟记在 `latency` 中
+pub fn spawn(
+    name: String,
+    capabilities: DeviceCapabilities,
+    mut events: mpsc::Receiver<RoutedEvent>,
+    black_box: BlackBox,
+    latency: LatencyStats,
+) -> JoinHandle<Result<(), DispatchError>> {
+    tokio::task::spawn_blocking(move || {
+        let _span = info_span!("uinput", %name).entered();
+        let mut tablet = UinputTablet::new(&name, &capabilities).map_err(DispatchError::Create)?;
+        info!(
+            "已创建虚拟数位板 {}",
+            tablet.devnode().unwrap_or("(unknown)")
+        );
+        while let Some(routed) = events.blocking_recv() {
+            if routed.is_consumed() {
+                black_box.skipped_consumed(&routed, "uinput");
+                continue;
+            }
+            if let Err(e) = tablet.dispatch(&routed.event) {
+                black_box.failed(&routed, "uinput", &e);
+                return Err(DispatchError::Write {
+                    sink: "uinput",
+                    source: e,
+                });
+            }
+            let now = monotonic_micros();
+            latency.record(LatencyStage::Dispatch, routed.routed_at, now);
+            latency.record(LatencyStage::Total, routed.stamp.timestamp, now);
+            black_box.delivered(&routed, "uinput");
+        }
+        Ok(())
+    })
+}
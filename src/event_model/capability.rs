@@ -0,0 +1,87 @@
+use serde::{Deserialize, Serialize};
+
+/// 不知道倾斜范围时使用的最大角度
+pub const DEFAULT_MAX_TILT: u8 = 90;
+
+fn default_max_tilt() -> u8 {
+    DEFAULT_MAX_TILT
+}
+
+/// 设备的种类
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceClass {
+    /// 有笔的数位板
+    #[default]
+    Tablet,
+    /// 数位板配套的按键设备(如 Huion Keydial), 只有按键和滚轮, 没有笔.
+    /// 坐标范围、压感等字段都是 0
+    Keypad,
+}
+
+/// 数位板声明的能力和坐标范围, 二进制格式见 [`wire`](super::wire)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(remote = "Self")]
+pub struct DeviceCapabilities {
+    /// X 坐标最大值(设备单位)
+    pub max_x: u32,
+    /// Y 坐标最大值(设备单位)
+    pub max_y: u32,
+    /// X 方向分辨率(设备单位/毫米)
+    pub resolution_x: u32,
+    /// Y 方向分辨率(设备单位/毫米)
+    pub resolution_y: u32,
+    /// 压感最大值, 0 表示不支持压感
+    pub max_pressure: u32,
+    /// 是否支持倾斜, 不支持时笔事件中的倾斜总是 0，不应该被使用
+    #[serde(default)]
+    pub tilt: bool,
+    /// 是否支持旋转(如 Wacom Art Pen)
+    #[serde(default)]
+    pub rotation: bool,
+    /// 是否有橡皮擦(笔的另一端), 没有时不会出现 [`ToolType::Eraser`](super::event::ToolType::Eraser)
+    #[serde(default)]
+    pub eraser: bool,
+    /// 倾斜的范围为 ±`max_tilt` 度
+    #[serde(default = "default_max_tilt")]
+    pub max_tilt: u8,
+    #[serde(default)]
+    pub class: DeviceClass,
+}
+
+impl DeviceCapabilities {
+    /// 按键设备的能力
+    pub fn keypad() -> Self {
+        Self {
+            max_x: 0,
+            max_y: 0,
+            resolution_x: 0,
+            resolution_y: 0,
+            max_pressure: 0,
+            tilt: false,
+            rotation: false,
+            eraser: false,
+            max_tilt: DEFAULT_MAX_TILT,
+            class: DeviceClass::Keypad,
+        }
+    }
+
+    pub fn is_keypad(&self) -> bool {
+        self.class == DeviceClass::Keypad
+    }
+
+    pub fn has_pressure(&self) -> bool {
+        self.max_pressure > 0
+    }
+
+    /// 工作区的物理尺寸(毫米)
+    pub fn physical_size(&self) -> Option<(f64, f64)> {
+        if self.resolution_x == 0 || self.resolution_y == 0 {
+            return None;
+        }
+        Some((
+            self.max_x as f64 / self.resolution_x as f64,
+            self.max_y as f64 / self.resolution_y as f64,
+        ))
+    }
+}
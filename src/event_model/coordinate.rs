@@ -0,0 +1,83 @@
+use serde::{Deserialize, Serialize};
+
+use super::capability::DeviceCapabilities;
+
+/// 坐标的单位
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CoordinateSpace {
+    /// 设备原始单位
+    #[default]
+    Raw,
+    /// 归一化到 0 ~ 1
+    Normalized,
+    /// 毫米
+    Millimeters,
+    /// 映射后某个显示器上的像素坐标
+    Screen { output: String },
+}
+
+/// 坐标原点的位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Origin {
+    #[default]
+    TopLeft,
+    BottomLeft,
+    Center,
+}
+
+/// 坐标的表示方式
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct CoordinateFormat {
+    pub space: CoordinateSpace,
+    pub origin: Origin,
+}
+
+/// 把归一化的数位板坐标映射到显示器像素
+pub trait ScreenMapping {
+    /// 返回 `output` 上的像素坐标, 显示器不存在时返回 `None`
+    fn to_output(&self, output: &str, x: f64, y: f64) -> Option<(f64, f64)>;
+
+    /// 显示器的像素尺寸
+    fn output_size(&self, output: &str) -> Option<(f64, f64)>;
+}
+
+impl CoordinateFormat {
+    /// 把设备坐标转换为这种表示方式
+    ///
+    /// 无法转换时(比如设备没有提供分辨率，或者显示器不存在)返回 `None`
+    pub fn convert(
+        &self,
+        x: u32,
+        y: u32,
+        caps: &DeviceCapabilities,
+        mapping: Option<&dyn ScreenMapping>,
+    ) -> Option<(f64, f64)> {
+        let nx = x as f64 / caps.max_x.max(1) as f64;
+        let ny = y as f64 / caps.max_y.max(1) as f64;
+
+        // 先得到左上角为原点的坐标和这个坐标系的范围
+        let ((x, y), (width, height)) = match &self.space {
+            CoordinateSpace::Raw => ((x as f64, y as f64), (caps.max_x as f64, caps.max_y as f64)),
+            CoordinateSpace::Normalized => ((nx, ny), (1.0, 1.0)),
+            CoordinateSpace::Millimeters => {
+                let (width, height) = caps.physical_size()?;
+                ((nx * width, ny * height), (width, height))
+            }
+            CoordinateSpace::Screen { output } => {
+                let mapping = mapping?;
+                (
+                    mapping.to_output(output, nx, ny)?,
+                    mapping.output_size(output)?,
+                )
+            }
+        };
+
+        Some(match self.origin {
+            Origin::TopLeft => (x, y),
+            Origin::BottomLeft => (x, height - y),
+            Origin::Center => (x - width / 2.0, y - height / 2.0),
+        })
+    }
+}
@@ -1,29 +1,46 @@
-#[derive(Debug, Clone, Copy)]
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Tilt {
     pub x: i16,
     pub y: i16,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PenLocation {
     Leaved,
     Floating,
     Pressed,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ToolType {
     Pen,
     Eraser,
 }
 
-#[derive(Debug, Clone, Copy)]
+/// 笔的硬件序列号, 只有能区分每支笔的数位板(如 Wacom Intuos)才会报告
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ToolId(pub u32);
+
+impl fmt::Display for ToolId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#010x}", self.0)
+    }
+}
+
+/// 笔杆上按键的状态
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct PenButton {
     pub upper: bool,
+    /// 靠近笔尖的按键
     pub lower: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PenState {
     pub x: u32,
     pub y: u32,
@@ -33,28 +50,83 @@ pub struct PenState {
     pub location: PenLocation,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuxButtonEvent {
     pub button_id: u8,
     pub pressed: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum WheelDirection {
     Clockwise,
     CounterClockwise,
 }
 
-#[derive(Debug, Clone)]
+/// 滚轮或触控环的一次转动, 一个报告中转过的所有格合并成一个事件
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WheelEvent {
+    pub direction: WheelDirection,
+    /// 转过的格数, 至少为 1
+    pub steps: u16,
+}
+
+impl WheelEvent {
+    /// 按带符号的转动构造, 顺时针为正. 没有转动时返回 `None`
+    pub fn from_delta(delta: i32) -> Option<Self> {
+        let direction = match delta.signum() {
+            1 => WheelDirection::Clockwise,
+            -1 => WheelDirection::CounterClockwise,
+            _ => return None,
+        };
+        Some(Self {
+            direction,
+            steps: delta.unsigned_abs().min(u16::MAX as u32) as u16,
+        })
+    }
+
+    /// 带符号的转动, 顺时针为正
+    pub fn delta(&self) -> i32 {
+        match self.direction {
+            WheelDirection::Clockwise => self.steps as i32,
+            WheelDirection::CounterClockwise => -(self.steps as i32),
+        }
+    }
+}
+
+/// 转动一格
+impl From<WheelDirection> for WheelEvent {
+    fn from(direction: WheelDirection) -> Self {
+        Self {
+            direction,
+            steps: 1,
+        }
+    }
+}
+
+/// 绝对式触控环上手指的位置
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RingEvent {
+    pub ring: u8,
+    /// 一圈中的位置(0.0 ~ 1.0), 从驱动报告的零点顺时针增加. 手指离开时为 `None`
+    pub position: Option<f32>,
+}
+
+/// 二进制格式见 [`wire`](super::wire)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub enum TabletEvent {
     PenEvent(PenState),
     AuxButton(AuxButtonEvent),
-    Wheel(WheelDirection),
+    Wheel(WheelEvent),
+    #[default]
     Unknown,
-}
-
-impl Default for TabletEvent {
-    fn default() -> Self {
-        Self::Unknown
-    }
+    /// 放在最后，不改变已有事件的编码. 触控环转动时同时还会有 `Wheel` 事件
+    Ring(RingEvent),
+    /// 笔进入感应范围, 在这支笔的第一个 `PenEvent` 之前
+    ToolIn(ToolId),
+    /// 笔杆上的按键按下或松开, 值是变化后所有按键的状态
+    PenButton(PenButton),
+    /// 笔离开感应范围, 在这支笔 `location` 为 `Leaved` 的 `PenEvent` 之后.
+    /// 和 `ToolIn` 成对出现, 两者之间的笔事件都来自这支笔
+    ToolOut(ToolId),
 }
@@ -0,0 +1,210 @@
+//! 事件各处理阶段的延迟
+//!
+//! 每个事件有三个时间点: 驱动读到报告([`EventStamp::timestamp`], 即 USB 中断传输完成)、
+//! 离开路由器、写入虚拟数位板. 相邻时间点之差分别计入 [`LatencyStage::Route`] 和
+//! [`LatencyStage::Dispatch`], 首尾之差计入 [`LatencyStage::Total`]. 被 tabletd 消费的事件
+//! 不会写入虚拟数位板, 只计入路由.
+//!
+//! 抬笔延迟故意推迟的抬笔也计入路由的延迟, 所以路由阶段的最大值可能接近抬笔延迟的设置.
+//! 统计可以用 `tabletctl latency` 查看, 也可以在 `[daemon] metrics` 设置的地址上以
+//! Prometheus 格式读取
+//!
+//! [`EventStamp::timestamp`]: super::stamp::EventStamp::timestamp
+
+use std::{
+    fmt::Write,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use serde::{Deserialize, Serialize};
+
+/// 直方图各个桶的上界(微秒), 最后还有一个没有上界的桶
+const BUCKETS_US: [u64; 11] = [
+    100, 250, 500, 1_000, 2_000, 4_000, 8_000, 16_000, 32_000, 64_000, 128_000,
+];
+
+/// 处理阶段
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LatencyStage {
+    /// 读到报告 → 离开路由器
+    Route,
+    /// 离开路由器 → 写入虚拟数位板
+    Dispatch,
+    /// 读到报告 → 写入虚拟数位板
+    Total,
+}
+
+impl LatencyStage {
+    pub const ALL: [LatencyStage; 3] = [
+        LatencyStage::Route,
+        LatencyStage::Dispatch,
+        LatencyStage::Total,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LatencyStage::Route => "route",
+            LatencyStage::Dispatch => "dispatch",
+            LatencyStage::Total => "total",
+        }
+    }
+}
+
+/// 一个阶段的延迟分布, 可以在多个线程中同时记录
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [AtomicU64; BUCKETS_US.len() + 1],
+    sum_us: AtomicU64,
+    max_us: AtomicU64,
+}
+
+impl Histogram {
+    fn record(&self, micros: u64) {
+        let bucket = BUCKETS_US
+            .iter()
+            .position(|bound| micros <= *bound)
+            .unwrap_or(BUCKETS_US.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_us.fetch_add(micros, Ordering::Relaxed);
+        self.max_us.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    fn summary(&self, stage: LatencyStage) -> LatencySummary {
+        let counts: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect();
+        let count: u64 = counts.iter().sum();
+        let sum_us = self.sum_us.load(Ordering::Relaxed);
+        let max_us = self.max_us.load(Ordering::Relaxed);
+        let mut cumulative = 0;
+        let buckets = counts
+            .iter()
+            .zip(BUCKETS_US.iter().map(|bound| Some(*bound)).chain([None]))
+            .map(|(count, bound)| {
+                cumulative += count;
+                LatencyBucket {
+                    le_ms: bound.map(|bound| bound as f64 / 1000.0),
+                    count: cumulative,
+                }
+            })
+            .collect::<Vec<_>>();
+        // 分位数取所在桶的上界, 不超过最大值
+        let quantile = |q: f64| {
+            let rank = (count as f64 * q).ceil().max(1.0) as u64;
+            let bound = buckets
+                .iter()
+                .find(|bucket| bucket.count >= rank)
+                .and_then(|bucket| bucket.le_ms)
+                .unwrap_or(f64::INFINITY);
+            bound.min(max_us as f64 / 1000.0)
+        };
+        LatencySummary {
+            stage,
+            count,
+            mean_ms: if count > 0 {
+                sum_us as f64 / count as f64 / 1000.0
+            } else {
+                0.0
+            },
+            p50_ms: if count > 0 { quantile(0.5) } else { 0.0 },
+            p99_ms: if count > 0 { quantile(0.99) } else { 0.0 },
+            max_ms: max_us as f64 / 1000.0,
+            sum_ms: sum_us as f64 / 1000.0,
+            buckets,
+        }
+    }
+}
+
+/// 直方图的一个桶, 计数包括所有更小的桶
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LatencyBucket {
+    /// 上界(毫秒), 最后一个桶没有上界
+    pub le_ms: Option<f64>,
+    pub count: u64,
+}
+
+/// 一个阶段的延迟统计
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LatencySummary {
+    pub stage: LatencyStage,
+    pub count: u64,
+    pub mean_ms: f64,
+    /// 中位数, 精确到直方图的桶
+    pub p50_ms: f64,
+    /// 99% 分位数, 精确到直方图的桶
+    pub p99_ms: f64,
+    pub max_ms: f64,
+    pub sum_ms: f64,
+    pub buckets: Vec<LatencyBucket>,
+}
+
+/// 所有阶段的延迟, 路由器和出口写入, 控制接口和 metrics 端点读取
+#[derive(Debug, Clone, Default)]
+pub struct LatencyStats {
+    stages: Arc<[Histogram; 3]>,
+}
+
+impl LatencyStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 记录一个事件在 `stage` 上用的时间, `from` 和 `to` 是 `CLOCK_MONOTONIC` 的微秒数.
+    /// `from` 为 0 表示事件没有经过这个阶段的起点, 不记录
+    pub fn record(&self, stage: LatencyStage, from: u64, to: u64) {
+        if from == 0 {
+            return;
+        }
+        self.histogram(stage).record(to.saturating_sub(from));
+    }
+
+    fn histogram(&self, stage: LatencyStage) -> &Histogram {
+        &self.stages[stage as usize]
+    }
+
+    /// 所有阶段当前的统计
+    pub fn snapshot(&self) -> Vec<LatencySummary> {
+        LatencyStage::ALL
+            .into_iter()
+            .map(|stage| self.histogram(stage).summary(stage))
+            .collect()
+    }
+
+    /// Prometheus 文本格式的直方图, 单位为秒
+    pub fn prometheus(&self) -> String {
+        let mut text = String::new();
+        text.push_str("# HELP tabletd_event_latency_seconds 事件各处理阶段的延迟\n");
+        text.push_str("# TYPE tabletd_event_latency_seconds histogram\n");
+        for summary in self.snapshot() {
+            let stage = summary.stage.name();
+            for bucket in &summary.buckets {
+                let le = match bucket.le_ms {
+                    Some(le_ms) => format!("{}", le_ms / 1000.0),
+                    None => "+Inf".to_string(),
+                };
+                let _ = writeln!(
+                    text,
+                    "tabletd_event_latency_seconds_bucket{{stage=\"{stage}\",le=\"{le}\"}} {}",
+                    bucket.count
+                );
+            }
+            let _ = writeln!(
+                text,
+                "tabletd_event_latency_seconds_sum{{stage=\"{stage}\"}} {}",
+                summary.sum_ms / 1000.0
+            );
+            let _ = writeln!(
+                text,
+                "tabletd_event_latency_seconds_count{{stage=\"{stage}\"}} {}",
+                summary.count
+            );
+        }
+        text
+    }
+}
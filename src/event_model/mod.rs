@@ -1 +1,11 @@
+pub mod capability;
+pub mod coordinate;
+/// 数位板事件. 驱动、路由器、分发器和 `tabletd API` 共用这一套类型, 不应该另外定义平行的事件模型
 pub mod event;
+/// 事件各处理阶段的延迟
+pub mod latency;
+/// 事件的时间戳和序号
+pub mod stamp;
+pub mod tablet;
+/// 事件和设备信息的二进制格式
+pub mod wire;
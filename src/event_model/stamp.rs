@@ -0,0 +1,71 @@
+//! 事件的时间戳和序号
+//!
+//! 驱动读到报告时记下单调时钟的时间, 并给同一个设备连接上的事件依次编号. 之后的处理(抬笔延迟、
+//! 过滤器、转发给 `tabletd API` 客户端)都保留这两个值, 下游可以用它们判断事件的先后、计算延迟,
+//! 以及发现丢失的事件. 同一份报告解析出的多个事件时间戳相同, 序号不同
+//!
+//! 时间戳使用 `CLOCK_MONOTONIC`, 和 evdev、Wayland 事件的时间戳是同一个时钟,
+//! 但不能和另一台机器上的时间戳比较
+
+use std::time::Duration;
+
+use nix::time::{ClockId, clock_gettime};
+use serde::{Deserialize, Serialize};
+
+/// 事件被读取的时间和顺序
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct EventStamp {
+    /// `CLOCK_MONOTONIC` 的微秒数
+    pub timestamp: u64,
+    /// 同一个设备连接上的序号, 从 0 开始连续递增. 重新连接后从 0 开始
+    pub sequence: u64,
+}
+
+impl EventStamp {
+    /// 从读取到现在经过的时间
+    pub fn age(&self) -> Duration {
+        Duration::from_micros(monotonic_micros().saturating_sub(self.timestamp))
+    }
+
+    /// 由 tabletd 在这个事件之后补发的事件(比如连接断开时的抬笔), 接着使用这个连接的序号
+    pub fn follow(&self) -> EventStamp {
+        EventStamp {
+            timestamp: monotonic_micros().max(self.timestamp),
+            sequence: self.sequence + 1,
+        }
+    }
+}
+
+/// `CLOCK_MONOTONIC` 的当前时间(微秒)
+pub fn monotonic_micros() -> u64 {
+    // 在 Linux 上 `CLOCK_MONOTONIC` 总是可用
+    let now = clock_gettime(ClockId::CLOCK_MONOTONIC).expect("无法读取 CLOCK_MONOTONIC");
+    now.tv_sec() as u64 * 1_000_000 + now.tv_nsec() as u64 / 1_000
+}
+
+/// 给一个设备连接上的事件编号
+#[derive(Debug, Clone, Default)]
+pub struct EventSequence {
+    next: u64,
+}
+
+impl EventSequence {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 下一个在 `timestamp` 读取的事件
+    pub fn stamp(&mut self, timestamp: u64) -> EventStamp {
+        let stamp = EventStamp {
+            timestamp,
+            sequence: self.next,
+        };
+        self.next += 1;
+        stamp
+    }
+
+    /// 下一个现在读取的事件
+    pub fn stamp_now(&mut self) -> EventStamp {
+        self.stamp(monotonic_micros())
+    }
+}
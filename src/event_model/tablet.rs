@@ -0,0 +1,15 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// 逻辑上的一块数位板
+///
+/// 同一块数位板通过不同方式(USB、蓝牙)连接时应该是同一个 ID
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct TabletId(pub u32);
+
+impl fmt::Display for TabletId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "tablet-{}", self.0)
+    }
+}
@@ -0,0 +1,171 @@
+//! 事件和设备信息的二进制格式
+//!
+//! `tabletd API`、事件录制等使用 postcard 的地方共用这套格式. [`TabletEvent`] 和
+//! [`DeviceCapabilities`] 编码为一个 [`Envelope`]: 格式版本、事件种类和带长度前缀的内容.
+//! 解码时:
+//!
+//! - 不认识的事件种类解码为 [`TabletEvent::Unknown`], 不会让整条消息失败
+//! - 内容末尾多出的字段(新版本追加的)被忽略
+//!
+//! 所以新增事件种类或者在结构体末尾追加字段都不需要修改 [`SCHEMA_VERSION`],
+//! 只有删除、重排字段这类不兼容的修改才需要.
+//!
+//! TOML、JSON 等自描述的格式仍然使用 serde 默认的表示
+
+use serde::{
+    Deserialize, Deserializer, Serialize, Serializer,
+    de::{DeserializeOwned, Error as _},
+    ser::Error as _,
+};
+
+use super::{
+    capability::DeviceCapabilities,
+    event::{TabletEvent, WheelDirection, WheelEvent},
+};
+
+/// 二进制格式的版本, 只在不兼容的修改时加一
+pub const SCHEMA_VERSION: u16 = 1;
+
+/// 二进制格式中的一个值
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Envelope {
+    /// 编码时的 [`SCHEMA_VERSION`]
+    pub schema: u16,
+    /// 事件种类, 见 [`TabletEvent::kind`]. 设备信息总是 0
+    pub kind: u16,
+    /// postcard 编码的内容
+    pub payload: Vec<u8>,
+}
+
+impl Envelope {
+    pub fn new<T: Serialize>(kind: u16, value: &T) -> postcard::Result<Self> {
+        Ok(Self {
+            schema: SCHEMA_VERSION,
+            kind,
+            payload: postcard::to_stdvec(value)?,
+        })
+    }
+
+    /// 检查格式版本
+    pub fn check(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.schema == SCHEMA_VERSION,
+            "不支持的格式版本 {}, 当前为 {SCHEMA_VERSION}",
+            self.schema
+        );
+        Ok(())
+    }
+
+    /// 解码内容, 忽略末尾多出的字节
+    pub fn open<T: DeserializeOwned>(&self) -> anyhow::Result<T> {
+        self.check()?;
+        Ok(postcard::from_bytes(&self.payload)?)
+    }
+}
+
+impl TabletEvent {
+    /// 二进制格式中的事件种类, 已有的值不能修改
+    pub fn kind(&self) -> u16 {
+        match self {
+            TabletEvent::PenEvent(_) => 0,
+            TabletEvent::AuxButton(_) => 1,
+            TabletEvent::Wheel(_) => 2,
+            TabletEvent::Unknown => 3,
+            TabletEvent::Ring(_) => 4,
+            TabletEvent::ToolIn(_) => 5,
+            TabletEvent::PenButton(_) => 6,
+            TabletEvent::ToolOut(_) => 7,
+        }
+    }
+
+    pub fn to_envelope(&self) -> postcard::Result<Envelope> {
+        let kind = self.kind();
+        match self {
+            TabletEvent::PenEvent(state) => Envelope::new(kind, state),
+            TabletEvent::AuxButton(button) => Envelope::new(kind, button),
+            TabletEvent::Wheel(wheel) => Envelope::new(kind, wheel),
+            TabletEvent::Unknown => Envelope::new(kind, &()),
+            TabletEvent::Ring(ring) => Envelope::new(kind, ring),
+            TabletEvent::ToolIn(tool) => Envelope::new(kind, tool),
+            TabletEvent::PenButton(button) => Envelope::new(kind, button),
+            TabletEvent::ToolOut(tool) => Envelope::new(kind, tool),
+        }
+    }
+
+    /// 不认识的事件种类返回 [`TabletEvent::Unknown`]
+    pub fn from_envelope(envelope: &Envelope) -> anyhow::Result<Self> {
+        Ok(match envelope.kind {
+            0 => TabletEvent::PenEvent(envelope.open()?),
+            1 => TabletEvent::AuxButton(envelope.open()?),
+            // 格数是后来加在方向后面的, 旧的录制只有方向, 当作一格
+            2 => TabletEvent::Wheel(
+                envelope
+                    .open()
+                    .or_else(|_| envelope.open::<WheelDirection>().map(WheelEvent::from))?,
+            ),
+            4 => TabletEvent::Ring(envelope.open()?),
+            5 => TabletEvent::ToolIn(envelope.open()?),
+            6 => TabletEvent::PenButton(envelope.open()?),
+            7 => TabletEvent::ToolOut(envelope.open()?),
+            _ => {
+                envelope.check()?;
+                TabletEvent::Unknown
+            }
+        })
+    }
+}
+
+// `#[serde(remote = "Self")]` 生成的同名固有函数是默认的表示, 自描述的格式继续使用它们
+
+impl Serialize for TabletEvent {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            return TabletEvent::serialize(self, serializer);
+        }
+        self.to_envelope()
+            .map_err(S::Error::custom)?
+            .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for TabletEvent {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            return TabletEvent::deserialize(deserializer);
+        }
+        let envelope = Envelope::deserialize(deserializer)?;
+        TabletEvent::from_envelope(&envelope).map_err(D::Error::custom)
+    }
+}
+
+/// 用默认的表示编码设备信息, 作为 [`Envelope`] 的内容
+struct Fields<'a>(&'a DeviceCapabilities);
+
+impl Serialize for Fields<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        DeviceCapabilities::serialize(self.0, serializer)
+    }
+}
+
+impl Serialize for DeviceCapabilities {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            return DeviceCapabilities::serialize(self, serializer);
+        }
+        Envelope::new(0, &Fields(self))
+            .map_err(S::Error::custom)?
+            .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for DeviceCapabilities {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            return DeviceCapabilities::deserialize(deserializer);
+        }
+        let envelope = Envelope::deserialize(deserializer)?;
+        envelope.check().map_err(D::Error::custom)?;
+        let mut payload = postcard::Deserializer::from_bytes(&envelope.payload);
+        DeviceCapabilities::deserialize(&mut payload).map_err(D::Error::custom)
+    }
+}
@@ -0,0 +1,346 @@
+//! 快捷键和滚轮绑定
+//!
+//! 按键和滚轮事件在这里查找当前数位板、当前模式组的绑定，
+//! 匹配的事件被消费，动作交给 [`crate::event_dispatcher::actions`] 执行.
+//! 订阅了 [`super::gesture::Gestures`] 时笔杆按键的手势也可以绑定动作.
+//! 绑定保存在 [`BindingTable`] 中，设置重新加载后下一个事件就会使用新的绑定
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, RwLock},
+    time::Instant,
+};
+
+use tokio::sync::{
+    broadcast::{self, error::TryRecvError},
+    mpsc,
+};
+
+use crate::{
+    event_model::{
+        event::{PenButton, TabletEvent, WheelDirection},
+        tablet::TabletId,
+    },
+    hud_interface::HudSender,
+    profile::{
+        Profile,
+        binding::{Action, Binding, GestureBinding, QuickMenuEntry, WheelBinding},
+        wheel::WheelPreset,
+    },
+};
+
+use super::{
+    RoutedEvent, RouterFilter, Verdict,
+    confirm::ConfirmGate,
+    gesture::{BarrelButton, GestureKind, PenGesture},
+};
+
+/// 一块数位板的绑定
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TabletBindings {
+    pub buttons: Vec<Binding>,
+    pub wheel: Vec<WheelBinding>,
+    /// 没有匹配的滚轮绑定时使用的预设
+    pub wheel_preset: Option<WheelPreset>,
+    pub quick_menu: Vec<QuickMenuEntry>,
+    pub gestures: Vec<GestureBinding>,
+}
+
+impl TabletBindings {
+    /// 使用设置中的按键绑定、滚轮绑定和滚轮预设
+    pub fn from_profile(profile: &Profile) -> Self {
+        Self {
+            buttons: profile.bindings.clone(),
+            wheel: profile.wheel_bindings.clone(),
+            wheel_preset: profile.wheel,
+            quick_menu: profile.quick_menu.clone(),
+            gestures: profile.gesture_bindings.clone(),
+        }
+    }
+
+    /// 编入数位板的按键设备使用的绑定: 编号从 `first_button` 开始的按键绑定依次对应按键设备的
+    /// 按键 0、1、..., 滚轮绑定不变
+    pub fn for_keypad(&self, first_button: u8) -> Self {
+        let buttons = self
+            .buttons
+            .iter()
+            .filter(|binding| binding.button >= first_button)
+            .map(|binding| Binding {
+                button: binding.button - first_button,
+                ..binding.clone()
+            })
+            .collect();
+        Self {
+            buttons,
+            ..self.clone()
+        }
+    }
+
+    pub fn button(&self, button: u8, bank: u8) -> Option<&Binding> {
+        self.buttons
+            .iter()
+            .find(|binding| binding.matches(button, bank))
+    }
+
+    pub fn gesture(
+        &self,
+        gesture: GestureKind,
+        button: BarrelButton,
+        bank: u8,
+    ) -> Option<&GestureBinding> {
+        self.gestures
+            .iter()
+            .find(|binding| binding.matches(gesture, button, bank))
+    }
+
+    /// 笔杆按键在某个模式组中是否绑定了手势
+    pub fn has_gesture(&self, button: BarrelButton, bank: u8) -> bool {
+        self.gestures
+            .iter()
+            .any(|binding| binding.button == button && binding.bank.is_none_or(|b| b == bank))
+    }
+
+    /// 滚轮转动对应的动作, 由预设产生时附带预设
+    pub fn wheel(
+        &self,
+        direction: &WheelDirection,
+        bank: u8,
+    ) -> Option<(Action, Option<WheelPreset>)> {
+        if let Some(binding) = self
+            .wheel
+            .iter()
+            .find(|binding| binding.matches(direction, bank))
+        {
+            return Some((binding.action.clone(), None));
+        }
+        self.wheel_preset
+            .map(|preset| (preset.action(direction), Some(preset)))
+    }
+}
+
+#[derive(Debug, Default)]
+struct Table {
+    default: TabletBindings,
+    tablets: HashMap<TabletId, TabletBindings>,
+}
+
+/// 所有数位板的绑定, 可以在其他任务中修改
+#[derive(Debug, Clone, Default)]
+pub struct BindingTable {
+    table: Arc<RwLock<Table>>,
+}
+
+impl BindingTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 设置某块数位板的绑定
+    pub fn set(&self, tablet: TabletId, bindings: TabletBindings) {
+        self.table.write().unwrap().tablets.insert(tablet, bindings);
+    }
+
+    /// 设置没有单独设置绑定的数位板使用的绑定
+    pub fn set_default(&self, bindings: TabletBindings) {
+        self.table.write().unwrap().default = bindings;
+    }
+
+    /// 替换所有绑定
+    pub fn replace(&self, default: TabletBindings, tablets: HashMap<TabletId, TabletBindings>) {
+        *self.table.write().unwrap() = Table { default, tablets };
+    }
+
+    /// 去掉数位板单独的绑定，改用默认绑定
+    pub fn reset(&self, tablet: TabletId) -> Option<TabletBindings> {
+        self.table.write().unwrap().tablets.remove(&tablet)
+    }
+
+    /// 数位板的快捷菜单
+    pub fn quick_menu(&self, tablet: TabletId) -> Vec<QuickMenuEntry> {
+        self.with(tablet, |b| b.quick_menu.clone())
+    }
+
+    fn with<T>(&self, tablet: TabletId, f: impl FnOnce(&TabletBindings) -> T) -> T {
+        let table = self.table.read().unwrap();
+        f(table.tablets.get(&tablet).unwrap_or(&table.default))
+    }
+}
+
+/// 触发动作的滚轮转动
+#[derive(Debug, Clone)]
+pub struct WheelSource {
+    pub direction: WheelDirection,
+    /// 由滚轮预设产生时的预设
+    pub preset: Option<WheelPreset>,
+}
+
+/// 需要执行的动作
+#[derive(Debug, Clone)]
+pub struct Triggered {
+    pub tablet: TabletId,
+    pub action: Action,
+    /// 由滚轮触发时的方向和预设, 执行后在 HUD 上显示滚轮转盘
+    pub wheel: Option<WheelSource>,
+}
+
+/// 执行动作的通道
+pub type ActionSender = mpsc::UnboundedSender<Triggered>;
+
+/// 按 [`BindingTable`] 把按键和滚轮事件变成动作
+///
+/// 有绑定的按键按下和松开都被消费，需要确认的绑定先经过 [`ConfirmGate`].
+/// 绑定了手势的笔杆按键同样被消费, 不再传给系统
+pub struct BindingEngine {
+    bindings: BindingTable,
+    confirm: ConfirmGate,
+    actions: ActionSender,
+    /// 按下时有绑定的按键, 松开时即使模式组已经变化也要消费
+    held: HashSet<(TabletId, u8)>,
+    gestures: Option<broadcast::Receiver<PenGesture>>,
+    /// 每块数位板上笔杆按键最后的状态, 用来找出变化的按键
+    pen_buttons: HashMap<TabletId, PenButton>,
+}
+
+impl BindingEngine {
+    pub fn new(bindings: BindingTable, actions: ActionSender) -> Self {
+        Self {
+            bindings,
+            confirm: ConfirmGate::default(),
+            actions,
+            held: HashSet::new(),
+            gestures: None,
+            pen_buttons: HashMap::new(),
+        }
+    }
+
+    /// 执行笔杆按键手势的绑定, `gestures` 来自 [`super::gesture::Gestures::subscribe`],
+    /// 手势过滤器要添加在这个过滤器之前
+    pub fn set_gestures(&mut self, gestures: broadcast::Receiver<PenGesture>) {
+        self.gestures = Some(gestures);
+    }
+
+    pub fn bindings(&self) -> &BindingTable {
+        &self.bindings
+    }
+
+    /// 设置 HUD 通道，用于显示确认提示
+    pub fn set_hud(&mut self, hud: HudSender) {
+        self.confirm.set_hud(hud);
+    }
+
+    pub fn confirm_gate(&mut self) -> &mut ConfirmGate {
+        &mut self.confirm
+    }
+
+    /// 执行已经识别出的手势的绑定
+    fn run_gestures(&mut self) {
+        let Some(rx) = self.gestures.as_mut() else {
+            return;
+        };
+        let mut gestures = Vec::new();
+        loop {
+            match rx.try_recv() {
+                Ok(gesture) => gestures.push(gesture),
+                Err(TryRecvError::Lagged(_)) => continue,
+                Err(_) => break,
+            }
+        }
+        for gesture in gestures {
+            let Some(binding) = self.bindings.with(gesture.tablet, |b| {
+                b.gesture(gesture.kind, gesture.button, gesture.bank)
+                    .cloned()
+            }) else {
+                continue;
+            };
+            self.trigger(gesture.tablet, binding.action, None);
+        }
+    }
+
+    /// 变化的笔杆按键中有绑定了手势的
+    fn pen_buttons_bound(&mut self, tablet: TabletId, buttons: PenButton, bank: u8) -> bool {
+        let previous = self.pen_buttons.insert(tablet, buttons).unwrap_or_default();
+        let changed = [
+            (BarrelButton::Upper, previous.upper != buttons.upper),
+            (BarrelButton::Lower, previous.lower != buttons.lower),
+        ];
+        self.bindings.with(tablet, |b| {
+            changed
+                .iter()
+                .any(|(button, changed)| *changed && b.has_gesture(*button, bank))
+        })
+    }
+
+    fn trigger(&self, tablet: TabletId, action: Action, wheel: Option<WheelSource>) {
+        let _ = self.actions.send(Triggered {
+            tablet,
+            action,
+            wheel,
+        });
+    }
+}
+
+impl RouterFilter for BindingEngine {
+    fn name(&self) -> &str {
+        "bindings"
+    }
+
+    fn filter(&mut self, event: &mut RoutedEvent) -> Verdict {
+        let tablet = event.tablet;
+        // 已经被 HUD 等消费的事件不再触发绑定, 但打开 HUD 的按键还要能关闭它
+        let consumed = event.is_consumed();
+        self.run_gestures();
+        match &event.event {
+            TabletEvent::AuxButton(button) if button.pressed => {
+                let Some(binding) = self
+                    .bindings
+                    .with(tablet, |b| b.button(button.button_id, event.bank).cloned())
+                else {
+                    return Verdict::Pass;
+                };
+                if consumed && binding.action != Action::ToggleHud {
+                    return Verdict::Pass;
+                }
+                self.held.insert((tablet, button.button_id));
+                if let Some(action) = self.confirm.press(&binding, Instant::now()) {
+                    self.trigger(tablet, action, None);
+                }
+                Verdict::Consume
+            }
+            TabletEvent::AuxButton(button) => {
+                if self.held.remove(&(tablet, button.button_id)) {
+                    Verdict::Consume
+                } else {
+                    Verdict::Pass
+                }
+            }
+            TabletEvent::Wheel(wheel) => {
+                let Some((action, preset)) = self
+                    .bindings
+                    .with(tablet, |b| b.wheel(&wheel.direction, event.bank))
+                else {
+                    return Verdict::Pass;
+                };
+                if consumed && action != Action::ToggleHud {
+                    return Verdict::Pass;
+                }
+                let source = WheelSource {
+                    direction: wheel.direction.clone(),
+                    preset,
+                };
+                // 每转一格执行一次
+                for _ in 0..wheel.steps {
+                    self.trigger(tablet, action.clone(), Some(source.clone()));
+                }
+                Verdict::Consume
+            }
+            TabletEvent::PenButton(buttons) if self.gestures.is_some() => {
+                if self.pen_buttons_bound(tablet, *buttons, event.bank) {
+                    Verdict::Consume
+                } else {
+                    Verdict::Pass
+                }
+            }
+            _ => Verdict::Pass,
+        }
+    }
+}
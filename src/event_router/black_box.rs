@@ -0,0 +1,236 @@
+//! 事件黑匣子
+//!
+//! 一直在内存中记录最近 [`WINDOW`] 内收到的事件、路由器的处理结果和每个出口的去向
+//! (写入了哪里、为什么没有写入). 用户报告“点击被吃掉了”这类偶发问题时,
+//! 可以通过控制接口导出, 或者在进程 panic 时自动写入
+//! `$XDG_STATE_HOME/tabletd/black-box-<时间>.txt`
+//!
+//! 记录的时间和 [`EventStamp`] 一样使用 `CLOCK_MONOTONIC`, 可以直接和事件的时间戳比较
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tracing::warn;
+
+use crate::{
+    event_model::{
+        event::TabletEvent,
+        stamp::{EventStamp, monotonic_micros},
+        tablet::TabletId,
+    },
+    input_devices::transport::Transport,
+    mapping::ScreenPoint,
+};
+
+use super::RoutedEvent;
+
+/// 保留的时间范围
+pub const WINDOW: Duration = Duration::from_secs(10);
+/// 最多保留的记录数量, 报告频率很高时限制内存
+const MAX_RECORDS: usize = 50_000;
+
+/// 一条记录的内容
+#[derive(Debug, Clone)]
+pub enum RecordKind {
+    /// 驱动发来的事件
+    Received {
+        transport: Transport,
+        event: TabletEvent,
+    },
+    /// 路由器没有处理的事件
+    Ignored {
+        transport: Transport,
+        reason: &'static str,
+    },
+    /// 经过所有过滤器之后的事件
+    Routed {
+        event: TabletEvent,
+        position: Option<ScreenPoint>,
+        consumed_by: Option<String>,
+    },
+    /// 事件交给了某个出口
+    Delivered { sink: &'static str },
+    /// 出口没有使用这个事件
+    Skipped { sink: &'static str, reason: String },
+    /// 出口写入失败
+    Failed { sink: &'static str, error: String },
+}
+
+/// 黑匣子中的一条记录
+#[derive(Debug, Clone)]
+pub struct Record {
+    /// 记录的时间(`CLOCK_MONOTONIC` 微秒)
+    pub time: u64,
+    pub tablet: TabletId,
+    pub stamp: EventStamp,
+    pub kind: RecordKind,
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:>8}.{:06} {} #{:<8} ",
+            self.time / 1_000_000,
+            self.time % 1_000_000,
+            self.tablet,
+            self.stamp.sequence,
+        )?;
+        match &self.kind {
+            RecordKind::Received { transport, event } => {
+                write!(f, "收到 [{transport}] {event:?}")
+            }
+            RecordKind::Ignored { transport, reason } => {
+                write!(f, "忽略 [{transport}] {reason}")
+            }
+            RecordKind::Routed {
+                event,
+                position,
+                consumed_by,
+            } => {
+                write!(f, "路由 {event:?}")?;
+                if let Some(position) = position {
+                    write!(
+                        f,
+                        " -> {} ({:.1}, {:.1})",
+                        position.output, position.x, position.y
+                    )?;
+                }
+                match consumed_by {
+                    Some(filter) => write!(f, " 被 {filter} 消费"),
+                    None => Ok(()),
+                }
+            }
+            RecordKind::Delivered { sink } => write!(f, "写入 {sink}"),
+            RecordKind::Skipped { sink, reason } => write!(f, "跳过 {sink}: {reason}"),
+            RecordKind::Failed { sink, error } => write!(f, "失败 {sink}: {error}"),
+        }
+    }
+}
+
+/// 最近事件的记录, 可以在多个任务之间共享
+#[derive(Debug, Clone, Default)]
+pub struct BlackBox {
+    records: Arc<Mutex<VecDeque<Record>>>,
+}
+
+impl BlackBox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 添加一条记录, 丢弃超出时间范围的旧记录
+    pub fn record(&self, tablet: TabletId, stamp: EventStamp, kind: RecordKind) {
+        let time = monotonic_micros();
+        let oldest = time.saturating_sub(WINDOW.as_micros() as u64);
+        let mut records = self.records.lock().unwrap();
+        while records
+            .front()
+            .is_some_and(|record| record.time < oldest || records.len() >= MAX_RECORDS)
+        {
+            records.pop_front();
+        }
+        records.push_back(Record {
+            time,
+            tablet,
+            stamp,
+            kind,
+        });
+    }
+
+    /// 记录出口对一个路由后事件的处理
+    pub fn delivered(&self, routed: &RoutedEvent, sink: &'static str) {
+        self.record(routed.tablet, routed.stamp, RecordKind::Delivered { sink });
+    }
+
+    /// 记录出口因为事件已被消费而跳过它
+    pub fn skipped_consumed(&self, routed: &RoutedEvent, sink: &'static str) {
+        let reason = match &routed.consumed_by {
+            Some(filter) => format!("已被 {filter} 消费"),
+            None => "未被消费".to_string(),
+        };
+        self.record(
+            routed.tablet,
+            routed.stamp,
+            RecordKind::Skipped { sink, reason },
+        );
+    }
+
+    /// 记录出口写入失败
+    pub fn failed(&self, routed: &RoutedEvent, sink: &'static str, error: impl fmt::Display) {
+        self.record(
+            routed.tablet,
+            routed.stamp,
+            RecordKind::Failed {
+                sink,
+                error: error.to_string(),
+            },
+        );
+    }
+
+    /// 目前保留的所有记录, 从旧到新
+    pub fn snapshot(&self) -> Vec<Record> {
+        self.records.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// 导出为文本, 每行一条记录
+    pub fn dump(&self) -> String {
+        let records = self.snapshot();
+        let mut text = format!(
+            "# tabletd 黑匣子, 最近 {} 秒的 {} 条记录, 当前时间 {}\n",
+            WINDOW.as_secs(),
+            records.len(),
+            monotonic_micros() as f64 / 1e6,
+        );
+        for record in &records {
+            let _ = writeln!(text, "{record}");
+        }
+        text
+    }
+
+    /// 默认的导出目录: `$XDG_STATE_HOME/tabletd`
+    pub fn default_dir() -> PathBuf {
+        std::env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| Path::new(&home).join(".local").join("state"))
+            })
+            .unwrap_or_else(std::env::temp_dir)
+            .join("tabletd")
+    }
+
+    /// 写入 `dir` 下以当前时间命名的文件, 返回文件路径
+    pub fn dump_to(&self, dir: &Path) -> anyhow::Result<PathBuf> {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("black-box-{seconds}.txt"));
+        fs::write(&path, self.dump())?;
+        Ok(path)
+    }
+
+    /// panic 时先把记录写入 [`BlackBox::default_dir`], 再交给原来的处理函数
+    pub fn install_panic_hook(&self) {
+        let black_box = self.clone();
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            // panic 的线程可能正拿着锁, 这时放弃导出
+            if black_box.records.try_lock().is_ok() {
+                match black_box.dump_to(&Self::default_dir()) {
+                    Ok(path) => warn!("黑匣子已写入 {}", path.display()),
+                    Err(e) => warn!("无法写入黑匣子: {e:#}"),
+                }
+            }
+            previous(info);
+        }));
+    }
+}
@@ -0,0 +1,65 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+use crate::hud_interface::pointer::HudElement;
+
+use super::{RoutedEvent, RouterFilter, Verdict};
+
+/// 打开时把所有事件标记为已消费
+///
+/// 例如 HUD 打开后，笔和按键只用来操作 HUD，不应该传给下层窗口
+pub struct Capture {
+    name: String,
+    active: Arc<AtomicBool>,
+    /// 消费的事件交给 HUD 的哪个元素
+    target: Option<HudElement>,
+}
+
+impl Capture {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            active: Arc::new(AtomicBool::new(false)),
+            target: None,
+        }
+    }
+
+    /// 使用已有的开关, 路由器重建后仍然由同一个开关控制
+    pub fn with_switch(name: impl Into<String>, active: Arc<AtomicBool>) -> Self {
+        Self {
+            name: name.into(),
+            active,
+            target: None,
+        }
+    }
+
+    /// 打开时把事件交给 HUD 的 `element`
+    pub fn target(mut self, element: HudElement) -> Self {
+        self.target = Some(element);
+        self
+    }
+
+    /// 开关, 可以在其他任务中切换
+    pub fn switch(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.active)
+    }
+}
+
+impl RouterFilter for Capture {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn filter(&mut self, event: &mut RoutedEvent) -> Verdict {
+        if self.active.load(Ordering::Relaxed) {
+            if let Some(element) = self.target {
+                event.target_hud(element);
+            }
+            Verdict::Consume
+        } else {
+            Verdict::Pass
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::time::{Duration, Instant};
+
+use crate::{
+    hud_interface::{HudEvent, HudSender},
+    profile::binding::{Action, Binding},
+};
+
+/// 等待确认的动作
+#[derive(Debug, Clone)]
+struct Pending {
+    button: u8,
+    action: Action,
+    deadline: Instant,
+}
+
+/// 破坏性操作的确认
+///
+/// 第一次按下需要确认的绑定时只在 HUD 上显示提示，
+/// 在超时之前再按一次同一个键(或者用笔点一下提示)才会执行
+pub struct ConfirmGate {
+    timeout: Duration,
+    pending: Option<Pending>,
+    hud: Option<HudSender>,
+}
+
+impl ConfirmGate {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            pending: None,
+            hud: None,
+        }
+    }
+
+    pub fn set_hud(&mut self, hud: HudSender) {
+        self.hud = Some(hud);
+    }
+
+    /// 是否有动作在等待确认
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// 按下了绑定的按键，返回可以立即执行的动作
+    pub fn press(&mut self, binding: &Binding, now: Instant) -> Option<Action> {
+        self.expire(now);
+        if !binding.confirm {
+            return Some(binding.action.clone());
+        }
+        if self
+            .pending
+            .as_ref()
+            .is_some_and(|pending| pending.button == binding.button)
+        {
+            return self.confirm(now);
+        }
+        self.pending = Some(Pending {
+            button: binding.button,
+            action: binding.action.clone(),
+            deadline: now + self.timeout,
+        });
+        self.send(HudEvent::ConfirmPrompt {
+            text: binding.action.describe(),
+            timeout: self.timeout,
+        });
+        None
+    }
+
+    /// 用户在 HUD 上确认了提示
+    pub fn confirm(&mut self, now: Instant) -> Option<Action> {
+        self.expire(now);
+        let pending = self.pending.take()?;
+        self.send(HudEvent::ConfirmDismissed);
+        Some(pending.action)
+    }
+
+    /// 取消等待中的动作
+    pub fn cancel(&mut self) {
+        if self.pending.take().is_some() {
+            self.send(HudEvent::ConfirmDismissed);
+        }
+    }
+
+    /// 超时后取消，需要定期调用
+    pub fn expire(&mut self, now: Instant) {
+        if self
+            .pending
+            .as_ref()
+            .is_some_and(|pending| now >= pending.deadline)
+        {
+            self.cancel();
+        }
+    }
+
+    fn send(&self, event: HudEvent) {
+        if let Some(hud) = self.hud.as_ref() {
+            let _ = hud.send(event);
+        }
+    }
+}
+
+impl Default for ConfirmGate {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(2))
+    }
+}
@@ -0,0 +1,92 @@
+//! 多块数位板之间的公平调度
+//!
+//! 所有数位板的事件经过同一个通道进入 [`super::Router`], 一块数位板高频连发时，
+//! 其他数位板的事件会排在它后面. 这里把已经到达的事件按数位板分开排队，再轮流各取一个,
+//! 同一块数位板的事件保持原来的顺序. 状态变化到达时, 这块数位板排着的移动按种类各合并成最后一个,
+//! 变化不用等前面的移动一个个处理完, 见 [`super::lanes`]
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::event_model::tablet::TabletId;
+
+use super::{
+    InputEvent,
+    lanes::{EventReceiver, Lane, MotionKind},
+};
+
+/// 最多暂存的事件数量, 超过后留在通道里，让发送端感受到背压
+pub const MAX_QUEUED: usize = 256;
+
+/// 按数位板轮流取出事件的队列
+#[derive(Default)]
+pub struct FairQueue {
+    queues: HashMap<TabletId, VecDeque<(Lane, InputEvent)>>,
+    /// 还有事件的数位板, 按轮到的顺序排列
+    turns: VecDeque<TabletId>,
+    len: usize,
+}
+
+impl FairQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, lane: Lane, event: InputEvent) {
+        let queue = self.queues.entry(event.tablet).or_default();
+        if queue.is_empty() {
+            self.turns.push_back(event.tablet);
+        }
+        if lane == Lane::Transition {
+            // 只合并上一个变化之后的移动, 更早的移动属于上一个变化之前
+            let start = queue
+                .iter()
+                .rposition(|(lane, _)| *lane == Lane::Transition)
+                .map_or(0, |index| index + 1);
+            // 从后往前, 每种移动留下第一个见到的
+            let count = queue.len() - start;
+            let mut kinds = HashSet::new();
+            let mut motions: Vec<_> = queue
+                .drain(start..)
+                .rev()
+                .filter(|(_, event)| kinds.insert(MotionKind::of(&event.event)))
+                .collect();
+            motions.reverse();
+            self.len -= count - motions.len();
+            queue.extend(motions);
+        }
+        queue.push_back((lane, event));
+        self.len += 1;
+    }
+
+    /// 取出下一个数位板最早的事件
+    pub fn pop(&mut self) -> Option<InputEvent> {
+        let tablet = self.turns.pop_front()?;
+        let queue = self.queues.get_mut(&tablet)?;
+        let (_, event) = queue.pop_front()?;
+        if queue.is_empty() {
+            self.queues.remove(&tablet);
+        } else {
+            self.turns.push_back(tablet);
+        }
+        self.len -= 1;
+        Some(event)
+    }
+
+    /// 不等待地收下通道中已经到达的事件
+    pub fn fill(&mut self, input: &mut EventReceiver) {
+        while self.len < MAX_QUEUED {
+            let Some((lane, event)) = input.try_recv_lane() else {
+                break;
+            };
+            self.push(lane, event);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
@@ -0,0 +1,334 @@
+//! 落笔、抬笔和按键时的声音/振动反馈
+//!
+//! 适合听力/视觉辅助，以及笔尖开关很轻、不确定有没有点到的用户
+
+use std::{
+    collections::HashMap,
+    io::Write,
+    process::{Child, Command, Stdio},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use tempfile::NamedTempFile;
+use tracing::warn;
+
+use crate::{
+    config::{Config, ConfigChange, transaction::ConfigStage},
+    event_model::{
+        event::{PenLocation, TabletEvent},
+        tablet::TabletId,
+    },
+};
+
+use super::{RoutedEvent, RouterFilter, Verdict};
+
+/// 触发反馈的时机
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FeedbackKind {
+    PenDown,
+    PenUp,
+    ButtonPress,
+}
+
+/// 反馈设置
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FeedbackConfig {
+    pub pen_down: bool,
+    pub pen_up: bool,
+    pub button_press: bool,
+    /// 播放提示音
+    pub sound: bool,
+    /// 提示音音量, 0.0 ~ 1.0
+    pub volume: f32,
+    /// 设备支持时振动
+    pub haptics: bool,
+}
+
+impl Default for FeedbackConfig {
+    fn default() -> Self {
+        Self {
+            pen_down: false,
+            pen_up: false,
+            button_press: false,
+            sound: true,
+            volume: 0.5,
+            haptics: true,
+        }
+    }
+}
+
+impl FeedbackConfig {
+    pub fn is_enabled(&self, kind: FeedbackKind) -> bool {
+        match kind {
+            FeedbackKind::PenDown => self.pen_down,
+            FeedbackKind::PenUp => self.pen_up,
+            FeedbackKind::ButtonPress => self.button_press,
+        }
+    }
+}
+
+/// 反馈的输出, 比如扬声器或者设备的振动马达
+pub trait FeedbackSink: Send {
+    fn name(&self) -> &str;
+
+    /// 触发一次反馈, 不应该阻塞
+    fn trigger(&mut self, tablet: TabletId, kind: FeedbackKind);
+}
+
+/// 支持振动的设备, 由驱动实现并注册
+pub trait Haptics: Send {
+    /// 振动 `duration_ms` 毫秒, `strength` 为 0.0 ~ 1.0
+    fn pulse(&mut self, duration_ms: u16, strength: f32);
+}
+
+/// 把反馈转发给数位板的振动马达
+pub struct HapticSink {
+    devices: HashMap<TabletId, Box<dyn Haptics>>,
+}
+
+impl HapticSink {
+    pub fn new() -> Self {
+        Self {
+            devices: HashMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, tablet: TabletId, device: Box<dyn Haptics>) {
+        self.devices.insert(tablet, device);
+    }
+
+    pub fn remove(&mut self, tablet: TabletId) {
+        self.devices.remove(&tablet);
+    }
+}
+
+impl Default for HapticSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FeedbackSink for HapticSink {
+    fn name(&self) -> &str {
+        "haptics"
+    }
+
+    fn trigger(&mut self, tablet: TabletId, kind: FeedbackKind) {
+        if let Some(device) = self.devices.get_mut(&tablet) {
+            let (duration, strength) = match kind {
+                FeedbackKind::PenDown => (15, 0.6),
+                FeedbackKind::PenUp => (10, 0.3),
+                FeedbackKind::ButtonPress => (20, 0.8),
+            };
+            device.pulse(duration, strength);
+        }
+    }
+}
+
+/// 短促的提示音，通过 `pw-play` 播放
+pub struct SoundSink {
+    volume: f32,
+    clips: HashMap<FeedbackKind, NamedTempFile>,
+    /// 还在播放的进程
+    playing: Vec<Child>,
+}
+
+const SAMPLE_RATE: u32 = 48000;
+
+/// 生成一段衰减的正弦波, 16 位单声道 WAV
+fn click_wav(frequency: f32, duration_ms: u32) -> Vec<u8> {
+    let samples = SAMPLE_RATE * duration_ms / 1000;
+    let mut wav = Vec::with_capacity(44 + samples as usize * 2);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + samples * 2).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&(samples * 2).to_le_bytes());
+    for i in 0..samples {
+        let t = i as f32 / SAMPLE_RATE as f32;
+        let envelope = 1.0 - i as f32 / samples as f32;
+        let value = (t * frequency * std::f32::consts::TAU).sin() * envelope * envelope;
+        wav.extend_from_slice(&((value * i16::MAX as f32) as i16).to_le_bytes());
+    }
+    wav
+}
+
+impl SoundSink {
+    pub fn new(volume: f32) -> anyhow::Result<Self> {
+        let mut clips = HashMap::new();
+        for (kind, frequency, duration) in [
+            (FeedbackKind::PenDown, 1800.0, 25),
+            (FeedbackKind::PenUp, 1200.0, 20),
+            (FeedbackKind::ButtonPress, 900.0, 40),
+        ] {
+            let mut file = tempfile::Builder::new()
+                .prefix("tabletd-click-")
+                .suffix(".wav")
+                .tempfile()
+                .context("无法创建提示音文件")?;
+            file.write_all(&click_wav(frequency, duration))
+                .context("无法写入提示音文件")?;
+            clips.insert(kind, file);
+        }
+        Ok(Self {
+            volume: volume.clamp(0.0, 1.0),
+            clips,
+            playing: Vec::new(),
+        })
+    }
+}
+
+impl FeedbackSink for SoundSink {
+    fn name(&self) -> &str {
+        "sound"
+    }
+
+    fn trigger(&mut self, _tablet: TabletId, kind: FeedbackKind) {
+        // 回收已经播放完的进程
+        self.playing
+            .retain_mut(|child| matches!(child.try_wait(), Ok(None)));
+        let Some(clip) = self.clips.get(&kind) else {
+            return;
+        };
+        let result = Command::new("pw-play")
+            .arg(format!("--volume={}", self.volume))
+            .arg(clip.path())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        match result {
+            Ok(child) => self.playing.push(child),
+            Err(e) => warn!("无法播放提示音: {e}"),
+        }
+    }
+}
+
+/// 在落笔、抬笔和按键时触发反馈, 不修改事件
+pub struct Feedback {
+    config: FeedbackConfig,
+    /// 单独设置了反馈的数位板, 跟随配置时使用
+    tablets: HashMap<TabletId, FeedbackConfig>,
+    sinks: Vec<Box<dyn FeedbackSink>>,
+    /// 每块数位板的笔是否按下
+    pressed: HashMap<TabletId, bool>,
+}
+
+impl Feedback {
+    pub fn new(config: FeedbackConfig) -> Self {
+        Self {
+            config,
+            tablets: HashMap::new(),
+            sinks: Vec::new(),
+            pressed: HashMap::new(),
+        }
+    }
+
+    /// 按设置创建默认的输出
+    pub fn with_default_sinks(config: FeedbackConfig) -> Self {
+        let mut feedback = Self::new(config);
+        if feedback.config.sound {
+            match SoundSink::new(feedback.config.volume) {
+                Ok(sink) => feedback.add_sink(Box::new(sink)),
+                Err(e) => warn!("提示音不可用: {e:#}"),
+            }
+        }
+        feedback
+    }
+
+    pub fn config(&self) -> &FeedbackConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: FeedbackConfig) {
+        self.config = config;
+    }
+
+    pub fn add_sink(&mut self, sink: Box<dyn FeedbackSink>) {
+        self.sinks.push(sink);
+    }
+
+    fn trigger(&mut self, tablet: TabletId, kind: FeedbackKind) {
+        let config = self.tablets.get(&tablet).unwrap_or(&self.config);
+        if !config.is_enabled(kind) {
+            return;
+        }
+        for sink in &mut self.sinks {
+            let enabled = match sink.name() {
+                "sound" => config.sound,
+                "haptics" => config.haptics,
+                _ => true,
+            };
+            if enabled {
+                sink.trigger(tablet, kind);
+            }
+        }
+    }
+}
+
+impl ConfigStage for Feedback {
+    fn name(&self) -> &str {
+        "feedback"
+    }
+
+    /// 第一次有数位板需要提示音时才创建 [`SoundSink`]
+    fn apply(&mut self, config: &Config, _change: &ConfigChange) -> anyhow::Result<()> {
+        self.config = config.defaults.feedback.clone();
+        self.tablets = config
+            .tablets
+            .iter()
+            .map(|tablet| (tablet.id, tablet.profile.feedback.clone()))
+            .collect();
+        let sound = std::iter::once(&self.config)
+            .chain(self.tablets.values())
+            .find(|config| {
+                config.sound && (config.pen_down || config.pen_up || config.button_press)
+            });
+        if let Some(sound) = sound
+            && !self.sinks.iter().any(|sink| sink.name() == "sound")
+        {
+            match SoundSink::new(sound.volume) {
+                Ok(sink) => self.sinks.push(Box::new(sink)),
+                Err(e) => warn!("提示音不可用: {e:#}"),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RouterFilter for Feedback {
+    fn name(&self) -> &str {
+        "feedback"
+    }
+
+    fn config_stage(&mut self) -> Option<&mut dyn ConfigStage> {
+        Some(self)
+    }
+
+    fn filter(&mut self, event: &mut RoutedEvent) -> Verdict {
+        match &event.event {
+            TabletEvent::PenEvent(pen) => {
+                let pressed = matches!(pen.location, PenLocation::Pressed);
+                let was_pressed = self.pressed.insert(event.tablet, pressed).unwrap_or(false);
+                if pressed && !was_pressed {
+                    self.trigger(event.tablet, FeedbackKind::PenDown);
+                } else if !pressed && was_pressed {
+                    self.trigger(event.tablet, FeedbackKind::PenUp);
+                }
+            }
+            TabletEvent::AuxButton(button) if button.pressed => {
+                self.trigger(event.tablet, FeedbackKind::ButtonPress);
+            }
+            _ => {}
+        }
+        Verdict::Pass
+    }
+}
@@ -0,0 +1,344 @@
+//! 笔杆按键的手势
+//!
+//! 把笔杆按键的按下和松开识别成更高层的手势, 发给订阅者([`Gestures::subscribe`]):
+//!
+//! - [`GestureKind::Press`]: 按下时立刻发出
+//! - [`GestureKind::Click`]: 在 `hold_ms` 之内松开, 且 `double_click_ms` 之内没有再次按下.
+//!   要等双击的时间过去才能确定, 所以比按下晚一点发出
+//! - [`GestureKind::DoubleClick`]: 单击后 `double_click_ms` 之内再次按下, 在第二次按下时发出
+//! - [`GestureKind::Hold`]: 笔尖没有接触时按住超过 `hold_ms`
+//! - [`GestureKind::Chord`]: 按住按键时笔尖接触(或者接触时按下按键), 比如按住下键落笔拖动画布
+//!
+//! 同一次按下只会产生单击、双击、长按、组合中的一种. 时间按事件的时间戳计算, 笔在感应范围内时
+//! 事件不断到达, 长按和单击在超时后的下一个事件时发出; 笔离开感应范围时立刻发出等待中的单击.
+//!
+//! 过滤器本身不消费事件, 绑定([`super::bindings::BindingEngine::set_gestures`])和快捷菜单
+//! ([`super::quick_menu::QuickMenu::set_gestures`])在自己的过滤器中读取手势, 所以要添加在它们之前.
+//! 时间由 [`Profile::gestures`](crate::profile::Profile::gestures) 设置
+//!
+//! ```toml
+//! [defaults.gestures]
+//! hold_ms = 500
+//! double_click_ms = 250
+//!
+//! [[defaults.gesture_bindings]]
+//! gesture = "double_click"
+//! button = "upper"
+//! action = "keys"
+//! keys = "ctrl+z"
+//! ```
+
+use std::collections::HashMap;
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tracing::debug;
+
+use crate::{
+    config::{Config, ConfigChange, transaction::ConfigStage},
+    event_model::{
+        event::{PenButton, PenLocation, TabletEvent},
+        stamp::EventStamp,
+        tablet::TabletId,
+    },
+    mapping::ScreenPoint,
+};
+
+use super::{RoutedEvent, RouterFilter, Verdict};
+
+/// 允许设置的最长时间(毫秒)
+pub const MAX_GESTURE_MS: u32 = 2000;
+/// 订阅者读取得太慢时最多积压的手势数量
+const CHANNEL_CAPACITY: usize = 64;
+
+/// 笔杆上的按键
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BarrelButton {
+    Upper,
+    /// 靠近笔尖的按键
+    Lower,
+}
+
+impl BarrelButton {
+    const ALL: [BarrelButton; 2] = [BarrelButton::Upper, BarrelButton::Lower];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn is_pressed(self, buttons: &PenButton) -> bool {
+        match self {
+            BarrelButton::Upper => buttons.upper,
+            BarrelButton::Lower => buttons.lower,
+        }
+    }
+}
+
+/// 手势的种类
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GestureKind {
+    Press,
+    Click,
+    DoubleClick,
+    Hold,
+    Chord,
+}
+
+/// 识别出的手势
+#[derive(Debug, Clone)]
+pub struct PenGesture {
+    pub tablet: TabletId,
+    pub button: BarrelButton,
+    pub kind: GestureKind,
+    /// 识别出手势时的模式组
+    pub bank: u8,
+    /// 识别出手势时笔的屏幕位置
+    pub position: Option<ScreenPoint>,
+    /// 识别出手势的事件
+    pub stamp: EventStamp,
+}
+
+/// 手势的时间设置
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GestureConfig {
+    /// 按住多久(毫秒)算长按
+    pub hold_ms: u32,
+    /// 两次单击间隔多久(毫秒)之内算双击
+    pub double_click_ms: u32,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        Self {
+            hold_ms: 500,
+            double_click_ms: 250,
+        }
+    }
+}
+
+impl GestureConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (name, ms) in [
+            ("hold_ms", self.hold_ms),
+            ("double_click_ms", self.double_click_ms),
+        ] {
+            if ms == 0 || ms > MAX_GESTURE_MS {
+                bail!("{name} 必须在 1 ~ {MAX_GESTURE_MS} 之间, 实际为 {ms}");
+            }
+        }
+        Ok(())
+    }
+
+    fn hold_us(&self) -> u64 {
+        self.hold_ms as u64 * 1000
+    }
+
+    fn double_click_us(&self) -> u64 {
+        self.double_click_ms as u64 * 1000
+    }
+}
+
+/// 一个按键的识别状态, 时间都是事件的时间戳(微秒)
+#[derive(Debug, Default, Clone, Copy)]
+struct ButtonState {
+    /// 正按着时按下的时间
+    pressed_at: Option<u64>,
+    /// 这次按下已经产生了手势, 松开时不再算单击
+    decided: bool,
+    /// 等待双击的单击松开的时间
+    click_at: Option<u64>,
+}
+
+/// 一块数位板的识别状态
+#[derive(Debug, Default)]
+struct TabletState {
+    buttons: [ButtonState; 2],
+    /// 笔尖正接触
+    tip: bool,
+}
+
+/// 识别笔杆按键的手势, 不修改也不消费事件
+pub struct Gestures {
+    default: GestureConfig,
+    configs: HashMap<TabletId, GestureConfig>,
+    tablets: HashMap<TabletId, TabletState>,
+    tx: broadcast::Sender<PenGesture>,
+}
+
+impl Default for Gestures {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Gestures {
+    pub fn new() -> Self {
+        Self {
+            default: GestureConfig::default(),
+            configs: HashMap::new(),
+            tablets: HashMap::new(),
+            tx: broadcast::channel(CHANNEL_CAPACITY).0,
+        }
+    }
+
+    /// 订阅识别出的手势. 在同一个路由器中, 后面的过滤器处理同一个事件时就能读到
+    pub fn subscribe(&self) -> broadcast::Receiver<PenGesture> {
+        self.tx.subscribe()
+    }
+
+    /// 数位板使用的时间设置
+    pub fn config(&self, tablet: TabletId) -> &GestureConfig {
+        self.configs.get(&tablet).unwrap_or(&self.default)
+    }
+
+    /// 识别 `event` 之前(包括到 `event` 为止已经超时)的手势
+    fn recognize(&mut self, event: &RoutedEvent) -> Vec<(BarrelButton, GestureKind)> {
+        let config = self.config(event.tablet).clone();
+        let state = self.tablets.entry(event.tablet).or_default();
+        let now = event.stamp.timestamp;
+        let mut gestures = Vec::new();
+
+        for button in BarrelButton::ALL {
+            let b = &mut state.buttons[button.index()];
+            if let Some(pressed_at) = b.pressed_at
+                && !b.decided
+                && !state.tip
+                && now.saturating_sub(pressed_at) >= config.hold_us()
+            {
+                b.decided = true;
+                gestures.push((button, GestureKind::Hold));
+            }
+            if let Some(click_at) = b.click_at
+                && now.saturating_sub(click_at) >= config.double_click_us()
+            {
+                b.click_at = None;
+                gestures.push((button, GestureKind::Click));
+            }
+        }
+
+        match &event.event {
+            TabletEvent::PenButton(buttons) => {
+                for button in BarrelButton::ALL {
+                    let b = &mut state.buttons[button.index()];
+                    match (b.pressed_at, button.is_pressed(buttons)) {
+                        (None, true) => {
+                            gestures.push((button, GestureKind::Press));
+                            b.pressed_at = Some(now);
+                            b.decided = false;
+                            if b.click_at.take().is_some() {
+                                b.decided = true;
+                                gestures.push((button, GestureKind::DoubleClick));
+                            } else if state.tip {
+                                b.decided = true;
+                                gestures.push((button, GestureKind::Chord));
+                            }
+                        }
+                        (Some(pressed_at), false) => {
+                            b.pressed_at = None;
+                            if b.decided {
+                                continue;
+                            }
+                            // 按住期间没有其他事件, 松开时才发现已经超时
+                            if now.saturating_sub(pressed_at) >= config.hold_us() {
+                                gestures.push((button, GestureKind::Hold));
+                            } else {
+                                b.click_at = Some(now);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            TabletEvent::PenEvent(pen) => {
+                let tip = matches!(pen.location, PenLocation::Pressed);
+                if tip && !state.tip {
+                    for button in BarrelButton::ALL {
+                        let b = &mut state.buttons[button.index()];
+                        if b.pressed_at.is_some() && !b.decided {
+                            b.decided = true;
+                            gestures.push((button, GestureKind::Chord));
+                        }
+                    }
+                }
+                state.tip = tip;
+                if matches!(pen.location, PenLocation::Leaved) {
+                    Self::flush_clicks(state, &mut gestures);
+                }
+            }
+            TabletEvent::ToolOut(_) => Self::flush_clicks(state, &mut gestures),
+            _ => {}
+        }
+        gestures
+    }
+
+    /// 笔离开后不会再有第二次单击
+    fn flush_clicks(state: &mut TabletState, gestures: &mut Vec<(BarrelButton, GestureKind)>) {
+        for button in BarrelButton::ALL {
+            if state.buttons[button.index()].click_at.take().is_some() {
+                gestures.push((button, GestureKind::Click));
+            }
+        }
+    }
+}
+
+impl RouterFilter for Gestures {
+    fn name(&self) -> &str {
+        "gestures"
+    }
+
+    fn config_stage(&mut self) -> Option<&mut dyn ConfigStage> {
+        Some(self)
+    }
+
+    fn filter(&mut self, event: &mut RoutedEvent) -> Verdict {
+        for (button, kind) in self.recognize(event) {
+            debug!("{} 的笔杆按键手势: {button:?} {kind:?}", event.tablet);
+            // 没有订阅者时发送失败, 手势直接丢弃
+            let _ = self.tx.send(PenGesture {
+                tablet: event.tablet,
+                button,
+                kind,
+                bank: event.bank,
+                position: event.position.clone(),
+                stamp: event.stamp,
+            });
+        }
+        Verdict::Pass
+    }
+}
+
+impl ConfigStage for Gestures {
+    fn name(&self) -> &str {
+        "gestures"
+    }
+
+    fn validate(&self, config: &Config) -> anyhow::Result<()> {
+        config
+            .defaults
+            .gestures
+            .validate()
+            .context("默认的手势设置无效")?;
+        for tablet in &config.tablets {
+            tablet
+                .profile
+                .gestures
+                .validate()
+                .with_context(|| format!("{} 的手势设置无效", tablet.id))?;
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, config: &Config, _change: &ConfigChange) -> anyhow::Result<()> {
+        self.default = config.defaults.gestures.clone();
+        self.configs = config
+            .tablets
+            .iter()
+            .map(|tablet| (tablet.id, tablet.profile.gestures.clone()))
+            .collect();
+        Ok(())
+    }
+}
@@ -0,0 +1,168 @@
+//! 抬笔延迟(笔画粘合)
+//!
+//! 有些数位板的传感器偶尔会丢失几毫秒的接触，一笔被断成两笔. 打开后抬笔先暂存一小段时间，
+//! 期间笔重新接触就丢弃这次抬笔，笔画不会断开; 超时后再补发. 笔离开感应范围时立刻抬笔.
+//!
+//! 延迟由 [`Profile::pen_up_delay_ms`](crate::profile::Profile::pen_up_delay_ms) 设置,
+//! 默认为 0 (不启用), 最大为 [`MAX_PEN_UP_DELAY_MS`]
+
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use anyhow::bail;
+
+use crate::{
+    config::{Config, ConfigChange, transaction::ConfigStage},
+    event_model::{
+        event::{PenLocation, TabletEvent},
+        tablet::TabletId,
+    },
+    profile::Profile,
+};
+
+use super::InputEvent;
+
+/// 允许的最大延迟, 再长会明显感觉到抬笔滞后
+pub const MAX_PEN_UP_DELAY_MS: u32 = 50;
+
+/// 暂存的抬笔
+struct HeldPenUp {
+    pen_up: InputEvent,
+    /// 暂存期间最后一个悬浮事件, 超时后跟在抬笔之后补发
+    latest: Option<InputEvent>,
+    deadline: Instant,
+}
+
+/// 按数位板暂存抬笔事件
+#[derive(Default)]
+pub struct PenUpGlue {
+    default: Duration,
+    delays: HashMap<TabletId, Duration>,
+    /// 笔正按下的数位板
+    pressed: HashSet<TabletId>,
+    held: HashMap<TabletId, HeldPenUp>,
+}
+
+fn delay(profile: &Profile) -> Duration {
+    Duration::from_millis(profile.pen_up_delay_ms as u64)
+}
+
+impl PenUpGlue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 数位板的抬笔延迟, 为 0 时不启用
+    pub fn delay(&self, tablet: TabletId) -> Duration {
+        self.delays.get(&tablet).copied().unwrap_or(self.default)
+    }
+
+    /// 处理一个输入事件, 返回现在应该继续传递的事件
+    pub fn push(&mut self, input: InputEvent, now: Instant) -> Vec<InputEvent> {
+        let TabletEvent::PenEvent(pen) = &input.event else {
+            return vec![input];
+        };
+        let tablet = input.tablet;
+        match (pen.location, self.held.remove(&tablet)) {
+            // 重新接触, 丢弃暂存的抬笔
+            (PenLocation::Pressed, _) => {
+                self.pressed.insert(tablet);
+                vec![input]
+            }
+            (PenLocation::Leaved, Some(held)) => {
+                self.pressed.remove(&tablet);
+                vec![held.pen_up, input]
+            }
+            (PenLocation::Floating, Some(mut held)) => {
+                held.latest = Some(input);
+                self.held.insert(tablet, held);
+                Vec::new()
+            }
+            (location, None) => {
+                let delay = self.delay(tablet);
+                if !self.pressed.remove(&tablet)
+                    || delay.is_zero()
+                    || matches!(location, PenLocation::Leaved)
+                {
+                    return vec![input];
+                }
+                self.held.insert(
+                    tablet,
+                    HeldPenUp {
+                        pen_up: input,
+                        latest: None,
+                        deadline: now + delay,
+                    },
+                );
+                Vec::new()
+            }
+        }
+    }
+
+    /// 最早需要补发抬笔的时间
+    pub fn deadline(&self) -> Option<Instant> {
+        self.held.values().map(|held| held.deadline).min()
+    }
+
+    /// 补发已经超时的抬笔
+    pub fn expire(&mut self, now: Instant) -> Vec<InputEvent> {
+        let expired: Vec<_> = self
+            .held
+            .iter()
+            .filter(|(_, held)| held.deadline <= now)
+            .map(|(tablet, _)| *tablet)
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|tablet| self.held.remove(&tablet))
+            .flat_map(|held| std::iter::once(held.pen_up).chain(held.latest))
+            .collect()
+    }
+
+    /// 立刻补发所有暂存的抬笔
+    pub fn flush(&mut self) -> Vec<InputEvent> {
+        self.pressed.clear();
+        self.held
+            .drain()
+            .flat_map(|(_, held)| std::iter::once(held.pen_up).chain(held.latest))
+            .collect()
+    }
+
+    /// 数位板已断开, 丢弃它的状态
+    pub fn forget(&mut self, tablet: TabletId) {
+        self.pressed.remove(&tablet);
+        self.held.remove(&tablet);
+    }
+}
+
+impl ConfigStage for PenUpGlue {
+    fn name(&self) -> &str {
+        "pen-up-glue"
+    }
+
+    fn validate(&self, config: &Config) -> anyhow::Result<()> {
+        let profiles = std::iter::once(&config.defaults)
+            .chain(config.tablets.iter().map(|tablet| &tablet.profile));
+        for profile in profiles {
+            if profile.pen_up_delay_ms > MAX_PEN_UP_DELAY_MS {
+                bail!(
+                    "pen_up_delay_ms 不能超过 {MAX_PEN_UP_DELAY_MS}, 实际为 {}",
+                    profile.pen_up_delay_ms
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, config: &Config, _change: &ConfigChange) -> anyhow::Result<()> {
+        self.default = delay(&config.defaults);
+        self.delays = config
+            .tablets
+            .iter()
+            .map(|tablet| (tablet.id, delay(&tablet.profile)))
+            .collect();
+        Ok(())
+    }
+}
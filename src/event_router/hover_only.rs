@@ -0,0 +1,98 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use crate::{
+    config::{Config, ConfigChange, transaction::ConfigStage},
+    event_model::{
+        event::{PenLocation, TabletEvent},
+        tablet::TabletId,
+    },
+    screen_overlay::trail::LaserTrail,
+};
+
+use super::{RoutedEvent, RouterFilter, Verdict};
+
+/// 演示模式过滤器
+///
+/// 笔尖按下时不再向后传递点击和压力，而是把笔的屏幕位置记录为激光笔轨迹，
+/// 交给 overlay 显示, 所以要添加在映射到屏幕之后. 只处理设置中打开了
+/// [`Profile::hover_only`](crate::profile::Profile::hover_only) 的数位板
+pub struct HoverOnly {
+    trail: Arc<Mutex<LaserTrail>>,
+    default: bool,
+    tablets: HashMap<TabletId, bool>,
+}
+
+impl HoverOnly {
+    /// `trail` 和 overlay 共享, 见 [`crate::screen_overlay::layers::OverlayLayers::set_trail`]
+    pub fn new(trail: Arc<Mutex<LaserTrail>>) -> Self {
+        Self {
+            trail,
+            default: false,
+            tablets: HashMap::new(),
+        }
+    }
+
+    /// 数位板是否处于演示模式
+    pub fn is_enabled(&self, tablet: TabletId) -> bool {
+        self.tablets.get(&tablet).copied().unwrap_or(self.default)
+    }
+
+    /// 当前的激光笔轨迹
+    pub fn trail(&self) -> Arc<Mutex<LaserTrail>> {
+        self.trail.clone()
+    }
+
+    /// 把按下的笔降级为悬浮状态, 没有屏幕位置时不记录轨迹
+    pub fn apply(&mut self, event: &mut RoutedEvent) {
+        let TabletEvent::PenEvent(pen) = &mut event.event else {
+            return;
+        };
+        let mut trail = self.trail.lock().unwrap();
+        if let PenLocation::Pressed = pen.location {
+            if let Some(position) = &event.position {
+                trail.push(position.logical_x, position.logical_y, Instant::now());
+            }
+            pen.location = PenLocation::Floating;
+            pen.pressure = 0;
+        } else {
+            trail.lift();
+        }
+    }
+}
+
+impl RouterFilter for HoverOnly {
+    fn name(&self) -> &str {
+        "hover-only"
+    }
+
+    fn config_stage(&mut self) -> Option<&mut dyn ConfigStage> {
+        Some(self)
+    }
+
+    fn filter(&mut self, event: &mut RoutedEvent) -> Verdict {
+        if self.is_enabled(event.tablet) {
+            self.apply(event);
+        }
+        Verdict::Pass
+    }
+}
+
+impl ConfigStage for HoverOnly {
+    fn name(&self) -> &str {
+        "hover-only"
+    }
+
+    fn apply(&mut self, config: &Config, _change: &ConfigChange) -> anyhow::Result<()> {
+        self.default = config.defaults.hover_only;
+        self.tablets = config
+            .tablets
+            .iter()
+            .map(|tablet| (tablet.id, tablet.profile.hover_only))
+            .collect();
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+//! 驱动到路由器的两条通道
+//!
+//! 笔按下和抬起、按键、工具切换这些状态变化少一个就会出错(比如按下后收不到抬起), 而移动只需要
+//! 最新的位置. 两者走同一个通道时, 负载高的时候一次点击要排在一长串移动后面. 这里把它们分开:
+//!
+//! - 变化通道: 不合并、不丢弃, 满了以后发送端等待
+//! - 移动通道: 满了以后丢弃新的移动, 之后的移动带着更新的位置
+//!
+//! 接收端优先取出变化. 为了不打乱同一块数位板的顺序, 交出变化前先把这块数位板在它之前的移动
+//! 按种类([`MotionKind`])各合并成最后一个排在前面, 其他数位板的移动排在变化后面
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::mpsc::{
+    self,
+    error::{SendError, TrySendError},
+};
+
+use crate::event_model::{
+    event::{PenLocation, TabletEvent, ToolType},
+    tablet::TabletId,
+};
+
+use super::InputEvent;
+
+/// 事件走哪条通道
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lane {
+    /// 按下、抬起、离开、工具切换、按键、滚轮和手指离开触控环, 不合并也不丢弃
+    Transition,
+    /// 笔的位置和触控环上的位置, 可以合并
+    Motion,
+}
+
+/// 移动的种类, 同一块数位板同一种的移动只需要最后一个
+///
+/// 笔的位置不能覆盖触控环上的位置, 反过来也一样
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MotionKind {
+    Pen,
+    Ring(u8),
+    Other,
+}
+
+impl MotionKind {
+    pub fn of(event: &TabletEvent) -> Self {
+        match event {
+            TabletEvent::PenEvent(_) => Self::Pen,
+            TabletEvent::Ring(ring) => Self::Ring(ring.ring),
+            _ => Self::Other,
+        }
+    }
+}
+
+/// 通道中的事件, 按发送的顺序编号
+struct Ticketed {
+    ticket: u64,
+    event: InputEvent,
+}
+
+#[derive(Default)]
+struct SenderState {
+    next_ticket: u64,
+    /// 每块数位板上一个笔事件的工具和位置
+    pens: HashMap<TabletId, (ToolType, PenLocation)>,
+}
+
+impl SenderState {
+    fn classify(&mut self, input: &InputEvent) -> Lane {
+        match &input.event {
+            TabletEvent::PenEvent(pen) => {
+                let previous = self.pens.insert(input.tablet, (pen.tool, pen.location));
+                if previous == Some((pen.tool, pen.location)) {
+                    Lane::Motion
+                } else {
+                    Lane::Transition
+                }
+            }
+            // 手指离开后 HUD 要收起转盘
+            TabletEvent::Ring(ring) if ring.position.is_none() => Lane::Transition,
+            TabletEvent::Ring(_) | TabletEvent::Unknown => Lane::Motion,
+            // 滚轮的每一格都是一次操作
+            TabletEvent::AuxButton(_)
+            | TabletEvent::Wheel(_)
+            | TabletEvent::ToolIn(_)
+            | TabletEvent::ToolOut(_)
+            | TabletEvent::PenButton(_) => Lane::Transition,
+        }
+    }
+}
+
+/// 驱动向路由器发送事件的通道
+#[derive(Clone)]
+pub struct EventSender {
+    transitions: mpsc::Sender<Ticketed>,
+    motion: mpsc::Sender<Ticketed>,
+    state: Arc<Mutex<SenderState>>,
+}
+
+impl EventSender {
+    /// 发送一个事件. 变化通道满了时等待, 移动通道满了时丢弃这个移动
+    pub async fn send(&self, event: InputEvent) -> Result<(), SendError<InputEvent>> {
+        let (lane, ticket) = {
+            let mut state = self.state.lock().unwrap();
+            let lane = state.classify(&event);
+            state.next_ticket += 1;
+            (lane, state.next_ticket)
+        };
+        let item = Ticketed { ticket, event };
+        match lane {
+            Lane::Transition => self
+                .transitions
+                .send(item)
+                .await
+                .map_err(|e| SendError(e.0.event)),
+            Lane::Motion => match self.motion.try_send(item) {
+                Ok(()) | Err(TrySendError::Full(_)) => Ok(()),
+                Err(TrySendError::Closed(item)) => Err(SendError(item.event)),
+            },
+        }
+    }
+
+    /// 路由器是否已经关闭
+    pub fn is_closed(&self) -> bool {
+        self.transitions.is_closed()
+    }
+}
+
+/// 路由器接收事件的一端, 见模块文档
+pub struct EventReceiver {
+    transitions: mpsc::Receiver<Ticketed>,
+    motion: mpsc::Receiver<Ticketed>,
+    /// 已经从移动通道取出、还没有交出的移动
+    backlog: VecDeque<Ticketed>,
+    /// 已经取出的变化, 同一块数位板在它之前的移动交出后再交出
+    transition: Option<Ticketed>,
+}
+
+/// 创建一对通道, 每条通道最多缓存 `capacity` 个事件
+pub fn channel(capacity: usize) -> (EventSender, EventReceiver) {
+    let (transitions_tx, transitions) = mpsc::channel(capacity);
+    let (motion_tx, motion) = mpsc::channel(capacity);
+    let sender = EventSender {
+        transitions: transitions_tx,
+        motion: motion_tx,
+        state: Arc::default(),
+    };
+    let receiver = EventReceiver {
+        transitions,
+        motion,
+        backlog: VecDeque::new(),
+        transition: None,
+    };
+    (sender, receiver)
+}
+
+impl EventReceiver {
+    /// 等待下一个事件, 所有发送端都关闭后返回 `None`
+    pub async fn recv(&mut self) -> Option<InputEvent> {
+        self.recv_lane().await.map(|(_, event)| event)
+    }
+
+    /// 等待下一个事件和它走的通道
+    pub async fn recv_lane(&mut self) -> Option<(Lane, InputEvent)> {
+        loop {
+            if let Some(ready) = self.try_recv_lane() {
+                return Some(ready);
+            }
+            tokio::select! {
+                biased;
+                Some(item) = self.transitions.recv() => self.transition = Some(item),
+                Some(item) = self.motion.recv() => self.backlog.push_back(item),
+                else => return None,
+            }
+        }
+    }
+
+    /// 不等待地取出下一个已经到达的事件
+    pub fn try_recv_lane(&mut self) -> Option<(Lane, InputEvent)> {
+        // 先取变化再取移动: 取到的变化之前发送的移动这时一定已经在移动通道中
+        if self.transition.is_none() {
+            self.transition = self.transitions.try_recv().ok();
+        }
+        while let Ok(item) = self.motion.try_recv() {
+            self.backlog.push_back(item);
+        }
+        if let Some(transition) = self.transition.take() {
+            if let Some(motion) = self.coalesce_before(&transition) {
+                self.transition = Some(transition);
+                return Some((Lane::Motion, motion));
+            }
+            return Some((Lane::Transition, transition.event));
+        }
+        self.backlog
+            .pop_front()
+            .map(|item| (Lane::Motion, item.event))
+    }
+
+    /// 从 `backlog` 中取出同一块数位板在 `transition` 之前的移动, 每种只留最后一个,
+    /// 按顺序每次返回一个
+    fn coalesce_before(&mut self, transition: &Ticketed) -> Option<InputEvent> {
+        let before = |item: &Ticketed| {
+            item.event.tablet == transition.event.tablet && item.ticket < transition.ticket
+        };
+        let latest: HashMap<_, _> = self
+            .backlog
+            .iter()
+            .filter(|item| before(item))
+            .map(|item| (MotionKind::of(&item.event.event), item.ticket))
+            .collect();
+        self.backlog.retain(|item| {
+            !before(item) || latest.get(&MotionKind::of(&item.event.event)) == Some(&item.ticket)
+        });
+        let index = self.backlog.iter().position(before)?;
+        self.backlog.remove(index).map(|item| item.event)
+    }
+}
@@ -0,0 +1,644 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::Arc,
+    time::Instant,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc, watch};
+use tracing::{error, info, warn};
+
+use crate::{
+    config::{
+        Config, ConfigBus,
+        transaction::{self, ConfigStage},
+    },
+    event_model::{
+        capability::DeviceCapabilities,
+        event::{PenLocation, TabletEvent},
+        latency::{LatencyStage, LatencyStats},
+        stamp::{EventStamp, monotonic_micros},
+        tablet::TabletId,
+    },
+    hud_interface::{
+        HudEvent, HudSender,
+        pointer::{HudElement, HudPointer, HudTarget, OverlayPoint},
+    },
+    input_devices::{
+        hotplug::{ConnectedDevice, DeviceEvent},
+        transport::{Disconnected, Transport, TransportArbiter},
+    },
+    mapping::{
+        ScreenPoint,
+        geometry::{GeometryBus, GeometryChanged},
+    },
+    profile::focus::FocusBus,
+};
+
+use black_box::{BlackBox, RecordKind};
+use fair::FairQueue;
+use glue::PenUpGlue;
+
+/// 快捷键和滚轮绑定
+pub mod bindings;
+/// 最近事件的记录, 用于诊断偶发问题
+pub mod black_box;
+/// 拦截所有事件的开关(比如 HUD 打开时)
+pub mod capture;
+/// 破坏性操作的确认
+pub mod confirm;
+/// 多块数位板之间的公平调度
+pub mod fair;
+/// 落笔、抬笔和按键的声音/振动反馈
+pub mod feedback;
+/// 笔杆按键的手势(单击、双击、长按、和笔尖的组合)
+pub mod gesture;
+/// 抬笔延迟(笔画粘合)
+pub mod glue;
+/// 演示模式(只悬浮不点击)
+pub mod hover_only;
+/// 驱动到路由器的变化通道和移动通道
+pub mod lanes;
+/// 模式组切换(类似 Wacom ExpressKey 模式)
+pub mod mode_bank;
+/// 压感曲线
+pub mod pressure;
+/// 按住笔杆按键弹出的快捷菜单
+pub mod quick_menu;
+/// 触控环的 HUD 转盘
+pub mod ring;
+/// 映射到屏幕坐标
+pub mod screen;
+/// 用表达式修改笔事件
+pub mod script;
+/// 笔的位置平滑和预测
+pub mod smoothing;
+/// 按笔识别用户
+pub mod users;
+
+/// 驱动发出的事件，附带来源
+#[derive(Debug, Clone)]
+pub struct InputEvent {
+    pub tablet: TabletId,
+    /// 经过哪种连接收到的, 用于同一块数位板多个连接之间的选择
+    pub transport: Transport,
+    pub event: TabletEvent,
+    /// 驱动读到这个事件的时间和序号
+    pub stamp: EventStamp,
+}
+
+pub use lanes::{EventReceiver, EventSender};
+
+/// 经过路由器处理、交给 `event_dispatcher` 的事件
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoutedEvent {
+    pub tablet: TabletId,
+    pub event: TabletEvent,
+    /// 当前模式组
+    pub bank: u8,
+    /// 笔事件映射到的屏幕位置
+    pub position: Option<ScreenPoint>,
+    /// 被 tabletd 内部处理了，值为处理它的过滤器名称.
+    /// 这样的事件不会交给系统输入，只通过 `tabletd API` 发出
+    pub consumed_by: Option<String>,
+    /// 驱动读到这个事件的时间和序号, 见 [`InputEvent::stamp`]
+    pub stamp: EventStamp,
+    /// 被 HUD 拦截时接收它的界面元素, 由路由器补上笔在 overlay 上的位置
+    #[serde(default)]
+    pub hud: Option<HudTarget>,
+    /// 离开路由器的时间, `CLOCK_MONOTONIC` 的微秒数. 见 [`crate::event_model::latency`]
+    #[serde(default)]
+    pub routed_at: u64,
+}
+
+impl RoutedEvent {
+    pub fn is_consumed(&self) -> bool {
+        self.consumed_by.is_some()
+    }
+
+    /// 把事件交给 HUD 的 `element`, 已经交给其他元素时不变
+    pub fn target_hud(&mut self, element: HudElement) {
+        self.hud.get_or_insert(HudTarget {
+            element,
+            point: None,
+        });
+    }
+}
+
+/// 过滤器的处理结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// 继续传递
+    Pass,
+    /// 由 tabletd 处理，标记为已消费
+    Consume,
+}
+
+/// 路由器中的一个处理步骤
+///
+/// 过滤器可以修改事件，或者把它标记为已消费. 已消费的事件仍然会经过后面的过滤器
+/// (比如需要记录笔的位置)，但不能再被取消标记
+pub trait RouterFilter: Send {
+    /// 过滤器名称，会出现在 [`RoutedEvent::consumed_by`] 中
+    fn name(&self) -> &str;
+
+    fn filter(&mut self, event: &mut RoutedEvent) -> Verdict;
+
+    /// 需要跟随配置变化时返回自己
+    fn config_stage(&mut self) -> Option<&mut dyn ConfigStage> {
+        None
+    }
+
+    /// 显示器布局变化(比如显示器接入或断开)
+    fn apply_geometry(&mut self, _geometry: &GeometryChanged) {}
+
+    /// 数位板接入, 在它的第一个事件之前
+    fn connect(&mut self, _tablet: TabletId, _capabilities: &DeviceCapabilities) {}
+
+    /// 数位板通过 hidraw 节点 `node` 接入, 在 [`RouterFilter::connect`] 之前
+    fn attach_node(&mut self, _tablet: TabletId, _node: &Path) {}
+
+    /// 数位板的所有连接都已断开, 补发的抬笔和松开按键的事件已经经过过滤器
+    fn disconnect(&mut self, _tablet: TabletId) {}
+}
+
+/// `event_model` 到 `event_dispatcher` 之间的事件管道
+///
+/// 先在同一块数位板的多个连接之间选出一个，再按注册顺序经过所有过滤器
+///
+/// 配置变化在事件之间应用，有笔按下时推迟到所有笔抬起，
+/// 所以一笔之中映射和绑定不会变化(显示器布局变化除外). 聚焦的应用有自己的设置时
+/// ([`crate::profile::app`]), 应用的是叠加后的配置, 切换同样等到笔抬起. [`Router::run`] 按数位板轮流处理
+/// 已到达的事件([`FairQueue`]), 在选择连接之前先经过 [`PenUpGlue`]
+pub struct Router {
+    arbiter: TransportArbiter<TabletId>,
+    glue: PenUpGlue,
+    filters: Vec<Box<dyn RouterFilter>>,
+    /// 除过滤器以外需要跟随配置的子系统
+    stages: Vec<Box<dyn ConfigStage>>,
+    /// 已应用的配置, 包括聚焦应用的设置
+    config: Arc<Config>,
+    /// 配置文件中的配置, 不含聚焦应用的设置
+    base: Arc<Config>,
+    /// 聚焦的应用
+    focus: Option<String>,
+    /// 等待笔抬起后应用的配置
+    pending: Option<Arc<Config>>,
+    config_rx: Option<watch::Receiver<Arc<Config>>>,
+    geometry_rx: Option<watch::Receiver<GeometryChanged>>,
+    focus_rx: Option<watch::Receiver<Option<String>>>,
+    device_rx: Option<broadcast::Receiver<DeviceEvent>>,
+    /// 笔正按下的数位板
+    pressed: HashSet<TabletId>,
+    /// 每块数位板最后一个事件的模式组, 下一个事件从它开始
+    banks: HashMap<TabletId, u8>,
+    black_box: BlackBox,
+    latency: LatencyStats,
+    /// 当前的显示器布局, 用来换算交给 HUD 的位置
+    geometry: GeometryChanged,
+    hud: Option<HudSender>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self {
+            arbiter: TransportArbiter::new(),
+            glue: PenUpGlue::new(),
+            filters: Vec::new(),
+            stages: Vec::new(),
+            config: Arc::default(),
+            base: Arc::default(),
+            focus: None,
+            pending: None,
+            config_rx: None,
+            geometry_rx: None,
+            focus_rx: None,
+            device_rx: None,
+            pressed: HashSet::new(),
+            banks: HashMap::new(),
+            black_box: BlackBox::new(),
+            latency: LatencyStats::new(),
+            geometry: GeometryChanged::default(),
+            hud: None,
+        }
+    }
+
+    /// 被 HUD 拦截的事件连同 overlay 上的位置发往 `hud`
+    pub fn set_hud(&mut self, hud: HudSender) {
+        self.hud = Some(hud);
+    }
+
+    /// 记录收到的事件和处理结果的黑匣子, 出口也应该把事件的去向记在这里
+    pub fn black_box(&self) -> &BlackBox {
+        &self.black_box
+    }
+
+    /// 改用 `black_box` 记录, 比如让重新创建的路由器沿用之前的记录
+    pub fn set_black_box(&mut self, black_box: BlackBox) {
+        self.black_box = black_box;
+    }
+
+    /// 事件离开路由器时把路由的延迟记在 `latency` 中, 出口应该记录之后的阶段
+    pub fn set_latency(&mut self, latency: LatencyStats) {
+        self.latency = latency;
+    }
+
+    /// 在末尾添加过滤器
+    pub fn add_filter(&mut self, filter: Box<dyn RouterFilter>) {
+        self.filters.push(filter);
+    }
+
+    /// 按名称移除过滤器
+    pub fn remove_filter(&mut self, name: &str) -> Option<Box<dyn RouterFilter>> {
+        let index = self.filters.iter().position(|f| f.name() == name)?;
+        Some(self.filters.remove(index))
+    }
+
+    /// 添加需要跟随配置的子系统, 比如 [`bindings::BindingTable`]
+    pub fn add_stage(&mut self, stage: Box<dyn ConfigStage>) {
+        self.stages.push(stage);
+    }
+
+    /// 在 [`Router::run`] 中跟随 `bus` 上的配置, 当前配置会在第一个事件之前应用
+    pub fn follow_config(&mut self, bus: &ConfigBus) {
+        let mut rx = bus.subscribe();
+        rx.mark_changed();
+        self.config_rx = Some(rx);
+    }
+
+    /// 在 [`Router::run`] 中跟随 `bus` 上的显示器布局, 当前布局会在第一个事件之前应用
+    pub fn follow_geometry(&mut self, bus: &GeometryBus) {
+        let mut rx = bus.subscribe();
+        rx.mark_changed();
+        self.geometry_rx = Some(rx);
+    }
+
+    /// 在 [`Router::run`] 中跟随 `bus` 上聚焦的应用, 切换到应用的设置
+    pub fn follow_focus(&mut self, bus: &FocusBus) {
+        let mut rx = bus.subscribe();
+        rx.mark_changed();
+        self.focus_rx = Some(rx);
+    }
+
+    /// 在 [`Router::run`] 中跟随数位板的接入和断开,
+    /// 比如 [`crate::input_devices::hotplug::HotplugWatcher::subscribe`]
+    pub fn follow_devices(&mut self, rx: broadcast::Receiver<DeviceEvent>) {
+        self.device_rx = Some(rx);
+    }
+
+    /// 显示器布局变化时让所有过滤器重新计算映射
+    ///
+    /// 不等笔抬起: 目标显示器断开后旧的映射已经没有意义
+    pub fn apply_geometry(&mut self, geometry: &GeometryChanged) {
+        for filter in &mut self.filters {
+            filter.apply_geometry(geometry);
+        }
+        self.geometry = geometry.clone();
+    }
+
+    /// 已应用的配置, 包括聚焦应用的设置
+    pub fn config(&self) -> &Arc<Config> {
+        &self.config
+    }
+
+    /// 应用新配置, 有笔按下时推迟到所有笔抬起
+    ///
+    /// 失败时所有子系统保持旧配置
+    pub fn reconfigure(&mut self, config: Arc<Config>) -> anyhow::Result<()> {
+        config.validate_apps()?;
+        self.base = config;
+        self.pending = Some(self.effective());
+        self.apply_pending()
+    }
+
+    /// 聚焦的应用变化, 新旧应用都没有自己的设置时什么也不做
+    pub fn set_focus(&mut self, app_id: Option<String>) -> anyhow::Result<()> {
+        let app = |focus: &Option<String>| focus.as_deref().and_then(|id| self.base.app(id));
+        let changed = app(&self.focus) != app(&app_id);
+        self.focus = app_id;
+        if !changed {
+            return Ok(());
+        }
+        if let Some(app) = app(&self.focus) {
+            info!("切换到 {} 的设置", app.app_id);
+        }
+        self.pending = Some(self.effective());
+        self.apply_pending()
+    }
+
+    /// 叠加了聚焦应用设置的配置
+    fn effective(&self) -> Arc<Config> {
+        let Some(app) = self.focus.as_deref().and_then(|id| self.base.app(id)) else {
+            return Arc::clone(&self.base);
+        };
+        match self.base.with_app(app) {
+            Ok(config) => Arc::new(config),
+            // 配置在应用前已经检查过
+            Err(e) => {
+                warn!("{e:#}");
+                Arc::clone(&self.base)
+            }
+        }
+    }
+
+    fn apply_pending(&mut self) -> anyhow::Result<()> {
+        if !self.pressed.is_empty() {
+            return Ok(());
+        }
+        let Some(config) = self.pending.take() else {
+            return Ok(());
+        };
+        let mut stages: Vec<&mut dyn ConfigStage> = self
+            .stages
+            .iter_mut()
+            .map(|stage| stage.as_mut() as &mut dyn ConfigStage)
+            .chain(std::iter::once(&mut self.glue as &mut dyn ConfigStage))
+            .chain(
+                self.filters
+                    .iter_mut()
+                    .filter_map(|filter| filter.config_stage()),
+            )
+            .collect();
+        transaction::apply(&mut stages, &self.config, &config)?;
+        self.config = config;
+        Ok(())
+    }
+
+    /// 数位板的某个连接已接入, 第一个连接接入时通知所有过滤器
+    pub fn connect(
+        &mut self,
+        tablet: TabletId,
+        transport: Transport,
+        capabilities: &DeviceCapabilities,
+    ) {
+        let first = self.arbiter.active(&tablet).is_none();
+        self.arbiter.connect(tablet, transport);
+        if first {
+            for filter in &mut self.filters {
+                filter.connect(tablet, capabilities);
+            }
+        }
+    }
+
+    /// 接入设备的某个连接, 有 hidraw 节点时先交给过滤器
+    pub fn connect_device(&mut self, device: &ConnectedDevice) {
+        if !device.path.as_os_str().is_empty() {
+            for filter in &mut self.filters {
+                filter.attach_node(device.tablet, &device.path);
+            }
+        }
+        self.connect(device.tablet, device.transport, &device.capabilities);
+    }
+
+    /// 数位板的某个连接已断开, 所有连接都断开时返回补发的抬笔和松开按键的事件
+    pub fn disconnect(&mut self, tablet: TabletId, transport: Transport) -> Vec<RoutedEvent> {
+        // 暂存的抬笔由下面补发的松开事件代替
+        if self.arbiter.active(&tablet) == Some(transport) {
+            self.glue.forget(tablet);
+        }
+        let Disconnected::Lost(releases) = self.arbiter.disconnect(&tablet, transport) else {
+            return Vec::new();
+        };
+        let releases = releases
+            .into_iter()
+            .map(|(release, stamp)| self.run_filters(tablet, release, stamp))
+            .collect();
+        self.banks.remove(&tablet);
+        for filter in &mut self.filters {
+            filter.disconnect(tablet);
+        }
+        releases
+    }
+
+    /// 处理一个事件, 来自非活动连接的事件返回 `None`
+    pub fn route(&mut self, input: InputEvent) -> Option<RoutedEvent> {
+        if !self
+            .arbiter
+            .accept(&input.tablet, input.transport, &input.event, input.stamp)
+        {
+            self.black_box.record(
+                input.tablet,
+                input.stamp,
+                RecordKind::Ignored {
+                    transport: input.transport,
+                    reason: "不是正在使用的连接",
+                },
+            );
+            return None;
+        }
+        Some(self.run_filters(input.tablet, input.event, input.stamp))
+    }
+
+    /// 补上笔在 overlay 上的位置, 把事件交给 HUD
+    fn forward_to_hud(&self, routed: &mut RoutedEvent) {
+        let Some(target) = routed.hud.as_mut() else {
+            return;
+        };
+        if target.point.is_none()
+            && let Some(position) = &routed.position
+        {
+            target.point = OverlayPoint::from_screen(position, &self.geometry);
+        }
+        if let Some(hud) = &self.hud {
+            let _ = hud.send(HudEvent::Pointer(HudPointer {
+                tablet: routed.tablet,
+                target: target.clone(),
+                event: routed.event.clone(),
+            }));
+        }
+    }
+
+    fn run_filters(
+        &mut self,
+        tablet: TabletId,
+        event: TabletEvent,
+        stamp: EventStamp,
+    ) -> RoutedEvent {
+        let mut routed = RoutedEvent {
+            tablet,
+            event,
+            bank: self.banks.get(&tablet).copied().unwrap_or_default(),
+            position: None,
+            consumed_by: None,
+            stamp,
+            hud: None,
+            routed_at: 0,
+        };
+        for filter in &mut self.filters {
+            if filter.filter(&mut routed) == Verdict::Consume && routed.consumed_by.is_none() {
+                routed.consumed_by = Some(filter.name().to_string());
+            }
+        }
+        self.banks.insert(tablet, routed.bank);
+        if routed.is_consumed() {
+            self.forward_to_hud(&mut routed);
+        } else {
+            routed.hud = None;
+        }
+        self.black_box.record(
+            tablet,
+            stamp,
+            RecordKind::Routed {
+                event: routed.event.clone(),
+                position: routed.position.clone(),
+                consumed_by: routed.consumed_by.clone(),
+            },
+        );
+        if let TabletEvent::PenEvent(pen) = &routed.event {
+            if matches!(pen.location, PenLocation::Pressed) {
+                self.pressed.insert(tablet);
+            } else if self.pressed.remove(&tablet)
+                && let Err(e) = self.apply_pending()
+            {
+                error!("无法应用配置: {e:#}");
+            }
+        }
+        routed.routed_at = monotonic_micros();
+        self.latency
+            .record(LatencyStage::Route, stamp.timestamp, routed.routed_at);
+        routed
+    }
+
+    /// 从 `input` 读取事件，处理后发往 `output`, 任意一端关闭时返回
+    pub async fn run(self, mut input: EventReceiver, output: mpsc::Sender<RoutedEvent>) {
+        self.run_with(&mut input, output).await;
+    }
+
+    /// 和 [`Router::run`] 相同, 但不取得 `input`. 路由器崩溃后 `input` 可以交给新的路由器,
+    /// 驱动不需要重新连接
+    pub async fn run_with(mut self, input: &mut EventReceiver, output: mpsc::Sender<RoutedEvent>) {
+        let mut config_rx = self.config_rx.take();
+        let mut geometry_rx = self.geometry_rx.take();
+        let mut focus_rx = self.focus_rx.take();
+        let mut device_rx = self.device_rx.take();
+        let mut queue = FairQueue::new();
+        loop {
+            queue.fill(input);
+            let deadline = self.glue.deadline();
+            // 很少发生的变化排在前面: 数位板的接入要在它的第一个事件之前处理, 配置和布局
+            // 不会在持续的输入下一直得不到处理
+            let events = tokio::select! {
+                biased;
+                Some(device) = device_event(&mut device_rx) => {
+                    let releases = match device {
+                        DeviceEvent::Connected(device) => {
+                            self.connect_device(&device);
+                            Vec::new()
+                        }
+                        DeviceEvent::Disconnected(device) => {
+                            self.disconnect(device.tablet, device.transport)
+                        }
+                    };
+                    for release in releases {
+                        if output.send(release).await.is_err() {
+                            return;
+                        }
+                    }
+                    continue;
+                }
+                Some(config) = changed(&mut config_rx) => {
+                    if let Err(e) = self.reconfigure(config) {
+                        error!("无法应用配置: {e:#}");
+                    }
+                    continue;
+                }
+                Some(geometry) = changed(&mut geometry_rx) => {
+                    self.apply_geometry(&geometry);
+                    continue;
+                }
+                Some(focus) = changed(&mut focus_rx) => {
+                    if let Err(e) = self.set_focus(focus) {
+                        error!("无法应用配置: {e:#}");
+                    }
+                    continue;
+                }
+                _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now).into()),
+                    if deadline.is_some() => self.glue.expire(Instant::now()),
+                event = next(&mut queue, input) => match event {
+                    Some(event) => {
+                        // 在抬笔延迟之前记录, 被丢弃的抬笔只有这一条记录
+                        self.black_box.record(
+                            event.tablet,
+                            event.stamp,
+                            RecordKind::Received {
+                                transport: event.transport,
+                                event: event.event.clone(),
+                            },
+                        );
+                        self.glue.push(event, Instant::now())
+                    }
+                    None => {
+                        let held = self.glue.flush();
+                        self.forward(held, &output).await;
+                        break;
+                    }
+                },
+            };
+            if !self.forward(events, &output).await {
+                break;
+            }
+        }
+    }
+
+    /// 处理并发送事件, `output` 关闭时返回 `false`
+    async fn forward(
+        &mut self,
+        events: Vec<InputEvent>,
+        output: &mpsc::Sender<RoutedEvent>,
+    ) -> bool {
+        for event in events {
+            if let Some(routed) = self.route(event)
+                && output.send(routed).await.is_err()
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// 按数位板轮流取出已到达的事件, 都处理完后再等待新事件
+async fn next(queue: &mut FairQueue, input: &mut EventReceiver) -> Option<InputEvent> {
+    match queue.pop() {
+        Some(event) => Some(event),
+        None => input.recv().await,
+    }
+}
+
+/// 等待下一个配置、布局或聚焦的应用, 没有订阅或者发送端已关闭时永远等待
+async fn changed<T: Clone>(rx: &mut Option<watch::Receiver<T>>) -> Option<T> {
+    let Some(receiver) = rx.as_mut() else {
+        return std::future::pending().await;
+    };
+    if receiver.changed().await.is_err() {
+        *rx = None;
+        return None;
+    }
+    Some(receiver.borrow_and_update().clone())
+}
+
+/// 等待下一个接入或断开, 没有订阅或者发送端已关闭时永远等待
+async fn device_event(rx: &mut Option<broadcast::Receiver<DeviceEvent>>) -> Option<DeviceEvent> {
+    let Some(receiver) = rx.as_mut() else {
+        return std::future::pending().await;
+    };
+    loop {
+        match receiver.recv().await {
+            Ok(event) => return Some(event),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!("路由器落后，丢失了 {skipped} 个数位板接入/断开事件");
+            }
+            Err(broadcast::error::RecvError::Closed) => {
+                *rx = None;
+                return None;
+            }
+        }
+    }
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,255 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    config::{Config, ConfigChange, transaction::ConfigStage},
+    event_model::{event::TabletEvent, tablet::TabletId},
+    hud_interface::{HudEvent, HudSender},
+    tablet_driver::led::{ModeLed, SysfsStatusLed},
+};
+
+use super::{RoutedEvent, RouterFilter, Verdict};
+
+/// 模式组配置
+///
+/// 类似 Wacom ExpressKey 的模式切换: 按下切换键后，
+/// 快捷键和滚轮在 `bank_count` 组绑定之间循环. 在设置中写作
+///
+/// ```toml
+/// [defaults.mode_bank]
+/// switch_button = 0
+/// bank_count = 3
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModeBankConfig {
+    /// 用于切换模式组的按键
+    pub switch_button: u8,
+    /// 模式组数量
+    pub bank_count: u8,
+}
+
+/// 经过模式组处理后的事件
+#[derive(Debug, Clone)]
+pub enum BankedEvent {
+    /// 切换键被按下，已切换到新的模式组
+    Switched(u8),
+    /// 切换键的其他事件(比如松开)，已被吞掉
+    Swallowed,
+    /// 普通事件，附带当前模式组
+    Event { bank: u8, event: TabletEvent },
+}
+
+/// 模式组状态机
+pub struct ModeBanks {
+    config: ModeBankConfig,
+    current: u8,
+    led: Option<Box<dyn ModeLed + Send>>,
+    hud: Option<HudSender>,
+}
+
+impl ModeBanks {
+    pub fn new(config: ModeBankConfig) -> Self {
+        Self {
+            config: ModeBankConfig {
+                bank_count: config.bank_count.max(1),
+                ..config
+            },
+            current: 0,
+            led: None,
+            hud: None,
+        }
+    }
+
+    /// 设置模式指示灯，并立即同步当前模式组
+    pub fn set_led(&mut self, mut led: Box<dyn ModeLed + Send>) {
+        if let Err(e) = led.show_bank(self.current) {
+            warn!("无法设置模式指示灯: {e}");
+        }
+        self.led = Some(led);
+    }
+
+    /// 设置 HUD 通道，模式切换时通知 HUD
+    pub fn set_hud(&mut self, hud: HudSender) {
+        self.hud = Some(hud);
+    }
+
+    /// 当前模式组
+    pub fn current(&self) -> u8 {
+        self.current
+    }
+
+    /// 直接切换到指定模式组
+    pub fn select(&mut self, bank: u8) {
+        self.current = bank % self.config.bank_count;
+        if let Some(led) = self.led.as_mut()
+            && let Err(e) = led.show_bank(self.current)
+        {
+            warn!("无法设置模式指示灯: {e}");
+        }
+        if let Some(hud) = self.hud.as_ref() {
+            let _ = hud.send(HudEvent::ModeBankChanged {
+                bank: self.current,
+                bank_count: self.config.bank_count,
+            });
+        }
+    }
+
+    /// 处理一个事件
+    ///
+    /// 切换键的按下会切换到下一个模式组，切换键的松开被吞掉，
+    /// 其他事件原样返回并标注当前模式组
+    pub fn handle(&mut self, event: TabletEvent) -> BankedEvent {
+        match event {
+            TabletEvent::AuxButton(ref button) if button.button_id == self.config.switch_button => {
+                if button.pressed {
+                    self.select(self.current.wrapping_add(1));
+                    BankedEvent::Switched(self.current)
+                } else {
+                    BankedEvent::Swallowed
+                }
+            }
+            event => BankedEvent::Event {
+                bank: self.current,
+                event,
+            },
+        }
+    }
+}
+
+impl RouterFilter for ModeBanks {
+    fn name(&self) -> &str {
+        "mode-bank"
+    }
+
+    /// 切换键的事件被消费，其他事件标注当前模式组
+    fn filter(&mut self, event: &mut RoutedEvent) -> Verdict {
+        match self.handle(event.event.clone()) {
+            BankedEvent::Switched(bank) => {
+                event.bank = bank;
+                Verdict::Consume
+            }
+            BankedEvent::Swallowed => {
+                event.bank = self.current;
+                Verdict::Consume
+            }
+            BankedEvent::Event { bank, .. } => {
+                event.bank = bank;
+                Verdict::Pass
+            }
+        }
+    }
+}
+
+/// 按设置为每块数位板维护模式组, 守护进程的路由器使用
+///
+/// 设置了 [`crate::profile::Profile::mode_bank`] 的数位板在第一个事件时创建 [`ModeBanks`],
+/// 设置变化后保留当前模式组. 没有设置的数位板的事件原样通过
+///
+/// 通过 hidraw 接入的数位板有内核 wacom 驱动的状态指示灯([`SysfsStatusLed::for_hidraw`])时,
+/// 模式组同时点亮对应的指示灯
+#[derive(Default)]
+pub struct TabletBanks {
+    banks: HashMap<TabletId, ModeBanks>,
+    /// 数位板的 hidraw 节点
+    nodes: HashMap<TabletId, PathBuf>,
+    /// 已应用的配置
+    config: Config,
+    hud: Option<HudSender>,
+}
+
+impl TabletBanks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_hud(&mut self, hud: HudSender) {
+        self.hud = Some(hud);
+    }
+
+    /// 数位板当前的模式组
+    pub fn current(&self, tablet: TabletId) -> Option<u8> {
+        self.banks.get(&tablet).map(ModeBanks::current)
+    }
+
+    fn banks(&mut self, tablet: TabletId) -> Option<&mut ModeBanks> {
+        let config = self.config.profile(tablet).mode_bank?;
+        let banks = self.banks.entry(tablet).or_insert_with(|| {
+            let mut banks = ModeBanks::new(config);
+            if let Some(hud) = &self.hud {
+                banks.set_hud(hud.clone());
+            }
+            if let Some(led) = self
+                .nodes
+                .get(&tablet)
+                .and_then(|node| SysfsStatusLed::for_hidraw(node))
+            {
+                banks.set_led(Box::new(led));
+            }
+            banks
+        });
+        Some(banks)
+    }
+}
+
+impl ConfigStage for TabletBanks {
+    fn name(&self) -> &str {
+        "mode-bank"
+    }
+
+    fn apply(&mut self, config: &Config, _change: &ConfigChange) -> anyhow::Result<()> {
+        self.banks
+            .retain(|tablet, banks| match config.profile(*tablet).mode_bank {
+                Some(new) => {
+                    if new.switch_button != banks.config.switch_button
+                        || new.bank_count.max(1) != banks.config.bank_count
+                    {
+                        let current = banks.current;
+                        banks.config = ModeBanks::new(new).config;
+                        banks.current = current % banks.config.bank_count;
+                    }
+                    true
+                }
+                None => false,
+            });
+        self.config = config.clone();
+        Ok(())
+    }
+}
+
+impl RouterFilter for TabletBanks {
+    fn name(&self) -> &str {
+        "mode-bank"
+    }
+
+    fn config_stage(&mut self) -> Option<&mut dyn ConfigStage> {
+        Some(self)
+    }
+
+    /// 先通过蓝牙接入、之后才插上 USB 时模式组已经存在, 直接接上指示灯
+    fn attach_node(&mut self, tablet: TabletId, node: &Path) {
+        if let Some(banks) = self.banks.get_mut(&tablet)
+            && banks.led.is_none()
+            && let Some(led) = SysfsStatusLed::for_hidraw(node)
+        {
+            banks.set_led(Box::new(led));
+        }
+        self.nodes.insert(tablet, node.to_path_buf());
+    }
+
+    fn disconnect(&mut self, tablet: TabletId) {
+        self.banks.remove(&tablet);
+        self.nodes.remove(&tablet);
+    }
+
+    fn filter(&mut self, event: &mut RoutedEvent) -> Verdict {
+        match self.banks(event.tablet) {
+            Some(banks) => banks.filter(event),
+            None => Verdict::Pass,
+        }
+    }
+}
@@ -0,0 +1,246 @@
+//! 压感曲线
+//!
+//! 在分发前把驱动报告的压力值经过一条曲线重新映射, 每块数位板可以有自己的曲线.
+//! 曲线保存在 [`PressureCurves`] 中，运行时修改后下一个事件就会生效
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::event_model::{capability::DeviceCapabilities, event::TabletEvent, tablet::TabletId};
+
+use super::{RoutedEvent, RouterFilter, Verdict};
+
+/// 求解贝塞尔曲线参数时的迭代次数, 二分法每次把误差减半
+const BEZIER_ITERATIONS: usize = 24;
+
+/// 压力传递函数, 输入和输出都是归一化的压力(0 ~ 1)
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum PressureCurve {
+    /// 不修改压力
+    #[default]
+    Linear,
+    /// 从 (0, 0) 到 (1, 1) 的三次贝塞尔曲线, `p1` `p2` 是两个控制点
+    Bezier { p1: (f32, f32), p2: (f32, f32) },
+    /// 折线, 点按输入压力排列. 第一个点之前和最后一个点之后保持端点的输出
+    Piecewise { points: Vec<(f32, f32)> },
+}
+
+impl PressureCurve {
+    /// 检查曲线是否可用: 坐标在 0 ~ 1 之间，输出只由输入决定
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let in_range = |(x, y): (f32, f32)| (0.0..=1.0).contains(&x) && (0.0..=1.0).contains(&y);
+        match self {
+            PressureCurve::Linear => {}
+            PressureCurve::Bezier { p1, p2 } => {
+                if !in_range(*p1) || !in_range(*p2) {
+                    bail!("贝塞尔曲线的控制点必须在 0 ~ 1 之间");
+                }
+            }
+            PressureCurve::Piecewise { points } => {
+                if points.is_empty() {
+                    bail!("折线至少需要一个点");
+                }
+                if !points.iter().all(|point| in_range(*point)) {
+                    bail!("折线的点必须在 0 ~ 1 之间");
+                }
+                if points.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+                    bail!("折线的点必须按输入压力严格递增排列");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 映射一个归一化的压力值
+    pub fn apply(&self, pressure: f32) -> f32 {
+        let x = pressure.clamp(0.0, 1.0);
+        let y = match self {
+            PressureCurve::Linear => x,
+            PressureCurve::Bezier { p1, p2 } => bezier(x, *p1, *p2),
+            PressureCurve::Piecewise { points } => piecewise(x, points),
+        };
+        y.clamp(0.0, 1.0)
+    }
+
+    /// 映射设备单位的压力值, `max_pressure` 为 0 时原样返回
+    pub fn apply_raw(&self, pressure: u32, max_pressure: u32) -> u32 {
+        if max_pressure == 0 || *self == PressureCurve::Linear {
+            return pressure;
+        }
+        let mapped = self.apply(pressure as f32 / max_pressure as f32);
+        (mapped * max_pressure as f32).round() as u32
+    }
+}
+
+/// 一维的三次贝塞尔曲线
+fn cubic(t: f32, a: f32, b: f32) -> f32 {
+    let u = 1.0 - t;
+    3.0 * u * u * t * a + 3.0 * u * t * t * b + t * t * t
+}
+
+fn bezier(x: f32, p1: (f32, f32), p2: (f32, f32)) -> f32 {
+    // 端点是精确的，不需要迭代
+    if x <= 0.0 || x >= 1.0 {
+        return x;
+    }
+    // 控制点的 x 在 0 ~ 1 之间时 x(t) 单调递增，可以用二分法求 t
+    let (mut low, mut high) = (0.0, 1.0);
+    for _ in 0..BEZIER_ITERATIONS {
+        let t = (low + high) / 2.0;
+        if cubic(t, p1.0, p2.0) < x {
+            low = t;
+        } else {
+            high = t;
+        }
+    }
+    cubic((low + high) / 2.0, p1.1, p2.1)
+}
+
+fn piecewise(x: f32, points: &[(f32, f32)]) -> f32 {
+    let (Some(first), Some(last)) = (points.first(), points.last()) else {
+        return x;
+    };
+    if x <= first.0 {
+        return first.1;
+    }
+    if x >= last.0 {
+        return last.1;
+    }
+    points
+        .windows(2)
+        .find(|pair| x <= pair[1].0)
+        .map(|pair| {
+            let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+            y0 + (y1 - y0) * (x - x0) / (x1 - x0)
+        })
+        .unwrap_or(last.1)
+}
+
+#[derive(Debug, Default)]
+struct CurveTable {
+    default: PressureCurve,
+    tablets: HashMap<TabletId, PressureCurve>,
+}
+
+/// 所有数位板的压感曲线, 可以在其他任务中修改
+#[derive(Debug, Clone, Default)]
+pub struct PressureCurves {
+    table: Arc<RwLock<CurveTable>>,
+}
+
+impl PressureCurves {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 数位板当前使用的曲线
+    pub fn get(&self, tablet: TabletId) -> PressureCurve {
+        let table = self.table.read().unwrap();
+        table.tablets.get(&tablet).unwrap_or(&table.default).clone()
+    }
+
+    /// 用数位板当前的曲线映射设备单位的压力值
+    pub fn apply_raw(&self, tablet: TabletId, pressure: u32, max_pressure: u32) -> u32 {
+        let table = self.table.read().unwrap();
+        table
+            .tablets
+            .get(&tablet)
+            .unwrap_or(&table.default)
+            .apply_raw(pressure, max_pressure)
+    }
+
+    /// 设置某块数位板的曲线, 曲线无效时不修改
+    pub fn set(&self, tablet: TabletId, curve: PressureCurve) -> anyhow::Result<()> {
+        curve.validate()?;
+        self.table.write().unwrap().tablets.insert(tablet, curve);
+        Ok(())
+    }
+
+    /// 设置没有单独设置曲线的数位板使用的曲线
+    pub fn set_default(&self, curve: PressureCurve) -> anyhow::Result<()> {
+        curve.validate()?;
+        self.table.write().unwrap().default = curve;
+        Ok(())
+    }
+
+    /// 替换所有曲线, 有无效的曲线时什么也不修改
+    pub fn replace(
+        &self,
+        default: PressureCurve,
+        tablets: HashMap<TabletId, PressureCurve>,
+    ) -> anyhow::Result<()> {
+        default.validate()?;
+        for (tablet, curve) in &tablets {
+            curve
+                .validate()
+                .with_context(|| format!("{tablet} 的压感曲线无效"))?;
+        }
+        *self.table.write().unwrap() = CurveTable { default, tablets };
+        Ok(())
+    }
+
+    /// 去掉数位板单独的曲线，改用默认曲线
+    pub fn reset(&self, tablet: TabletId) -> Option<PressureCurve> {
+        self.table.write().unwrap().tablets.remove(&tablet)
+    }
+}
+
+/// 按 [`PressureCurves`] 重新映射笔的压力
+pub struct PressureMapper {
+    curves: PressureCurves,
+    /// 每块数位板的压感最大值
+    max_pressure: HashMap<TabletId, u32>,
+}
+
+impl PressureMapper {
+    pub fn new(curves: PressureCurves) -> Self {
+        Self {
+            curves,
+            max_pressure: HashMap::new(),
+        }
+    }
+
+    pub fn curves(&self) -> &PressureCurves {
+        &self.curves
+    }
+
+    /// 数位板接入时登记压感范围, 没有登记的数位板压力不会被修改
+    pub fn add_tablet(&mut self, tablet: TabletId, capabilities: &DeviceCapabilities) {
+        self.max_pressure.insert(tablet, capabilities.max_pressure);
+    }
+
+    pub fn remove_tablet(&mut self, tablet: TabletId) {
+        self.max_pressure.remove(&tablet);
+    }
+}
+
+impl RouterFilter for PressureMapper {
+    fn name(&self) -> &str {
+        "pressure-curve"
+    }
+
+    fn connect(&mut self, tablet: TabletId, capabilities: &DeviceCapabilities) {
+        self.add_tablet(tablet, capabilities);
+    }
+
+    fn disconnect(&mut self, tablet: TabletId) {
+        self.remove_tablet(tablet);
+    }
+
+    fn filter(&mut self, event: &mut RoutedEvent) -> Verdict {
+        if let TabletEvent::PenEvent(pen) = &mut event.event
+            && let Some(&max_pressure) = self.max_pressure.get(&event.tablet)
+        {
+            pen.pressure = self
+                .curves
+                .apply_raw(event.tablet, pen.pressure, max_pressure);
+        }
+        Verdict::Pass
+    }
+}
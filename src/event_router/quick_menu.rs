@@ -0,0 +1,258 @@
+//! 快捷菜单
+//!
+//! 和三星 S Pen 一样，按住笔杆上面的按键时在光标附近弹出一圈菜单(设置中的
+//! [`crate::profile::Profile::quick_menu`]), 移动笔指向其中一项，松开按键执行对应的动作.
+//! 在中心松开或者笔离开感应范围时不执行. 菜单打开期间这块数位板的笔事件都被消费,
+//! 不会在下层窗口中画出笔画.
+//!
+//! 订阅了 [`super::gesture::Gestures`] 时改为长按上面的按键才打开菜单, 短按可以另外绑定手势
+//! (比如双击). 这时有快捷菜单的数位板上, 上面的按键总是被消费.
+//!
+//! ```toml
+//! [[defaults.quick_menu]]
+//! label = "撤销"
+//! action = "keys"
+//! keys = "ctrl+z"
+//!
+//! [[defaults.quick_menu]]
+//! action = "toggle_history"
+//! ```
+
+use std::collections::HashMap;
+
+use tokio::sync::broadcast::{self, error::TryRecvError};
+
+use crate::{
+    event_model::{
+        event::{PenLocation, TabletEvent},
+        tablet::TabletId,
+    },
+    hud_interface::{
+        HudEvent, HudSender,
+        pointer::HudElement,
+        quick_menu::{self, QuickMenu as MenuView},
+    },
+    mapping::ScreenPoint,
+    profile::binding::QuickMenuEntry,
+};
+
+use super::{
+    RoutedEvent, RouterFilter, Verdict,
+    bindings::{ActionSender, BindingTable, Triggered},
+    gesture::{BarrelButton, GestureKind, PenGesture},
+};
+
+/// 打开的菜单
+struct Open {
+    tablet: TabletId,
+    center: ScreenPoint,
+    entries: Vec<QuickMenuEntry>,
+    selected: Option<usize>,
+}
+
+/// 按 [`BindingTable`] 中的快捷菜单处理笔杆按键
+///
+/// 需要笔的屏幕位置, 所以要添加在 [`super::screen::MapToScreen`] 之后.
+/// 没有设置快捷菜单的数位板上按键照常传给系统
+pub struct QuickMenu {
+    bindings: BindingTable,
+    actions: ActionSender,
+    /// 每块数位板上笔最后的位置
+    positions: HashMap<TabletId, ScreenPoint>,
+    open: Option<Open>,
+    hud: Option<HudSender>,
+    gestures: Option<broadcast::Receiver<PenGesture>>,
+    /// 订阅了手势时, 按着上面的按键、等待长按的数位板
+    pending: Option<TabletId>,
+}
+
+impl QuickMenu {
+    pub fn new(bindings: BindingTable, actions: ActionSender) -> Self {
+        Self {
+            bindings,
+            actions,
+            positions: HashMap::new(),
+            open: None,
+            hud: None,
+            gestures: None,
+            pending: None,
+        }
+    }
+
+    /// 长按上面的按键才打开菜单, `gestures` 来自 [`super::gesture::Gestures::subscribe`],
+    /// 手势过滤器要添加在这个过滤器之前
+    pub fn set_gestures(&mut self, gestures: broadcast::Receiver<PenGesture>) {
+        self.gestures = Some(gestures);
+    }
+
+    /// 等待长按的按键是否已经长按
+    fn held(&mut self, tablet: TabletId) -> bool {
+        let Some(rx) = self.gestures.as_mut() else {
+            return false;
+        };
+        let mut held = false;
+        loop {
+            match rx.try_recv() {
+                Ok(gesture) => {
+                    held |= gesture.tablet == tablet
+                        && gesture.button == BarrelButton::Upper
+                        && gesture.kind == GestureKind::Hold;
+                }
+                Err(TryRecvError::Lagged(_)) => continue,
+                Err(_) => break,
+            }
+        }
+        held
+    }
+
+    /// 订阅了手势时按下上面的按键, 有菜单时等待长按
+    fn press(&mut self, tablet: TabletId) -> bool {
+        if self.hud.is_none() || self.bindings.quick_menu(tablet).is_empty() {
+            return false;
+        }
+        self.pending = Some(tablet);
+        true
+    }
+
+    /// 菜单显示在 HUD 上, 没有 HUD 时菜单不会打开
+    pub fn set_hud(&mut self, hud: HudSender) {
+        self.hud = Some(hud);
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open.is_some()
+    }
+
+    fn send(&self, event: HudEvent) {
+        if let Some(hud) = self.hud.as_ref() {
+            let _ = hud.send(event);
+        }
+    }
+
+    /// 在笔的位置打开菜单, 没有菜单项或者不知道笔的位置时不打开
+    fn open(&mut self, tablet: TabletId) -> bool {
+        if self.hud.is_none() {
+            return false;
+        }
+        let Some(center) = self.positions.get(&tablet).cloned() else {
+            return false;
+        };
+        let entries = self.bindings.quick_menu(tablet);
+        if entries.is_empty() {
+            return false;
+        }
+        let labels = entries.iter().map(QuickMenuEntry::label).collect();
+        self.send(HudEvent::QuickMenuOpened(MenuView::new(
+            center.clone(),
+            labels,
+        )));
+        self.open = Some(Open {
+            tablet,
+            center,
+            entries,
+            selected: None,
+        });
+        true
+    }
+
+    /// 关闭菜单, `choose` 时执行选中的项
+    fn close(&mut self, choose: bool) {
+        let Some(open) = self.open.take() else {
+            return;
+        };
+        self.send(HudEvent::QuickMenuClosed);
+        if choose && let Some(entry) = open.selected.and_then(|index| open.entries.get(index)) {
+            let _ = self.actions.send(Triggered {
+                tablet: open.tablet,
+                action: entry.action.clone(),
+                wheel: None,
+            });
+        }
+    }
+
+    fn point(&mut self, position: &ScreenPoint) {
+        let Some(open) = self.open.as_mut() else {
+            return;
+        };
+        // 跨显示器时逻辑坐标仍然是连续的
+        let selected = quick_menu::entry_at(
+            position.logical_x - open.center.logical_x,
+            position.logical_y - open.center.logical_y,
+            open.entries.len(),
+        );
+        if selected != open.selected {
+            open.selected = selected;
+            self.send(HudEvent::QuickMenuSelected(selected));
+        }
+    }
+}
+
+impl RouterFilter for QuickMenu {
+    fn name(&self) -> &str {
+        "quick-menu"
+    }
+
+    fn filter(&mut self, event: &mut RoutedEvent) -> Verdict {
+        let tablet = event.tablet;
+        if let TabletEvent::PenEvent(pen) = &event.event {
+            match (&pen.location, &event.position) {
+                (PenLocation::Leaved, _) => {
+                    self.positions.remove(&tablet);
+                }
+                (_, Some(position)) => {
+                    self.positions.insert(tablet, position.clone());
+                }
+                _ => {}
+            }
+        }
+
+        let held = self.held(tablet);
+        if self.pending == Some(tablet) {
+            match &event.event {
+                TabletEvent::PenButton(buttons) if !buttons.upper => {
+                    self.pending = None;
+                    return Verdict::Consume;
+                }
+                TabletEvent::PenButton(_) => return Verdict::Consume,
+                // 打不开时继续消费这个按键, 直到松开
+                _ if held && self.open(tablet) => self.pending = None,
+                _ => return Verdict::Pass,
+            }
+        }
+
+        let Some(open) = self.open.as_ref() else {
+            return match &event.event {
+                // HUD 面板打开时按键用来操作面板
+                _ if event.is_consumed() => Verdict::Pass,
+                TabletEvent::PenButton(buttons) if buttons.upper && self.gestures.is_some() => {
+                    if self.press(tablet) {
+                        Verdict::Consume
+                    } else {
+                        Verdict::Pass
+                    }
+                }
+                TabletEvent::PenButton(buttons) if buttons.upper && self.open(tablet) => {
+                    event.target_hud(HudElement::QuickMenu);
+                    Verdict::Consume
+                }
+                _ => Verdict::Pass,
+            };
+        };
+        if open.tablet != tablet {
+            return Verdict::Pass;
+        }
+        match &event.event {
+            TabletEvent::PenButton(buttons) if !buttons.upper => self.close(true),
+            TabletEvent::PenEvent(pen) => match (&pen.location, &event.position) {
+                (PenLocation::Leaved, _) => self.close(false),
+                (_, Some(position)) => self.point(position),
+                _ => {}
+            },
+            TabletEvent::PenButton(_) | TabletEvent::ToolIn(_) | TabletEvent::ToolOut(_) => {}
+            // 快捷键和触控环不受影响
+            _ => return Verdict::Pass,
+        }
+        event.target_hud(HudElement::QuickMenu);
+        Verdict::Consume
+    }
+}
@@ -0,0 +1,41 @@
+use crate::{
+    event_model::event::TabletEvent,
+    hud_interface::{HudEvent, HudSender},
+};
+
+use super::{RoutedEvent, RouterFilter, Verdict};
+
+/// 把触控环上手指的位置转发给 HUD 的转盘
+///
+/// 只转发位置, 转动对应的动作仍然由 [`super::bindings`] 处理 `Wheel` 事件
+#[derive(Default)]
+pub struct RingFeedback {
+    hud: Option<HudSender>,
+}
+
+impl RingFeedback {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_hud(&mut self, hud: HudSender) {
+        self.hud = Some(hud);
+    }
+}
+
+impl RouterFilter for RingFeedback {
+    fn name(&self) -> &str {
+        "ring-dial"
+    }
+
+    fn filter(&mut self, event: &mut RoutedEvent) -> Verdict {
+        if let TabletEvent::Ring(ring) = &event.event
+            && let Some(hud) = self.hud.as_ref()
+        {
+            let _ = hud.send(HudEvent::RingTouched {
+                position: ring.position,
+            });
+        }
+        Verdict::Pass
+    }
+}
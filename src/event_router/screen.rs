@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+
+use crate::{
+    config::{Config, ConfigChange, transaction::ConfigStage},
+    event_model::{
+        capability::DeviceCapabilities,
+        event::{PenLocation, TabletEvent},
+        tablet::TabletId,
+    },
+    hud_interface::{HudEvent, HudSender, mapping_overlay::MappingView},
+    mapping::{
+        Mapper, OutputChange, barrier::EdgeBarrier, calibration::CalibrationView,
+        geometry::GeometryChanged,
+    },
+};
+
+use super::{RoutedEvent, RouterFilter, Verdict};
+
+/// 在分发前把一块数位板的笔位置映射到屏幕
+pub struct MapToScreen {
+    tablet: TabletId,
+    mapper: Mapper,
+    capabilities: DeviceCapabilities,
+    barrier: EdgeBarrier,
+    hud: Option<HudSender>,
+    view: Option<MappingView>,
+    calibration: Option<CalibrationView>,
+    /// 上一个事件中笔尖是否按下
+    pressed: bool,
+    /// 校准中的点击不传给应用, 最后一次点击要等抬笔
+    swallow: bool,
+}
+
+impl MapToScreen {
+    pub fn new(tablet: TabletId, mapper: Mapper, capabilities: DeviceCapabilities) -> Self {
+        Self {
+            tablet,
+            mapper,
+            capabilities,
+            barrier: EdgeBarrier::new(),
+            hud: None,
+            view: None,
+            calibration: None,
+            pressed: false,
+            swallow: false,
+        }
+    }
+
+    /// 映射目标显示器断开或恢复时在 HUD 上提示
+    pub fn set_hud(&mut self, hud: HudSender) {
+        self.hud = Some(hud);
+    }
+
+    /// 把映射区域和笔的位置交给 HUD 的映射区域显示
+    pub fn set_mapping_view(&mut self, view: MappingView) {
+        view.set_target(self.tablet, self.mapper.target_rect());
+        self.view = Some(view);
+    }
+
+    /// 校准这块数位板时记录笔尖按下的位置
+    pub fn set_calibration(&mut self, calibration: CalibrationView) {
+        self.calibration = Some(calibration);
+    }
+
+    fn update_view(&self) {
+        if let Some(view) = &self.view {
+            view.set_target(self.tablet, self.mapper.target_rect());
+        }
+        if let Some(calibration) = &self.calibration {
+            calibration.set_target(self.tablet, self.mapper.target_rect());
+        }
+    }
+
+    pub fn mapper(&self) -> &Mapper {
+        &self.mapper
+    }
+
+    /// 显示器布局或映射设置变化时使用
+    pub fn mapper_mut(&mut self) -> &mut Mapper {
+        &mut self.mapper
+    }
+
+    pub fn apply_geometry(&mut self, geometry: &GeometryChanged) {
+        let change = self.mapper.apply_geometry(geometry);
+        self.update_view();
+        let Some(change) = change else {
+            return;
+        };
+        let event = match change {
+            OutputChange::Reassigned { from, to } => HudEvent::MappingReassigned { from, to },
+            OutputChange::Restored { output } => HudEvent::MappingRestored { output },
+        };
+        if let Some(hud) = self.hud.as_ref() {
+            let _ = hud.send(event);
+        }
+    }
+}
+
+impl ConfigStage for MapToScreen {
+    fn name(&self) -> &str {
+        "map-to-screen"
+    }
+
+    fn apply(&mut self, config: &Config, change: &ConfigChange) -> anyhow::Result<()> {
+        if change.affects(self.tablet) {
+            let mapping = &config.profile(self.tablet).mapping;
+            if mapping != self.mapper.config() {
+                self.mapper.set_config(mapping.clone());
+                self.update_view();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RouterFilter for MapToScreen {
+    fn name(&self) -> &str {
+        "map-to-screen"
+    }
+
+    fn config_stage(&mut self) -> Option<&mut dyn ConfigStage> {
+        Some(self)
+    }
+
+    fn apply_geometry(&mut self, geometry: &GeometryChanged) {
+        MapToScreen::apply_geometry(self, geometry);
+    }
+
+    fn filter(&mut self, event: &mut RoutedEvent) -> Verdict {
+        if event.tablet == self.tablet
+            && let TabletEvent::PenEvent(pen) = &event.event
+        {
+            if matches!(pen.location, PenLocation::Leaved) {
+                self.barrier.reset();
+            }
+            event.position =
+                self.mapper
+                    .map_with_barrier(pen.x, pen.y, &self.capabilities, &mut self.barrier);
+            if let Some(view) = self.view.as_ref().filter(|view| view.is_visible()) {
+                let pen = event
+                    .position
+                    .as_ref()
+                    .filter(|_| !matches!(pen.location, PenLocation::Leaved))
+                    .map(|position| (position.logical_x, position.logical_y));
+                view.set_pen(self.tablet, pen);
+            }
+            let pressed = matches!(pen.location, PenLocation::Pressed);
+            let calibrating = self
+                .calibration
+                .as_ref()
+                .filter(|calibration| calibration.is_calibrating(self.tablet));
+            if let Some(calibration) = calibrating
+                && pressed
+                && !self.pressed
+                && let Some(point) = self
+                    .mapper
+                    .calibration_point(pen.x, pen.y, &self.capabilities)
+            {
+                calibration.record(self.tablet, point);
+                if let Some(hud) = self.hud.as_ref() {
+                    let _ = hud.send(HudEvent::CalibrationChanged);
+                }
+            }
+            self.swallow = calibrating.is_some() || (self.swallow && pressed);
+            self.pressed = pressed;
+            if self.swallow {
+                return Verdict::Consume;
+            }
+        }
+        Verdict::Pass
+    }
+}
+
+/// 为每块接入的数位板创建 [`MapToScreen`], 守护进程的路由器使用
+///
+/// 新接入的数位板按已应用的配置和当前的显示器布局创建映射, 断开后丢弃
+#[derive(Default)]
+pub struct ScreenMapping {
+    tablets: HashMap<TabletId, MapToScreen>,
+    /// 已应用的配置
+    config: Config,
+    geometry: GeometryChanged,
+    hud: Option<HudSender>,
+    view: Option<MappingView>,
+    calibration: Option<CalibrationView>,
+}
+
+impl ScreenMapping {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 见 [`MapToScreen::set_hud`]
+    pub fn set_hud(&mut self, hud: HudSender) {
+        self.hud = Some(hud);
+    }
+
+    /// 见 [`MapToScreen::set_mapping_view`]
+    pub fn set_mapping_view(&mut self, view: MappingView) {
+        self.view = Some(view);
+    }
+
+    /// 见 [`MapToScreen::set_calibration`]
+    pub fn set_calibration(&mut self, calibration: CalibrationView) {
+        self.calibration = Some(calibration);
+    }
+
+    /// 接入的数位板的映射
+    pub fn mapper(&self, tablet: TabletId) -> Option<&Mapper> {
+        self.tablets.get(&tablet).map(MapToScreen::mapper)
+    }
+}
+
+impl ConfigStage for ScreenMapping {
+    fn name(&self) -> &str {
+        "map-to-screen"
+    }
+
+    fn apply(&mut self, config: &Config, change: &ConfigChange) -> anyhow::Result<()> {
+        for map in self.tablets.values_mut() {
+            ConfigStage::apply(map, config, change)?;
+        }
+        self.config = config.clone();
+        Ok(())
+    }
+}
+
+impl RouterFilter for ScreenMapping {
+    fn name(&self) -> &str {
+        "map-to-screen"
+    }
+
+    fn config_stage(&mut self) -> Option<&mut dyn ConfigStage> {
+        Some(self)
+    }
+
+    fn apply_geometry(&mut self, geometry: &GeometryChanged) {
+        for map in self.tablets.values_mut() {
+            map.apply_geometry(geometry);
+        }
+        self.geometry = geometry.clone();
+    }
+
+    fn connect(&mut self, tablet: TabletId, capabilities: &DeviceCapabilities) {
+        let mut mapper = Mapper::new(self.config.profile(tablet).mapping.clone());
+        mapper.apply_geometry(&self.geometry);
+        let mut map = MapToScreen::new(tablet, mapper, capabilities.clone());
+        if let Some(hud) = &self.hud {
+            map.set_hud(hud.clone());
+        }
+        if let Some(view) = &self.view {
+            map.set_mapping_view(view.clone());
+        }
+        if let Some(calibration) = &self.calibration {
+            map.set_calibration(calibration.clone());
+        }
+        self.tablets.insert(tablet, map);
+    }
+
+    fn disconnect(&mut self, tablet: TabletId) {
+        self.tablets.remove(&tablet);
+    }
+
+    fn filter(&mut self, event: &mut RoutedEvent) -> Verdict {
+        match self.tablets.get_mut(&event.tablet) {
+            Some(map) => map.filter(event),
+            None => Verdict::Pass,
+        }
+    }
+}
+
+impl Drop for MapToScreen {
+    fn drop(&mut self) {
+        if let Some(view) = &self.view {
+            view.remove(self.tablet);
+        }
+    }
+}
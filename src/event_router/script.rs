@@ -0,0 +1,678 @@
+//! 用表达式修改笔事件
+//!
+//! 内置过滤器做不到的调整可以写成表达式, 不需要编译插件. 每一行是一条赋值语句, 按顺序对
+//! 每个笔事件执行, 后面的语句读到的是前面修改后的值:
+//!
+//! ```toml
+//! [defaults]
+//! transforms = [
+//!     "pressure = clamp(pressure * 1.2, 0, 1)",
+//!     # 橡皮擦不需要那么灵敏
+//!     "pressure = if(eraser, pow(pressure, 2), pressure)",
+//!     # 左右镜像
+//!     "x = max_x - x",
+//! ]
+//! ```
+//!
+//! - 可以修改的变量: `x` `y` (设备单位), `pressure` (0 ~ 1), `tilt_x` `tilt_y` (度)
+//! - 只读的变量: `max_x` `max_y`, `pressed` (笔尖接触), `hovering` (悬浮), `eraser`, `bank`
+//! - 运算符: `+ - * / %`, `< <= > >= == !=`, `&& || !`, 括号
+//! - 函数: `clamp(v, lo, hi)` `min` `max` `abs` `pow` `sqrt` `round` `floor`
+//!   `lerp(a, b, t)` `smoothstep(e0, e1, v)` `if(条件, 真, 假)`
+//!
+//! 所有值都是浮点数, 比较和逻辑运算的结果为 1 或 0, 不为 0 的值当作真. 结果不是有限数
+//! (比如除以 0)时这条语句不生效. 写回事件时坐标和压力限制在设备的范围内.
+//! 表达式在加载配置时检查, 有错误时整个配置不生效
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{Context, bail};
+
+use crate::{
+    config::{Config, ConfigChange, transaction::ConfigStage},
+    event_model::{
+        capability::DeviceCapabilities,
+        event::{PenLocation, PenState, TabletEvent, ToolType},
+        tablet::TabletId,
+    },
+};
+
+use super::{RoutedEvent, RouterFilter, Verdict};
+
+/// 表达式中的变量
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Var {
+    X,
+    Y,
+    Pressure,
+    TiltX,
+    TiltY,
+    MaxX,
+    MaxY,
+    Pressed,
+    Hovering,
+    Eraser,
+    Bank,
+}
+
+impl Var {
+    const ALL: [Var; 11] = [
+        Var::X,
+        Var::Y,
+        Var::Pressure,
+        Var::TiltX,
+        Var::TiltY,
+        Var::MaxX,
+        Var::MaxY,
+        Var::Pressed,
+        Var::Hovering,
+        Var::Eraser,
+        Var::Bank,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Var::X => "x",
+            Var::Y => "y",
+            Var::Pressure => "pressure",
+            Var::TiltX => "tilt_x",
+            Var::TiltY => "tilt_y",
+            Var::MaxX => "max_x",
+            Var::MaxY => "max_y",
+            Var::Pressed => "pressed",
+            Var::Hovering => "hovering",
+            Var::Eraser => "eraser",
+            Var::Bank => "bank",
+        }
+    }
+
+    fn writable(self) -> bool {
+        matches!(
+            self,
+            Var::X | Var::Y | Var::Pressure | Var::TiltX | Var::TiltY
+        )
+    }
+}
+
+/// 内置函数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Function {
+    Clamp,
+    Min,
+    Max,
+    Abs,
+    Pow,
+    Sqrt,
+    Round,
+    Floor,
+    Lerp,
+    Smoothstep,
+    If,
+}
+
+impl Function {
+    const ALL: [Function; 11] = [
+        Function::Clamp,
+        Function::Min,
+        Function::Max,
+        Function::Abs,
+        Function::Pow,
+        Function::Sqrt,
+        Function::Round,
+        Function::Floor,
+        Function::Lerp,
+        Function::Smoothstep,
+        Function::If,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Function::Clamp => "clamp",
+            Function::Min => "min",
+            Function::Max => "max",
+            Function::Abs => "abs",
+            Function::Pow => "pow",
+            Function::Sqrt => "sqrt",
+            Function::Round => "round",
+            Function::Floor => "floor",
+            Function::Lerp => "lerp",
+            Function::Smoothstep => "smoothstep",
+            Function::If => "if",
+        }
+    }
+
+    fn arity(self) -> usize {
+        match self {
+            Function::Abs | Function::Sqrt | Function::Round | Function::Floor => 1,
+            Function::Min | Function::Max | Function::Pow => 2,
+            Function::Clamp | Function::Lerp | Function::Smoothstep | Function::If => 3,
+        }
+    }
+
+    fn call(self, args: &[f64]) -> f64 {
+        match (self, args) {
+            (Function::Clamp, [v, lo, hi]) => v.max(*lo).min(*hi),
+            (Function::Min, [a, b]) => a.min(*b),
+            (Function::Max, [a, b]) => a.max(*b),
+            (Function::Abs, [v]) => v.abs(),
+            (Function::Pow, [v, e]) => v.powf(*e),
+            (Function::Sqrt, [v]) => v.sqrt(),
+            (Function::Round, [v]) => v.round(),
+            (Function::Floor, [v]) => v.floor(),
+            (Function::Lerp, [a, b, t]) => a + (b - a) * t,
+            (Function::Smoothstep, [e0, e1, v]) => {
+                let t = ((v - e0) / (e1 - e0)).clamp(0.0, 1.0);
+                t * t * (3.0 - 2.0 * t)
+            }
+            (Function::If, [condition, then, otherwise]) => {
+                if *condition != 0.0 {
+                    *then
+                } else {
+                    *otherwise
+                }
+            }
+            // 参数个数在解析时已经检查过
+            _ => f64::NAN,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    /// 运算符和它的优先级, 数字越大越先计算
+    fn parse(token: &str) -> Option<(BinaryOp, u8)> {
+        Some(match token {
+            "||" => (BinaryOp::Or, 1),
+            "&&" => (BinaryOp::And, 2),
+            "==" => (BinaryOp::Eq, 3),
+            "!=" => (BinaryOp::Ne, 3),
+            "<" => (BinaryOp::Lt, 3),
+            "<=" => (BinaryOp::Le, 3),
+            ">" => (BinaryOp::Gt, 3),
+            ">=" => (BinaryOp::Ge, 3),
+            "+" => (BinaryOp::Add, 4),
+            "-" => (BinaryOp::Sub, 4),
+            "*" => (BinaryOp::Mul, 5),
+            "/" => (BinaryOp::Div, 5),
+            "%" => (BinaryOp::Rem, 5),
+            _ => return None,
+        })
+    }
+
+    fn apply(self, a: f64, b: f64) -> f64 {
+        let truth = |value: bool| if value { 1.0 } else { 0.0 };
+        match self {
+            BinaryOp::Add => a + b,
+            BinaryOp::Sub => a - b,
+            BinaryOp::Mul => a * b,
+            BinaryOp::Div => a / b,
+            BinaryOp::Rem => a % b,
+            BinaryOp::Lt => truth(a < b),
+            BinaryOp::Le => truth(a <= b),
+            BinaryOp::Gt => truth(a > b),
+            BinaryOp::Ge => truth(a >= b),
+            BinaryOp::Eq => truth(a == b),
+            BinaryOp::Ne => truth(a != b),
+            BinaryOp::And => truth(a != 0.0 && b != 0.0),
+            BinaryOp::Or => truth(a != 0.0 || b != 0.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Number(f64),
+    Var(Var),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Call(Function, Vec<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, vars: &Vars) -> f64 {
+        match self {
+            Expr::Number(value) => *value,
+            Expr::Var(var) => vars.get(*var),
+            Expr::Neg(expr) => -expr.eval(vars),
+            Expr::Not(expr) => {
+                if expr.eval(vars) == 0.0 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Expr::Binary(op, a, b) => op.apply(a.eval(vars), b.eval(vars)),
+            Expr::Call(function, args) => {
+                let args: Vec<_> = args.iter().map(|arg| arg.eval(vars)).collect();
+                function.call(&args)
+            }
+        }
+    }
+}
+
+/// 一个笔事件的变量值
+#[derive(Debug, Clone, Copy, Default)]
+struct Vars([f64; Var::ALL.len()]);
+
+impl Vars {
+    fn get(&self, var: Var) -> f64 {
+        self.0[var as usize]
+    }
+
+    fn set(&mut self, var: Var, value: f64) {
+        self.0[var as usize] = value;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    /// 运算符、括号、逗号和 `=`
+    Punct(&'static str),
+}
+
+/// 按长度排列, 先匹配两个字符的运算符
+const PUNCTS: [&str; 18] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "%", "!", "(", ")", ",", "=",
+];
+
+/// 拆分成单词, 每个单词带着它在语句中的字符位置
+fn tokenize(source: &str) -> anyhow::Result<Vec<(usize, Token)>> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit()
+            || (c == '.' && chars.get(i + 1).is_some_and(char::is_ascii_digit))
+        {
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            let value = text
+                .parse()
+                .ok()
+                .with_context(|| format!("第 {} 个字符处的数字 `{text}` 无效", start + 1))?;
+            tokens.push((start, Token::Number(value)));
+        } else if c.is_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push((start, Token::Ident(chars[start..i].iter().collect())));
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            let Some(punct) = PUNCTS.iter().find(|punct| rest.starts_with(**punct)) else {
+                bail!("第 {} 个字符 `{c}` 无法识别", start + 1);
+            };
+            i += punct.chars().count();
+            tokens.push((start, Token::Punct(punct)));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    /// 语句的字符数, 用于报告语句末尾的错误
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, token)| token)
+    }
+
+    /// 下一个单词的位置(从 1 开始)
+    fn position(&self) -> usize {
+        self.tokens
+            .get(self.next)
+            .map_or(self.end, |(position, _)| *position)
+            + 1
+    }
+
+    fn bump(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).map(|(_, token)| token.clone());
+        self.next += 1;
+        token
+    }
+
+    fn expect(&mut self, punct: &str) -> anyhow::Result<()> {
+        let position = self.position();
+        match self.bump() {
+            Some(Token::Punct(found)) if found == punct => Ok(()),
+            _ => bail!("第 {position} 个字符处缺少 `{punct}`"),
+        }
+    }
+
+    /// 解析优先级不低于 `min` 的二元运算
+    fn expr(&mut self, min: u8) -> anyhow::Result<Expr> {
+        let mut left = self.unary()?;
+        while let Some(Token::Punct(punct)) = self.peek()
+            && let Some((op, precedence)) = BinaryOp::parse(punct)
+            && precedence >= min
+        {
+            self.bump();
+            let right = self.expr(precedence + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> anyhow::Result<Expr> {
+        match self.peek() {
+            Some(Token::Punct("-")) => {
+                self.bump();
+                Ok(Expr::Neg(Box::new(self.unary()?)))
+            }
+            Some(Token::Punct("!")) => {
+                self.bump();
+                Ok(Expr::Not(Box::new(self.unary()?)))
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> anyhow::Result<Expr> {
+        let position = self.position();
+        match self.bump() {
+            Some(Token::Number(value)) => Ok(Expr::Number(value)),
+            Some(Token::Punct("(")) => {
+                let expr = self.expr(0)?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Some(Token::Ident(name)) if self.peek() == Some(&Token::Punct("(")) => {
+                let function = Function::ALL
+                    .into_iter()
+                    .find(|function| function.name() == name)
+                    .with_context(|| format!("第 {position} 个字符处的函数 `{name}` 不存在"))?;
+                self.bump();
+                let mut args = Vec::new();
+                if self.peek() != Some(&Token::Punct(")")) {
+                    args.push(self.expr(0)?);
+                    while self.peek() == Some(&Token::Punct(",")) {
+                        self.bump();
+                        args.push(self.expr(0)?);
+                    }
+                }
+                self.expect(")")?;
+                if args.len() != function.arity() {
+                    bail!(
+                        "{name} 需要 {} 个参数, 实际为 {} 个",
+                        function.arity(),
+                        args.len()
+                    );
+                }
+                Ok(Expr::Call(function, args))
+            }
+            Some(Token::Ident(name)) => {
+                Ok(Expr::Var(variable(&name).with_context(|| {
+                    format!("第 {position} 个字符处的变量 `{name}` 不存在")
+                })?))
+            }
+            Some(Token::Punct(punct)) => bail!("第 {position} 个字符处不应该出现 `{punct}`"),
+            None => bail!("语句不完整"),
+        }
+    }
+}
+
+fn variable(name: &str) -> Option<Var> {
+    Var::ALL.into_iter().find(|var| var.name() == name)
+}
+
+/// 一条赋值语句
+#[derive(Debug, Clone, PartialEq)]
+struct Statement {
+    target: Var,
+    expr: Expr,
+}
+
+impl Statement {
+    fn parse(source: &str) -> anyhow::Result<Self> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            next: 0,
+            end: source.chars().count(),
+        };
+        let target = match parser.bump() {
+            Some(Token::Ident(name)) => {
+                variable(&name).with_context(|| format!("变量 `{name}` 不存在"))?
+            }
+            _ => bail!("语句应该以要修改的变量开头, 比如 `pressure = pressure * 1.2`"),
+        };
+        if !target.writable() {
+            bail!("`{}` 是只读的", target.name());
+        }
+        parser.expect("=")?;
+        let expr = parser.expr(0)?;
+        if parser.peek().is_some() {
+            bail!("第 {} 个字符处应该是语句的结尾", parser.position());
+        }
+        Ok(Self { target, expr })
+    }
+}
+
+/// 一组按顺序执行的语句
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Script {
+    statements: Vec<Statement>,
+}
+
+impl Script {
+    /// 解析设置中的 `transforms`
+    pub fn parse(lines: &[String]) -> anyhow::Result<Self> {
+        let statements = lines
+            .iter()
+            .enumerate()
+            .map(|(index, line)| {
+                Statement::parse(line)
+                    .with_context(|| format!("第 {} 条变换 `{line}` 无效", index + 1))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self { statements })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.statements.is_empty()
+    }
+
+    /// 对笔事件执行所有语句, `capabilities` 决定坐标和压力的范围
+    pub fn apply(&self, pen: &mut PenState, bank: u8, capabilities: &DeviceCapabilities) {
+        if self.is_empty() {
+            return;
+        }
+        let flag = |value: bool| if value { 1.0 } else { 0.0 };
+        let max_pressure = capabilities.max_pressure as f64;
+        let mut vars = Vars::default();
+        vars.set(Var::X, pen.x as f64);
+        vars.set(Var::Y, pen.y as f64);
+        if max_pressure > 0.0 {
+            vars.set(Var::Pressure, pen.pressure as f64 / max_pressure);
+        }
+        vars.set(Var::TiltX, pen.tilt.x as f64);
+        vars.set(Var::TiltY, pen.tilt.y as f64);
+        vars.set(Var::MaxX, capabilities.max_x as f64);
+        vars.set(Var::MaxY, capabilities.max_y as f64);
+        vars.set(Var::Pressed, flag(pen.location == PenLocation::Pressed));
+        vars.set(Var::Hovering, flag(pen.location == PenLocation::Floating));
+        vars.set(Var::Eraser, flag(pen.tool == ToolType::Eraser));
+        vars.set(Var::Bank, bank as f64);
+
+        for statement in &self.statements {
+            let value = statement.expr.eval(&vars);
+            if value.is_finite() {
+                vars.set(statement.target, value);
+            }
+        }
+
+        let coordinate = |value: f64, max: u32| {
+            let value = value.round().max(0.0);
+            if max > 0 {
+                value.min(max as f64)
+            } else {
+                value
+            }
+        };
+        pen.x = coordinate(vars.get(Var::X), capabilities.max_x) as u32;
+        pen.y = coordinate(vars.get(Var::Y), capabilities.max_y) as u32;
+        if max_pressure > 0.0 {
+            pen.pressure = (vars.get(Var::Pressure).clamp(0.0, 1.0) * max_pressure).round() as u32;
+        }
+        let tilt = |value: f64| value.round().clamp(i16::MIN as f64, i16::MAX as f64) as i16;
+        pen.tilt.x = tilt(vars.get(Var::TiltX));
+        pen.tilt.y = tilt(vars.get(Var::TiltY));
+    }
+}
+
+#[derive(Debug, Default)]
+struct ScriptTable {
+    default: Arc<Script>,
+    tablets: HashMap<TabletId, Arc<Script>>,
+}
+
+/// 所有数位板的变换, 可以在其他任务中修改
+#[derive(Debug, Clone, Default)]
+pub struct TransformScripts {
+    table: Arc<RwLock<ScriptTable>>,
+}
+
+impl TransformScripts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 数位板当前使用的变换
+    pub fn get(&self, tablet: TabletId) -> Arc<Script> {
+        let table = self.table.read().unwrap();
+        Arc::clone(table.tablets.get(&tablet).unwrap_or(&table.default))
+    }
+
+    /// 替换所有变换
+    pub fn replace(&self, default: Script, tablets: HashMap<TabletId, Script>) {
+        *self.table.write().unwrap() = ScriptTable {
+            default: Arc::new(default),
+            tablets: tablets
+                .into_iter()
+                .map(|(tablet, script)| (tablet, Arc::new(script)))
+                .collect(),
+        };
+    }
+}
+
+/// 解析配置中所有数位板的变换
+fn parse_config(config: &Config) -> anyhow::Result<(Script, HashMap<TabletId, Script>)> {
+    let default = Script::parse(&config.defaults.transforms).context("默认的变换无效")?;
+    let tablets = config
+        .tablets
+        .iter()
+        .map(|tablet| {
+            let script = Script::parse(&tablet.profile.transforms)
+                .with_context(|| format!("{} 的变换无效", tablet.id))?;
+            Ok((tablet.id, script))
+        })
+        .collect::<anyhow::Result<_>>()?;
+    Ok((default, tablets))
+}
+
+impl ConfigStage for TransformScripts {
+    fn name(&self) -> &str {
+        "transforms"
+    }
+
+    fn validate(&self, config: &Config) -> anyhow::Result<()> {
+        parse_config(config).map(|_| ())
+    }
+
+    fn apply(&mut self, config: &Config, _change: &ConfigChange) -> anyhow::Result<()> {
+        let (default, tablets) = parse_config(config)?;
+        self.replace(default, tablets);
+        Ok(())
+    }
+}
+
+/// 按 [`TransformScripts`] 修改笔事件
+///
+/// 应该放在压感曲线和映射到屏幕之前, 这样表达式读到的是设备报告的值
+pub struct Transforms {
+    scripts: TransformScripts,
+    /// 每块数位板的坐标和压感范围
+    capabilities: HashMap<TabletId, DeviceCapabilities>,
+}
+
+impl Transforms {
+    pub fn new(scripts: TransformScripts) -> Self {
+        Self {
+            scripts,
+            capabilities: HashMap::new(),
+        }
+    }
+
+    pub fn scripts(&self) -> &TransformScripts {
+        &self.scripts
+    }
+
+    /// 数位板接入时登记范围, 没有登记的数位板不执行变换
+    pub fn add_tablet(&mut self, tablet: TabletId, capabilities: &DeviceCapabilities) {
+        self.capabilities.insert(tablet, capabilities.clone());
+    }
+
+    pub fn remove_tablet(&mut self, tablet: TabletId) {
+        self.capabilities.remove(&tablet);
+    }
+}
+
+impl RouterFilter for Transforms {
+    fn name(&self) -> &str {
+        "transform"
+    }
+
+    fn connect(&mut self, tablet: TabletId, capabilities: &DeviceCapabilities) {
+        self.add_tablet(tablet, capabilities);
+    }
+
+    fn disconnect(&mut self, tablet: TabletId) {
+        self.remove_tablet(tablet);
+    }
+
+    fn filter(&mut self, event: &mut RoutedEvent) -> Verdict {
+        if let TabletEvent::PenEvent(pen) = &mut event.event
+            && let Some(capabilities) = self.capabilities.get(&event.tablet)
+        {
+            self.scripts
+                .get(event.tablet)
+                .apply(pen, event.bank, capabilities);
+        }
+        Verdict::Pass
+    }
+
+    fn config_stage(&mut self) -> Option<&mut dyn ConfigStage> {
+        Some(&mut self.scripts)
+    }
+}
@@ -0,0 +1,411 @@
+//! 笔的位置平滑和预测
+//!
+//! 便宜的数位板坐标抖动明显, 打开平滑后笔的位置先经过一个低通滤波器:
+//!
+//! - [`SmoothingFilter::Ema`]: 指数移动平均, 简单但移动越快滞后越明显
+//! - [`SmoothingFilter::OneEuro`]: [One Euro Filter](https://gery.casiez.net/1euro/),
+//!   慢速时强平滑去掉抖动, 快速移动时自动减弱平滑, 滞后很小
+//!
+//! 预测按最近的速度把位置向前推 `prediction_ms` 毫秒, 抵消平滑和合成器带来的延迟.
+//! 抬笔的那个事件不做预测, 避免笔画末尾冲出去.
+//!
+//! 两者分别是 [`Smoothing`] 和 [`Prediction`] 两个过滤器, 读取同一张 [`SmoothingSettings`],
+//! 应该放在映射到屏幕之前. 每块数位板可以有自己的设置, 运行时修改后下一个事件就会生效
+//!
+//! ```toml
+//! [defaults.smoothing]
+//! filter = { type = "one_euro", min_cutoff = 1.0, beta = 0.001 }
+//! prediction_ms = 8
+//! ```
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{Config, ConfigChange, transaction::ConfigStage},
+    event_model::{
+        event::{PenLocation, PenState, TabletEvent},
+        stamp::EventStamp,
+        tablet::TabletId,
+    },
+};
+
+use super::{RoutedEvent, RouterFilter, Verdict};
+
+/// 允许的最长预测时间, 再长预测的位置会明显偏离笔画
+pub const MAX_PREDICTION_MS: u32 = 30;
+/// 两个事件间隔超过这个时间(微秒)就重新开始, 不和之前的位置平滑
+const RESET_GAP_US: u64 = 100_000;
+/// 同一份报告中的事件时间戳相同, 计算速度时至少按这个间隔(秒)
+const MIN_DT: f64 = 0.001;
+
+/// 平滑使用的滤波器
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum SmoothingFilter {
+    /// 不平滑
+    #[default]
+    None,
+    /// 指数移动平均, `alpha` (0 ~ 1) 越小越平滑
+    Ema { alpha: f32 },
+    /// One Euro Filter. `min_cutoff` (Hz) 越小静止时越平滑, `beta` 越大快速移动时滞后越小,
+    /// 速度以设备单位每秒计算
+    OneEuro {
+        min_cutoff: f32,
+        beta: f32,
+        #[serde(default = "default_d_cutoff")]
+        d_cutoff: f32,
+    },
+}
+
+fn default_d_cutoff() -> f32 {
+    1.0
+}
+
+/// 一块数位板的平滑和预测设置
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SmoothingConfig {
+    pub filter: SmoothingFilter,
+    /// 预测的时间(毫秒), 0 表示不预测, 最大为 [`MAX_PREDICTION_MS`]
+    pub prediction_ms: u32,
+}
+
+impl SmoothingConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        match self.filter {
+            SmoothingFilter::None => {}
+            SmoothingFilter::Ema { alpha } => {
+                if !(alpha > 0.0 && alpha <= 1.0) {
+                    bail!("alpha 必须在 0 ~ 1 之间且大于 0, 实际为 {alpha}");
+                }
+            }
+            SmoothingFilter::OneEuro {
+                min_cutoff,
+                beta,
+                d_cutoff,
+            } => {
+                if !(min_cutoff > 0.0 && d_cutoff > 0.0) {
+                    bail!("min_cutoff 和 d_cutoff 必须大于 0");
+                }
+                if beta.is_nan() || beta < 0.0 {
+                    bail!("beta 不能小于 0, 实际为 {beta}");
+                }
+            }
+        }
+        if self.prediction_ms > MAX_PREDICTION_MS {
+            bail!(
+                "prediction_ms 不能超过 {MAX_PREDICTION_MS}, 实际为 {}",
+                self.prediction_ms
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct SettingsTable {
+    default: SmoothingConfig,
+    tablets: HashMap<TabletId, SmoothingConfig>,
+}
+
+/// 所有数位板的平滑和预测设置, 可以在其他任务中修改
+#[derive(Debug, Clone, Default)]
+pub struct SmoothingSettings {
+    table: Arc<RwLock<SettingsTable>>,
+}
+
+impl SmoothingSettings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 数位板当前使用的设置
+    pub fn get(&self, tablet: TabletId) -> SmoothingConfig {
+        let table = self.table.read().unwrap();
+        table.tablets.get(&tablet).unwrap_or(&table.default).clone()
+    }
+
+    /// 设置某块数位板, 设置无效时不修改
+    pub fn set(&self, tablet: TabletId, config: SmoothingConfig) -> anyhow::Result<()> {
+        config.validate()?;
+        self.table.write().unwrap().tablets.insert(tablet, config);
+        Ok(())
+    }
+
+    /// 设置没有单独设置的数位板使用的设置
+    pub fn set_default(&self, config: SmoothingConfig) -> anyhow::Result<()> {
+        config.validate()?;
+        self.table.write().unwrap().default = config;
+        Ok(())
+    }
+
+    /// 替换所有设置, 有无效的设置时什么也不修改
+    pub fn replace(
+        &self,
+        default: SmoothingConfig,
+        tablets: HashMap<TabletId, SmoothingConfig>,
+    ) -> anyhow::Result<()> {
+        default.validate()?;
+        for (tablet, config) in &tablets {
+            config
+                .validate()
+                .with_context(|| format!("{tablet} 的平滑设置无效"))?;
+        }
+        *self.table.write().unwrap() = SettingsTable { default, tablets };
+        Ok(())
+    }
+
+    /// 去掉数位板单独的设置，改用默认设置
+    pub fn reset(&self, tablet: TabletId) -> Option<SmoothingConfig> {
+        self.table.write().unwrap().tablets.remove(&tablet)
+    }
+}
+
+impl ConfigStage for SmoothingSettings {
+    fn name(&self) -> &str {
+        "smoothing"
+    }
+
+    fn validate(&self, config: &Config) -> anyhow::Result<()> {
+        config
+            .defaults
+            .smoothing
+            .validate()
+            .context("默认的平滑设置无效")?;
+        for tablet in &config.tablets {
+            tablet
+                .profile
+                .smoothing
+                .validate()
+                .with_context(|| format!("{} 的平滑设置无效", tablet.id))?;
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, config: &Config, _change: &ConfigChange) -> anyhow::Result<()> {
+        self.replace(
+            config.defaults.smoothing.clone(),
+            config
+                .tablets
+                .iter()
+                .map(|tablet| (tablet.id, tablet.profile.smoothing.clone()))
+                .collect(),
+        )
+    }
+}
+
+/// 两个事件之间的时间(秒)
+fn interval(previous: &EventStamp, stamp: &EventStamp) -> f64 {
+    (stamp.timestamp.saturating_sub(previous.timestamp) as f64 / 1e6).max(MIN_DT)
+}
+
+/// 是否应该丢弃之前的状态重新开始
+fn is_break(previous: &EventStamp, stamp: &EventStamp) -> bool {
+    stamp.timestamp < previous.timestamp || stamp.timestamp - previous.timestamp > RESET_GAP_US
+}
+
+/// 截止频率为 `cutoff` 的一阶低通滤波器在间隔 `dt` 下的系数
+fn lowpass_alpha(cutoff: f64, dt: f64) -> f64 {
+    let tau = 1.0 / (std::f64::consts::TAU * cutoff);
+    1.0 / (1.0 + tau / dt)
+}
+
+/// 一个坐标轴的滤波状态
+#[derive(Debug, Clone, Copy)]
+struct Axis {
+    value: f64,
+    /// 平滑后的速度, 只有 One Euro Filter 使用
+    derivative: f64,
+}
+
+impl Axis {
+    fn new(value: f64) -> Self {
+        Self {
+            value,
+            derivative: 0.0,
+        }
+    }
+
+    fn update(&mut self, filter: SmoothingFilter, raw: f64, dt: f64) -> f64 {
+        match filter {
+            SmoothingFilter::None => self.value = raw,
+            SmoothingFilter::Ema { alpha } => self.value += alpha as f64 * (raw - self.value),
+            SmoothingFilter::OneEuro {
+                min_cutoff,
+                beta,
+                d_cutoff,
+            } => {
+                let derivative = (raw - self.value) / dt;
+                self.derivative +=
+                    lowpass_alpha(d_cutoff as f64, dt) * (derivative - self.derivative);
+                let cutoff = min_cutoff as f64 + beta as f64 * self.derivative.abs();
+                self.value += lowpass_alpha(cutoff, dt) * (raw - self.value);
+            }
+        }
+        self.value
+    }
+}
+
+/// 一块数位板上正在平滑的笔
+struct Track {
+    filter: SmoothingFilter,
+    stamp: EventStamp,
+    x: Axis,
+    y: Axis,
+}
+
+/// 按 [`SmoothingSettings`] 平滑笔的位置
+pub struct Smoothing {
+    settings: SmoothingSettings,
+    tracks: HashMap<TabletId, Track>,
+}
+
+impl Smoothing {
+    pub fn new(settings: SmoothingSettings) -> Self {
+        Self {
+            settings,
+            tracks: HashMap::new(),
+        }
+    }
+
+    pub fn settings(&self) -> &SmoothingSettings {
+        &self.settings
+    }
+
+    fn apply(&mut self, tablet: TabletId, pen: &mut PenState, stamp: EventStamp) {
+        let filter = self.settings.get(tablet).filter;
+        if filter == SmoothingFilter::None || matches!(pen.location, PenLocation::Leaved) {
+            self.tracks.remove(&tablet);
+            return;
+        }
+        let (x, y) = (pen.x as f64, pen.y as f64);
+        let track = match self.tracks.get_mut(&tablet) {
+            Some(track) if track.filter == filter && !is_break(&track.stamp, &stamp) => track,
+            // 第一个事件、设置变化或者停顿之后从当前位置开始
+            _ => {
+                self.tracks.insert(
+                    tablet,
+                    Track {
+                        filter,
+                        stamp,
+                        x: Axis::new(x),
+                        y: Axis::new(y),
+                    },
+                );
+                return;
+            }
+        };
+        let dt = interval(&track.stamp, &stamp);
+        track.stamp = stamp;
+        pen.x = track.x.update(filter, x, dt).round().max(0.0) as u32;
+        pen.y = track.y.update(filter, y, dt).round().max(0.0) as u32;
+    }
+}
+
+impl RouterFilter for Smoothing {
+    fn name(&self) -> &str {
+        "smoothing"
+    }
+
+    fn filter(&mut self, event: &mut RoutedEvent) -> Verdict {
+        match &mut event.event {
+            TabletEvent::PenEvent(pen) => self.apply(event.tablet, pen, event.stamp),
+            // 换笔后重新开始
+            TabletEvent::ToolIn(_) | TabletEvent::ToolOut(_) => {
+                self.tracks.remove(&event.tablet);
+            }
+            _ => {}
+        }
+        Verdict::Pass
+    }
+}
+
+/// 预测用的最近一个位置
+struct Motion {
+    stamp: EventStamp,
+    location: PenLocation,
+    position: (f64, f64),
+    /// 平滑后的速度(设备单位每秒)
+    velocity: (f64, f64),
+}
+
+/// 平滑速度时新速度的权重
+const VELOCITY_ALPHA: f64 = 0.5;
+
+/// 按 [`SmoothingSettings`] 把笔的位置沿着移动方向向前推
+pub struct Prediction {
+    settings: SmoothingSettings,
+    motions: HashMap<TabletId, Motion>,
+}
+
+impl Prediction {
+    pub fn new(settings: SmoothingSettings) -> Self {
+        Self {
+            settings,
+            motions: HashMap::new(),
+        }
+    }
+
+    pub fn settings(&self) -> &SmoothingSettings {
+        &self.settings
+    }
+
+    fn apply(&mut self, tablet: TabletId, pen: &mut PenState, stamp: EventStamp) {
+        let lead = self.settings.get(tablet).prediction_ms as f64 / 1000.0;
+        if lead == 0.0 || matches!(pen.location, PenLocation::Leaved) {
+            self.motions.remove(&tablet);
+            return;
+        }
+        let position = (pen.x as f64, pen.y as f64);
+        let previous = self.motions.insert(
+            tablet,
+            Motion {
+                stamp,
+                location: pen.location,
+                position,
+                velocity: (0.0, 0.0),
+            },
+        );
+        // 抬笔、落笔和停顿之后没有可用的速度
+        let Some(previous) = previous.filter(|previous| {
+            previous.location == pen.location && !is_break(&previous.stamp, &stamp)
+        }) else {
+            return;
+        };
+        let dt = interval(&previous.stamp, &stamp);
+        let velocity = (
+            previous.velocity.0
+                + VELOCITY_ALPHA * ((position.0 - previous.position.0) / dt - previous.velocity.0),
+            previous.velocity.1
+                + VELOCITY_ALPHA * ((position.1 - previous.position.1) / dt - previous.velocity.1),
+        );
+        if let Some(motion) = self.motions.get_mut(&tablet) {
+            motion.velocity = velocity;
+        }
+        pen.x = (position.0 + velocity.0 * lead).round().max(0.0) as u32;
+        pen.y = (position.1 + velocity.1 * lead).round().max(0.0) as u32;
+    }
+}
+
+impl RouterFilter for Prediction {
+    fn name(&self) -> &str {
+        "prediction"
+    }
+
+    fn filter(&mut self, event: &mut RoutedEvent) -> Verdict {
+        match &mut event.event {
+            TabletEvent::PenEvent(pen) => self.apply(event.tablet, pen, event.stamp),
+            TabletEvent::ToolIn(_) | TabletEvent::ToolOut(_) => {
+                self.motions.remove(&event.tablet);
+            }
+            _ => {}
+        }
+        Verdict::Pass
+    }
+}
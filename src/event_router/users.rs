@@ -0,0 +1,151 @@
+//! 按笔识别用户
+//!
+//! 多人共用一块数位板时，每个人用自己的笔. 笔进入感应范围时驱动报告序列号
+//! ([`TabletEvent::ToolIn`]), 登记了这支笔的用户([`crate::config::UserConfig`])
+//! 的压感曲线和绑定立刻替换数位板自己的设置; 换成未登记的笔时恢复数位板的设置.
+//! 只有能报告序列号的数位板(如 Wacom Intuos)才能识别用户.
+//!
+//! 一个人的两支笔需要不同的压感曲线时, 给每支笔各登记一个用户即可. 笔离开感应范围
+//! ([`TabletEvent::ToolOut`])后设置保持不变, 快捷键仍然使用这支笔的绑定
+
+use std::collections::HashMap;
+
+use tracing::{info, warn};
+
+use crate::{
+    config::{Config, ConfigChange, transaction::ConfigStage},
+    event_model::{
+        event::{TabletEvent, ToolId},
+        tablet::TabletId,
+    },
+    hud_interface::{HudEvent, HudSender},
+};
+
+use super::{
+    RoutedEvent, RouterFilter, Verdict,
+    bindings::{BindingTable, TabletBindings},
+    pressure::PressureCurves,
+};
+
+/// 按笔切换 [`BindingTable`] 和 [`PressureCurves`] 中的设置
+///
+/// 配置重新加载时这两张表会被整个替换, 所以这个过滤器的 [`ConfigStage`]
+/// 必须在它们之后应用(过滤器总是在 [`super::Router::add_stage`] 添加的子系统之后),
+/// 然后重新套用当前用户的设置
+pub struct PenUsers {
+    config: Config,
+    bindings: BindingTable,
+    curves: PressureCurves,
+    /// 每块数位板上最近一支笔
+    pens: HashMap<TabletId, ToolId>,
+    hud: Option<HudSender>,
+}
+
+impl PenUsers {
+    pub fn new(bindings: BindingTable, curves: PressureCurves) -> Self {
+        Self {
+            config: Config::default(),
+            bindings,
+            curves,
+            pens: HashMap::new(),
+            hud: None,
+        }
+    }
+
+    /// 切换用户时在 HUD 上提示
+    pub fn set_hud(&mut self, hud: HudSender) {
+        self.hud = Some(hud);
+    }
+
+    /// 数位板当前的用户名
+    pub fn user(&self, tablet: TabletId) -> Option<&str> {
+        let pen = self.pens.get(&tablet)?;
+        self.config
+            .user(*pen)
+            .map(|user| user.profile.name.as_str())
+    }
+
+    fn tool_in(&mut self, tablet: TabletId, pen: ToolId) {
+        let previous = self.pens.insert(tablet, pen);
+        let user = self.config.user(pen).map(|user| user.profile.name.clone());
+        let previous_user = previous
+            .and_then(|pen| self.config.user(pen))
+            .map(|user| user.profile.name.clone());
+        if user.is_none() {
+            info!("{tablet}: 未登记的笔 {pen}");
+        }
+        // 同一位用户换笔(比如笔尖和橡皮擦)时不需要切换
+        if previous.is_some() && user == previous_user {
+            return;
+        }
+        self.load(tablet);
+        if let Some(hud) = self.hud.as_ref()
+            && (user.is_some() || previous_user.is_some())
+        {
+            let _ = hud.send(HudEvent::PenUserChanged { user });
+        }
+    }
+
+    /// 按数位板上最近的笔设置绑定和压感曲线
+    fn load(&self, tablet: TabletId) {
+        let user = self
+            .pens
+            .get(&tablet)
+            .and_then(|pen| self.config.user(*pen));
+        let profile = match user {
+            Some(user) => &user.profile,
+            None => match self.config.tablet(tablet) {
+                Some(profile) => profile,
+                // 没有单独设置的数位板使用默认设置
+                None => {
+                    self.bindings.reset(tablet);
+                    self.curves.reset(tablet);
+                    return;
+                }
+            },
+        };
+        self.bindings
+            .set(tablet, TabletBindings::from_profile(profile));
+        // 配置在应用前已经检查过，曲线总是有效的
+        if let Err(e) = self.curves.set(tablet, profile.pressure_curve.clone()) {
+            warn!("{tablet}: 无法设置压感曲线: {e:#}");
+        }
+    }
+}
+
+impl ConfigStage for PenUsers {
+    fn name(&self) -> &str {
+        "pen-users"
+    }
+
+    fn validate(&self, config: &Config) -> anyhow::Result<()> {
+        config.validate_users()
+    }
+
+    fn apply(&mut self, config: &Config, _change: &ConfigChange) -> anyhow::Result<()> {
+        self.config = config.clone();
+        for (tablet, pen) in &self.pens {
+            if self.config.user(*pen).is_some() {
+                self.load(*tablet);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RouterFilter for PenUsers {
+    fn name(&self) -> &str {
+        "pen-users"
+    }
+
+    fn config_stage(&mut self) -> Option<&mut dyn ConfigStage> {
+        Some(self)
+    }
+
+    fn filter(&mut self, event: &mut RoutedEvent) -> Verdict {
+        if let TabletEvent::ToolIn(pen) = &event.event {
+            self.tool_in(event.tablet, *pen);
+        }
+        Verdict::Pass
+    }
+}
@@ -0,0 +1,32 @@
+use crate::tablet_driver::stats::{DeviceStats, ReportStats};
+
+/// 显示驱动报告统计的诊断面板
+///
+/// 统计一直在变化, 面板打开时 overlay 需要持续重绘
+#[derive(Debug, Clone, Default)]
+pub struct DiagnosticsPanel {
+    visible: bool,
+    stats: DeviceStats,
+}
+
+impl DiagnosticsPanel {
+    pub fn new(stats: DeviceStats) -> Self {
+        Self {
+            visible: false,
+            stats,
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    /// 每块连接过的数位板一行, 按 ID 排序
+    pub fn rows(&self) -> Vec<ReportStats> {
+        self.stats.snapshot()
+    }
+}
@@ -0,0 +1,68 @@
+use std::time::{Duration, Instant};
+
+/// 触控环的转盘, 显示手指在触控环上的位置
+///
+/// 手指放在触控环上时一直显示，离开后停留一会儿再淡出
+#[derive(Debug, Clone)]
+pub struct Dial {
+    /// 最后一次的位置(0.0 ~ 1.0)
+    position: Option<f32>,
+    touching: bool,
+    /// 手指离开的时间
+    released_at: Option<Instant>,
+    linger: Duration,
+    fade: Duration,
+}
+
+impl Dial {
+    pub fn new(linger: Duration, fade: Duration) -> Self {
+        Self {
+            position: None,
+            touching: false,
+            released_at: None,
+            linger,
+            fade,
+        }
+    }
+
+    /// 更新手指的位置，`None` 表示手指离开
+    pub fn touch(&mut self, position: Option<f32>, now: Instant) {
+        match position {
+            Some(position) => {
+                self.position = Some(position.rem_euclid(1.0));
+                self.touching = true;
+                self.released_at = None;
+            }
+            None if self.touching => {
+                self.touching = false;
+                self.released_at = Some(now);
+            }
+            None => {}
+        }
+    }
+
+    pub fn is_touching(&self) -> bool {
+        self.touching
+    }
+
+    /// 需要显示的位置和不透明度
+    pub fn visible(&self, now: Instant) -> Option<(f32, f32)> {
+        let position = self.position?;
+        let Some(released_at) = self.released_at else {
+            return self.touching.then_some((position, 1.0));
+        };
+        let elapsed = now.saturating_duration_since(released_at);
+        if elapsed <= self.linger {
+            return Some((position, 1.0));
+        }
+        let fading =
+            (elapsed - self.linger).as_secs_f32() / self.fade.as_secs_f32().max(f32::EPSILON);
+        (fading < 1.0).then_some((position, 1.0 - fading))
+    }
+}
+
+impl Default for Dial {
+    fn default() -> Self {
+        Self::new(Duration::from_millis(600), Duration::from_millis(300))
+    }
+}
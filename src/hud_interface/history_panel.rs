@@ -0,0 +1,57 @@
+use super::notification::{Notification, NotificationHistory};
+
+/// 可滚动的通知历史面板
+#[derive(Debug, Clone)]
+pub struct HistoryPanel {
+    visible: bool,
+    /// 从最新一条开始跳过的条数
+    scroll: usize,
+    /// 一页显示的条数
+    page_size: usize,
+}
+
+impl HistoryPanel {
+    pub fn new(page_size: usize) -> Self {
+        Self {
+            visible: false,
+            scroll: 0,
+            page_size: page_size.max(1),
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    /// 打开或关闭面板，打开时回到最新的通知
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+        self.scroll = 0;
+    }
+
+    /// 滚动面板，正数向更早的通知滚动
+    pub fn scroll_by(&mut self, delta: i32, history: &NotificationHistory) {
+        let max_scroll = history.len().saturating_sub(self.page_size);
+        self.scroll = self
+            .scroll
+            .saturating_add_signed(delta as isize)
+            .min(max_scroll);
+    }
+
+    /// 当前页要显示的通知，从新到旧
+    pub fn page<'a>(
+        &self,
+        history: &'a NotificationHistory,
+    ) -> impl Iterator<Item = &'a Notification> + 'a {
+        history
+            .newest_first()
+            .skip(self.scroll)
+            .take(self.page_size)
+    }
+}
+
+impl Default for HistoryPanel {
+    fn default() -> Self {
+        Self::new(8)
+    }
+}
@@ -0,0 +1,55 @@
+use std::{collections::BTreeMap, time::Instant};
+
+/// 到远程服务端的连接状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkStatus {
+    Connected,
+    /// 连接断开, 正在重连
+    Reconnecting,
+    /// 重连多次失败, 之后只会偶尔重试
+    Offline,
+}
+
+impl LinkStatus {
+    pub fn label(self) -> &'static str {
+        match self {
+            LinkStatus::Connected => "已连接",
+            LinkStatus::Reconnecting => "正在重连",
+            LinkStatus::Offline => "离线",
+        }
+    }
+}
+
+/// 所有远程连接的状态, 按服务端地址排序
+#[derive(Debug, Clone, Default)]
+pub struct RemoteLinks {
+    /// 状态和进入这个状态的时间
+    links: BTreeMap<String, (LinkStatus, Instant)>,
+}
+
+impl RemoteLinks {
+    /// 更新一个连接的状态, 返回之前的状态
+    pub fn set(&mut self, server: String, status: LinkStatus, now: Instant) -> Option<LinkStatus> {
+        let previous = self.links.get(&server).map(|(status, _)| *status);
+        if previous != Some(status) {
+            self.links.insert(server, (status, now));
+        }
+        previous
+    }
+
+    /// 连接已停止，不再显示
+    pub fn remove(&mut self, server: &str) {
+        self.links.remove(server);
+    }
+
+    /// 每个连接的地址、状态和进入这个状态的时间
+    pub fn iter(&self) -> impl Iterator<Item = (&str, LinkStatus, Instant)> {
+        self.links
+            .iter()
+            .map(|(server, (status, since))| (server.as_str(), *status, *since))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+}
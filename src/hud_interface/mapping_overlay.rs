@@ -0,0 +1,102 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use crate::{event_model::tablet::TabletId, mapping::Rect};
+
+/// 一块数位板的映射, 都是逻辑坐标
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TabletMapping {
+    /// 数位板映射到的区域, 没有显示器时为 `None`
+    pub target: Option<Rect>,
+    /// 笔的位置, 笔不在感应范围内时为 `None`
+    pub pen: Option<(f64, f64)>,
+}
+
+/// 每块数位板的映射区域和笔的位置
+///
+/// 路由器的 [`crate::event_router::screen::MapToScreen`] 写入, HUD 读取. 只在显示时记录
+/// 笔的位置, 关闭时路由器只多读一次开关
+#[derive(Debug, Clone, Default)]
+pub struct MappingView {
+    visible: Arc<AtomicBool>,
+    tablets: Arc<Mutex<HashMap<TabletId, TabletMapping>>>,
+}
+
+impl MappingView {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible.load(Ordering::Relaxed)
+    }
+
+    /// 映射设置或显示器布局变化后更新映射区域
+    pub fn set_target(&self, tablet: TabletId, target: Option<Rect>) {
+        self.tablets
+            .lock()
+            .unwrap()
+            .entry(tablet)
+            .or_default()
+            .target = target;
+    }
+
+    pub fn set_pen(&self, tablet: TabletId, pen: Option<(f64, f64)>) {
+        self.tablets.lock().unwrap().entry(tablet).or_default().pen = pen;
+    }
+
+    /// 数位板断开
+    pub fn remove(&self, tablet: TabletId) {
+        self.tablets.lock().unwrap().remove(&tablet);
+    }
+
+    /// 所有数位板的映射, 按 ID 排序
+    pub fn snapshot(&self) -> Vec<(TabletId, TabletMapping)> {
+        let mut tablets: Vec<_> = self
+            .tablets
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(tablet, mapping)| (*tablet, *mapping))
+            .collect();
+        tablets.sort_by_key(|(tablet, _)| tablet.0);
+        tablets
+    }
+}
+
+/// 在所有显示器上显示数位板的映射区域和笔位置的十字线, 用于检查多显示器的映射
+///
+/// 笔一直在移动, 打开时 overlay 需要持续重绘
+#[derive(Debug, Clone, Default)]
+pub struct MappingOverlay {
+    view: MappingView,
+}
+
+impl MappingOverlay {
+    pub fn new(view: MappingView) -> Self {
+        Self { view }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.view.is_visible()
+    }
+
+    /// 关闭时清除笔的位置, 下次打开前路由器不会更新它
+    pub fn toggle(&mut self) {
+        let visible = !self.view.visible.fetch_xor(true, Ordering::Relaxed);
+        if !visible {
+            for mapping in self.view.tablets.lock().unwrap().values_mut() {
+                mapping.pen = None;
+            }
+        }
+    }
+
+    pub fn tablets(&self) -> Vec<(TabletId, TabletMapping)> {
+        self.view.snapshot()
+    }
+}
@@ -0,0 +1,373 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::mpsc;
+
+use crate::{
+    event_model::event::{PenLocation, TabletEvent},
+    input_devices::transport::Transport,
+    mapping::{OutputGeometry, calibration::CalibrationView, geometry::GeometryChanged},
+    screen_overlay::backend_wayland::frame::RedrawHandle,
+};
+
+use diagnostics::DiagnosticsPanel;
+use dial::Dial;
+use history_panel::HistoryPanel;
+use link::{LinkStatus, RemoteLinks};
+use mapping_overlay::MappingOverlay;
+use notification::{Notification, NotificationHistory, NotificationLevel};
+use osd::{Osd, OsdSlot};
+use pointer::{HudElement, HudPointer, OverlayPoint};
+use progress::{ProgressBoard, ProgressState, ProgressUpdate};
+use quick_menu::QuickMenu;
+use toast::{Toast, ToastQueue};
+use wheel_ring::{WheelRing, WheelTurn};
+
+/// 驱动报告统计的诊断面板
+pub mod diagnostics;
+/// 触控环的转盘
+pub mod dial;
+/// 通知历史面板
+pub mod history_panel;
+/// 远程连接的状态指示
+pub mod link;
+/// 映射区域和笔位置的十字线
+pub mod mapping_overlay;
+/// HUD 通知及其历史记录
+pub mod notification;
+/// 音量条等状态指示
+pub mod osd;
+/// 交给 HUD 的笔输入
+pub mod pointer;
+/// 耗时操作的进度
+pub mod progress;
+/// 按住笔杆按键弹出的快捷菜单
+pub mod quick_menu;
+/// 短暂显示的提示
+pub mod toast;
+/// 转动滚轮时显示的转盘
+pub mod wheel_ring;
+
+/// 需要由 HUD 展示给用户的事件
+#[derive(Debug, Clone)]
+pub enum HudEvent {
+    /// 数位板切换到了另一个按键模式组
+    ModeBankChanged { bank: u8, bank_count: u8 },
+    /// 显示一条通知
+    Notify(Notification),
+    /// 打开或关闭 HUD
+    ToggleHud,
+    /// 打开或关闭通知历史面板
+    ToggleHistory,
+    /// 滚动通知历史面板
+    ScrollHistory(i32),
+    /// 打开或关闭诊断面板
+    ToggleDiagnostics,
+    /// 显示或隐藏映射区域和十字线
+    ToggleMappingOverlay,
+    /// 校准开始、前进或结束, 进度在 [`HudState::calibration`] 中
+    CalibrationChanged,
+    /// 只看模式下远程数位板的墨迹变化, 墨迹在 [`crate::input_devices::remote::RemoteViewer::ink`] 中
+    RemoteInkChanged,
+    /// 显示器布局变化
+    GeometryChanged(GeometryChanged),
+    /// 显示确认提示, 超时前再按一次或者点击提示才会执行
+    ConfirmPrompt { text: String, timeout: Duration },
+    /// 确认提示已被确认、取消或超时
+    ConfirmDismissed,
+    /// 数位板接入
+    TabletConnected { name: String, transport: Transport },
+    /// 数位板断开
+    TabletDisconnected { name: String, transport: Transport },
+    /// 显示 OSD, 比如滚轮调整音量后的音量条
+    Osd(Osd),
+    /// 映射的目标显示器断开，临时映射到另一个显示器
+    MappingReassigned { from: String, to: Option<String> },
+    /// 目标显示器重新接入，恢复了原来的映射
+    MappingRestored { output: String },
+    /// 手指在触控环上的位置(0.0 ~ 1.0), 手指离开时为 `None`
+    RingTouched { position: Option<f32> },
+    /// 到远程服务端的连接状态变化, 连接停止后为 `None`
+    RemoteLink {
+        server: String,
+        status: Option<LinkStatus>,
+    },
+    /// 换笔后数位板切换到另一位用户的设置, 恢复数位板自己的设置时为 `None`
+    PenUserChanged { user: Option<String> },
+    /// 耗时操作的进度, 由 [`progress::Progress`] 发送
+    Progress(ProgressUpdate),
+    /// 在光标附近打开快捷菜单
+    QuickMenuOpened(QuickMenu),
+    /// 笔指向了快捷菜单中的另一项, 回到中心时为 `None`
+    QuickMenuSelected(Option<usize>),
+    /// 快捷菜单已关闭
+    QuickMenuClosed,
+    /// 滚轮转动触发了动作
+    WheelTurned(WheelTurn),
+    /// 被 HUD 拦截的笔输入, 位置已经换算到 overlay 上
+    Pointer(HudPointer),
+}
+
+/// 向 HUD 发送事件的通道
+pub type HudSender = mpsc::UnboundedSender<HudEvent>;
+
+/// HUD 的界面状态
+pub struct HudState {
+    /// 通知历史，和控制接口共享
+    pub notifications: Arc<Mutex<NotificationHistory>>,
+    pub history_panel: HistoryPanel,
+    pub diagnostics: DiagnosticsPanel,
+    pub mapping_overlay: MappingOverlay,
+    /// 正在进行的校准, 和控制接口、路由器共享
+    pub calibration: CalibrationView,
+    /// 当前模式组和模式组数量
+    pub mode_bank: Option<(u8, u8)>,
+    /// 当前的显示器布局
+    pub geometry: GeometryChanged,
+    /// 正在显示的确认提示
+    pub confirm_prompt: Option<String>,
+    pub toasts: ToastQueue,
+    pub osd: OsdSlot,
+    pub dial: Dial,
+    pub wheel_ring: WheelRing,
+    pub links: RemoteLinks,
+    pub progress: ProgressBoard,
+    pub quick_menu: Option<QuickMenu>,
+    /// 最近一次交给 HUD 的笔输入, 笔离开感应范围后为 `None`
+    pub pointer: Option<HudPointer>,
+    /// HUD 是否打开, 打开时笔和按键只用来操作 HUD
+    pub open: bool,
+    /// 界面变化后请求重绘 overlay
+    redraw: Option<RedrawHandle>,
+}
+
+impl HudState {
+    pub fn new(notifications: Arc<Mutex<NotificationHistory>>) -> Self {
+        Self {
+            notifications,
+            history_panel: HistoryPanel::default(),
+            diagnostics: DiagnosticsPanel::default(),
+            mapping_overlay: MappingOverlay::default(),
+            calibration: CalibrationView::default(),
+            mode_bank: None,
+            geometry: GeometryChanged::default(),
+            confirm_prompt: None,
+            toasts: ToastQueue::default(),
+            osd: OsdSlot::default(),
+            dial: Dial::default(),
+            wheel_ring: WheelRing::default(),
+            links: RemoteLinks::default(),
+            progress: ProgressBoard::default(),
+            quick_menu: None,
+            pointer: None,
+            open: false,
+            redraw: None,
+        }
+    }
+
+    /// 每次界面状态变化后通过 `redraw` 请求重绘
+    pub fn set_redraw(&mut self, redraw: RedrawHandle) {
+        self.redraw = Some(redraw);
+    }
+
+    /// 请求重绘 overlay, 比如动画还没结束时
+    pub fn request_redraw(&self) {
+        if let Some(redraw) = &self.redraw {
+            redraw.request();
+        }
+    }
+
+    /// 显示 HUD 的显示器，默认为布局中最左上的一个
+    pub fn hud_output(&self) -> Option<&OutputGeometry> {
+        self.geometry
+            .outputs
+            .iter()
+            .min_by(|a, b| a.y.total_cmp(&b.y).then(a.x.total_cmp(&b.x)))
+    }
+
+    /// 根据事件更新界面状态并请求重绘
+    pub fn apply(&mut self, event: HudEvent) {
+        self.update(event);
+        self.request_redraw();
+    }
+
+    fn update(&mut self, event: HudEvent) {
+        match event {
+            HudEvent::ModeBankChanged { bank, bank_count } => {
+                self.mode_bank = Some((bank, bank_count));
+            }
+            HudEvent::Notify(notification) => {
+                self.notifications.lock().unwrap().push(notification);
+            }
+            HudEvent::ToggleHud => self.open = !self.open,
+            HudEvent::ToggleHistory => self.history_panel.toggle(),
+            HudEvent::ToggleDiagnostics => self.diagnostics.toggle(),
+            HudEvent::ToggleMappingOverlay => self.mapping_overlay.toggle(),
+            HudEvent::CalibrationChanged | HudEvent::RemoteInkChanged => {}
+            HudEvent::ScrollHistory(delta) => {
+                let history = self.notifications.lock().unwrap();
+                self.history_panel.scroll_by(delta, &history);
+            }
+            HudEvent::GeometryChanged(geometry) => self.geometry_changed(geometry),
+            HudEvent::ConfirmPrompt { text, .. } => self.confirm_prompt = Some(text),
+            HudEvent::ConfirmDismissed => self.confirm_prompt = None,
+            HudEvent::TabletConnected { name, transport } => {
+                self.hotplug(NotificationLevel::Info, "数位板已连接", name, transport);
+            }
+            HudEvent::TabletDisconnected { name, transport } => {
+                self.hotplug(NotificationLevel::Warning, "数位板已断开", name, transport);
+            }
+            HudEvent::Osd(osd) => self.osd.show(osd, Instant::now()),
+            HudEvent::RingTouched { position } => self.dial.touch(position, Instant::now()),
+            HudEvent::WheelTurned(turn) => {
+                let now = Instant::now();
+                // 触控环转盘从 OSD 中取当前的值
+                self.osd.show(turn.osd.clone(), now);
+                self.wheel_ring.turn(turn, now);
+            }
+            HudEvent::RemoteLink { server, status } => self.remote_link(server, status),
+            HudEvent::MappingReassigned { from, to } => {
+                let detail = match to {
+                    Some(to) => format!("{from} 已断开, 暂时映射到 {to}"),
+                    None => format!("{from} 已断开, 没有可用的显示器"),
+                };
+                self.notice(NotificationLevel::Warning, "显示器已断开", detail);
+            }
+            HudEvent::Progress(update) => {
+                if update.state == ProgressState::Failed {
+                    let detail = update.detail.clone();
+                    self.notice(NotificationLevel::Error, &update.title, detail);
+                }
+                self.progress.update(update, Instant::now());
+            }
+            HudEvent::QuickMenuOpened(menu) => self.quick_menu = Some(menu),
+            HudEvent::QuickMenuSelected(selected) => {
+                if let Some(menu) = self.quick_menu.as_mut() {
+                    menu.selected = selected;
+                }
+            }
+            HudEvent::QuickMenuClosed => self.quick_menu = None,
+            HudEvent::Pointer(pointer) => self.pointer(pointer),
+            HudEvent::PenUserChanged { user } => match user {
+                Some(user) => self.notice(NotificationLevel::Info, "已切换用户", user),
+                None => self.notice(
+                    NotificationLevel::Info,
+                    "已切换用户",
+                    "未登记的笔, 使用数位板的设置".to_string(),
+                ),
+            },
+            HudEvent::MappingRestored { output } => {
+                self.notice(
+                    NotificationLevel::Info,
+                    "显示器已恢复",
+                    format!("已恢复映射到 {output}"),
+                );
+            }
+        }
+    }
+
+    /// 笔在 `element` 上时返回它的位置
+    pub fn pointer_on(&self, element: HudElement) -> Option<&OverlayPoint> {
+        self.pointer
+            .as_ref()
+            .filter(|pointer| pointer.target.element == element)
+            .and_then(|pointer| pointer.target.point.as_ref())
+    }
+
+    /// 记录笔的位置, 按键等没有位置的事件不改变它
+    fn pointer(&mut self, pointer: HudPointer) {
+        match &pointer.event {
+            TabletEvent::PenEvent(pen) if matches!(pen.location, PenLocation::Leaved) => {
+                self.pointer = None;
+            }
+            _ if pointer.target.point.is_some() => self.pointer = Some(pointer),
+            _ => {}
+        }
+    }
+
+    /// 弹出提示，同时记入通知历史
+    fn hotplug(
+        &mut self,
+        level: NotificationLevel,
+        title: &str,
+        name: String,
+        transport: Transport,
+    ) {
+        self.notifications.lock().unwrap().push(Notification::new(
+            level,
+            format!("{title}: {name} ({transport})"),
+        ));
+        self.toasts
+            .push(Toast::new(level, title, format!("{name} · {transport}")));
+    }
+
+    /// 更新显示器布局, 显示器接入或断开时弹出提示
+    ///
+    /// 第一次收到布局时不提示
+    fn geometry_changed(&mut self, geometry: GeometryChanged) {
+        let previous = std::mem::replace(&mut self.geometry, geometry);
+        if previous.outputs.is_empty() {
+            return;
+        }
+        let added: Vec<_> = self
+            .geometry
+            .outputs
+            .iter()
+            .filter(|output| previous.output(&output.name).is_none())
+            .map(|output| format!("{} ({}x{})", output.name, output.width, output.height))
+            .collect();
+        let removed: Vec<_> = previous
+            .outputs
+            .into_iter()
+            .filter(|output| self.geometry.output(&output.name).is_none())
+            .map(|output| output.name)
+            .collect();
+        for detail in added {
+            self.notice(NotificationLevel::Info, "显示器已接入", detail);
+        }
+        for name in removed {
+            self.notice(NotificationLevel::Info, "显示器已移除", name);
+        }
+    }
+
+    /// 更新连接状态, 断开和恢复时弹出提示
+    fn remote_link(&mut self, server: String, status: Option<LinkStatus>) {
+        let Some(status) = status else {
+            self.links.remove(&server);
+            return;
+        };
+        let previous = self.links.set(server.clone(), status, Instant::now());
+        if previous == Some(status) {
+            return;
+        }
+        match (previous, status) {
+            (None, LinkStatus::Connected) => {
+                self.notice(NotificationLevel::Info, "远程数位板已连接", server);
+            }
+            (Some(_), LinkStatus::Connected) => {
+                self.notice(NotificationLevel::Info, "远程连接已恢复", server);
+            }
+            (_, LinkStatus::Reconnecting) => {
+                self.notice(NotificationLevel::Warning, "远程连接已断开", server);
+            }
+            (_, LinkStatus::Offline) => {
+                self.notice(
+                    NotificationLevel::Error,
+                    "远程服务端离线",
+                    format!("{server} 无法连接, 稍后会继续重试"),
+                );
+            }
+        }
+    }
+
+    /// 弹出提示，同时记入通知历史
+    fn notice(&mut self, level: NotificationLevel, title: &str, detail: String) {
+        self.notifications
+            .lock()
+            .unwrap()
+            .push(Notification::new(level, format!("{title}: {detail}")));
+        self.toasts.push(Toast::new(level, title, detail));
+    }
+}
@@ -0,0 +1,72 @@
+use std::{collections::VecDeque, time::SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+/// 通知的重要程度
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationLevel {
+    Info,
+    Warning,
+    Error,
+}
+
+/// 一条 HUD 通知
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Notification {
+    pub time: SystemTime,
+    pub level: NotificationLevel,
+    pub text: String,
+}
+
+impl Notification {
+    pub fn new(level: NotificationLevel, text: impl Into<String>) -> Self {
+        Self {
+            time: SystemTime::now(),
+            level,
+            text: text.into(),
+        }
+    }
+}
+
+/// 最近的 N 条通知
+#[derive(Debug, Clone)]
+pub struct NotificationHistory {
+    entries: VecDeque<Notification>,
+    capacity: usize,
+}
+
+impl NotificationHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, notification: Notification) {
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(notification);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// 从新到旧遍历
+    pub fn newest_first(&self) -> impl Iterator<Item = &Notification> {
+        self.entries.iter().rev()
+    }
+}
+
+impl Default for NotificationHistory {
+    fn default() -> Self {
+        Self::new(50)
+    }
+}
@@ -0,0 +1,74 @@
+use std::time::{Duration, Instant};
+
+/// OSD 的图标
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OsdIcon {
+    Volume,
+    ZoomIn,
+    ZoomOut,
+    RotateLeft,
+    RotateRight,
+    Scroll,
+    Brush,
+    /// 自定义的滚轮绑定
+    Wheel,
+}
+
+/// 屏幕中下方短暂显示的状态指示(音量条等)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Osd {
+    pub icon: OsdIcon,
+    pub label: String,
+    /// 0.0 ~ 1.0, 有值时显示进度条
+    pub level: Option<f32>,
+}
+
+impl Osd {
+    pub fn new(icon: OsdIcon, label: impl Into<String>, level: Option<f32>) -> Self {
+        Self {
+            icon,
+            label: label.into(),
+            level: level.map(|level| level.clamp(0.0, 1.0)),
+        }
+    }
+}
+
+/// 当前显示的 OSD, 新的 OSD 直接替换旧的
+#[derive(Debug, Clone)]
+pub struct OsdSlot {
+    current: Option<(Osd, Instant)>,
+    duration: Duration,
+    fade: Duration,
+}
+
+impl OsdSlot {
+    pub fn new(duration: Duration, fade: Duration) -> Self {
+        Self {
+            current: None,
+            duration,
+            fade,
+        }
+    }
+
+    pub fn show(&mut self, osd: Osd, now: Instant) {
+        self.current = Some((osd, now));
+    }
+
+    /// 当前显示的 OSD 和透明度
+    pub fn visible(&self, now: Instant) -> Option<(&Osd, f32)> {
+        let (osd, shown_at) = self.current.as_ref()?;
+        let elapsed = now.saturating_duration_since(*shown_at);
+        if elapsed <= self.duration {
+            return Some((osd, 1.0));
+        }
+        let fading =
+            (elapsed - self.duration).as_secs_f32() / self.fade.as_secs_f32().max(f32::EPSILON);
+        (fading < 1.0).then_some((osd, 1.0 - fading))
+    }
+}
+
+impl Default for OsdSlot {
+    fn default() -> Self {
+        Self::new(Duration::from_millis(1200), Duration::from_millis(300))
+    }
+}
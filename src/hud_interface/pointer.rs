@@ -0,0 +1,71 @@
+//! 交给 HUD 的笔输入
+//!
+//! HUD 打开或者快捷菜单弹出时, 路由器把笔事件标记为已消费, 同时附上接收它的界面元素和笔在
+//! overlay 上的位置. 位置已经换算成那个显示器上 overlay surface 的逻辑坐标, HUD 直接用它
+//! 判断笔指向了哪里, 不需要再经过映射, 也不用关心各个显示器的缩放比例
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    event_model::{event::TabletEvent, tablet::TabletId},
+    mapping::{ScreenPoint, geometry::GeometryChanged},
+};
+
+/// 接收笔输入的 HUD 界面元素
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum HudElement {
+    /// 打开的 HUD 面板
+    Panel,
+    /// 快捷菜单
+    QuickMenu,
+}
+
+/// overlay surface 上的位置
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OverlayPoint {
+    /// 所在的显示器
+    pub output: String,
+    /// 相对显示器左上角的逻辑坐标
+    pub x: f64,
+    pub y: f64,
+    /// 显示器的缩放比例, 乘上它得到 buffer 中的像素坐标
+    pub scale: f64,
+}
+
+impl OverlayPoint {
+    /// 把映射后的屏幕位置换算到所在显示器的 overlay 上, 布局中没有这个显示器时返回 `None`
+    pub fn from_screen(point: &ScreenPoint, geometry: &GeometryChanged) -> Option<Self> {
+        let output = geometry.output(&point.output)?;
+        Some(Self {
+            output: output.name.clone(),
+            x: point.logical_x - output.x,
+            y: point.logical_y - output.y,
+            scale: if output.scale > 0.0 {
+                output.scale
+            } else {
+                1.0
+            },
+        })
+    }
+
+    /// buffer 中的像素坐标
+    pub fn buffer(&self) -> (f64, f64) {
+        (self.x * self.scale, self.y * self.scale)
+    }
+}
+
+/// 被 HUD 拦截的事件的去向
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HudTarget {
+    pub element: HudElement,
+    /// 笔的位置, 按键等没有位置的事件为 `None`
+    pub point: Option<OverlayPoint>,
+}
+
+/// 路由器交给 HUD 的一个事件
+#[derive(Debug, Clone)]
+pub struct HudPointer {
+    pub tablet: TabletId,
+    pub target: HudTarget,
+    pub event: TabletEvent,
+}
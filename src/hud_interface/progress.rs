@@ -0,0 +1,216 @@
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicU32, Ordering},
+    time::{Duration, Instant},
+};
+
+use super::{HudEvent, HudSender};
+
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+/// 一项耗时操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ProgressId(u32);
+
+/// 操作的状态
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProgressState {
+    /// 进行中, 进度为 0.0 ~ 1.0, 不知道进度时为 `None`
+    Running(Option<f32>),
+    Succeeded,
+    Failed,
+    /// 不再需要显示, 比如发现设备不是数位板
+    Cancelled,
+}
+
+/// 进度的一次更新
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgressUpdate {
+    pub id: ProgressId,
+    pub title: String,
+    /// 当前步骤或结果
+    pub detail: String,
+    pub state: ProgressState,
+}
+
+/// 向 HUD 报告一项操作的进度(比如初始化设备、读取固件信息)
+///
+/// 没有调用 [`Progress::finish`] 或 [`Progress::fail`] 就被丢弃时视为取消，不会留下提示
+pub struct Progress {
+    id: ProgressId,
+    title: String,
+    hud: Option<HudSender>,
+    done: bool,
+}
+
+impl Progress {
+    /// 开始一项操作, 没有 HUD 时所有报告都被忽略
+    pub fn start(hud: Option<&HudSender>, title: impl Into<String>) -> Self {
+        let progress = Self {
+            id: ProgressId(NEXT_ID.fetch_add(1, Ordering::Relaxed)),
+            title: title.into(),
+            hud: hud.cloned(),
+            done: false,
+        };
+        progress.send(String::new(), ProgressState::Running(None));
+        progress
+    }
+
+    pub fn id(&self) -> ProgressId {
+        self.id
+    }
+
+    /// 更新当前步骤, `fraction` 为 `None` 时显示为不确定的进度
+    pub fn set(&self, fraction: Option<f32>, detail: impl Into<String>) {
+        let fraction = fraction.map(|fraction| fraction.clamp(0.0, 1.0));
+        self.send(detail.into(), ProgressState::Running(fraction));
+    }
+
+    pub fn finish(mut self, detail: impl Into<String>) {
+        self.done = true;
+        self.send(detail.into(), ProgressState::Succeeded);
+    }
+
+    /// 操作失败, 同时记入通知历史
+    pub fn fail(mut self, detail: impl Into<String>) {
+        self.done = true;
+        self.send(detail.into(), ProgressState::Failed);
+    }
+
+    fn send(&self, detail: String, state: ProgressState) {
+        if let Some(hud) = self.hud.as_ref() {
+            let _ = hud.send(HudEvent::Progress(ProgressUpdate {
+                id: self.id,
+                title: self.title.clone(),
+                detail,
+                state,
+            }));
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if !self.done {
+            self.send(String::new(), ProgressState::Cancelled);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    update: ProgressUpdate,
+    started: Instant,
+    finished: Option<Instant>,
+}
+
+/// 正在显示的操作
+#[derive(Debug, Clone, Copy)]
+pub struct VisibleProgress<'a> {
+    pub update: &'a ProgressUpdate,
+    /// 从下到上的位置
+    pub slot: usize,
+    /// 开始的时间, 用于不确定进度的动画
+    pub started: Instant,
+    pub opacity: f32,
+}
+
+/// HUD 中的进度列表
+///
+/// 操作超过 `show_after` 还没有完成才显示，很快完成的操作不会闪一下.
+/// 完成后保持 `linger` 再淡出
+#[derive(Debug, Clone)]
+pub struct ProgressBoard {
+    entries: BTreeMap<ProgressId, Entry>,
+    show_after: Duration,
+    linger: Duration,
+    fade: Duration,
+}
+
+impl ProgressBoard {
+    pub fn new(show_after: Duration, linger: Duration, fade: Duration) -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            show_after,
+            linger,
+            fade,
+        }
+    }
+
+    pub fn update(&mut self, update: ProgressUpdate, now: Instant) {
+        if update.state == ProgressState::Cancelled {
+            self.entries.remove(&update.id);
+            return;
+        }
+        let id = update.id;
+        let finished = !matches!(update.state, ProgressState::Running(_));
+        let entry = self.entries.entry(id).or_insert_with(|| Entry {
+            update: update.clone(),
+            started: now,
+            finished: None,
+        });
+        entry.update = update;
+        if !finished || entry.finished.is_some() {
+            return;
+        }
+        entry.finished = Some(now);
+        // 没来得及显示就完成了
+        if now.saturating_duration_since(entry.started) < self.show_after {
+            self.entries.remove(&id);
+        }
+    }
+
+    /// 移除已经淡出的操作
+    pub fn tick(&mut self, now: Instant) {
+        let lifetime = self.linger + self.fade;
+        self.entries.retain(|_, entry| {
+            entry
+                .finished
+                .is_none_or(|finished| now.saturating_duration_since(finished) < lifetime)
+        });
+    }
+
+    /// 有需要显示或者即将显示的操作, 这时 overlay 需要持续重绘
+    pub fn is_animating(&self) -> bool {
+        !self.entries.is_empty()
+    }
+
+    /// 正在显示的操作和它们的不透明度
+    pub fn visible(&self, now: Instant) -> impl Iterator<Item = VisibleProgress<'_>> {
+        self.entries
+            .values()
+            .filter(move |entry| now.saturating_duration_since(entry.started) >= self.show_after)
+            .filter_map(move |entry| {
+                let opacity = match entry.finished {
+                    None => 1.0,
+                    Some(finished) => {
+                        let age = now.saturating_duration_since(finished);
+                        if age < self.linger {
+                            1.0
+                        } else {
+                            let fade = self.fade.as_secs_f32().max(f32::EPSILON);
+                            1.0 - (age - self.linger).as_secs_f32() / fade
+                        }
+                    }
+                };
+                (opacity > 0.0).then_some((entry, opacity))
+            })
+            .enumerate()
+            .map(|(slot, (entry, opacity))| VisibleProgress {
+                update: &entry.update,
+                slot,
+                started: entry.started,
+                opacity,
+            })
+    }
+}
+
+impl Default for ProgressBoard {
+    fn default() -> Self {
+        Self::new(
+            Duration::from_millis(400),
+            Duration::from_secs(2),
+            Duration::from_millis(300),
+        )
+    }
+}
@@ -0,0 +1,38 @@
+use std::f64::consts::TAU;
+
+use crate::mapping::ScreenPoint;
+
+/// 离中心小于这个距离(逻辑像素)时不选中任何一项, 这时松开按键只关闭菜单
+pub const DEAD_ZONE: f64 = 24.0;
+
+/// 笔相对菜单中心的位置对应的项, 第一项在正上方，顺时针排列
+///
+/// `dx`、`dy` 是逻辑坐标的偏移, 向右、向下为正
+pub fn entry_at(dx: f64, dy: f64, count: usize) -> Option<usize> {
+    if count == 0 || dx.hypot(dy) < DEAD_ZONE {
+        return None;
+    }
+    let step = TAU / count as f64;
+    let angle = dx.atan2(-dy).rem_euclid(TAU);
+    Some(((angle + step / 2.0) / step) as usize % count)
+}
+
+/// 打开的快捷菜单
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuickMenu {
+    /// 打开菜单时光标的位置
+    pub center: ScreenPoint,
+    pub labels: Vec<String>,
+    /// 笔指向的项
+    pub selected: Option<usize>,
+}
+
+impl QuickMenu {
+    pub fn new(center: ScreenPoint, labels: Vec<String>) -> Self {
+        Self {
+            center,
+            labels,
+            selected: None,
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use super::notification::NotificationLevel;
+
+/// 一条短暂显示的提示(osu!lazer 风格)
+#[derive(Debug, Clone)]
+pub struct Toast {
+    pub title: String,
+    pub detail: String,
+    pub level: NotificationLevel,
+    /// 开始显示的时间, 还在排队时为 `None`
+    shown_at: Option<Instant>,
+}
+
+impl Toast {
+    pub fn new(
+        level: NotificationLevel,
+        title: impl Into<String>,
+        detail: impl Into<String>,
+    ) -> Self {
+        Self {
+            title: title.into(),
+            detail: detail.into(),
+            level,
+            shown_at: None,
+        }
+    }
+}
+
+/// 正在显示的提示
+#[derive(Debug, Clone, Copy)]
+pub struct VisibleToast<'a> {
+    pub toast: &'a Toast,
+    /// 从上到下的位置
+    pub slot: usize,
+    /// 0.0 ~ 1.0, 淡入时从 0 增加到 1，淡出时减小到 0
+    pub opacity: f32,
+}
+
+/// 提示队列
+///
+/// 同时最多显示 `max_visible` 条，其余的排队，有空位时再显示，避免重叠
+#[derive(Debug, Clone)]
+pub struct ToastQueue {
+    waiting: VecDeque<Toast>,
+    shown: Vec<Toast>,
+    max_visible: usize,
+    /// 完全显示的时间, 不包括淡入淡出
+    duration: Duration,
+    fade: Duration,
+}
+
+impl ToastQueue {
+    pub fn new(max_visible: usize, duration: Duration, fade: Duration) -> Self {
+        Self {
+            waiting: VecDeque::new(),
+            shown: Vec::new(),
+            max_visible: max_visible.max(1),
+            duration,
+            fade,
+        }
+    }
+
+    pub fn push(&mut self, toast: Toast) {
+        self.waiting.push_back(toast);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.waiting.is_empty() && self.shown.is_empty()
+    }
+
+    fn lifetime(&self) -> Duration {
+        self.duration + self.fade * 2
+    }
+
+    /// 移除已经消失的提示，把排队的提示补上来
+    pub fn tick(&mut self, now: Instant) {
+        let lifetime = self.lifetime();
+        self.shown.retain(|toast| {
+            toast
+                .shown_at
+                .is_some_and(|shown_at| now.saturating_duration_since(shown_at) < lifetime)
+        });
+        while self.shown.len() < self.max_visible {
+            let Some(mut toast) = self.waiting.pop_front() else {
+                break;
+            };
+            toast.shown_at = Some(now);
+            self.shown.push(toast);
+        }
+    }
+
+    /// 正在显示的提示和它们的不透明度
+    pub fn visible(&self, now: Instant) -> impl Iterator<Item = VisibleToast<'_>> {
+        self.shown
+            .iter()
+            .enumerate()
+            .filter_map(move |(slot, toast)| {
+                let age = now.saturating_duration_since(toast.shown_at?);
+                let fade = self.fade.as_secs_f32();
+                let opacity = if fade <= 0.0 {
+                    1.0
+                } else if age < self.fade {
+                    age.as_secs_f32() / fade
+                } else if age < self.fade + self.duration {
+                    1.0
+                } else {
+                    1.0 - (age - self.fade - self.duration).as_secs_f32() / fade
+                };
+                (age < self.lifetime()).then_some(VisibleToast {
+                    toast,
+                    slot,
+                    opacity: opacity.clamp(0.0, 1.0),
+                })
+            })
+    }
+}
+
+impl Default for ToastQueue {
+    fn default() -> Self {
+        Self::new(3, Duration::from_secs(3), Duration::from_millis(250))
+    }
+}
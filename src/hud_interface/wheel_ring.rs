@@ -0,0 +1,76 @@
+use std::time::{Duration, Instant};
+
+use super::osd::Osd;
+
+/// 滚轮转动一格, 由执行滚轮动作的 [`crate::event_dispatcher::actions::ActionRunner`] 发送
+#[derive(Debug, Clone, PartialEq)]
+pub struct WheelTurn {
+    /// 动作的图标、描述和执行后的值(比如音量)
+    pub osd: Osd,
+    pub clockwise: bool,
+}
+
+/// 连续转动中的滚轮
+#[derive(Debug, Clone)]
+pub struct Spin {
+    pub osd: Osd,
+    /// 这次连续转动了多少格, 顺时针为正
+    pub steps: i32,
+    turned_at: Instant,
+}
+
+/// 滚轮转盘, 转动滚轮时出现，显示当前的动作和值
+///
+/// 停止转动 `linger` 之后淡出, 淡出之前继续转动算作同一次转动
+#[derive(Debug, Clone)]
+pub struct WheelRing {
+    current: Option<Spin>,
+    linger: Duration,
+    fade: Duration,
+}
+
+impl WheelRing {
+    pub fn new(linger: Duration, fade: Duration) -> Self {
+        Self {
+            current: None,
+            linger,
+            fade,
+        }
+    }
+
+    pub fn turn(&mut self, turn: WheelTurn, now: Instant) {
+        let step = if turn.clockwise { 1 } else { -1 };
+        let steps = match &self.current {
+            Some(spin) if self.opacity(spin, now) > 0.0 => spin.steps + step,
+            _ => step,
+        };
+        self.current = Some(Spin {
+            osd: turn.osd,
+            steps,
+            turned_at: now,
+        });
+    }
+
+    fn opacity(&self, spin: &Spin, now: Instant) -> f32 {
+        let elapsed = now.saturating_duration_since(spin.turned_at);
+        if elapsed <= self.linger {
+            return 1.0;
+        }
+        let fading =
+            (elapsed - self.linger).as_secs_f32() / self.fade.as_secs_f32().max(f32::EPSILON);
+        (1.0 - fading).max(0.0)
+    }
+
+    /// 需要显示的转动和不透明度
+    pub fn visible(&self, now: Instant) -> Option<(&Spin, f32)> {
+        let spin = self.current.as_ref()?;
+        let opacity = self.opacity(spin, now);
+        (opacity > 0.0).then_some((spin, opacity))
+    }
+}
+
+impl Default for WheelRing {
+    fn default() -> Self {
+        Self::new(Duration::from_millis(800), Duration::from_millis(300))
+    }
+}
@@ -0,0 +1,391 @@
+//! 直接通过 GATT 访问蓝牙数位板
+//!
+//! bluez 的 HoG (HID over GATT) 插件会把数位板变成 `/dev/input` 下的绝对鼠标，
+//! 这里绕过它: 断开设备的 HID profile，自己订阅 HID Report 特征值，
+//! 然后交给 `tablet_driver` 中同一套解析器处理.
+//!
+//! bluez 会隐藏已被 HoG 插件占用的服务，所以 `bluetoothd` 需要以
+//! `--noplugin=hog` 启动(或在 `input.conf` 中设置 `UserspaceHID=true`)
+//!
+//! 蓝牙没有 hidraw 那样的 uevent, [`BleWatcher`] 定期扫描已配对的数位板, 连接上的数位板
+//! 和 USB 一样广播 [`DeviceEvent`]
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bluer::{
+    Adapter, Address, Device, Session, Uuid,
+    gatt::remote::{Characteristic, Service},
+};
+use futures::{StreamExt, stream::SelectAll};
+use tokio::{sync::broadcast, task::JoinHandle};
+use tracing::{Instrument, debug, error, info, info_span, warn};
+
+use super::{
+    hotplug::{self, ConnectedDevice, DEVICE_EVENT_QUEUE_LEN, DeviceEvent},
+    identity::{Fingerprint, IdentityRegistry},
+    transport::Transport,
+};
+
+use crate::{
+    error::ErrorReport,
+    event_dispatcher::api::ApiServer,
+    event_model::{
+        stamp::{EventSequence, monotonic_micros},
+        tablet::TabletId,
+    },
+    event_router::{EventSender, InputEvent},
+    hud_interface::HudSender,
+    tablet_driver::{
+        self, ReportParser,
+        spec::DeviceSpec,
+        stats::{DeviceStats, StatsTracker},
+    },
+};
+
+/// HID 服务
+const HID_SERVICE: Uuid = Uuid::from_u128(0x0000_1812_0000_1000_8000_0080_5f9b_34fb);
+/// HID Report 特征值
+const HID_REPORT: Uuid = Uuid::from_u128(0x0000_2a4d_0000_1000_8000_0080_5f9b_34fb);
+/// Report Reference 描述符, 内容为 `[report id, report type]`
+const REPORT_REFERENCE: Uuid = Uuid::from_u128(0x0000_2908_0000_1000_8000_0080_5f9b_34fb);
+/// Report Type: Input
+const REPORT_TYPE_INPUT: u8 = 0x01;
+/// 两次扫描已配对数位板的间隔
+const SCAN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// 已配对的蓝牙数位板
+pub struct BleTablet {
+    pub device: Device,
+    pub name: String,
+    pub vendor_id: u16,
+    pub product_id: u16,
+}
+
+/// `蓝牙(BLE)` 后端
+pub struct BleBackend {
+    _session: Session,
+    adapter: Adapter,
+    specs: Vec<DeviceSpec>,
+}
+
+impl BleBackend {
+    /// `specs` 是除内置设备外，额外支持的设备
+    pub async fn new(specs: Vec<DeviceSpec>) -> anyhow::Result<Self> {
+        let session = Session::new().await?;
+        let adapter = session.default_adapter().await?;
+        adapter.set_powered(true).await?;
+        let mut all = tablet_driver::spec::builtin_specs();
+        all.extend(specs);
+        Ok(Self {
+            _session: session,
+            adapter,
+            specs: all,
+        })
+    }
+
+    /// 查找已配对的、受支持的蓝牙数位板
+    pub async fn scan(&self) -> anyhow::Result<Vec<BleTablet>> {
+        let mut tablets = Vec::new();
+        for address in self.adapter.device_addresses().await? {
+            let device = self.adapter.device(address)?;
+            if !device.is_paired().await.unwrap_or(false) {
+                continue;
+            }
+            let has_hid = device
+                .uuids()
+                .await?
+                .is_some_and(|uuids| uuids.contains(&HID_SERVICE));
+            let Some(modalias) = device.modalias().await? else {
+                continue;
+            };
+            let (vendor_id, product_id) = (modalias.vendor as u16, modalias.product as u16);
+            if !has_hid || tablet_driver::parser_for(vendor_id, product_id, &self.specs).is_none() {
+                continue;
+            }
+            tablets.push(BleTablet {
+                name: device.name().await?.unwrap_or_else(|| address.to_string()),
+                device,
+                vendor_id,
+                product_id,
+            });
+        }
+        Ok(tablets)
+    }
+
+    /// 连接数位板并独占它，把解析出的事件发往 `event_router`, 每个报告记入 `stats`
+    ///
+    /// 连接断开或者路由器关闭时返回
+    pub async fn run(
+        &self,
+        address: Address,
+        tablet: TabletId,
+        events: EventSender,
+        mut stats: StatsTracker,
+    ) -> anyhow::Result<()> {
+        let device = self.adapter.device(address)?;
+        let modalias = device
+            .modalias()
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("{address}: 无法获取 VID/PID"))?;
+        let mut parser =
+            tablet_driver::parser_for(modalias.vendor as u16, modalias.product as u16, &self.specs)
+                .ok_or_else(|| anyhow::anyhow!("{address}: 不支持的设备"))?;
+
+        if !device.is_connected().await? {
+            device.connect().await?;
+        }
+        // 让 bluez 释放它创建的输入设备，之后由我们独占
+        if let Err(e) = device.disconnect_profile(&HID_SERVICE).await {
+            warn!("{address}: 无法断开 HID profile: {e}");
+        }
+
+        let service = find_hid_service(&device).await?.ok_or_else(|| {
+            anyhow::anyhow!("{address}: 找不到 HID 服务, bluetoothd 是否禁用了 hog 插件?")
+        })?;
+        let mut reports = SelectAll::new();
+        for characteristic in service.characteristics().await? {
+            if characteristic.uuid().await? != HID_REPORT {
+                continue;
+            }
+            let Some(report_id) = input_report_id(&characteristic).await? else {
+                continue;
+            };
+            let stream = characteristic.notify().await?;
+            // GATT 通知里没有 report id，补上它，让解析器看到和 hidraw 一样的报告.
+            // 描述符里的 id 为 0 表示设备不使用编号的报告, hidraw 也不会有这个字节
+            reports.push(Box::pin(stream.map(move |mut report| {
+                if report_id != 0 {
+                    report.insert(0, report_id);
+                }
+                (monotonic_micros(), report)
+            })));
+        }
+        anyhow::ensure!(!reports.is_empty(), "{address}: 没有可订阅的输入报告");
+
+        let mut sequence = EventSequence::new();
+        while let Some((timestamp, report)) = reports.next().await {
+            let parsed = parser.parse(&report);
+            stats.record(timestamp, &parsed);
+            for event in parsed {
+                let event = InputEvent {
+                    tablet,
+                    transport: Transport::Bluetooth,
+                    event,
+                    stamp: sequence.stamp(timestamp),
+                };
+                if events.send(event).await.is_err() {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 设备标识，用于分配 [`TabletId`]
+    pub fn fingerprint(&self, tablet: &BleTablet) -> Fingerprint {
+        Fingerprint {
+            transport: Transport::Bluetooth,
+            vendor_id: tablet.vendor_id,
+            product_id: tablet.product_id,
+            name: tablet.name.clone(),
+            serial: None,
+            mac: Some(tablet.device.address().to_string()),
+        }
+    }
+
+    /// 事件解析器，供调用者查询设备能力
+    pub fn parser_for(&self, tablet: &BleTablet) -> Option<Box<dyn ReportParser>> {
+        tablet_driver::parser_for(tablet.vendor_id, tablet.product_id, &self.specs)
+    }
+}
+
+async fn find_hid_service(device: &Device) -> anyhow::Result<Option<Service>> {
+    for service in device.services().await? {
+        if service.uuid().await? == HID_SERVICE {
+            return Ok(Some(service));
+        }
+    }
+    Ok(None)
+}
+
+/// 读取 Report Reference 描述符, 不是输入报告时返回 `None`
+async fn input_report_id(characteristic: &Characteristic) -> anyhow::Result<Option<u8>> {
+    for descriptor in characteristic.descriptors().await? {
+        if descriptor.uuid().await? != REPORT_REFERENCE {
+            continue;
+        }
+        let value = descriptor.read().await?;
+        return Ok(match value.as_slice() {
+            [report_id, REPORT_TYPE_INPUT, ..] => Some(*report_id),
+            _ => None,
+        });
+    }
+    Ok(None)
+}
+
+/// 正在运行的驱动
+struct Running {
+    device: ConnectedDevice,
+    task: JoinHandle<()>,
+}
+
+/// 定期扫描已配对的蓝牙数位板, 为每块连接上的数位板运行驱动
+pub struct BleWatcher {
+    ble: Arc<BleBackend>,
+    identities: Arc<Mutex<IdentityRegistry>>,
+    events: EventSender,
+    running: HashMap<Address, Running>,
+    lifecycle: broadcast::Sender<DeviceEvent>,
+    hud: Option<HudSender>,
+    api: Option<ApiServer>,
+    stats: DeviceStats,
+}
+
+impl BleWatcher {
+    /// 驱动解析出的事件发往 `events`
+    pub fn new(
+        ble: BleBackend,
+        identities: Arc<Mutex<IdentityRegistry>>,
+        events: EventSender,
+    ) -> Self {
+        Self {
+            ble: Arc::new(ble),
+            identities,
+            events,
+            running: HashMap::new(),
+            lifecycle: broadcast::channel(DEVICE_EVENT_QUEUE_LEN).0,
+            hud: None,
+            api: None,
+            stats: DeviceStats::new(),
+        }
+    }
+
+    /// 接入和断开时在 HUD 上提示
+    pub fn set_hud(&mut self, hud: HudSender) {
+        self.hud = Some(hud);
+    }
+
+    /// 驱动出错时通知 `tabletd API` 客户端
+    pub fn set_api(&mut self, api: ApiServer) {
+        self.api = Some(api);
+    }
+
+    /// 驱动的报告统计写入 `stats`
+    pub fn set_stats(&mut self, stats: DeviceStats) {
+        self.stats = stats;
+    }
+
+    /// 生命周期事件改为发往 `lifecycle`, 和 USB 的 [`hotplug::HotplugWatcher`] 共用
+    pub fn set_lifecycle(&mut self, lifecycle: broadcast::Sender<DeviceEvent>) {
+        self.lifecycle = lifecycle;
+    }
+
+    /// 每隔 [`SCAN_INTERVAL`] 扫描一次, BlueZ 出错时返回
+    pub async fn run(mut self) -> anyhow::Result<()> {
+        loop {
+            self.rescan().await?;
+            tokio::time::sleep(SCAN_INTERVAL).await;
+        }
+    }
+
+    /// 启动新连接上的数位板, 同时清理连接已经断开的驱动
+    async fn rescan(&mut self) -> anyhow::Result<()> {
+        let finished: Vec<_> = self
+            .running
+            .iter()
+            .filter(|(_, running)| running.task.is_finished())
+            .map(|(address, _)| *address)
+            .collect();
+        for address in finished {
+            self.stop(address);
+        }
+
+        for tablet in self.ble.scan().await? {
+            let address = tablet.device.address();
+            if self.running.contains_key(&address) {
+                continue;
+            }
+            // 关机或者不在附近的数位板连接不上, 下次扫描时再试
+            if !tablet.device.is_connected().await.unwrap_or(false)
+                && let Err(e) = tablet.device.connect().await
+            {
+                debug!("无法连接 {} ({address}): {e}", tablet.name);
+                continue;
+            }
+            self.start(tablet);
+        }
+        Ok(())
+    }
+
+    fn start(&mut self, tablet: BleTablet) {
+        let Some(parser) = self.ble.parser_for(&tablet) else {
+            return;
+        };
+        let address = tablet.device.address();
+        let fingerprint = self.ble.fingerprint(&tablet);
+        let id = self.identities.lock().unwrap().resolve(&fingerprint);
+        let device = ConnectedDevice {
+            tablet: id,
+            name: parser.name().to_string(),
+            transport: Transport::Bluetooth,
+            capabilities: parser.capabilities(),
+            path: PathBuf::new(),
+        };
+        info!("{} ({}) 已接入: {address}", device.name, device.transport);
+        // 先通知路由器，驱动发出的第一个事件不会被当作未知连接丢弃
+        self.announce(DeviceEvent::Connected(device.clone()));
+
+        let (ble, events, stats) = (
+            self.ble.clone(),
+            self.events.clone(),
+            self.stats.track(id, &device.name),
+        );
+        let api = self.api.clone();
+        let span = info_span!("device", name = %device.name, tablet = %id);
+        let task = tokio::spawn(
+            async move {
+                let Err(e) = ble.run(address, id, events, stats).await else {
+                    return;
+                };
+                let report = ErrorReport::from_anyhow(e).with_tablet(id);
+                error!("驱动出错: {}", report.message);
+                if let Some(api) = api {
+                    api.report_error(report);
+                }
+            }
+            .instrument(span),
+        );
+        self.running.insert(address, Running { device, task });
+    }
+
+    fn stop(&mut self, address: Address) {
+        let Some(running) = self.running.remove(&address) else {
+            return;
+        };
+        running.task.abort();
+        info!(
+            "{} ({}) 已断开: {address}",
+            running.device.name, running.device.transport
+        );
+        self.announce(DeviceEvent::Disconnected(running.device));
+    }
+
+    fn announce(&self, event: DeviceEvent) {
+        hotplug::announce(event, &self.lifecycle, self.hud.as_ref());
+    }
+}
+
+impl Drop for BleWatcher {
+    /// 停止所有驱动, 并把它们的数位板当作已断开
+    fn drop(&mut self) {
+        let addresses: Vec<_> = self.running.keys().copied().collect();
+        for address in addresses {
+            self.stop(address);
+        }
+    }
+}
@@ -0,0 +1,171 @@
+//! 独占数位板的 evdev 节点
+//!
+//! 内核的 HID 驱动也会为数位板创建 evdev 节点, libinput 会用它们再创建一个指针, 和 tabletd 的
+//! 虚拟设备同时移动光标. 接管数位板后对同一 USB 设备的所有 evdev 节点(包括 UC-Logic 的鼠标、
+//! 键盘模拟接口)发出 `EVIOCGRAB`, 其他程序就收不到这些节点的事件.
+//!
+//! 独占跟随打开的文件: [`Grab`] 释放时解除; 守护进程崩溃时内核关闭文件, 独占同样会解除,
+//! 不会留下无法使用的数位板
+//!
+//! ```toml
+//! [daemon]
+//! grab = true
+//! ```
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io,
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use tracing::{debug, info, warn};
+
+use crate::event_model::tablet::TabletId;
+
+use super::{hidraw::HidrawNode, privilege};
+
+nix::ioctl_write_int!(eviocgrab, b'E', 0x90);
+
+/// 在 sysfs 中查找 evdev 节点时最多进入的目录层数
+const MAX_DEPTH: usize = 6;
+
+/// 和 hidraw 节点属于同一设备的 evdev 节点
+///
+/// USB 设备包括所有接口上的节点, 其他总线只包括这个 HID 设备的节点
+pub fn evdev_nodes(node: &HidrawNode) -> Vec<PathBuf> {
+    let root = match node.usb_interface() {
+        // HID 设备目录的上一级是 USB 接口, 再上一级是 USB 设备
+        Some(_) => node.sys_path.parent().and_then(Path::parent),
+        None => Some(node.sys_path.as_path()),
+    };
+    let mut nodes = Vec::new();
+    if let Some(root) = root {
+        collect_event_nodes(root, 0, &mut nodes);
+    }
+    nodes.sort();
+    nodes.dedup();
+    nodes
+}
+
+/// 只进入真正的目录, sysfs 中的符号链接(`subsystem`、`driver` 等)会形成环
+fn collect_event_nodes(dir: &Path, depth: usize, nodes: &mut Vec<PathBuf>) {
+    if depth > MAX_DEPTH {
+        return;
+    }
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        if !entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+            continue;
+        }
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        let in_input = dir
+            .file_name()
+            .is_some_and(|parent| parent.to_string_lossy().starts_with("input"));
+        if in_input && name.starts_with("event") {
+            nodes.push(PathBuf::from("/dev/input").join(name.as_ref()));
+        } else {
+            collect_event_nodes(&entry.path(), depth + 1, nodes);
+        }
+    }
+}
+
+/// 一个已经独占的 evdev 节点, 释放时解除独占
+#[derive(Debug)]
+pub struct Grab {
+    path: PathBuf,
+    file: File,
+}
+
+impl Grab {
+    /// 打开并独占节点, 没有权限时由特权辅助进程打开
+    pub fn acquire(path: &Path) -> io::Result<Self> {
+        let file = privilege::open_evdev(path)?;
+        // SAFETY: EVIOCGRAB 只读取整数参数
+        unsafe { eviocgrab(file.as_raw_fd(), 1) }?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Grab {
+    fn drop(&mut self) {
+        // 关闭文件也会解除独占, 这里显式解除只是为了在日志中留下失败的记录
+        // SAFETY: 同上
+        if let Err(e) = unsafe { eviocgrab(self.file.as_raw_fd(), 0) } {
+            debug!("无法解除 {} 的独占: {e}", self.path.display());
+        }
+    }
+}
+
+/// 记录每块数位板独占的节点, 克隆后共享
+#[derive(Debug, Clone, Default)]
+pub struct GrabManager {
+    grabs: Arc<Mutex<HashMap<TabletId, Vec<Grab>>>>,
+}
+
+impl GrabManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 独占 `node` 所在设备的 evdev 节点, 返回成功独占的数量. 部分节点失败时只记录日志
+    pub fn grab(&self, tablet: TabletId, node: &HidrawNode) -> usize {
+        let mut grabs = Vec::new();
+        for path in evdev_nodes(node) {
+            match Grab::acquire(&path) {
+                Ok(grab) => grabs.push(grab),
+                Err(e) => warn!("无法独占 {}: {e}", path.display()),
+            }
+        }
+        let count = grabs.len();
+        if count > 0 {
+            info!("已独占 {count} 个 evdev 节点");
+        }
+        self.grabs
+            .lock()
+            .unwrap()
+            .entry(tablet)
+            .or_default()
+            .extend(grabs);
+        count
+    }
+
+    /// 数位板断开或者不再由 tabletd 接管时解除独占
+    pub fn release(&self, tablet: TabletId) {
+        if let Some(grabs) = self.grabs.lock().unwrap().remove(&tablet)
+            && !grabs.is_empty()
+        {
+            info!("已解除 {} 个 evdev 节点的独占", grabs.len());
+        }
+    }
+
+    /// 退出时解除所有独占
+    pub fn release_all(&self) {
+        self.grabs.lock().unwrap().clear();
+    }
+
+    /// 每块数位板独占的节点
+    pub fn grabbed(&self) -> Vec<(TabletId, Vec<PathBuf>)> {
+        self.grabs
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(tablet, grabs)| {
+                let paths = grabs.iter().map(|grab| grab.path().to_path_buf()).collect();
+                (*tablet, paths)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,138 @@
+use std::{
+    fs,
+    io::{self, Read},
+    path::PathBuf,
+};
+
+use tokio::sync::mpsc;
+
+use crate::event_model::stamp::monotonic_micros;
+
+use super::privilege;
+
+/// 常见数位板厂商的 USB Vendor ID
+pub const KNOWN_TABLET_VENDORS: &[(u16, &str)] = &[
+    (0x056a, "Wacom"),
+    (0x256c, "Huion"),
+    (0x28bd, "XP-Pen"),
+    (0x5543, "UC-Logic"),
+];
+
+/// 读到报告时的单调时钟时间(微秒)和报告的内容
+pub type TimedReport = (u64, Vec<u8>);
+
+/// 一个 `/dev/hidraw*` 节点及其 HID 信息
+#[derive(Debug, Clone)]
+pub struct HidrawNode {
+    /// 设备节点路径，如 `/dev/hidraw3`
+    pub path: PathBuf,
+    /// sysfs 中对应的 HID 设备目录
+    pub sys_path: PathBuf,
+    pub bus: u16,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub name: String,
+    pub uniq: String,
+}
+
+impl HidrawNode {
+    /// 是否为已知数位板厂商的设备
+    pub fn is_known_tablet(&self) -> bool {
+        KNOWN_TABLET_VENDORS
+            .iter()
+            .any(|(vid, _)| *vid == self.vendor_id)
+    }
+
+    /// USB 设备的接口号
+    ///
+    /// HID 设备目录的上一级是 USB 接口，名字形如 `1-2:1.0`
+    pub fn usb_interface(&self) -> Option<u8> {
+        let interface = self.sys_path.parent()?.file_name()?.to_str()?;
+        let (_, number) = interface.rsplit_once('.')?;
+        number.parse().ok()
+    }
+
+    /// 打开节点，在单独的线程中读取 HID 报告. 没有权限时由特权辅助进程打开, 见 [`privilege`]
+    ///
+    /// hidraw 的读取是阻塞的; 接收端被丢弃后，线程会在下一个报告到达时退出, 读取出错时发送错误后退出.
+    /// 每个报告附带读到它时的单调时钟时间(微秒), 见 [`monotonic_micros`]
+    pub fn spawn_reader(&self) -> io::Result<mpsc::UnboundedReceiver<io::Result<TimedReport>>> {
+        let mut file = privilege::open_hidraw(&self.path)?;
+        let (tx, rx) = mpsc::unbounded_channel();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 256];
+            loop {
+                let report = match file.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(len) => Ok((monotonic_micros(), buf[..len].to_vec())),
+                    Err(e) => {
+                        let _ = tx.send(Err(e));
+                        break;
+                    }
+                };
+                if tx.send(report).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(rx)
+    }
+}
+
+/// 枚举系统中所有 hidraw 节点
+pub fn enumerate() -> Vec<HidrawNode> {
+    let Ok(entries) = fs::read_dir("/sys/class/hidraw") else {
+        return Vec::new();
+    };
+    let mut nodes: Vec<_> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let sys_path = entry.path().join("device");
+            let uevent = fs::read_to_string(sys_path.join("uevent")).ok()?;
+            let mut node = parse_uevent(&uevent)?;
+            node.path = PathBuf::from("/dev").join(entry.file_name());
+            node.sys_path = fs::canonicalize(&sys_path).unwrap_or(sys_path);
+            Some(node)
+        })
+        .collect();
+    nodes.sort_by(|a, b| a.path.cmp(&b.path));
+    nodes
+}
+
+/// 解析 HID 设备的 uevent，例如:
+///
+/// ```text
+/// HID_ID=0003:0000056A:00000374
+/// HID_NAME=Wacom Intuos S Pen
+/// HID_UNIQ=8CH00L1234567
+/// ```
+fn parse_uevent(uevent: &str) -> Option<HidrawNode> {
+    let mut node = HidrawNode {
+        path: PathBuf::new(),
+        sys_path: PathBuf::new(),
+        bus: 0,
+        vendor_id: 0,
+        product_id: 0,
+        name: String::new(),
+        uniq: String::new(),
+    };
+    let mut has_id = false;
+    for line in uevent.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        match key {
+            "HID_ID" => {
+                let mut parts = value.split(':');
+                node.bus = u16::from_str_radix(parts.next()?, 16).ok()?;
+                node.vendor_id = u32::from_str_radix(parts.next()?, 16).ok()? as u16;
+                node.product_id = u32::from_str_radix(parts.next()?, 16).ok()? as u16;
+                has_id = true;
+            }
+            "HID_NAME" => node.name = value.to_string(),
+            "HID_UNIQ" => node.uniq = value.to_string(),
+            _ => {}
+        }
+    }
+    has_id.then_some(node)
+}
@@ -0,0 +1,446 @@
+//! USB 数位板的热插拔
+//!
+//! 监听内核的 uevent(netlink `NETLINK_KOBJECT_UEVENT`), hidraw 节点出现时重新扫描
+//! [`UsbBackend`] 并启动新数位板的驱动，节点消失时停止对应的驱动.
+//! 每次接入和断开都广播一个 [`DeviceEvent`], 由路由器、HUD 和 `tabletd API` 使用
+
+use std::{
+    collections::HashMap,
+    os::fd::{AsRawFd, OwnedFd},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Context;
+use nix::sys::socket::{
+    AddressFamily, MsgFlags, NetlinkAddr, SockFlag, SockProtocol, SockType, bind, recv, socket,
+};
+use tokio::{io::unix::AsyncFd, sync::broadcast, task::JoinHandle};
+use tracing::{Instrument, error, info, info_span, warn};
+
+use crate::{
+    error::ErrorReport,
+    event_dispatcher::api::{ApiServer, protocol::TabletInfo},
+    event_model::{capability::DeviceCapabilities, tablet::TabletId},
+    event_router::EventSender,
+    hud_interface::{HudEvent, HudSender, progress::Progress},
+    tablet_driver::{self, ReportParser, stats::DeviceStats},
+};
+
+use super::{
+    grab::GrabManager,
+    hidraw::HidrawNode,
+    identity::{Fingerprint, IdentityRegistry},
+    privilege,
+    transport::Transport,
+    usb::UsbBackend,
+};
+
+/// 内核发出的 uevent 所在的 netlink 组
+const KERNEL_UEVENT_GROUP: u32 = 1;
+/// 生命周期事件队列的长度
+pub(super) const DEVICE_EVENT_QUEUE_LEN: usize = 64;
+/// 内核的 uevent 先于 udev 设置好节点权限到达，打开失败时重试
+const OPEN_RETRIES: u32 = 10;
+const OPEN_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// 一次连接的数位板
+#[derive(Debug, Clone)]
+pub struct ConnectedDevice {
+    pub tablet: TabletId,
+    pub name: String,
+    pub transport: Transport,
+    pub capabilities: DeviceCapabilities,
+    /// hidraw 节点, 如 `/dev/hidraw3`. 直接通过 GATT 连接的蓝牙数位板没有节点, 为空
+    pub path: PathBuf,
+}
+
+impl ConnectedDevice {
+    /// `tabletd API` 握手中的数位板信息
+    pub fn info(&self) -> TabletInfo {
+        TabletInfo {
+            id: self.tablet,
+            name: self.name.clone(),
+            capabilities: self.capabilities.clone(),
+        }
+    }
+}
+
+/// 数位板的生命周期事件
+#[derive(Debug, Clone)]
+pub enum DeviceEvent {
+    /// 已接入，驱动已经启动
+    Connected(ConnectedDevice),
+    /// 已断开，驱动已经停止
+    Disconnected(ConnectedDevice),
+}
+
+/// 每块数位板已接入的连接
+///
+/// 同一块数位板可以同时通过 USB 和蓝牙接入, 两个后端按连接各自发出 [`DeviceEvent`],
+/// 由路由器中的 [`super::transport::TransportArbiter`] 选出使用的连接. 虚拟数位板、控制接口这些
+/// 按数位板计数的订阅者用它判断是第一个连接接入, 还是最后一个连接断开
+#[derive(Debug, Default)]
+pub struct TabletConnections {
+    tablets: HashMap<TabletId, Vec<ConnectedDevice>>,
+}
+
+impl TabletConnections {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 记下接入的连接, 是这块数位板的第一个连接时返回 `true`
+    pub fn connect(&mut self, device: ConnectedDevice) -> bool {
+        let connections = self.tablets.entry(device.tablet).or_default();
+        let first = connections.is_empty();
+        connections.retain(|connected| connected.transport != device.transport);
+        connections.push(device);
+        first
+    }
+
+    /// 记下断开的连接, 这块数位板的所有连接都断开时返回 `true`
+    pub fn disconnect(&mut self, device: &ConnectedDevice) -> bool {
+        let Some(connections) = self.tablets.get_mut(&device.tablet) else {
+            return false;
+        };
+        connections.retain(|connected| connected.transport != device.transport);
+        if !connections.is_empty() {
+            return false;
+        }
+        self.tablets.remove(&device.tablet);
+        true
+    }
+
+    /// 数位板还接着的任意一个连接
+    pub fn get(&self, tablet: TabletId) -> Option<&ConnectedDevice> {
+        self.tablets.get(&tablet)?.first()
+    }
+
+    /// 所有数位板的所有连接
+    pub fn iter(&self) -> impl Iterator<Item = &ConnectedDevice> {
+        self.tablets.values().flatten()
+    }
+}
+
+/// 一个 uevent 中用到的字段
+#[derive(Debug, Default, PartialEq)]
+struct Uevent {
+    action: String,
+    subsystem: String,
+    /// `/dev` 下的节点名，如 `hidraw3`
+    devname: Option<String>,
+}
+
+/// 解析内核 uevent: `ACTION@DEVPATH\0KEY=VALUE\0...`
+fn parse_uevent(message: &[u8]) -> Option<Uevent> {
+    let mut fields = message.split(|byte| *byte == 0);
+    // 第一段是摘要, 同样的信息在后面的字段中还有
+    if !fields.next()?.contains(&b'@') {
+        return None;
+    }
+    let mut uevent = Uevent::default();
+    for field in fields {
+        let Some((key, value)) = std::str::from_utf8(field).ok()?.split_once('=') else {
+            continue;
+        };
+        match key {
+            "ACTION" => uevent.action = value.to_string(),
+            "SUBSYSTEM" => uevent.subsystem = value.to_string(),
+            "DEVNAME" => uevent.devname = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    Some(uevent)
+}
+
+/// 订阅内核 uevent 的 netlink socket
+struct UeventSocket {
+    fd: AsyncFd<OwnedFd>,
+}
+
+impl UeventSocket {
+    fn open() -> anyhow::Result<Self> {
+        let fd = socket(
+            AddressFamily::Netlink,
+            SockType::Datagram,
+            SockFlag::SOCK_CLOEXEC | SockFlag::SOCK_NONBLOCK,
+            SockProtocol::NetlinkKObjectUEvent,
+        )
+        .context("无法创建 uevent socket")?;
+        bind(
+            fd.as_raw_fd(),
+            &NetlinkAddr::new(std::process::id(), KERNEL_UEVENT_GROUP),
+        )
+        .context("无法订阅内核 uevent")?;
+        Ok(Self {
+            fd: AsyncFd::new(fd)?,
+        })
+    }
+
+    async fn next(&self) -> anyhow::Result<Uevent> {
+        let mut buf = vec![0u8; 8192];
+        loop {
+            let mut guard = self.fd.readable().await?;
+            let received = guard.try_io(|fd| {
+                recv(fd.as_raw_fd(), &mut buf, MsgFlags::empty()).map_err(std::io::Error::from)
+            });
+            let Ok(len) = received else {
+                continue;
+            };
+            if let Some(uevent) = parse_uevent(&buf[..len?]) {
+                return Ok(uevent);
+            }
+        }
+    }
+}
+
+/// 正在运行的驱动
+struct Running {
+    device: ConnectedDevice,
+    task: JoinHandle<()>,
+}
+
+/// 监听 USB 数位板的接入和断开，为每块数位板运行驱动
+pub struct HotplugWatcher {
+    usb: UsbBackend,
+    identities: Arc<Mutex<IdentityRegistry>>,
+    events: EventSender,
+    /// 以 hidraw 节点为键
+    running: HashMap<PathBuf, Running>,
+    lifecycle: broadcast::Sender<DeviceEvent>,
+    hud: Option<HudSender>,
+    api: Option<ApiServer>,
+    stats: DeviceStats,
+    grabs: Option<GrabManager>,
+}
+
+impl HotplugWatcher {
+    /// 驱动解析出的事件发往 `events`
+    pub fn new(
+        usb: UsbBackend,
+        identities: Arc<Mutex<IdentityRegistry>>,
+        events: EventSender,
+    ) -> Self {
+        Self {
+            usb,
+            identities,
+            events,
+            running: HashMap::new(),
+            lifecycle: broadcast::channel(DEVICE_EVENT_QUEUE_LEN).0,
+            hud: None,
+            api: None,
+            stats: DeviceStats::new(),
+            grabs: None,
+        }
+    }
+
+    /// 接入和断开时在 HUD 上提示
+    pub fn set_hud(&mut self, hud: HudSender) {
+        self.hud = Some(hud);
+    }
+
+    /// 驱动出错时通知 `tabletd API` 客户端
+    pub fn set_api(&mut self, api: ApiServer) {
+        self.api = Some(api);
+    }
+
+    /// 驱动的报告统计写入 `stats`, 重新创建 watcher 后仍然累计重新启动的次数
+    pub fn set_stats(&mut self, stats: DeviceStats) {
+        self.stats = stats;
+    }
+
+    /// 接入时独占数位板的 evdev 节点, 断开时解除
+    pub fn set_grabs(&mut self, grabs: GrabManager) {
+        self.grabs = Some(grabs);
+    }
+
+    /// 生命周期事件改为发往 `lifecycle`, 重新创建 watcher 后订阅者不需要重新订阅
+    pub fn set_lifecycle(&mut self, lifecycle: broadcast::Sender<DeviceEvent>) {
+        self.lifecycle = lifecycle;
+    }
+
+    /// 订阅生命周期事件, 例如交给 [`crate::event_router::Router::follow_devices`]
+    ///
+    /// 应该在 [`HotplugWatcher::run`] 之前订阅，否则会错过已经连接的数位板
+    pub fn subscribe(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.lifecycle.subscribe()
+    }
+
+    /// 启动已经连接的数位板，然后跟随热插拔, uevent socket 出错时返回
+    pub async fn run(mut self) -> anyhow::Result<()> {
+        // 先订阅再扫描，扫描期间接入的设备不会漏掉
+        let socket = UeventSocket::open()?;
+        self.rescan().await;
+        loop {
+            let uevent = socket.next().await?;
+            if uevent.subsystem != "hidraw" {
+                continue;
+            }
+            match uevent.action.as_str() {
+                "add" => self.rescan().await,
+                "remove" => {
+                    if let Some(name) = uevent.devname {
+                        self.stop(&PathBuf::from("/dev").join(name));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// 启动所有还没有运行驱动的数位板, 同时清理已经自己退出的驱动
+    async fn rescan(&mut self) {
+        let finished: Vec<_> = self
+            .running
+            .iter()
+            .filter(|(_, running)| running.task.is_finished())
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in finished {
+            self.stop(&path);
+        }
+
+        // 已经在运行的设备不再探测, UC-Logic 设备探测时会重新切换模式
+        for node in self.usb.nodes() {
+            if self.running.contains_key(&node.path) {
+                continue;
+            }
+            // UC-Logic 设备探测时要读取固件信息, 可能需要几秒
+            let progress = Progress::start(self.hud.as_ref(), "正在初始化数位板");
+            progress.set(None, format!("正在读取 {} 的设备信息", node.path.display()));
+            if let Some(parser) = self.usb.probe(&node) {
+                self.start(node, parser, progress).await;
+            }
+        }
+    }
+
+    async fn start(&mut self, node: HidrawNode, parser: Box<dyn ReportParser>, progress: Progress) {
+        let fingerprint = Fingerprint::from_hidraw(&node);
+        let tablet = self.identities.lock().unwrap().resolve(&fingerprint);
+        let device = ConnectedDevice {
+            tablet,
+            name: parser.name().to_string(),
+            transport: fingerprint.transport,
+            capabilities: parser.capabilities(),
+            path: node.path.clone(),
+        };
+
+        // 节点刚出现时 udev 可能还没有设置好权限, 最后一次才交给特权辅助进程
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let opened = if attempt < OPEN_RETRIES {
+                std::fs::File::open(&node.path)
+            } else {
+                privilege::open_hidraw(&node.path)
+            };
+            let Err(e) = opened else {
+                break;
+            };
+            if attempt >= OPEN_RETRIES {
+                warn!("无法打开 {}: {e}", node.path.display());
+                progress.fail(format!("无法打开 {}: {e}", node.path.display()));
+                return;
+            }
+            progress.set(
+                Some(attempt as f32 / OPEN_RETRIES as f32),
+                format!("等待 {} 就绪", device.name),
+            );
+            tokio::time::sleep(OPEN_RETRY_DELAY).await;
+        }
+        progress.finish(format!("{} ({})", device.name, device.transport));
+
+        info!(
+            "{} ({}) 已接入: {}",
+            device.name,
+            device.transport,
+            node.path.display()
+        );
+        // 先通知路由器，驱动发出的第一个事件不会被当作未知连接丢弃
+        self.announce(DeviceEvent::Connected(device.clone()));
+        let driver = tablet_driver::run(
+            node.clone(),
+            parser,
+            tablet,
+            self.events.clone(),
+            self.stats.track(tablet, &device.name),
+        );
+        let span = info_span!("device", name = %device.name, %tablet);
+        if let Some(grabs) = &self.grabs {
+            span.in_scope(|| grabs.grab(tablet, &node));
+        }
+        let api = self.api.clone();
+        let task = tokio::spawn(
+            async move {
+                let Err(e) = driver.await else {
+                    return;
+                };
+                let report = ErrorReport::from_anyhow(e.into()).with_tablet(tablet);
+                error!("驱动出错: {}", report.message);
+                if let Some(api) = api {
+                    api.report_error(report);
+                }
+            }
+            .instrument(span),
+        );
+        self.running.insert(node.path, Running { device, task });
+    }
+
+    fn stop(&mut self, path: &Path) {
+        let Some(running) = self.running.remove(path) else {
+            return;
+        };
+        running.task.abort();
+        if let Some(grabs) = &self.grabs {
+            grabs.release(running.device.tablet);
+        }
+        info!(
+            "{} ({}) 已断开: {}",
+            running.device.name,
+            running.device.transport,
+            path.display()
+        );
+        self.announce(DeviceEvent::Disconnected(running.device));
+    }
+
+    fn announce(&self, event: DeviceEvent) {
+        announce(event, &self.lifecycle, self.hud.as_ref());
+    }
+}
+
+/// 在 HUD 上提示接入和断开, 然后广播生命周期事件
+///
+/// `tabletd API` 按数位板而不是按连接通知客户端, 由订阅者用 [`TabletConnections`] 判断
+pub(super) fn announce(
+    event: DeviceEvent,
+    lifecycle: &broadcast::Sender<DeviceEvent>,
+    hud: Option<&HudSender>,
+) {
+    if let Some(hud) = hud {
+        let hud_event = match &event {
+            DeviceEvent::Connected(device) => HudEvent::TabletConnected {
+                name: device.name.clone(),
+                transport: device.transport,
+            },
+            DeviceEvent::Disconnected(device) => HudEvent::TabletDisconnected {
+                name: device.name.clone(),
+                transport: device.transport,
+            },
+        };
+        let _ = hud.send(hud_event);
+    }
+    // 没有订阅者时发送会失败，这没关系
+    let _ = lifecycle.send(event);
+}
+
+impl Drop for HotplugWatcher {
+    /// 停止所有驱动, 并把它们的数位板当作已断开
+    fn drop(&mut self) {
+        let paths: Vec<_> = self.running.keys().cloned().collect();
+        for path in paths {
+            self.stop(&path);
+        }
+    }
+}
@@ -0,0 +1,302 @@
+//! 数位板的唯一 ID
+//!
+//! 同一块数位板可能通过 USB 和蓝牙同时连接，两边看到的标识不同:
+//! USB 上是序列号，蓝牙上是 MAC 地址. 这里为每块数位板记录它的所有标识，
+//! 分配一个不随连接方式和重启变化的 [`TabletId`]
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::event_model::tablet::TabletId;
+
+use super::{hidraw::HidrawNode, transport::Transport};
+
+/// 一次连接中能看到的设备标识
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub transport: Transport,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub name: String,
+    /// USB 序列号
+    pub serial: Option<String>,
+    /// 蓝牙地址
+    pub mac: Option<String>,
+}
+
+/// HID 总线类型: 蓝牙
+const BUS_BLUETOOTH: u16 = 0x0005;
+
+impl Fingerprint {
+    /// 从 hidraw 节点取得标识, 蓝牙 HID 设备的 `HID_UNIQ` 是 MAC 地址
+    pub fn from_hidraw(node: &HidrawNode) -> Self {
+        let uniq = (!node.uniq.is_empty()).then(|| node.uniq.clone());
+        let bluetooth = node.bus == BUS_BLUETOOTH;
+        Self {
+            transport: if bluetooth {
+                Transport::Bluetooth
+            } else {
+                Transport::Usb
+            },
+            vendor_id: node.vendor_id,
+            product_id: node.product_id,
+            name: node.name.clone(),
+            serial: if bluetooth { None } else { uniq.clone() },
+            mac: if bluetooth { uniq } else { None },
+        }
+    }
+}
+
+/// 统一大小写，去掉分隔符, 这样 `AA:BB:..` 和 `aabb..` 能匹配上
+fn normalize(id: &str) -> String {
+    id.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// 一块数位板的所有已知标识
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceRecord {
+    pub id: TabletId,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub name: String,
+    #[serde(default)]
+    pub serials: Vec<String>,
+    #[serde(default)]
+    pub macs: Vec<String>,
+}
+
+impl DeviceRecord {
+    fn has_serial(&self, serial: &str) -> bool {
+        self.serials
+            .iter()
+            .any(|s| normalize(s) == normalize(serial))
+    }
+
+    fn has_mac(&self, mac: &str) -> bool {
+        self.macs.iter().any(|m| normalize(m) == normalize(mac))
+    }
+
+    /// 有些数位板在 USB 上把蓝牙地址当作序列号报告
+    fn cross_matches(&self, fingerprint: &Fingerprint) -> bool {
+        fingerprint
+            .serial
+            .as_deref()
+            .is_some_and(|s| self.has_mac(s))
+            || fingerprint
+                .mac
+                .as_deref()
+                .is_some_and(|m| self.has_serial(m))
+    }
+
+    /// 记录里还没有这种连接方式的标识
+    fn lacks(&self, transport: Transport) -> bool {
+        match transport {
+            Transport::Usb => self.serials.is_empty(),
+            Transport::Bluetooth => self.macs.is_empty(),
+            // 远程和回放的数位板不与本地设备合并
+            Transport::Remote | Transport::Replay => false,
+        }
+    }
+
+    /// 记下新的标识, 返回是否有变化
+    fn learn(&mut self, fingerprint: &Fingerprint) -> bool {
+        let mut changed = false;
+        if let Some(serial) = &fingerprint.serial
+            && !self.has_serial(serial)
+        {
+            self.serials.push(serial.clone());
+            changed = true;
+        }
+        if let Some(mac) = &fingerprint.mac
+            && !self.has_mac(mac)
+        {
+            self.macs.push(mac.clone());
+            changed = true;
+        }
+        changed
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct RegistryFile {
+    #[serde(default)]
+    device: Vec<DeviceRecord>,
+}
+
+/// 设备标识登记表
+///
+/// 设置了路径时，每次变化都会写回文件
+#[derive(Default)]
+pub struct IdentityRegistry {
+    path: Option<PathBuf>,
+    records: Vec<DeviceRecord>,
+}
+
+impl IdentityRegistry {
+    /// 只在内存中记录
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 默认路径: `$XDG_STATE_HOME/tabletd/devices.toml`
+    pub fn default_path() -> PathBuf {
+        std::env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| Path::new(&home).join(".local").join("state"))
+            })
+            .unwrap_or_else(std::env::temp_dir)
+            .join("tabletd")
+            .join("devices.toml")
+    }
+
+    /// 从文件读取，文件不存在时为空
+    pub fn load(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let path = path.into();
+        let records = match fs::read_to_string(&path) {
+            Ok(text) => toml::from_str::<RegistryFile>(&text)?.device,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path: Some(path),
+            records,
+        })
+    }
+
+    pub fn records(&self) -> &[DeviceRecord] {
+        &self.records
+    }
+
+    pub fn record(&self, id: TabletId) -> Option<&DeviceRecord> {
+        self.records.iter().find(|record| record.id == id)
+    }
+
+    /// 为一次连接找到对应的数位板，没见过的设备会分配新的 ID
+    ///
+    /// 匹配顺序:
+    /// 1. 序列号或 MAC 地址相同
+    /// 2. USB 序列号与已知的 MAC 地址相同(或者反过来)
+    /// 3. VID/PID 相同、还没有这种连接方式的标识的设备只有一个
+    pub fn resolve(&mut self, fingerprint: &Fingerprint) -> TabletId {
+        let index = self
+            .find(|record| {
+                fingerprint
+                    .serial
+                    .as_deref()
+                    .is_some_and(|s| record.has_serial(s))
+                    || fingerprint
+                        .mac
+                        .as_deref()
+                        .is_some_and(|m| record.has_mac(m))
+            })
+            .or_else(|| self.find(|record| record.cross_matches(fingerprint)))
+            .or_else(|| {
+                self.find(|record| {
+                    record.vendor_id == fingerprint.vendor_id
+                        && record.product_id == fingerprint.product_id
+                        && record.lacks(fingerprint.transport)
+                })
+            });
+
+        let id = match index {
+            Some(index) => {
+                let record = &mut self.records[index];
+                if !record.learn(fingerprint) {
+                    return record.id;
+                }
+                record.id
+            }
+            None => {
+                let id = TabletId(self.records.iter().map(|r| r.id.0 + 1).max().unwrap_or(1));
+                let mut record = DeviceRecord {
+                    id,
+                    vendor_id: fingerprint.vendor_id,
+                    product_id: fingerprint.product_id,
+                    name: fingerprint.name.clone(),
+                    serials: Vec::new(),
+                    macs: Vec::new(),
+                };
+                record.learn(fingerprint);
+                self.records.push(record);
+                id
+            }
+        };
+        self.persist();
+        id
+    }
+
+    /// 手动把 `from` 合并到 `into`，用于自动匹配失败的情况
+    pub fn merge(&mut self, from: TabletId, into: TabletId) -> bool {
+        if from == into {
+            return false;
+        }
+        let Some(index) = self.records.iter().position(|r| r.id == from) else {
+            return false;
+        };
+        let Some(target) = self.records.iter().position(|r| r.id == into) else {
+            return false;
+        };
+        let source = self.records[index].clone();
+        let target = &mut self.records[target];
+        for serial in source.serials {
+            if !target.has_serial(&serial) {
+                target.serials.push(serial);
+            }
+        }
+        for mac in source.macs {
+            if !target.has_mac(&mac) {
+                target.macs.push(mac);
+            }
+        }
+        self.records.remove(index);
+        self.persist();
+        true
+    }
+
+    /// 只有唯一匹配时才返回
+    fn find(&self, predicate: impl Fn(&DeviceRecord) -> bool) -> Option<usize> {
+        let mut matches = self
+            .records
+            .iter()
+            .enumerate()
+            .filter(|(_, record)| predicate(record));
+        let (index, _) = matches.next()?;
+        matches.next().is_none().then_some(index)
+    }
+
+    /// 写入读取时的文件, 只在内存中记录时什么都不做
+    pub fn save(&self) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let file = RegistryFile {
+            device: self.records.clone(),
+        };
+        toml::to_string_pretty(&file)
+            .map_err(anyhow::Error::from)
+            .and_then(|text| {
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                fs::write(path, text)?;
+                Ok(())
+            })
+            .with_context(|| format!("无法保存设备列表 {}", path.display()))
+    }
+
+    fn persist(&self) {
+        if let Err(e) = self.save() {
+            warn!("{e:#}");
+        }
+    }
+}
@@ -1,4 +1,20 @@
 /// `蓝牙(BLE)` 后端
-pub mod ble;
+pub mod bluetooth;
+/// 独占数位板的 evdev 节点
+pub mod grab;
+/// `hidraw` 节点枚举
+pub mod hidraw;
+/// USB 数位板的热插拔
+pub mod hotplug;
+/// 数位板唯一 ID 的分配
+pub mod identity;
+/// 设备权限检查和特权辅助进程
+pub mod privilege;
+/// 事件的录制和回放
+pub mod recorder;
+/// 通过 `tabletd API` 接收的远程数位板
+pub mod remote;
+/// 同一设备多种连接方式的去重
+pub mod transport;
 /// `USB` 后端
 pub mod usb;
@@ -0,0 +1,625 @@
+//! 设备权限检查和特权辅助进程
+//!
+//! 读取 hidraw 节点、创建 uinput 设备需要对应设备节点的权限, 数位板节点通常由 `dist/udev/70-tabletd.rules`
+//! 授予当前登录的用户, `/dev/uinput` 默认只有 root 能写. 没有权限时打开设备不再直接失败, 而是交给特权辅助进程
+//! (`tabletd --raw-io-helper`): 辅助进程只负责打开已知数位板的 hidraw、evdev 节点和创建名字以 `tabletd `
+//! 开头、只有数位板事件代码的 uinput 设备, 通过 Unix socket 把文件描述符传回来. 之后的读写都在
+//! 守护进程中进行, 守护进程的其余部分不需要特权.
+//!
+//! 绑定用到的虚拟键盘不由辅助进程创建, 需要用户自己有 `/dev/uinput` 的权限, 见
+//! `dist/udev/71-tabletd-uinput.rules`
+//!
+//! 第一次遇到没有权限的设备时才启动辅助进程: 先连接 systemd 提供的 socket
+//! (`dist/systemd/tabletd-helper.socket`), 连不上时用 pkexec 启动
+//!
+//! ```toml
+//! [daemon.helper]
+//! mode = "auto"  # auto、socket、pkexec、off
+//! socket = "/run/tabletd-helper.sock"
+//! ```
+
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, IoSlice, IoSliceMut, Write},
+    os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::Mutex,
+};
+
+use anyhow::{Context, anyhow, bail};
+use evdev_rs::{
+    AbsInfo, DeviceWrapper, EnableCodeData, InputEvent, TimeVal, UInputDevice, UninitDevice,
+    enums::{EV_ABS, EV_KEY, EV_REL, EventCode, InputProp},
+    util::{event_code_to_int, int_to_event_code},
+};
+use nix::{
+    libc,
+    sys::socket::{
+        self, AddressFamily, ControlMessage, ControlMessageOwned, MsgFlags, SockFlag, SockType,
+        UnixAddr,
+    },
+};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use super::{grab, hidraw};
+
+const UINPUT: &str = "/dev/uinput";
+
+/// 辅助进程创建的 uinput 设备名字必须以此开头
+const UINPUT_PREFIX: &str = "tabletd ";
+
+/// 一个连接最多同时持有的 uinput 设备
+const MAX_UINPUT_DEVICES: usize = 64;
+
+/// 辅助进程创建的 uinput 设备只能启用这些事件代码, 也就是虚拟数位板的笔和快捷键.
+/// 不能用它创建键盘, 否则能连上辅助进程的用户都可以注入按键
+const UINPUT_ALLOWED_CODES: [EventCode; 26] = [
+    EventCode::EV_ABS(EV_ABS::ABS_X),
+    EventCode::EV_ABS(EV_ABS::ABS_Y),
+    EventCode::EV_ABS(EV_ABS::ABS_PRESSURE),
+    EventCode::EV_ABS(EV_ABS::ABS_TILT_X),
+    EventCode::EV_ABS(EV_ABS::ABS_TILT_Y),
+    EventCode::EV_ABS(EV_ABS::ABS_WHEEL),
+    EventCode::EV_REL(EV_REL::REL_WHEEL),
+    EventCode::EV_KEY(EV_KEY::BTN_TOOL_PEN),
+    EventCode::EV_KEY(EV_KEY::BTN_TOOL_RUBBER),
+    EventCode::EV_KEY(EV_KEY::BTN_TOUCH),
+    EventCode::EV_KEY(EV_KEY::BTN_STYLUS),
+    EventCode::EV_KEY(EV_KEY::BTN_STYLUS2),
+    EventCode::EV_KEY(EV_KEY::BTN_0),
+    EventCode::EV_KEY(EV_KEY::BTN_1),
+    EventCode::EV_KEY(EV_KEY::BTN_2),
+    EventCode::EV_KEY(EV_KEY::BTN_3),
+    EventCode::EV_KEY(EV_KEY::BTN_4),
+    EventCode::EV_KEY(EV_KEY::BTN_5),
+    EventCode::EV_KEY(EV_KEY::BTN_6),
+    EventCode::EV_KEY(EV_KEY::BTN_7),
+    EventCode::EV_KEY(EV_KEY::BTN_8),
+    EventCode::EV_KEY(EV_KEY::BTN_9),
+    EventCode::EV_KEY(EV_KEY::BTN_A),
+    EventCode::EV_KEY(EV_KEY::BTN_B),
+    EventCode::EV_KEY(EV_KEY::BTN_C),
+    EventCode::EV_KEY(EV_KEY::BTN_BASE),
+];
+
+/// 一条消息的最大长度
+const MAX_MESSAGE: usize = 64 * 1024;
+
+/// 什么时候使用特权辅助进程
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HelperMode {
+    /// 先连接 systemd socket, 连不上时用 pkexec 启动
+    #[default]
+    Auto,
+    Socket,
+    Pkexec,
+    /// 不使用辅助进程, 没有权限的设备无法使用
+    Off,
+}
+
+/// 配置文件中的 `[daemon.helper]`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HelperConfig {
+    pub mode: HelperMode,
+    /// systemd 监听的 socket
+    pub socket: PathBuf,
+}
+
+impl Default for HelperConfig {
+    fn default() -> Self {
+        Self {
+            mode: HelperMode::default(),
+            socket: PathBuf::from("/run/tabletd-helper.sock"),
+        }
+    }
+}
+
+/// 当前进程能否直接访问原始设备
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Access {
+    /// 没有读取权限的已知数位板 hidraw 节点
+    pub hidraw_denied: Vec<PathBuf>,
+    /// 没有 `/dev/uinput` 的写权限
+    pub uinput_denied: bool,
+}
+
+impl Access {
+    /// 逐个尝试打开设备节点. 节点不存在不算没有权限, 辅助进程也无能为力
+    pub fn check() -> Self {
+        let denied = |result: io::Result<File>| matches!(result, Err(e) if e.kind() == io::ErrorKind::PermissionDenied);
+        Self {
+            hidraw_denied: hidraw::enumerate()
+                .into_iter()
+                .filter(|node| node.is_known_tablet() && denied(File::open(&node.path)))
+                .map(|node| node.path)
+                .collect(),
+            uinput_denied: denied(OpenOptions::new().write(true).open(UINPUT)),
+        }
+    }
+
+    pub fn sufficient(&self) -> bool {
+        self.hidraw_denied.is_empty() && !self.uinput_denied
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.sufficient() {
+            return f.write_str("可以直接访问所有数位板和 uinput");
+        }
+        let mut denied: Vec<_> = self
+            .hidraw_denied
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        if self.uinput_denied {
+            denied.push(UINPUT.to_string());
+        }
+        write!(f, "没有 {} 的权限", denied.join("、"))
+    }
+}
+
+/// 坐标轴的范围
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AbsRange {
+    pub minimum: i32,
+    pub maximum: i32,
+    /// 单位/毫米, 倾斜为单位/弧度
+    pub resolution: i32,
+}
+
+/// 要创建的 uinput 设备, 可以发给辅助进程
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UinputSpec {
+    pub name: String,
+    /// 启用的事件类型和代码, 坐标轴附带范围
+    codes: Vec<(u32, u32, Option<AbsRange>)>,
+    /// 设置 `INPUT_PROP_POINTER`
+    pub pointer: bool,
+}
+
+impl UinputSpec {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn enable(&mut self, code: EventCode, range: Option<AbsRange>) {
+        let (kind, code) = event_code_to_int(&code);
+        self.codes.push((kind, code, range));
+    }
+
+    /// 第一个不在 [`UINPUT_ALLOWED_CODES`] 中的事件代码, 辅助进程不创建这样的设备
+    fn disallowed_code(&self) -> Option<EventCode> {
+        self.codes
+            .iter()
+            .map(|(kind, code, _)| int_to_event_code(*kind, *code))
+            .find(|code| !UINPUT_ALLOWED_CODES.contains(code))
+    }
+
+    /// 用 libevdev 创建设备, 需要 `/dev/uinput` 的写权限
+    fn create(&self) -> anyhow::Result<UInputDevice> {
+        let device = UninitDevice::new().context("无法初始化 libevdev")?;
+        device.set_name(&self.name);
+        for (kind, code, range) in &self.codes {
+            let code = int_to_event_code(*kind, *code);
+            let data = range.map(|range| {
+                EnableCodeData::AbsInfo(AbsInfo {
+                    value: 0,
+                    minimum: range.minimum,
+                    maximum: range.maximum,
+                    fuzz: 0,
+                    flat: 0,
+                    resolution: range.resolution,
+                })
+            });
+            device
+                .enable_event_code(&code, data)
+                .with_context(|| format!("无法启用 {code}"))?;
+        }
+        if self.pointer {
+            device
+                .enable_property(&InputProp::INPUT_PROP_POINTER)
+                .context("无法设置设备属性")?;
+        }
+        Ok(UInputDevice::create_from_device(&device)?)
+    }
+}
+
+/// 创建好的 uinput 设备
+pub enum VirtualDevice {
+    /// 守护进程自己创建的
+    Direct(UInputDevice),
+    /// 辅助进程创建的, 直接向传回来的文件描述符写入 `input_event`
+    Helper {
+        id: u32,
+        file: File,
+        devnode: Option<String>,
+    },
+}
+
+impl VirtualDevice {
+    /// 创建设备, 没有 `/dev/uinput` 的权限时交给辅助进程, 辅助进程只创建数位板设备
+    pub fn create(spec: &UinputSpec) -> anyhow::Result<Self> {
+        let error = match spec.create() {
+            Ok(device) => return Ok(VirtualDevice::Direct(device)),
+            Err(e) => e,
+        };
+        if !is_permission_denied(&error) {
+            return Err(error.context("无法创建 uinput 设备"));
+        }
+        // 辅助进程一定会拒绝, 不必为此启动它
+        if let Some(code) = spec.disallowed_code() {
+            return Err(error.context(format!(
+                "特权辅助进程不创建启用了 {code} 的设备, 需要安装 71-tabletd-uinput.rules"
+            )));
+        }
+        let (reply, fd) = request(Request::CreateUinput(spec.clone()))
+            .map_err(|e| error.context(format!("无法通过特权辅助进程创建 uinput 设备: {e:#}")))?;
+        match (reply, fd) {
+            (Reply::Created { id, devnode }, Some(fd)) => Ok(VirtualDevice::Helper {
+                id,
+                file: File::from(fd),
+                devnode,
+            }),
+            (reply, _) => Err(reply.error().context("特权辅助进程无法创建 uinput 设备")),
+        }
+    }
+
+    /// 虚拟设备的节点, 如 `/dev/input/event20`
+    pub fn devnode(&self) -> Option<&str> {
+        match self {
+            VirtualDevice::Direct(device) => device.devnode(),
+            VirtualDevice::Helper { devnode, .. } => devnode.as_deref(),
+        }
+    }
+
+    pub fn write(&self, code: EventCode, value: i32) -> io::Result<()> {
+        match self {
+            VirtualDevice::Direct(device) => {
+                device.write_event(&InputEvent::new(&TimeVal::new(0, 0), &code, value))
+            }
+            VirtualDevice::Helper { file, .. } => {
+                let (kind, code) = event_code_to_int(&code);
+                // 时间为 0 时由内核填写
+                let event = libc::input_event {
+                    time: libc::timeval {
+                        tv_sec: 0,
+                        tv_usec: 0,
+                    },
+                    type_: kind as u16,
+                    code: code as u16,
+                    value,
+                };
+                // SAFETY: input_event 是没有填充字节的 C 结构
+                let bytes = unsafe {
+                    std::slice::from_raw_parts(
+                        (&event as *const libc::input_event).cast::<u8>(),
+                        size_of::<libc::input_event>(),
+                    )
+                };
+                let mut file: &File = file;
+                file.write_all(bytes)
+            }
+        }
+    }
+}
+
+impl Drop for VirtualDevice {
+    fn drop(&mut self) {
+        // 辅助进程持有设备, 要通知它销毁
+        if let VirtualDevice::Helper { id, .. } = self {
+            let _ = request(Request::Release(*id));
+        }
+    }
+}
+
+/// 打开 hidraw 节点, 没有权限时交给辅助进程
+pub fn open_hidraw(path: &Path) -> io::Result<File> {
+    open_with_helper(path, Request::OpenHidraw)
+}
+
+/// 打开数位板的 evdev 节点, 没有权限时交给辅助进程. 用于独占, 见 [`super::grab`]
+pub fn open_evdev(path: &Path) -> io::Result<File> {
+    open_with_helper(path, Request::OpenEvdev)
+}
+
+fn open_with_helper(path: &Path, request_for: fn(PathBuf) -> Request) -> io::Result<File> {
+    let error = match File::open(path) {
+        Ok(file) => return Ok(file),
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => e,
+        Err(e) => return Err(e),
+    };
+    match request(request_for(path.to_path_buf())) {
+        Ok((Reply::Opened, Some(fd))) => Ok(File::from(fd)),
+        Ok((reply, _)) => Err(io::Error::new(
+            error.kind(),
+            format!("{error}, 特权辅助进程也无法打开: {:#}", reply.error()),
+        )),
+        Err(e) => Err(io::Error::new(
+            error.kind(),
+            format!("{error}, 无法使用特权辅助进程: {e:#}"),
+        )),
+    }
+}
+
+fn is_permission_denied(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<io::Error>()
+        .is_some_and(|e| e.kind() == io::ErrorKind::PermissionDenied)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum Request {
+    OpenHidraw(PathBuf),
+    CreateUinput(UinputSpec),
+    Release(u32),
+    OpenEvdev(PathBuf),
+}
+
+/// `Opened` 和 `Created` 附带文件描述符
+#[derive(Debug, Serialize, Deserialize)]
+enum Reply {
+    Opened,
+    Created { id: u32, devnode: Option<String> },
+    Released,
+    Failed(String),
+}
+
+impl Reply {
+    fn error(self) -> anyhow::Error {
+        match self {
+            Reply::Failed(message) => anyhow!(message),
+            reply => anyhow!("意外的回复 {reply:?}"),
+        }
+    }
+}
+
+/// 和辅助进程的连接
+struct Helper {
+    socket: OwnedFd,
+}
+
+enum HelperState {
+    /// 还没有需要特权的设备
+    Idle(HelperConfig),
+    Connected(Helper),
+    /// 启动失败后不再尝试, 避免反复弹出 pkexec 的认证
+    Unavailable(String),
+}
+
+static HELPER: Mutex<Option<HelperState>> = Mutex::new(None);
+
+/// 设置辅助进程并检查权限, 在打开任何设备之前调用. 不调用时按默认设置
+pub fn configure(config: &HelperConfig) {
+    let access = Access::check();
+    if !access.sufficient() {
+        match config.mode {
+            HelperMode::Off => error!("{access}, 没有启用特权辅助进程, 这些设备无法使用"),
+            _ => warn!("{access}, 将通过特权辅助进程打开. 安装 udev 规则后不再需要"),
+        }
+    }
+    *HELPER.lock().unwrap() = Some(HelperState::Idle(config.clone()));
+}
+
+/// 发送请求并等待回复, 需要时先启动辅助进程
+fn request(request: Request) -> anyhow::Result<(Reply, Option<OwnedFd>)> {
+    let mut state = HELPER.lock().unwrap();
+    let state = state.get_or_insert_with(|| HelperState::Idle(HelperConfig::default()));
+    if let HelperState::Idle(config) = state {
+        *state = match Helper::start(config) {
+            Ok(helper) => HelperState::Connected(helper),
+            Err(e) => {
+                error!("无法启动特权辅助进程: {e:#}");
+                HelperState::Unavailable(format!("{e:#}"))
+            }
+        };
+    }
+    let helper = match state {
+        HelperState::Connected(helper) => helper,
+        HelperState::Unavailable(reason) => bail!("特权辅助进程不可用: {reason}"),
+        HelperState::Idle(_) => unreachable!(),
+    };
+    send(helper.socket.as_raw_fd(), &request, None)?;
+    match receive(helper.socket.as_raw_fd())? {
+        Some(reply) => Ok(reply),
+        None => {
+            *state = HelperState::Unavailable("已退出".to_string());
+            bail!("特权辅助进程已退出")
+        }
+    }
+}
+
+impl Helper {
+    fn start(config: &HelperConfig) -> anyhow::Result<Self> {
+        match config.mode {
+            HelperMode::Auto => Self::connect(&config.socket).or_else(|e| {
+                info!("{e:#}, 改用 pkexec 启动特权辅助进程");
+                Self::spawn()
+            }),
+            HelperMode::Socket => Self::connect(&config.socket),
+            HelperMode::Pkexec => Self::spawn(),
+            HelperMode::Off => bail!("[daemon.helper] mode = \"off\""),
+        }
+    }
+
+    /// 连接 systemd 监听的 socket, 每个连接由一个新的辅助进程处理
+    fn connect(path: &Path) -> anyhow::Result<Self> {
+        let socket = socket::socket(
+            AddressFamily::Unix,
+            SockType::SeqPacket,
+            SockFlag::SOCK_CLOEXEC,
+            None,
+        )?;
+        socket::connect(socket.as_raw_fd(), &UnixAddr::new(path)?)
+            .with_context(|| format!("无法连接 {}", path.display()))?;
+        info!("已连接特权辅助进程 {}", path.display());
+        Ok(Self { socket })
+    }
+
+    /// 用 pkexec 启动辅助进程, socket 的另一端作为它的标准输入
+    fn spawn() -> anyhow::Result<Self> {
+        let (socket, theirs) = socket::socketpair(
+            AddressFamily::Unix,
+            SockType::SeqPacket,
+            None,
+            SockFlag::SOCK_CLOEXEC,
+        )?;
+        let exe = std::env::current_exe().context("找不到 tabletd 的路径")?;
+        let mut child = Command::new("pkexec")
+            .arg(exe)
+            .arg("--raw-io-helper")
+            .stdin(Stdio::from(theirs))
+            .stdout(Stdio::null())
+            .spawn()
+            .context("无法运行 pkexec")?;
+        info!("已通过 pkexec 启动特权辅助进程 (pid {})", child.id());
+        // 认证失败时 pkexec 退出, 第一个请求会收到 EOF
+        std::thread::spawn(move || {
+            if let Ok(status) = child.wait()
+                && !status.success()
+            {
+                warn!("特权辅助进程退出: {status}");
+            }
+        });
+        Ok(Self { socket })
+    }
+}
+
+fn send<T: Serialize>(socket: RawFd, message: &T, fd: Option<RawFd>) -> anyhow::Result<()> {
+    let bytes = postcard::to_stdvec(message)?;
+    let fds = fd.map(|fd| [fd]);
+    let cmsgs: Vec<_> = fds
+        .iter()
+        .map(|fds| ControlMessage::ScmRights(fds))
+        .collect();
+    socket::sendmsg::<()>(
+        socket,
+        &[IoSlice::new(&bytes)],
+        &cmsgs,
+        MsgFlags::empty(),
+        None,
+    )?;
+    Ok(())
+}
+
+/// 对方关闭连接时返回 `None`
+fn receive<T: for<'de> Deserialize<'de>>(
+    socket: RawFd,
+) -> anyhow::Result<Option<(T, Option<OwnedFd>)>> {
+    let mut buf = vec![0; MAX_MESSAGE];
+    let mut cmsg = nix::cmsg_space!([RawFd; 1]);
+    let mut iov = [IoSliceMut::new(&mut buf)];
+    let msg = socket::recvmsg::<()>(
+        socket,
+        &mut iov,
+        Some(&mut cmsg),
+        MsgFlags::MSG_CMSG_CLOEXEC,
+    )?;
+    let mut fd = None;
+    for cmsg in msg.cmsgs()? {
+        if let ControlMessageOwned::ScmRights(fds) = cmsg {
+            for raw in fds {
+                // SAFETY: SCM_RIGHTS 收到的是新的文件描述符, 归这里所有
+                let owned = unsafe { OwnedFd::from_raw_fd(raw) };
+                fd.get_or_insert(owned);
+            }
+        }
+    }
+    let len = msg.bytes;
+    if len == 0 {
+        return Ok(None);
+    }
+    let message = postcard::from_bytes(&buf[..len]).context("无法解析消息")?;
+    Ok(Some((message, fd)))
+}
+
+/// 辅助进程: 在标准输入(systemd 或 pkexec 传来的 socket)上处理请求, 守护进程断开后退出
+///
+/// 退出时销毁创建的所有 uinput 设备
+pub fn serve() -> anyhow::Result<()> {
+    let socket = io::stdin()
+        .as_fd()
+        .try_clone_to_owned()
+        .context("标准输入不可用")?;
+    let mut devices = HashMap::new();
+    let mut next_id = 0;
+    while let Some((request, _)) = receive::<Request>(socket.as_raw_fd())? {
+        let (reply, fd) = match handle(request, &mut devices, &mut next_id) {
+            Ok(reply) => reply,
+            Err(e) => (Reply::Failed(format!("{e:#}")), None),
+        };
+        // 发送后关闭这一端, 守护进程收到的是复制的描述符
+        send(
+            socket.as_raw_fd(),
+            &reply,
+            fd.as_ref().map(|fd| fd.as_raw_fd()),
+        )?;
+    }
+    Ok(())
+}
+
+fn handle(
+    request: Request,
+    devices: &mut HashMap<u32, UInputDevice>,
+    next_id: &mut u32,
+) -> anyhow::Result<(Reply, Option<OwnedFd>)> {
+    match request {
+        Request::OpenHidraw(path) => {
+            // 只打开已知数位板, 不能用来读取键盘
+            if !hidraw::enumerate()
+                .iter()
+                .any(|node| node.path == path && node.is_known_tablet())
+            {
+                bail!("{} 不是已知数位板的 hidraw 节点", path.display());
+            }
+            Ok((Reply::Opened, Some(File::open(&path)?.into())))
+        }
+        Request::OpenEvdev(path) => {
+            // 同样只打开已知数位板的节点
+            if !hidraw::enumerate()
+                .iter()
+                .filter(|node| node.is_known_tablet())
+                .any(|node| grab::evdev_nodes(node).contains(&path))
+            {
+                bail!("{} 不是已知数位板的 evdev 节点", path.display());
+            }
+            Ok((Reply::Opened, Some(File::open(&path)?.into())))
+        }
+        Request::CreateUinput(spec) => {
+            if !spec.name.starts_with(UINPUT_PREFIX) {
+                bail!("只能创建名字以 `{UINPUT_PREFIX}` 开头的设备");
+            }
+            if let Some(code) = spec.disallowed_code() {
+                bail!("只能创建数位板设备, 不允许启用 {code}");
+            }
+            if devices.len() >= MAX_UINPUT_DEVICES {
+                bail!("已经创建了 {MAX_UINPUT_DEVICES} 个设备");
+            }
+            let device = spec.create()?;
+            let fd = device.as_fd().context("uinput 设备没有文件描述符")?;
+            // SAFETY: 描述符属于 device, 这里只是复制它
+            let fd = unsafe { BorrowedFd::borrow_raw(fd) }.try_clone_to_owned()?;
+            let id = *next_id;
+            *next_id = next_id.wrapping_add(1);
+            let reply = Reply::Created {
+                id,
+                devnode: device.devnode().map(str::to_string),
+            };
+            devices.insert(id, device);
+            Ok((reply, Some(fd)))
+        }
+        Request::Release(id) => {
+            devices.remove(&id);
+            Ok((Reply::Released, None))
+        }
+    }
+}
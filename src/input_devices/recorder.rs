@@ -0,0 +1,258 @@
+//! 事件的录制和回放
+//!
+//! [`tap`] 把驱动发往路由器的事件(带时间戳和数位板 ID)同时写入文件. 之后可以用 [`replay`]
+//! 按原来的节奏把它们重新发给路由器, 回放的数位板是一个 [`Transport::Replay`] 连接,
+//! 和真实设备一样经过映射、过滤器和分发. 这样不需要手边有那块数位板就能复现驱动的怪异行为和映射问题
+//!
+//! 文件以 [`MAGIC`] 开头, 之后是一系列帧(格式和 [`codec::encode_frame`] 相同):
+//! 第一帧是 [`Header`], 之后每帧一个 [`Recorded`]. 事件使用 [`wire`](crate::event_model::wire)
+//! 的格式, 所以新版本 tabletd 录制的文件中不认识的事件会回放为 [`TabletEvent::Unknown`]
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use crate::{
+    event_dispatcher::api::{codec, protocol::TabletInfo},
+    event_model::{
+        event::{PenLocation, PenState, TabletEvent},
+        stamp::{EventStamp, monotonic_micros},
+        tablet::TabletId,
+    },
+    event_router::{EventSender, InputEvent, Router, lanes},
+};
+
+use super::transport::Transport;
+
+/// 录制文件的开头
+pub const MAGIC: &[u8; 8] = b"TABLETRC";
+/// 录制文件的格式版本, 不兼容时加一
+pub const FORMAT_VERSION: u16 = 1;
+
+/// 录制文件的第一帧
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Header {
+    pub version: u16,
+    /// 录制时接入的数位板, 回放时用它们的能力建立映射
+    pub tablets: Vec<TabletInfo>,
+    /// 开始录制的时间(`CLOCK_MONOTONIC` 微秒)
+    pub started: u64,
+}
+
+/// 录制的一个事件
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recorded {
+    pub tablet: TabletId,
+    pub event: TabletEvent,
+    /// 驱动读到这个事件的时间和序号
+    pub stamp: EventStamp,
+}
+
+/// 正在写入的录制文件
+pub struct Recorder {
+    writer: BufWriter<File>,
+    path: PathBuf,
+    count: u64,
+}
+
+impl Recorder {
+    /// 创建录制文件, `tablets` 是当前接入的数位板
+    pub fn create(path: impl Into<PathBuf>, tablets: Vec<TabletInfo>) -> anyhow::Result<Self> {
+        let path = path.into();
+        let file = File::create(&path).with_context(|| format!("{}", path.display()))?;
+        let mut recorder = Self {
+            writer: BufWriter::new(file),
+            path,
+            count: 0,
+        };
+        recorder.writer.write_all(MAGIC)?;
+        let header = Header {
+            version: FORMAT_VERSION,
+            tablets,
+            started: monotonic_micros(),
+        };
+        recorder.writer.write_all(&codec::encode_frame(&header)?)?;
+        Ok(recorder)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 已写入的事件数量
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn write(&mut self, input: &InputEvent) -> anyhow::Result<()> {
+        let recorded = Recorded {
+            tablet: input.tablet,
+            event: input.event.clone(),
+            stamp: input.stamp,
+        };
+        self.writer.write_all(&codec::encode_frame(&recorded)?)?;
+        self.count += 1;
+        Ok(())
+    }
+
+    /// 写入缓冲区中剩下的内容
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// 录制发往 `events` 的事件
+///
+/// 返回的发送端代替 `events` 交给驱动, 收到的事件先写入 `recorder` 再原样转发.
+/// 驱动全部关闭或者 `events` 的接收端关闭时结束录制. 写入失败时停止录制, 转发不受影响.
+/// 和直接发往路由器一样, 转发跟不上时移动会被丢弃, 见 [`crate::event_router::lanes`]
+pub fn tap(recorder: Recorder, events: EventSender) -> EventSender {
+    let (tx, mut rx) = lanes::channel(64);
+    tokio::spawn(async move {
+        let mut recorder = Some(recorder);
+        while let Some(input) = rx.recv().await {
+            if let Some(current) = &mut recorder
+                && let Err(e) = current.write(&input)
+            {
+                warn!("录制 {} 失败, 停止录制: {e:#}", current.path().display());
+                recorder = None;
+            }
+            if events.send(input).await.is_err() {
+                break;
+            }
+        }
+        if let Some(recorder) = recorder {
+            let (path, count) = (recorder.path().to_path_buf(), recorder.count());
+            match recorder.finish() {
+                Ok(()) => info!("已录制 {count} 个事件到 {}", path.display()),
+                Err(e) => warn!("录制 {} 失败: {e:#}", path.display()),
+            }
+        }
+    });
+    tx
+}
+
+/// 读取的录制文件
+#[derive(Debug, Clone)]
+pub struct Recording {
+    pub header: Header,
+    pub events: Vec<Recorded>,
+}
+
+impl Recording {
+    /// 读取录制文件. tabletd 没有正常退出时最后一帧可能不完整, 这时忽略它
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let data = std::fs::read(path).with_context(|| format!("{}", path.display()))?;
+        let Some(mut rest) = data.strip_prefix(MAGIC.as_slice()) else {
+            bail!("{} 不是 tabletd 录制文件", path.display());
+        };
+        let mut frames = std::iter::from_fn(|| {
+            let (len, _) = rest.split_first_chunk::<4>()?;
+            let end = 4 + u32::from_be_bytes(*len) as usize;
+            if rest.len() < end {
+                warn!("{} 的最后一帧不完整, 已忽略", path.display());
+                return None;
+            }
+            let (frame, next) = rest.split_at(end);
+            rest = next;
+            Some(frame)
+        });
+
+        let header: Header = match frames.next() {
+            Some(frame) => codec::decode_frame(frame)?,
+            None => bail!("{} 缺少文件头", path.display()),
+        };
+        if header.version != FORMAT_VERSION {
+            bail!(
+                "录制文件格式版本不一致: 文件 {}, 本地 {FORMAT_VERSION}",
+                header.version
+            );
+        }
+        let events = frames
+            .map(codec::decode_frame)
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self { header, events })
+    }
+
+    /// 录制的时长
+    pub fn duration(&self) -> Duration {
+        let last = self
+            .events
+            .last()
+            .map_or(self.header.started, |recorded| recorded.stamp.timestamp);
+        Duration::from_micros(last.saturating_sub(self.header.started))
+    }
+
+    /// 在路由器中接入回放的数位板, 需要在 [`replay`] 之前调用
+    ///
+    /// 同一块数位板的真实设备接入时优先使用真实设备, 回放的事件会被忽略
+    pub fn connect(&self, router: &mut Router) {
+        for tablet in &self.header.tablets {
+            router.connect(tablet.id, Transport::Replay, &tablet.capabilities);
+        }
+    }
+}
+
+/// 按录制时的节奏把事件发往 `events`, `speed` 为回放速度(1.0 为原速)
+///
+/// 事件的时间戳换成回放时的时间, 序号保持不变. 录制在笔画中途结束时, 最后补发抬笔事件.
+/// `events` 的接收端关闭时提前返回
+pub async fn replay(recording: &Recording, events: &EventSender, speed: f64) -> anyhow::Result<()> {
+    if !(speed.is_finite() && speed > 0.0) {
+        bail!("回放速度必须大于 0: {speed}");
+    }
+    let start = Instant::now();
+    let start_micros = monotonic_micros();
+    let offset = |timestamp: u64| {
+        Duration::from_micros(timestamp.saturating_sub(recording.header.started)).div_f64(speed)
+    };
+    let mut pens: HashMap<TabletId, (PenState, EventStamp)> = HashMap::new();
+    for recorded in &recording.events {
+        let delay = offset(recorded.stamp.timestamp);
+        tokio::time::sleep_until(start + delay).await;
+        let stamp = EventStamp {
+            timestamp: start_micros + delay.as_micros() as u64,
+            sequence: recorded.stamp.sequence,
+        };
+        if let TabletEvent::PenEvent(pen) = &recorded.event {
+            pens.insert(recorded.tablet, (pen.clone(), stamp));
+        }
+        let input = InputEvent {
+            tablet: recorded.tablet,
+            transport: Transport::Replay,
+            event: recorded.event.clone(),
+            stamp,
+        };
+        if events.send(input).await.is_err() {
+            return Ok(());
+        }
+    }
+
+    for (tablet, (mut pen, stamp)) in pens {
+        if matches!(pen.location, PenLocation::Leaved) {
+            continue;
+        }
+        pen.location = PenLocation::Leaved;
+        pen.pressure = 0;
+        let input = InputEvent {
+            tablet,
+            transport: Transport::Replay,
+            event: TabletEvent::PenEvent(pen),
+            stamp: stamp.follow(),
+        };
+        if events.send(input).await.is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
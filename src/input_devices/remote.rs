@@ -0,0 +1,662 @@
+//! 通过 `tabletd API` 接收另一台机器上的数位板
+//!
+//! 可以把远程事件当作本地数位板输入，也可以只在 overlay 上显示为墨迹(只看模式),
+//! 例如把老师的数位板镜像为学生屏幕上的批注，而不交出输入控制权.
+//!
+//! 作为输入时，每块远程数位板在本地有自己的 [`TabletId`], 之后和本地设备一样经过映射、overlay 和分发.
+//! [`RemoteLink`] 用心跳检测失去响应的服务端，断开后按指数退避重连;
+//! 连接断开期间仍然按着的笔和按键会被松开，不会卡在按下的状态.
+//! 两台机器共用一块数位板时还可以在连接上同步设置([`RemoteLink::set_sync`])
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, anyhow, bail};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadHalf, WriteHalf},
+    net::{TcpStream, ToSocketAddrs},
+    sync::{mpsc, watch},
+    time::MissedTickBehavior,
+};
+use tracing::{info, warn};
+
+use crate::{
+    event_dispatcher::api::{
+        codec,
+        filter::EventFilter,
+        protocol::{ApiEvent, ClientMessage, Handshake, ServerMessage, Subscription, TabletInfo},
+    },
+    event_model::{
+        capability::DeviceCapabilities,
+        coordinate::{CoordinateFormat, CoordinateSpace, Origin},
+        event::{AuxButtonEvent, PenLocation, PenState, TabletEvent},
+        stamp::{EventSequence, EventStamp},
+        tablet::TabletId,
+    },
+    event_router::{EventSender, InputEvent},
+    hud_interface::{HudEvent, HudSender, link::LinkStatus},
+    mapping::{
+        Mapper,
+        geometry::{GeometryBus, GeometryChanged},
+    },
+    profile::sync::{ProfileSync, SyncMessage},
+    screen_overlay::ink::InkLayer,
+};
+
+use super::{
+    identity::{Fingerprint, IdentityRegistry},
+    transport::Transport,
+};
+
+/// 心跳间隔
+const PING_INTERVAL: Duration = Duration::from_secs(2);
+/// 超过这个时间没有收到任何消息就认为服务端已经失去响应
+const PEER_TIMEOUT: Duration = Duration::from_secs(6);
+/// 第一次重连前的等待时间, 之后每次失败翻倍
+const RECONNECT_MIN: Duration = Duration::from_millis(500);
+const RECONNECT_MAX: Duration = Duration::from_secs(30);
+/// 连续失败这么多次后显示为离线
+const OFFLINE_AFTER: u32 = 5;
+/// 同步设置时交换修改时间的间隔
+const SYNC_INTERVAL: Duration = Duration::from_secs(10);
+
+/// `tabletd API` 客户端
+pub struct RemoteClient<S> {
+    reader: ReadHalf<S>,
+    writer: WriteHalf<S>,
+    handshake: Handshake,
+}
+
+impl RemoteClient<TcpStream> {
+    /// 通过 TCP 连接
+    pub async fn connect_tcp(address: impl ToSocketAddrs) -> anyhow::Result<Self> {
+        let stream = TcpStream::connect(address)
+            .await
+            .context("无法连接到 tabletd API")?;
+        stream.set_nodelay(true)?;
+        Self::connect(stream).await
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> RemoteClient<S> {
+    /// 在已建立的连接上完成握手并订阅事件, 坐标使用归一化的数位板坐标
+    pub async fn connect(stream: S) -> anyhow::Result<Self> {
+        let (mut reader, writer) = tokio::io::split(stream);
+        let handshake = match codec::read_frame(&mut reader).await.context("握手失败")? {
+            Some(ServerMessage::Hello(handshake)) => handshake,
+            Some(_) => bail!("握手失败: 服务端没有先发送 Hello"),
+            None => bail!("握手失败: 服务端关闭了连接"),
+        };
+        if handshake.version != codec::PROTOCOL_VERSION {
+            bail!(
+                "协议版本不一致: 服务端 {}, 本地 {}",
+                handshake.version,
+                codec::PROTOCOL_VERSION
+            );
+        }
+        let mut client = Self {
+            reader,
+            writer,
+            handshake,
+        };
+        let subscription = Subscription {
+            coordinates: CoordinateFormat {
+                space: CoordinateSpace::Normalized,
+                origin: Origin::TopLeft,
+            },
+            filter: EventFilter::default(),
+        };
+        codec::write_frame(&mut client.writer, &ClientMessage::Subscribe(subscription))
+            .await
+            .context("无法发送订阅请求")?;
+        Ok(client)
+    }
+
+    /// 服务端的握手信息，包括可用的数位板
+    pub fn handshake(&self) -> &Handshake {
+        &self.handshake
+    }
+
+    /// 读取下一条消息, 服务端关闭连接时返回 `None`
+    pub async fn recv(&mut self) -> anyhow::Result<Option<ServerMessage>> {
+        codec::read_frame(&mut self.reader).await
+    }
+
+    /// 服务端提供的数位板, `server` 是服务端地址，用于区分不同服务端上 ID 相同的数位板
+    pub fn tablets(&self, server: &str) -> Vec<RemoteTablet> {
+        self.handshake
+            .tablets
+            .iter()
+            .map(|info| RemoteTablet {
+                server: server.to_string(),
+                info: info.clone(),
+            })
+            .collect()
+    }
+}
+
+/// 为服务端的每块数位板分配本地 ID, 结果用于 [`RemoteMode::Input`]
+pub fn resolve_ids(
+    tablets: &[RemoteTablet],
+    registry: &mut IdentityRegistry,
+) -> HashMap<TabletId, TabletId> {
+    tablets
+        .iter()
+        .map(|tablet| (tablet.info.id, registry.resolve(&tablet.fingerprint())))
+        .collect()
+}
+
+/// 远程服务端上的一块数位板
+#[derive(Debug, Clone)]
+pub struct RemoteTablet {
+    pub server: String,
+    pub info: TabletInfo,
+}
+
+impl RemoteTablet {
+    /// 设备标识，用于分配本地的 [`TabletId`]
+    ///
+    /// 远程数位板没有 VID/PID 和序列号, 用服务端地址和远程 ID 作为序列号
+    pub fn fingerprint(&self) -> Fingerprint {
+        Fingerprint {
+            transport: Transport::Remote,
+            vendor_id: 0,
+            product_id: 0,
+            name: format!("{} @ {}", self.info.name, self.server),
+            serial: Some(format!("{}/{}", self.server, self.info.id)),
+            mac: None,
+        }
+    }
+}
+
+/// 把远程笔事件画成本地 overlay 上的墨迹
+///
+/// 墨迹按远程的 [`TabletId`] 区分笔画, 画在和 overlay 共享的 [`InkLayer`] 上
+/// ([`crate::screen_overlay::layers::OverlayLayers::set_ink`]), 变化后通过 HUD 请求重绘
+pub struct RemoteViewer {
+    /// 远程数位板到本地屏幕的映射
+    mapper: Mapper,
+    /// 远程数位板的能力, 按远程的 [`TabletId`]
+    capabilities: HashMap<TabletId, DeviceCapabilities>,
+    ink: Arc<Mutex<InkLayer>>,
+    hud: Option<HudSender>,
+}
+
+impl RemoteViewer {
+    pub fn new(mapper: Mapper, ink: Arc<Mutex<InkLayer>>) -> Self {
+        Self {
+            mapper,
+            capabilities: HashMap::new(),
+            ink,
+            hud: None,
+        }
+    }
+
+    /// 墨迹变化后发送 [`HudEvent::RemoteInkChanged`]
+    pub fn set_hud(&mut self, hud: HudSender) {
+        self.hud = Some(hud);
+    }
+
+    pub fn ink(&self) -> Arc<Mutex<InkLayer>> {
+        self.ink.clone()
+    }
+
+    /// 本地显示器布局变化
+    pub fn apply_geometry(&mut self, geometry: &GeometryChanged) {
+        // 只看模式下没有光标，目标显示器的变化不需要提示
+        let _ = self.mapper.apply_geometry(geometry);
+    }
+
+    pub fn handle(&mut self, message: ServerMessage, now: Instant) {
+        match message {
+            ServerMessage::Capabilities(tablet, capabilities) => {
+                self.capabilities.insert(tablet, capabilities);
+            }
+            ServerMessage::TabletAdded(info) => {
+                self.capabilities.insert(info.id, info.capabilities);
+            }
+            ServerMessage::Event(event) => self.event(event, now),
+            ServerMessage::TabletRemoved(tablet) => {
+                self.capabilities.remove(&tablet);
+                self.lift(tablet, now);
+            }
+            ServerMessage::Error(report) => warn!("远程 tabletd 出错: {}", report.message),
+            // 远程的显示器布局与本地无关
+            ServerMessage::Geometry(_)
+            | ServerMessage::Hello(_)
+            | ServerMessage::Pong(_)
+            | ServerMessage::Sync(_) => {}
+        }
+    }
+
+    /// 连接断开, 结束所有正在画的笔画
+    pub fn disconnected(&mut self, now: Instant) {
+        self.ink.lock().unwrap().lift_all(now);
+        self.changed();
+    }
+
+    fn event(&mut self, event: ApiEvent, now: Instant) {
+        // 被对方 tabletd 消费的事件(比如操作 HUD)不算批注
+        if event.consumed {
+            return;
+        }
+        let TabletEvent::PenEvent(pen) = event.event else {
+            return;
+        };
+        let (PenLocation::Pressed, Some((nx, ny)), Some(capabilities)) = (
+            pen.location,
+            event.position,
+            self.capabilities.get(&event.tablet),
+        ) else {
+            self.lift(event.tablet, now);
+            return;
+        };
+        let Some(position) = self.mapper.to_logical(nx, ny, capabilities) else {
+            return;
+        };
+        let pressure = if capabilities.has_pressure() {
+            pen.pressure as f32 / capabilities.max_pressure as f32
+        } else {
+            0.5
+        };
+        self.ink
+            .lock()
+            .unwrap()
+            .push(event.tablet, position, pressure);
+        self.changed();
+    }
+
+    fn lift(&mut self, tablet: TabletId, now: Instant) {
+        if self.ink.lock().unwrap().lift(tablet, now) {
+            self.changed();
+        }
+    }
+
+    fn changed(&self) {
+        if let Some(hud) = &self.hud {
+            let _ = hud.send(HudEvent::RemoteInkChanged);
+        }
+    }
+}
+
+/// 收到的远程事件的用途
+pub enum RemoteMode {
+    /// 作为本地数位板输入, `tablets` 是远程 ID 到本地 ID 的对应, 不在其中的数位板被忽略
+    Input {
+        tablets: HashMap<TabletId, TabletId>,
+        events: EventSender,
+    },
+    /// 只在 overlay 上显示，不产生输入
+    ViewOnly(Arc<Mutex<RemoteViewer>>),
+}
+
+/// 作为输入时仍然处于感应范围内的笔和按着的按键, 连接断开时用来补发松开事件
+#[derive(Debug, Default)]
+struct Held {
+    pens: HashMap<TabletId, PenState>,
+    buttons: HashSet<(TabletId, u8)>,
+    /// 收到事件时按本地的时钟重新编号, 对方的时间戳不能和本机比较
+    sequences: HashMap<TabletId, EventSequence>,
+}
+
+impl Held {
+    fn stamp(&mut self, tablet: TabletId) -> EventStamp {
+        self.sequences.entry(tablet).or_default().stamp_now()
+    }
+
+    fn track(&mut self, tablet: TabletId, event: &TabletEvent) {
+        match event {
+            TabletEvent::PenEvent(pen) if matches!(pen.location, PenLocation::Leaved) => {
+                self.pens.remove(&tablet);
+            }
+            TabletEvent::PenEvent(pen) => {
+                self.pens.insert(tablet, pen.clone());
+            }
+            TabletEvent::AuxButton(button) if button.pressed => {
+                self.buttons.insert((tablet, button.button_id));
+            }
+            TabletEvent::AuxButton(button) => {
+                self.buttons.remove(&(tablet, button.button_id));
+            }
+            _ => {}
+        }
+    }
+
+    /// 松开所有按键, 笔离开感应范围
+    fn release(&mut self) -> Vec<InputEvent> {
+        let buttons = self.buttons.drain().map(|(tablet, button_id)| {
+            (
+                tablet,
+                TabletEvent::AuxButton(AuxButtonEvent {
+                    button_id,
+                    pressed: false,
+                }),
+            )
+        });
+        let pens = self.pens.drain().map(|(tablet, mut pen)| {
+            pen.location = PenLocation::Leaved;
+            pen.pressure = 0;
+            (tablet, TabletEvent::PenEvent(pen))
+        });
+        let released: Vec<_> = buttons.chain(pens).collect();
+        let events = released
+            .into_iter()
+            .map(|(tablet, event)| InputEvent {
+                tablet,
+                transport: Transport::Remote,
+                event,
+                stamp: self.stamp(tablet),
+            })
+            .collect();
+        // 重新连接后是新的事件流
+        self.sequences.clear();
+        events
+    }
+}
+
+/// 接收远程事件直到连接关闭
+///
+/// 每隔 [`PING_INTERVAL`] 发送一次心跳, 超过 [`PEER_TIMEOUT`] 没有收到任何消息时返回错误
+pub async fn receive<S>(client: RemoteClient<S>, mode: &RemoteMode) -> anyhow::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    receive_held(client, mode, None, &mut Held::default()).await
+}
+
+async fn receive_held<S>(
+    client: RemoteClient<S>,
+    mode: &RemoteMode,
+    sync: Option<(&ProfileSync, &str)>,
+    held: &mut Held,
+) -> anyhow::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let RemoteClient {
+        mut reader,
+        mut writer,
+        ..
+    } = client;
+
+    // 读帧不能被 select! 打断，所以放到单独的任务里
+    let (message_tx, mut message_rx) = mpsc::channel(8);
+    let read_task = tokio::spawn(async move {
+        loop {
+            let message = codec::read_frame::<_, ServerMessage>(&mut reader).await;
+            let done = !matches!(message, Ok(Some(_)));
+            if message_tx.send(message).await.is_err() || done {
+                break;
+            }
+        }
+    });
+
+    let mut ping = tokio::time::interval(PING_INTERVAL);
+    ping.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut seq: u32 = 0;
+    let mut last_seen = Instant::now();
+    if let Some((_, token)) = sync {
+        codec::write_frame(&mut writer, &ClientMessage::Auth(token.to_string())).await?;
+    }
+    let sync = sync.map(|(sync, _)| sync);
+    // 第一次在连接后立刻触发
+    let mut sync_tick = tokio::time::interval(SYNC_INTERVAL);
+    sync_tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let result = loop {
+        tokio::select! {
+            message = message_rx.recv() => {
+                let message = match message {
+                    Some(Ok(Some(message))) => message,
+                    Some(Err(e)) => break Err(e),
+                    Some(Ok(None)) | None => break Ok(()),
+                };
+                last_seen = Instant::now();
+                if let ServerMessage::Sync(message) = message {
+                    if let Some(sync) = sync
+                        && let Err(e) = sync_reply(sync, message, &mut writer).await
+                    {
+                        break Err(e);
+                    }
+                    continue;
+                }
+                if !deliver(message, mode, held).await {
+                    break Ok(());
+                }
+            }
+            _ = ping.tick() => {
+                if last_seen.elapsed() > PEER_TIMEOUT {
+                    break Err(anyhow!("服务端超过 {} 秒没有响应", PEER_TIMEOUT.as_secs()));
+                }
+                seq = seq.wrapping_add(1);
+                if let Err(e) = codec::write_frame(&mut writer, &ClientMessage::Ping(seq)).await {
+                    break Err(e);
+                }
+            }
+            _ = sync_tick.tick(), if sync.is_some() => {
+                let Some(sync) = sync else {
+                    continue;
+                };
+                let manifest = match sync.manifest() {
+                    Ok(manifest) => manifest,
+                    Err(e) => {
+                        warn!("无法读取要同步的设置: {e:#}");
+                        continue;
+                    }
+                };
+                if let Err(e) = codec::write_frame(&mut writer, &ClientMessage::Sync(manifest)).await {
+                    break Err(e);
+                }
+            }
+        }
+    };
+
+    read_task.abort();
+    result
+}
+
+/// 处理服务端的同步消息并回复, 只有连接出错时返回错误
+async fn sync_reply<W>(
+    sync: &ProfileSync,
+    message: SyncMessage,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let replies = match sync.handle(message) {
+        Ok(replies) => replies,
+        Err(e) => {
+            warn!("同步设置失败: {e:#}");
+            return Ok(());
+        }
+    };
+    for reply in replies {
+        codec::write_frame(writer, &ClientMessage::Sync(reply)).await?;
+    }
+    Ok(())
+}
+
+/// 处理一条消息, 本地的事件接收端关闭时返回 `false`
+async fn deliver(message: ServerMessage, mode: &RemoteMode, held: &mut Held) -> bool {
+    match mode {
+        RemoteMode::Input { tablets, events } => {
+            let ServerMessage::Event(event) = message else {
+                return true;
+            };
+            // 对方消费的事件不应该变成本地输入
+            if event.consumed {
+                return true;
+            }
+            let Some(tablet) = tablets.get(&event.tablet) else {
+                return true;
+            };
+            held.track(*tablet, &event.event);
+            let input = InputEvent {
+                tablet: *tablet,
+                transport: Transport::Remote,
+                event: event.event,
+                stamp: held.stamp(*tablet),
+            };
+            events.send(input).await.is_ok()
+        }
+        RemoteMode::ViewOnly(viewer) => {
+            viewer.lock().unwrap().handle(message, Instant::now());
+            true
+        }
+    }
+}
+
+/// 到一个远程服务端的连接, 断开后自动重连
+///
+/// 连接断开时先松开仍然按着的笔和按键，然后按指数退避重连. 状态变化会发往 HUD
+pub struct RemoteLink {
+    address: String,
+    mode: RemoteMode,
+    hud: Option<HudSender>,
+    sync: Option<(ProfileSync, String)>,
+    geometry: Option<watch::Receiver<GeometryChanged>>,
+}
+
+impl RemoteLink {
+    pub fn new(address: impl Into<String>, mode: RemoteMode) -> Self {
+        Self {
+            address: address.into(),
+            mode,
+            hud: None,
+            sync: None,
+            geometry: None,
+        }
+    }
+
+    /// 在 [`RemoteLink::run`] 中把 `bus` 上本地的显示器布局交给 [`RemoteMode::ViewOnly`] 的墨迹,
+    /// 没有布局时墨迹无法映射到屏幕上
+    pub fn follow_geometry(&mut self, bus: &GeometryBus) {
+        let mut rx = bus.subscribe();
+        rx.mark_changed();
+        self.geometry = Some(rx);
+    }
+
+    /// 和服务端同步设置, 服务端也需要开启([`crate::event_dispatcher::api::ApiServer::set_sync`]),
+    /// 每次连接后先发送服务端的口令 `token`
+    pub fn set_sync(&mut self, sync: ProfileSync, token: impl Into<String>) {
+        self.sync = Some((sync, token.into()));
+    }
+
+    /// 把连接状态发往 HUD
+    pub fn set_hud(&mut self, hud: HudSender) {
+        self.hud = Some(hud);
+    }
+
+    fn report(&self, status: Option<LinkStatus>) {
+        if let Some(hud) = &self.hud {
+            let _ = hud.send(HudEvent::RemoteLink {
+                server: self.address.clone(),
+                status,
+            });
+        }
+    }
+
+    /// 本地不再需要远程事件
+    fn closed(&self) -> bool {
+        match &self.mode {
+            RemoteMode::Input { events, .. } => events.is_closed(),
+            RemoteMode::ViewOnly(_) => false,
+        }
+    }
+
+    /// 断开后清理: 松开按着的笔和按键，结束墨迹
+    async fn release(&self, held: &mut Held) {
+        match &self.mode {
+            RemoteMode::Input { events, .. } => {
+                for input in held.release() {
+                    if events.send(input).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            RemoteMode::ViewOnly(viewer) => viewer.lock().unwrap().disconnected(Instant::now()),
+        }
+    }
+
+    /// 接收事件并在断开后重连，直到本地的事件接收端关闭
+    ///
+    /// `client` 是已经建立的连接(比如为了用握手信息分配 [`TabletId`]), 为 `None` 时先连接.
+    /// 重连后服务端的数位板列表变化时，新出现的数位板会被忽略
+    pub async fn run(mut self, client: Option<RemoteClient<TcpStream>>) {
+        let geometry = self.geometry.take();
+        tokio::select! {
+            _ = self.connect(client) => {}
+            _ = follow_geometry(&self.mode, geometry) => {}
+        }
+        self.report(None);
+    }
+
+    async fn connect(&self, mut client: Option<RemoteClient<TcpStream>>) {
+        let mut held = Held::default();
+        let mut failures: u32 = 0;
+        let mut delay = RECONNECT_MIN;
+        while !self.closed() {
+            let current = match client.take() {
+                Some(client) => client,
+                None => match RemoteClient::connect_tcp(&self.address).await {
+                    Ok(client) => client,
+                    Err(e) => {
+                        failures += 1;
+                        warn!(
+                            "{}: 重连失败 (第 {failures} 次), {:.1} 秒后重试: {e:#}",
+                            self.address,
+                            delay.as_secs_f32()
+                        );
+                        self.report(Some(if failures >= OFFLINE_AFTER {
+                            LinkStatus::Offline
+                        } else {
+                            LinkStatus::Reconnecting
+                        }));
+                        tokio::time::sleep(delay).await;
+                        delay = (delay * 2).min(RECONNECT_MAX);
+                        continue;
+                    }
+                },
+            };
+            info!("已连接到远程服务端 {}", self.address);
+            failures = 0;
+            self.report(Some(LinkStatus::Connected));
+
+            let connected_at = Instant::now();
+            let sync = self
+                .sync
+                .as_ref()
+                .map(|(sync, token)| (sync, token.as_str()));
+            let result = receive_held(current, &self.mode, sync, &mut held).await;
+            self.release(&mut held).await;
+            match result {
+                Ok(()) => info!("远程服务端 {} 关闭了连接", self.address),
+                Err(e) => warn!("与远程服务端 {} 的连接出错: {e:#}", self.address),
+            }
+            if self.closed() {
+                break;
+            }
+            // 连接刚建立就断开时继续退避，避免反复快速重连
+            if connected_at.elapsed() >= PEER_TIMEOUT {
+                delay = RECONNECT_MIN;
+            }
+            self.report(Some(LinkStatus::Reconnecting));
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(RECONNECT_MAX);
+        }
+    }
+}
+
+/// 把本地的显示器布局交给只看模式的墨迹. 不会返回
+async fn follow_geometry(mode: &RemoteMode, geometry: Option<watch::Receiver<GeometryChanged>>) {
+    if let (RemoteMode::ViewOnly(viewer), Some(mut geometry)) = (mode, geometry) {
+        while geometry.changed().await.is_ok() {
+            let current = geometry.borrow_and_update().clone();
+            viewer.lock().unwrap().apply_geometry(&current);
+        }
+    }
+    std::future::pending().await
+}
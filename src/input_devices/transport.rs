@@ -0,0 +1,209 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+    hash::Hash,
+};
+
+use crate::event_model::{
+    event::{AuxButtonEvent, PenButton, PenLocation, TabletEvent},
+    stamp::EventStamp,
+};
+
+/// 数位板的连接方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Transport {
+    Usb,
+    Bluetooth,
+    /// 通过 `tabletd API` 接收的远程数位板
+    Remote,
+    /// 回放的录制文件, 见 [`super::recorder`]
+    Replay,
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Transport::Usb => "USB",
+            Transport::Bluetooth => "蓝牙",
+            Transport::Remote => "远程",
+            Transport::Replay => "回放",
+        })
+    }
+}
+
+impl Transport {
+    /// 优先级，数值越小越优先
+    fn priority(self) -> u8 {
+        match self {
+            Transport::Usb => 0,
+            Transport::Bluetooth => 1,
+            Transport::Remote => 2,
+            Transport::Replay => 3,
+        }
+    }
+}
+
+/// 断开连接后的结果
+#[derive(Debug, Clone)]
+pub enum Disconnected {
+    /// 断开的不是正在使用的连接，什么都不用做
+    Inactive,
+    /// 已切换到另一个连接
+    Failover(Transport),
+    /// 所有连接都断开了，需要依次补发这些松开事件: 按着的笔、笔杆上的按键和快捷键.
+    /// 序号接着最后一个事件
+    Lost(Vec<(TabletEvent, EventStamp)>),
+}
+
+#[derive(Debug, Default)]
+struct DeviceTransports {
+    connected: Vec<Transport>,
+    active: Option<Transport>,
+    /// 最后一个笔事件，用于判断是否处于笔画中
+    last_pen: Option<TabletEvent>,
+    /// 笔杆上按键的状态
+    pen_buttons: PenButton,
+    /// 按着的快捷键
+    aux_held: BTreeSet<u8>,
+    /// 最后一个通过的事件
+    last_stamp: Option<EventStamp>,
+}
+
+impl DeviceTransports {
+    fn pen_down(&self) -> bool {
+        matches!(
+            &self.last_pen,
+            Some(TabletEvent::PenEvent(pen)) if matches!(pen.location, PenLocation::Pressed)
+        )
+    }
+
+    fn preferred(&self) -> Option<Transport> {
+        self.connected.iter().copied().min_by_key(|t| t.priority())
+    }
+
+    /// 松开所有还按着的笔和按键
+    fn releases(&mut self) -> Vec<(TabletEvent, EventStamp)> {
+        let mut events = Vec::new();
+        if self.pen_down()
+            && let Some(TabletEvent::PenEvent(mut pen)) = self.last_pen.take()
+        {
+            pen.location = PenLocation::Leaved;
+            pen.pressure = 0;
+            events.push(TabletEvent::PenEvent(pen));
+        }
+        if self.pen_buttons != PenButton::default() {
+            events.push(TabletEvent::PenButton(PenButton::default()));
+        }
+        events.extend(
+            std::mem::take(&mut self.aux_held)
+                .into_iter()
+                .map(|button_id| {
+                    TabletEvent::AuxButton(AuxButtonEvent {
+                        button_id,
+                        pressed: false,
+                    })
+                }),
+        );
+        let mut stamp = self.last_stamp.unwrap_or_default();
+        events
+            .into_iter()
+            .map(|event| {
+                stamp = stamp.follow();
+                (event, stamp)
+            })
+            .collect()
+    }
+}
+
+/// 同一块数位板同时通过 USB 和蓝牙连接时，只让一个连接的事件通过
+///
+/// 优先使用 USB. 正在使用的连接断开时立刻切换到另一个连接，
+/// 这样笔画中途拔掉 USB 也不会断笔; 更优先的连接接入时，等当前笔画结束再切换
+pub struct TransportArbiter<K> {
+    devices: HashMap<K, DeviceTransports>,
+}
+
+impl<K: Eq + Hash + Clone> TransportArbiter<K> {
+    pub fn new() -> Self {
+        Self {
+            devices: HashMap::new(),
+        }
+    }
+
+    /// 当前正在使用的连接
+    pub fn active(&self, device: &K) -> Option<Transport> {
+        self.devices.get(device)?.active
+    }
+
+    /// 某个连接已接入
+    pub fn connect(&mut self, device: K, transport: Transport) {
+        let state = self.devices.entry(device).or_default();
+        if !state.connected.contains(&transport) {
+            state.connected.push(transport);
+        }
+        if state.active.is_none() || !state.pen_down() {
+            state.active = state.preferred();
+        }
+    }
+
+    /// 某个连接已断开
+    pub fn disconnect(&mut self, device: &K, transport: Transport) -> Disconnected {
+        let Some(state) = self.devices.get_mut(device) else {
+            return Disconnected::Inactive;
+        };
+        state.connected.retain(|t| *t != transport);
+        if state.active != Some(transport) {
+            return Disconnected::Inactive;
+        }
+
+        state.active = state.preferred();
+        match state.active {
+            Some(next) => Disconnected::Failover(next),
+            None => {
+                let releases = state.releases();
+                self.devices.remove(device);
+                Disconnected::Lost(releases)
+            }
+        }
+    }
+
+    /// 判断来自 `transport` 的事件是否应该通过
+    pub fn accept(
+        &mut self,
+        device: &K,
+        transport: Transport,
+        event: &TabletEvent,
+        stamp: EventStamp,
+    ) -> bool {
+        let Some(state) = self.devices.get_mut(device) else {
+            return false;
+        };
+        if state.active != Some(transport) {
+            return false;
+        }
+
+        match event {
+            TabletEvent::PenEvent(_) => state.last_pen = Some(event.clone()),
+            TabletEvent::PenButton(buttons) => state.pen_buttons = *buttons,
+            TabletEvent::AuxButton(button) if button.pressed => {
+                state.aux_held.insert(button.button_id);
+            }
+            TabletEvent::AuxButton(button) => {
+                state.aux_held.remove(&button.button_id);
+            }
+            _ => {}
+        }
+        state.last_stamp = Some(stamp);
+        // 笔画结束后，切换到更优先的连接
+        if !state.pen_down() {
+            state.active = state.preferred();
+        }
+        true
+    }
+}
+
+impl<K: Eq + Hash + Clone> Default for TransportArbiter<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
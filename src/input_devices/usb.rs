@@ -1 +1,103 @@
-pub struct UsbBackend {}
+use crate::tablet_driver::{
+    self, ReportParser,
+    keypad::{self, KeypadParser, KeypadSpec},
+    quirks::DeviceQuirk,
+    spec::DeviceSpec,
+    uclogic,
+};
+
+use super::hidraw::{self, HidrawNode};
+
+/// HID 总线类型: USB
+const BUS_USB: u16 = 0x0003;
+
+/// `USB` 后端，通过 hidraw 访问 USB 数位板
+pub struct UsbBackend {
+    specs: Vec<DeviceSpec>,
+    keypads: Vec<KeypadSpec>,
+    quirks: Vec<DeviceQuirk>,
+}
+
+impl UsbBackend {
+    /// `specs` 是除内置设备外，额外支持的设备(通常来自配置文件)
+    pub fn new(specs: Vec<DeviceSpec>) -> Self {
+        let mut all = tablet_driver::spec::builtin_specs();
+        all.extend(specs);
+        Self {
+            specs: all,
+            keypads: Vec::new(),
+            quirks: Vec::new(),
+        }
+    }
+
+    /// 同时支持 `keypads` 中的按键设备
+    pub fn set_keypads(&mut self, keypads: Vec<KeypadSpec>) {
+        self.keypads = keypads;
+    }
+
+    /// 接入时按 `quirks` 初始化设备、修正参数, 见 [`tablet_driver::quirks`]
+    pub fn set_quirks(&mut self, quirks: Vec<DeviceQuirk>) {
+        self.quirks = quirks;
+    }
+
+    /// 节点属于某种按键设备, 不一定是报告按键的接口
+    fn is_keypad(&self, node: &HidrawNode) -> bool {
+        self.keypads
+            .iter()
+            .any(|spec| spec.is_model(node.vendor_id, node.product_id))
+    }
+
+    fn keypad(&self, node: &HidrawNode) -> Option<&KeypadSpec> {
+        keypad::find(
+            node.vendor_id,
+            node.product_id,
+            node.usb_interface(),
+            &self.keypads,
+        )
+    }
+
+    /// 查找已连接的、受支持的 USB 数位板
+    pub fn scan(&self) -> Vec<(HidrawNode, Box<dyn ReportParser>)> {
+        self.nodes()
+            .into_iter()
+            .filter_map(|node| {
+                let parser = self.probe(&node)?;
+                Some((node, parser))
+            })
+            .collect()
+    }
+
+    /// 可能是数位板的 USB hidraw 节点, 还没有选择解析器
+    pub fn nodes(&self) -> Vec<HidrawNode> {
+        hidraw::enumerate()
+            .into_iter()
+            .filter(|node| node.bus == BUS_USB)
+            .filter(|node| {
+                // 按键设备只使用描述中的接口
+                if self.is_keypad(node) {
+                    return self.keypad(node).is_some();
+                }
+                // UC-Logic 系列每个接口都有一个 hidraw 节点，只有一个接口报告数位板数据
+                !uclogic::is_uclogic(node.vendor_id)
+                    || node.usb_interface() == Some(uclogic::report_interface(node.vendor_id))
+            })
+            .collect()
+    }
+
+    /// 为节点选择解析器, 不受支持时返回 `None`. UC-Logic 设备和有初始化序列的设备会在这时切换模式
+    ///
+    /// 按键设备先于数位板匹配, 和数位板同一厂商的按键设备不会被当作 UC-Logic 数位板初始化
+    pub fn probe(&self, node: &HidrawNode) -> Option<Box<dyn ReportParser>> {
+        if self.is_keypad(node) {
+            let spec = self.keypad(node)?.clone();
+            return Some(Box::new(KeypadParser::new(spec)));
+        }
+        tablet_driver::parser_for_node(node, &self.specs, &self.quirks)
+    }
+}
+
+impl Default for UsbBackend {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
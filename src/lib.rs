@@ -11,8 +11,7 @@ pub mod tablet_driver;
 pub mod hud_interface;
 
 /// 屏幕叠加层接口，用于显示光标和 HUD
-// TODO: 人工修一下cursor整的烂活
-// pub mod screen_overlay;
+pub mod screen_overlay;
 
 /// 原始输入接口实现（如 USB 和蓝牙设备）
 pub mod input_devices;
@@ -23,6 +22,39 @@ pub mod event_router;
 /// 数位板事件的抽象层，定义事件模型
 pub mod event_model;
 
+/// 数位板到屏幕的坐标映射
+pub mod mapping;
+
+/// 用户配置的数位板设置
+pub mod profile;
+
+/// 配置文件的加载和热重载
+pub mod config;
+
+/// 退出时按顺序清理各子系统
+pub mod shutdown;
+
+/// 守护进程的运行模式
+pub mod daemon;
+
+/// 启动子系统并在崩溃后重启
+pub mod supervisor;
+
+/// 启动自检，逐个检查各子系统能否正常工作
+pub mod self_test;
+
+/// 数值和单位的格式化
+pub mod units;
+
+/// 各模块的错误类型
+pub mod error;
+
+/// 基于 `tracing` 的日志和运行时的过滤规则
+pub mod logging;
+
+/// 对外的稳定接口, 把 tabletd 当作库使用时从这里导入
+pub mod prelude;
+
 // `screen_overlay`要做的事情就是给每个显示器都创建一个全屏overlay
 // 然后通过DMA或者什么东西暴露出接口，由`hud_interface`渲染每个overlay的界面
 // 至于光标要不要单独整一个overlay.. 如果移动它的效率很高，而且开销比重新渲染更低，那可以考虑这样
@@ -34,10 +66,9 @@ pub mod event_model;
 // 需要能够处理接入多个数位板的情况，每个光标可以使用不同颜色标注，光标旁可以显示文字
 // 当然还有不同屏幕，甚至有人喜欢给不同的屏幕设置不同的缩放比例
 
-// wayland协议并不支持分数缩放，看样子这是混成器自己搞的奇怪东西，把buffer放大又缩小
-// 在 event_model 或 event_dispatcher 层面实现对分数缩放的支持。
-// 将逻辑坐标转换为整数缩放比例，并通过 Wayland 提供的 API 调整输出分辨率。
-// 提供用户选项，允许手动指定分数缩放比例。
+// 分数缩放靠 wp-fractional-scale-v1 拿到显示器真正的缩放比例(比如 1.25, 1.5)，overlay 按这个比例分配 buffer，
+// 再用 wp-viewporter 缩放回逻辑尺寸，这样画出来的东西和光标坐标都是按实际像素算的。
+// 混成器不支持这两个协议的话就退回整数缩放，buffer 只能放大又缩小了
 
 // HUD `hud_interface` 用来显示提示信息(我挺喜欢 osu!lazer 那个风格), 比如数位板接入，拔出等事件
 // 当然，三星的 s pen 也可以抄抄，比如按下笔上的按钮之后弹出快捷菜单
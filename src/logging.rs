@@ -0,0 +1,152 @@
+//! 日志
+//!
+//! 诊断信息通过 `tracing` 输出到 stderr. 每个子系统运行在名为 `subsystem` 的 span 中,
+//! 每块数位板的驱动运行在名为 `device` 的 span 中, 日志的前缀会显示它们.
+//!
+//! 过滤规则使用 `RUST_LOG` 的语法, 设置了 `RUST_LOG` 时优先使用它. 运行时可以用
+//! `tabletctl log <FILTER>` 修改, 重启后恢复配置文件中的设置.
+//!
+//! 打开 `tabletd::hid` 的 trace 级别时输出每个原始 HID 报告, 每块数位板每秒最多
+//! `hid_dump_rate` 个, 超出的只记录数量
+//!
+//! ```toml
+//! [daemon.log]
+//! filter = "info,tabletd::hid=trace"
+//! hid_dump_rate = 20
+//! ```
+
+use std::{
+    fmt::Write,
+    sync::{
+        Mutex,
+        atomic::{AtomicU32, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, anyhow};
+use serde::{Deserialize, Serialize};
+use tracing::{Level, trace};
+use tracing_subscriber::{
+    EnvFilter, Registry, fmt, layer::SubscriberExt, reload, util::SubscriberInitExt,
+};
+
+/// 原始 HID 报告使用的 target
+pub const HID_TARGET: &str = "tabletd::hid";
+
+/// 配置文件中的 `[daemon.log]`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    /// 过滤规则, 例如 `info,tabletd::input_devices=debug`
+    pub filter: String,
+    /// 每块数位板每秒最多输出多少个原始 HID 报告
+    pub hid_dump_rate: u32,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            filter: "info".to_string(),
+            hid_dump_rate: 20,
+        }
+    }
+}
+
+static FILTER: Mutex<Option<reload::Handle<EnvFilter, Registry>>> = Mutex::new(None);
+static HID_DUMP_RATE: AtomicU32 = AtomicU32::new(20);
+
+/// 安装输出到 stderr 的 subscriber. 已经安装过其他 subscriber 时(比如测试中)不做任何事
+pub fn init(config: &LogConfig) {
+    HID_DUMP_RATE.store(config.hid_dump_rate, Ordering::Relaxed);
+    let filter = std::env::var("RUST_LOG")
+        .ok()
+        .and_then(|spec| EnvFilter::try_new(spec).ok())
+        .or_else(|| EnvFilter::try_new(&config.filter).ok())
+        .unwrap_or_else(|| {
+            eprintln!("日志过滤规则 {:?} 有错误, 使用 info", config.filter);
+            EnvFilter::new("info")
+        });
+    let (filter, handle) = reload::Layer::new(filter);
+    let installed = tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer().with_writer(std::io::stderr))
+        .try_init();
+    if installed.is_ok() {
+        *FILTER.lock().unwrap() = Some(handle);
+    }
+}
+
+/// 当前的过滤规则, 没有调用 [`init`] 时为 `None`
+pub fn filter() -> Option<String> {
+    FILTER
+        .lock()
+        .unwrap()
+        .as_ref()?
+        .with_current(|filter| filter.to_string())
+        .ok()
+}
+
+/// 修改过滤规则, 返回修改后的规则
+pub fn set_filter(spec: &str) -> anyhow::Result<String> {
+    let filter = EnvFilter::try_new(spec).with_context(|| format!("无效的过滤规则 {spec:?}"))?;
+    let filter_text = filter.to_string();
+    FILTER
+        .lock()
+        .unwrap()
+        .as_ref()
+        .context("日志没有初始化")?
+        .reload(filter)
+        .map_err(|e| anyhow!("无法修改过滤规则: {e}"))?;
+    Ok(filter_text)
+}
+
+/// 按频率限制原始 HID 报告的输出, 每块数位板一个
+#[derive(Debug)]
+pub struct HidDump {
+    window: Instant,
+    dumped: u32,
+    suppressed: u64,
+}
+
+impl Default for HidDump {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HidDump {
+    pub fn new() -> Self {
+        Self {
+            window: Instant::now(),
+            dumped: 0,
+            suppressed: 0,
+        }
+    }
+
+    /// 打开了 [`HID_TARGET`] 的 trace 级别时输出报告
+    pub fn report(&mut self, report: &[u8]) {
+        if !tracing::enabled!(target: HID_TARGET, Level::TRACE) {
+            return;
+        }
+        let now = Instant::now();
+        if now.duration_since(self.window) >= Duration::from_secs(1) {
+            if self.suppressed > 0 {
+                trace!(target: HID_TARGET, "超出频率限制, 省略了 {} 个报告", self.suppressed);
+            }
+            self.window = now;
+            self.dumped = 0;
+            self.suppressed = 0;
+        }
+        if self.dumped >= HID_DUMP_RATE.load(Ordering::Relaxed) {
+            self.suppressed += 1;
+            return;
+        }
+        self.dumped += 1;
+        let mut hex = String::with_capacity(report.len() * 3);
+        for byte in report {
+            let _ = write!(hex, "{byte:02x} ");
+        }
+        trace!(target: HID_TARGET, "{}", hex.trim_end());
+    }
+}
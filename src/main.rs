@@ -1,10 +1,102 @@
-// use tabletd::screen_overlay;
+use std::time::Duration;
+
+use clap::Parser;
+use tabletd::{
+    config::Config,
+    daemon::{DaemonMode, tasks},
+    input_devices::privilege,
+    logging,
+    screen_overlay::strategy::{StrategyCache, compositor_name},
+    self_test::{self, bench, soak},
+    units::{self, Units},
+};
+
+/// Userspace tablet driver
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// 逐个检查各子系统并输出报告
+    #[arg(long)]
+    self_test: bool,
+    /// 用合成输入长时间驱动虚拟数位板(单位: 分钟), 检查内存、文件描述符和延迟的增长
+    #[arg(long, value_name = "MINUTES")]
+    soak: Option<u64>,
+    /// 比较各种 overlay 绘制方式的性能, 记住当前合成器上最快的方式
+    #[arg(long)]
+    bench_overlay: bool,
+    /// 运行模式(full、overlay_only、input_only), 覆盖配置文件中的 `[daemon] mode`
+    #[arg(long, value_name = "MODE")]
+    mode: Option<DaemonMode>,
+    /// 作为特权辅助进程运行, 由 pkexec 或 systemd 启动
+    #[arg(long, hide = true)]
+    raw_io_helper: bool,
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    println!("Hello, world!");
+    let cli = Cli::parse();
+    // 辅助进程以 root 运行, 不读取用户的配置
+    if cli.raw_io_helper {
+        logging::init(&Default::default());
+        return privilege::serve();
+    }
+
+    let loaded = Config::load(&Config::default_path());
+    logging::init(
+        &loaded
+            .as_ref()
+            .map(|config| config.daemon.log.clone())
+            .unwrap_or_default(),
+    );
+    let config = loaded.unwrap_or_else(|e| {
+        tracing::warn!("无法读取配置, 使用默认配置: {e:#}");
+        Config::default()
+    });
+    // 报告中的数值按配置文件中的单位显示
+    units::set(Units::from_config(&config.units));
+    let mut daemon = config.daemon.clone();
+    if let Some(mode) = cli.mode {
+        daemon.mode = mode;
+    }
+    daemon.validate(&config.api)?;
+    let plan = daemon.plan();
+    privilege::configure(&daemon.helper);
+
+    if cli.self_test {
+        let report = self_test::run(&plan, &daemon.startup, &config.overlay.display).await;
+        println!("{report}");
+        std::process::exit(if report.passed() { 0 } else { 1 });
+    }
+
+    if let Some(minutes) = cli.soak {
+        let config = soak::SoakConfig {
+            duration: Duration::from_secs(minutes * 60),
+            ..Default::default()
+        };
+        let report = soak::run(config).await;
+        println!("{report}");
+        std::process::exit(if report.passed() { 0 } else { 1 });
+    }
 
-    // screen_overlay::backend_wayland::test_overlay().await?;
+    if cli.bench_overlay {
+        let report = bench::run(bench::BenchConfig::default()).await;
+        println!("{report}");
+        if report.failure.is_some() || report.fastest().is_none() {
+            std::process::exit(1);
+        }
+        let mut cache = StrategyCache::load(StrategyCache::default_path())?;
+        let compositor = compositor_name();
+        if let Some(strategy) = cache.record(&compositor, report.reports) {
+            println!("{compositor} 上默认使用 {strategy}");
+        }
+        return Ok(());
+    }
 
+    tracing::info!("{plan}");
+    let report = tasks::run(config, &daemon).await?;
+    tracing::info!("{report}");
+    if !report.is_clean() {
+        std::process::exit(1);
+    }
     Ok(())
 }
@@ -0,0 +1,67 @@
+//! 显示器边界的阻力
+//!
+//! 映射到多个显示器时，笔在边界附近快速画线很容易让光标跳到另一个显示器.
+//! 打开 [`super::MappingConfig::edge_resistance`] 后，光标越过边界时先停在原来的显示器边缘，
+//! 继续移动超过这个距离才会进入另一个显示器. 数位板是绝对定位的, 所以进入之后光标直接回到笔的位置
+
+use super::OutputGeometry;
+
+/// 一支笔的边界阻力状态
+#[derive(Debug, Clone, Default)]
+pub struct EdgeBarrier {
+    /// 光标当前所在的显示器
+    current: Option<String>,
+}
+
+impl EdgeBarrier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 笔离开感应范围后重新进入时，直接落在笔所在的显示器上
+    pub fn reset(&mut self) {
+        self.current = None;
+    }
+
+    /// 光标当前所在的显示器
+    pub fn current(&self) -> Option<&str> {
+        self.current.as_deref()
+    }
+
+    /// 按阻力调整逻辑坐标, `resistance` 不大于 0 时不调整
+    pub fn apply(
+        &mut self,
+        outputs: &[OutputGeometry],
+        resistance: f64,
+        x: f64,
+        y: f64,
+    ) -> (f64, f64) {
+        let under = outputs
+            .iter()
+            .find(|output| output.logical_rect().contains(x, y));
+        let current = self
+            .current
+            .as_deref()
+            .and_then(|name| outputs.iter().find(|output| output.name == name));
+        let Some(current) = current.filter(|_| resistance > 0.0) else {
+            self.current = under.map(|output| output.name.clone());
+            return (x, y);
+        };
+
+        let rect = current.logical_rect();
+        if rect.contains(x, y) {
+            return (x, y);
+        }
+        let right = rect.x + rect.width;
+        let bottom = rect.y + rect.height;
+        let dx = (rect.x - x).max(x - right).max(0.0);
+        let dy = (rect.y - y).max(y - bottom).max(0.0);
+        if dx.hypot(dy) < resistance {
+            // `contains` 不包括右边和下边，停在边缘内侧
+            let inside = |value: f64, low: f64, high: f64| value.clamp(low, (high - 1e-6).max(low));
+            return (inside(x, rect.x, right), inside(y, rect.y, bottom));
+        }
+        self.current = under.map(|output| output.name.clone());
+        (x, y)
+    }
+}
@@ -0,0 +1,250 @@
+//! 屏幕映射的校准
+//!
+//! 数位屏的数位板和屏幕不会完全对齐, 数位板本身也可能有轻微的倾斜和缩放误差. 校准时 HUD 在
+//! 映射区域中依次显示 [`CALIBRATION_TARGETS`], 用户用笔点击它们, 再用最小二乘法求出把点到的
+//! 位置变换到目标位置的仿射变换, 保存在这块数位板设置的 `[mapping.calibration]` 中.
+//!
+//! 坐标都是映射区域内的归一化坐标(0 ~ 1), 所以显示器的分辨率和布局变化后校准仍然有效.
+//! 点击位置不经过原来的校准, 重新校准得到的是完整的变换
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+use crate::event_model::tablet::TabletId;
+
+use super::Rect;
+
+/// 依次显示的目标点, 映射区域内的归一化坐标
+pub const CALIBRATION_TARGETS: [(f64, f64); 5] =
+    [(0.1, 0.1), (0.9, 0.1), (0.9, 0.9), (0.1, 0.9), (0.5, 0.5)];
+
+/// 校准把映射区域的角移动超过这个距离(归一化)时认为点错了目标
+const MAX_CORRECTION: f64 = 0.15;
+
+/// 映射区域内的仿射变换 `x' = a x + b y + c`, `y' = d x + e y + f`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+    /// `[a, b, c, d, e, f]`
+    pub matrix: [f64; 6],
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+/// 校准时的一次点击
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CalibrationSample {
+    /// 显示的目标点
+    pub expected: (f64, f64),
+    /// 笔点到的位置, 不经过校准
+    pub measured: (f64, f64),
+}
+
+impl Calibration {
+    pub const IDENTITY: Calibration = Calibration {
+        matrix: [1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+    };
+
+    pub fn apply(&self, x: f64, y: f64) -> (f64, f64) {
+        let [a, b, c, d, e, f] = self.matrix;
+        (a * x + b * y + c, d * x + e * y + f)
+    }
+
+    /// 用最小二乘法求出把 `measured` 变换到 `expected` 的仿射变换
+    ///
+    /// 至少需要三个不在同一直线上的点. 变换偏离原来的映射太多时, 多半是点错了目标, 返回错误
+    pub fn fit(samples: &[CalibrationSample]) -> anyhow::Result<Self> {
+        if samples.len() < 3 {
+            bail!("至少需要三个点, 只有 {} 个", samples.len());
+        }
+        // 正规方程 (Σ p pᵀ) [a b c]ᵀ = Σ p x', p = (x, y, 1)
+        let mut normal = [[0.0; 3]; 3];
+        let mut rhs_x = [0.0; 3];
+        let mut rhs_y = [0.0; 3];
+        for sample in samples {
+            let p = [sample.measured.0, sample.measured.1, 1.0];
+            for i in 0..3 {
+                for j in 0..3 {
+                    normal[i][j] += p[i] * p[j];
+                }
+                rhs_x[i] += p[i] * sample.expected.0;
+                rhs_y[i] += p[i] * sample.expected.1;
+            }
+        }
+        let (Some(row_x), Some(row_y)) = (solve(normal, rhs_x), solve(normal, rhs_y)) else {
+            bail!("点击的位置几乎在一条直线上");
+        };
+        let calibration = Calibration {
+            matrix: [row_x[0], row_x[1], row_x[2], row_y[0], row_y[1], row_y[2]],
+        };
+        let correction = [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)]
+            .into_iter()
+            .map(|(x, y)| {
+                let (cx, cy) = calibration.apply(x, y);
+                (cx - x).hypot(cy - y)
+            })
+            .fold(0.0, f64::max);
+        if correction > MAX_CORRECTION {
+            bail!(
+                "需要把映射区域移动 {:.0}%, 可能点错了目标",
+                correction * 100.0
+            );
+        }
+        Ok(calibration)
+    }
+
+    /// 校准后点击位置和目标之间的最大距离(归一化)
+    pub fn residual(&self, samples: &[CalibrationSample]) -> f64 {
+        samples
+            .iter()
+            .map(|sample| {
+                let (x, y) = self.apply(sample.measured.0, sample.measured.1);
+                (x - sample.expected.0).hypot(y - sample.expected.1)
+            })
+            .fold(0.0, f64::max)
+    }
+}
+
+/// 克拉默法则解 3×3 线性方程组, 系数矩阵接近奇异时返回 `None`
+fn solve(m: [[f64; 3]; 3], rhs: [f64; 3]) -> Option<[f64; 3]> {
+    let det = |m: &[[f64; 3]; 3]| {
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    };
+    let d = det(&m);
+    if d.abs() < 1e-9 {
+        return None;
+    }
+    let mut solution = [0.0; 3];
+    for (column, value) in solution.iter_mut().enumerate() {
+        let mut replaced = m;
+        for row in 0..3 {
+            replaced[row][column] = rhs[row];
+        }
+        *value = det(&replaced) / d;
+    }
+    Some(solution)
+}
+
+/// 校准完成或取消时调用, 参数是数位板、结果和所有点击
+pub type CalibrationDone =
+    Box<dyn FnOnce(TabletId, anyhow::Result<Calibration>, &[CalibrationSample]) + Send>;
+
+/// 正在进行的校准
+struct CalibrationSession {
+    tablet: TabletId,
+    /// 映射区域的逻辑坐标, HUD 在这里显示目标点
+    target: Option<Rect>,
+    samples: Vec<CalibrationSample>,
+    done: CalibrationDone,
+}
+
+/// HUD 显示的校准进度
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CalibrationProgress {
+    pub tablet: TabletId,
+    pub target: Option<Rect>,
+    /// 下一个目标点在 [`CALIBRATION_TARGETS`] 中的位置
+    pub step: usize,
+}
+
+/// 正在进行的校准, 同一时间只能校准一块数位板
+///
+/// 控制接口开始和取消校准, 路由器的 [`crate::event_router::screen::MapToScreen`] 记录点击,
+/// HUD 显示目标点
+#[derive(Clone, Default)]
+pub struct CalibrationView {
+    session: Arc<Mutex<Option<CalibrationSession>>>,
+}
+
+impl CalibrationView {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 开始校准 `tablet`, 完成或取消时调用 `done`. 正在校准另一块数位板时先取消它
+    pub fn start(&self, tablet: TabletId, target: Option<Rect>, done: CalibrationDone) {
+        let previous = self.session.lock().unwrap().replace(CalibrationSession {
+            tablet,
+            target,
+            samples: Vec::new(),
+            done,
+        });
+        if let Some(previous) = previous {
+            (previous.done)(previous.tablet, Err(anyhow::anyhow!("已取消")), &[]);
+        }
+    }
+
+    /// 取消校准, 没有在校准时返回 `false`
+    pub fn cancel(&self) -> bool {
+        let session = self.session.lock().unwrap().take();
+        let Some(session) = session else {
+            return false;
+        };
+        (session.done)(
+            session.tablet,
+            Err(anyhow::anyhow!("已取消")),
+            &session.samples,
+        );
+        true
+    }
+
+    pub fn is_calibrating(&self, tablet: TabletId) -> bool {
+        self.session
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|session| session.tablet == tablet)
+    }
+
+    pub fn progress(&self) -> Option<CalibrationProgress> {
+        self.session
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|session| CalibrationProgress {
+                tablet: session.tablet,
+                target: session.target,
+                step: session.samples.len(),
+            })
+    }
+
+    /// 映射区域变化
+    pub fn set_target(&self, tablet: TabletId, target: Option<Rect>) {
+        if let Some(session) = self.session.lock().unwrap().as_mut()
+            && session.tablet == tablet
+        {
+            session.target = target;
+        }
+    }
+
+    /// 记录一次点击, `measured` 是不经过校准的映射区域内的归一化坐标.
+    /// 点完所有目标后求出校准并结束
+    pub fn record(&self, tablet: TabletId, measured: (f64, f64)) {
+        let finished = {
+            let mut session = self.session.lock().unwrap();
+            let Some(current) = session.as_mut().filter(|session| session.tablet == tablet) else {
+                return;
+            };
+            current.samples.push(CalibrationSample {
+                expected: CALIBRATION_TARGETS[current.samples.len()],
+                measured,
+            });
+            if current.samples.len() < CALIBRATION_TARGETS.len() {
+                return;
+            }
+            session.take()
+        };
+        // 回调可能修改配置, 不在锁内调用
+        if let Some(session) = finished {
+            let result = Calibration::fit(&session.samples);
+            (session.done)(session.tablet, result, &session.samples);
+        }
+    }
+}
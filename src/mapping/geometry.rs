@@ -0,0 +1,86 @@
+use serde::{Deserialize, Serialize};
+use tokio::{sync::watch, task::JoinHandle};
+
+use crate::hud_interface::{HudEvent, HudSender};
+
+use super::{OutputGeometry, Rect};
+
+/// 显示器布局变化(分辨率、缩放比例、位置改变或者热插拔)
+///
+/// 总是携带完整的布局，而不是变化的部分
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct GeometryChanged {
+    pub outputs: Vec<OutputGeometry>,
+}
+
+impl GeometryChanged {
+    pub fn output(&self, name: &str) -> Option<&OutputGeometry> {
+        self.outputs.iter().find(|output| output.name == name)
+    }
+
+    /// 包含所有显示器的矩形
+    pub fn bounds(&self) -> Option<Rect> {
+        self.outputs
+            .iter()
+            .map(OutputGeometry::logical_rect)
+            .reduce(|a, b| a.union(&b))
+    }
+}
+
+/// 显示器布局的广播通道
+///
+/// 由 overlay 后端发布，映射、光标、HUD 和 `tabletd API` 订阅.
+/// 订阅者总能拿到最新的布局，中间的变化可能被合并
+#[derive(Clone)]
+pub struct GeometryBus {
+    tx: watch::Sender<GeometryChanged>,
+}
+
+impl GeometryBus {
+    pub fn new() -> Self {
+        Self {
+            tx: watch::Sender::new(GeometryChanged::default()),
+        }
+    }
+
+    /// 发布新的布局，和当前布局相同时不会通知订阅者
+    pub fn publish(&self, outputs: Vec<OutputGeometry>) {
+        self.tx.send_if_modified(|current| {
+            if current.outputs == outputs {
+                return false;
+            }
+            current.outputs = outputs;
+            true
+        });
+    }
+
+    /// 当前的布局
+    pub fn current(&self) -> GeometryChanged {
+        self.tx.borrow().clone()
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<GeometryChanged> {
+        self.tx.subscribe()
+    }
+
+    /// 把布局变化转发给 HUD
+    pub fn forward_to_hud(&self, hud: HudSender) -> JoinHandle<()> {
+        let mut rx = self.subscribe();
+        tokio::spawn(async move {
+            loop {
+                let geometry = rx.borrow_and_update().clone();
+                if hud.send(HudEvent::GeometryChanged(geometry)).is_err()
+                    || rx.changed().await.is_err()
+                {
+                    break;
+                }
+            }
+        })
+    }
+}
+
+impl Default for GeometryBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
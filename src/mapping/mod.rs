@@ -0,0 +1,418 @@
+//! 数位板 -> 屏幕的映射
+//!
+//! 所有显示器放在同一个逻辑坐标系(混成器的布局坐标)里，
+//! 数位板先映射到这个坐标系中的一个矩形，再换算成具体显示器上的像素.
+//! 每个显示器的缩放比例可以不同，换算像素时分别处理
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    event_model::{
+        capability::{DeviceCapabilities, DeviceClass},
+        coordinate::ScreenMapping,
+    },
+    screen_overlay::id::OutputId,
+};
+
+use barrier::EdgeBarrier;
+use calibration::Calibration;
+use geometry::GeometryChanged;
+
+/// 显示器边界的阻力
+pub mod barrier;
+/// 映射的校准
+pub mod calibration;
+/// 显示器布局变化的通知
+pub mod geometry;
+/// 映射设置的示意图
+pub mod preview;
+
+/// 逻辑坐标系中的矩形
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct Rect {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Rect {
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+
+    /// 两个矩形是否重叠, 只有边相接不算
+    pub fn intersects(&self, other: &Rect) -> bool {
+        self.x < other.x + other.width
+            && other.x < self.x + self.width
+            && self.y < other.y + other.height
+            && other.y < self.y + self.height
+    }
+
+    /// 包含两个矩形的最小矩形
+    fn union(&self, other: &Rect) -> Rect {
+        let left = self.x.min(other.x);
+        let top = self.y.min(other.y);
+        let right = (self.x + self.width).max(other.x + other.width);
+        let bottom = (self.y + self.height).max(other.y + other.height);
+        Rect {
+            x: left,
+            y: top,
+            width: right - left,
+            height: bottom - top,
+        }
+    }
+}
+
+/// 一个显示器在布局中的位置
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutputGeometry {
+    /// 由 overlay 后端分配，显示器重新接入后会变化
+    #[serde(default)]
+    pub id: Option<OutputId>,
+    pub name: String,
+    /// 逻辑坐标系中的左上角
+    pub x: f64,
+    pub y: f64,
+    /// 像素尺寸
+    pub width: u32,
+    pub height: u32,
+    /// 缩放比例，可以是分数
+    pub scale: f64,
+}
+
+impl OutputGeometry {
+    /// 显示器在逻辑坐标系中占据的矩形
+    pub fn logical_rect(&self) -> Rect {
+        let scale = if self.scale > 0.0 { self.scale } else { 1.0 };
+        Rect {
+            x: self.x,
+            y: self.y,
+            width: self.width as f64 / scale,
+            height: self.height as f64 / scale,
+        }
+    }
+
+    /// 逻辑坐标 -> 这个显示器上的像素坐标
+    pub fn to_pixels(&self, x: f64, y: f64) -> (f64, f64) {
+        let rect = self.logical_rect();
+        (
+            (x - rect.x) / rect.width * self.width as f64,
+            (y - rect.y) / rect.height * self.height as f64,
+        )
+    }
+}
+
+/// 映射的目标
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum MappingTarget {
+    /// 所有显示器组成的整个桌面
+    #[default]
+    Desktop,
+    /// 某一个显示器
+    Output { name: String },
+    /// 逻辑坐标系中的任意区域
+    Region(Rect),
+}
+
+/// 一块数位板的映射设置
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MappingConfig {
+    pub target: MappingTarget,
+    /// 只使用数位板的一部分，归一化坐标(0 ~ 1)，默认为整个工作区
+    pub area: Option<Rect>,
+    /// 裁剪数位板工作区，使其长宽比与目标一致
+    pub keep_aspect: bool,
+    /// 目标显示器断开时临时映射到的显示器, 不设置时使用主显示器(布局中最左上的一个)
+    pub fallback_output: Option<String>,
+    /// 光标从一个显示器进入另一个之前需要多移动的距离(逻辑像素), 0 表示没有阻力.
+    /// 只在映射到多个显示器时有用
+    pub edge_resistance: f64,
+    /// 数位屏的数位板和屏幕没有对齐时的修正, 由 `tabletctl calibrate` 求出
+    pub calibration: Option<Calibration>,
+}
+
+/// 显示器布局变化导致的映射目标变化
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputChange {
+    /// 目标显示器断开, 临时映射到 `to`, 没有可用的显示器时为 `None`
+    Reassigned { from: String, to: Option<String> },
+    /// 目标显示器重新接入，恢复原来的映射
+    Restored { output: String },
+}
+
+/// 映射到屏幕上的一个点
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScreenPoint {
+    /// 点所在的显示器
+    pub output: String,
+    pub output_id: Option<OutputId>,
+    /// 显示器上的像素坐标
+    pub x: f64,
+    pub y: f64,
+    /// 逻辑坐标
+    pub logical_x: f64,
+    pub logical_y: f64,
+}
+
+/// 映射引擎
+#[derive(Debug, Clone, Default)]
+pub struct Mapper {
+    config: MappingConfig,
+    outputs: Vec<OutputGeometry>,
+    /// 目标显示器断开时，临时使用的显示器
+    reassigned: Option<Option<String>>,
+}
+
+impl Mapper {
+    pub fn new(config: MappingConfig) -> Self {
+        Self {
+            config,
+            outputs: Vec::new(),
+            reassigned: None,
+        }
+    }
+
+    pub fn config(&self) -> &MappingConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: MappingConfig) {
+        self.config = config;
+        self.reassigned = None;
+        self.update_reassignment();
+    }
+
+    pub fn outputs(&self) -> &[OutputGeometry] {
+        &self.outputs
+    }
+
+    /// 显示器布局变化(热插拔、分辨率或缩放比例改变)时更新
+    ///
+    /// 映射的目标显示器断开或重新接入时返回变化
+    pub fn set_outputs(&mut self, outputs: Vec<OutputGeometry>) -> Option<OutputChange> {
+        self.outputs = outputs;
+        self.update_reassignment()
+    }
+
+    pub fn apply_geometry(&mut self, geometry: &GeometryChanged) -> Option<OutputChange> {
+        self.set_outputs(geometry.outputs.clone())
+    }
+
+    fn output(&self, name: &str) -> Option<&OutputGeometry> {
+        self.outputs.iter().find(|output| output.name == name)
+    }
+
+    /// 主显示器: 布局中最左上的一个
+    pub fn primary_output(&self) -> Option<&OutputGeometry> {
+        self.outputs
+            .iter()
+            .min_by(|a, b| a.y.total_cmp(&b.y).then(a.x.total_cmp(&b.x)))
+    }
+
+    /// 目标显示器断开时使用的显示器
+    fn fallback(&self) -> Option<&OutputGeometry> {
+        self.config
+            .fallback_output
+            .as_deref()
+            .and_then(|name| self.output(name))
+            .or_else(|| self.primary_output())
+    }
+
+    /// 映射到单个显示器时实际使用的显示器, 目标断开时为备用显示器
+    pub fn target_output(&self) -> Option<&OutputGeometry> {
+        let MappingTarget::Output { name } = &self.config.target else {
+            return None;
+        };
+        self.output(name).or_else(|| self.fallback())
+    }
+
+    /// 当前是否临时映射到了备用显示器
+    pub fn is_reassigned(&self) -> bool {
+        self.reassigned.is_some()
+    }
+
+    fn update_reassignment(&mut self) -> Option<OutputChange> {
+        let MappingTarget::Output { name } = &self.config.target else {
+            self.reassigned = None;
+            return None;
+        };
+        // 还没有收到显示器布局
+        if self.outputs.is_empty() && self.reassigned.is_none() {
+            return None;
+        }
+        let name = name.clone();
+        if self.output(&name).is_some() {
+            return self
+                .reassigned
+                .take()
+                .map(|_| OutputChange::Restored { output: name });
+        }
+        let to = self.fallback().map(|output| output.name.clone());
+        if self.reassigned.as_ref() == Some(&to) {
+            return None;
+        }
+        self.reassigned = Some(to.clone());
+        Some(OutputChange::Reassigned { from: name, to })
+    }
+
+    /// 按标识查找显示器, 标识已过期(显示器被移除或重新接入)时返回 `None`
+    pub fn output_by_id(&self, id: OutputId) -> Option<&OutputGeometry> {
+        self.outputs.iter().find(|output| output.id == Some(id))
+    }
+
+    /// 目标区域在逻辑坐标系中的矩形
+    pub fn target_rect(&self) -> Option<Rect> {
+        match &self.config.target {
+            MappingTarget::Desktop => self
+                .outputs
+                .iter()
+                .map(OutputGeometry::logical_rect)
+                .reduce(|a, b| a.union(&b)),
+            MappingTarget::Output { .. } => self.target_output().map(OutputGeometry::logical_rect),
+            MappingTarget::Region(rect) => Some(*rect),
+        }
+    }
+
+    /// 归一化的数位板坐标 -> 逻辑坐标
+    pub fn to_logical(&self, nx: f64, ny: f64, caps: &DeviceCapabilities) -> Option<(f64, f64)> {
+        let target = self.target_rect()?;
+        let (ax, ay) = self.to_target(nx, ny, caps)?;
+        let (ax, ay) = match &self.config.calibration {
+            Some(calibration) => {
+                let (x, y) = calibration.apply(ax, ay);
+                (x.clamp(0.0, 1.0), y.clamp(0.0, 1.0))
+            }
+            None => (ax, ay),
+        };
+        Some((target.x + ax * target.width, target.y + ay * target.height))
+    }
+
+    /// 归一化的数位板坐标 -> 映射区域内的归一化坐标, 不经过校准
+    fn to_target(&self, nx: f64, ny: f64, caps: &DeviceCapabilities) -> Option<(f64, f64)> {
+        let area = self.active_area(caps)?;
+        let ax = ((nx - area.x) / area.width).clamp(0.0, 1.0);
+        let ay = ((ny - area.y) / area.height).clamp(0.0, 1.0);
+        Some((ax, ay))
+    }
+
+    /// 设备坐标在映射区域内的归一化位置, 不经过校准. 校准时记录点击的位置
+    pub fn calibration_point(
+        &self,
+        x: u32,
+        y: u32,
+        caps: &DeviceCapabilities,
+    ) -> Option<(f64, f64)> {
+        let nx = x as f64 / caps.max_x.max(1) as f64;
+        let ny = y as f64 / caps.max_y.max(1) as f64;
+        self.to_target(nx, ny, caps)
+    }
+
+    /// 实际使用的数位板区域(归一化坐标)，考虑了长宽比裁剪
+    pub fn active_area(&self, caps: &DeviceCapabilities) -> Option<Rect> {
+        let target = self.target_rect()?;
+        let mut area = self.config.area.unwrap_or(Rect {
+            x: 0.0,
+            y: 0.0,
+            width: 1.0,
+            height: 1.0,
+        });
+        if self.config.keep_aspect
+            && let Some((width, height)) = caps.physical_size()
+            && target.height > 0.0
+        {
+            // 在工作区中居中裁剪出与目标长宽比相同的区域
+            let (area_width, area_height) = (area.width * width, area.height * height);
+            let target_aspect = target.width / target.height;
+            if area_width / area_height > target_aspect {
+                let cropped = area.width * (area_height * target_aspect / area_width);
+                area.x += (area.width - cropped) / 2.0;
+                area.width = cropped;
+            } else {
+                let cropped = area.height * (area_width / target_aspect / area_height);
+                area.y += (area.height - cropped) / 2.0;
+                area.height = cropped;
+            }
+        }
+        (area.width > 0.0 && area.height > 0.0).then_some(area)
+    }
+
+    /// 把设备坐标映射到屏幕上
+    ///
+    /// 没有显示器，或者点落在显示器之间的空隙时返回 `None`
+    pub fn map(&self, x: u32, y: u32, caps: &DeviceCapabilities) -> Option<ScreenPoint> {
+        let (lx, ly) = self.device_to_logical(x, y, caps)?;
+        self.point_at(lx, ly)
+    }
+
+    /// 和 [`Mapper::map`] 相同, 但是越过显示器边界时经过 `barrier` 的阻力
+    pub fn map_with_barrier(
+        &self,
+        x: u32,
+        y: u32,
+        caps: &DeviceCapabilities,
+        barrier: &mut EdgeBarrier,
+    ) -> Option<ScreenPoint> {
+        let (lx, ly) = self.device_to_logical(x, y, caps)?;
+        let (lx, ly) = match &self.config.target {
+            MappingTarget::Output { .. } => (lx, ly),
+            _ => barrier.apply(&self.outputs, self.config.edge_resistance, lx, ly),
+        };
+        self.point_at(lx, ly)
+    }
+
+    fn device_to_logical(&self, x: u32, y: u32, caps: &DeviceCapabilities) -> Option<(f64, f64)> {
+        let nx = x as f64 / caps.max_x.max(1) as f64;
+        let ny = y as f64 / caps.max_y.max(1) as f64;
+        self.to_logical(nx, ny, caps)
+    }
+
+    /// 逻辑坐标上的点所在的显示器和像素坐标
+    fn point_at(&self, lx: f64, ly: f64) -> Option<ScreenPoint> {
+        let output = match &self.config.target {
+            // 映射到单个显示器时，边缘上的点也算在这个显示器上
+            MappingTarget::Output { .. } => self.target_output()?,
+            _ => self
+                .outputs
+                .iter()
+                .find(|output| output.logical_rect().contains(lx, ly))?,
+        };
+        let (px, py) = output.to_pixels(lx, ly);
+        Some(ScreenPoint {
+            output: output.name.clone(),
+            output_id: output.id,
+            x: px,
+            y: py,
+            logical_x: lx,
+            logical_y: ly,
+        })
+    }
+}
+
+impl ScreenMapping for Mapper {
+    fn to_output(&self, output: &str, x: f64, y: f64) -> Option<(f64, f64)> {
+        let output = self.output(output)?;
+        // `ScreenMapping` 不知道设备的物理尺寸，这里不做长宽比裁剪
+        let caps = DeviceCapabilities {
+            max_x: 1,
+            max_y: 1,
+            resolution_x: 0,
+            resolution_y: 0,
+            max_pressure: 0,
+            tilt: false,
+            rotation: false,
+            eraser: false,
+            max_tilt: 0,
+            class: DeviceClass::Tablet,
+        };
+        let (lx, ly) = self.to_logical(x, y, &caps)?;
+        Some(output.to_pixels(lx, ly))
+    }
+
+    fn output_size(&self, output: &str) -> Option<(f64, f64)> {
+        let output = self.output(output)?;
+        Some((output.width as f64, output.height as f64))
+    }
+}
@@ -0,0 +1,123 @@
+//! 映射设置的示意图
+//!
+//! 左边是数位板和使用的区域，右边是显示器布局和映射的目标区域.
+//! 用软件画布绘制，不需要显示器，CLI 和网页界面可以直接显示
+
+use crate::{
+    event_model::capability::DeviceCapabilities,
+    screen_overlay::canvas::{Canvas, Color},
+};
+
+use super::{Mapper, Rect};
+
+const BACKGROUND: Color = Color::rgb(0x18, 0x18, 0x20);
+const TABLET: Color = Color::rgb(0x30, 0x30, 0x3c);
+const OUTPUT: Color = Color::rgb(0x30, 0x30, 0x3c);
+const OUTLINE: Color = Color::rgb(0x80, 0x80, 0x90);
+const SELECTED: Color = Color::rgba(0x33, 0x99, 0xff, 0x90);
+const SELECTED_OUTLINE: Color = Color::rgb(0x66, 0xcc, 0xff);
+const PADDING: f64 = 8.0;
+
+/// 把 `content` 等比缩放后放进 `frame` 的中间，返回缩放后的矩形
+fn fit(content: (f64, f64), frame: Rect) -> Rect {
+    let scale = (frame.width / content.0).min(frame.height / content.1);
+    let (width, height) = (content.0 * scale, content.1 * scale);
+    Rect {
+        x: frame.x + (frame.width - width) / 2.0,
+        y: frame.y + (frame.height - height) / 2.0,
+        width,
+        height,
+    }
+}
+
+/// `inner` 是相对于 `outer` 的归一化矩形
+fn place(outer: Rect, inner: Rect) -> Rect {
+    Rect {
+        x: outer.x + inner.x * outer.width,
+        y: outer.y + inner.y * outer.height,
+        width: inner.width * outer.width,
+        height: inner.height * outer.height,
+    }
+}
+
+fn draw(canvas: &mut Canvas, rect: Rect, fill: Color, outline: Color) {
+    let (x, y) = (rect.x.round() as i32, rect.y.round() as i32);
+    let (width, height) = (rect.width.round() as u32, rect.height.round() as u32);
+    if width == 0 || height == 0 {
+        return;
+    }
+    canvas.fill_rect(x, y, width, height, fill);
+    canvas.stroke_rect(x, y, width, height, 1, outline);
+}
+
+/// 绘制 `width` x `height` 像素的示意图
+pub fn render_preview(
+    mapper: &Mapper,
+    caps: &DeviceCapabilities,
+    width: u32,
+    height: u32,
+) -> Canvas {
+    let mut canvas = Canvas::new(width, height);
+    canvas.fill_rect(0, 0, width, height, BACKGROUND);
+
+    let half = width as f64 / 2.0;
+    let panel = |x: f64| Rect {
+        x: x + PADDING,
+        y: PADDING,
+        width: half - PADDING * 2.0,
+        height: height as f64 - PADDING * 2.0,
+    };
+    if panel(0.0).width <= 0.0 || panel(0.0).height <= 0.0 {
+        return canvas;
+    }
+
+    // 数位板, 没有分辨率时按坐标范围估计长宽比
+    let tablet_size = caps
+        .physical_size()
+        .unwrap_or((caps.max_x.max(1) as f64, caps.max_y.max(1) as f64));
+    let tablet = fit(tablet_size, panel(0.0));
+    draw(&mut canvas, tablet, TABLET, OUTLINE);
+    if let Some(area) = mapper.active_area(caps) {
+        draw(&mut canvas, place(tablet, area), SELECTED, SELECTED_OUTLINE);
+    }
+
+    // 显示器布局, 目标区域可能超出显示器
+    let target = mapper.target_rect();
+    let Some(bounds) = mapper
+        .outputs()
+        .iter()
+        .map(|output| output.logical_rect())
+        .chain(target)
+        .reduce(|a, b| a.union(&b))
+    else {
+        return canvas;
+    };
+    if bounds.width <= 0.0 || bounds.height <= 0.0 {
+        return canvas;
+    }
+    let desktop = fit((bounds.width, bounds.height), panel(half));
+    let to_preview = |rect: Rect| {
+        place(
+            desktop,
+            Rect {
+                x: (rect.x - bounds.x) / bounds.width,
+                y: (rect.y - bounds.y) / bounds.height,
+                width: rect.width / bounds.width,
+                height: rect.height / bounds.height,
+            },
+        )
+    };
+    for output in mapper.outputs() {
+        draw(
+            &mut canvas,
+            to_preview(output.logical_rect()),
+            OUTPUT,
+            OUTLINE,
+        );
+    }
+    if let Some(target) = target {
+        draw(&mut canvas, to_preview(target), SELECTED, SELECTED_OUTLINE);
+    }
+
+    canvas
+}
@@ -0,0 +1,40 @@
+//! 对外的稳定接口
+//!
+//! 把 tabletd 当作库使用的程序(比如 `tabletd API` 的客户端、读取事件录制的工具)应该只从这里
+//! 导入事件模型、设备标识和设备信息. 这里的路径和类型遵守 semver: 0.x 版本中只有次版本号变化时
+//! 才会有不兼容的修改, 内部模块重新组织时这里的路径保持不变. 没有列在这里的模块(驱动、路由器、
+//! overlay、HUD 等)是守护进程的内部实现, 随时可能调整.
+//!
+//! 公开函数的错误都可以用 `?` 转换成 [`TabletdError`], 配合这里的 [`Result`] 使用, 调用者不需要
+//! 直接依赖 `anyhow`. 守护进程通过 `tabletd API` 报告的错误是 [`ErrorReport`], 按 [`ErrorKind`] 分类
+//!
+//! ```
+//! use tabletd::prelude::*;
+//!
+//! let event = TabletEvent::ToolIn(ToolId(0x0802));
+//! let envelope = event.to_envelope()?;
+//! assert_eq!(envelope.schema, SCHEMA_VERSION);
+//! assert!(matches!(
+//!     TabletEvent::from_envelope(&envelope)?,
+//!     TabletEvent::ToolIn(ToolId(0x0802))
+//! ));
+//! # Ok::<(), TabletdError>(())
+//! ```
+
+pub use crate::error::{ErrorKind, ErrorReport, TabletdError};
+
+/// 默认错误为 [`TabletdError`] 的结果
+pub type Result<T, E = TabletdError> = std::result::Result<T, E>;
+
+pub use crate::event_model::{
+    capability::{DEFAULT_MAX_TILT, DeviceCapabilities, DeviceClass},
+    coordinate::{CoordinateFormat, CoordinateSpace, Origin, ScreenMapping},
+    event::{
+        AuxButtonEvent, PenButton, PenLocation, PenState, RingEvent, TabletEvent, Tilt, ToolId,
+        ToolType, WheelDirection, WheelEvent,
+    },
+    stamp::EventStamp,
+    tablet::TabletId,
+    wire::{Envelope, SCHEMA_VERSION},
+};
+pub use crate::screen_overlay::id::OutputId;
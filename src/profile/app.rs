@@ -0,0 +1,40 @@
+//! 按聚焦的应用切换设置
+//!
+//! 配置中的 `[[app]]` 是叠加在数位板设置上的修改(合并规则和 [`super::layers`] 相同),
+//! 对应的应用获得焦点时所有数位板改用叠加后的设置，失去焦点后恢复. 例如平时只用数位板中间的一块区域,
+//! 在 Krita 中使用整个工作区:
+//!
+//! ```toml
+//! [defaults.mapping]
+//! area = { x = 0.25, y = 0.25, width = 0.5, height = 0.5 }
+//!
+//! [[app]]
+//! app_id = "org.kde.krita"
+//! mapping = { area = { x = 0.0, y = 0.0, width = 1.0, height = 1.0 } }
+//! ```
+
+use serde::{Deserialize, Serialize};
+use toml::Table;
+
+use super::{Profile, layers};
+
+/// 一个应用的设置叠加
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AppProfile {
+    /// Wayland 的 app_id (X11 程序通常是 WM_CLASS), 不区分大小写
+    pub app_id: String,
+    /// 叠加在数位板设置上的字段, 比如 `mapping`、`pressure_curve`、`bindings`
+    #[serde(flatten)]
+    pub overlay: Table,
+}
+
+impl AppProfile {
+    pub fn matches(&self, app_id: &str) -> bool {
+        self.app_id.eq_ignore_ascii_case(app_id)
+    }
+
+    /// 叠加到一套设置上
+    pub fn apply(&self, profile: &Profile) -> anyhow::Result<Profile> {
+        layers::apply_overlay(profile, &self.overlay)
+    }
+}
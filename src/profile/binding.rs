@@ -0,0 +1,143 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    event_model::{event::WheelDirection, tablet::TabletId},
+    event_router::gesture::{BarrelButton, GestureKind},
+};
+
+/// 按键触发的动作
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "action")]
+pub enum Action {
+    /// 关闭当前窗口
+    CloseWindow,
+    /// 执行命令, 默认不经过 shell, 见 [`crate::event_dispatcher::exec::ExecPolicy`]
+    RunCommand { command: String },
+    /// 打开或关闭通知历史
+    ToggleHistory,
+    /// 发送组合键, 例如 `ctrl+equal`
+    Keys { keys: String },
+    /// 调整系统音量，单位为百分比
+    Volume { step: i8 },
+    /// 打开或关闭 HUD
+    ToggleHud,
+    /// 切换到另一套设置
+    SwitchProfile { profile: String },
+    /// 滚动鼠标滚轮, 正数向上
+    Scroll { amount: i8 },
+    /// 打开或关闭诊断面板
+    ToggleDiagnostics,
+    /// 在所有显示器上显示或隐藏映射区域和笔位置的十字线
+    ToggleMappingOverlay,
+    /// 同时切换多块数位板的设置, 例如直播时一块数位板映射到副屏, 另一块当作按键设备.
+    /// 任何一块数位板的设置无效时都不切换
+    SwitchProfiles {
+        /// 在 HUD 上显示的名称
+        #[serde(default)]
+        name: Option<String>,
+        profiles: Vec<ProfileAssignment>,
+    },
+}
+
+/// 一块数位板切换到的设置
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProfileAssignment {
+    pub tablet: TabletId,
+    pub profile: String,
+}
+
+impl Action {
+    /// 在 HUD 上显示的描述
+    pub fn describe(&self) -> String {
+        match self {
+            Action::CloseWindow => "关闭窗口".to_string(),
+            Action::RunCommand { command } => format!("执行 `{command}`"),
+            Action::ToggleHistory => "通知历史".to_string(),
+            Action::Keys { keys } => format!("按下 {keys}"),
+            Action::Volume { step } => format!("音量 {step:+}%"),
+            Action::ToggleHud => "HUD".to_string(),
+            Action::SwitchProfile { profile } => format!("切换到 {profile}"),
+            Action::Scroll { amount } if *amount >= 0 => "向上滚动".to_string(),
+            Action::Scroll { .. } => "向下滚动".to_string(),
+            Action::ToggleDiagnostics => "诊断面板".to_string(),
+            Action::ToggleMappingOverlay => "映射区域".to_string(),
+            Action::SwitchProfiles {
+                name: Some(name), ..
+            } => format!("切换到 {name}"),
+            Action::SwitchProfiles { profiles, .. } => {
+                format!("切换 {} 块数位板的设置", profiles.len())
+            }
+        }
+    }
+}
+
+/// 快捷键绑定
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Binding {
+    pub button: u8,
+    /// 只在这个模式组生效, 不设置时所有模式组都生效
+    #[serde(default)]
+    pub bank: Option<u8>,
+    #[serde(flatten)]
+    pub action: Action,
+    /// 破坏性操作: 需要在 HUD 上确认才会执行，防止不小心碰到按键
+    #[serde(default)]
+    pub confirm: bool,
+}
+
+impl Binding {
+    pub fn matches(&self, button: u8, bank: u8) -> bool {
+        self.button == button && self.bank.is_none_or(|b| b == bank)
+    }
+}
+
+/// 滚轮绑定, 优先于滚轮预设
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WheelBinding {
+    pub direction: WheelDirection,
+    /// 只在这个模式组生效, 不设置时所有模式组都生效
+    #[serde(default)]
+    pub bank: Option<u8>,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+impl WheelBinding {
+    pub fn matches(&self, direction: &WheelDirection, bank: u8) -> bool {
+        self.direction == *direction && self.bank.is_none_or(|b| b == bank)
+    }
+}
+
+/// 笔杆按键手势的绑定, 见 [`crate::event_router::gesture`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GestureBinding {
+    pub gesture: GestureKind,
+    pub button: BarrelButton,
+    /// 只在这个模式组生效, 不设置时所有模式组都生效
+    #[serde(default)]
+    pub bank: Option<u8>,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+impl GestureBinding {
+    pub fn matches(&self, gesture: GestureKind, button: BarrelButton, bank: u8) -> bool {
+        self.gesture == gesture && self.button == button && self.bank.is_none_or(|b| b == bank)
+    }
+}
+
+/// 快捷菜单中的一项
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuickMenuEntry {
+    /// 菜单上显示的文字, 不设置时使用动作的描述
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+impl QuickMenuEntry {
+    pub fn label(&self) -> String {
+        self.label.clone().unwrap_or_else(|| self.action.describe())
+    }
+}
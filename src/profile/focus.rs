@@ -0,0 +1,196 @@
+//! 当前聚焦的应用
+//!
+//! 通过 `wlr-foreign-toplevel-management` 跟踪 Wayland 混成器上处于激活状态的窗口
+//! (wlroots 系的混成器和 KWin 支持). 不支持这个协议的桌面(比如 GNOME)可以由外部脚本监听
+//! D-Bus 上的焦点变化，再通过控制接口的 [`crate::control::ControlRequest::SetFocus`] 报告
+
+use std::collections::HashMap;
+
+use anyhow::{Context, bail};
+use tokio::{sync::watch, task::JoinHandle};
+use wayland_client::{
+    Connection, Dispatch, Proxy, QueueHandle, backend::ObjectId, event_created_child,
+    protocol::wl_registry,
+};
+use wayland_protocols_wlr::foreign_toplevel::v1::client::{
+    zwlr_foreign_toplevel_handle_v1::{self, ZwlrForeignToplevelHandleV1},
+    zwlr_foreign_toplevel_manager_v1::{self, ZwlrForeignToplevelManagerV1},
+};
+
+/// 聚焦应用的广播通道, 值是 app_id, 没有聚焦的窗口时为 `None`
+///
+/// 由 [`spawn_wayland`] 或控制接口发布, [`crate::event_router::Router`] 订阅后切换应用的设置
+#[derive(Clone)]
+pub struct FocusBus {
+    tx: watch::Sender<Option<String>>,
+}
+
+impl FocusBus {
+    pub fn new() -> Self {
+        Self {
+            tx: watch::Sender::new(None),
+        }
+    }
+
+    /// 发布聚焦的应用，和当前相同时不会通知订阅者
+    pub fn publish(&self, app_id: Option<String>) {
+        self.tx.send_if_modified(|current| {
+            if *current == app_id {
+                return false;
+            }
+            *current = app_id;
+            true
+        });
+    }
+
+    pub fn current(&self) -> Option<String> {
+        self.tx.borrow().clone()
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<Option<String>> {
+        self.tx.subscribe()
+    }
+}
+
+impl Default for FocusBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 一个窗口, 属性在 `done` 之后才算完整
+#[derive(Debug, Default)]
+struct Toplevel {
+    app_id: Option<String>,
+    activated: bool,
+}
+
+struct FocusState {
+    bus: FocusBus,
+    manager: Option<ZwlrForeignToplevelManagerV1>,
+    toplevels: HashMap<ObjectId, Toplevel>,
+    /// 混成器不再发送窗口信息
+    finished: bool,
+}
+
+impl FocusState {
+    fn publish(&self) {
+        let focused = self
+            .toplevels
+            .values()
+            .find(|toplevel| toplevel.activated)
+            .and_then(|toplevel| toplevel.app_id.clone());
+        self.bus.publish(focused);
+    }
+}
+
+/// 在阻塞线程中跟踪聚焦的窗口并发布到 `bus`, 混成器不支持协议或连接断开时返回错误
+pub fn spawn_wayland(bus: FocusBus) -> JoinHandle<anyhow::Result<()>> {
+    tokio::task::spawn_blocking(move || {
+        let conn = Connection::connect_to_env().context("无法连接到 Wayland 混成器")?;
+        let mut queue = conn.new_event_queue();
+        let qhandle = queue.handle();
+        conn.display().get_registry(&qhandle, ());
+
+        let mut state = FocusState {
+            bus,
+            manager: None,
+            toplevels: HashMap::new(),
+            finished: false,
+        };
+        queue
+            .roundtrip(&mut state)
+            .context("Wayland registry 查询失败")?;
+        if state.manager.is_none() {
+            bail!("混成器不支持 wlr-foreign-toplevel-management");
+        }
+        while !state.finished {
+            queue
+                .blocking_dispatch(&mut state)
+                .context("Wayland 事件处理失败")?;
+        }
+        Ok(())
+    })
+}
+
+impl Dispatch<wl_registry::WlRegistry, ()> for FocusState {
+    fn event(
+        state: &mut Self,
+        registry: &wl_registry::WlRegistry,
+        event: wl_registry::Event,
+        _: &(),
+        _: &Connection,
+        qhandle: &QueueHandle<Self>,
+    ) {
+        if let wl_registry::Event::Global {
+            name,
+            interface,
+            version,
+        } = event
+            && interface == "zwlr_foreign_toplevel_manager_v1"
+        {
+            state.manager = Some(registry.bind::<ZwlrForeignToplevelManagerV1, _, _>(
+                name,
+                version.min(3),
+                qhandle,
+                (),
+            ));
+        }
+    }
+}
+
+impl Dispatch<ZwlrForeignToplevelManagerV1, ()> for FocusState {
+    fn event(
+        state: &mut Self,
+        _: &ZwlrForeignToplevelManagerV1,
+        event: zwlr_foreign_toplevel_manager_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_foreign_toplevel_manager_v1::Event::Toplevel { toplevel } => {
+                state.toplevels.insert(toplevel.id(), Toplevel::default());
+            }
+            zwlr_foreign_toplevel_manager_v1::Event::Finished => state.finished = true,
+            _ => {}
+        }
+    }
+
+    event_created_child!(FocusState, ZwlrForeignToplevelManagerV1, [
+        zwlr_foreign_toplevel_manager_v1::EVT_TOPLEVEL_OPCODE => (ZwlrForeignToplevelHandleV1, ()),
+    ]);
+}
+
+impl Dispatch<ZwlrForeignToplevelHandleV1, ()> for FocusState {
+    fn event(
+        state: &mut Self,
+        handle: &ZwlrForeignToplevelHandleV1,
+        event: zwlr_foreign_toplevel_handle_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let Some(toplevel) = state.toplevels.get_mut(&handle.id()) else {
+            return;
+        };
+        match event {
+            zwlr_foreign_toplevel_handle_v1::Event::AppId { app_id } => {
+                toplevel.app_id = Some(app_id);
+            }
+            zwlr_foreign_toplevel_handle_v1::Event::State { state: states } => {
+                let activated = zwlr_foreign_toplevel_handle_v1::State::Activated as u32;
+                toplevel.activated = states
+                    .chunks_exact(4)
+                    .any(|value| u32::from_ne_bytes(value.try_into().unwrap()) == activated);
+            }
+            zwlr_foreign_toplevel_handle_v1::Event::Done => state.publish(),
+            zwlr_foreign_toplevel_handle_v1::Event::Closed => {
+                state.toplevels.remove(&handle.id());
+                handle.destroy();
+                state.publish();
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,270 @@
+//! 设置的继承和运行时叠加
+//!
+//! 设置可以用 `extends = "base"` 继承另一个设置，只写出不同的字段. 合并在 TOML 层面进行:
+//! 表逐层合并，其他值整个覆盖; `bindings` 按 `(button, bank)` 合并, 同一个按键的绑定覆盖基础设置中的绑定.
+//!
+//! 叠加([`ProfileOverlays`])是运行时临时套在设置上的修改，比如临时换一个映射区域，不会被保存
+
+use std::collections::HashSet;
+
+use anyhow::{Context, bail};
+use toml::{Table, Value};
+
+use super::{Profile, storage::ProfileStorage};
+
+/// 继承链的最大长度
+const MAX_DEPTH: usize = 16;
+
+/// 按某些字段合并的数组, 其他数组整个覆盖
+const KEYED_ARRAYS: &[(&str, &[&str])] = &[("bindings", &["button", "bank"])];
+
+fn to_table(profile: &Profile) -> anyhow::Result<Table> {
+    match Value::try_from(profile)? {
+        Value::Table(table) => Ok(table),
+        _ => bail!("设置没有序列化为 TOML 表"),
+    }
+}
+
+fn to_profile(table: Table) -> anyhow::Result<Profile> {
+    Ok(Value::Table(table).try_into()?)
+}
+
+fn array_key(table: &str) -> Option<&'static [&'static str]> {
+    KEYED_ARRAYS
+        .iter()
+        .find(|(name, _)| *name == table)
+        .map(|(_, key)| *key)
+}
+
+/// 数组元素的键, 缺少的字段当作空
+fn element_key<'a>(value: &'a Value, key: &[&str]) -> Vec<Option<&'a Value>> {
+    key.iter()
+        .map(|field| value.as_table().and_then(|table| table.get(*field)))
+        .collect()
+}
+
+/// 把 `overlay` 合并到 `base` 上
+pub fn merge(base: &mut Table, overlay: &Table) {
+    for (name, value) in overlay {
+        match (base.get_mut(name), value) {
+            (Some(Value::Table(base)), Value::Table(overlay)) => merge(base, overlay),
+            (Some(Value::Array(base)), Value::Array(overlay)) if array_key(name).is_some() => {
+                let key = array_key(name).unwrap();
+                for element in overlay {
+                    let element_key = element_key(element, key);
+                    match base
+                        .iter_mut()
+                        .find(|existing| self::element_key(existing, key) == element_key)
+                    {
+                        Some(existing) => *existing = element.clone(),
+                        None => base.push(element.clone()),
+                    }
+                }
+            }
+            _ => {
+                base.insert(name.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// `child` 中与 `base` 不同的部分, 是 [`merge`] 的逆操作
+pub fn diff(child: &Table, base: &Table) -> Table {
+    let mut result = Table::new();
+    for (name, value) in child {
+        match (base.get(name), value) {
+            (Some(base), value) if base == value => {}
+            (Some(Value::Table(base)), Value::Table(child)) => {
+                let changed = diff(child, base);
+                if !changed.is_empty() {
+                    result.insert(name.clone(), Value::Table(changed));
+                }
+            }
+            (Some(Value::Array(base)), Value::Array(child)) if array_key(name).is_some() => {
+                let changed: Vec<_> = child
+                    .iter()
+                    .filter(|element| !base.contains(element))
+                    .cloned()
+                    .collect();
+                if !changed.is_empty() {
+                    result.insert(name.clone(), Value::Array(changed));
+                }
+            }
+            _ => {
+                result.insert(name.clone(), value.clone());
+            }
+        }
+    }
+    result
+}
+
+/// 读取设置并展开继承链, 得到完整的 TOML 表
+pub fn resolve_table<S>(storage: &S, name: &str) -> anyhow::Result<Option<Table>>
+where
+    S: ProfileStorage + ?Sized,
+{
+    let mut chain = Vec::new();
+    let mut visited = HashSet::new();
+    let mut next = Some(name.to_string());
+    while let Some(current) = next.take() {
+        if !visited.insert(current.clone()) {
+            bail!("设置 {name} 的继承链有循环: {current}");
+        }
+        if chain.len() >= MAX_DEPTH {
+            bail!("设置 {name} 的继承链太长");
+        }
+        let Some(table) = storage.load_table(&current)? else {
+            if chain.is_empty() {
+                return Ok(None);
+            }
+            bail!("设置 {name} 继承的 {current} 不存在");
+        };
+        next = table
+            .get("extends")
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        chain.push(table);
+    }
+
+    // 从最底层的基础设置开始合并
+    let mut own = chain.remove(0);
+    let mut resolved = Table::new();
+    for table in chain.iter().rev() {
+        merge(&mut resolved, table);
+    }
+    // 名称和继承关系不继承
+    own.insert("name".to_string(), Value::String(name.to_string()));
+    resolved.remove("extends");
+    merge(&mut resolved, &own);
+    Ok(Some(resolved))
+}
+
+/// 读取设置并展开继承链
+pub fn resolve<S>(storage: &S, name: &str) -> anyhow::Result<Option<Profile>>
+where
+    S: ProfileStorage + ?Sized,
+{
+    let Some(table) = resolve_table(storage, name)? else {
+        return Ok(None);
+    };
+    let profile = to_profile(table).with_context(|| format!("设置 {name}"))?;
+    Ok(Some(profile))
+}
+
+/// 保存时只写出与基础设置不同的字段
+pub fn own_fields<S>(storage: &S, profile: &Profile) -> anyhow::Result<Table>
+where
+    S: ProfileStorage + ?Sized,
+{
+    let table = to_table(profile)?;
+    let Some(base_name) = &profile.extends else {
+        return Ok(table);
+    };
+    let base = resolve_table(storage, base_name)?
+        .with_context(|| format!("设置 {} 继承的 {base_name} 不存在", profile.name))?;
+    // 补上默认值再比较, 否则基础设置中省略的字段都会被当作不同
+    let base = to_table(&to_profile(base)?)?;
+    let mut own = diff(&table, &base);
+    own.insert("name".to_string(), Value::String(profile.name.clone()));
+    own.insert("extends".to_string(), Value::String(base_name.clone()));
+    Ok(own)
+}
+
+/// 把一个叠加合并到设置上, 名称保持不变
+pub fn apply_overlay(profile: &Profile, overlay: &Table) -> anyhow::Result<Profile> {
+    let mut table = to_table(profile)?;
+    merge(&mut table, overlay);
+    table.insert("name".to_string(), Value::String(profile.name.clone()));
+    to_profile(table)
+}
+
+/// 运行时叠加在设置上的临时修改
+///
+/// 后添加的叠加优先. 叠加只影响 [`ProfileOverlays::effective`], 保存时使用 [`ProfileOverlays::base`]
+#[derive(Debug, Clone)]
+pub struct ProfileOverlays {
+    base: Profile,
+    base_table: Table,
+    overlays: Vec<(String, Table)>,
+    effective: Profile,
+}
+
+impl ProfileOverlays {
+    pub fn new(base: Profile) -> anyhow::Result<Self> {
+        let base_table = to_table(&base)?;
+        Ok(Self {
+            effective: base.clone(),
+            base,
+            base_table,
+            overlays: Vec::new(),
+        })
+    }
+
+    /// 不含叠加的设置
+    pub fn base(&self) -> &Profile {
+        &self.base
+    }
+
+    /// 替换基础设置(比如设置被修改后重新读取), 保留叠加
+    pub fn set_base(&mut self, base: Profile) -> anyhow::Result<()> {
+        self.base_table = to_table(&base)?;
+        self.base = base;
+        self.rebuild()
+    }
+
+    /// 叠加之后的设置
+    pub fn effective(&self) -> &Profile {
+        &self.effective
+    }
+
+    /// 已添加的叠加名称
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.overlays.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// 添加或替换叠加, 叠加后的设置无效时不做修改
+    pub fn push(&mut self, name: impl Into<String>, overlay: Table) -> anyhow::Result<()> {
+        let name = name.into();
+        let previous = self.overlays.clone();
+        self.overlays.retain(|(existing, _)| *existing != name);
+        self.overlays.push((name, overlay));
+        if let Err(e) = self.rebuild() {
+            self.overlays = previous;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// 从 TOML 文本添加叠加
+    pub fn push_str(&mut self, name: impl Into<String>, overlay: &str) -> anyhow::Result<()> {
+        let overlay = overlay.parse().context("无法解析叠加")?;
+        self.push(name, overlay)
+    }
+
+    /// 移除叠加, 返回是否存在
+    pub fn remove(&mut self, name: &str) -> anyhow::Result<bool> {
+        let count = self.overlays.len();
+        self.overlays.retain(|(existing, _)| existing != name);
+        if self.overlays.len() == count {
+            return Ok(false);
+        }
+        self.rebuild()?;
+        Ok(true)
+    }
+
+    pub fn clear(&mut self) {
+        self.overlays.clear();
+        self.effective = self.base.clone();
+    }
+
+    fn rebuild(&mut self) -> anyhow::Result<()> {
+        let mut table = self.base_table.clone();
+        for (_, overlay) in &self.overlays {
+            merge(&mut table, overlay);
+        }
+        // 叠加不能改名
+        table.insert("name".to_string(), Value::String(self.base.name.clone()));
+        self.effective = to_profile(table)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+use serde::{Deserialize, Serialize};
+
+use crate::mapping::MappingConfig;
+
+use binding::{Action, Binding, GestureBinding, QuickMenuEntry, WheelBinding};
+use wheel::WheelPreset;
+
+use crate::{
+    event_model::event::WheelDirection,
+    event_router::{
+        feedback::FeedbackConfig, gesture::GestureConfig, mode_bank::ModeBankConfig,
+        pressure::PressureCurve, smoothing::SmoothingConfig,
+    },
+};
+
+/// 按聚焦的应用切换设置
+pub mod app;
+/// 快捷键绑定
+pub mod binding;
+/// 当前聚焦的应用
+pub mod focus;
+/// 设置的继承和运行时叠加
+pub mod layers;
+/// 设置的存储后端
+pub mod storage;
+/// 在两台机器之间同步设置
+pub mod sync;
+/// 滚轮预设
+pub mod wheel;
+
+/// 一套数位板设置
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    pub name: String,
+    /// 继承的基础设置, 只需要写出不同的字段
+    pub extends: Option<String>,
+    /// 演示模式: 笔尖接触不会产生点击，只在 overlay 上显示激光笔轨迹
+    pub hover_only: bool,
+    /// 数位板到屏幕的映射
+    pub mapping: MappingConfig,
+    /// 快捷键绑定
+    pub bindings: Vec<Binding>,
+    /// 模式组, 设置后绑定可以用 `bank` 只在某一组生效
+    pub mode_bank: Option<ModeBankConfig>,
+    /// 光标颜色, `#rrggbb` 或 `#rrggbbaa`, 不设置时自动分配
+    pub cursor_color: Option<String>,
+    /// 光标旁显示的文字, 通常是设备名称
+    pub cursor_label: Option<String>,
+    /// 滚轮绑定
+    pub wheel_bindings: Vec<WheelBinding>,
+    /// 滚轮的内置用法, 没有匹配的滚轮绑定时使用, 都没有时滚轮事件原样传递
+    pub wheel: Option<WheelPreset>,
+    /// 按住笔杆上面的按键时弹出的快捷菜单, 为空时按键照常传给系统
+    pub quick_menu: Vec<QuickMenuEntry>,
+    /// 笔杆按键手势的绑定
+    pub gesture_bindings: Vec<GestureBinding>,
+    /// 识别笔杆按键手势的时间
+    pub gestures: GestureConfig,
+    /// 落笔、抬笔和按键的反馈
+    pub feedback: FeedbackConfig,
+    /// 压感曲线
+    pub pressure_curve: PressureCurve,
+    /// 抬笔延迟(毫秒), 期间重新接触时不断开笔画. 用于偶尔丢失接触的数位板, 0 表示不启用
+    pub pen_up_delay_ms: u32,
+    /// 笔的位置平滑和预测
+    pub smoothing: SmoothingConfig,
+    /// 按顺序执行的表达式, 比如 `pressure = clamp(pressure * 1.2, 0, 1)`,
+    /// 见 [`crate::event_router::script`]
+    pub transforms: Vec<String>,
+}
+
+impl Profile {
+    /// 查找按键在某个模式组中的绑定
+    pub fn binding_for(&self, button: u8, bank: u8) -> Option<&Binding> {
+        self.bindings
+            .iter()
+            .find(|binding| binding.matches(button, bank))
+    }
+
+    /// 滚轮在某个模式组中转动对应的动作
+    pub fn wheel_action(&self, direction: &WheelDirection, bank: u8) -> Option<Action> {
+        self.wheel_bindings
+            .iter()
+            .find(|binding| binding.matches(direction, bank))
+            .map(|binding| binding.action.clone())
+            .or_else(|| self.wheel.map(|preset| preset.action(direction)))
+    }
+}
@@ -0,0 +1,312 @@
+//! 设置的持久化
+//!
+//! 默认保存为 TOML 文件; 桌面环境可以换成 dconf，或者实现 [`ProfileStorage`] 接入自己的设置系统
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::Context;
+
+use toml::Table;
+
+use super::{Profile, layers};
+
+/// 设置的存储后端
+pub trait ProfileStorage: Send + Sync {
+    /// 后端名称, 用于日志
+    fn name(&self) -> &str;
+
+    /// 所有已保存的设置名称
+    fn list(&self) -> anyhow::Result<Vec<String>>;
+
+    /// 读取保存的原始内容(未展开继承), 不存在时返回 `None`
+    fn load_table(&self, name: &str) -> anyhow::Result<Option<Table>>;
+
+    /// 保存原始内容，已存在时覆盖
+    fn save_table(&self, name: &str, table: &Table) -> anyhow::Result<()>;
+
+    /// 读取设置并展开继承, 不存在时返回 `None`
+    fn load(&self, name: &str) -> anyhow::Result<Option<Profile>> {
+        layers::resolve(self, name)
+    }
+
+    /// 保存设置，已存在时覆盖. 继承了其他设置时只保存不同的字段
+    fn save(&self, profile: &Profile) -> anyhow::Result<()> {
+        let table = layers::own_fields(self, profile)?;
+        self.save_table(&profile.name, &table)
+    }
+
+    /// 删除设置, 不存在时什么也不做
+    fn remove(&self, name: &str) -> anyhow::Result<()>;
+}
+
+/// 设置名称会出现在文件名和 dconf 路径中
+fn check_name(name: &str) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        !name.is_empty() && !name.starts_with('.') && !name.contains(['/', '\0']),
+        "无效的设置名称: {name:?}"
+    );
+    Ok(())
+}
+
+/// 每个设置一个 TOML 文件
+pub struct FileStorage {
+    dir: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// 默认目录: `$XDG_CONFIG_HOME/tabletd/profiles`
+    pub fn default_dir() -> PathBuf {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .unwrap_or_else(std::env::temp_dir)
+            .join("tabletd")
+            .join("profiles")
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{name}.toml"))
+    }
+}
+
+impl Default for FileStorage {
+    fn default() -> Self {
+        Self::new(Self::default_dir())
+    }
+}
+
+impl ProfileStorage for FileStorage {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    fn list(&self) -> anyhow::Result<Vec<String>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut names: Vec<_> = entries
+            .flatten()
+            .filter_map(|entry| {
+                let path = entry.path();
+                if path.extension()? != "toml" {
+                    return None;
+                }
+                Some(path.file_stem()?.to_str()?.to_string())
+            })
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    fn load_table(&self, name: &str) -> anyhow::Result<Option<Table>> {
+        check_name(name)?;
+        let path = self.path(name);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let table = text
+            .parse()
+            .with_context(|| format!("{}", path.display()))?;
+        Ok(Some(table))
+    }
+
+    fn save_table(&self, name: &str, table: &Table) -> anyhow::Result<()> {
+        check_name(name)?;
+        fs::create_dir_all(&self.dir)?;
+        // 先写临时文件再改名，避免写到一半时退出把设置弄坏
+        let path = self.path(name);
+        let tmp = path.with_extension("toml.tmp");
+        fs::write(&tmp, toml::to_string_pretty(table)?)?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    fn remove(&self, name: &str) -> anyhow::Result<()> {
+        check_name(name)?;
+        match fs::remove_file(self.path(name)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// 保存在 dconf 中，每个设置是 `/org/tabletd/profiles/` 下的一个字符串键，内容为 TOML
+///
+/// 通过 `dconf` 命令访问，不需要安装 GSettings schema
+pub struct DconfStorage {
+    /// 以 `/` 结尾的目录
+    dir: String,
+}
+
+impl DconfStorage {
+    pub fn new(dir: impl Into<String>) -> Self {
+        let mut dir = dir.into();
+        if !dir.ends_with('/') {
+            dir.push('/');
+        }
+        Self { dir }
+    }
+
+    /// `dconf` 命令是否可用
+    pub fn available() -> bool {
+        Command::new("dconf")
+            .arg("help")
+            .output()
+            .is_ok_and(|output| output.status.success())
+    }
+
+    fn dconf(&self, args: &[&str]) -> anyhow::Result<String> {
+        let output = Command::new("dconf")
+            .args(args)
+            .output()
+            .context("无法执行 dconf")?;
+        anyhow::ensure!(
+            output.status.success(),
+            "dconf {} 失败: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        Ok(String::from_utf8(output.stdout)?)
+    }
+
+    fn key(&self, name: &str) -> String {
+        format!("{}{}", self.dir, encode_key(name))
+    }
+}
+
+impl Default for DconfStorage {
+    fn default() -> Self {
+        Self::new("/org/tabletd/profiles/")
+    }
+}
+
+impl ProfileStorage for DconfStorage {
+    fn name(&self) -> &str {
+        "dconf"
+    }
+
+    fn list(&self) -> anyhow::Result<Vec<String>> {
+        let output = self.dconf(&["list", &self.dir])?;
+        let mut names: Vec<_> = output
+            .lines()
+            // 子目录以 `/` 结尾
+            .filter(|line| !line.is_empty() && !line.ends_with('/'))
+            .filter_map(decode_key)
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    fn load_table(&self, name: &str) -> anyhow::Result<Option<Table>> {
+        check_name(name)?;
+        let value = self.dconf(&["read", &self.key(name)])?;
+        let value = value.trim_end_matches('\n');
+        if value.is_empty() {
+            return Ok(None);
+        }
+        let text = parse_gvariant_string(value)
+            .with_context(|| format!("无法解析 dconf 中的值: {value}"))?;
+        Ok(Some(text.parse()?))
+    }
+
+    fn save_table(&self, name: &str, table: &Table) -> anyhow::Result<()> {
+        check_name(name)?;
+        let text = toml::to_string_pretty(table)?;
+        self.dconf(&["write", &self.key(name), &gvariant_string(&text)])?;
+        Ok(())
+    }
+
+    fn remove(&self, name: &str) -> anyhow::Result<()> {
+        check_name(name)?;
+        self.dconf(&["reset", &self.key(name)])?;
+        Ok(())
+    }
+}
+
+/// dconf 的键只能包含字母、数字和 `-`，其他字符编码为 `_xx`
+fn encode_key(name: &str) -> String {
+    name.bytes()
+        .map(|byte| {
+            if byte.is_ascii_alphanumeric() || byte == b'-' {
+                (byte as char).to_string()
+            } else {
+                format!("_{byte:02x}")
+            }
+        })
+        .collect()
+}
+
+fn decode_key(key: &str) -> Option<String> {
+    let mut bytes = Vec::new();
+    let mut rest = key.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'_' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// 写成 GVariant 文本格式的字符串
+fn gvariant_string(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('\'');
+    for c in text.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\'' => out.push_str("\\'"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('\'');
+    out
+}
+
+/// 解析 `dconf read` 输出的字符串
+fn parse_gvariant_string(value: &str) -> Option<String> {
+    let quote = value.chars().next()?;
+    if quote != '\'' && quote != '"' {
+        return None;
+    }
+    let inner = value.strip_prefix(quote)?.strip_suffix(quote)?;
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => out.push('\n'),
+            't' => out.push('\t'),
+            'r' => out.push('\r'),
+            'u' => {
+                let hex: String = chars.by_ref().take(4).collect();
+                out.push(char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+            }
+            c => out.push(c),
+        }
+    }
+    Some(out)
+}
@@ -0,0 +1,360 @@
+//! 在两台机器之间同步设置
+//!
+//! 同一块数位板在两台机器上使用(比如笔记本接上扩展坞前后)时, 可以在
+//! [`crate::input_devices::remote::RemoteLink`] 的连接上同步 [`ProfileStorage`] 中的设置(压感曲线、绑定等):
+//! 双方交换每个设置的修改时间([`SyncMessage::Manifest`]), 只传送对方没有或者比对方新的设置.
+//! 两边都改过同一个设置时以修改时间较晚的为准，所以两台机器的时钟需要大致同步.
+//! 删除设置同样会同步到对方
+//!
+//! 存储后端不记录修改时间，所以每个设置的摘要和修改时间另外记在一个文件中,
+//! 每次比较前重新扫描: 内容变了的设置, 修改时间记为扫描的时间
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use toml::Table;
+use tracing::{info, warn};
+
+use crate::config::{Config, ConfigBus};
+
+use super::{Profile, storage::ProfileStorage};
+
+/// 一个设置的版本
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProfileStamp {
+    pub name: String,
+    /// 修改时间, Unix 时间戳(毫秒)
+    pub modified: u64,
+    pub deleted: bool,
+}
+
+/// 传给对方的设置
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncedProfile {
+    pub stamp: ProfileStamp,
+    /// 保存的原始内容(TOML), 已删除时为 `None`
+    pub content: Option<String>,
+}
+
+/// 同步设置的消息, 双方使用相同的格式
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SyncMessage {
+    /// 所有设置的版本. `reply` 时对方还要回复自己的版本, 这样一次交换就能同步两个方向
+    Manifest {
+        stamps: Vec<ProfileStamp>,
+        reply: bool,
+    },
+    /// 比对方新的设置
+    Profiles(Vec<SyncedProfile>),
+}
+
+/// 一个设置上次扫描时的状态
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexEntry {
+    modified: u64,
+    /// 内容的摘要, 用来发现设置被修改
+    digest: String,
+    #[serde(default)]
+    deleted: bool,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct IndexFile {
+    #[serde(default)]
+    profile: BTreeMap<String, IndexEntry>,
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+/// 内容的 FNV-1a 摘要. 两台机器上的结果必须相同, 所以不能用 `DefaultHasher`
+fn digest(table: &Table) -> anyhow::Result<String> {
+    let text = toml::to_string(table)?;
+    let hash = text.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    Ok(format!("{hash:016x}"))
+}
+
+struct Inner {
+    storage: Arc<dyn ProfileStorage>,
+    path: Option<PathBuf>,
+    index: BTreeMap<String, IndexEntry>,
+    /// 收到设置后重新加载的配置文件
+    reload: Option<(PathBuf, ConfigBus)>,
+}
+
+impl Inner {
+    /// 重新扫描存储, 记下被修改或删除的设置
+    fn scan(&mut self) -> anyhow::Result<()> {
+        let now = now_millis();
+        let names = self.storage.list()?;
+        let mut changed = false;
+        for name in &names {
+            let Some(table) = self.storage.load_table(name)? else {
+                continue;
+            };
+            let digest = digest(&table)?;
+            if self
+                .index
+                .get(name)
+                .is_some_and(|entry| !entry.deleted && entry.digest == digest)
+            {
+                continue;
+            }
+            self.index.insert(
+                name.clone(),
+                IndexEntry {
+                    modified: now,
+                    digest,
+                    deleted: false,
+                },
+            );
+            changed = true;
+        }
+        for (name, entry) in &mut self.index {
+            if !entry.deleted && !names.contains(name) {
+                *entry = IndexEntry {
+                    modified: now,
+                    digest: String::new(),
+                    deleted: true,
+                };
+                changed = true;
+            }
+        }
+        if changed {
+            self.persist();
+        }
+        Ok(())
+    }
+
+    fn stamps(&self) -> Vec<ProfileStamp> {
+        self.index
+            .iter()
+            .map(|(name, entry)| ProfileStamp {
+                name: name.clone(),
+                modified: entry.modified,
+                deleted: entry.deleted,
+            })
+            .collect()
+    }
+
+    /// 比对方新的设置
+    fn newer_than(&self, peer: &[ProfileStamp]) -> anyhow::Result<Vec<SyncedProfile>> {
+        let peer: HashMap<_, _> = peer
+            .iter()
+            .map(|stamp| (stamp.name.as_str(), stamp.modified))
+            .collect();
+        let mut profiles = Vec::new();
+        for stamp in self.stamps() {
+            let newer = match peer.get(stamp.name.as_str()) {
+                Some(modified) => stamp.modified > *modified,
+                // 对方从来没有的设置不需要告诉它已经删除
+                None => !stamp.deleted,
+            };
+            if !newer {
+                continue;
+            }
+            let content = if stamp.deleted {
+                None
+            } else {
+                match self.storage.load_table(&stamp.name)? {
+                    Some(table) => Some(toml::to_string(&table)?),
+                    None => continue,
+                }
+            };
+            profiles.push(SyncedProfile { stamp, content });
+        }
+        Ok(profiles)
+    }
+
+    /// 保存对方较新的设置, 返回实际更新的设置名称
+    fn apply(&mut self, profiles: Vec<SyncedProfile>) -> anyhow::Result<Vec<String>> {
+        let mut updated = Vec::new();
+        for SyncedProfile { stamp, content } in profiles {
+            if self
+                .index
+                .get(&stamp.name)
+                .is_some_and(|entry| entry.modified >= stamp.modified)
+            {
+                continue;
+            }
+            let digest = match content {
+                Some(content) => {
+                    let table: Table = content
+                        .parse()
+                        .with_context(|| format!("收到的设置 {} 无效", stamp.name))?;
+                    table
+                        .clone()
+                        .try_into::<Profile>()
+                        .with_context(|| format!("收到的设置 {} 无效", stamp.name))?;
+                    self.storage.save_table(&stamp.name, &table)?;
+                    digest(&table)?
+                }
+                None => {
+                    self.storage.remove(&stamp.name)?;
+                    String::new()
+                }
+            };
+            self.index.insert(
+                stamp.name.clone(),
+                IndexEntry {
+                    modified: stamp.modified,
+                    digest,
+                    deleted: stamp.deleted,
+                },
+            );
+            updated.push(stamp.name);
+        }
+        if !updated.is_empty() {
+            self.persist();
+        }
+        Ok(updated)
+    }
+
+    fn persist(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let file = IndexFile {
+            profile: self.index.clone(),
+        };
+        let result = toml::to_string_pretty(&file)
+            .map_err(anyhow::Error::from)
+            .and_then(|text| {
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                fs::write(path, text)?;
+                Ok(())
+            });
+        if let Err(e) = result {
+            warn!("无法保存设置同步记录 {}: {e}", path.display());
+        }
+    }
+
+    /// 重新读取配置文件，让继承了这些设置的配置生效
+    fn reload(&self) -> anyhow::Result<()> {
+        let Some((path, bus)) = &self.reload else {
+            return Ok(());
+        };
+        let mut config = Config::load(path)?;
+        config.resolve(self.storage.as_ref())?;
+        bus.publish(config);
+        Ok(())
+    }
+}
+
+/// 设置的同步状态, 可以在多个连接之间共享
+#[derive(Clone)]
+pub struct ProfileSync {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl ProfileSync {
+    /// 只在内存中记录修改时间, 重启后所有设置都被当作刚刚修改过
+    pub fn new(storage: Arc<dyn ProfileStorage>) -> Self {
+        Self::with_index(storage, None, BTreeMap::new())
+    }
+
+    /// 默认路径: `$XDG_STATE_HOME/tabletd/sync.toml`
+    pub fn default_path() -> PathBuf {
+        std::env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| Path::new(&home).join(".local").join("state"))
+            })
+            .unwrap_or_else(std::env::temp_dir)
+            .join("tabletd")
+            .join("sync.toml")
+    }
+
+    /// 从文件读取修改记录，文件不存在时为空. 之后每次变化都会写回文件
+    pub fn load(
+        storage: Arc<dyn ProfileStorage>,
+        path: impl Into<PathBuf>,
+    ) -> anyhow::Result<Self> {
+        let path = path.into();
+        let index = match fs::read_to_string(&path) {
+            Ok(text) => {
+                toml::from_str::<IndexFile>(&text)
+                    .with_context(|| format!("{}", path.display()))?
+                    .profile
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self::with_index(storage, Some(path), index))
+    }
+
+    fn with_index(
+        storage: Arc<dyn ProfileStorage>,
+        path: Option<PathBuf>,
+        index: BTreeMap<String, IndexEntry>,
+    ) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                storage,
+                path,
+                index,
+                reload: None,
+            })),
+        }
+    }
+
+    /// 收到设置后重新读取 `path` 并发布到 `bus`
+    pub fn set_reload(&self, path: impl Into<PathBuf>, bus: ConfigBus) {
+        self.inner.lock().unwrap().reload = Some((path.into(), bus));
+    }
+
+    /// 本地所有设置的版本, 请求对方回复
+    pub fn manifest(&self) -> anyhow::Result<SyncMessage> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.scan()?;
+        Ok(SyncMessage::Manifest {
+            stamps: inner.stamps(),
+            reply: true,
+        })
+    }
+
+    /// 处理对方的消息, 返回需要发给对方的消息
+    pub fn handle(&self, message: SyncMessage) -> anyhow::Result<Vec<SyncMessage>> {
+        let mut inner = self.inner.lock().unwrap();
+        // 先记下本地的修改，比较时才有正确的修改时间
+        inner.scan()?;
+        match message {
+            SyncMessage::Manifest { stamps, reply } => {
+                let mut replies = Vec::new();
+                let profiles = inner.newer_than(&stamps)?;
+                if !profiles.is_empty() {
+                    replies.push(SyncMessage::Profiles(profiles));
+                }
+                if reply {
+                    replies.push(SyncMessage::Manifest {
+                        stamps: inner.stamps(),
+                        reply: false,
+                    });
+                }
+                Ok(replies)
+            }
+            SyncMessage::Profiles(profiles) => {
+                let updated = inner.apply(profiles)?;
+                if !updated.is_empty() {
+                    info!("已同步设置: {}", updated.join(", "));
+                    inner.reload().context("同步设置后无法重新加载配置")?;
+                }
+                Ok(Vec::new())
+            }
+        }
+    }
+}
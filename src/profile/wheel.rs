@@ -0,0 +1,130 @@
+use std::process::Command;
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    event_model::event::WheelDirection,
+    hud_interface::osd::{Osd, OsdIcon},
+    units,
+};
+
+use super::binding::Action;
+
+/// 音量预设每格调整的百分比
+const VOLUME_STEP: i8 = 5;
+/// 滚动预设每格滚动的格数
+const SCROLL_STEP: i8 = 1;
+
+/// 内置的滚轮用法, 不需要自己写绑定
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WheelPreset {
+    /// 调整系统音量，HUD 上显示音量条
+    Volume,
+    /// 缩放, `Ctrl+=` / `Ctrl+-`
+    Zoom,
+    /// 旋转画布, `Ctrl+]` / `Ctrl+[` (Krita 的默认快捷键)
+    Rotate,
+    /// 滚动, 顺时针向下
+    Scroll,
+    /// 调整画笔大小, `]` / `[` (Krita、GIMP 和 Photoshop 的默认快捷键)
+    BrushSize,
+}
+
+impl WheelPreset {
+    /// 滚轮转动一格对应的动作
+    pub fn action(&self, direction: &WheelDirection) -> Action {
+        let clockwise = matches!(direction, WheelDirection::Clockwise);
+        match self {
+            WheelPreset::Volume => Action::Volume {
+                step: if clockwise { VOLUME_STEP } else { -VOLUME_STEP },
+            },
+            WheelPreset::Zoom => Action::Keys {
+                keys: if clockwise {
+                    "ctrl+equal"
+                } else {
+                    "ctrl+minus"
+                }
+                .to_string(),
+            },
+            WheelPreset::Rotate => Action::Keys {
+                keys: if clockwise {
+                    "ctrl+bracketright"
+                } else {
+                    "ctrl+bracketleft"
+                }
+                .to_string(),
+            },
+            WheelPreset::Scroll => Action::Scroll {
+                amount: if clockwise { -SCROLL_STEP } else { SCROLL_STEP },
+            },
+            WheelPreset::BrushSize => Action::Keys {
+                keys: if clockwise {
+                    "bracketright"
+                } else {
+                    "bracketleft"
+                }
+                .to_string(),
+            },
+        }
+    }
+
+    /// 动作执行后在 HUD 上显示的 OSD, `volume` 为执行后的音量(0.0 ~ 1.0)
+    pub fn osd(&self, direction: &WheelDirection, volume: Option<f32>) -> Osd {
+        let clockwise = matches!(direction, WheelDirection::Clockwise);
+        match self {
+            WheelPreset::Volume => Osd::new(
+                OsdIcon::Volume,
+                match volume {
+                    Some(volume) => format!("音量 {}", units::current().percent(volume as f64, 0)),
+                    None => "音量".to_string(),
+                },
+                volume,
+            ),
+            WheelPreset::Zoom if clockwise => Osd::new(OsdIcon::ZoomIn, "放大", None),
+            WheelPreset::Zoom => Osd::new(OsdIcon::ZoomOut, "缩小", None),
+            WheelPreset::Rotate if clockwise => Osd::new(OsdIcon::RotateRight, "顺时针旋转", None),
+            WheelPreset::Rotate => Osd::new(OsdIcon::RotateLeft, "逆时针旋转", None),
+            WheelPreset::Scroll if clockwise => Osd::new(OsdIcon::Scroll, "向下滚动", None),
+            WheelPreset::Scroll => Osd::new(OsdIcon::Scroll, "向上滚动", None),
+            WheelPreset::BrushSize if clockwise => Osd::new(OsdIcon::Brush, "画笔变大", None),
+            WheelPreset::BrushSize => Osd::new(OsdIcon::Brush, "画笔变小", None),
+        }
+    }
+}
+
+/// 调整默认输出设备的音量(通过 `wpctl`), 返回调整后的音量
+pub fn adjust_volume(step: i8) -> anyhow::Result<f32> {
+    let change = format!(
+        "{}%{}",
+        step.unsigned_abs(),
+        if step >= 0 { '+' } else { '-' }
+    );
+    let status = Command::new("wpctl")
+        .args(["set-volume", "-l", "1.0", "@DEFAULT_AUDIO_SINK@", &change])
+        .status()
+        .context("无法运行 wpctl")?;
+    if !status.success() {
+        bail!("wpctl set-volume 失败: {status}");
+    }
+    current_volume()
+}
+
+/// 默认输出设备的音量
+pub fn current_volume() -> anyhow::Result<f32> {
+    let output = Command::new("wpctl")
+        .args(["get-volume", "@DEFAULT_AUDIO_SINK@"])
+        .output()
+        .context("无法运行 wpctl")?;
+    if !output.status.success() {
+        bail!("wpctl get-volume 失败: {}", output.status);
+    }
+    // 输出形如 `Volume: 0.45` 或 `Volume: 0.45 [MUTED]`
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout
+        .split_whitespace()
+        .nth(1)
+        .and_then(|value| value.parse().ok())
+        .with_context(|| format!("无法解析 wpctl 输出: {}", stdout.trim()))
+}
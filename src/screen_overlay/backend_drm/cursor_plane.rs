@@ -0,0 +1,230 @@
+use anyhow::Context;
+use drm::control::{
+    Device as ControlDevice,
+    atomic::AtomicModeReq,
+    crtc, framebuffer, plane,
+    property::{Handle as PropertyHandle, Value},
+};
+use gbm::{BufferObject, BufferObjectFlags, Device as GbmDevice};
+use tracing::warn;
+
+use crate::screen_overlay::canvas::Canvas;
+
+use super::drm_util::property::PropertySet;
+
+/// DRM 平面的类型, 即平面 `type` 属性的值
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaneKind {
+    Overlay,
+    Primary,
+    Cursor,
+}
+
+impl PlaneKind {
+    pub fn from_raw(value: u64) -> Option<Self> {
+        match value {
+            0 => Some(PlaneKind::Overlay),
+            1 => Some(PlaneKind::Primary),
+            2 => Some(PlaneKind::Cursor),
+            _ => None,
+        }
+    }
+}
+
+/// atomic commit 中需要设置的平面属性
+struct PlaneProps {
+    fb_id: PropertyHandle,
+    crtc_id: PropertyHandle,
+    src_x: PropertyHandle,
+    src_y: PropertyHandle,
+    src_w: PropertyHandle,
+    src_h: PropertyHandle,
+    crtc_x: PropertyHandle,
+    crtc_y: PropertyHandle,
+    crtc_w: PropertyHandle,
+    crtc_h: PropertyHandle,
+}
+
+impl PlaneProps {
+    fn new(props: &PropertySet) -> std::io::Result<Self> {
+        Ok(Self {
+            fb_id: props.handle("FB_ID")?,
+            crtc_id: props.handle("CRTC_ID")?,
+            src_x: props.handle("SRC_X")?,
+            src_y: props.handle("SRC_Y")?,
+            src_w: props.handle("SRC_W")?,
+            src_h: props.handle("SRC_H")?,
+            crtc_x: props.handle("CRTC_X")?,
+            crtc_y: props.handle("CRTC_Y")?,
+            crtc_w: props.handle("CRTC_W")?,
+            crtc_h: props.handle("CRTC_H")?,
+        })
+    }
+}
+
+/// 一个 GBM 缓冲区和它的 framebuffer
+struct Buffer {
+    bo: BufferObject<()>,
+    fb: framebuffer::Handle,
+}
+
+/// 一个显示器(CRTC)上用来显示光标的平面
+///
+/// 有两个缓冲区轮流使用: 绘制写入后台的缓冲区，下一次 commit 时才切换过去,
+/// 显示器扫描的缓冲区不会被修改
+pub struct CursorPlane {
+    /// 接口名称, 例如 `DP-1`
+    pub output: String,
+    pub crtc: crtc::Handle,
+    /// 显示器的像素尺寸
+    pub mode_size: (u32, u32),
+    pub plane: plane::Handle,
+    pub kind: PlaneKind,
+    props: PlaneProps,
+    buffers: [Buffer; 2],
+    /// 正在显示(或最后一次提交)的缓冲区
+    front: usize,
+    canvas: Canvas,
+    /// 平面当前是否打开
+    visible: bool,
+}
+
+impl CursorPlane {
+    /// 为 `plane` 分配缓冲区. 光标平面使用驱动报告的光标尺寸，叠加平面和显示器一样大
+    pub fn new<D: ControlDevice>(
+        gbm: &GbmDevice<D>,
+        output: String,
+        crtc: crtc::Handle,
+        mode_size: (u32, u32),
+        plane: plane::Handle,
+        kind: PlaneKind,
+        cursor_size: (u32, u32),
+    ) -> anyhow::Result<Self> {
+        let props = PropertySet::query(gbm, plane)
+            .and_then(|props| PlaneProps::new(&props))
+            .with_context(|| format!("无法读取平面 {plane:?} 的属性"))?;
+        let (width, height, flags) = match kind {
+            PlaneKind::Cursor => (cursor_size.0, cursor_size.1, BufferObjectFlags::CURSOR),
+            _ => (mode_size.0, mode_size.1, BufferObjectFlags::empty()),
+        };
+        let flags = flags | BufferObjectFlags::SCANOUT | BufferObjectFlags::LINEAR;
+        let buffer = || -> anyhow::Result<Buffer> {
+            let bo = gbm
+                .create_buffer_object::<()>(width, height, gbm::Format::Argb8888, flags)
+                .context("无法分配 GBM 缓冲区")?;
+            let fb = gbm
+                .add_framebuffer(&bo, 32, 32)
+                .context("无法创建 framebuffer")?;
+            Ok(Buffer { bo, fb })
+        };
+        let mut plane = Self {
+            output,
+            crtc,
+            mode_size,
+            plane,
+            kind,
+            props,
+            buffers: [buffer()?, buffer()?],
+            front: 0,
+            canvas: Canvas::new(width, height),
+            visible: false,
+        };
+        // 新分配的缓冲区内容不确定，先清空
+        for index in 0..plane.buffers.len() {
+            plane.upload(index)?;
+        }
+        Ok(plane)
+    }
+
+    /// 缓冲区的像素尺寸
+    pub fn size(&self) -> (u32, u32) {
+        (self.canvas.width(), self.canvas.height())
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    /// 清空后台缓冲区对应的画布, 返回画布供绘制
+    pub fn begin(&mut self) -> &mut Canvas {
+        self.canvas.clear();
+        &mut self.canvas
+    }
+
+    /// 把画布写入后台缓冲区, 并在 `req` 中让平面显示它, 左上角放在显示器的 `(x, y)` 处
+    pub fn present(&mut self, req: &mut AtomicModeReq, (x, y): (i32, i32)) -> anyhow::Result<()> {
+        let back = 1 - self.front;
+        self.upload(back)?;
+        self.show(req, back, (x, y));
+        self.front = back;
+        self.visible = true;
+        Ok(())
+    }
+
+    /// 在 `req` 中关闭平面
+    pub fn hide(&mut self, req: &mut AtomicModeReq) {
+        req.add_property(self.plane, self.props.fb_id, Value::Framebuffer(None));
+        req.add_property(self.plane, self.props.crtc_id, Value::CRTC(None));
+        self.visible = false;
+    }
+
+    /// 在 `req` 中显示当前的缓冲区, 用来在真正使用前测试平面的设置
+    pub fn test(&self, req: &mut AtomicModeReq) {
+        self.show(req, self.front, (0, 0));
+    }
+
+    /// 释放 framebuffer, 缓冲区本身随平面一起释放
+    pub fn destroy<D: ControlDevice>(&self, device: &D) {
+        for buffer in &self.buffers {
+            if let Err(e) = device.destroy_framebuffer(buffer.fb) {
+                warn!("无法释放 framebuffer {:?}: {e}", buffer.fb);
+            }
+        }
+    }
+
+    fn show(&self, req: &mut AtomicModeReq, index: usize, (x, y): (i32, i32)) {
+        let (width, height) = self.size();
+        let props = &self.props;
+        let fb = self.buffers[index].fb;
+        req.add_property(self.plane, props.fb_id, Value::Framebuffer(Some(fb)));
+        req.add_property(self.plane, props.crtc_id, Value::CRTC(Some(self.crtc)));
+        // SRC_* 是 16.16 定点数
+        req.add_property(self.plane, props.src_x, Value::UnsignedRange(0));
+        req.add_property(self.plane, props.src_y, Value::UnsignedRange(0));
+        req.add_property(
+            self.plane,
+            props.src_w,
+            Value::UnsignedRange((width as u64) << 16),
+        );
+        req.add_property(
+            self.plane,
+            props.src_h,
+            Value::UnsignedRange((height as u64) << 16),
+        );
+        req.add_property(self.plane, props.crtc_x, Value::SignedRange(x as i64));
+        req.add_property(self.plane, props.crtc_y, Value::SignedRange(y as i64));
+        req.add_property(self.plane, props.crtc_w, Value::UnsignedRange(width as u64));
+        req.add_property(
+            self.plane,
+            props.crtc_h,
+            Value::UnsignedRange(height as u64),
+        );
+    }
+
+    /// 把画布逐行复制到缓冲区, 缓冲区每行的字节数可能比画布多
+    fn upload(&mut self, index: usize) -> anyhow::Result<()> {
+        let (width, height) = self.size();
+        let row = width as usize * 4;
+        let data = self.canvas.data();
+        self.buffers[index]
+            .bo
+            .map_mut(0, 0, width, height, |mapped| {
+                let stride = mapped.stride() as usize;
+                let buffer = mapped.buffer_mut();
+                for (y, pixels) in data.chunks_exact(row).enumerate() {
+                    buffer[y * stride..y * stride + row].copy_from_slice(pixels);
+                }
+            })
+            .context("无法映射 GBM 缓冲区")
+    }
+}
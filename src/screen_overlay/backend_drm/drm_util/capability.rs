@@ -1,4 +1,5 @@
 use drm::ClientCapability as CC;
+use tracing::{debug, warn};
 pub const CLIENT_CAP_ENUMS: &[CC] = &[CC::Stereo3D, CC::UniversalPlanes, CC::Atomic];
 
 use drm::DriverCapability as DC;
@@ -22,7 +23,7 @@ pub const DRIVER_CAP_ENUMS: &[DC] = &[
 pub fn enable_client_cap<T: drm::Device>(card: &T) {
     for &cap in CLIENT_CAP_ENUMS {
         if let Err(e) = card.set_client_capability(cap, true) {
-            eprintln!("Unable to activate client capability {:?}: {}", cap, e);
+            warn!("Unable to activate client capability {:?}: {}", cap, e);
             return;
         }
     }
@@ -30,6 +31,6 @@ pub fn enable_client_cap<T: drm::Device>(card: &T) {
 
 pub fn get_driver_cap<T: drm::Device>(card: &T) {
     for &cap in DRIVER_CAP_ENUMS {
-        println!("{:?}: {:?}", cap, card.get_driver_capability(cap));
+        debug!("{:?}: {:?}", cap, card.get_driver_capability(cap));
     }
 }
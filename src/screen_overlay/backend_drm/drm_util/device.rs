@@ -1,4 +1,4 @@
-use std::io;
+use std::{io, path::Path};
 
 pub use drm::Device;
 pub use drm::control::Device as ControlDevice;
@@ -21,7 +21,7 @@ impl ControlDevice for Card {}
 
 /// Simple helper methods for opening a `Card`.
 impl Card {
-    pub fn open(path: &str) -> io::Result<Self> {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
         let mut options = std::fs::OpenOptions::new();
         options.read(true);
         options.write(true);
@@ -31,4 +31,41 @@ impl Card {
     pub fn open_global() -> Self {
         Self::open("/dev/dri/card1").unwrap()
     }
+
+    /// 打开第一块有显示器接入的显卡
+    pub fn find() -> io::Result<Self> {
+        let mut paths: Vec<_> = std::fs::read_dir("/dev/dri")?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with("card"))
+            })
+            .collect();
+        paths.sort();
+        for path in paths {
+            let Ok(card) = Self::open(&path) else {
+                continue;
+            };
+            if card.has_connected() {
+                return Ok(card);
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "没有接入显示器的显卡",
+        ))
+    }
+
+    fn has_connected(&self) -> bool {
+        let Ok(resources) = self.resource_handles() else {
+            return false;
+        };
+        resources.connectors().iter().any(|&handle| {
+            self.get_connector(handle, false).is_ok_and(|connector| {
+                connector.state() == drm::control::connector::State::Connected
+            })
+        })
+    }
 }
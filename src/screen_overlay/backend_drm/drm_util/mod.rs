@@ -1,2 +1,4 @@
 pub mod capability;
 pub mod device;
+/// 按名称查找属性
+pub mod property;
@@ -0,0 +1,41 @@
+use std::{collections::HashMap, io};
+
+use drm::control::{Device as ControlDevice, ResourceHandle, property};
+
+/// 一个 DRM 对象的属性, 按名称查找
+///
+/// atomic commit 需要属性的 id，而 id 由驱动分配，只能按名称查询
+#[derive(Debug, Clone, Default)]
+pub struct PropertySet {
+    props: HashMap<String, (property::Handle, property::RawValue)>,
+}
+
+impl PropertySet {
+    pub fn query<D, H>(device: &D, handle: H) -> io::Result<Self>
+    where
+        D: ControlDevice,
+        H: ResourceHandle,
+    {
+        let set = device.get_properties(handle)?;
+        let (ids, values) = set.as_props_and_values();
+        let mut props = HashMap::new();
+        for (&id, &value) in ids.iter().zip(values) {
+            let info = device.get_property(id)?;
+            props.insert(info.name().to_string_lossy().into_owned(), (id, value));
+        }
+        Ok(Self { props })
+    }
+
+    /// 属性的 id, 对象没有这个属性时返回 [`io::ErrorKind::NotFound`]
+    pub fn handle(&self, name: &str) -> io::Result<property::Handle> {
+        self.props
+            .get(name)
+            .map(|(id, _)| *id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("没有属性 {name}")))
+    }
+
+    /// 查询时属性的值
+    pub fn value(&self, name: &str) -> Option<property::RawValue> {
+        self.props.get(name).map(|(_, value)| *value)
+    }
+}
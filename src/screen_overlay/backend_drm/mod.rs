@@ -1,121 +1,244 @@
+//! # DRM overlay backend
+//!
+//! 没有合成器时(例如 kiosk 或者直接在 tty 上运行的程序)直接通过 DRM 显示光标.
+//! 每个已经点亮的显示器(CRTC)分配一个光标平面，没有空闲的光标平面时改用叠加平面;
+//! 光标绘制到 GBM 缓冲区，用 atomic commit 在垂直同步时切换缓冲区和移动平面，不会撕裂
+//!
+//! 这个后端不设置显示模式: 显示器需要已经由其他程序点亮，tabletd 只在上面叠加光标.
+//! atomic commit 需要 DRM master, 有合成器在运行时应该使用 `wayland` 后端
+
+use anyhow::{Context, bail};
+use drm::{
+    ClientCapability, Device as _, DriverCapability,
+    control::{
+        AtomicCommitFlags, Device as ControlDevice, ResourceHandles, atomic::AtomicModeReq,
+        connector, crtc, plane,
+    },
+};
+use gbm::Device as GbmDevice;
+use tracing::{debug, warn};
+
+use crate::mapping::OutputGeometry;
+
+use super::cursor_manager::CursorManager;
+
+use cursor_plane::{CursorPlane, PlaneKind};
+use drm_util::{device::Card, property::PropertySet};
+
+/// 每个显示器上显示光标的平面
+pub mod cursor_plane;
 pub mod drm_util;
-use drm::control::{Device, Mode, connector, crtc};
-// use drm::Device;
-use gbm::{BufferObjectFlags, Device as GbmDevice};
 
-pub fn test_overlay() {
-    // 打开 DRM 设备
-    let device = drm_util::device::Card::open_global();
+/// 驱动没有报告光标尺寸时使用的尺寸
+const DEFAULT_CURSOR_SIZE: u64 = 64;
 
-    // 创建一个新的 DRM 平面
-    // let plane = device.create_plane().unwrap();
-
-    // 设置平面的格式和大小
-    // plane.set_format(drm::Format::Argb8888).unwrap();
-    // plane.set_size(1024, 768).unwrap();
-
-    drm_util::capability::enable_client_cap(&device);
-    drm_util::capability::get_driver_cap(&device);
-
-    println!("enable_client_cap done");
-
-    // let cursor_surface = gbm_device
-    //     .create_surface::<()>(256, 256, gbm::Format::Argb8888, BufferObjectFlags::CURSOR)
-    //     .unwrap();
-
-    fn test_display<T: drm::Device + drm::control::Device>(
-        device: &T,
-        crtc_handle: crtc::Handle,
-        conn: connector::Handle,
-        mode: Option<Mode>,
-    ) {
-        let gbm_device = GbmDevice::new(device).unwrap();
+/// 直接在 DRM 平面上显示光标
+pub struct DrmOverlay {
+    gbm: GbmDevice<Card>,
+    planes: Vec<CursorPlane>,
+}
 
-        println!("gbm backend {}", gbm_device.backend_name());
+impl DrmOverlay {
+    /// 打开第一块有显示器接入的显卡
+    pub fn open() -> anyhow::Result<Self> {
+        Self::with_card(Card::find().context("找不到可用的显卡")?)
+    }
 
-        let supported = gbm_device.is_format_supported(
-            gbm::Format::Argb8888,
-            BufferObjectFlags::SCANOUT | BufferObjectFlags::WRITE,
+    pub fn with_card(card: Card) -> anyhow::Result<Self> {
+        card.set_client_capability(ClientCapability::UniversalPlanes, true)
+            .context("驱动不支持 universal planes")?;
+        card.set_client_capability(ClientCapability::Atomic, true)
+            .context("驱动不支持 atomic modesetting")?;
+        if let Err(e) = card.acquire_master_lock() {
+            warn!("无法成为 DRM master, 其他程序正在使用显示器时光标无法显示: {e}");
+        }
+        let cursor_size = (
+            card.get_driver_capability(DriverCapability::CursorWidth)
+                .unwrap_or(DEFAULT_CURSOR_SIZE) as u32,
+            card.get_driver_capability(DriverCapability::CursorHeight)
+                .unwrap_or(DEFAULT_CURSOR_SIZE) as u32,
         );
-        println!("{}", supported);
-
-        // let cursor_surface = gbm_device
-        //     .create_surface::<()>(512, 512, gbm::Format::Argb8888, BufferObjectFlags::SCANOUT)
-        //     .unwrap();
-
-        let mut bo = gbm_device
-            .create_buffer_object::<()>(
-                512,
-                512,
-                gbm::Format::Argb8888,
-                BufferObjectFlags::SCANOUT | BufferObjectFlags::WRITE, // BufferObjectFlags::SCANOUT | BufferObjectFlags::WRITE,
-            )
-            .unwrap();
 
-        let buffer = {
-            let mut buffer = Vec::new();
-            for i in 0..512 {
-                for _ in 0..512 {
-                    buffer.push(if i % 2 == 0 { 0 } else { 255 });
+        let gbm = GbmDevice::new(card).context("无法创建 GBM 设备")?;
+        let resources = gbm.resource_handles()?;
+        let mut planes: Vec<CursorPlane> = Vec::new();
+        for &handle in resources.connectors() {
+            let connector = gbm.get_connector(handle, false)?;
+            if connector.state() != connector::State::Connected {
+                continue;
+            }
+            let Some(encoder) = connector.current_encoder() else {
+                continue;
+            };
+            let Some(crtc) = gbm.get_encoder(encoder)?.crtc() else {
+                continue;
+            };
+            // 没有点亮的显示器
+            let Some(mode) = gbm.get_crtc(crtc)?.mode() else {
+                continue;
+            };
+            let output = format!(
+                "{}-{}",
+                connector.interface().as_str(),
+                connector.interface_id()
+            );
+            let (width, height) = mode.size();
+            let taken: Vec<_> = planes.iter().map(|plane| plane.plane).collect();
+            match pick_plane(
+                &gbm,
+                &resources,
+                &taken,
+                &output,
+                crtc,
+                (width as u32, height as u32),
+                cursor_size,
+            )? {
+                Some(plane) => {
+                    debug!("{output} 使用 {:?} 平面 {:?}", plane.kind, plane.plane);
+                    planes.push(plane);
                 }
+                None => warn!("{output} 没有可以显示光标的平面"),
             }
-            buffer
-        };
-
-        bo.write(&buffer).unwrap();
-
-        let fb = gbm_device.add_framebuffer(&bo, 32, 32).unwrap();
+        }
+        if planes.is_empty() {
+            bail!("没有可以显示光标的显示器");
+        }
+        Ok(Self { gbm, planes })
+    }
 
-        gbm_device
-            .set_crtc(crtc_handle, Some(fb), (0, 0), &[conn], mode)
-            .unwrap();
+    /// 显示器的几何信息. DRM 没有显示器的布局，按接口顺序从左到右排列
+    pub fn outputs(&self) -> Vec<OutputGeometry> {
+        let mut x = 0.0;
+        self.planes
+            .iter()
+            .map(|plane| {
+                let (width, height) = plane.mode_size;
+                let output = OutputGeometry {
+                    id: None,
+                    name: plane.output.clone(),
+                    x,
+                    y: 0.0,
+                    width,
+                    height,
+                    scale: 1.0,
+                };
+                x += width as f64;
+                output
+            })
+            .collect()
     }
 
-    // ---
+    /// 绘制一帧并提交，阻塞到切换完成(下一次垂直同步)
+    ///
+    /// 光标平面只能显示一个光标: 同一个显示器上有多个光标时以第一个为中心，
+    /// 平面之外的部分不显示
+    pub fn render(&mut self, cursors: &CursorManager) -> anyhow::Result<()> {
+        let mut req = AtomicModeReq::new();
+        let mut changed = false;
+        for plane in &mut self.planes {
+            let Some(&(x, y)) = cursors.positions(&plane.output).first() else {
+                if plane.is_visible() {
+                    plane.hide(&mut req);
+                    changed = true;
+                }
+                continue;
+            };
+            let origin = match plane.kind {
+                PlaneKind::Cursor => {
+                    let (width, height) = plane.size();
+                    (
+                        (x - width as f32 / 2.0).round() as i32,
+                        (y - height as f32 / 2.0).round() as i32,
+                    )
+                }
+                _ => (0, 0),
+            };
+            let output = plane.output.clone();
+            cursors.render_at(&output, (origin.0 as f32, origin.1 as f32), plane.begin());
+            plane.present(&mut req, origin)?;
+            changed = true;
+        }
+        if changed {
+            self.gbm
+                .atomic_commit(AtomicCommitFlags::empty(), req)
+                .context("atomic commit 失败")?;
+        }
+        Ok(())
+    }
+}
 
-    let resources = device.resource_handles().unwrap();
+impl Drop for DrmOverlay {
+    fn drop(&mut self) {
+        let mut req = AtomicModeReq::new();
+        for plane in &mut self.planes {
+            plane.hide(&mut req);
+        }
+        if let Err(e) = self.gbm.atomic_commit(AtomicCommitFlags::empty(), req) {
+            warn!("无法关闭光标平面: {e}");
+        }
+        for plane in &self.planes {
+            plane.destroy(&self.gbm);
+        }
+    }
+}
 
-    for connector_handle in resources.connectors() {
-        let connector = device.get_connector(*connector_handle, false).unwrap();
-        if connector.state() != drm::control::connector::State::Connected {
+/// 为 `crtc` 选择一个平面: 优先使用光标平面, 其次是叠加平面.
+/// 每个候选平面先用 `TEST_ONLY` 提交一次，驱动接受的才会被使用
+fn pick_plane(
+    gbm: &GbmDevice<Card>,
+    resources: &ResourceHandles,
+    taken: &[plane::Handle],
+    output: &str,
+    crtc: crtc::Handle,
+    mode_size: (u32, u32),
+    cursor_size: (u32, u32),
+) -> anyhow::Result<Option<CursorPlane>> {
+    let mut candidates = Vec::new();
+    for handle in gbm.plane_handles()? {
+        if taken.contains(&handle) {
             continue;
         }
-        println!("connector handle {connector_handle:?}");
-        println!("connector info {connector:?}");
-        if let Some(encoder_handle) = connector.current_encoder() {
-            let encoder = device.get_encoder(encoder_handle).unwrap();
-            println!("encoder info {encoder:?}");
-            if let Some(crtc_handle) = encoder.crtc() {
-                let crtc = device.get_crtc(crtc_handle).unwrap();
-                println!("crtc info {crtc:?}");
-                test_display(&device, crtc_handle, *connector_handle, crtc.mode());
-                println!("");
-                for plane_handle in device.plane_handles().unwrap() {
-                    let plane = device.get_plane(plane_handle).unwrap();
-                    if plane.crtc() != Some(crtc_handle) {
-                        continue;
-                    }
-                    println!("plane info {plane:?}");
-                    let fb_handle = plane.framebuffer().unwrap();
-                    let fb = device.get_planar_framebuffer(fb_handle).unwrap();
-                    println!("fb info {fb:?}");
-                    // display
-                    println!("");
-                }
+        let info = gbm.get_plane(handle)?;
+        if !resources
+            .filter_crtcs(info.possible_crtcs())
+            .contains(&crtc)
+        {
+            continue;
+        }
+        if !info.formats().contains(&(gbm::Format::Argb8888 as u32)) {
+            continue;
+        }
+        let kind = PropertySet::query(gbm, handle)?
+            .value("type")
+            .and_then(PlaneKind::from_raw);
+        if let Some(kind @ (PlaneKind::Cursor | PlaneKind::Overlay)) = kind {
+            candidates.push((handle, kind));
+        }
+    }
+    candidates.sort_by_key(|(_, kind)| *kind != PlaneKind::Cursor);
+
+    for (handle, kind) in candidates {
+        let plane = match CursorPlane::new(
+            gbm,
+            output.to_string(),
+            crtc,
+            mode_size,
+            handle,
+            kind,
+            cursor_size,
+        ) {
+            Ok(plane) => plane,
+            Err(e) => {
+                warn!("{output} 无法使用平面 {handle:?}: {e:#}");
+                continue;
             }
+        };
+        let mut req = AtomicModeReq::new();
+        plane.test(&mut req);
+        if gbm.atomic_commit(AtomicCommitFlags::TEST_ONLY, req).is_ok() {
+            return Ok(Some(plane));
         }
+        plane.destroy(gbm);
     }
-
-    // let surface = gbm_device
-    //     .create_surface(1024, 768, gbm::Format::Abgr8888, BufferObjectFlags::CURSOR)
-    //     .unwrap();
-
-    // // 将 GBM 表面附加到 DRM 平面
-    // plane.attach_surface(&surface).unwrap();
-
-    // // 渲染内容到平面上
-    // //...
-
-    // // 提交平面
-    // plane.commit().unwrap();
+    Ok(None)
 }
@@ -0,0 +1,202 @@
+use std::time::Instant;
+
+use wayland_client::{
+    Connection, Dispatch, QueueHandle, delegate_noop,
+    protocol::{wl_callback, wl_compositor, wl_shm, wl_subcompositor, wl_subsurface, wl_surface},
+};
+use wayland_protocols::wp::viewporter::client::{wp_viewport, wp_viewporter};
+
+use crate::screen_overlay::{canvas::Canvas, id::SurfaceId};
+
+use super::{
+    WaylandEventState,
+    frame::{CURSOR_SIZE, ShmBuffer},
+    surface_info::RawSurfaceInfo,
+};
+
+/// 光标 subsurface 的帧回调
+pub(super) struct CursorFrame(SurfaceId);
+
+/// 显示光标的 subsurface, 见 [`crate::screen_overlay::strategy::OverlayStrategy::CursorSurface`]
+///
+/// 和全屏 surface 一样由帧回调控制绘制的频率
+pub(super) struct CursorSurface {
+    surface: wl_surface::WlSurface,
+    subsurface: wl_subsurface::WlSubsurface,
+    viewport: Option<wp_viewport::WpViewport>,
+    buffers: Vec<ShmBuffer>,
+    /// 当前的位置(逻辑坐标), `None` 表示隐藏
+    position: Option<(i32, i32)>,
+    callback_pending: bool,
+    /// 绘制线程正在绘制光标
+    rendering: bool,
+    dirty: bool,
+    /// 新位置开始等待帧回调的时间
+    waiting_since: Option<Instant>,
+}
+
+impl CursorSurface {
+    pub(super) fn new(
+        parent: &RawSurfaceInfo,
+        compositor: &wl_compositor::WlCompositor,
+        subcompositor: &wl_subcompositor::WlSubcompositor,
+        viewporter: Option<&wp_viewporter::WpViewporter>,
+        qhandle: &QueueHandle<WaylandEventState>,
+    ) -> Self {
+        let surface = compositor.create_surface(qhandle, ());
+        let input_region = compositor.create_region(qhandle, ());
+        surface.set_input_region(Some(&input_region));
+        input_region.destroy();
+        let subsurface = subcompositor.get_subsurface(&surface, &parent.surface, qhandle, ());
+        // 光标单独提交, 不等全屏 surface 的下一帧
+        subsurface.set_desync();
+        // 全屏 surface 有 viewport 时按分数缩放分配缓冲区
+        let viewport = parent
+            .viewport
+            .as_ref()
+            .and(viewporter)
+            .map(|viewporter| viewporter.get_viewport(&surface, qhandle, ()));
+        Self {
+            surface,
+            subsurface,
+            viewport,
+            buffers: Vec::new(),
+            position: None,
+            callback_pending: false,
+            rendering: false,
+            dirty: false,
+            waiting_since: None,
+        }
+    }
+
+    /// 请求重绘, 返回现在是否应该绘制
+    pub(super) fn request(&mut self) -> bool {
+        if self.callback_pending || self.rendering {
+            self.mark_dirty();
+            return false;
+        }
+        true
+    }
+
+    /// 把光标交给绘制线程
+    pub(super) fn begin_render(&mut self) {
+        self.rendering = true;
+    }
+
+    /// 绘制线程画好了光标
+    pub(super) fn rendered(&mut self) {
+        self.rendering = false;
+    }
+
+    /// 隐藏光标时不请求帧回调, 绘制期间又有变化的话需要立刻重画
+    pub(super) fn take_idle_dirty(&mut self) -> bool {
+        if self.callback_pending || self.rendering {
+            return false;
+        }
+        self.waiting_since = None;
+        std::mem::take(&mut self.dirty)
+    }
+
+    /// 收到帧回调, 返回是否需要绘制
+    pub(super) fn done(&mut self) -> bool {
+        self.callback_pending = false;
+        self.waiting_since = None;
+        std::mem::take(&mut self.dirty)
+    }
+
+    /// 新位置从什么时候开始等待帧回调, 没有等待的内容时为 `None`
+    pub(super) fn waiting_since(&self) -> Option<Instant> {
+        self.waiting_since
+    }
+
+    fn mark_dirty(&mut self) {
+        self.dirty = true;
+        if self.waiting_since.is_none() {
+            self.waiting_since = Some(Instant::now());
+        }
+    }
+
+    /// 在 `origin` (全屏 surface 上的像素位置) 显示画好的光标, `None` 时隐藏. 返回损坏区域的像素数
+    pub(super) fn present(
+        &mut self,
+        parent: &RawSurfaceInfo,
+        canvas: &Canvas,
+        origin: Option<(i32, i32)>,
+        shm: &wl_shm::WlShm,
+        qhandle: &QueueHandle<WaylandEventState>,
+    ) -> anyhow::Result<u64> {
+        let Some((x, y)) = origin else {
+            if self.position.take().is_some() {
+                self.surface.attach(None, 0, 0);
+                self.surface.commit();
+            }
+            return Ok(0);
+        };
+        let Some(buffer) = ShmBuffer::write(&mut self.buffers, canvas, shm, qhandle)? else {
+            // 合成器还拿着所有缓冲区, 等下一帧
+            self.surface.frame(qhandle, CursorFrame(parent.id));
+            self.surface.commit();
+            self.callback_pending = true;
+            self.mark_dirty();
+            return Ok(0);
+        };
+
+        let scale = canvas.scale();
+        let position = (
+            (x as f64 / scale).round() as i32,
+            (y as f64 / scale).round() as i32,
+        );
+        let moved = self.position != Some(position);
+        if moved {
+            self.subsurface.set_position(position.0, position.1);
+        }
+        self.surface.attach(Some(buffer), 0, 0);
+        match &self.viewport {
+            Some(viewport) => viewport.set_destination(CURSOR_SIZE as i32, CURSOR_SIZE as i32),
+            None => self.surface.set_buffer_scale(scale as i32),
+        }
+        let size = (canvas.width(), canvas.height());
+        self.surface
+            .damage_buffer(0, 0, size.0 as i32, size.1 as i32);
+        self.surface.frame(qhandle, CursorFrame(parent.id));
+        self.surface.commit();
+        // subsurface 的位置在父 surface 提交时才生效, 空提交不会重绘全屏 surface 的内容
+        if moved {
+            parent.surface.commit();
+        }
+        self.position = Some(position);
+        self.callback_pending = true;
+        Ok(size.0 as u64 * size.1 as u64)
+    }
+
+    pub(super) fn destroy(self) {
+        for buffer in &self.buffers {
+            buffer.destroy();
+        }
+        if let Some(viewport) = &self.viewport {
+            viewport.destroy();
+        }
+        self.subsurface.destroy();
+        self.surface.destroy();
+    }
+}
+
+impl Dispatch<wl_callback::WlCallback, CursorFrame> for WaylandEventState {
+    fn event(
+        state: &mut Self,
+        _: &wl_callback::WlCallback,
+        event: wl_callback::Event,
+        CursorFrame(id): &CursorFrame,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wl_callback::Event::Done { .. } = event
+            && let Ok(mut shared) = state.shared.lock()
+        {
+            shared.cursor_frame_done(*id);
+        }
+    }
+}
+
+delegate_noop!(WaylandEventState: ignore wl_subcompositor::WlSubcompositor);
+delegate_noop!(WaylandEventState: ignore wl_subsurface::WlSubsurface);
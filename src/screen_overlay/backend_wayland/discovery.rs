@@ -0,0 +1,277 @@
+//! 找到要连接的 Wayland 合成器
+//!
+//! 通常从 `WAYLAND_DISPLAY` 得知合成器的 socket. tabletd 作为用户服务启动时环境中常常没有
+//! 这个变量, 这时扫描 `$XDG_RUNTIME_DIR` 中的 `wayland-*` socket, 逐个连接检查是否支持
+//! `wlr-layer-shell`, 再按 [`DisplayPolicy`] 选出一个. 同时运行多个合成器(比如嵌套的合成器)
+//! 时可以在配置文件中指定名称, 或者由控制接口选择:
+//!
+//! ```toml
+//! [overlay]
+//! display = "ask"   # 或 "first", 或 { name = "wayland-1" }
+//! ```
+
+use std::{
+    os::unix::{fs::FileTypeExt, net::UnixStream},
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::info;
+use wayland_client::{Connection, Dispatch, QueueHandle, protocol::wl_registry};
+
+/// 没有 `WAYLAND_DISPLAY` 时选择哪个合成器, 配置文件中的 `[overlay] display`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisplayPolicy {
+    /// 按名称排序后第一个支持 `wlr-layer-shell` 的合成器
+    #[default]
+    First,
+    /// 总是连接这个 socket, 如 `wayland-1`, 设置了 `WAYLAND_DISPLAY` 也不使用
+    Name(String),
+    /// 只有一个可用的合成器时直接连接, 有多个时等待控制接口选择
+    Ask,
+}
+
+/// 扫描到的一个合成器
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DisplayCandidate {
+    /// socket 名称, 如 `wayland-0`
+    pub name: String,
+    /// 支持 `wlr-layer-shell`, 可以创建 overlay
+    pub layer_shell: bool,
+}
+
+#[derive(Debug, Default)]
+struct Choice {
+    /// 等待选择的合成器, 不在等待时为空
+    pending: Vec<DisplayCandidate>,
+    /// 上一次选择的 socket 名称, 重新连接时如果还在就不再询问
+    chosen: Option<String>,
+}
+
+/// 按 [`DisplayPolicy`] 选择合成器, 控制接口通过它回答 [`DisplayPolicy::Ask`] 的询问
+#[derive(Debug, Clone, Default)]
+pub struct DisplayChooser {
+    policy: DisplayPolicy,
+    choice: Arc<(Mutex<Choice>, Condvar)>,
+}
+
+impl DisplayChooser {
+    pub fn new(policy: DisplayPolicy) -> Self {
+        Self {
+            policy,
+            choice: Default::default(),
+        }
+    }
+
+    /// 正在等待选择的合成器, 没有在等待时为空
+    pub fn pending(&self) -> Vec<DisplayCandidate> {
+        self.choice.0.lock().unwrap().pending.clone()
+    }
+
+    /// 上一次连接的合成器, 通过 `WAYLAND_DISPLAY` 连接时为 `None`
+    pub fn chosen(&self) -> Option<String> {
+        self.choice.0.lock().unwrap().chosen.clone()
+    }
+
+    /// 回答正在等待的询问
+    pub fn choose(&self, name: &str) -> anyhow::Result<()> {
+        let (choice, ready) = &*self.choice;
+        let mut choice = choice.lock().unwrap();
+        if choice.pending.is_empty() {
+            bail!("没有在等待选择合成器");
+        }
+        if !choice
+            .pending
+            .iter()
+            .any(|candidate| candidate.name == name && candidate.layer_shell)
+        {
+            bail!("{name} 不是可用的合成器");
+        }
+        choice.pending.clear();
+        choice.chosen = Some(name.to_string());
+        ready.notify_all();
+        Ok(())
+    }
+
+    /// 按策略连接合成器, 需要询问时阻塞到控制接口选择或 `alive` 关闭
+    pub(super) fn connect(&self, alive: &mpsc::Receiver<()>) -> anyhow::Result<Connection> {
+        if let DisplayPolicy::Name(name) = &self.policy {
+            self.choice.0.lock().unwrap().chosen = Some(name.clone());
+            return connect_to(name);
+        }
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            return Ok(Connection::connect_to_env()?);
+        }
+
+        let runtime_dir = runtime_dir()?;
+        let candidates: Vec<_> = scan(&runtime_dir)
+            .into_iter()
+            .map(|name| {
+                let layer_shell = probe(&runtime_dir.join(&name)).unwrap_or(false);
+                DisplayCandidate { name, layer_shell }
+            })
+            .collect();
+        let usable: Vec<_> = candidates
+            .iter()
+            .filter(|candidate| candidate.layer_shell)
+            .map(|candidate| candidate.name.clone())
+            .collect();
+        let name = match usable.as_slice() {
+            [] if candidates.is_empty() => {
+                bail!(
+                    "未设置 WAYLAND_DISPLAY, {} 中也没有 Wayland socket",
+                    runtime_dir.display()
+                )
+            }
+            [] => bail!("没有支持 wlr-layer-shell 的合成器"),
+            [only] => only.clone(),
+            [first, ..] => match self.policy {
+                DisplayPolicy::Ask => self.ask(candidates, alive)?,
+                _ => {
+                    info!("找到多个Wayland合成器 {usable:?}, 使用 {first}");
+                    first.clone()
+                }
+            },
+        };
+        self.choice.0.lock().unwrap().chosen = Some(name.clone());
+        connect_to(&name)
+    }
+
+    /// 检查有没有可以连接的合成器, 不连接也不询问, 返回找到的 socket 名称
+    pub fn probe(&self) -> anyhow::Result<String> {
+        let name = match &self.policy {
+            DisplayPolicy::Name(name) => Some(name.clone()),
+            _ => std::env::var("WAYLAND_DISPLAY").ok(),
+        };
+        if let Some(name) = name {
+            if !probe(&socket_path(&name)?)? {
+                bail!("{name} 不支持 wlr-layer-shell");
+            }
+            return Ok(name);
+        }
+        let runtime_dir = runtime_dir()?;
+        scan(&runtime_dir)
+            .into_iter()
+            .find(|name| probe(&runtime_dir.join(name)).unwrap_or(false))
+            .with_context(|| {
+                format!(
+                    "{} 中没有支持 wlr-layer-shell 的合成器",
+                    runtime_dir.display()
+                )
+            })
+    }
+
+    /// 等待控制接口选择, 上一次选择的合成器还在时直接使用
+    fn ask(
+        &self,
+        candidates: Vec<DisplayCandidate>,
+        alive: &mpsc::Receiver<()>,
+    ) -> anyhow::Result<String> {
+        let (choice, ready) = &*self.choice;
+        let mut choice = choice.lock().unwrap();
+        if let Some(chosen) = &choice.chosen
+            && candidates
+                .iter()
+                .any(|candidate| &candidate.name == chosen && candidate.layer_shell)
+        {
+            return Ok(chosen.clone());
+        }
+        let names: Vec<_> = candidates
+            .iter()
+            .filter(|candidate| candidate.layer_shell)
+            .map(|candidate| candidate.name.as_str())
+            .collect();
+        info!("找到多个Wayland合成器 {names:?}, 等待通过 `tabletctl display` 选择");
+        choice.chosen = None;
+        choice.pending = candidates;
+        loop {
+            // 定时醒来检查 overlay 是否已经停止
+            choice = ready
+                .wait_timeout(choice, Duration::from_secs(1))
+                .unwrap()
+                .0;
+            if let Some(chosen) = &choice.chosen {
+                return Ok(chosen.clone());
+            }
+            if alive.is_closed() {
+                choice.pending.clear();
+                bail!("overlay 已停止");
+            }
+        }
+    }
+}
+
+fn runtime_dir() -> anyhow::Result<PathBuf> {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .context("未设置 WAYLAND_DISPLAY 和 XDG_RUNTIME_DIR")
+}
+
+/// 和 `WAYLAND_DISPLAY` 一样, `name` 可以是绝对路径或 `$XDG_RUNTIME_DIR` 中的名称
+fn socket_path(name: &str) -> anyhow::Result<PathBuf> {
+    if Path::new(name).is_absolute() {
+        Ok(PathBuf::from(name))
+    } else {
+        Ok(runtime_dir()?.join(name))
+    }
+}
+
+fn connect_to(name: &str) -> anyhow::Result<Connection> {
+    let path = socket_path(name)?;
+    let stream =
+        UnixStream::connect(&path).with_context(|| format!("无法连接 {}", path.display()))?;
+    Ok(Connection::from_socket(stream)?)
+}
+
+/// `dir` 中 `wayland-*` socket 的名称, 按名称排序
+pub fn scan(dir: &Path) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut names: Vec<_> = entries
+        .flatten()
+        .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_socket()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| name.starts_with("wayland-"))
+        .collect();
+    names.sort();
+    names
+}
+
+#[derive(Default)]
+struct Probe {
+    layer_shell: bool,
+}
+
+impl Dispatch<wl_registry::WlRegistry, ()> for Probe {
+    fn event(
+        state: &mut Self,
+        _: &wl_registry::WlRegistry,
+        event: wl_registry::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wl_registry::Event::Global { interface, .. } = event
+            && interface == "zwlr_layer_shell_v1"
+        {
+            state.layer_shell = true;
+        }
+    }
+}
+
+/// 连接 `path` 上的合成器, 检查是否支持 `wlr-layer-shell`
+pub fn probe(path: &Path) -> anyhow::Result<bool> {
+    let stream = UnixStream::connect(path)?;
+    let conn = Connection::from_socket(stream)?;
+    let mut queue = conn.new_event_queue();
+    conn.display().get_registry(&queue.handle(), ());
+    let mut probe = Probe::default();
+    queue.roundtrip(&mut probe)?;
+    Ok(probe.layer_shell)
+}
@@ -0,0 +1,365 @@
+use std::{
+    fs::File,
+    os::{
+        fd::{AsFd, OwnedFd},
+        unix::fs::{FileExt, MetadataExt},
+    },
+    path::PathBuf,
+};
+
+use anyhow::{Context, bail};
+use gbm::{BufferObject, BufferObjectFlags, Device as GbmDevice, Format, Modifier};
+use tracing::warn;
+use wayland_client::{
+    Connection, Dispatch, QueueHandle,
+    protocol::{wl_buffer, wl_surface},
+};
+use wayland_protocols::wp::linux_dmabuf::zv1::client::{
+    zwp_linux_buffer_params_v1, zwp_linux_dmabuf_feedback_v1, zwp_linux_dmabuf_v1,
+};
+
+use crate::screen_overlay::canvas::Canvas;
+
+use super::WaylandEventState;
+
+/// overlay 使用的格式, 和 shm 缓冲区以及 [`crate::screen_overlay::canvas::Canvas`] 相同
+pub const FORMAT: Format = Format::Argb8888;
+
+/// DMA-BUF 缓冲区的一个平面
+#[derive(Debug)]
+pub struct DmaPlane {
+    pub fd: OwnedFd,
+    pub offset: u32,
+    /// 每行的字节数
+    pub stride: u32,
+}
+
+/// 可以交给 GPU 绘制的 overlay 缓冲区
+///
+/// 用 EGL (`EGL_EXT_image_dma_buf_import`) 或 wgpu 导入后直接绘制，
+/// 画完后调用 [`super::Display::commit_dma_buffer`] 显示. 文件描述符是复制出来的，
+/// 丢弃这个结构不会释放缓冲区
+#[derive(Debug)]
+pub struct DmaBuffer {
+    pub width: u32,
+    pub height: u32,
+    /// DRM fourcc
+    pub format: u32,
+    /// DRM 格式修饰符, `DRM_FORMAT_MOD_INVALID` 表示由驱动隐式决定
+    pub modifier: u64,
+    pub planes: Vec<DmaPlane>,
+}
+
+/// 合成器支持的格式和修饰符
+#[derive(Debug, Clone, Default)]
+pub struct DmabufFormats {
+    /// 合成器使用的显卡(`dev_t`), 只有第 4 版协议才会报告
+    pub main_device: Option<u64>,
+    pub formats: Vec<(u32, u64)>,
+}
+
+impl DmabufFormats {
+    /// 合成器接受的 [`FORMAT`] 修饰符
+    pub fn modifiers(&self) -> Vec<u64> {
+        self.formats
+            .iter()
+            .filter(|(format, _)| *format == FORMAT as u32)
+            .map(|(_, modifier)| *modifier)
+            .collect()
+    }
+}
+
+/// 第 4 版协议通过 feedback 对象报告格式, 收到 `done` 之前的内容暂存在这里
+#[derive(Debug, Default)]
+pub(super) struct PendingFeedback {
+    /// 格式表, 每项为格式和修饰符
+    table: Vec<(u32, u64)>,
+    formats: DmabufFormats,
+}
+
+/// 一个 surface 导出的 DMA-BUF 缓冲区
+pub struct DmaSurface {
+    bo: BufferObject<()>,
+    /// 创建 `wl_buffer` 时使用的修饰符
+    modifier: u64,
+    buffer: wl_buffer::WlBuffer,
+}
+
+impl DmaSurface {
+    pub fn size(&self) -> (u32, u32) {
+        (self.bo.width(), self.bo.height())
+    }
+
+    /// 复制文件描述符, 返回给调用者的句柄
+    pub fn export(&self) -> anyhow::Result<DmaBuffer> {
+        let planes = (0..self.bo.plane_count() as i32)
+            .map(|plane| {
+                Ok(DmaPlane {
+                    fd: self.bo.fd_for_plane(plane).context("无法导出 DMA-BUF")?,
+                    offset: self.bo.offset(plane),
+                    stride: self.bo.stride_for_plane(plane),
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(DmaBuffer {
+            width: self.bo.width(),
+            height: self.bo.height(),
+            format: self.bo.format() as u32,
+            modifier: self.modifier,
+            planes,
+        })
+    }
+
+    pub(super) fn buffer(&self) -> &wl_buffer::WlBuffer {
+        &self.buffer
+    }
+
+    /// 把软件绘制的画布逐行复制到缓冲区, 缓冲区每行的字节数可能比画布多
+    pub fn upload(&mut self, canvas: &Canvas) -> anyhow::Result<()> {
+        let (width, height) = self.size();
+        if (canvas.width(), canvas.height()) != (width, height) {
+            bail!(
+                "画布 {}x{} 和 DMA-BUF {width}x{height} 的尺寸不同",
+                canvas.width(),
+                canvas.height()
+            );
+        }
+        let row = width as usize * 4;
+        let data = canvas.data();
+        self.bo
+            .map_mut(0, 0, width, height, |mapped| {
+                let stride = mapped.stride() as usize;
+                let buffer = mapped.buffer_mut();
+                for (y, pixels) in data.chunks_exact(row).enumerate() {
+                    buffer[y * stride..y * stride + row].copy_from_slice(pixels);
+                }
+            })
+            .context("无法映射 DMA-BUF")
+    }
+
+    pub fn attach(&self, surface: &wl_surface::WlSurface) {
+        let (width, height) = self.size();
+        surface.attach(Some(&self.buffer), 0, 0);
+        surface.damage_buffer(0, 0, width as i32, height as i32);
+        surface.commit();
+    }
+
+    pub fn destroy(self) {
+        self.buffer.destroy();
+    }
+}
+
+/// 在合成器使用的显卡上分配 DMA-BUF 并包装成 `wl_buffer`
+pub struct DmabufContext {
+    global: zwp_linux_dmabuf_v1::ZwpLinuxDmabufV1,
+    qhandle: QueueHandle<WaylandEventState>,
+    pub(crate) formats: DmabufFormats,
+    /// 第一次分配时才打开显卡
+    gbm: Option<GbmDevice<File>>,
+}
+
+impl DmabufContext {
+    pub(super) fn new(
+        global: zwp_linux_dmabuf_v1::ZwpLinuxDmabufV1,
+        qhandle: QueueHandle<WaylandEventState>,
+    ) -> Self {
+        Self {
+            global,
+            qhandle,
+            formats: DmabufFormats::default(),
+            gbm: None,
+        }
+    }
+
+    /// 分配 `width`x`height` 的缓冲区
+    pub fn allocate(&mut self, width: u32, height: u32) -> anyhow::Result<DmaSurface> {
+        let modifiers = self.formats.modifiers();
+        if modifiers.is_empty() && !self.formats.formats.is_empty() {
+            bail!("合成器不支持 {FORMAT:?} 格式的 DMA-BUF");
+        }
+        let gbm = match &mut self.gbm {
+            Some(gbm) => gbm,
+            gbm => gbm.insert(open_device(self.formats.main_device)?),
+        };
+
+        // 只有 `DRM_FORMAT_MOD_INVALID` 时由驱动选择修饰符
+        let explicit: Vec<_> = modifiers
+            .iter()
+            .map(|&modifier| Modifier::from(modifier))
+            .filter(|modifier| *modifier != Modifier::Invalid)
+            .collect();
+        let implicit = explicit.is_empty();
+        let bo = if implicit {
+            gbm.create_buffer_object::<()>(width, height, FORMAT, BufferObjectFlags::RENDERING)
+        } else {
+            gbm.create_buffer_object_with_modifiers2::<()>(
+                width,
+                height,
+                FORMAT,
+                explicit.into_iter(),
+                BufferObjectFlags::RENDERING,
+            )
+        }
+        .context("无法分配 DMA-BUF")?;
+        let modifier: u64 = if implicit {
+            Modifier::Invalid.into()
+        } else {
+            bo.modifier().into()
+        };
+
+        let params = self.global.create_params(&self.qhandle, ());
+        for plane in 0..bo.plane_count() as i32 {
+            let fd = bo.fd_for_plane(plane).context("无法导出 DMA-BUF")?;
+            params.add(
+                fd.as_fd(),
+                plane as u32,
+                bo.offset(plane),
+                bo.stride_for_plane(plane),
+                (modifier >> 32) as u32,
+                modifier as u32,
+            );
+        }
+        let buffer = params.create_immed(
+            width as i32,
+            height as i32,
+            FORMAT as u32,
+            zwp_linux_buffer_params_v1::Flags::empty(),
+            &self.qhandle,
+            (),
+        );
+        params.destroy();
+        Ok(DmaSurface {
+            bo,
+            modifier,
+            buffer,
+        })
+    }
+}
+
+/// 打开合成器使用的显卡, 不知道时使用第一个 render node
+fn open_device(main_device: Option<u64>) -> anyhow::Result<GbmDevice<File>> {
+    let mut nodes: Vec<PathBuf> = std::fs::read_dir("/dev/dri")
+        .context("无法列出 /dev/dri")?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .collect();
+    nodes.sort();
+    let path = match main_device {
+        Some(device) => nodes
+            .into_iter()
+            .find(|path| std::fs::metadata(path).is_ok_and(|meta| meta.rdev() == device)),
+        None => nodes.into_iter().find(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("renderD"))
+        }),
+    }
+    .context("找不到合成器使用的显卡")?;
+    let file = File::options()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("无法打开 {}", path.display()))?;
+    GbmDevice::new(file).with_context(|| format!("无法在 {} 上创建 GBM 设备", path.display()))
+}
+
+impl WaylandEventState {
+    fn dmabuf_formats(&self, update: impl FnOnce(&mut DmabufFormats)) {
+        if let Ok(mut shared) = self.shared.lock()
+            && let Some(dmabuf) = shared.dmabuf.as_mut()
+        {
+            update(&mut dmabuf.formats);
+        }
+    }
+}
+
+impl Dispatch<zwp_linux_dmabuf_v1::ZwpLinuxDmabufV1, ()> for WaylandEventState {
+    fn event(
+        state: &mut Self,
+        _: &zwp_linux_dmabuf_v1::ZwpLinuxDmabufV1,
+        event: zwp_linux_dmabuf_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        // 第 3 版协议直接报告修饰符
+        if let zwp_linux_dmabuf_v1::Event::Modifier {
+            format,
+            modifier_hi,
+            modifier_lo,
+        } = event
+        {
+            let modifier = (modifier_hi as u64) << 32 | modifier_lo as u64;
+            state.dmabuf_formats(|formats| formats.formats.push((format, modifier)));
+        }
+    }
+}
+
+impl Dispatch<zwp_linux_dmabuf_feedback_v1::ZwpLinuxDmabufFeedbackV1, ()> for WaylandEventState {
+    fn event(
+        state: &mut Self,
+        _: &zwp_linux_dmabuf_feedback_v1::ZwpLinuxDmabufFeedbackV1,
+        event: zwp_linux_dmabuf_feedback_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let pending = &mut state.dmabuf_feedback;
+        match event {
+            zwp_linux_dmabuf_feedback_v1::Event::FormatTable { fd, size } => {
+                match read_format_table(fd, size) {
+                    Ok(table) => pending.table = table,
+                    Err(e) => warn!("无法读取 DMA-BUF 格式表: {e}"),
+                }
+            }
+            zwp_linux_dmabuf_feedback_v1::Event::MainDevice { device } => {
+                pending.formats.main_device = device
+                    .try_into()
+                    .ok()
+                    .map(|bytes: [u8; 8]| u64::from_ne_bytes(bytes));
+            }
+            zwp_linux_dmabuf_feedback_v1::Event::TrancheFormats { indices } => {
+                let entries = indices
+                    .chunks_exact(2)
+                    .map(|index| u16::from_ne_bytes([index[0], index[1]]) as usize)
+                    .filter_map(|index| pending.table.get(index).copied());
+                pending.formats.formats.extend(entries);
+            }
+            zwp_linux_dmabuf_feedback_v1::Event::Done => {
+                let formats = std::mem::take(&mut pending.formats);
+                state.dmabuf_formats(|current| *current = formats);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// 格式表每项 16 字节: 32 位格式、4 字节填充、64 位修饰符
+fn read_format_table(fd: OwnedFd, size: u32) -> std::io::Result<Vec<(u32, u64)>> {
+    let mut bytes = vec![0; size as usize];
+    File::from(fd).read_exact_at(&mut bytes, 0)?;
+    Ok(bytes
+        .chunks_exact(16)
+        .map(|entry| {
+            let format = u32::from_ne_bytes(entry[0..4].try_into().unwrap());
+            let modifier = u64::from_ne_bytes(entry[8..16].try_into().unwrap());
+            (format, modifier)
+        })
+        .collect())
+}
+
+impl Dispatch<zwp_linux_buffer_params_v1::ZwpLinuxBufferParamsV1, ()> for WaylandEventState {
+    fn event(
+        _: &mut Self,
+        _: &zwp_linux_buffer_params_v1::ZwpLinuxBufferParamsV1,
+        event: zwp_linux_buffer_params_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        // `create_immed` 失败时合成器可能只发送 failed 事件而不是断开连接
+        if let zwp_linux_buffer_params_v1::Event::Failed = event {
+            warn!("合成器拒绝了 DMA-BUF 缓冲区");
+        }
+    }
+}
@@ -0,0 +1,361 @@
+use std::{
+    fs::File,
+    os::{fd::AsFd, unix::fs::FileExt},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use tokio::sync::mpsc;
+use wayland_client::{
+    Connection, Dispatch, QueueHandle,
+    protocol::{wl_buffer, wl_callback, wl_shm},
+};
+
+use crate::screen_overlay::{canvas::Canvas, id::SurfaceId, strategy::OverlayStrategy};
+
+use super::{
+    OverlayCommand, WaylandEventState,
+    dmabuf::DmaSurface,
+    surface_info::{RawSurfaceInfo, SurfaceInfo},
+};
+
+/// 绘制一个 surface 的内容, 返回 `true` 表示还在动画中，下一帧需要继续绘制
+///
+/// 在每个显示器自己的绘制线程中调用, 不同显示器可能同时调用. 调用时不持有 overlay 的内部状态锁,
+/// 需要的状态从 [`crate::screen_overlay::snapshot::Snapshot`] 读取
+pub type Renderer = Arc<dyn Fn(&SurfaceInfo, &mut Canvas) -> bool + Send + Sync>;
+
+/// 光标画布的逻辑尺寸(正方形), 光标要完整地画在里面
+pub const CURSOR_SIZE: u32 = 64;
+
+/// 在 [`CURSOR_SIZE`] 见方(乘以缩放比例)的画布上绘制 surface 上的光标,
+/// 返回画布左上角在 surface 上的像素位置, 这个 surface 上没有光标时返回 `None`
+///
+/// 使用 [`OverlayStrategy::CursorSurface`] 时画布显示在单独的 subsurface 上, 否则叠加到
+/// [`Renderer`] 画好的内容上. 和 [`Renderer`] 一样在显示器的绘制线程中调用
+pub type CursorRenderer =
+    Arc<dyn Fn(&SurfaceInfo, &mut Canvas) -> Option<(i32, i32)> + Send + Sync>;
+
+/// 一帧的绘制记录
+#[derive(Debug, Clone)]
+pub struct FrameSample {
+    /// 实际使用的方式, 合成器不支持配置的方式时会退回 shm
+    pub strategy: OverlayStrategy,
+    /// 绘制、上传和提交的总耗时
+    pub elapsed: Duration,
+    /// 提交给合成器的损坏区域(像素)
+    pub damage: u64,
+}
+
+/// 记录每一帧的绘制, 见 [`super::WaylandOverlay::set_frame_log`]
+pub type FrameLog = Arc<Mutex<Vec<FrameSample>>>;
+
+/// 请求重绘所有 overlay, 可以在任何线程中使用
+///
+/// 多次请求会合并成一次, 实际绘制等合成器准备好下一帧
+#[derive(Clone)]
+pub struct RedrawHandle {
+    requested: Arc<AtomicBool>,
+    cursor_requested: Arc<AtomicBool>,
+    commands: mpsc::Sender<OverlayCommand>,
+}
+
+impl RedrawHandle {
+    pub(super) fn new(commands: mpsc::Sender<OverlayCommand>) -> Self {
+        Self {
+            requested: Arc::new(AtomicBool::new(false)),
+            cursor_requested: Arc::new(AtomicBool::new(false)),
+            commands,
+        }
+    }
+
+    pub fn request(&self) {
+        self.send(&self.requested, OverlayCommand::Redraw);
+    }
+
+    /// 只有光标变化时使用. 光标在单独的 subsurface 上时只重绘光标, 否则和 [`Self::request`] 相同
+    pub fn request_cursor(&self) {
+        self.send(&self.cursor_requested, OverlayCommand::RedrawCursor);
+    }
+
+    fn send(&self, requested: &Arc<AtomicBool>, command: fn(Arc<AtomicBool>) -> OverlayCommand) {
+        if requested.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        // 队列满时 overlay 正忙, 标记留着等下一次请求再发送
+        if self
+            .commands
+            .try_send(command(Arc::clone(requested)))
+            .is_err()
+        {
+            requested.store(false, Ordering::Release);
+        }
+    }
+}
+
+/// 一个 shm 缓冲区, 合成器读取期间不能再写入
+pub(super) struct ShmBuffer {
+    file: File,
+    buffer: wl_buffer::WlBuffer,
+    busy: Arc<AtomicBool>,
+}
+
+impl ShmBuffer {
+    /// 从 `buffers` 中找一个合成器没有在使用的缓冲区写入画布, 不够 [`BUFFER_COUNT`] 个时分配新的.
+    /// 合成器还拿着所有缓冲区时返回 `None`
+    pub(super) fn write<'a>(
+        buffers: &'a mut Vec<ShmBuffer>,
+        canvas: &Canvas,
+        shm: &wl_shm::WlShm,
+        qhandle: &QueueHandle<WaylandEventState>,
+    ) -> anyhow::Result<Option<&'a wl_buffer::WlBuffer>> {
+        let size = (canvas.width(), canvas.height());
+        let index = match buffers
+            .iter()
+            .position(|buffer| !buffer.busy.load(Ordering::Acquire))
+        {
+            Some(index) => index,
+            None if buffers.len() < BUFFER_COUNT => {
+                buffers.push(ShmBuffer::new(shm, size, qhandle)?);
+                buffers.len() - 1
+            }
+            None => return Ok(None),
+        };
+        let buffer = &buffers[index];
+        buffer
+            .file
+            .write_all_at(canvas.data(), 0)
+            .context("无法写入 shm 缓冲区")?;
+        buffer.busy.store(true, Ordering::Release);
+        Ok(Some(&buffer.buffer))
+    }
+
+    pub(super) fn destroy(&self) {
+        self.buffer.destroy();
+    }
+
+    fn new(
+        shm: &wl_shm::WlShm,
+        (width, height): (u32, u32),
+        qhandle: &QueueHandle<WaylandEventState>,
+    ) -> anyhow::Result<Self> {
+        let len = width * height * 4;
+        let file = tempfile::tempfile().context("无法创建 shm 文件")?;
+        file.set_len(len as u64).context("无法分配 shm 缓冲区")?;
+        let pool = shm.create_pool(file.as_fd(), len as i32, qhandle, ());
+        let busy = Arc::new(AtomicBool::new(false));
+        let buffer = pool.create_buffer(
+            0,
+            width as i32,
+            height as i32,
+            (width * 4) as i32,
+            wl_shm::Format::Argb8888,
+            qhandle,
+            Arc::clone(&busy),
+        );
+        pool.destroy();
+        Ok(Self { file, buffer, busy })
+    }
+}
+
+/// 一个 surface 的绘制状态
+///
+/// 没有等待中的帧回调时请求重绘会立刻绘制; 否则只做标记，收到回调后再绘制,
+/// 这样绘制的频率不会超过显示器的刷新率
+#[derive(Default)]
+pub(super) struct FrameState {
+    /// configure 给出的逻辑尺寸, 收到之前不能绘制
+    size: Option<(u32, u32)>,
+    /// `wp_fractional_scale_v1` 给出的缩放比例
+    scale: Option<f64>,
+    /// 已经请求了帧回调，还没有收到
+    callback_pending: bool,
+    /// 绘制线程正在绘制, 画好之前不再开始新的一帧
+    rendering: bool,
+    /// 收到帧回调后需要重绘
+    dirty: bool,
+    /// 需要重绘的内容开始等待帧回调的时间, 见 [`crate::screen_overlay::stacking`]
+    waiting_since: Option<Instant>,
+    /// 最多两个缓冲区轮流使用
+    buffers: Vec<ShmBuffer>,
+}
+
+/// 同时使用的缓冲区数量
+const BUFFER_COUNT: usize = 2;
+
+impl FrameState {
+    pub(super) fn configure(&mut self, size: (u32, u32)) {
+        if self.size != Some(size) {
+            self.size = Some(size);
+            self.destroy_buffers();
+        }
+    }
+
+    pub(super) fn set_scale(&mut self, scale: f64) {
+        if self.scale != Some(scale) {
+            self.scale = Some(scale);
+            self.destroy_buffers();
+        }
+    }
+
+    /// 缓冲区的缩放比例, 没有分数缩放时使用显示器的整数缩放比例
+    pub(super) fn scale(&self, output_scale: i32) -> f64 {
+        self.scale.unwrap_or(output_scale.max(1) as f64)
+    }
+
+    /// 按缩放比例放大后的缓冲区尺寸
+    pub(super) fn buffer_size(&self, output_scale: i32) -> Option<(u32, u32)> {
+        let scale = self.scale(output_scale);
+        self.size.map(|(width, height)| {
+            (
+                (width as f64 * scale).round() as u32,
+                (height as f64 * scale).round() as u32,
+            )
+        })
+    }
+
+    /// 请求重绘, 返回现在是否应该绘制
+    pub(super) fn request(&mut self) -> bool {
+        if self.callback_pending || self.rendering || self.size.is_none() {
+            self.mark_dirty();
+            return false;
+        }
+        true
+    }
+
+    /// 把一帧交给绘制线程
+    pub(super) fn begin_render(&mut self) {
+        self.rendering = true;
+    }
+
+    /// 绘制线程画好了一帧, 返回画好的内容是否还能使用. 绘制期间尺寸或缩放变化时需要重画
+    pub(super) fn rendered(&mut self, canvas: &Canvas, output_scale: i32) -> bool {
+        self.rendering = false;
+        self.buffer_size(output_scale) == Some((canvas.width(), canvas.height()))
+            && self.scale(output_scale) == canvas.scale()
+    }
+
+    /// 收到帧回调, 返回是否需要绘制
+    pub(super) fn done(&mut self) -> bool {
+        self.callback_pending = false;
+        self.waiting_since = None;
+        std::mem::take(&mut self.dirty) && self.size.is_some()
+    }
+
+    /// 需要重绘的内容从什么时候开始等待帧回调, 没有等待的内容时为 `None`
+    pub(super) fn waiting_since(&self) -> Option<Instant> {
+        self.waiting_since
+    }
+
+    fn mark_dirty(&mut self) {
+        self.dirty = true;
+        if self.callback_pending && self.waiting_since.is_none() {
+            self.waiting_since = Some(Instant::now());
+        }
+    }
+
+    /// 把画好的内容写入 shm 缓冲区交给 surface, 并请求下一个帧回调. 返回损坏区域的像素数
+    pub(super) fn present(
+        &mut self,
+        raw: &RawSurfaceInfo,
+        canvas: &Canvas,
+        animating: bool,
+        shm: &wl_shm::WlShm,
+        qhandle: &QueueHandle<WaylandEventState>,
+    ) -> anyhow::Result<u64> {
+        let Some(buffer) = ShmBuffer::write(&mut self.buffers, canvas, shm, qhandle)? else {
+            // 合成器还拿着所有缓冲区, 等下一帧
+            raw.surface.frame(qhandle, raw.id);
+            raw.surface.commit();
+            self.callback_pending = true;
+            self.mark_dirty();
+            return Ok(0);
+        };
+        let buffer = buffer.clone();
+        Ok(self.commit(raw, &buffer, canvas, animating, qhandle))
+    }
+
+    /// 把画好的内容上传到 DMA-BUF 交给 surface, 并请求下一个帧回调. 返回损坏区域的像素数
+    ///
+    /// 只有一个缓冲区, 帧回调之后才会再次写入, 合成器读取得慢时仍然可能看到撕裂
+    pub(super) fn present_dma(
+        &mut self,
+        raw: &RawSurfaceInfo,
+        canvas: &Canvas,
+        animating: bool,
+        dma: &mut DmaSurface,
+        qhandle: &QueueHandle<WaylandEventState>,
+    ) -> anyhow::Result<u64> {
+        dma.upload(canvas)?;
+        Ok(self.commit(raw, dma.buffer(), canvas, animating, qhandle))
+    }
+
+    fn commit(
+        &mut self,
+        raw: &RawSurfaceInfo,
+        buffer: &wl_buffer::WlBuffer,
+        canvas: &Canvas,
+        animating: bool,
+        qhandle: &QueueHandle<WaylandEventState>,
+    ) -> u64 {
+        let surface = &raw.surface;
+        let size = (canvas.width(), canvas.height());
+        surface.attach(Some(buffer), 0, 0);
+        match (&raw.viewport, self.scale, self.size) {
+            (Some(viewport), Some(_), Some((width, height))) => {
+                viewport.set_destination(width as i32, height as i32);
+            }
+            _ => surface.set_buffer_scale(canvas.scale() as i32),
+        }
+        surface.damage_buffer(0, 0, size.0 as i32, size.1 as i32);
+        surface.frame(qhandle, raw.id);
+        surface.commit();
+        self.callback_pending = true;
+        if animating {
+            self.mark_dirty();
+        }
+        size.0 as u64 * size.1 as u64
+    }
+
+    pub(super) fn destroy_buffers(&mut self) {
+        for buffer in self.buffers.drain(..) {
+            buffer.destroy();
+        }
+    }
+}
+
+impl Dispatch<wl_callback::WlCallback, SurfaceId> for WaylandEventState {
+    fn event(
+        state: &mut Self,
+        _: &wl_callback::WlCallback,
+        event: wl_callback::Event,
+        id: &SurfaceId,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wl_callback::Event::Done { .. } = event
+            && let Ok(mut shared) = state.shared.lock()
+        {
+            shared.frame_done(*id);
+        }
+    }
+}
+
+impl Dispatch<wl_buffer::WlBuffer, Arc<AtomicBool>> for WaylandEventState {
+    fn event(
+        _: &mut Self,
+        _: &wl_buffer::WlBuffer,
+        event: wl_buffer::Event,
+        busy: &Arc<AtomicBool>,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wl_buffer::Event::Release = event {
+            busy.store(false, Ordering::Release);
+        }
+    }
+}
@@ -1,35 +1,76 @@
+/// 光标使用的 subsurface
+mod cursor_surface;
+/// 没有 `WAYLAND_DISPLAY` 时寻找合成器
+pub mod discovery;
+/// GPU 绘制用的 DMA-BUF 缓冲区
+pub mod dmabuf;
+/// 由帧回调驱动的绘制
+pub mod frame;
+/// 合成器重启后重新连接
+mod reconnect;
+/// 每个显示器的绘制线程
+mod render_thread;
+/// 分数缩放
+mod scale;
 pub mod surface_info;
 use std::{
     collections::HashMap,
-    fs::File,
-    os::fd::AsFd,
-    sync::{Arc, Mutex},
+    sync::{Arc, Mutex, atomic::AtomicBool},
+    time::Duration,
 };
 
-use tokio::sync::{mpsc, oneshot};
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::MissedTickBehavior,
+};
+use tracing::{debug, info};
 use wayland_client::{
     Connection, Dispatch, QueueHandle, delegate_noop,
     protocol::{
-        wl_buffer, wl_compositor, wl_output, wl_region, wl_registry, wl_shm, wl_shm_pool,
-        wl_surface,
+        wl_buffer, wl_callback, wl_compositor, wl_output, wl_region, wl_registry, wl_shm,
+        wl_shm_pool, wl_subcompositor, wl_surface,
     },
 };
+use wayland_protocols::wp::{
+    fractional_scale::v1::client::wp_fractional_scale_manager_v1,
+    linux_dmabuf::zv1::client::zwp_linux_dmabuf_v1, viewporter::client::wp_viewporter,
+};
 use wayland_protocols_wlr::layer_shell::v1::client::{zwlr_layer_shell_v1, zwlr_layer_surface_v1};
 
+use crate::{
+    config::{Config, ConfigChange, transaction::ConfigStage},
+    mapping::{OutputGeometry, geometry::GeometryBus},
+    screen_overlay::{
+        builder::{InputPolicy, SurfaceOptions},
+        error::OverlayError,
+        id::{Generations, OutputId, SurfaceId},
+        selection::OutputSelection,
+        stacking::StackingConfig,
+        strategy::{OverlayStrategy, StrategyCache, compositor_name},
+    },
+};
+
 mod surface_state;
 
+use discovery::DisplayChooser;
+use dmabuf::{DmaBuffer, DmabufContext, PendingFeedback};
+use frame::{CursorRenderer, FrameLog, RedrawHandle, Renderer};
+use surface_info::{RawSurfaceInfo, SurfaceInfo};
 use surface_state::SurfaceState;
 
 #[derive(Debug)]
 pub struct DisplayInfo {
-    width: u32,
-    height: u32,
-    scale_factor: i32,
-    name: String,
+    pub id: SurfaceId,
+    pub output: OutputId,
+    pub width: u32,
+    pub height: u32,
+    pub scale_factor: i32,
+    pub name: String,
 }
 
 enum DisplayCommand {
-    GetDmaBuffer(oneshot::Sender<()>),
+    GetDmaBuffer(oneshot::Sender<anyhow::Result<DmaBuffer>>),
+    CommitDmaBuffer(oneshot::Sender<anyhow::Result<()>>),
     GetInfo(oneshot::Sender<DisplayInfo>),
 }
 
@@ -38,24 +79,56 @@ pub struct Display {
 }
 
 impl Display {
-    pub async fn get_dma_buffer(&self) -> Result<(), Box<dyn std::error::Error>> {
+    /// 导出 overlay 的 DMA-BUF 缓冲区, 用 GPU 直接绘制，不经过 CPU 复制
+    ///
+    /// 只有一个缓冲区，合成器可能还在读取上一帧，所以绘制时可能看到撕裂
+    pub async fn get_dma_buffer(&self) -> Result<DmaBuffer, OverlayError> {
         let (tx, rx) = oneshot::channel();
         self.channel.send(DisplayCommand::GetDmaBuffer(tx)).await?;
-        Ok(rx.await?)
+        rx.await?.map_err(OverlayError::Buffer)
     }
 
-    pub async fn get_info(&self) -> Result<DisplayInfo, Box<dyn std::error::Error>> {
+    /// 绘制完成后显示 DMA-BUF 缓冲区的内容
+    pub async fn commit_dma_buffer(&self) -> Result<(), OverlayError> {
+        let (tx, rx) = oneshot::channel();
+        self.channel
+            .send(DisplayCommand::CommitDmaBuffer(tx))
+            .await?;
+        rx.await?.map_err(OverlayError::Buffer)
+    }
+
+    pub async fn get_info(&self) -> Result<DisplayInfo, OverlayError> {
         let (tx, rx) = oneshot::channel();
         self.channel.send(DisplayCommand::GetInfo(tx)).await?;
         Ok(rx.await?)
     }
 }
 
+/// 检查 surface 是否被遮住的间隔
+const STACKING_CHECK: Duration = Duration::from_millis(250);
+
 /// WaylandOverlay层支持的命令
+#[allow(clippy::enum_variant_names)]
 enum OverlayCommand {
     GetNextDisplay(oneshot::Sender<Option<SurfaceInfo>>),
     GetCurrentDisplay(oneshot::Sender<Option<SurfaceInfo>>),
-    ReleaseDisplay(u32),
+    ReleaseDisplay(SurfaceId),
+    DestroySurfaces(oneshot::Sender<()>),
+    GetDmaBuffer(SurfaceId, oneshot::Sender<anyhow::Result<DmaBuffer>>),
+    CommitDmaBuffer(SurfaceId, oneshot::Sender<anyhow::Result<()>>),
+    /// 重绘所有 surface, 处理前清除 [`RedrawHandle`] 的请求标记
+    Redraw(Arc<AtomicBool>),
+    /// 只重绘光标, 见 [`RedrawHandle::request_cursor`]
+    RedrawCursor(Arc<AtomicBool>),
+    SetRenderer(Renderer),
+    SetCursorRenderer(CursorRenderer),
+    /// 修改需要 overlay 的显示器
+    SetSelection(OutputSelection),
+    SetStrategy(OverlayStrategy),
+    SetFrameLog(Option<FrameLog>),
+    SetStacking(StackingConfig),
+    /// 定时检查 surface 是否被遮住
+    CheckStacking,
 }
 
 /// WaylandOverlay 代表在Wayland下实现的屏幕叠加层
@@ -68,11 +141,41 @@ pub struct WaylandOverlay {
 impl WaylandOverlay {
     /// 创建一个新的WaylandOverlay实例
     pub fn new() -> Self {
+        Self::with_geometry(GeometryBus::new())
+    }
+
+    /// 创建WaylandOverlay实例，显示器布局变化时发布到 `geometry`
+    pub fn with_geometry(geometry: GeometryBus) -> Self {
+        Self::with_surface(
+            geometry,
+            SurfaceOptions::default(),
+            OutputSelection::all(),
+            OverlayStrategy::default(),
+            DisplayChooser::default(),
+        )
+    }
+
+    /// 连接 `display` 选择的合成器, 按 `options` 在 `selection` 选择的显示器上创建 overlay,
+    /// 见 [`crate::screen_overlay::builder::OverlayBuilder`]
+    pub fn with_surface(
+        geometry: GeometryBus,
+        options: SurfaceOptions,
+        selection: OutputSelection,
+        strategy: OverlayStrategy,
+        display: DisplayChooser,
+    ) -> Self {
         let (command_tx, command_rx) = mpsc::channel(32);
 
         // 启动后台任务来处理Wayland事件
         let task_handle = tokio::spawn(async move {
-            let state = Arc::new(Mutex::new(SurfaceState::new()));
+            let mut initial = SurfaceState::new();
+            initial.selection = selection;
+            initial.requested_strategy = strategy;
+            initial.layer = options.layer;
+            let state = Arc::new_cyclic(|this| {
+                initial.this = this.clone();
+                Mutex::new(initial)
+            });
 
             // 创建一个tokio通道用于启动创建displays的任务
             let (create_tx, mut create_rx) = mpsc::channel::<()>(1);
@@ -81,168 +184,22 @@ impl WaylandOverlay {
             // 发送初始信号以创建displays
             let _ = create_tx.send(()).await;
 
-            // 创建任务来处理Wayland连接
+            // 创建任务来处理Wayland连接, 连接断开后自动重新连接
             let wayland_task = tokio::task::spawn_blocking(move || {
                 if let Some(()) = create_rx.blocking_recv() {
-                    // 在阻塞线程中执行Wayland连接和事件处理
-                    if let Ok(conn) = Connection::connect_to_env() {
-                        let mut event_queue = conn.new_event_queue();
-                        let qhandle = event_queue.handle();
-
-                        // 获取显示
-                        let display = conn.display();
-                        display.get_registry(&qhandle, ());
-
-                        // 创建state
-                        let mut wayland_state = WaylandEventState {
-                            running: true,
-                            compositor: None,
-                            shm: None,
-                            layer_shell: None,
-                            outputs: HashMap::new(),
-                            surfaces: HashMap::new(),
-                            registry_done: false,
-                        };
-
-                        // 第一步：获取所有接口和显示器
-                        println!("获取Wayland接口和显示器信息...");
-                        while !wayland_state.registry_done
-                            || wayland_state.outputs.is_empty()
-                            || !wayland_state.all_outputs_have_size()
-                        {
-                            if let Err(e) = event_queue.blocking_dispatch(&mut wayland_state) {
-                                println!("Wayland事件处理错误: {:?}", e);
-                                break;
-                            }
-                        }
-
-                        // 第二步：为每个显示器创建overlay
-                        println!("为{}个显示器创建overlay", wayland_state.outputs.len());
-                        for (id, output_info) in &wayland_state.outputs {
-                            // 跳过尺寸为0x0的显示器
-                            if !output_info.has_valid_size {
-                                println!("跳过尺寸无效的显示器 #{}", id);
-                                continue;
-                            }
-
-                            println!("为显示器 {} 创建overlay", id);
-
-                            if let (Some(ref compositor), Some(ref layer_shell)) = (
-                                wayland_state.compositor.as_ref(),
-                                wayland_state.layer_shell.as_ref(),
-                            ) {
-                                // 创建基础surface
-                                let surface = compositor.create_surface(&qhandle, ());
-
-                                // 创建输入区域（使overlay不捕获输入）
-                                let input_region = compositor.create_region(&qhandle, ());
-                                surface.set_input_region(Some(&input_region));
-
-                                // 创建layer_surface
-                                let layer_surface = layer_shell.get_layer_surface(
-                                    &surface,
-                                    Some(&output_info.output),
-                                    zwlr_layer_shell_v1::Layer::Overlay,
-                                    format!("tabletd overlay"),
-                                    &qhandle,
-                                    (),
-                                );
-
-                                // 使用显示器实际尺寸
-                                let width = output_info.width.unwrap();
-                                let height = output_info.height.unwrap();
-
-                                // 配置layer_surface
-                                layer_surface.set_size(width as u32, height as u32);
-                                layer_surface.set_anchor(
-                                    zwlr_layer_surface_v1::Anchor::Top
-                                        | zwlr_layer_surface_v1::Anchor::Left
-                                        | zwlr_layer_surface_v1::Anchor::Right
-                                        | zwlr_layer_surface_v1::Anchor::Bottom,
-                                );
-                                layer_surface.set_exclusive_zone(-1);
-                                layer_surface.set_margin(0, 0, 0, 0);
-                                layer_surface.set_keyboard_interactivity(
-                                    zwlr_layer_surface_v1::KeyboardInteractivity::None,
-                                );
-
-                                // 初始化提交surface
-                                surface.commit();
-
-                                // 保存surface信息
-                                println!("保存surface #{}信息", *id);
-                                wayland_state.surfaces.insert(
-                                    *id,
-                                    RawSurfaceInfo {
-                                        id: *id,
-                                        surface,
-                                        layer_surface,
-                                        input_region,
-                                        buffer: None,
-                                    },
-                                );
-
-                                // 更新共享状态
-                                if let Ok(mut state) = state_clone.lock() {
-                                    // state
-                                    //     .raw_surfaces
-                                    //     .insert(*id, wayland_state.surfaces[id].clone());
-
-                                    // 同时更新用于公开API的表面信息
-                                    // state.surfaces.insert(
-                                    //     *id,
-                                    //     SurfaceInfo {
-                                    //         id: *id,
-                                    //         width,
-                                    //         height,
-                                    //         name: output_info.name.clone(),
-                                    //         scale_factor: output_info.scale_factor,
-                                    //     },
-                                    // );
-
-                                    // 如果这是第一个surface，设置为当前surface
-                                    // if state.current_surface_id.is_none() {
-                                    //     state.current_surface_id = Some(*id);
-                                    // }
-                                    state.add_surface(
-                                        *id,
-                                        SurfaceInfo {
-                                            id: *id,
-                                            width,
-                                            height,
-                                            name: output_info.name.clone(),
-                                            scale_factor: output_info.scale_factor,
-                                        },
-                                        wayland_state.surfaces[id].clone(),
-                                    );
-                                }
-                            }
-                        }
-
-                        // 确保至少有一个surface被创建
-                        if wayland_state.surfaces.is_empty() {
-                            println!("没有创建任何surface，请检查显示器配置");
-                            return;
-                        }
-
-                        // 进入主事件循环
-                        println!("进入事件循环...等待configure事件");
-                        while wayland_state.running {
-                            if let Err(e) = event_queue.blocking_dispatch(&mut wayland_state) {
-                                println!("Wayland事件循环错误: {:?}", e);
-                                break;
-                            }
-
-                            // 给其他任务机会处理
-                            // std::thread::sleep(std::time::Duration::from_millis(10));
-                        }
-                    }
+                    // `create_tx` 随命令任务一起释放, 之后不再重新连接
+                    reconnect::run(state_clone, geometry, options, display, create_rx);
                 }
             });
 
             // 处理overlay命令
             let mut command_rx = command_rx;
-            while let Some(cmd) = command_rx.recv().await {
+            let mut stacking_check = tokio::time::interval(STACKING_CHECK);
+            stacking_check.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            while let Some(cmd) = tokio::select! {
+                cmd = command_rx.recv() => cmd,
+                _ = stacking_check.tick() => Some(OverlayCommand::CheckStacking),
+            } {
                 match cmd {
                     OverlayCommand::GetNextDisplay(resp) => {
                         let next_surface = {
@@ -294,10 +251,49 @@ impl WaylandOverlay {
                             if *count == 0 {
                                 state.used_surfaces.remove(&id);
                                 state.available_surfaces.push(id);
-                                println!("显示器 #{} 已释放，现在可用", id);
+                                info!("显示器 {} 已释放，现在可用", id);
                             }
                         }
                     }
+                    OverlayCommand::DestroySurfaces(resp) => {
+                        state.lock().unwrap().destroy_all();
+                        let _ = resp.send(());
+                    }
+                    OverlayCommand::GetDmaBuffer(id, resp) => {
+                        let _ = resp.send(state.lock().unwrap().dma_buffer(id));
+                    }
+                    OverlayCommand::CommitDmaBuffer(id, resp) => {
+                        let _ = resp.send(state.lock().unwrap().commit_dma_buffer(id));
+                    }
+                    OverlayCommand::Redraw(requested) => {
+                        requested.store(false, std::sync::atomic::Ordering::Release);
+                        state.lock().unwrap().request_redraw_all();
+                    }
+                    OverlayCommand::RedrawCursor(requested) => {
+                        requested.store(false, std::sync::atomic::Ordering::Release);
+                        state.lock().unwrap().request_cursor_redraw_all();
+                    }
+                    OverlayCommand::SetRenderer(renderer) => {
+                        state.lock().unwrap().set_renderer(renderer);
+                    }
+                    OverlayCommand::SetCursorRenderer(renderer) => {
+                        state.lock().unwrap().set_cursor_renderer(renderer);
+                    }
+                    OverlayCommand::SetSelection(selection) => {
+                        state.lock().unwrap().set_selection(selection);
+                    }
+                    OverlayCommand::SetStrategy(strategy) => {
+                        state.lock().unwrap().set_strategy(strategy);
+                    }
+                    OverlayCommand::SetFrameLog(log) => {
+                        state.lock().unwrap().frame_log = log;
+                    }
+                    OverlayCommand::SetStacking(config) => {
+                        state.lock().unwrap().set_stacking(config);
+                    }
+                    OverlayCommand::CheckStacking => {
+                        state.lock().unwrap().check_stacking();
+                    }
                 }
             }
 
@@ -312,7 +308,7 @@ impl WaylandOverlay {
     }
 
     /// 获取下一个显示器
-    pub async fn next_display(&self) -> Result<Display, Box<dyn std::error::Error>> {
+    pub async fn next_display(&self) -> Result<Display, OverlayError> {
         let (tx, rx) = oneshot::channel();
 
         // 发送获取下一个显示器的请求
@@ -325,7 +321,7 @@ impl WaylandOverlay {
         let surface = rx.await?;
 
         // 如果没有获取到显示器信息，返回错误
-        let surf = surface.ok_or_else(|| unreachable!())?;
+        let surf = surface.ok_or(OverlayError::NoDisplay)?;
 
         // 创建用于返回的Display实例
         let (channel_tx, mut channel_rx) = mpsc::channel(10);
@@ -347,6 +343,8 @@ impl WaylandOverlay {
                 match cmd {
                     DisplayCommand::GetInfo(resp) => {
                         let info = DisplayInfo {
+                            id: surf_info.id,
+                            output: surf_info.output,
                             width: surf_info.width as u32,
                             height: surf_info.height as u32,
                             scale_factor: surf_info.scale_factor,
@@ -355,8 +353,14 @@ impl WaylandOverlay {
                         let _ = resp.send(info);
                     }
                     DisplayCommand::GetDmaBuffer(resp) => {
-                        // 目前简单返回空结果
-                        let _ = resp.send(());
+                        let _ = tx_clone
+                            .send(OverlayCommand::GetDmaBuffer(display_id, resp))
+                            .await;
+                    }
+                    DisplayCommand::CommitDmaBuffer(resp) => {
+                        let _ = tx_clone
+                            .send(OverlayCommand::CommitDmaBuffer(display_id, resp))
+                            .await;
                     }
                 }
             }
@@ -371,22 +375,101 @@ impl WaylandOverlay {
         Ok(display)
     }
 
-    // 获取当前显示器
-    // pub async fn current_display(&self) -> Option<SurfaceInfo> {
-    //     let (tx, rx) = oneshot::channel();
-    //     if let Err(_) = self
-    //         .command_tx
-    //         .send(OverlayCommand::GetCurrentDisplay(tx))
-    //         .await
-    //     {
-    //         return None;
-    //     }
-
-    //     match rx.await {
-    //         Ok(surface) => surface,
-    //         Err(_) => None,
-    //     }
-    // }
+    /// 销毁所有overlay surface，用于退出前的清理
+    pub async fn destroy_surfaces(&self) -> Result<(), OverlayError> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(OverlayCommand::DestroySurfaces(tx))
+            .await?;
+        Ok(rx.await?)
+    }
+
+    /// 设置绘制 overlay 内容的函数, 之后每一帧都用它绘制. 每个显示器在自己的线程中调用它
+    pub async fn set_renderer(&self, renderer: Renderer) -> Result<(), OverlayError> {
+        self.command_tx
+            .send(OverlayCommand::SetRenderer(renderer))
+            .await?;
+        Ok(())
+    }
+
+    /// 设置绘制光标的函数, 光标变化时用 [`RedrawHandle::request_cursor`] 请求重绘
+    pub async fn set_cursor_renderer(&self, renderer: CursorRenderer) -> Result<(), OverlayError> {
+        self.command_tx
+            .send(OverlayCommand::SetCursorRenderer(renderer))
+            .await?;
+        Ok(())
+    }
+
+    /// 修改绘制方式, 合成器不支持时使用 shm
+    pub async fn set_strategy(&self, strategy: OverlayStrategy) -> Result<(), OverlayError> {
+        self.command_tx
+            .send(OverlayCommand::SetStrategy(strategy))
+            .await?;
+        Ok(())
+    }
+
+    /// 把之后每一帧的耗时和损坏区域记录到 `log`, `None` 时停止记录
+    pub async fn set_frame_log(&self, log: Option<FrameLog>) -> Result<(), OverlayError> {
+        self.command_tx
+            .send(OverlayCommand::SetFrameLog(log))
+            .await?;
+        Ok(())
+    }
+
+    /// 修改需要 overlay 的显示器, 新接入的显示器也按它筛选
+    pub async fn set_selection(&self, selection: OutputSelection) -> Result<(), OverlayError> {
+        self.command_tx
+            .send(OverlayCommand::SetSelection(selection))
+            .await?;
+        Ok(())
+    }
+
+    /// 修改层级和被其他 layer-shell 客户端遮住时的处理, 见 [`crate::screen_overlay::stacking`]
+    pub async fn set_stacking(&self, config: StackingConfig) -> Result<(), OverlayError> {
+        self.command_tx
+            .send(OverlayCommand::SetStacking(config))
+            .await?;
+        Ok(())
+    }
+
+    /// 跟随配置中的 `[overlay]` 修改需要 overlay 的显示器、绘制方式和层叠,
+    /// 交给 [`crate::event_router::Router::add_stage`]
+    ///
+    /// 没有配置绘制方式时使用 `strategies` 中当前合成器测得最快的方式
+    pub fn selector(&self, strategies: StrategyCache) -> OverlaySelector {
+        OverlaySelector {
+            commands: self.command_tx.clone(),
+            strategies,
+            compositor: compositor_name(),
+        }
+    }
+
+    /// 用于请求重绘的句柄, 例如交给 [`crate::hud_interface::HudState::set_redraw`]
+    pub fn redraw_handle(&self) -> RedrawHandle {
+        RedrawHandle::new(self.command_tx.clone())
+    }
+
+    /// 等待后台任务结束, 比如处理命令时 panic
+    pub async fn closed(&self) {
+        self.command_tx.closed().await
+    }
+
+    /// 获取当前显示器
+    pub async fn current_display(&self) -> Option<SurfaceInfo> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(OverlayCommand::GetCurrentDisplay(tx))
+            .await
+            .ok()?;
+
+        rx.await.ok().flatten()
+    }
+}
+
+impl Default for WaylandOverlay {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Drop for WaylandOverlay {
@@ -398,24 +481,117 @@ impl Drop for WaylandOverlay {
     }
 }
 
+/// 按配置选择需要 overlay 的显示器和绘制方式, 见 [`WaylandOverlay::selector`]
+pub struct OverlaySelector {
+    commands: mpsc::Sender<OverlayCommand>,
+    strategies: StrategyCache,
+    compositor: String,
+}
+
+impl ConfigStage for OverlaySelector {
+    fn name(&self) -> &str {
+        "overlay-outputs"
+    }
+
+    fn apply(&mut self, config: &Config, change: &ConfigChange) -> anyhow::Result<()> {
+        // `lazy` 时映射变化也会影响需要 overlay 的显示器
+        if !change.overlay
+            && !(config.overlay.lazy && (change.defaults || !change.tablets.is_empty()))
+        {
+            return Ok(());
+        }
+        let busy = |_| anyhow::anyhow!("overlay 已停止或者正忙");
+        self.commands
+            .try_send(OverlayCommand::SetSelection(OutputSelection::from_config(
+                config,
+            )))
+            .map_err(busy)?;
+        if change.overlay {
+            let strategy = self
+                .strategies
+                .resolve(config.overlay.strategy, &self.compositor);
+            self.commands
+                .try_send(OverlayCommand::SetStrategy(strategy))
+                .map_err(busy)?;
+            self.commands
+                .try_send(OverlayCommand::SetStacking(config.overlay.stacking.clone()))
+                .map_err(busy)?;
+        }
+        Ok(())
+    }
+}
+
+/// `wl_display.sync` 的回调, 在 Wayland 线程中重新选择需要 overlay 的显示器
+pub(super) struct ReconcileSurfaces;
+
+impl Dispatch<wl_callback::WlCallback, ReconcileSurfaces> for WaylandEventState {
+    fn event(
+        state: &mut Self,
+        _: &wl_callback::WlCallback,
+        event: wl_callback::Event,
+        _: &ReconcileSurfaces,
+        _: &Connection,
+        qhandle: &QueueHandle<Self>,
+    ) {
+        if let wl_callback::Event::Done { .. } = event {
+            state.reconcile_surfaces(qhandle);
+        }
+    }
+}
+
+/// `wl_display.sync` 的回调, 在 Wayland 线程中重新创建这些 surface
+pub(super) struct RecreateSurfaces(pub(super) Vec<SurfaceId>);
+
+impl Dispatch<wl_callback::WlCallback, RecreateSurfaces> for WaylandEventState {
+    fn event(
+        state: &mut Self,
+        _: &wl_callback::WlCallback,
+        event: wl_callback::Event,
+        ids: &RecreateSurfaces,
+        _: &Connection,
+        qhandle: &QueueHandle<Self>,
+    ) {
+        if let wl_callback::Event::Done { .. } = event {
+            state.recreate_surfaces(&ids.0, qhandle);
+        }
+    }
+}
+
 // Wayland事件状态
 struct WaylandEventState {
     running: bool,
     compositor: Option<wl_compositor::WlCompositor>,
     shm: Option<wl_shm::WlShm>,
     layer_shell: Option<zwlr_layer_shell_v1::ZwlrLayerShellV1>,
+    fractional_scale_manager: Option<wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1>,
+    viewporter: Option<wp_viewporter::WpViewporter>,
+    /// 以 registry `name` 为键, 只在内部使用
     outputs: HashMap<u32, OutputInfo>,
-    surfaces: HashMap<u32, RawSurfaceInfo>,
+    surfaces: HashMap<SurfaceId, RawSurfaceInfo>,
+    generations: Generations,
+    /// `zwp_linux_dmabuf_v1` 第 4 版报告的格式
+    dmabuf_feedback: PendingFeedback,
     registry_done: bool,
+    geometry: GeometryBus,
+    /// 新建 surface 使用的设置
+    options: SurfaceOptions,
+    /// 公开API使用的状态
+    shared: Arc<Mutex<SurfaceState>>,
 }
 
 /// 显示器信息
 struct OutputInfo {
+    id: OutputId,
     output: wl_output::WlOutput,
+    /// 在混成器全局坐标系中的位置
+    x: i32,
+    y: i32,
     width: Option<i32>,
     height: Option<i32>,
     name: Option<String>,
     scale_factor: i32,
+    /// overlay 收到的分数缩放比例, 比 `scale_factor` 准确
+    fractional_scale: Option<f64>,
     has_valid_size: bool,
 }
 
@@ -433,56 +609,107 @@ impl Dispatch<wl_registry::WlRegistry, ()> for WaylandEventState {
                 name,
                 interface,
                 version,
-            } => match &interface[..] {
-                "wl_compositor" => {
-                    println!("找到wl_compositor");
-                    let compositor = registry.bind::<wl_compositor::WlCompositor, _, _>(
-                        name,
-                        version,
-                        qhandle,
-                        (),
-                    );
-                    state.compositor = Some(compositor);
-                }
-                "wl_shm" => {
-                    println!("找到wl_shm");
-                    let shm = registry.bind::<wl_shm::WlShm, _, _>(name, version, qhandle, ());
-                    state.shm = Some(shm);
-                }
-                "wl_output" => {
-                    println!("找到wl_output #{}", name);
-                    let output =
-                        registry.bind::<wl_output::WlOutput, _, _>(name, version, qhandle, ());
-                    state.outputs.insert(
-                        name,
-                        OutputInfo {
-                            output,
-                            width: None,
-                            height: None,
-                            name: None,
-                            scale_factor: 1,
-                            has_valid_size: false,
-                        },
-                    );
-                }
-                "zwlr_layer_shell_v1" => {
-                    println!("找到zwlr_layer_shell_v1");
-                    let layer_shell = registry.bind::<zwlr_layer_shell_v1::ZwlrLayerShellV1, _, _>(
-                        name,
-                        version,
-                        qhandle,
-                        (),
-                    );
-                    state.layer_shell = Some(layer_shell);
+            } => {
+                match &interface[..] {
+                    "wl_compositor" => {
+                        debug!("找到wl_compositor");
+                        let compositor = registry.bind::<wl_compositor::WlCompositor, _, _>(
+                            name,
+                            version,
+                            qhandle,
+                            (),
+                        );
+                        if let Ok(mut shared) = state.shared.lock() {
+                            shared.compositor = Some(compositor.clone());
+                        }
+                        state.compositor = Some(compositor);
+                    }
+                    "wl_subcompositor" => {
+                        debug!("找到wl_subcompositor");
+                        let subcompositor = registry
+                            .bind::<wl_subcompositor::WlSubcompositor, _, _>(name, 1, qhandle, ());
+                        if let Ok(mut shared) = state.shared.lock() {
+                            shared.subcompositor = Some(subcompositor);
+                        }
+                    }
+                    "wl_shm" => {
+                        debug!("找到wl_shm");
+                        let shm = registry.bind::<wl_shm::WlShm, _, _>(name, version, qhandle, ());
+                        if let Ok(mut shared) = state.shared.lock() {
+                            shared.shm = Some(shm.clone());
+                        }
+                        state.shm = Some(shm);
+                    }
+                    "wl_output" => {
+                        debug!("找到wl_output #{}", name);
+                        let output =
+                            registry.bind::<wl_output::WlOutput, _, _>(name, version, qhandle, ());
+                        state.outputs.insert(
+                            name,
+                            OutputInfo {
+                                id: state.generations.output(name),
+                                output,
+                                x: 0,
+                                y: 0,
+                                width: None,
+                                height: None,
+                                name: None,
+                                scale_factor: 1,
+                                fractional_scale: None,
+                                has_valid_size: false,
+                            },
+                        );
+                    }
+                    "zwlr_layer_shell_v1" => {
+                        debug!("找到zwlr_layer_shell_v1");
+                        let layer_shell = registry
+                            .bind::<zwlr_layer_shell_v1::ZwlrLayerShellV1, _, _>(
+                                name,
+                                version,
+                                qhandle,
+                                (),
+                            );
+                        state.layer_shell = Some(layer_shell);
+                    }
+                    "wp_fractional_scale_manager_v1" => {
+                        debug!("找到wp_fractional_scale_manager_v1");
+                        state.fractional_scale_manager = Some(registry.bind(name, 1, qhandle, ()));
+                    }
+                    "wp_viewporter" => {
+                        debug!("找到wp_viewporter");
+                        let viewporter: wp_viewporter::WpViewporter =
+                            registry.bind(name, 1, qhandle, ());
+                        if let Ok(mut shared) = state.shared.lock() {
+                            shared.viewporter = Some(viewporter.clone());
+                        }
+                        state.viewporter = Some(viewporter);
+                    }
+                    "zwp_linux_dmabuf_v1" if version >= 3 => {
+                        debug!("找到zwp_linux_dmabuf_v1");
+                        let dmabuf = registry.bind::<zwp_linux_dmabuf_v1::ZwpLinuxDmabufV1, _, _>(
+                            name,
+                            version.min(4),
+                            qhandle,
+                            (),
+                        );
+                        // 第 4 版不再发送 modifier 事件，改用 feedback 对象
+                        if version >= 4 {
+                            dmabuf.get_default_feedback(qhandle, ());
+                        }
+                        if let Ok(mut shared) = state.shared.lock() {
+                            shared.dmabuf = Some(DmabufContext::new(dmabuf, qhandle.clone()));
+                        }
+                    }
+                    _ => {}
                 }
-                _ => {}
-            },
+            }
             wl_registry::Event::GlobalRemove { name } => {
-                if state.outputs.remove(&name).is_some() {
-                    println!("显示器 #{} 已移除", name);
-                }
-                if state.surfaces.remove(&name).is_some() {
-                    println!("Surface #{} 已移除", name);
+                if let Some(info) = state.outputs.remove(&name) {
+                    info!("显示器 {} 已移除", info.id);
+                    state.destroy_surface(Generations::surface(info.id));
+                    state.publish_geometry();
+                    // 映射可能改到了备用显示器上, 它也许还没有overlay
+                    state.reconcile_surfaces(qhandle);
                 }
             }
             _ => {}
@@ -502,7 +729,7 @@ impl Dispatch<wl_output::WlOutput, ()> for WaylandEventState {
         event: wl_output::Event,
         _: &(),
         _: &Connection,
-        _: &QueueHandle<Self>,
+        qhandle: &QueueHandle<Self>,
     ) {
         // 找到对应的输出设备
         let mut output_id = None;
@@ -513,28 +740,37 @@ impl Dispatch<wl_output::WlOutput, ()> for WaylandEventState {
             }
         }
 
-        if let Some(id) = output_id {
-            if let Some(info) = state.outputs.get_mut(&id) {
-                match event {
-                    wl_output::Event::Mode { width, height, .. } => {
-                        println!("显示器分辨率: {}x{}", width, height);
-                        info.width = Some(width);
-                        info.height = Some(height);
-                        if width > 0 && height > 0 {
-                            info.has_valid_size = true;
-                            println!("显示器 #{} 已获取到有效尺寸: {}x{}", id, width, height);
-                        }
-                    }
-                    wl_output::Event::Scale { factor } => {
-                        println!("显示器缩放因子: {}", factor);
-                        info.scale_factor = factor;
-                    }
-                    wl_output::Event::Name { name } => {
-                        println!("显示器名称: {}", name);
-                        info.name = Some(name);
+        if let Some(id) = output_id
+            && let Some(info) = state.outputs.get_mut(&id)
+        {
+            match event {
+                wl_output::Event::Geometry { x, y, .. } => {
+                    info.x = x;
+                    info.y = y;
+                }
+                wl_output::Event::Mode { width, height, .. } => {
+                    debug!("显示器分辨率: {}x{}", width, height);
+                    info.width = Some(width);
+                    info.height = Some(height);
+                    if width > 0 && height > 0 {
+                        info.has_valid_size = true;
+                        debug!("显示器 #{} 已获取到有效尺寸: {}x{}", id, width, height);
                     }
-                    _ => {}
                 }
+                wl_output::Event::Scale { factor } => {
+                    debug!("显示器缩放因子: {}", factor);
+                    info.scale_factor = factor;
+                }
+                wl_output::Event::Name { name } => {
+                    debug!("显示器名称: {}", name);
+                    info.name = Some(name);
+                }
+                // 一组属性发送完毕, 新接入的显示器也在这时创建overlay
+                wl_output::Event::Done => {
+                    state.publish_geometry();
+                    state.reconcile_surfaces(qhandle);
+                }
+                _ => {}
             }
         }
     }
@@ -547,7 +783,7 @@ impl Dispatch<zwlr_layer_surface_v1::ZwlrLayerSurfaceV1, ()> for WaylandEventSta
         event: zwlr_layer_surface_v1::Event,
         _: &(),
         _: &Connection,
-        qhandle: &QueueHandle<Self>,
+        _: &QueueHandle<Self>,
     ) {
         match event {
             zwlr_layer_surface_v1::Event::Configure {
@@ -555,7 +791,7 @@ impl Dispatch<zwlr_layer_surface_v1::ZwlrLayerSurfaceV1, ()> for WaylandEventSta
                 width,
                 height,
             } => {
-                println!(
+                info!(
                     "Layer surface配置: {}x{} (serial: {})",
                     width, height, serial
                 );
@@ -564,49 +800,25 @@ impl Dispatch<zwlr_layer_surface_v1::ZwlrLayerSurfaceV1, ()> for WaylandEventSta
                 layer_surface.ack_configure(serial);
 
                 // 查找对应的surface
-                for surf_info in state.surfaces.values_mut() {
-                    if &surf_info.layer_surface == layer_surface {
-                        // 创建缓冲区
-                        if width > 0 && height > 0 && state.shm.is_some() {
-                            println!("创建{}x{}的缓冲区", width, height);
-                            // 创建并绘制缓冲区
-                            if let Ok(mut file) = tempfile::tempfile() {
-                                draw(&mut file, (width as u32, height as u32));
-
-                                let pool = state.shm.as_ref().unwrap().create_pool(
-                                    file.as_fd(),
-                                    (width * height * 4) as i32,
-                                    qhandle,
-                                    (),
-                                );
-
-                                let buffer = pool.create_buffer(
-                                    0,
-                                    width as i32,
-                                    height as i32,
-                                    (width * 4) as i32,
-                                    wl_shm::Format::Argb8888,
-                                    qhandle,
-                                    (),
-                                );
-
-                                println!("附加缓冲区到surface");
-                                // 附加缓冲区并提交
-                                surf_info.surface.attach(Some(&buffer), 0, 0);
-                                surf_info.surface.damage(0, 0, width as i32, height as i32);
-                                surf_info.buffer = Some(buffer);
-                            }
-                        }
-
-                        println!("提交surface");
-                        // 提交surface应用更改
-                        surf_info.surface.commit();
-                        break;
-                    }
+                let Some(surf_info) = state
+                    .surfaces
+                    .values()
+                    .find(|surf_info| &surf_info.layer_surface == layer_surface)
+                else {
+                    return;
+                };
+                if width > 0
+                    && height > 0
+                    && let Ok(mut shared) = state.shared.lock()
+                {
+                    // 绘制第一帧, 之后由帧回调驱动
+                    shared.configure(surf_info.id, (width, height));
+                } else {
+                    surf_info.surface.commit();
                 }
             }
             zwlr_layer_surface_v1::Event::Closed => {
-                println!("Layer surface closed");
+                debug!("Layer surface closed");
 
                 // 查找并移除对应的surface
                 let mut id_to_remove = None;
@@ -619,12 +831,12 @@ impl Dispatch<zwlr_layer_surface_v1::ZwlrLayerSurfaceV1, ()> for WaylandEventSta
 
                 if let Some(id) = id_to_remove {
                     state.surfaces.remove(&id);
-                    println!("移除surface #{}", id);
+                    debug!("移除{}", id);
                 }
 
                 // 如果所有surface都关闭了，退出
                 if state.surfaces.is_empty() {
-                    println!("所有surface已关闭，退出事件循环");
+                    info!("所有surface已关闭，退出事件循环");
                     state.running = false;
                 }
             }
@@ -644,7 +856,7 @@ delegate_noop!(WaylandEventState: ignore zwlr_layer_shell_v1::ZwlrLayerShellV1);
 
 /// 测试Wayland overlay的实现
 /// 创建一个简单的彩色矩形，显示在屏幕左上角
-pub async fn test_overlay() -> Result<(), Box<dyn std::error::Error>> {
+pub async fn test_overlay() -> Result<(), OverlayError> {
     let overlay = WaylandOverlay::new();
 
     // 等待一段时间，让overlay有时间设置
@@ -664,36 +876,193 @@ pub async fn test_overlay() -> Result<(), Box<dyn std::error::Error>> {
     loop {
         let display = overlay.next_display().await?;
         let display_info = display.get_info().await?;
-        println!("new display {display_info:?}");
+        debug!("new display {display_info:?}");
     }
 }
 
-/// 在临时文件中绘制示例图像
-fn draw(tmp: &mut File, (buf_x, buf_y): (u32, u32)) {
-    use std::{cmp::min, io::Write};
-    let mut buf = std::io::BufWriter::new(tmp);
-    println!("绘制{}x{}的缓冲区", buf_x, buf_y);
-    for y in 0..buf_y {
-        for x in 0..buf_x {
-            // 设置半透明背景
-            let a = 0x80; // 50%透明度
-            let r = 0x00;
-            let g = 0x80;
-            let b = 0xFF;
-            buf.write_all(&[b as u8, g as u8, r as u8, a as u8])
-                .unwrap();
-        }
+impl OutputInfo {
+    /// 对外使用的名称, 混成器没有给出名称时用 registry `name` 代替
+    fn display_name(&self, name: u32) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| format!("wl_output-{name}"))
     }
-    buf.flush().unwrap();
-    println!("缓冲区绘制完成");
 }
 
 impl WaylandEventState {
+    /// 所有有效显示器的布局
+    fn output_geometry(&self) -> Vec<OutputGeometry> {
+        let mut outputs: Vec<_> = self
+            .outputs
+            .iter()
+            .filter(|(_, info)| info.has_valid_size)
+            .map(|(name, info)| OutputGeometry {
+                id: Some(info.id),
+                name: info.display_name(*name),
+                x: info.x as f64,
+                y: info.y as f64,
+                width: info.width.unwrap_or(0) as u32,
+                height: info.height.unwrap_or(0) as u32,
+                scale: info.fractional_scale.unwrap_or(info.scale_factor as f64),
+            })
+            .collect();
+        outputs.sort_by(|a, b| a.name.cmp(&b.name));
+        outputs
+    }
+
+    /// 把所有有效显示器的布局发布出去
+    fn publish_geometry(&self) {
+        self.geometry.publish(self.output_geometry());
+    }
+
+    /// 按 [`OutputSelection`] 为需要的显示器创建 overlay, 移除不再需要的
+    ///
+    /// 重新启用的显示器沿用原来的 surface 标识, 它仍然对应同一个显示器
+    fn reconcile_surfaces(&mut self, qhandle: &QueueHandle<Self>) {
+        let selected = match self.shared.lock() {
+            Ok(shared) => shared.selection.select(&self.output_geometry()),
+            Err(_) => return,
+        };
+        let mut create = Vec::new();
+        let mut destroy = Vec::new();
+        for (name, info) in &self.outputs {
+            let id = Generations::surface(info.id);
+            let wanted = info.has_valid_size && selected.contains(&info.display_name(*name));
+            match (wanted, self.surfaces.contains_key(&id)) {
+                (true, false) => create.push(*name),
+                (false, true) => destroy.push(id),
+                _ => {}
+            }
+        }
+        for id in destroy {
+            info!("{} 不再需要overlay", id);
+            self.destroy_surface(id);
+        }
+        for name in create {
+            self.create_surface(name, qhandle);
+        }
+    }
+
+    /// 销毁并重新创建 surface, 使用 [`SurfaceState::layer`] 的层级
+    fn recreate_surfaces(&mut self, ids: &[SurfaceId], qhandle: &QueueHandle<Self>) {
+        let names: Vec<_> = self
+            .outputs
+            .iter()
+            .map(|(name, info)| (*name, Generations::surface(info.id)))
+            .filter(|(_, id)| ids.contains(id) && self.surfaces.contains_key(id))
+            .collect();
+        for (name, id) in names {
+            self.destroy_surface(id);
+            self.create_surface(name, qhandle);
+        }
+    }
+
+    /// 为显示器创建 overlay
+    fn create_surface(&mut self, name: u32, qhandle: &QueueHandle<Self>) {
+        let (Some(output_info), Some(compositor), Some(layer_shell)) = (
+            self.outputs.get(&name),
+            self.compositor.as_ref(),
+            self.layer_shell.as_ref(),
+        ) else {
+            return;
+        };
+        let id = Generations::surface(output_info.id);
+        info!("为显示器 {} 创建overlay", output_info.id);
+
+        // 创建基础surface
+        let surface = compositor.create_surface(qhandle, ());
+
+        // 创建输入区域, 空区域使overlay不捕获输入
+        let input_region = compositor.create_region(qhandle, ());
+        match self.options.input {
+            InputPolicy::PassThrough => surface.set_input_region(Some(&input_region)),
+            InputPolicy::Capture => surface.set_input_region(None),
+        }
+
+        // 支持分数缩放时按合成器给出的比例分配buffer, 再用viewport缩放回逻辑尺寸
+        let (viewport, fractional_scale) = match (
+            self.viewporter.as_ref(),
+            self.fractional_scale_manager.as_ref(),
+        ) {
+            (Some(viewporter), Some(manager)) => (
+                Some(viewporter.get_viewport(&surface, qhandle, ())),
+                Some(manager.get_fractional_scale(&surface, qhandle, id)),
+            ),
+            _ => (None, None),
+        };
+
+        // 创建layer_surface, 配置可以修改层级
+        let layer = match self.shared.lock() {
+            Ok(shared) => shared.layer,
+            Err(_) => self.options.layer,
+        };
+        let layer_surface = layer_shell.get_layer_surface(
+            &surface,
+            Some(&output_info.output),
+            layer.wayland(),
+            self.options.namespace.clone(),
+            qhandle,
+            (),
+        );
+
+        // 没有设置尺寸时使用显示器实际尺寸
+        let (width, height) = match self.options.size {
+            Some((width, height)) => (width as i32, height as i32),
+            None => (
+                output_info.width.unwrap_or(0),
+                output_info.height.unwrap_or(0),
+            ),
+        };
+
+        // 配置layer_surface
+        layer_surface.set_size(width as u32, height as u32);
+        layer_surface.set_anchor(self.options.anchor.wayland());
+        layer_surface.set_exclusive_zone(-1);
+        layer_surface.set_margin(0, 0, 0, 0);
+        layer_surface
+            .set_keyboard_interactivity(zwlr_layer_surface_v1::KeyboardInteractivity::None);
+
+        // 初始化提交surface
+        surface.commit();
+
+        let raw = RawSurfaceInfo {
+            id,
+            surface,
+            layer_surface,
+            input_region,
+            viewport,
+            fractional_scale,
+        };
+        let info = SurfaceInfo {
+            id,
+            output: output_info.id,
+            width,
+            height,
+            name: output_info.name.clone(),
+            scale_factor: output_info.scale_factor,
+        };
+        if let Ok(mut state) = self.shared.lock() {
+            state.add_surface(id, info, raw.clone());
+        }
+        self.surfaces.insert(id, raw);
+    }
+
+    /// 销毁显示器的 overlay
+    fn destroy_surface(&mut self, id: SurfaceId) {
+        if let Some(raw) = self.surfaces.remove(&id) {
+            raw.destroy();
+            info!("{} 已移除", id);
+        }
+        if let Ok(mut shared) = self.shared.lock() {
+            shared.remove_surface(id);
+        }
+    }
+
     /// 检查是否所有显示器都已获取到有效尺寸
     fn all_outputs_have_size(&self) -> bool {
         // 如果没有显示器，返回false
         if self.outputs.is_empty() {
-            println!("没有检测到显示器");
+            info!("没有检测到显示器");
             return false;
         }
 
@@ -708,19 +1077,18 @@ impl WaylandEventState {
 
         // 如果至少有一个显示器有有效尺寸，就可以继续
         if !has_any_valid {
-            println!("等待至少一个显示器获取有效尺寸...");
+            debug!("等待至少一个显示器获取有效尺寸...");
             return false;
         }
 
-        println!("至少一个显示器已准备好");
-        return true;
+        debug!("至少一个显示器已准备好");
+        true
     }
 }
 
 /* 将来需要实现的功能:
  * 1. 支持多显示器 - 为每个显示器创建独立的overlay
  * 2. 动态光标 - 根据笔的状态(悬空、压力、倾斜等)做出变化
- * 3. 支持不同的缩放比例
- * 4. 与hud_interface模块集成，提供界面渲染接口
- * 5. 支持多数位板，每个光标可以使用不同颜色标注
+ * 3. 与hud_interface模块集成，提供界面渲染接口
+ * 4. 支持多数位板，每个光标可以使用不同颜色标注
  */
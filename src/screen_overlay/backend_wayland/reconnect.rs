@@ -0,0 +1,158 @@
+//! 合成器重启后重新建立 overlay
+//!
+//! 合成器崩溃、重启或者连接出错时, 事件循环从 `blocking_dispatch` 收到错误. 这时丢弃所有
+//! surface 和绑定的全局对象, 等 Wayland socket 重新可用后连接, 按原来的选择、绘制方式和绘制函数
+//! 重新创建 overlay. 新 surface 收到 configure 后由绘制函数重绘, HUD 等内容随之恢复
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+use wayland_client::{Connection, DispatchError};
+
+use crate::{
+    mapping::geometry::GeometryBus,
+    screen_overlay::{builder::SurfaceOptions, id::Generations},
+};
+
+use super::{
+    WaylandEventState, discovery::DisplayChooser, dmabuf::PendingFeedback,
+    surface_state::SurfaceState,
+};
+
+/// 第一次重新连接前等待的时间, 之后每次失败加倍
+const RECONNECT_MIN: Duration = Duration::from_millis(500);
+const RECONNECT_MAX: Duration = Duration::from_secs(10);
+/// 连接保持了这么久才断开时, 重新从 [`RECONNECT_MIN`] 开始等待
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+/// 连接 `display` 选择的合成器并处理事件, 连接断开后重新连接, 直到 `alive` 关闭
+///
+/// 启动时无法连接说明不在 Wayland 会话中, 直接返回
+pub(super) fn run(
+    shared: Arc<Mutex<SurfaceState>>,
+    geometry: GeometryBus,
+    options: SurfaceOptions,
+    display: DisplayChooser,
+    alive: mpsc::Receiver<()>,
+) {
+    // 重新连接后显示器的标识也要和之前的不同, 所以代数在多次连接间保留
+    let mut generations = Generations::new();
+    let mut delay = RECONNECT_MIN;
+    let mut connected = false;
+    loop {
+        match display.connect(&alive) {
+            Ok(conn) => {
+                if connected {
+                    info!("已重新连接Wayland合成器");
+                }
+                connected = true;
+                let started = Instant::now();
+                let result = dispatch(
+                    conn,
+                    &shared,
+                    &mut generations,
+                    geometry.clone(),
+                    options.clone(),
+                );
+                let Err(e) = result else {
+                    return;
+                };
+                warn!("与Wayland合成器的连接断开: {e}, 销毁所有overlay后等待重新连接");
+                if let Ok(mut shared) = shared.lock() {
+                    shared.disconnect();
+                }
+                if started.elapsed() >= STABLE_AFTER {
+                    delay = RECONNECT_MIN;
+                }
+            }
+            Err(e) if !connected => {
+                warn!("无法连接Wayland合成器: {e:#}");
+                return;
+            }
+            // socket 还没有重新出现
+            Err(_) => {}
+        }
+        if alive.is_closed() {
+            return;
+        }
+        std::thread::sleep(delay);
+        delay = (delay * 2).min(RECONNECT_MAX);
+    }
+}
+
+/// 在一个连接上创建 overlay 并处理事件, 所有 surface 都关闭时返回 `Ok`
+fn dispatch(
+    conn: Connection,
+    shared: &Arc<Mutex<SurfaceState>>,
+    generations: &mut Generations,
+    geometry: GeometryBus,
+    options: SurfaceOptions,
+) -> Result<(), DispatchError> {
+    if let Ok(mut state) = shared.lock() {
+        state.connection = Some(conn.clone());
+    }
+
+    let mut event_queue = conn.new_event_queue();
+    let qhandle = event_queue.handle();
+    if let Ok(mut state) = shared.lock() {
+        state.qhandle = Some(qhandle.clone());
+    }
+
+    // 获取显示
+    let display = conn.display();
+    display.get_registry(&qhandle, ());
+
+    // 创建state
+    let mut wayland_state = WaylandEventState {
+        running: true,
+        compositor: None,
+        shm: None,
+        layer_shell: None,
+        fractional_scale_manager: None,
+        viewporter: None,
+        outputs: Default::default(),
+        surfaces: Default::default(),
+        generations: std::mem::take(generations),
+        dmabuf_feedback: PendingFeedback::default(),
+        registry_done: false,
+        geometry,
+        options,
+        shared: Arc::clone(shared),
+    };
+    let result = run_queue(&mut event_queue, &mut wayland_state);
+    *generations = std::mem::take(&mut wayland_state.generations);
+    result
+}
+
+fn run_queue(
+    event_queue: &mut wayland_client::EventQueue<WaylandEventState>,
+    wayland_state: &mut WaylandEventState,
+) -> Result<(), DispatchError> {
+    let qhandle = event_queue.handle();
+
+    // 第一步：获取所有接口和显示器
+    debug!("获取Wayland接口和显示器信息...");
+    while !wayland_state.registry_done
+        || wayland_state.outputs.is_empty()
+        || !wayland_state.all_outputs_have_size()
+    {
+        event_queue.blocking_dispatch(wayland_state)?;
+    }
+
+    // 第二步：为需要overlay的显示器创建surface
+    wayland_state.reconcile_surfaces(&qhandle);
+    if wayland_state.surfaces.is_empty() {
+        info!("没有需要overlay的显示器，等待配置变化");
+    }
+
+    // 进入主事件循环
+    debug!("进入事件循环...等待configure事件");
+    while wayland_state.running {
+        event_queue.blocking_dispatch(wayland_state)?;
+    }
+    Ok(())
+}
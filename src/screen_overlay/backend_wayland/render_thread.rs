@@ -0,0 +1,147 @@
+//! 每个显示器的绘制线程
+//!
+//! 软件绘制在显示器自己的线程中进行, 不持有 overlay 的内部状态锁. 画好后线程短暂地拿锁,
+//! 把画布交给 surface 提交. 这样 30Hz 电视上画得慢的一帧不会推迟 144Hz 显示器上的光标,
+//! 每个显示器仍然按自己的帧回调控制绘制的频率
+
+use std::{
+    sync::{Mutex, Weak, mpsc},
+    thread,
+    time::Instant,
+};
+
+use crate::screen_overlay::{canvas::Canvas, id::SurfaceId};
+
+use super::{
+    frame::{CURSOR_SIZE, CursorRenderer, Renderer},
+    surface_info::SurfaceInfo,
+    surface_state::SurfaceState,
+};
+
+/// 交给绘制线程的工作
+pub(super) enum RenderJob {
+    Frame(FrameJob),
+    Cursor(CursorJob),
+}
+
+/// 绘制整个 surface
+pub(super) struct FrameJob {
+    pub(super) info: SurfaceInfo,
+    /// 缓冲区的像素尺寸
+    pub(super) size: (u32, u32),
+    pub(super) scale: f64,
+    pub(super) renderer: Option<Renderer>,
+    /// 光标不在单独的 subsurface 上时叠加到内容上
+    pub(super) cursor: Option<CursorRenderer>,
+    pub(super) start: Instant,
+}
+
+/// 画好的一帧
+pub(super) struct RenderedFrame {
+    pub(super) canvas: Canvas,
+    pub(super) animating: bool,
+    pub(super) start: Instant,
+}
+
+impl FrameJob {
+    pub(super) fn render(self) -> RenderedFrame {
+        let mut canvas = Canvas::new(self.size.0, self.size.1);
+        canvas.set_scale(self.scale);
+        let animating = match &self.renderer {
+            Some(renderer) => renderer(&self.info, &mut canvas),
+            None => false,
+        };
+        if let Some(renderer) = &self.cursor
+            && let Some((cursor, (x, y))) = render_cursor(renderer, &self.info, self.scale)
+        {
+            canvas.composite(x, y, &cursor);
+        }
+        RenderedFrame {
+            canvas,
+            animating,
+            start: self.start,
+        }
+    }
+}
+
+/// 在光标 subsurface 上绘制光标
+pub(super) struct CursorJob {
+    pub(super) info: SurfaceInfo,
+    pub(super) scale: f64,
+    pub(super) renderer: Option<CursorRenderer>,
+    pub(super) start: Instant,
+}
+
+/// 画好的光标, 这个 surface 上没有光标时 `cursor` 为 `None`
+pub(super) struct RenderedCursor {
+    pub(super) cursor: Option<(Canvas, (i32, i32))>,
+    pub(super) start: Instant,
+}
+
+impl CursorJob {
+    pub(super) fn render(self) -> RenderedCursor {
+        RenderedCursor {
+            cursor: self
+                .renderer
+                .as_ref()
+                .and_then(|renderer| render_cursor(renderer, &self.info, self.scale)),
+            start: self.start,
+        }
+    }
+}
+
+/// 在光标画布上绘制 surface 上的光标, 返回画布和它左上角的像素位置
+fn render_cursor(
+    renderer: &CursorRenderer,
+    info: &SurfaceInfo,
+    scale: f64,
+) -> Option<(Canvas, (i32, i32))> {
+    let size = (CURSOR_SIZE as f64 * scale).round() as u32;
+    let mut canvas = Canvas::new(size, size);
+    canvas.set_scale(scale);
+    let origin = renderer(info, &mut canvas)?;
+    Some((canvas, origin))
+}
+
+/// 一个显示器的绘制线程, 释放后线程画完手上的工作就退出
+pub(super) struct RenderThread {
+    jobs: mpsc::Sender<RenderJob>,
+}
+
+impl RenderThread {
+    /// 画好的内容交给 `state` 提交, `state` 已经释放时线程退出
+    pub(super) fn spawn(id: SurfaceId, state: Weak<Mutex<SurfaceState>>) -> std::io::Result<Self> {
+        let (jobs, rx) = mpsc::channel();
+        thread::Builder::new()
+            .name(format!("overlay-{id}"))
+            .spawn(move || {
+                for job in rx {
+                    let Some(state) = state.upgrade() else {
+                        break;
+                    };
+                    match job {
+                        RenderJob::Frame(job) => {
+                            let frame = job.render();
+                            let Ok(mut state) = state.lock() else {
+                                break;
+                            };
+                            state.present_frame(id, frame);
+                        }
+                        RenderJob::Cursor(job) => {
+                            let cursor = job.render();
+                            let Ok(mut state) = state.lock() else {
+                                break;
+                            };
+                            state.present_cursor(id, cursor);
+                        }
+                    }
+                }
+            })?;
+        Ok(Self { jobs })
+    }
+
+    /// 线程已经退出时退回 `job`
+    pub(super) fn send(&self, job: RenderJob) -> Result<(), RenderJob> {
+        self.jobs.send(job).map_err(|e| e.0)
+    }
+}
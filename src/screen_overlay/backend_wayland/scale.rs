@@ -0,0 +1,48 @@
+use tracing::debug;
+use wayland_client::{Connection, Dispatch, QueueHandle, delegate_noop};
+use wayland_protocols::wp::{
+    fractional_scale::v1::client::{wp_fractional_scale_manager_v1, wp_fractional_scale_v1},
+    viewporter::client::{wp_viewport, wp_viewporter},
+};
+
+use crate::screen_overlay::id::{Generations, SurfaceId};
+
+use super::WaylandEventState;
+
+/// `preferred_scale` 的分母, 协议规定缩放比例以 1/120 为单位
+const SCALE_DENOMINATOR: f64 = 120.0;
+
+impl Dispatch<wp_fractional_scale_v1::WpFractionalScaleV1, SurfaceId> for WaylandEventState {
+    fn event(
+        state: &mut Self,
+        _: &wp_fractional_scale_v1::WpFractionalScaleV1,
+        event: wp_fractional_scale_v1::Event,
+        id: &SurfaceId,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let wp_fractional_scale_v1::Event::PreferredScale { scale } = event else {
+            return;
+        };
+        let scale = scale as f64 / SCALE_DENOMINATOR;
+        debug!("{id} 的缩放比例: {scale}");
+
+        // overlay 铺满整个显示器, 它的缩放比例就是显示器真正的缩放比例
+        if let Some(info) = state
+            .outputs
+            .values_mut()
+            .find(|info| Generations::surface(info.id) == *id)
+        {
+            info.fractional_scale = Some(scale);
+        }
+        state.publish_geometry();
+
+        if let Ok(mut shared) = state.shared.lock() {
+            shared.set_scale(*id, scale);
+        }
+    }
+}
+
+delegate_noop!(WaylandEventState: ignore wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1);
+delegate_noop!(WaylandEventState: ignore wp_viewporter::WpViewporter);
+delegate_noop!(WaylandEventState: ignore wp_viewport::WpViewport);
@@ -1,10 +1,17 @@
-use wayland_client::protocol::{wl_buffer, wl_region, wl_surface};
+use wayland_client::protocol::{wl_region, wl_surface};
+use wayland_protocols::wp::{
+    fractional_scale::v1::client::wp_fractional_scale_v1, viewporter::client::wp_viewport,
+};
 use wayland_protocols_wlr::layer_shell::v1::client::zwlr_layer_surface_v1;
 
+use crate::screen_overlay::id::{OutputId, SurfaceId};
+
 /// 存储WaylandOverlay需要的表面信息
 #[derive(Clone)]
 pub struct SurfaceInfo {
-    pub id: u32,
+    pub id: SurfaceId,
+    /// surface 所在的显示器
+    pub output: OutputId,
     pub width: i32,
     pub height: i32,
     pub name: Option<String>,
@@ -13,10 +20,28 @@ pub struct SurfaceInfo {
 
 /// Surface内部信息，包含Wayland对象
 #[derive(Clone)]
+#[allow(dead_code)]
 pub struct RawSurfaceInfo {
-    id: u32,
-    surface: wl_surface::WlSurface,
-    layer_surface: zwlr_layer_surface_v1::ZwlrLayerSurfaceV1,
-    input_region: wl_region::WlRegion,
-    buffer: Option<wl_buffer::WlBuffer>,
+    pub(crate) id: SurfaceId,
+    pub(crate) surface: wl_surface::WlSurface,
+    pub(crate) layer_surface: zwlr_layer_surface_v1::ZwlrLayerSurfaceV1,
+    pub(crate) input_region: wl_region::WlRegion,
+    /// 合成器支持分数缩放时才有, 两个对象总是一起创建
+    pub(crate) viewport: Option<wp_viewport::WpViewport>,
+    pub(crate) fractional_scale: Option<wp_fractional_scale_v1::WpFractionalScaleV1>,
+}
+
+impl RawSurfaceInfo {
+    /// 销毁 surface 和附属的对象
+    pub(crate) fn destroy(&self) {
+        if let Some(viewport) = &self.viewport {
+            viewport.destroy();
+        }
+        if let Some(fractional_scale) = &self.fractional_scale {
+            fractional_scale.destroy();
+        }
+        self.layer_surface.destroy();
+        self.input_region.destroy();
+        self.surface.destroy();
+    }
 }
@@ -1,18 +1,75 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::{Mutex, Weak},
+    time::Instant,
+};
 
-use super::surface_info::{RawSurfaceInfo, SurfaceInfo};
+use anyhow::Context;
+use tracing::{info, warn};
+use wayland_client::{
+    Connection, QueueHandle,
+    protocol::{wl_compositor, wl_shm, wl_subcompositor},
+};
+use wayland_protocols::wp::viewporter::client::wp_viewporter;
 
-pub struct SurfaceItem {
-    
-}
+use crate::screen_overlay::{
+    builder::OverlayLayer,
+    canvas::Canvas,
+    id::SurfaceId,
+    selection::OutputSelection,
+    stacking::{StackingConfig, StackingMonitor},
+    strategy::OverlayStrategy,
+};
+
+use super::{
+    ReconcileSurfaces, RecreateSurfaces, WaylandEventState,
+    cursor_surface::CursorSurface,
+    dmabuf::{DmaBuffer, DmaSurface, DmabufContext},
+    frame::{CursorRenderer, FrameLog, FrameSample, FrameState, Renderer},
+    render_thread::{CursorJob, FrameJob, RenderJob, RenderThread, RenderedCursor, RenderedFrame},
+    surface_info::{RawSurfaceInfo, SurfaceInfo},
+};
 
 /// 内部状态对象，用于在异步任务内维护
 pub struct SurfaceState {
-    surfaces: HashMap<u32, SurfaceInfo>,
-    current_surface_id: Option<u32>,
-    raw_surfaces: HashMap<u32, RawSurfaceInfo>,
-    available_surfaces: Vec<u32>,     // 可用的显示器ID列表
-    used_surfaces: HashMap<u32, u32>, // 显示器ID到引用计数的映射
+    pub(crate) surfaces: HashMap<SurfaceId, SurfaceInfo>,
+    pub(crate) current_surface_id: Option<SurfaceId>,
+    pub(crate) raw_surfaces: HashMap<SurfaceId, RawSurfaceInfo>,
+    pub(crate) available_surfaces: Vec<SurfaceId>, // 可用的显示器ID列表
+    pub(crate) used_surfaces: HashMap<SurfaceId, u32>, // 显示器ID到引用计数的映射
+    pub(crate) connection: Option<Connection>,
+    /// 合成器支持 `zwp_linux_dmabuf_v1` 时才有
+    pub(crate) dmabuf: Option<DmabufContext>,
+    /// 已经导出的 DMA-BUF 缓冲区
+    pub(crate) dma_surfaces: HashMap<SurfaceId, DmaSurface>,
+    pub(crate) shm: Option<wl_shm::WlShm>,
+    pub(crate) qhandle: Option<QueueHandle<WaylandEventState>>,
+    /// 绘制 overlay 内容, 没有设置时 overlay 是透明的
+    pub(crate) renderer: Option<Renderer>,
+    pub(crate) frames: HashMap<SurfaceId, FrameState>,
+    /// 需要 overlay 的显示器, 由 Wayland 线程读取
+    pub(crate) selection: OutputSelection,
+    pub(crate) compositor: Option<wl_compositor::WlCompositor>,
+    /// 合成器支持 subsurface 时才能使用 [`OverlayStrategy::CursorSurface`]
+    pub(crate) subcompositor: Option<wl_subcompositor::WlSubcompositor>,
+    pub(crate) viewporter: Option<wp_viewporter::WpViewporter>,
+    /// 配置的绘制方式, 实际使用的见 [`Self::strategy`]
+    pub(crate) requested_strategy: OverlayStrategy,
+    /// 上传 DMA-BUF 失败过, 之后改用 shm
+    pub(crate) dmabuf_failed: bool,
+    /// [`OverlayStrategy::Dmabuf`] 时软件绘制的内容上传到这些缓冲区
+    pub(crate) uploads: HashMap<SurfaceId, DmaSurface>,
+    pub(crate) cursor_renderer: Option<CursorRenderer>,
+    /// [`OverlayStrategy::CursorSurface`] 时每个 surface 上的光标
+    pub(crate) cursor_surfaces: HashMap<SurfaceId, CursorSurface>,
+    pub(crate) frame_log: Option<FrameLog>,
+    /// 新建 surface 使用的层级, 由 Wayland 线程读取
+    pub(crate) layer: OverlayLayer,
+    pub(crate) stacking: StackingMonitor,
+    /// 指向自己, 绘制线程画好后通过它提交
+    pub(crate) this: Weak<Mutex<SurfaceState>>,
+    /// 每个 surface 的绘制线程, 第一次绘制时创建
+    render_threads: HashMap<SurfaceId, RenderThread>,
 }
 
 impl SurfaceState {
@@ -24,11 +81,37 @@ impl SurfaceState {
             raw_surfaces: HashMap::new(),
             available_surfaces: Vec::new(),
             used_surfaces: HashMap::new(),
+            connection: None,
+            dmabuf: None,
+            dma_surfaces: HashMap::new(),
+            shm: None,
+            qhandle: None,
+            renderer: None,
+            frames: HashMap::new(),
+            selection: OutputSelection::all(),
+            compositor: None,
+            subcompositor: None,
+            viewporter: None,
+            requested_strategy: OverlayStrategy::default(),
+            dmabuf_failed: false,
+            uploads: HashMap::new(),
+            cursor_renderer: None,
+            cursor_surfaces: HashMap::new(),
+            frame_log: None,
+            layer: OverlayLayer::default(),
+            stacking: StackingMonitor::default(),
+            this: Weak::new(),
+            render_threads: HashMap::new(),
         }
     }
 
     /// 添加新的surface
-    pub fn add_surface(&mut self, id: u32, surface_info: SurfaceInfo, raw_info: RawSurfaceInfo) {
+    pub fn add_surface(
+        &mut self,
+        id: SurfaceId,
+        surface_info: SurfaceInfo,
+        raw_info: RawSurfaceInfo,
+    ) {
         self.surfaces.insert(id, surface_info);
         self.raw_surfaces.insert(id, raw_info);
         self.available_surfaces.push(id);
@@ -38,4 +121,518 @@ impl SurfaceState {
         //     self.current_surface_id = Some(id);
         // }
     }
+
+    /// 显示器被移除，忘记它的surface
+    ///
+    /// 之后用旧标识发来的请求(比如释放)都会被忽略
+    pub fn remove_surface(&mut self, id: SurfaceId) {
+        self.surfaces.remove(&id);
+        self.raw_surfaces.remove(&id);
+        self.available_surfaces.retain(|surface| *surface != id);
+        self.used_surfaces.remove(&id);
+        if let Some(dma) = self.dma_surfaces.remove(&id) {
+            dma.destroy();
+        }
+        if let Some(mut frame) = self.frames.remove(&id) {
+            frame.destroy_buffers();
+        }
+        if let Some(dma) = self.uploads.remove(&id) {
+            dma.destroy();
+        }
+        if let Some(cursor) = self.cursor_surfaces.remove(&id) {
+            cursor.destroy();
+        }
+        self.render_threads.remove(&id);
+        if self.current_surface_id == Some(id) {
+            self.current_surface_id = None;
+        }
+    }
+
+    /// 销毁所有surface，并把销毁请求立即发给混成器
+    pub fn destroy_all(&mut self) {
+        for (_, dma) in self.dma_surfaces.drain() {
+            dma.destroy();
+        }
+        for (_, mut frame) in self.frames.drain() {
+            frame.destroy_buffers();
+        }
+        for (_, dma) in self.uploads.drain() {
+            dma.destroy();
+        }
+        for (_, cursor) in self.cursor_surfaces.drain() {
+            cursor.destroy();
+        }
+        self.render_threads.clear();
+        for (_, raw) in self.raw_surfaces.drain() {
+            raw.destroy();
+        }
+        self.surfaces.clear();
+        self.available_surfaces.clear();
+        self.used_surfaces.clear();
+        self.current_surface_id = None;
+
+        if let Some(conn) = self.connection.as_ref()
+            && let Err(e) = conn.flush()
+        {
+            warn!("无法提交surface销毁请求: {:?}", e);
+        }
+    }
+
+    /// 与合成器的连接已经断开, 丢弃所有 surface 和绑定的全局对象
+    ///
+    /// 绘制函数、需要 overlay 的显示器和绘制方式保留, 重新连接后沿用
+    pub fn disconnect(&mut self) {
+        // 连接已经断开, 不再提交销毁请求
+        self.connection = None;
+        self.destroy_all();
+        self.qhandle = None;
+        self.dmabuf = None;
+        self.shm = None;
+        self.compositor = None;
+        self.subcompositor = None;
+        self.viewporter = None;
+        self.dmabuf_failed = false;
+    }
+
+    /// surface 的 DMA-BUF 缓冲区, 第一次调用时分配. 多次调用返回同一个缓冲区
+    pub fn dma_buffer(&mut self, id: SurfaceId) -> anyhow::Result<DmaBuffer> {
+        let info = self.surfaces.get(&id).context("surface 已被移除")?;
+        let size = (info.width as u32, info.height as u32);
+        if let Some(dma) = self.dma_surfaces.get(&id)
+            && dma.size() == size
+        {
+            return dma.export();
+        }
+        let dmabuf = self
+            .dmabuf
+            .as_mut()
+            .context("合成器不支持 zwp_linux_dmabuf_v1")?;
+        let dma = dmabuf.allocate(size.0, size.1)?;
+        let buffer = dma.export()?;
+        if let Some(old) = self.dma_surfaces.insert(id, dma) {
+            old.destroy();
+        }
+        self.flush();
+        Ok(buffer)
+    }
+
+    /// 显示 DMA-BUF 缓冲区中已经画好的内容
+    pub fn commit_dma_buffer(&mut self, id: SurfaceId) -> anyhow::Result<()> {
+        let raw = self.raw_surfaces.get(&id).context("surface 已被移除")?;
+        let dma = self
+            .dma_surfaces
+            .get(&id)
+            .context("还没有分配 DMA-BUF 缓冲区")?;
+        dma.attach(&raw.surface);
+        self.flush();
+        Ok(())
+    }
+
+    /// 设置绘制函数并重绘所有 surface
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = Some(renderer);
+        self.request_redraw_all();
+    }
+
+    pub fn set_cursor_renderer(&mut self, renderer: CursorRenderer) {
+        self.cursor_renderer = Some(renderer);
+        self.request_redraw_all();
+        self.request_cursor_redraw_all();
+    }
+
+    /// 实际使用的绘制方式, 合成器不支持配置的方式时使用 shm
+    pub fn strategy(&self) -> OverlayStrategy {
+        match self.requested_strategy {
+            OverlayStrategy::Dmabuf if self.dmabuf.is_none() || self.dmabuf_failed => {
+                OverlayStrategy::Shm
+            }
+            OverlayStrategy::CursorSurface
+                if self.subcompositor.is_none() || self.compositor.is_none() =>
+            {
+                OverlayStrategy::Shm
+            }
+            strategy => strategy,
+        }
+    }
+
+    /// 修改绘制方式并按新的方式重绘
+    pub fn set_strategy(&mut self, strategy: OverlayStrategy) {
+        if strategy == self.requested_strategy {
+            return;
+        }
+        self.requested_strategy = strategy;
+        let strategy = self.strategy();
+        info!("overlay 使用 {strategy} 绘制");
+        if strategy != OverlayStrategy::Dmabuf {
+            for (_, dma) in self.uploads.drain() {
+                dma.destroy();
+            }
+        }
+        if strategy != OverlayStrategy::CursorSurface {
+            for (_, cursor) in self.cursor_surfaces.drain() {
+                cursor.destroy();
+            }
+        }
+        self.request_redraw_all();
+        self.request_cursor_redraw_all();
+    }
+
+    /// 修改需要 overlay 的显示器, 实际的创建和销毁在 Wayland 线程中进行
+    pub fn set_selection(&mut self, selection: OutputSelection) {
+        self.selection = selection;
+        let (Some(conn), Some(qhandle)) = (self.connection.as_ref(), self.qhandle.as_ref()) else {
+            return;
+        };
+        // 收到回调时 Wayland 线程按新的选择创建或销毁 surface
+        conn.display().sync(qhandle, ReconcileSurfaces);
+        self.flush();
+    }
+
+    /// 修改层级和被遮住时的处理, 层级变化时重新创建所有 surface
+    pub fn set_stacking(&mut self, config: StackingConfig) {
+        let layer = config.layer.into();
+        self.stacking.set_config(config);
+        if self.layer == layer {
+            return;
+        }
+        self.layer = layer;
+        let ids = self.raw_surfaces.keys().copied().collect();
+        self.recreate(ids);
+    }
+
+    /// 检查有没有 surface 被其他 layer-shell 客户端遮住, 需要时重新创建
+    pub fn check_stacking(&mut self) {
+        let now = Instant::now();
+        let mut reclaim = Vec::new();
+        for (id, frame) in &self.frames {
+            let cursor = self
+                .cursor_surfaces
+                .get(id)
+                .and_then(|cursor| cursor.waiting_since());
+            let waiting = match (frame.waiting_since(), cursor) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+            if self.stacking.check(*id, waiting, now) {
+                reclaim.push(*id);
+            }
+        }
+        if !reclaim.is_empty() {
+            self.recreate(reclaim);
+        }
+    }
+
+    /// 在 Wayland 线程中销毁并重新创建 surface, 新的 surface 在同一层的最上面
+    fn recreate(&mut self, ids: Vec<SurfaceId>) {
+        let (Some(conn), Some(qhandle)) = (self.connection.as_ref(), self.qhandle.as_ref()) else {
+            return;
+        };
+        if ids.is_empty() {
+            return;
+        }
+        conn.display().sync(qhandle, RecreateSurfaces(ids));
+        self.flush();
+    }
+
+    /// 合成器确定了 surface 的尺寸
+    pub fn configure(&mut self, id: SurfaceId, size: (u32, u32)) {
+        self.frames.entry(id).or_default().configure(size);
+        self.request_redraw(id);
+    }
+
+    /// 合成器给出了 surface 的分数缩放比例
+    pub fn set_scale(&mut self, id: SurfaceId, scale: f64) {
+        self.frames.entry(id).or_default().set_scale(scale);
+        self.request_redraw(id);
+    }
+
+    pub fn request_redraw_all(&mut self) {
+        let ids: Vec<_> = self.frames.keys().copied().collect();
+        for id in ids {
+            self.request_redraw(id);
+        }
+    }
+
+    /// 请求重绘, 合成器还没有显示上一帧时等到帧回调再绘制
+    pub fn request_redraw(&mut self, id: SurfaceId) {
+        if self
+            .frames
+            .get_mut(&id)
+            .is_some_and(|frame| frame.request())
+        {
+            self.draw(id);
+        }
+    }
+
+    /// 光标变化, 光标不在单独的 subsurface 上时重绘整个 surface
+    pub fn request_cursor_redraw_all(&mut self) {
+        if self.strategy() != OverlayStrategy::CursorSurface {
+            self.request_redraw_all();
+            return;
+        }
+        let ids: Vec<_> = self.frames.keys().copied().collect();
+        for id in ids {
+            if self
+                .cursor_surfaces
+                .get_mut(&id)
+                .is_none_or(|cursor| cursor.request())
+            {
+                self.draw_cursor(id);
+            }
+        }
+    }
+
+    /// 收到帧回调
+    pub fn frame_done(&mut self, id: SurfaceId) {
+        if self.frames.get_mut(&id).is_some_and(|frame| frame.done()) {
+            self.draw(id);
+        }
+    }
+
+    /// 收到光标 subsurface 的帧回调
+    pub fn cursor_frame_done(&mut self, id: SurfaceId) {
+        if self
+            .cursor_surfaces
+            .get_mut(&id)
+            .is_some_and(|cursor| cursor.done())
+        {
+            self.draw_cursor(id);
+        }
+    }
+
+    /// 把一帧交给 surface 的绘制线程
+    fn draw(&mut self, id: SurfaceId) {
+        // 使用 DMA-BUF 时内容由调用者用 GPU 绘制
+        if self.dma_surfaces.contains_key(&id) {
+            return;
+        }
+        let strategy = self.strategy();
+        let start = Instant::now();
+        let (Some(frame), Some(info)) = (self.frames.get_mut(&id), self.surfaces.get(&id)) else {
+            return;
+        };
+        let Some(size) = frame.buffer_size(info.scale_factor) else {
+            return;
+        };
+        frame.begin_render();
+        let job = FrameJob {
+            info: info.clone(),
+            size,
+            scale: frame.scale(info.scale_factor),
+            renderer: self.renderer.clone(),
+            cursor: self
+                .cursor_renderer
+                .clone()
+                .filter(|_| strategy != OverlayStrategy::CursorSurface),
+            start,
+        };
+        self.dispatch(id, RenderJob::Frame(job));
+    }
+
+    /// 交给绘制线程, 线程无法创建时在当前线程中绘制
+    fn dispatch(&mut self, id: SurfaceId, job: RenderJob) {
+        if !self.render_threads.contains_key(&id) {
+            match RenderThread::spawn(id, self.this.clone()) {
+                Ok(thread) => {
+                    self.render_threads.insert(id, thread);
+                }
+                Err(e) => warn!("{id} 无法创建绘制线程: {e}"),
+            }
+        }
+        let job = match self.render_threads.get(&id) {
+            Some(thread) => match thread.send(job) {
+                Ok(()) => return,
+                Err(job) => {
+                    self.render_threads.remove(&id);
+                    job
+                }
+            },
+            None => job,
+        };
+        match job {
+            RenderJob::Frame(job) => self.present_frame(id, job.render()),
+            RenderJob::Cursor(job) => self.present_cursor(id, job.render()),
+        }
+    }
+
+    /// 提交绘制线程画好的一帧
+    pub(super) fn present_frame(&mut self, id: SurfaceId, rendered: RenderedFrame) {
+        let (Some(frame), Some(info)) = (self.frames.get_mut(&id), self.surfaces.get(&id)) else {
+            return;
+        };
+        if !frame.rendered(&rendered.canvas, info.scale_factor) {
+            // 绘制期间尺寸或缩放变了
+            self.request_redraw(id);
+            return;
+        }
+        if self.dma_surfaces.contains_key(&id) {
+            return;
+        }
+        let RenderedFrame {
+            canvas,
+            animating,
+            start,
+        } = rendered;
+        let strategy = self.strategy();
+        let result = match strategy {
+            OverlayStrategy::Dmabuf => self.present_dma(id, &canvas, animating),
+            _ => self.present_shm(id, &canvas, animating),
+        };
+        match result {
+            Ok(damage) => self.log_frame(strategy, start, damage),
+            Err(e) if strategy == OverlayStrategy::Dmabuf => {
+                warn!("{id} 无法使用 DMA-BUF, 改用 shm: {e:#}");
+                self.dmabuf_failed = true;
+                for (_, dma) in self.uploads.drain() {
+                    dma.destroy();
+                }
+                if let Err(e) = self.present_shm(id, &canvas, animating) {
+                    warn!("{id} 无法绘制: {e:#}");
+                }
+            }
+            Err(e) => warn!("{id} 无法绘制: {e:#}"),
+        }
+        self.flush();
+    }
+
+    fn present_shm(
+        &mut self,
+        id: SurfaceId,
+        canvas: &Canvas,
+        animating: bool,
+    ) -> anyhow::Result<u64> {
+        let (Some(frame), Some(raw), Some(shm), Some(qhandle)) = (
+            self.frames.get_mut(&id),
+            self.raw_surfaces.get(&id),
+            self.shm.as_ref(),
+            self.qhandle.as_ref(),
+        ) else {
+            return Ok(0);
+        };
+        frame.present(raw, canvas, animating, shm, qhandle)
+    }
+
+    fn present_dma(
+        &mut self,
+        id: SurfaceId,
+        canvas: &Canvas,
+        animating: bool,
+    ) -> anyhow::Result<u64> {
+        let size = (canvas.width(), canvas.height());
+        if self.uploads.get(&id).is_none_or(|dma| dma.size() != size) {
+            let dmabuf = self
+                .dmabuf
+                .as_mut()
+                .context("合成器不支持 zwp_linux_dmabuf_v1")?;
+            let dma = dmabuf.allocate(size.0, size.1)?;
+            if let Some(old) = self.uploads.insert(id, dma) {
+                old.destroy();
+            }
+        }
+        let (Some(frame), Some(raw), Some(dma), Some(qhandle)) = (
+            self.frames.get_mut(&id),
+            self.raw_surfaces.get(&id),
+            self.uploads.get_mut(&id),
+            self.qhandle.as_ref(),
+        ) else {
+            return Ok(0);
+        };
+        frame.present_dma(raw, canvas, animating, dma, qhandle)
+    }
+
+    /// 把光标交给绘制线程, 第一次绘制时创建 subsurface
+    fn draw_cursor(&mut self, id: SurfaceId) {
+        if self.dma_surfaces.contains_key(&id) || self.strategy() != OverlayStrategy::CursorSurface
+        {
+            return;
+        }
+        let start = Instant::now();
+        let (Some(frame), Some(info)) = (self.frames.get(&id), self.surfaces.get(&id)) else {
+            return;
+        };
+        // 全屏 surface 还没有 configure 时 subsurface 也不会显示
+        if frame.buffer_size(info.scale_factor).is_none() {
+            return;
+        }
+        let job = CursorJob {
+            info: info.clone(),
+            scale: frame.scale(info.scale_factor),
+            renderer: self.cursor_renderer.clone(),
+            start,
+        };
+        let (Some(raw), Some(compositor), Some(subcompositor), Some(qhandle)) = (
+            self.raw_surfaces.get(&id),
+            self.compositor.as_ref(),
+            self.subcompositor.as_ref(),
+            self.qhandle.as_ref(),
+        ) else {
+            return;
+        };
+        self.cursor_surfaces
+            .entry(id)
+            .or_insert_with(|| {
+                CursorSurface::new(
+                    raw,
+                    compositor,
+                    subcompositor,
+                    self.viewporter.as_ref(),
+                    qhandle,
+                )
+            })
+            .begin_render();
+        self.dispatch(id, RenderJob::Cursor(job));
+    }
+
+    /// 在光标 subsurface 上显示绘制线程画好的光标
+    pub(super) fn present_cursor(&mut self, id: SurfaceId, rendered: RenderedCursor) {
+        let Some(cursor) = self.cursor_surfaces.get_mut(&id) else {
+            return;
+        };
+        cursor.rendered();
+        let (Some(raw), Some(shm), Some(qhandle)) = (
+            self.raw_surfaces.get(&id),
+            self.shm.as_ref(),
+            self.qhandle.as_ref(),
+        ) else {
+            return;
+        };
+        let (canvas, origin) = match rendered.cursor {
+            Some((canvas, origin)) => (canvas, Some(origin)),
+            None => (Canvas::new(0, 0), None),
+        };
+        match cursor.present(raw, &canvas, origin, shm, qhandle) {
+            Ok(damage) => self.log_frame(OverlayStrategy::CursorSurface, rendered.start, damage),
+            Err(e) => warn!("{id} 无法绘制光标: {e:#}"),
+        }
+        self.flush();
+        if self
+            .cursor_surfaces
+            .get_mut(&id)
+            .is_some_and(|cursor| cursor.take_idle_dirty())
+        {
+            self.draw_cursor(id);
+        }
+    }
+
+    /// 记录提交了内容的一帧
+    fn log_frame(&self, strategy: OverlayStrategy, start: Instant, damage: u64) {
+        if damage == 0 {
+            return;
+        }
+        if let Some(log) = self.frame_log.as_ref() {
+            log.lock().unwrap().push(FrameSample {
+                strategy,
+                elapsed: start.elapsed(),
+                damage,
+            });
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(conn) = self.connection.as_ref()
+            && let Err(e) = conn.flush()
+        {
+            warn!("无法提交请求: {:?}", e);
+        }
+    }
 }
@@ -0,0 +1,280 @@
+//! 用代码创建 overlay
+//!
+//! 守护进程的 overlay 覆盖整个显示器、不接收输入. 其他模块(或者把 tabletd 当作库使用的程序)
+//! 需要特殊用途的 overlay 时(比如临时的校准界面), 用 [`OverlayBuilder`] 选择后端、显示器、
+//! 层级、锚点、输入策略和绘制方式, 不需要接触后端的内部实现
+//!
+//! ```no_run
+//! # async fn example() -> anyhow::Result<()> {
+//! use tabletd::screen_overlay::{
+//!     builder::{Anchor, InputPolicy, OverlayBuilder, OverlayLayer},
+//!     selection::OutputSelection,
+//! };
+//!
+//! let overlay = OverlayBuilder::new()
+//!     .outputs(OutputSelection::only(["DP-1"]))
+//!     .layer(OverlayLayer::Top)
+//!     .anchor(Anchor::FILL)
+//!     .input(InputPolicy::Capture)
+//!     .namespace("tabletd calibration")
+//!     .build()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use wayland_protocols_wlr::layer_shell::v1::client::{zwlr_layer_shell_v1, zwlr_layer_surface_v1};
+
+use crate::mapping::geometry::GeometryBus;
+
+use super::{
+    backend_drm::DrmOverlay,
+    backend_wayland::{
+        WaylandOverlay,
+        discovery::DisplayChooser,
+        frame::{CursorRenderer, Renderer},
+    },
+    error::OverlayError,
+    selection::OutputSelection,
+    strategy::OverlayStrategy,
+};
+
+/// overlay 的后端
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverlayBackend {
+    /// `wlr layer shell`, 需要支持它的 Wayland 合成器
+    #[default]
+    Wayland,
+    /// 直接使用显示器的平面, 只能显示光标
+    Drm,
+}
+
+/// overlay 所在的层级, 对应 `zwlr_layer_shell_v1.layer`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverlayLayer {
+    Background,
+    Bottom,
+    Top,
+    /// 在全屏窗口之上
+    #[default]
+    Overlay,
+}
+
+impl OverlayLayer {
+    pub(super) fn wayland(self) -> zwlr_layer_shell_v1::Layer {
+        match self {
+            OverlayLayer::Background => zwlr_layer_shell_v1::Layer::Background,
+            OverlayLayer::Bottom => zwlr_layer_shell_v1::Layer::Bottom,
+            OverlayLayer::Top => zwlr_layer_shell_v1::Layer::Top,
+            OverlayLayer::Overlay => zwlr_layer_shell_v1::Layer::Overlay,
+        }
+    }
+}
+
+/// overlay 贴住显示器的哪些边. 相对的两条边都贴住时在这个方向上铺满
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Anchor {
+    pub top: bool,
+    pub bottom: bool,
+    pub left: bool,
+    pub right: bool,
+}
+
+impl Anchor {
+    /// 铺满整个显示器
+    pub const FILL: Anchor = Anchor {
+        top: true,
+        bottom: true,
+        left: true,
+        right: true,
+    };
+    /// 显示器中央
+    pub const CENTER: Anchor = Anchor {
+        top: false,
+        bottom: false,
+        left: false,
+        right: false,
+    };
+
+    pub(super) fn wayland(self) -> zwlr_layer_surface_v1::Anchor {
+        let mut anchor = zwlr_layer_surface_v1::Anchor::empty();
+        for (edge, set) in [
+            (zwlr_layer_surface_v1::Anchor::Top, self.top),
+            (zwlr_layer_surface_v1::Anchor::Bottom, self.bottom),
+            (zwlr_layer_surface_v1::Anchor::Left, self.left),
+            (zwlr_layer_surface_v1::Anchor::Right, self.right),
+        ] {
+            if set {
+                anchor |= edge;
+            }
+        }
+        anchor
+    }
+}
+
+/// overlay 是否接收输入
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InputPolicy {
+    /// 输入穿过 overlay 交给下面的窗口
+    #[default]
+    PassThrough,
+    /// overlay 接收指针和数位板输入, 键盘输入仍然交给原来的窗口
+    Capture,
+}
+
+/// 每个显示器上 overlay surface 的设置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SurfaceOptions {
+    /// `zwlr_layer_shell_v1.get_layer_surface` 的 namespace, 合成器可以按它设置规则
+    pub namespace: String,
+    pub layer: OverlayLayer,
+    pub anchor: Anchor,
+    /// 逻辑尺寸, `None` 时和显示器一样大
+    pub size: Option<(u32, u32)>,
+    pub input: InputPolicy,
+}
+
+impl Default for SurfaceOptions {
+    fn default() -> Self {
+        Self {
+            namespace: "tabletd overlay".to_string(),
+            layer: OverlayLayer::default(),
+            anchor: Anchor::FILL,
+            size: None,
+            input: InputPolicy::default(),
+        }
+    }
+}
+
+/// 创建好的 overlay
+pub enum Overlay {
+    Wayland(WaylandOverlay),
+    Drm(DrmOverlay),
+}
+
+/// 按设置创建 overlay, 默认和守护进程的 overlay 相同
+#[derive(Default)]
+pub struct OverlayBuilder {
+    backend: OverlayBackend,
+    selection: Option<OutputSelection>,
+    strategy: Option<OverlayStrategy>,
+    display: Option<DisplayChooser>,
+    surface: SurfaceOptions,
+    geometry: Option<GeometryBus>,
+    renderer: Option<Renderer>,
+    cursor_renderer: Option<CursorRenderer>,
+}
+
+impl OverlayBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn backend(mut self, backend: OverlayBackend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// 需要 overlay 的显示器, 默认所有显示器
+    pub fn outputs(mut self, selection: OutputSelection) -> Self {
+        self.selection = Some(selection);
+        self
+    }
+
+    pub fn layer(mut self, layer: OverlayLayer) -> Self {
+        self.surface.layer = layer;
+        self
+    }
+
+    pub fn anchor(mut self, anchor: Anchor) -> Self {
+        self.surface.anchor = anchor;
+        self
+    }
+
+    /// overlay 的逻辑尺寸, 默认和显示器一样大
+    pub fn size(mut self, width: u32, height: u32) -> Self {
+        self.surface.size = Some((width, height));
+        self
+    }
+
+    pub fn input(mut self, input: InputPolicy) -> Self {
+        self.surface.input = input;
+        self
+    }
+
+    pub fn namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.surface.namespace = namespace.into();
+        self
+    }
+
+    /// 绘制方式, 默认使用 shm
+    pub fn strategy(mut self, strategy: OverlayStrategy) -> Self {
+        self.strategy = Some(strategy);
+        self
+    }
+
+    /// 没有 `WAYLAND_DISPLAY` 时连接哪个合成器, 默认第一个可用的
+    pub fn display(mut self, display: DisplayChooser) -> Self {
+        self.display = Some(display);
+        self
+    }
+
+    /// 显示器布局变化时发布到 `geometry`
+    pub fn geometry(mut self, geometry: GeometryBus) -> Self {
+        self.geometry = Some(geometry);
+        self
+    }
+
+    pub fn renderer(mut self, renderer: Renderer) -> Self {
+        self.renderer = Some(renderer);
+        self
+    }
+
+    pub fn cursor_renderer(mut self, renderer: CursorRenderer) -> Self {
+        self.cursor_renderer = Some(renderer);
+        self
+    }
+
+    /// 创建 overlay. 选择的后端不支持某项设置时返回错误
+    pub async fn build(self) -> anyhow::Result<Overlay> {
+        match self.backend {
+            OverlayBackend::Wayland => {
+                let overlay = WaylandOverlay::with_surface(
+                    self.geometry.unwrap_or_default(),
+                    self.surface,
+                    self.selection.unwrap_or_else(OutputSelection::all),
+                    self.strategy.unwrap_or_default(),
+                    self.display.unwrap_or_default(),
+                );
+                if let Some(renderer) = self.renderer {
+                    overlay.set_renderer(renderer).await?;
+                }
+                if let Some(renderer) = self.cursor_renderer {
+                    overlay.set_cursor_renderer(renderer).await?;
+                }
+                Ok(Overlay::Wayland(overlay))
+            }
+            OverlayBackend::Drm => {
+                if self.surface != SurfaceOptions::default() {
+                    return Err(OverlayError::Unsupported(
+                        "DRM 后端不支持设置层级、锚点、尺寸和输入策略",
+                    )
+                    .into());
+                }
+                if self.selection.is_some() || self.strategy.is_some() || self.display.is_some() {
+                    return Err(OverlayError::Unsupported(
+                        "DRM 后端总是在所有显示器上显示, 不支持选择显示器、合成器和绘制方式",
+                    )
+                    .into());
+                }
+                if self.renderer.is_some() || self.cursor_renderer.is_some() {
+                    return Err(OverlayError::Unsupported(
+                        "DRM 后端只能显示光标, 由 DrmOverlay::render 绘制",
+                    )
+                    .into());
+                }
+                Ok(Overlay::Drm(DrmOverlay::open()?))
+            }
+        }
+    }
+}
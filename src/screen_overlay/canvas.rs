@@ -0,0 +1,402 @@
+use crate::mapping::OutputGeometry;
+
+/// 非预乘的 RGBA 颜色
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Color {
+    pub const TRANSPARENT: Color = Color::rgba(0, 0, 0, 0);
+
+    pub const fn rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Self { r, g, b, a }
+    }
+
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Self::rgba(r, g, b, 0xff)
+    }
+
+    /// 解析 `#rrggbb` 或 `#rrggbbaa`
+    pub fn from_hex(hex: &str) -> Option<Color> {
+        let hex = hex.strip_prefix('#').unwrap_or(hex);
+        let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+        match hex.len() {
+            6 => Some(Color::rgb(channel(0)?, channel(2)?, channel(4)?)),
+            8 => Some(Color::rgba(
+                channel(0)?,
+                channel(2)?,
+                channel(4)?,
+                channel(6)?,
+            )),
+            _ => None,
+        }
+    }
+
+    /// 按 `t` (0.0 ~ 1.0) 在两个颜色之间插值
+    pub fn mix(self, other: Color, t: f32) -> Color {
+        let t = t.clamp(0.0, 1.0);
+        let lerp = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+        Color {
+            r: lerp(self.r, other.r),
+            g: lerp(self.g, other.g),
+            b: lerp(self.b, other.b),
+            a: lerp(self.a, other.a),
+        }
+    }
+
+    /// 乘以不透明度
+    pub fn with_alpha(self, alpha: f32) -> Color {
+        Color {
+            a: (self.a as f32 * alpha.clamp(0.0, 1.0)).round() as u8,
+            ..self
+        }
+    }
+}
+
+/// 需要绘制的一段文字
+///
+/// 画布本身不处理文字，由 [`super::text::TextRenderer`] 统一绘制
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextRun {
+    pub text: String,
+    /// 左上角的像素坐标
+    pub x: f32,
+    pub y: f32,
+    /// 字号(像素)
+    pub size: f32,
+    pub color: Color,
+}
+
+/// 软件绘制用的像素缓冲区
+///
+/// 格式与 `wl_shm::Format::Argb8888` 相同: 预乘 alpha，小端字节序(B, G, R, A)
+pub struct Canvas {
+    width: u32,
+    height: u32,
+    /// 显示器的缩放比例，绘制时逻辑尺寸要乘以它
+    scale: f64,
+    data: Vec<u8>,
+}
+
+impl Canvas {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            scale: 1.0,
+            data: vec![0; width as usize * height as usize * 4],
+        }
+    }
+
+    /// 与显示器同样大小的画布
+    pub fn for_output(output: &OutputGeometry) -> Self {
+        let mut canvas = Self::new(output.width, output.height);
+        canvas.set_scale(output.scale);
+        canvas
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    pub fn set_scale(&mut self, scale: f64) {
+        self.scale = if scale > 0.0 { scale } else { 1.0 };
+    }
+
+    /// 像素数据，可以直接写入 shm 缓冲区
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// 清空为全透明
+    pub fn clear(&mut self) {
+        self.data.fill(0);
+    }
+
+    /// 以 `coverage` (0.0 ~ 1.0) 的覆盖率把颜色叠加到像素上
+    pub fn blend(&mut self, x: i32, y: i32, color: Color, coverage: f32) {
+        if x < 0 || y < 0 || x as u32 >= self.width || y as u32 >= self.height {
+            return;
+        }
+        let alpha = color.a as f32 / 255.0 * coverage.clamp(0.0, 1.0);
+        if alpha <= 0.0 {
+            return;
+        }
+        let offset = (y as usize * self.width as usize + x as usize) * 4;
+        let pixel = &mut self.data[offset..offset + 4];
+        let source = [color.b, color.g, color.r];
+        for (channel, source) in pixel.iter_mut().zip(source) {
+            *channel = (source as f32 * alpha + *channel as f32 * (1.0 - alpha)).round() as u8;
+        }
+        pixel[3] = (255.0 * alpha + pixel[3] as f32 * (1.0 - alpha)).round() as u8;
+    }
+
+    /// 把另一块画布叠加到 `(x, y)` (像素), 超出画布的部分被裁掉
+    pub fn composite(&mut self, x: i32, y: i32, source: &Canvas) {
+        let row = source.width as usize * 4;
+        for (sy, pixels) in source.data.chunks_exact(row).enumerate() {
+            let ty = y + sy as i32;
+            if ty < 0 || ty as u32 >= self.height {
+                continue;
+            }
+            for (sx, pixel) in pixels.chunks_exact(4).enumerate() {
+                let tx = x + sx as i32;
+                let alpha = pixel[3] as u32;
+                if tx < 0 || tx as u32 >= self.width || alpha == 0 {
+                    continue;
+                }
+                // 两边都是预乘 alpha: dst = src + dst * (1 - src_alpha)
+                let offset = (ty as usize * self.width as usize + tx as usize) * 4;
+                for (channel, source) in self.data[offset..offset + 4].iter_mut().zip(pixel) {
+                    let blended = *source as u32 + (*channel as u32 * (255 - alpha) + 127) / 255;
+                    *channel = blended.min(255) as u8;
+                }
+            }
+        }
+    }
+
+    /// 填充矩形
+    pub fn fill_rect(&mut self, x: i32, y: i32, width: u32, height: u32, color: Color) {
+        for py in y..y.saturating_add(height as i32) {
+            for px in x..x.saturating_add(width as i32) {
+                self.blend(px, py, color, 1.0);
+            }
+        }
+    }
+
+    /// 抗锯齿的实心圆
+    pub fn fill_circle(&mut self, cx: f32, cy: f32, radius: f32, color: Color) {
+        let extent = (radius + 1.0).ceil() as i32;
+        let (x0, y0) = (cx.round() as i32, cy.round() as i32);
+        for py in y0 - extent..=y0 + extent {
+            for px in x0 - extent..=x0 + extent {
+                let distance = (px as f32 + 0.5 - cx).hypot(py as f32 + 0.5 - cy);
+                let coverage = (radius + 0.5 - distance).clamp(0.0, 1.0);
+                if coverage > 0.0 {
+                    self.blend(px, py, color, coverage);
+                }
+            }
+        }
+    }
+
+    /// 抗锯齿的圆弧. 角度从正上方开始顺时针计算(弧度)，`sweep` 不小于一圈时画整个圆环
+    pub fn stroke_arc(
+        &mut self,
+        (cx, cy): (f32, f32),
+        radius: f32,
+        width: f32,
+        (start, sweep): (f32, f32),
+        color: Color,
+    ) {
+        use std::f32::consts::TAU;
+
+        let half = width / 2.0;
+        let extent = (radius + half + 1.0).ceil() as i32;
+        let (x0, y0) = (cx.round() as i32, cy.round() as i32);
+        for py in y0 - extent..=y0 + extent {
+            for px in x0 - extent..=x0 + extent {
+                let (dx, dy) = (px as f32 + 0.5 - cx, py as f32 + 0.5 - cy);
+                let coverage = (half + 0.5 - (dx.hypot(dy) - radius).abs()).clamp(0.0, 1.0);
+                if coverage <= 0.0 {
+                    continue;
+                }
+                // 屏幕坐标 y 向下, 这样算出的角度是从正上方顺时针的
+                let angle = dx.atan2(-dy);
+                if sweep < TAU && (angle - start).rem_euclid(TAU) > sweep {
+                    continue;
+                }
+                self.blend(px, py, color, coverage);
+            }
+        }
+    }
+
+    /// 矩形边框，线条画在矩形内侧
+    pub fn stroke_rect(
+        &mut self,
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+        line: u32,
+        color: Color,
+    ) {
+        let line = line.min(width / 2).min(height / 2).max(1);
+        self.fill_rect(x, y, width, line, color);
+        self.fill_rect(x, y + (height - line) as i32, width, line, color);
+        self.fill_rect(x, y + line as i32, line, height - 2 * line, color);
+        self.fill_rect(
+            x + (width - line) as i32,
+            y + line as i32,
+            line,
+            height - 2 * line,
+            color,
+        );
+    }
+
+    /// 编码为 PNG 图片(不压缩)
+    pub fn to_png(&self) -> Vec<u8> {
+        // 每行前面是过滤类型 0，像素转为非预乘的 RGBA
+        let mut raw = Vec::with_capacity((self.width as usize * 4 + 1) * self.height as usize);
+        for row in self.data.chunks_exact(self.width.max(1) as usize * 4) {
+            raw.push(0);
+            for pixel in row.chunks_exact(4) {
+                let [b, g, r, a] = [pixel[0], pixel[1], pixel[2], pixel[3]];
+                let unpremultiply = |c: u8| {
+                    if a == 0 {
+                        0
+                    } else {
+                        ((c as u32 * 255 + a as u32 / 2) / a as u32).min(255) as u8
+                    }
+                };
+                raw.extend([unpremultiply(r), unpremultiply(g), unpremultiply(b), a]);
+            }
+        }
+
+        let mut header = Vec::with_capacity(13);
+        header.extend(self.width.to_be_bytes());
+        header.extend(self.height.to_be_bytes());
+        // 8 位 RGBA, 默认压缩和过滤方式, 不隔行
+        header.extend([8, 6, 0, 0, 0]);
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png_chunk(&mut png, b"IHDR", &header);
+        png_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+        png_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+}
+
+/// HUD 和墨迹的绘制操作
+///
+/// [`Canvas`] 直接在 CPU 上绘制; [`super::scene::Scene`] 只记录图形, 之后交给 GPU 或者
+/// 再画到画布上
+pub trait Painter {
+    fn width(&self) -> u32;
+    fn height(&self) -> u32;
+    /// 显示器的缩放比例，绘制时逻辑尺寸要乘以它
+    fn scale(&self) -> f64;
+    fn fill_rect(&mut self, x: i32, y: i32, width: u32, height: u32, color: Color);
+    fn fill_circle(&mut self, cx: f32, cy: f32, radius: f32, color: Color);
+    fn stroke_arc(
+        &mut self,
+        center: (f32, f32),
+        radius: f32,
+        width: f32,
+        angles: (f32, f32),
+        color: Color,
+    );
+
+    /// 两端粗细不同的线段，由一串圆拼成
+    fn stroke_segment(
+        &mut self,
+        (x0, y0): (f32, f32),
+        (x1, y1): (f32, f32),
+        (width0, width1): (f32, f32),
+        color: Color,
+    ) {
+        let length = (x1 - x0).hypot(y1 - y0);
+        let step = (width0.min(width1) / 4.0).max(0.5);
+        let count = (length / step).ceil().max(1.0) as u32;
+        for i in 0..=count {
+            let t = i as f32 / count as f32;
+            self.fill_circle(
+                x0 + (x1 - x0) * t,
+                y0 + (y1 - y0) * t,
+                (width0 + (width1 - width0) * t) / 2.0,
+                color,
+            );
+        }
+    }
+}
+
+impl Painter for Canvas {
+    fn width(&self) -> u32 {
+        self.width
+    }
+
+    fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    fn fill_rect(&mut self, x: i32, y: i32, width: u32, height: u32, color: Color) {
+        Canvas::fill_rect(self, x, y, width, height, color);
+    }
+
+    fn fill_circle(&mut self, cx: f32, cy: f32, radius: f32, color: Color) {
+        Canvas::fill_circle(self, cx, cy, radius, color);
+    }
+
+    fn stroke_arc(
+        &mut self,
+        center: (f32, f32),
+        radius: f32,
+        width: f32,
+        angles: (f32, f32),
+        color: Color,
+    ) {
+        Canvas::stroke_arc(self, center, radius, width, angles, color);
+    }
+}
+
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend(kind);
+    png.extend(data);
+    let crc = crc32(&png[start..]);
+    png.extend(crc.to_be_bytes());
+}
+
+/// 只用 stored 块的 zlib 数据流
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut chunks = data.chunks(0xffff).peekable();
+    if chunks.peek().is_none() {
+        out.extend([1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(chunk) = chunks.next() {
+        out.push(chunks.peek().is_none() as u8);
+        let len = chunk.len() as u16;
+        out.extend(len.to_le_bytes());
+        out.extend((!len).to_le_bytes());
+        out.extend(chunk);
+    }
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    out.extend((b << 16 | a).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
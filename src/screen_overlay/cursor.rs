@@ -0,0 +1,185 @@
+//! 动态光标
+//!
+//! - 笔悬空时是一个空心圆，有倾斜时变形为椭圆，倾斜越大越扁，
+//!   深浅两种颜色标出倾斜的方向
+//! - 笔按下后过渡为实心圆，半径随压力变化，内部的扇形表示倾斜的方向和角度
+//!
+//! 设备不支持倾斜时始终是正圆，不支持压感时按下后的半径固定
+
+use std::{
+    f32::consts::PI,
+    time::{Duration, Instant},
+};
+
+use crate::event_model::{
+    capability::DeviceCapabilities,
+    event::{PenLocation, PenState},
+};
+
+use super::canvas::{Canvas, Color};
+
+/// 能表示的最大倾斜角(度)
+const MAX_TILT: f32 = 60.0;
+
+/// 光标外观，尺寸都是逻辑像素
+#[derive(Debug, Clone)]
+pub struct CursorStyle {
+    /// 悬空时的半径
+    pub hover_radius: f32,
+    /// 最小压力时的半径
+    pub min_radius: f32,
+    /// 最大压力时的半径
+    pub max_radius: f32,
+    /// 空心圆的线宽
+    pub line_width: f32,
+    pub light: Color,
+    pub dark: Color,
+    /// 悬空和按下之间的过渡时间
+    pub transition: Duration,
+}
+
+impl Default for CursorStyle {
+    fn default() -> Self {
+        Self {
+            hover_radius: 12.0,
+            min_radius: 3.0,
+            max_radius: 14.0,
+            line_width: 2.0,
+            light: Color::rgba(0xff, 0xff, 0xff, 0xe0),
+            dark: Color::rgba(0x20, 0x60, 0xff, 0xe0),
+            transition: Duration::from_millis(80),
+        }
+    }
+}
+
+/// 一支笔的光标
+pub struct Cursor {
+    style: CursorStyle,
+    capabilities: DeviceCapabilities,
+    /// 按下的程度: 0 为悬空，1 为完全按下
+    press: f32,
+    last_update: Option<Instant>,
+}
+
+impl Cursor {
+    pub fn new(style: CursorStyle, capabilities: DeviceCapabilities) -> Self {
+        Self {
+            style,
+            capabilities,
+            press: 0.0,
+            last_update: None,
+        }
+    }
+
+    pub fn style(&self) -> &CursorStyle {
+        &self.style
+    }
+
+    /// 修改深色部分的颜色, 浅色部分保持不变
+    pub fn set_color(&mut self, color: Color) {
+        self.style.dark = color;
+    }
+
+    /// 推进悬空/按下之间的过渡动画
+    pub fn update(&mut self, pen: &PenState, now: Instant) {
+        let target = if let PenLocation::Pressed = pen.location {
+            1.0
+        } else {
+            0.0
+        };
+        let elapsed = self
+            .last_update
+            .map_or(Duration::MAX, |last| now.saturating_duration_since(last));
+        self.last_update = Some(now);
+        let step = if self.style.transition.is_zero() {
+            1.0
+        } else {
+            elapsed.as_secs_f32() / self.style.transition.as_secs_f32()
+        };
+        self.press = if target > self.press {
+            (self.press + step).min(target)
+        } else {
+            (self.press - step).max(target)
+        };
+    }
+
+    /// 在画布的 `(x, y)` 像素处绘制光标，笔离开时不绘制
+    pub fn render_cursor(&self, pen: &PenState, (x, y): (f32, f32), canvas: &mut Canvas) {
+        if let PenLocation::Leaved = pen.location {
+            return;
+        }
+        let style = &self.style;
+        let scale = canvas.scale() as f32;
+
+        // 倾斜: 方向和程度(0 ~ 1)
+        let (tilt, direction) = if self.capabilities.tilt {
+            let (tilt_x, tilt_y) = (pen.tilt.x as f32, pen.tilt.y as f32);
+            (
+                (tilt_x.hypot(tilt_y) / MAX_TILT).min(1.0),
+                tilt_y.atan2(tilt_x),
+            )
+        } else {
+            (0.0, 0.0)
+        };
+
+        let pressure = if self.capabilities.has_pressure() {
+            (pen.pressure as f32 / self.capabilities.max_pressure as f32).min(1.0)
+        } else {
+            0.5
+        };
+        let pressed_radius = style.min_radius + (style.max_radius - style.min_radius) * pressure;
+
+        let press = self.press;
+        let radius = (style.hover_radius + (pressed_radius - style.hover_radius) * press) * scale;
+        // 按下的过程中椭圆逐渐变回圆形
+        let flatten = 1.0 - (1.0 - (tilt * PI / 2.0 * 0.9).cos()) * (1.0 - press);
+        let (major, minor) = (radius, radius * flatten);
+        let half_line = style.line_width * scale / 2.0;
+
+        let (sin, cos) = direction.sin_cos();
+        let extent = (major + half_line + 1.0).ceil() as i32;
+        let (cx, cy) = (x.round() as i32, y.round() as i32);
+        for py in cy - extent..=cy + extent {
+            for px in cx - extent..=cx + extent {
+                let dx = px as f32 + 0.5 - x;
+                let dy = py as f32 + 0.5 - y;
+                // 转到以倾斜方向为 u 轴的坐标系, 椭圆沿倾斜方向压扁
+                let u = dx * cos + dy * sin;
+                let v = -dx * sin + dy * cos;
+                let distance = ((u / minor).hypot(v / major) - 1.0) * minor.min(major);
+
+                // 实心部分
+                let fill = (0.5 - distance).clamp(0.0, 1.0) * press;
+                if fill > 0.0 {
+                    let angle = v.atan2(u).abs();
+                    let in_sector = tilt > 0.0 && angle < tilt * PI / 2.0;
+                    let color = if in_sector { style.dark } else { style.light };
+                    canvas.blend(px, py, color, fill);
+                }
+
+                // 空心圆环，颜色从倾斜方向的深色渐变到另一侧的浅色
+                let ring = (half_line + 0.5 - distance.abs()).clamp(0.0, 1.0) * (1.0 - press);
+                if ring > 0.0 {
+                    let t = if tilt > 0.0 {
+                        (u / minor.max(1.0) + 1.0) / 2.0
+                    } else {
+                        0.0
+                    };
+                    canvas.blend(px, py, style.light.mix(style.dark, t), ring);
+                }
+            }
+        }
+    }
+}
+
+/// 用默认样式绘制光标，不带过渡动画
+pub fn render_cursor(
+    pen: &PenState,
+    position: (f32, f32),
+    capabilities: &DeviceCapabilities,
+    canvas: &mut Canvas,
+) {
+    let mut cursor = Cursor::new(CursorStyle::default(), capabilities.clone());
+    cursor.update(pen, Instant::now());
+    cursor.render_cursor(pen, position, canvas);
+}
@@ -0,0 +1,172 @@
+use std::{collections::BTreeMap, time::Instant};
+
+use crate::{
+    event_model::{
+        capability::DeviceCapabilities,
+        event::{PenLocation, PenState},
+        tablet::TabletId,
+    },
+    mapping::ScreenPoint,
+};
+
+use super::{
+    canvas::{Canvas, Color, TextRun},
+    cursor::{Cursor, CursorStyle},
+};
+
+/// 没有配置颜色时依次使用的颜色
+const PALETTE: &[Color] = &[
+    Color::rgba(0x20, 0x60, 0xff, 0xe0),
+    Color::rgba(0xff, 0x40, 0x60, 0xe0),
+    Color::rgba(0x20, 0xc0, 0x60, 0xe0),
+    Color::rgba(0xff, 0xa0, 0x20, 0xe0),
+    Color::rgba(0xa0, 0x40, 0xff, 0xe0),
+];
+
+/// 标签相对于光标中心的偏移(逻辑像素)
+const LABEL_OFFSET: (f32, f32) = (18.0, 14.0);
+/// 标签字号(逻辑像素)
+const LABEL_SIZE: f32 = 12.0;
+
+struct TrackedCursor {
+    cursor: Cursor,
+    label: Option<String>,
+    pen: Option<PenState>,
+    position: Option<ScreenPoint>,
+}
+
+/// 管理每块数位板的光标
+///
+/// 出口把映射后的笔状态按数位板送进来(见 [`super::layers::OverlayLayers::update_cursor`]),
+/// overlay 为每个显示器调用 [`CursorManager::render`]
+#[derive(Default)]
+pub struct CursorManager {
+    cursors: BTreeMap<TabletId, TrackedCursor>,
+}
+
+impl CursorManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 数位板接入时添加光标
+    ///
+    /// 没有指定颜色时从调色板中按接入顺序选一个
+    pub fn add(
+        &mut self,
+        id: TabletId,
+        capabilities: DeviceCapabilities,
+        color: Option<Color>,
+        label: Option<String>,
+    ) {
+        let color = color.unwrap_or(PALETTE[self.cursors.len() % PALETTE.len()]);
+        let style = CursorStyle {
+            dark: color,
+            ..CursorStyle::default()
+        };
+        self.cursors.insert(
+            id,
+            TrackedCursor {
+                cursor: Cursor::new(style, capabilities),
+                label,
+                pen: None,
+                position: None,
+            },
+        );
+    }
+
+    /// 数位板断开时移除光标
+    pub fn remove(&mut self, id: TabletId) {
+        self.cursors.remove(&id);
+    }
+
+    /// 修改光标颜色, 例如配置文件重新加载后
+    pub fn set_color(&mut self, id: TabletId, color: Color) {
+        if let Some(tracked) = self.cursors.get_mut(&id) {
+            tracked.cursor.set_color(color);
+        }
+    }
+
+    pub fn label(&self, id: TabletId) -> Option<&str> {
+        self.cursors.get(&id)?.label.as_deref()
+    }
+
+    pub fn set_label(&mut self, id: TabletId, label: Option<String>) {
+        if let Some(tracked) = self.cursors.get_mut(&id) {
+            tracked.label = label;
+        }
+    }
+
+    /// 更新一块数位板的笔状态，`position` 为映射后的屏幕位置
+    pub fn update(
+        &mut self,
+        id: TabletId,
+        pen: &PenState,
+        position: Option<ScreenPoint>,
+        now: Instant,
+    ) {
+        let Some(tracked) = self.cursors.get_mut(&id) else {
+            return;
+        };
+        tracked.cursor.update(pen, now);
+        tracked.pen = Some(pen.clone());
+        tracked.position = position;
+    }
+
+    /// 某个显示器上可见的光标
+    fn visible<'a>(
+        &'a self,
+        output: &'a str,
+    ) -> impl Iterator<Item = (&'a TrackedCursor, &'a PenState, &'a ScreenPoint)> + 'a {
+        self.cursors.values().filter_map(move |tracked| {
+            let pen = tracked.pen.as_ref()?;
+            let position = tracked.position.as_ref()?;
+            let visible = !matches!(pen.location, PenLocation::Leaved) && position.output == output;
+            visible.then_some((tracked, pen, position))
+        })
+    }
+
+    /// 在 `output` 对应的画布上绘制所有光标
+    pub fn render(&self, output: &str, canvas: &mut Canvas) {
+        self.render_at(output, (0.0, 0.0), canvas);
+    }
+
+    /// 画布只覆盖显示器的一部分时使用(例如 DRM 的光标平面), `origin` 是画布左上角的像素位置
+    pub fn render_at(&self, output: &str, (ox, oy): (f32, f32), canvas: &mut Canvas) {
+        for (tracked, pen, position) in self.visible(output) {
+            let position = (position.x as f32 - ox, position.y as f32 - oy);
+            tracked.cursor.render_cursor(pen, position, canvas);
+        }
+    }
+
+    /// 除了第一个以外的光标, 第一个画在单独的光标画布上(见 [`Self::render_at`])
+    pub fn render_others(&self, output: &str, canvas: &mut Canvas) {
+        for (tracked, pen, position) in self.visible(output).skip(1) {
+            let position = (position.x as f32, position.y as f32);
+            tracked.cursor.render_cursor(pen, position, canvas);
+        }
+    }
+
+    /// `output` 上可见光标的像素位置, HUD 用来避开光标
+    pub fn positions(&self, output: &str) -> Vec<(f32, f32)> {
+        self.visible(output)
+            .map(|(_, _, position)| (position.x as f32, position.y as f32))
+            .collect()
+    }
+
+    /// `output` 上需要绘制的标签
+    pub fn labels(&self, output: &str, scale: f64) -> Vec<TextRun> {
+        let scale = scale as f32;
+        self.visible(output)
+            .filter_map(|(tracked, _, position)| {
+                Some(TextRun {
+                    text: tracked.label.clone()?,
+                    x: position.x as f32 + LABEL_OFFSET.0 * scale,
+                    y: position.y as f32 + LABEL_OFFSET.1 * scale,
+                    size: LABEL_SIZE * scale,
+                    color: tracked.cursor.style().dark,
+                })
+            })
+            .collect()
+    }
+}
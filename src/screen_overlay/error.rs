@@ -0,0 +1,42 @@
+//! overlay 的错误
+
+use thiserror::Error;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::error::ErrorKind;
+
+#[derive(Debug, Error)]
+pub enum OverlayError {
+    /// 处理 overlay 的任务已经结束, 通常是和合成器的连接断开了
+    #[error("overlay 已停止")]
+    Stopped,
+    #[error("没有可用的显示器")]
+    NoDisplay,
+    /// 获取或提交 DMA-BUF 失败
+    #[error("DMA-BUF 操作失败")]
+    Buffer(#[source] anyhow::Error),
+    /// 选择的后端不支持这项设置
+    #[error("{0}")]
+    Unsupported(&'static str),
+}
+
+impl OverlayError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            OverlayError::Unsupported(_) => ErrorKind::Unsupported,
+            _ => ErrorKind::Overlay,
+        }
+    }
+}
+
+impl<T> From<mpsc::error::SendError<T>> for OverlayError {
+    fn from(_: mpsc::error::SendError<T>) -> Self {
+        OverlayError::Stopped
+    }
+}
+
+impl From<oneshot::error::RecvError> for OverlayError {
+    fn from(_: oneshot::error::RecvError) -> Self {
+        OverlayError::Stopped
+    }
+}
@@ -0,0 +1,575 @@
+//! 用 GPU 绘制 HUD
+//!
+//! 需要启用 `gpu` feature. [`GpuRenderer`] 通过 Vulkan 把 overlay 的 DMA-BUF
+//! (见 [`Display::get_dma_buffer`]) 导入为 wgpu 纹理, 图形每帧在 GPU 上重新绘制,
+//! 快捷菜单、提示的动画和光标轨迹每帧都要重画时 CPU 几乎不参与. 文字仍由
+//! [`TextRenderer`] 在 CPU 上绘制, 只有文字变化时才重新绘制和上传.
+//!
+//! 没有 Vulkan 设备、显卡不支持导入 DMA-BUF 或者合成器只接受隐式修饰符时 [`GpuRenderer::new`]
+//! 或 [`GpuRenderer::present`] 返回错误, 这时改用软件绘制: 在 overlay 的绘制函数中用
+//! [`Scene::paint`] 和 [`TextRenderer::draw`] 画到 shm 缓冲区上
+
+use std::{
+    ffi::CStr,
+    os::fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd},
+};
+
+use anyhow::{Context, anyhow, bail};
+use ash::{ext, khr, vk};
+use wgpu::{hal, util::DeviceExt};
+
+use super::{
+    backend_wayland::{
+        Display,
+        dmabuf::{DmaBuffer, FORMAT},
+    },
+    canvas::{Canvas, Color, Painter, TextRun},
+    scene::{Scene, Shape},
+    text::TextRenderer,
+};
+
+/// 和 [`FORMAT`] (小端的 B, G, R, A) 对应的纹理格式
+const TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Bgra8Unorm;
+/// `DRM_FORMAT_MOD_INVALID`, 修饰符由驱动隐式决定
+const MOD_INVALID: u64 = 0x00ff_ffff_ffff_ffff;
+/// 导入 DMA-BUF 需要的设备扩展
+const EXTENSIONS: [&CStr; 4] = [
+    khr::external_memory_fd::NAME,
+    ext::external_memory_dma_buf::NAME,
+    ext::image_drm_format_modifier::NAME,
+    khr::image_format_list::NAME,
+];
+/// 每个图形的顶点数据: 范围、形状、圆弧参数和颜色, 各 4 个 f32
+const INSTANCE_SIZE: u64 = 16 * 4;
+
+/// 导入的 overlay 缓冲区
+struct Target {
+    size: (u32, u32),
+    view: wgpu::TextureView,
+    /// CPU 绘制的文字, 和目标一样大
+    text_layer: wgpu::Texture,
+    bind_group: wgpu::BindGroup,
+    /// 文字层中现在的文字
+    runs: Vec<TextRun>,
+}
+
+/// 在 GPU 上把 [`Scene`] 画到 overlay 的 DMA-BUF
+pub struct GpuRenderer {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    /// 和 wgpu 共用的 Vulkan 设备, 用于导入 DMA-BUF
+    raw: ash::Device,
+    memory_fd: khr::external_memory_fd::Device,
+    layout: wgpu::BindGroupLayout,
+    shapes: wgpu::RenderPipeline,
+    text: wgpu::RenderPipeline,
+    text_renderer: TextRenderer,
+    target: Option<Target>,
+}
+
+impl GpuRenderer {
+    /// 打开支持导入 DMA-BUF 的 Vulkan 设备
+    pub async fn new() -> anyhow::Result<Self> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::VULKAN,
+            ..Default::default()
+        });
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions::default())
+            .await
+            .context("找不到 Vulkan 设备")?;
+        // wgpu 不会打开导入 DMA-BUF 需要的扩展, 自己创建 Vulkan 设备再交给 wgpu
+        let (open, raw, memory_fd) = unsafe {
+            adapter.as_hal::<hal::api::Vulkan, _, _>(|hal| {
+                open_device(hal.context("不是 Vulkan 设备")?)
+            })
+        }?;
+        let (device, queue) = unsafe {
+            adapter.create_device_from_hal(
+                open,
+                &wgpu::DeviceDescriptor {
+                    label: Some("tabletd hud"),
+                    ..Default::default()
+                },
+            )
+        }?;
+
+        let shader = device.create_shader_module(wgpu::include_wgsl!("gpu.wgsl"));
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |vertex: &str, fragment: &str, buffers: &[wgpu::VertexBufferLayout]| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(fragment),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some(vertex),
+                    compilation_options: Default::default(),
+                    buffers,
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some(fragment),
+                    compilation_options: Default::default(),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: TEXTURE_FORMAT,
+                        blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleStrip,
+                    ..Default::default()
+                },
+                depth_stencil: None,
+                multisample: Default::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        let shapes = pipeline(
+            "shape_vertex",
+            "shape_fragment",
+            &[wgpu::VertexBufferLayout {
+                array_stride: INSTANCE_SIZE,
+                step_mode: wgpu::VertexStepMode::Instance,
+                attributes: &wgpu::vertex_attr_array![
+                    0 => Float32x4,
+                    1 => Float32x4,
+                    2 => Float32x4,
+                    3 => Float32x4,
+                ],
+            }],
+        );
+        let text = pipeline("text_vertex", "text_fragment", &[]);
+
+        Ok(Self {
+            device,
+            queue,
+            raw,
+            memory_fd,
+            layout,
+            shapes,
+            text,
+            text_renderer: TextRenderer::new(),
+            target: None,
+        })
+    }
+
+    /// 在 `display` 的 DMA-BUF 上绘制一帧并显示
+    ///
+    /// `scene` 的尺寸要和 overlay 的缓冲区相同. 等 GPU 画完后才提交给合成器
+    pub async fn present(
+        &mut self,
+        display: &Display,
+        scene: &Scene,
+        runs: &[TextRun],
+    ) -> anyhow::Result<()> {
+        let size = (scene.width(), scene.height());
+        if self
+            .target
+            .as_ref()
+            .is_none_or(|target| target.size != size)
+        {
+            self.target = None;
+            let buffer = display.get_dma_buffer().await.context("无法获取 DMA-BUF")?;
+            if (buffer.width, buffer.height) != size {
+                bail!(
+                    "DMA-BUF {}x{} 和场景 {}x{} 的尺寸不同",
+                    buffer.width,
+                    buffer.height,
+                    size.0,
+                    size.1
+                );
+            }
+            self.target = Some(self.import(buffer)?);
+        }
+        self.render(scene, runs)?;
+        display
+            .commit_dma_buffer()
+            .await
+            .context("无法提交 DMA-BUF")
+    }
+
+    fn render(&mut self, scene: &Scene, runs: &[TextRun]) -> anyhow::Result<()> {
+        let target = self.target.as_mut().context("还没有导入 DMA-BUF")?;
+        if target.runs != runs {
+            let mut canvas = Canvas::new(target.size.0, target.size.1);
+            self.text_renderer.draw(&mut canvas, runs);
+            self.queue.write_texture(
+                target.text_layer.as_image_copy(),
+                canvas.data(),
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(target.size.0 * 4),
+                    rows_per_image: None,
+                },
+                target.text_layer.size(),
+            );
+            target.runs = runs.to_vec();
+        }
+
+        let instances: Vec<u8> = scene
+            .shapes()
+            .iter()
+            .flat_map(instance)
+            .flat_map(f32::to_ne_bytes)
+            .collect();
+        let instances = (!instances.is_empty()).then(|| {
+            self.device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: None,
+                    contents: &instances,
+                    usage: wgpu::BufferUsages::VERTEX,
+                })
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &target.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                ..Default::default()
+            });
+            pass.set_bind_group(0, &target.bind_group, &[]);
+            if let Some(instances) = &instances {
+                pass.set_pipeline(&self.shapes);
+                pass.set_vertex_buffer(0, instances.slice(..));
+                pass.draw(0..4, 0..(instances.size() / INSTANCE_SIZE) as u32);
+            }
+            // 文字画在图形上面
+            if !runs.is_empty() {
+                pass.set_pipeline(&self.text);
+                pass.draw(0..3, 0..1);
+            }
+        }
+        self.queue.submit([encoder.finish()]);
+        // 合成器读取前必须画完, 没有显式同步时只能在这里等待
+        self.device.poll(wgpu::PollType::Wait)?;
+        Ok(())
+    }
+
+    /// 把 DMA-BUF 导入为可以绘制的纹理
+    fn import(&self, buffer: DmaBuffer) -> anyhow::Result<Target> {
+        if buffer.format != FORMAT as u32 {
+            bail!("不支持 DMA-BUF 格式 {:#x}", buffer.format);
+        }
+        if buffer.modifier == MOD_INVALID {
+            bail!("DMA-BUF 的修饰符由驱动隐式决定, 无法导入 Vulkan");
+        }
+        let [plane] = <[_; 1]>::try_from(buffer.planes)
+            .map_err(|planes| anyhow!("不支持 {} 个平面的 DMA-BUF", planes.len()))?;
+        let size = (buffer.width, buffer.height);
+
+        let layouts = [vk::SubresourceLayout {
+            offset: plane.offset as u64,
+            row_pitch: plane.stride as u64,
+            ..Default::default()
+        }];
+        let mut modifier = vk::ImageDrmFormatModifierExplicitCreateInfoEXT::default()
+            .drm_format_modifier(buffer.modifier)
+            .plane_layouts(&layouts);
+        let mut external = vk::ExternalMemoryImageCreateInfo::default()
+            .handle_types(vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT);
+        let info = vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(vk::Format::B8G8R8A8_UNORM)
+            .extent(vk::Extent3D {
+                width: size.0,
+                height: size.1,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT)
+            .usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .push_next(&mut modifier)
+            .push_next(&mut external);
+
+        let image =
+            unsafe { self.raw.create_image(&info, None) }.context("无法创建 Vulkan 图像")?;
+        let memory = match self.bind_memory(image, plane.fd) {
+            Ok(memory) => memory,
+            Err(e) => {
+                unsafe { self.raw.destroy_image(image, None) };
+                return Err(e);
+            }
+        };
+
+        let desc = wgpu::TextureDescriptor {
+            label: Some("overlay"),
+            size: wgpu::Extent3d {
+                width: size.0,
+                height: size.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: TEXTURE_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        };
+        let raw = self.raw.clone();
+        let texture = unsafe {
+            let hal_texture = hal::vulkan::Device::texture_from_raw(
+                image,
+                &hal::TextureDescriptor {
+                    label: desc.label,
+                    size: desc.size,
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: desc.dimension,
+                    format: desc.format,
+                    usage: wgpu::TextureUses::COLOR_TARGET,
+                    memory_flags: hal::MemoryFlags::empty(),
+                    view_formats: Vec::new(),
+                },
+                Some(Box::new(move || {
+                    raw.destroy_image(image, None);
+                    raw.free_memory(memory, None);
+                })),
+            );
+            self.device
+                .create_texture_from_hal::<hal::api::Vulkan>(hal_texture, &desc)
+        };
+
+        let text_layer = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("text"),
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            ..desc
+        });
+        let viewport = [size.0 as f32, size.1 as f32, 0.0, 0.0];
+        let viewport = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: &viewport.map(f32::to_ne_bytes).concat(),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: viewport.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(
+                        &text_layer.create_view(&Default::default()),
+                    ),
+                },
+            ],
+        });
+        Ok(Target {
+            size,
+            view: texture.create_view(&Default::default()),
+            text_layer,
+            bind_group,
+            // 新的文字层是空的
+            runs: Vec::new(),
+        })
+    }
+
+    /// 导入 DMA-BUF 的内存并绑定到图像上
+    fn bind_memory(&self, image: vk::Image, fd: OwnedFd) -> anyhow::Result<vk::DeviceMemory> {
+        let handle_type = vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT;
+        let mut properties = vk::MemoryFdPropertiesKHR::default();
+        unsafe {
+            self.memory_fd
+                .get_memory_fd_properties(handle_type, fd.as_raw_fd(), &mut properties)
+        }
+        .context("无法查询 DMA-BUF 的内存类型")?;
+        let requirements = unsafe { self.raw.get_image_memory_requirements(image) };
+        let types = properties.memory_type_bits & requirements.memory_type_bits;
+        if types == 0 {
+            bail!("没有可以导入 DMA-BUF 的内存类型");
+        }
+
+        // 导入成功后文件描述符归 Vulkan 所有
+        let raw_fd = fd.into_raw_fd();
+        let mut import = vk::ImportMemoryFdInfoKHR::default()
+            .handle_type(handle_type)
+            .fd(raw_fd);
+        let mut dedicated = vk::MemoryDedicatedAllocateInfo::default().image(image);
+        let info = vk::MemoryAllocateInfo::default()
+            .allocation_size(requirements.size)
+            .memory_type_index(types.trailing_zeros())
+            .push_next(&mut import)
+            .push_next(&mut dedicated);
+        let memory = match unsafe { self.raw.allocate_memory(&info, None) } {
+            Ok(memory) => memory,
+            Err(e) => {
+                drop(unsafe { OwnedFd::from_raw_fd(raw_fd) });
+                return Err(e).context("无法导入 DMA-BUF");
+            }
+        };
+        if let Err(e) = unsafe { self.raw.bind_image_memory(image, memory, 0) } {
+            unsafe { self.raw.free_memory(memory, None) };
+            return Err(e).context("无法绑定 DMA-BUF 内存");
+        }
+        Ok(memory)
+    }
+}
+
+/// 创建打开了 [`EXTENSIONS`] 的 Vulkan 设备
+fn open_device(
+    adapter: &hal::vulkan::Adapter,
+) -> anyhow::Result<(
+    hal::OpenDevice<hal::api::Vulkan>,
+    ash::Device,
+    khr::external_memory_fd::Device,
+)> {
+    let capabilities = adapter.physical_device_capabilities();
+    if let Some(missing) = EXTENSIONS
+        .iter()
+        .find(|extension| !capabilities.supports_extension(extension))
+    {
+        bail!("Vulkan 设备不支持 {}", missing.to_string_lossy());
+    }
+    let features = wgpu::Features::empty();
+    let mut extensions = adapter.required_device_extensions(features);
+    for extension in EXTENSIONS {
+        if !extensions.contains(&extension) {
+            extensions.push(extension);
+        }
+    }
+    let mut enabled = adapter.physical_device_features(&extensions, features);
+    let names: Vec<_> = extensions.iter().map(|name| name.as_ptr()).collect();
+    // 和 wgpu 一样使用第一个队列族
+    let priorities = [1.0];
+    let queues = [vk::DeviceQueueCreateInfo::default()
+        .queue_family_index(0)
+        .queue_priorities(&priorities)];
+    let info = enabled.add_to_device_create(
+        vk::DeviceCreateInfo::default()
+            .queue_create_infos(&queues)
+            .enabled_extension_names(&names),
+    );
+    let instance = adapter.shared_instance().raw_instance();
+    let raw = unsafe { instance.create_device(adapter.raw_physical_device(), &info, None) }
+        .context("无法创建 Vulkan 设备")?;
+    let memory_fd = khr::external_memory_fd::Device::new(instance, &raw);
+    let open = unsafe {
+        adapter.device_from_raw(
+            raw.clone(),
+            None,
+            &extensions,
+            features,
+            &wgpu::MemoryHints::MemoryUsage,
+            0,
+            0,
+        )
+    }?;
+    Ok((open, raw, memory_fd))
+}
+
+/// 一个图形的顶点数据, 见 `gpu.wgsl`
+fn instance(shape: &Shape) -> [f32; 16] {
+    let premultiplied = |color: Color| {
+        let alpha = color.a as f32 / 255.0;
+        [
+            color.r as f32 / 255.0 * alpha,
+            color.g as f32 / 255.0 * alpha,
+            color.b as f32 / 255.0 * alpha,
+            alpha,
+        ]
+    };
+    let (bounds, shape, arc, color) = match *shape {
+        Shape::Rect {
+            x,
+            y,
+            width,
+            height,
+            color,
+        } => (
+            [
+                x as f32,
+                y as f32,
+                x as f32 + width as f32,
+                y as f32 + height as f32,
+            ],
+            [0.0; 4],
+            [0.0; 4],
+            color,
+        ),
+        Shape::Circle {
+            center: (cx, cy),
+            radius,
+            color,
+        } => {
+            let extent = radius + 1.0;
+            (
+                [cx - extent, cy - extent, cx + extent, cy + extent],
+                [1.0, cx, cy, radius],
+                [0.0; 4],
+                color,
+            )
+        }
+        Shape::Arc {
+            center: (cx, cy),
+            radius,
+            width,
+            angles: (start, sweep),
+            color,
+        } => {
+            let extent = radius + width / 2.0 + 1.0;
+            (
+                [cx - extent, cy - extent, cx + extent, cy + extent],
+                [2.0, cx, cy, radius],
+                [width / 2.0, start, sweep, 0.0],
+                color,
+            )
+        }
+    };
+    let mut data = [0.0; 16];
+    for (chunk, values) in data
+        .chunks_exact_mut(4)
+        .zip([bounds, shape, arc, premultiplied(color)])
+    {
+        chunk.copy_from_slice(&values);
+    }
+    data
+}
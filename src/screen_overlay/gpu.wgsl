@@ -0,0 +1,72 @@
+// HUD 图形和文字层, 输出预乘 alpha 的颜色. 覆盖率的算法和 canvas.rs 中的软件绘制相同
+
+const TAU: f32 = 6.28318530718;
+
+struct Viewport {
+    // 目标的像素尺寸, 后两项不使用
+    size: vec4<f32>,
+}
+
+@group(0) @binding(0) var<uniform> viewport: Viewport;
+@group(0) @binding(1) var text_layer: texture_2d<f32>;
+
+struct ShapeOut {
+    @builtin(position) position: vec4<f32>,
+    // x 为种类: 0 矩形, 1 圆, 2 圆弧; yz 为圆心; w 为半径
+    @location(0) @interpolate(flat) shape: vec4<f32>,
+    // 圆弧的半线宽、起始角度和扫过的角度
+    @location(1) @interpolate(flat) arc: vec4<f32>,
+    @location(2) @interpolate(flat) color: vec4<f32>,
+}
+
+// 每个图形画一个覆盖 bounds 的四边形(三角形带的 4 个顶点)
+@vertex
+fn shape_vertex(
+    @builtin(vertex_index) index: u32,
+    @location(0) bounds: vec4<f32>,
+    @location(1) shape: vec4<f32>,
+    @location(2) arc: vec4<f32>,
+    @location(3) color: vec4<f32>,
+) -> ShapeOut {
+    let corner = vec2<f32>(f32(index & 1u), f32((index >> 1u) & 1u));
+    let pixel = mix(bounds.xy, bounds.zw, corner);
+    let ndc = pixel / viewport.size.xy * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0);
+    var out: ShapeOut;
+    out.position = vec4<f32>(ndc, 0.0, 1.0);
+    out.shape = shape;
+    out.arc = arc;
+    out.color = color;
+    return out;
+}
+
+@fragment
+fn shape_fragment(in: ShapeOut) -> @location(0) vec4<f32> {
+    // position.xy 是像素中心, 和软件绘制的 (px + 0.5, py + 0.5) 相同
+    let offset = in.position.xy - in.shape.yz;
+    var coverage = 1.0;
+    let kind = u32(in.shape.x);
+    if kind == 1u {
+        coverage = clamp(in.shape.w + 0.5 - length(offset), 0.0, 1.0);
+    } else if kind == 2u {
+        coverage = clamp(in.arc.x + 0.5 - abs(length(offset) - in.shape.w), 0.0, 1.0);
+        // 从正上方顺时针的角度
+        let angle = atan2(offset.x, -offset.y) - in.arc.y;
+        if in.arc.z < TAU && angle - TAU * floor(angle / TAU) > in.arc.z {
+            coverage = 0.0;
+        }
+    }
+    return in.color * coverage;
+}
+
+// 覆盖整个目标的三角形
+@vertex
+fn text_vertex(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+// 文字层由 CPU 绘制, 已经是预乘 alpha
+@fragment
+fn text_fragment(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    return textureLoad(text_layer, vec2<i32>(position.xy), 0);
+}
@@ -0,0 +1,864 @@
+use std::{f32::consts::TAU, time::Instant};
+
+use crate::mapping::{
+    OutputGeometry,
+    calibration::{CALIBRATION_TARGETS, CalibrationView},
+};
+
+use crate::hud_interface::{
+    diagnostics::DiagnosticsPanel,
+    dial::Dial,
+    link::{LinkStatus, RemoteLinks},
+    mapping_overlay::MappingOverlay,
+    notification::NotificationLevel,
+    osd::{OsdIcon, OsdSlot},
+    progress::{ProgressBoard, ProgressState},
+    quick_menu::{self, QuickMenu},
+    toast::ToastQueue,
+    wheel_ring::WheelRing,
+};
+
+use super::canvas::{Color, Painter, TextRun};
+
+/// 提示框的尺寸，逻辑像素
+const TOAST_WIDTH: f32 = 300.0;
+const TOAST_HEIGHT: f32 = 60.0;
+const TOAST_MARGIN: f32 = 16.0;
+const TOAST_SPACING: f32 = 8.0;
+/// 左侧色条的宽度
+const TOAST_ACCENT: f32 = 4.0;
+/// 淡入时从右侧滑入的距离
+const TOAST_SLIDE: f32 = 40.0;
+
+const TOAST_BACKGROUND: Color = Color::rgba(0x20, 0x20, 0x28, 0xd8);
+const TOAST_TITLE: Color = Color::rgb(0xff, 0xff, 0xff);
+const TOAST_DETAIL: Color = Color::rgb(0xb0, 0xb0, 0xc0);
+
+/// 光标进入这个距离(逻辑像素)后 HUD 元素开始变淡
+const AVOID_RADIUS: f32 = 48.0;
+/// 光标压在 HUD 元素上时的不透明度
+const AVOID_MIN_OPACITY: f32 = 0.15;
+
+/// 根据光标到矩形的距离计算 HUD 元素的不透明度系数
+///
+/// 光标越近越透明，让用户看得到笔下的内容. 距离是连续变化的，所以不会闪烁
+fn avoid_cursors(rect: (f32, f32, f32, f32), cursors: &[(f32, f32)], scale: f32) -> f32 {
+    let (x, y, width, height) = rect;
+    let radius = AVOID_RADIUS * scale;
+    cursors
+        .iter()
+        .map(|&(cx, cy)| {
+            let dx = (x - cx).max(cx - (x + width)).max(0.0);
+            let dy = (y - cy).max(cy - (y + height)).max(0.0);
+            let t = (dx.hypot(dy) / radius).min(1.0);
+            // smoothstep
+            let t = t * t * (3.0 - 2.0 * t);
+            AVOID_MIN_OPACITY + (1.0 - AVOID_MIN_OPACITY) * t
+        })
+        .fold(1.0, f32::min)
+}
+
+fn accent(level: NotificationLevel) -> Color {
+    match level {
+        NotificationLevel::Info => Color::rgb(0x66, 0xcc, 0xff),
+        NotificationLevel::Warning => Color::rgb(0xff, 0xcc, 0x22),
+        NotificationLevel::Error => Color::rgb(0xff, 0x55, 0x66),
+    }
+}
+
+/// 在画布右上角绘制提示, 返回需要绘制的文字
+///
+/// `cursors` 是这个画布上光标的像素位置, 光标附近的提示会变淡并向右让开
+pub fn render_toasts(
+    toasts: &ToastQueue,
+    now: Instant,
+    cursors: &[(f32, f32)],
+    canvas: &mut impl Painter,
+) -> Vec<TextRun> {
+    let scale = canvas.scale() as f32;
+    let mut text = Vec::new();
+    for visible in toasts.visible(now) {
+        let y = (TOAST_MARGIN + visible.slot as f32 * (TOAST_HEIGHT + TOAST_SPACING)) * scale;
+        let (width, height) = (TOAST_WIDTH * scale, TOAST_HEIGHT * scale);
+        // 按没有滑动时的位置判断，避免让开之后又滑回来
+        let resting_x = canvas.width() as f32 - (TOAST_WIDTH + TOAST_MARGIN) * scale;
+        let avoid = avoid_cursors((resting_x, y, width, height), cursors, scale);
+        let opacity = visible.opacity * avoid;
+        // 淡入淡出的同时水平滑动
+        let slide = (1.0 - visible.opacity) * TOAST_SLIDE + (1.0 - avoid) * TOAST_SLIDE;
+        let x = resting_x + slide * scale;
+
+        canvas.fill_rect(
+            x as i32,
+            y as i32,
+            width as u32,
+            height as u32,
+            TOAST_BACKGROUND.with_alpha(opacity),
+        );
+        canvas.fill_rect(
+            x as i32,
+            y as i32,
+            (TOAST_ACCENT * scale) as u32,
+            height as u32,
+            accent(visible.toast.level).with_alpha(opacity),
+        );
+
+        let text_x = x + (TOAST_ACCENT + 12.0) * scale;
+        text.push(TextRun {
+            text: visible.toast.title.clone(),
+            x: text_x,
+            y: y + 10.0 * scale,
+            size: 16.0 * scale,
+            color: TOAST_TITLE.with_alpha(opacity),
+        });
+        text.push(TextRun {
+            text: visible.toast.detail.clone(),
+            x: text_x,
+            y: y + 34.0 * scale,
+            size: 13.0 * scale,
+            color: TOAST_DETAIL.with_alpha(opacity),
+        });
+    }
+    text
+}
+
+/// OSD 的尺寸，逻辑像素
+const OSD_WIDTH: f32 = 260.0;
+const OSD_HEIGHT: f32 = 72.0;
+/// 距离屏幕底部的距离
+const OSD_BOTTOM: f32 = 96.0;
+const OSD_BAR_HEIGHT: f32 = 6.0;
+const OSD_BAR_TRACK: Color = Color::rgba(0xff, 0xff, 0xff, 0x40);
+const OSD_BAR_FILL: Color = Color::rgb(0x66, 0xcc, 0xff);
+
+fn osd_glyph(icon: OsdIcon) -> &'static str {
+    match icon {
+        OsdIcon::Volume => "🔊",
+        OsdIcon::ZoomIn => "＋",
+        OsdIcon::ZoomOut => "－",
+        OsdIcon::RotateLeft => "↺",
+        OsdIcon::RotateRight => "↻",
+        OsdIcon::Scroll => "↕",
+        OsdIcon::Brush => "●",
+        OsdIcon::Wheel => "◎",
+    }
+}
+
+/// 在画布中下方绘制 OSD, 返回需要绘制的文字
+///
+/// 光标靠近时 OSD 变淡
+pub fn render_osd(
+    osd: &OsdSlot,
+    now: Instant,
+    cursors: &[(f32, f32)],
+    canvas: &mut impl Painter,
+) -> Vec<TextRun> {
+    let Some((osd, opacity)) = osd.visible(now) else {
+        return Vec::new();
+    };
+    let scale = canvas.scale() as f32;
+    let (width, height) = (OSD_WIDTH * scale, OSD_HEIGHT * scale);
+    let x = (canvas.width() as f32 - width) / 2.0;
+    let y = canvas.height() as f32 - (OSD_BOTTOM + OSD_HEIGHT) * scale;
+    let opacity = opacity * avoid_cursors((x, y, width, height), cursors, scale);
+    canvas.fill_rect(
+        x as i32,
+        y as i32,
+        width as u32,
+        height as u32,
+        TOAST_BACKGROUND.with_alpha(opacity),
+    );
+
+    let padding = 16.0 * scale;
+    let mut text = vec![
+        TextRun {
+            text: osd_glyph(osd.icon).to_string(),
+            x: x + padding,
+            y: y + 14.0 * scale,
+            size: 22.0 * scale,
+            color: TOAST_TITLE.with_alpha(opacity),
+        },
+        TextRun {
+            text: osd.label.clone(),
+            x: x + padding + 36.0 * scale,
+            y: y + 16.0 * scale,
+            size: 16.0 * scale,
+            color: TOAST_TITLE.with_alpha(opacity),
+        },
+    ];
+    if let Some(level) = osd.level {
+        let bar_x = x + padding;
+        let bar_y = y + height - padding - OSD_BAR_HEIGHT * scale;
+        let bar_width = width - padding * 2.0;
+        let bar_height = (OSD_BAR_HEIGHT * scale) as u32;
+        canvas.fill_rect(
+            bar_x as i32,
+            bar_y as i32,
+            bar_width as u32,
+            bar_height,
+            OSD_BAR_TRACK.with_alpha(opacity),
+        );
+        canvas.fill_rect(
+            bar_x as i32,
+            bar_y as i32,
+            (bar_width * level) as u32,
+            bar_height,
+            OSD_BAR_FILL.with_alpha(opacity),
+        );
+    } else {
+        text[1].y = y + (OSD_HEIGHT - 16.0) / 2.0 * scale;
+        text[0].y = y + (OSD_HEIGHT - 22.0) / 2.0 * scale;
+    }
+    text
+}
+
+/// 转盘的尺寸，逻辑像素
+const DIAL_RADIUS: f32 = 64.0;
+const DIAL_TRACK_WIDTH: f32 = 8.0;
+/// 表示手指位置的圆点半径
+const DIAL_KNOB_RADIUS: f32 = 9.0;
+
+/// 在 OSD 的位置绘制触控环转盘, 返回需要绘制的文字
+///
+/// 圆点跟随手指在触控环上的位置; 正在调整的值(比如音量)取自当前的 OSD,
+/// 有数值时画在内圈. 转盘显示时 OSD 的内容已经包含在转盘中，不需要再调用 [`render_osd`]
+pub fn render_dial(
+    dial: &Dial,
+    osd: &OsdSlot,
+    now: Instant,
+    cursors: &[(f32, f32)],
+    canvas: &mut impl Painter,
+) -> Vec<TextRun> {
+    let Some((position, opacity)) = dial.visible(now) else {
+        return Vec::new();
+    };
+    let scale = canvas.scale() as f32;
+    let radius = DIAL_RADIUS * scale;
+    let center = (
+        canvas.width() as f32 / 2.0,
+        canvas.height() as f32 - (OSD_BOTTOM * scale + radius),
+    );
+    let rect = (
+        center.0 - radius,
+        center.1 - radius,
+        radius * 2.0,
+        radius * 2.0,
+    );
+    let opacity = opacity * avoid_cursors(rect, cursors, scale);
+
+    canvas.fill_circle(
+        center.0,
+        center.1,
+        radius,
+        TOAST_BACKGROUND.with_alpha(opacity),
+    );
+    let track = radius - (DIAL_KNOB_RADIUS + 4.0) * scale;
+    let width = DIAL_TRACK_WIDTH * scale;
+    canvas.stroke_arc(
+        center,
+        track,
+        width,
+        (0.0, TAU),
+        OSD_BAR_TRACK.with_alpha(opacity),
+    );
+
+    let osd = osd.visible(now).map(|(osd, _)| osd);
+    // 数值画成内圈的圆弧，和外圈的手指位置区分开
+    if let Some(level) = osd.and_then(|osd| osd.level) {
+        canvas.stroke_arc(
+            center,
+            track - width * 1.5,
+            width / 2.0,
+            (0.0, level * TAU),
+            OSD_BAR_FILL.with_alpha(opacity),
+        );
+    }
+    let angle = position * TAU;
+    canvas.fill_circle(
+        center.0 + track * angle.sin(),
+        center.1 - track * angle.cos(),
+        DIAL_KNOB_RADIUS * scale,
+        OSD_BAR_FILL.with_alpha(opacity),
+    );
+
+    let Some(osd) = osd else {
+        return Vec::new();
+    };
+    let size = 14.0 * scale;
+    vec![
+        TextRun {
+            text: osd_glyph(osd.icon).to_string(),
+            x: center.0 - 11.0 * scale,
+            y: center.1 - 28.0 * scale,
+            size: 22.0 * scale,
+            color: TOAST_TITLE.with_alpha(opacity),
+        },
+        TextRun {
+            text: osd.label.clone(),
+            // 没有文字排版，按字号粗略估计宽度来居中
+            x: center.0 - osd.label.chars().count() as f32 * size / 2.0,
+            y: center.1 + 2.0 * scale,
+            size,
+            color: TOAST_TITLE.with_alpha(opacity),
+        },
+    ]
+}
+
+/// 滚轮转盘上每一格转过的角度
+const WHEEL_STEP_ANGLE: f32 = TAU / 24.0;
+
+/// 在 OSD 的位置绘制滚轮转盘, 返回需要绘制的文字
+///
+/// 圆点随滚轮的每一格转动, 动作执行后的值(比如音量)画在内圈，没有值时显示这次转了多少格.
+/// 转盘显示时不需要再调用 [`render_osd`]; 手指在触控环上时由 [`render_dial`] 代替
+pub fn render_wheel_ring(
+    ring: &WheelRing,
+    now: Instant,
+    cursors: &[(f32, f32)],
+    canvas: &mut impl Painter,
+) -> Vec<TextRun> {
+    let Some((spin, opacity)) = ring.visible(now) else {
+        return Vec::new();
+    };
+    let scale = canvas.scale() as f32;
+    let radius = DIAL_RADIUS * scale;
+    let center = (
+        canvas.width() as f32 / 2.0,
+        canvas.height() as f32 - (OSD_BOTTOM * scale + radius),
+    );
+    let rect = (
+        center.0 - radius,
+        center.1 - radius,
+        radius * 2.0,
+        radius * 2.0,
+    );
+    let opacity = opacity * avoid_cursors(rect, cursors, scale);
+
+    canvas.fill_circle(
+        center.0,
+        center.1,
+        radius,
+        TOAST_BACKGROUND.with_alpha(opacity),
+    );
+    let track = radius - (DIAL_KNOB_RADIUS + 4.0) * scale;
+    let width = DIAL_TRACK_WIDTH * scale;
+    canvas.stroke_arc(
+        center,
+        track,
+        width,
+        (0.0, TAU),
+        OSD_BAR_TRACK.with_alpha(opacity),
+    );
+    if let Some(level) = spin.osd.level {
+        canvas.stroke_arc(
+            center,
+            track - width * 1.5,
+            width / 2.0,
+            (0.0, level * TAU),
+            OSD_BAR_FILL.with_alpha(opacity),
+        );
+    }
+    let angle = spin.steps as f32 * WHEEL_STEP_ANGLE;
+    canvas.fill_circle(
+        center.0 + track * angle.sin(),
+        center.1 - track * angle.cos(),
+        DIAL_KNOB_RADIUS * scale,
+        OSD_BAR_FILL.with_alpha(opacity),
+    );
+
+    let label = match spin.osd.level {
+        Some(_) => spin.osd.label.clone(),
+        None => format!("{} {:+}", spin.osd.label, spin.steps),
+    };
+    let size = 14.0 * scale;
+    vec![
+        TextRun {
+            text: osd_glyph(spin.osd.icon).to_string(),
+            x: center.0 - 11.0 * scale,
+            y: center.1 - 28.0 * scale,
+            size: 22.0 * scale,
+            color: TOAST_TITLE.with_alpha(opacity),
+        },
+        TextRun {
+            // 没有文字排版，按字号粗略估计宽度来居中
+            x: center.0 - label.chars().count() as f32 * size / 2.0,
+            text: label,
+            y: center.1 + 2.0 * scale,
+            size,
+            color: TOAST_TITLE.with_alpha(opacity),
+        },
+    ]
+}
+
+/// 连接状态指示的尺寸，逻辑像素
+const LINK_HEIGHT: f32 = 28.0;
+const LINK_WIDTH: f32 = 220.0;
+const LINK_MARGIN: f32 = 16.0;
+const LINK_DOT_RADIUS: f32 = 5.0;
+
+fn link_color(status: LinkStatus) -> Color {
+    match status {
+        LinkStatus::Connected => Color::rgb(0x44, 0xdd, 0x88),
+        LinkStatus::Reconnecting => accent(NotificationLevel::Warning),
+        LinkStatus::Offline => accent(NotificationLevel::Error),
+    }
+}
+
+/// 在画布左上角为每个远程连接绘制一行状态, 返回需要绘制的文字
+///
+/// 正在重连时圆点闪烁. 光标靠近时变淡
+pub fn render_links(
+    links: &RemoteLinks,
+    now: Instant,
+    cursors: &[(f32, f32)],
+    canvas: &mut impl Painter,
+) -> Vec<TextRun> {
+    let scale = canvas.scale() as f32;
+    let mut text = Vec::new();
+    for (row, (server, status, since)) in links.iter().enumerate() {
+        let x = LINK_MARGIN * scale;
+        let y = (LINK_MARGIN + row as f32 * (LINK_HEIGHT + TOAST_SPACING)) * scale;
+        let (width, height) = (LINK_WIDTH * scale, LINK_HEIGHT * scale);
+        let opacity = avoid_cursors((x, y, width, height), cursors, scale);
+        canvas.fill_rect(
+            x as i32,
+            y as i32,
+            width as u32,
+            height as u32,
+            TOAST_BACKGROUND.with_alpha(opacity),
+        );
+
+        let blink = match status {
+            LinkStatus::Reconnecting => {
+                let phase = now.saturating_duration_since(since).as_secs_f32();
+                0.4 + 0.6 * (phase * TAU).cos().abs()
+            }
+            _ => 1.0,
+        };
+        canvas.fill_circle(
+            x + height / 2.0,
+            y + height / 2.0,
+            LINK_DOT_RADIUS * scale,
+            link_color(status).with_alpha(opacity * blink),
+        );
+        text.push(TextRun {
+            text: format!("{server} · {}", status.label()),
+            x: x + height,
+            y: y + (LINK_HEIGHT - 13.0) / 2.0 * scale,
+            size: 13.0 * scale,
+            color: TOAST_DETAIL.with_alpha(opacity),
+        });
+    }
+    text
+}
+
+/// 进度条目的尺寸，逻辑像素
+const PROGRESS_WIDTH: f32 = 280.0;
+const PROGRESS_HEIGHT: f32 = 56.0;
+const PROGRESS_MARGIN: f32 = 16.0;
+/// 不确定进度时来回移动的色块占进度条的比例
+const PROGRESS_INDETERMINATE: f32 = 0.3;
+/// 不确定进度的色块来回一次的时间, 秒
+const PROGRESS_PERIOD: f32 = 1.6;
+
+fn progress_color(state: ProgressState) -> Color {
+    match state {
+        ProgressState::Running(_) | ProgressState::Cancelled => OSD_BAR_FILL,
+        ProgressState::Succeeded => link_color(LinkStatus::Connected),
+        ProgressState::Failed => accent(NotificationLevel::Error),
+    }
+}
+
+/// 在画布左下角从下往上绘制耗时操作的进度, 返回需要绘制的文字
+///
+/// 不知道进度时色块在进度条上来回移动. 光标靠近时变淡
+pub fn render_progress(
+    board: &ProgressBoard,
+    now: Instant,
+    cursors: &[(f32, f32)],
+    canvas: &mut impl Painter,
+) -> Vec<TextRun> {
+    let scale = canvas.scale() as f32;
+    let mut text = Vec::new();
+    for visible in board.visible(now) {
+        let (width, height) = (PROGRESS_WIDTH * scale, PROGRESS_HEIGHT * scale);
+        let x = PROGRESS_MARGIN * scale;
+        let y = canvas.height() as f32
+            - (PROGRESS_MARGIN + (visible.slot + 1) as f32 * (PROGRESS_HEIGHT + TOAST_SPACING))
+                * scale;
+        let opacity = visible.opacity * avoid_cursors((x, y, width, height), cursors, scale);
+        canvas.fill_rect(
+            x as i32,
+            y as i32,
+            width as u32,
+            height as u32,
+            TOAST_BACKGROUND.with_alpha(opacity),
+        );
+
+        let padding = 12.0 * scale;
+        let bar_x = x + padding;
+        let bar_y = y + height - padding - OSD_BAR_HEIGHT * scale;
+        let bar_width = width - padding * 2.0;
+        let bar_height = (OSD_BAR_HEIGHT * scale) as u32;
+        canvas.fill_rect(
+            bar_x as i32,
+            bar_y as i32,
+            bar_width as u32,
+            bar_height,
+            OSD_BAR_TRACK.with_alpha(opacity),
+        );
+        let (start, length) = match visible.update.state {
+            ProgressState::Running(Some(fraction)) => (0.0, fraction),
+            ProgressState::Running(None) => {
+                let phase = now.saturating_duration_since(visible.started).as_secs_f32()
+                    / PROGRESS_PERIOD
+                    * TAU;
+                // 0 ~ 1 之间来回
+                let t = 0.5 - 0.5 * phase.cos();
+                (t * (1.0 - PROGRESS_INDETERMINATE), PROGRESS_INDETERMINATE)
+            }
+            _ => (0.0, 1.0),
+        };
+        canvas.fill_rect(
+            (bar_x + bar_width * start) as i32,
+            bar_y as i32,
+            (bar_width * length) as u32,
+            bar_height,
+            progress_color(visible.update.state).with_alpha(opacity),
+        );
+
+        text.push(TextRun {
+            text: visible.update.title.clone(),
+            x: x + padding,
+            y: y + 8.0 * scale,
+            size: 14.0 * scale,
+            color: TOAST_TITLE.with_alpha(opacity),
+        });
+        text.push(TextRun {
+            text: visible.update.detail.clone(),
+            x: x + padding,
+            y: y + 26.0 * scale,
+            size: 12.0 * scale,
+            color: TOAST_DETAIL.with_alpha(opacity),
+        });
+    }
+    text
+}
+
+/// 诊断面板每行的尺寸，逻辑像素
+const DIAGNOSTICS_WIDTH: f32 = 340.0;
+const DIAGNOSTICS_HEIGHT: f32 = 48.0;
+const DIAGNOSTICS_MARGIN: f32 = 16.0;
+
+/// 在画布右下角从下往上为每块数位板绘制一行报告统计, 返回需要绘制的文字
+///
+/// 出现过断档的数位板用警告色标出. 光标靠近时变淡
+pub fn render_diagnostics(
+    panel: &DiagnosticsPanel,
+    cursors: &[(f32, f32)],
+    canvas: &mut impl Painter,
+) -> Vec<TextRun> {
+    if !panel.is_visible() {
+        return Vec::new();
+    }
+    let scale = canvas.scale() as f32;
+    let mut text = Vec::new();
+    for (row, stats) in panel.rows().iter().rev().enumerate() {
+        let (width, height) = (DIAGNOSTICS_WIDTH * scale, DIAGNOSTICS_HEIGHT * scale);
+        let x = canvas.width() as f32 - (DIAGNOSTICS_WIDTH + DIAGNOSTICS_MARGIN) * scale;
+        let y = canvas.height() as f32
+            - (DIAGNOSTICS_MARGIN + (row + 1) as f32 * (DIAGNOSTICS_HEIGHT + TOAST_SPACING))
+                * scale;
+        let opacity = avoid_cursors((x, y, width, height), cursors, scale);
+        canvas.fill_rect(
+            x as i32,
+            y as i32,
+            width as u32,
+            height as u32,
+            TOAST_BACKGROUND.with_alpha(opacity),
+        );
+        let level = if stats.gaps > 0 {
+            NotificationLevel::Warning
+        } else {
+            NotificationLevel::Info
+        };
+        canvas.fill_rect(
+            x as i32,
+            y as i32,
+            (TOAST_ACCENT * scale) as u32,
+            height as u32,
+            accent(level).with_alpha(opacity),
+        );
+
+        let text_x = x + (TOAST_ACCENT + 12.0) * scale;
+        text.push(TextRun {
+            text: format!("{} · {}", stats.tablet, stats.name),
+            x: text_x,
+            y: y + 7.0 * scale,
+            size: 13.0 * scale,
+            color: TOAST_TITLE.with_alpha(opacity),
+        });
+        text.push(TextRun {
+            text: format!(
+                "{:.0} Hz · 抖动 {:.2} ms · 断档 {} · 重连 {}",
+                stats.rate_hz, stats.jitter_ms, stats.gaps, stats.resets
+            ),
+            x: text_x,
+            y: y + 26.0 * scale,
+            size: 12.0 * scale,
+            color: TOAST_DETAIL.with_alpha(opacity),
+        });
+    }
+    text
+}
+
+/// 快捷菜单圆环的内外半径，逻辑像素
+const MENU_INNER_RADIUS: f32 = 40.0;
+const MENU_OUTER_RADIUS: f32 = 120.0;
+const MENU_SELECTED: Color = Color::rgba(0x66, 0xcc, 0xff, 0x90);
+
+/// 以打开时的光标位置为中心绘制快捷菜单, 返回需要绘制的文字
+///
+/// 菜单只画在打开它的显示器上(`output` 是这个画布对应的显示器). 菜单就在笔下，不避让光标
+pub fn render_quick_menu(
+    menu: &QuickMenu,
+    output: &str,
+    canvas: &mut impl Painter,
+) -> Vec<TextRun> {
+    if menu.center.output != output || menu.labels.is_empty() {
+        return Vec::new();
+    }
+    let scale = canvas.scale() as f32;
+    let center = (menu.center.x as f32, menu.center.y as f32);
+    let (inner, outer) = (MENU_INNER_RADIUS * scale, MENU_OUTER_RADIUS * scale);
+    let ring = (inner + outer) / 2.0;
+    let width = outer - inner;
+    let step = TAU / menu.labels.len() as f32;
+
+    canvas.stroke_arc(center, ring, width, (0.0, TAU), TOAST_BACKGROUND);
+    if let Some(selected) = menu.selected {
+        canvas.stroke_arc(
+            center,
+            ring,
+            width,
+            (selected as f32 * step - step / 2.0, step),
+            MENU_SELECTED,
+        );
+    }
+    // 中心是不选中任何一项的区域
+    canvas.fill_circle(
+        center.0,
+        center.1,
+        quick_menu::DEAD_ZONE as f32 * scale,
+        OSD_BAR_TRACK,
+    );
+    if menu.labels.len() > 1 {
+        for index in 0..menu.labels.len() {
+            let angle = index as f32 * step - step / 2.0;
+            canvas.stroke_segment(
+                (
+                    center.0 + inner * angle.sin(),
+                    center.1 - inner * angle.cos(),
+                ),
+                (
+                    center.0 + outer * angle.sin(),
+                    center.1 - outer * angle.cos(),
+                ),
+                (scale, scale),
+                OSD_BAR_TRACK,
+            );
+        }
+    }
+
+    let size = 13.0 * scale;
+    menu.labels
+        .iter()
+        .enumerate()
+        .map(|(index, label)| {
+            let angle = index as f32 * step;
+            let color = if menu.selected == Some(index) {
+                TOAST_TITLE
+            } else {
+                TOAST_DETAIL
+            };
+            TextRun {
+                text: label.clone(),
+                // 没有文字排版，按字号粗略估计宽度来居中
+                x: center.0 + ring * angle.sin() - label.chars().count() as f32 * size / 2.0,
+                y: center.1 - ring * angle.cos() - size / 2.0,
+                size,
+                color,
+            }
+        })
+        .collect()
+}
+
+/// 映射区域边框和十字线的线宽，逻辑像素
+const MAPPING_BORDER: f32 = 2.0;
+const MAPPING_CROSSHAIR: f32 = 1.0;
+const MAPPING_PEN_RADIUS: f32 = 4.0;
+/// 按数位板 ID 轮流使用的颜色
+const MAPPING_COLORS: [Color; 4] = [
+    Color::rgb(0x66, 0xcc, 0xff),
+    Color::rgb(0xff, 0xb0, 0x40),
+    Color::rgb(0x80, 0xe0, 0x80),
+    Color::rgb(0xe0, 0x80, 0xe0),
+];
+
+/// 画出矩形在画布内的部分
+fn fill_clipped(canvas: &mut impl Painter, rect: (f32, f32, f32, f32), color: Color) {
+    let (width, height) = (canvas.width() as f32, canvas.height() as f32);
+    let (left, top) = (rect.0.max(0.0), rect.1.max(0.0));
+    let (right, bottom) = ((rect.0 + rect.2).min(width), (rect.1 + rect.3).min(height));
+    if right > left && bottom > top {
+        canvas.fill_rect(
+            left as i32,
+            top as i32,
+            (right - left).ceil() as u32,
+            (bottom - top).ceil() as u32,
+            color,
+        );
+    }
+}
+
+/// 绘制每块数位板的映射区域和笔位置的十字线在 `output` 上的部分, 返回需要绘制的文字
+///
+/// 所有显示器用同一个逻辑坐标系, 所以区域的边框和十字线在相邻的显示器之间是连续的.
+/// 区域的左上角在这个显示器上时, 在那里标出数位板和区域的逻辑尺寸
+pub fn render_mapping_overlay(
+    overlay: &MappingOverlay,
+    output: &OutputGeometry,
+    canvas: &mut impl Painter,
+) -> Vec<TextRun> {
+    if !overlay.is_visible() {
+        return Vec::new();
+    }
+    let scale = canvas.scale() as f32;
+    let (width, height) = (canvas.width() as f32, canvas.height() as f32);
+    let mut text = Vec::new();
+    for (tablet, mapping) in overlay.tablets() {
+        let color = MAPPING_COLORS[tablet.0 as usize % MAPPING_COLORS.len()];
+        if let Some(target) = mapping.target {
+            let (x0, y0) = output.to_pixels(target.x, target.y);
+            let (x1, y1) = output.to_pixels(target.x + target.width, target.y + target.height);
+            let (x0, y0, x1, y1) = (x0 as f32, y0 as f32, x1 as f32, y1 as f32);
+            let border = MAPPING_BORDER * scale;
+            fill_clipped(canvas, (x0, y0, x1 - x0, y1 - y0), color.with_alpha(0.1));
+            fill_clipped(canvas, (x0, y0, x1 - x0, border), color);
+            fill_clipped(canvas, (x0, y1 - border, x1 - x0, border), color);
+            fill_clipped(canvas, (x0, y0, border, y1 - y0), color);
+            fill_clipped(canvas, (x1 - border, y0, border, y1 - y0), color);
+            if (0.0..width).contains(&x0) && (0.0..height).contains(&y0) {
+                text.push(TextRun {
+                    text: format!("{tablet} · {:.0}×{:.0}", target.width, target.height),
+                    x: x0 + 8.0 * scale,
+                    y: y0 + 6.0 * scale,
+                    size: 13.0 * scale,
+                    color,
+                });
+            }
+        }
+        if let Some((x, y)) = mapping.pen {
+            let (px, py) = output.to_pixels(x, y);
+            let (px, py) = (px as f32, py as f32);
+            let line = MAPPING_CROSSHAIR * scale;
+            let crosshair = color.with_alpha(0.8);
+            fill_clipped(canvas, (0.0, py - line / 2.0, width, line), crosshair);
+            fill_clipped(canvas, (px - line / 2.0, 0.0, line, height), crosshair);
+            if (0.0..width).contains(&px) && (0.0..height).contains(&py) {
+                canvas.fill_circle(px, py, MAPPING_PEN_RADIUS * scale, color);
+            }
+        }
+    }
+    text
+}
+
+/// 校准目标点的尺寸, 逻辑像素
+const CALIBRATION_RADIUS: f32 = 14.0;
+const CALIBRATION_LINE: f32 = 2.0;
+const CALIBRATION_ARM: f32 = 28.0;
+const CALIBRATION_DIM: Color = Color::rgba(0x00, 0x00, 0x00, 0x60);
+const CALIBRATION_TARGET: Color = Color::rgb(0xff, 0x50, 0x50);
+const CALIBRATION_TEXT: Color = Color::rgb(0xff, 0xff, 0xff);
+
+/// 校准时调暗映射区域, 在下一个目标点上画出靶心, 返回需要绘制的提示文字
+///
+/// 目标点不在 `output` 上时只调暗区域在这个显示器上的部分
+pub fn render_calibration(
+    calibration: &CalibrationView,
+    output: &OutputGeometry,
+    canvas: &mut impl Painter,
+) -> Vec<TextRun> {
+    let Some(progress) = calibration.progress() else {
+        return Vec::new();
+    };
+    let Some(target) = progress.target else {
+        return Vec::new();
+    };
+    let scale = canvas.scale() as f32;
+    let (width, height) = (canvas.width() as f32, canvas.height() as f32);
+    let (x0, y0) = output.to_pixels(target.x, target.y);
+    let (x1, y1) = output.to_pixels(target.x + target.width, target.y + target.height);
+    let (x0, y0, x1, y1) = (x0 as f32, y0 as f32, x1 as f32, y1 as f32);
+    fill_clipped(canvas, (x0, y0, x1 - x0, y1 - y0), CALIBRATION_DIM);
+
+    let Some((tx, ty)) = CALIBRATION_TARGETS.get(progress.step) else {
+        return Vec::new();
+    };
+    let (px, py) = output.to_pixels(target.x + tx * target.width, target.y + ty * target.height);
+    let (px, py) = (px as f32, py as f32);
+    if !(0.0..width).contains(&px) || !(0.0..height).contains(&py) {
+        return Vec::new();
+    }
+    let (line, arm) = (CALIBRATION_LINE * scale, CALIBRATION_ARM * scale);
+    fill_clipped(
+        canvas,
+        (px - arm, py - line / 2.0, arm * 2.0, line),
+        CALIBRATION_TARGET,
+    );
+    fill_clipped(
+        canvas,
+        (px - line / 2.0, py - arm, line, arm * 2.0),
+        CALIBRATION_TARGET,
+    );
+    canvas.stroke_arc(
+        (px, py),
+        CALIBRATION_RADIUS * scale,
+        line,
+        (0.0, TAU),
+        CALIBRATION_TARGET,
+    );
+    canvas.fill_circle(px, py, line * 1.5, CALIBRATION_TARGET);
+
+    // 提示放在目标点靠映射区域中心的一侧, 避免超出屏幕. 这里不排版, 按字符粗略估计宽度
+    let text = format!(
+        "校准 {}: 用笔点击目标 {}/{}",
+        progress.tablet,
+        progress.step + 1,
+        CALIBRATION_TARGETS.len()
+    );
+    let size = 14.0 * scale;
+    let text_width: f32 = text
+        .chars()
+        .map(|c| if c.is_ascii() { 0.55 } else { 1.0 })
+        .sum::<f32>()
+        * size;
+    let offset = arm + 8.0 * scale;
+    let x = if *tx > 0.5 {
+        px - offset - text_width
+    } else {
+        px + offset
+    };
+    let y = if *ty > 0.5 {
+        py - offset - size
+    } else {
+        py + offset
+    };
+    vec![TextRun {
+        text,
+        x,
+        y,
+        size,
+        color: CALIBRATION_TEXT,
+    }]
+}
@@ -0,0 +1,64 @@
+//! overlay 对外使用的显示器和 surface 标识
+//!
+//! Wayland registry 的 `name` 在 `GlobalRemove` 之后可能被混成器重新使用,
+//! 不能直接作为对外的标识. 这里给每个 `name` 加上代数，同一个 `name` 每次重新出现时代数加一,
+//! 旧的标识不会与新的相等
+
+use std::{collections::HashMap, fmt};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+struct RawId {
+    slot: u32,
+    generation: u32,
+}
+
+/// 显示器的标识
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct OutputId(RawId);
+
+/// overlay surface 的标识
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct SurfaceId(RawId);
+
+impl fmt::Display for OutputId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "output-{}.{}", self.0.slot, self.0.generation)
+    }
+}
+
+impl fmt::Display for SurfaceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "surface-{}.{}", self.0.slot, self.0.generation)
+    }
+}
+
+/// 为 registry `name` 分配带代数的标识
+#[derive(Debug, Default)]
+pub(crate) struct Generations {
+    next: HashMap<u32, u32>,
+}
+
+impl Generations {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    fn allocate(&mut self, slot: u32) -> RawId {
+        let next = self.next.entry(slot).or_insert(0);
+        let generation = *next;
+        *next = next.wrapping_add(1);
+        RawId { slot, generation }
+    }
+
+    /// 新出现的显示器
+    pub(crate) fn output(&mut self, name: u32) -> OutputId {
+        OutputId(self.allocate(name))
+    }
+
+    /// 为显示器创建的 surface, 与显示器使用同一个代数
+    pub(crate) fn surface(output: OutputId) -> SurfaceId {
+        SurfaceId(output.0)
+    }
+}
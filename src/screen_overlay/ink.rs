@@ -0,0 +1,179 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{event_model::tablet::TabletId, mapping::OutputGeometry};
+
+use super::canvas::{Color, Painter};
+
+/// 笔画上的一个点，逻辑坐标
+#[derive(Debug, Clone, Copy)]
+struct InkPoint {
+    x: f64,
+    y: f64,
+    /// 逻辑像素
+    width: f32,
+}
+
+#[derive(Debug, Clone)]
+struct Stroke {
+    color: Color,
+    points: Vec<InkPoint>,
+    /// 抬笔的时间，还在画时为 `None`
+    finished_at: Option<Instant>,
+}
+
+/// 画在 overlay 上的墨迹(批注)
+///
+/// 坐标使用所有显示器共用的逻辑坐标系，一条笔画可以跨越多个显示器
+#[derive(Debug, Clone)]
+pub struct InkLayer {
+    strokes: Vec<Stroke>,
+    /// 每块数位板正在画的笔画
+    drawing: HashMap<TabletId, usize>,
+    colors: HashMap<TabletId, Color>,
+    /// 抬笔后多久淡出, `None` 表示一直保留到 [`InkLayer::clear`]
+    lifetime: Option<Duration>,
+    /// 最小和最大压力对应的线宽
+    min_width: f32,
+    max_width: f32,
+}
+
+const DEFAULT_INK: Color = Color::rgb(0xff, 0x44, 0x66);
+
+impl InkLayer {
+    pub fn new(lifetime: Option<Duration>) -> Self {
+        Self {
+            strokes: Vec::new(),
+            drawing: HashMap::new(),
+            colors: HashMap::new(),
+            lifetime,
+            min_width: 1.5,
+            max_width: 6.0,
+        }
+    }
+
+    pub fn set_color(&mut self, tablet: TabletId, color: Color) {
+        self.colors.insert(tablet, color);
+    }
+
+    /// 添加一个落笔的点, `pressure` 为 0.0 ~ 1.0
+    pub fn push(&mut self, tablet: TabletId, (x, y): (f64, f64), pressure: f32) {
+        let point = InkPoint {
+            x,
+            y,
+            width: self.min_width + (self.max_width - self.min_width) * pressure.clamp(0.0, 1.0),
+        };
+        let index = *self.drawing.entry(tablet).or_insert_with(|| {
+            self.strokes.push(Stroke {
+                color: self.colors.get(&tablet).copied().unwrap_or(DEFAULT_INK),
+                points: Vec::new(),
+                finished_at: None,
+            });
+            self.strokes.len() - 1
+        });
+        self.strokes[index].points.push(point);
+    }
+
+    /// 抬笔，结束当前笔画. 返回是否有正在画的笔画
+    pub fn lift(&mut self, tablet: TabletId, now: Instant) -> bool {
+        let Some(index) = self.drawing.remove(&tablet) else {
+            return false;
+        };
+        self.strokes[index].finished_at = Some(now);
+        true
+    }
+
+    /// 所有数位板都抬笔
+    pub fn lift_all(&mut self, now: Instant) {
+        for (_, index) in self.drawing.drain() {
+            self.strokes[index].finished_at = Some(now);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.strokes.clear();
+        self.drawing.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strokes.is_empty()
+    }
+
+    /// 有笔画正在淡出, 下一帧需要继续绘制
+    pub fn is_fading(&self) -> bool {
+        self.lifetime.is_some()
+            && self
+                .strokes
+                .iter()
+                .any(|stroke| stroke.finished_at.is_some())
+    }
+
+    fn opacity(&self, stroke: &Stroke, now: Instant) -> f32 {
+        match (self.lifetime, stroke.finished_at) {
+            (Some(lifetime), Some(finished_at)) => {
+                let age = now.saturating_duration_since(finished_at);
+                1.0 - (age.as_secs_f32() / lifetime.as_secs_f32().max(f32::EPSILON)).min(1.0)
+            }
+            _ => 1.0,
+        }
+    }
+
+    /// 移除已经完全消失的笔画
+    pub fn expire(&mut self, now: Instant) {
+        let mut index = 0;
+        let mut remap = HashMap::new();
+        let mut kept = Vec::with_capacity(self.strokes.len());
+        for (old, stroke) in std::mem::take(&mut self.strokes).into_iter().enumerate() {
+            if self.opacity(&stroke, now) > 0.0 {
+                remap.insert(old, index);
+                kept.push(stroke);
+                index += 1;
+            }
+        }
+        self.strokes = kept;
+        self.drawing.retain(|_, stroke| match remap.get(stroke) {
+            Some(index) => {
+                *stroke = *index;
+                true
+            }
+            None => false,
+        });
+    }
+
+    /// 绘制落在 `output` 上的墨迹
+    pub fn render(&self, output: &OutputGeometry, canvas: &mut impl Painter, now: Instant) {
+        let scale = canvas.scale() as f32;
+        let to_pixels = |point: &InkPoint| {
+            let (x, y) = output.to_pixels(point.x, point.y);
+            (x as f32, y as f32)
+        };
+        for stroke in &self.strokes {
+            let color = stroke.color.with_alpha(self.opacity(stroke, now));
+            match stroke.points.as_slice() {
+                [] => {}
+                [point] => {
+                    let (x, y) = to_pixels(point);
+                    canvas.fill_circle(x, y, point.width * scale / 2.0, color);
+                }
+                points => {
+                    for pair in points.windows(2) {
+                        canvas.stroke_segment(
+                            to_pixels(&pair[0]),
+                            to_pixels(&pair[1]),
+                            (pair[0].width * scale, pair[1].width * scale),
+                            color,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Default for InkLayer {
+    fn default() -> Self {
+        Self::new(Some(Duration::from_secs(5)))
+    }
+}
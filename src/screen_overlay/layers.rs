@@ -0,0 +1,216 @@
+//! 守护进程 overlay 上的内容
+//!
+//! [`OverlayLayers::renderer`] 交给 [`super::backend_wayland::WaylandOverlay::set_renderer`],
+//! 每个显示器的每一帧依次画出:
+//!
+//! 1. 所有显示器: 只看模式下远程数位板的墨迹、演示模式的激光笔轨迹、映射区域和十字线、校准目标、快捷菜单(只在打开它的显示器上)
+//! 2. HUD 所在的显示器([`HudState::hud_output`]): 提示、OSD 和转盘、滚轮转盘、远程连接、
+//!    进度和诊断面板, 光标靠近时变淡
+//! 3. 所有显示器: 光标的标签, 以及同一显示器上第二个以后的光标
+//!
+//! 每个显示器上的第一个光标由 [`OverlayLayers::cursor_renderer`] 画在光标画布上, 笔移动时只需要
+//! 重绘光标. 绘制时短暂锁住 [`HudState`] 和 [`CursorManager`], 文字在释放锁之后统一交给
+//! [`TextRenderer`]
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use crate::{
+    event_model::event::TabletEvent, event_router::RoutedEvent, hud_interface::HudState,
+    mapping::OutputGeometry,
+};
+
+use super::{
+    backend_wayland::{
+        frame::{CURSOR_SIZE, CursorRenderer, Renderer},
+        surface_info::SurfaceInfo,
+    },
+    canvas::Canvas,
+    cursor_manager::CursorManager,
+    hud,
+    ink::InkLayer,
+    text::TextRenderer,
+    trail::LaserTrail,
+};
+
+/// 守护进程的 overlay 绘制函数需要的状态, 克隆后共享
+#[derive(Clone)]
+pub struct OverlayLayers {
+    hud: Arc<Mutex<HudState>>,
+    cursors: Arc<Mutex<CursorManager>>,
+    trail: Option<Arc<Mutex<LaserTrail>>>,
+    ink: Option<Arc<Mutex<InkLayer>>>,
+    /// 所有显示器共用, 加载字体很慢
+    text: Arc<Mutex<TextRenderer>>,
+}
+
+impl OverlayLayers {
+    pub fn new(hud: Arc<Mutex<HudState>>) -> Self {
+        Self {
+            hud,
+            cursors: Arc::default(),
+            trail: None,
+            ink: None,
+            text: Arc::new(Mutex::new(TextRenderer::new())),
+        }
+    }
+
+    /// 数位板的光标, 接入和断开时在这里增删
+    pub fn cursors(&self) -> &Mutex<CursorManager> {
+        &self.cursors
+    }
+
+    /// 绘制演示模式的激光笔轨迹, 由路由器的 [`crate::event_router::hover_only::HoverOnly`] 记录
+    pub fn set_trail(&mut self, trail: Arc<Mutex<LaserTrail>>) {
+        self.trail = Some(trail);
+    }
+
+    /// 绘制只看模式下远程数位板的墨迹, 由 [`crate::input_devices::remote::RemoteViewer`] 记录
+    pub fn set_ink(&mut self, ink: Arc<Mutex<InkLayer>>) {
+        self.ink = Some(ink);
+    }
+
+    /// 按路由后的笔事件移动光标, 返回是否要重绘整个 overlay 而不只是光标
+    ///
+    /// 光标的标签和激光笔轨迹画在内容上, 有它们时需要
+    pub fn update_cursor(&self, routed: &RoutedEvent) -> bool {
+        let TabletEvent::PenEvent(pen) = &routed.event else {
+            return false;
+        };
+        let mut cursors = self.cursors.lock().unwrap();
+        cursors.update(routed.tablet, pen, routed.position.clone(), Instant::now());
+        cursors.label(routed.tablet).is_some() || self.has_trail()
+    }
+
+    fn has_trail(&self) -> bool {
+        self.trail
+            .as_ref()
+            .is_some_and(|trail| !trail.lock().unwrap().is_empty())
+    }
+
+    /// 交给 overlay 的光标绘制函数, 画出 surface 上的第一个光标
+    pub fn cursor_renderer(&self) -> CursorRenderer {
+        let layers = self.clone();
+        Arc::new(move |surface, canvas| {
+            let output = layers.output(surface)?;
+            let cursors = layers.cursors.lock().unwrap();
+            let (x, y) = *cursors.positions(&output.name).first()?;
+            let half = (CURSOR_SIZE as f64 * canvas.scale() / 2.0) as f32;
+            let origin = ((x - half).round(), (y - half).round());
+            cursors.render_at(&output.name, origin, canvas);
+            Some((origin.0 as i32, origin.1 as i32))
+        })
+    }
+
+    /// 交给 overlay 的绘制函数
+    pub fn renderer(&self) -> Renderer {
+        let layers = self.clone();
+        Arc::new(move |surface, canvas| {
+            let Some(output) = layers.output(surface) else {
+                return false;
+            };
+            layers.render(&output, canvas)
+        })
+    }
+
+    /// surface 所在的显示器, 按 ID 查找, 没有 ID 时按名称
+    fn output(&self, surface: &SurfaceInfo) -> Option<OutputGeometry> {
+        let state = self.hud.lock().unwrap();
+        let outputs = &state.geometry.outputs;
+        outputs
+            .iter()
+            .find(|output| output.id == Some(surface.output))
+            .or_else(|| {
+                let name = surface.name.as_deref()?;
+                outputs.iter().find(|output| output.name == name)
+            })
+            .cloned()
+    }
+
+    /// 在 `output` 的画布上画一帧, 返回 `true` 表示还在动画中
+    pub fn render(&self, output: &OutputGeometry, canvas: &mut Canvas) -> bool {
+        let now = Instant::now();
+        let fading = self.ink.as_ref().is_some_and(|ink| {
+            let mut ink = ink.lock().unwrap();
+            ink.expire(now);
+            ink.render(output, canvas, now);
+            ink.is_fading()
+        });
+        let trailing = self.trail.as_ref().is_some_and(|trail| {
+            let mut trail = trail.lock().unwrap();
+            trail.expire(now);
+            trail.render(output, canvas, now);
+            !trail.is_empty()
+        });
+        let (positions, mut text) = {
+            let cursors = self.cursors.lock().unwrap();
+            cursors.render_others(&output.name, canvas);
+            (
+                cursors.positions(&output.name),
+                cursors.labels(&output.name, canvas.scale()),
+            )
+        };
+        let animating = {
+            let mut state = self.hud.lock().unwrap();
+            state.toasts.tick(now);
+            state.progress.tick(now);
+            text.extend(hud::render_mapping_overlay(
+                &state.mapping_overlay,
+                output,
+                canvas,
+            ));
+            text.extend(hud::render_calibration(&state.calibration, output, canvas));
+            if let Some(menu) = &state.quick_menu {
+                text.extend(hud::render_quick_menu(menu, &output.name, canvas));
+            }
+            let on_hud_output = state
+                .hud_output()
+                .is_some_and(|hud_output| hud_output.name == output.name);
+            if on_hud_output {
+                text.extend(hud::render_toasts(&state.toasts, now, &positions, canvas));
+                text.extend(hud::render_osd(&state.osd, now, &positions, canvas));
+                text.extend(hud::render_dial(
+                    &state.dial,
+                    &state.osd,
+                    now,
+                    &positions,
+                    canvas,
+                ));
+                text.extend(hud::render_wheel_ring(
+                    &state.wheel_ring,
+                    now,
+                    &positions,
+                    canvas,
+                ));
+                text.extend(hud::render_links(&state.links, now, &positions, canvas));
+                text.extend(hud::render_progress(
+                    &state.progress,
+                    now,
+                    &positions,
+                    canvas,
+                ));
+                text.extend(hud::render_diagnostics(
+                    &state.diagnostics,
+                    &positions,
+                    canvas,
+                ));
+            }
+            fading || trailing || (on_hud_output && is_animating(&state, now))
+        };
+        self.text.lock().unwrap().draw(canvas, &text);
+        animating
+    }
+}
+
+/// 提示等元素还在淡入淡出或者内容一直在变化, 下一帧需要继续绘制
+fn is_animating(state: &HudState, now: Instant) -> bool {
+    state.toasts.visible(now).next().is_some()
+        || state.osd.visible(now).is_some()
+        || state.dial.visible(now).is_some()
+        || state.wheel_ring.visible(now).is_some()
+        || state.progress.is_animating()
+        // 诊断面板的统计一直在变化
+        || state.diagnostics.is_visible()
+}
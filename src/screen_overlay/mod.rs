@@ -1,3 +1,4 @@
+/// `DRM` 后端, 没有合成器时直接在显示器的平面上显示光标
 pub mod backend_drm;
 /// # Wayland overlay backend
 ///
@@ -10,5 +11,38 @@ pub mod backend_drm;
 /// https://wayland.app/protocols/wlr-layer-shell-unstable-v1#compositor-support
 pub mod backend_wayland;
 pub mod backend_x11;
+/// 用代码创建特殊用途的 overlay
+pub mod builder;
+/// 软件绘制的像素缓冲区
+pub mod canvas;
+/// 动态光标
 pub mod cursor;
+/// 多块数位板的光标
+pub mod cursor_manager;
+/// overlay 的错误
+pub mod error;
+/// 用 GPU 绘制 HUD
+#[cfg(feature = "gpu")]
+pub mod gpu;
+/// HUD 的绘制
 pub mod hud;
+/// 显示器和 surface 的标识
+pub mod id;
+/// 墨迹(批注)
+pub mod ink;
+/// 守护进程 overlay 上的内容
+pub mod layers;
+/// 记录下来的 HUD 图形
+pub mod scene;
+/// 需要 overlay 的显示器
+pub mod selection;
+/// 绘制函数读取的共享状态
+pub mod snapshot;
+/// 和其他 layer-shell 客户端的层叠
+pub mod stacking;
+/// 绘制方式的选择
+pub mod strategy;
+/// 文字的排版和绘制
+pub mod text;
+/// 激光笔轨迹
+pub mod trail;
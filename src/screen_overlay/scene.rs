@@ -0,0 +1,147 @@
+//! 记录下来的 HUD 图形
+//!
+//! HUD 的绘制函数通过 [`Painter`] 绘制. 画到 [`Scene`] 上时只记录图形, 启用 `gpu` feature
+//! 后可以交给 [`super::gpu::GpuRenderer`] 直接画到 overlay 的 DMA-BUF 上; 没有 GPU 时用
+//! [`Scene::paint`] 在 CPU 上画到画布, 和直接画到画布上的结果相同
+
+use super::canvas::{Canvas, Color, Painter};
+
+/// 一个图形, 坐标和尺寸都是像素
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shape {
+    Rect {
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+        color: Color,
+    },
+    Circle {
+        center: (f32, f32),
+        radius: f32,
+        color: Color,
+    },
+    /// 见 [`Canvas::stroke_arc`]
+    Arc {
+        center: (f32, f32),
+        radius: f32,
+        width: f32,
+        angles: (f32, f32),
+        color: Color,
+    },
+}
+
+/// 一帧的图形, 按绘制顺序排列
+#[derive(Debug, Clone)]
+pub struct Scene {
+    width: u32,
+    height: u32,
+    scale: f64,
+    shapes: Vec<Shape>,
+}
+
+impl Scene {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            scale: 1.0,
+            shapes: Vec::new(),
+        }
+    }
+
+    pub fn set_scale(&mut self, scale: f64) {
+        self.scale = if scale > 0.0 { scale } else { 1.0 };
+    }
+
+    pub fn shapes(&self) -> &[Shape] {
+        &self.shapes
+    }
+
+    /// 清空图形, 开始下一帧
+    pub fn clear(&mut self) {
+        self.shapes.clear();
+    }
+
+    /// 在 CPU 上把所有图形画到 `canvas` 上
+    pub fn paint(&self, canvas: &mut Canvas) {
+        for shape in &self.shapes {
+            match *shape {
+                Shape::Rect {
+                    x,
+                    y,
+                    width,
+                    height,
+                    color,
+                } => canvas.fill_rect(x, y, width, height, color),
+                Shape::Circle {
+                    center,
+                    radius,
+                    color,
+                } => canvas.fill_circle(center.0, center.1, radius, color),
+                Shape::Arc {
+                    center,
+                    radius,
+                    width,
+                    angles,
+                    color,
+                } => canvas.stroke_arc(center, radius, width, angles, color),
+            }
+        }
+    }
+}
+
+impl Painter for Scene {
+    fn width(&self) -> u32 {
+        self.width
+    }
+
+    fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    fn fill_rect(&mut self, x: i32, y: i32, width: u32, height: u32, color: Color) {
+        if width > 0 && height > 0 && color.a > 0 {
+            self.shapes.push(Shape::Rect {
+                x,
+                y,
+                width,
+                height,
+                color,
+            });
+        }
+    }
+
+    fn fill_circle(&mut self, cx: f32, cy: f32, radius: f32, color: Color) {
+        if color.a > 0 {
+            self.shapes.push(Shape::Circle {
+                center: (cx, cy),
+                radius,
+                color,
+            });
+        }
+    }
+
+    fn stroke_arc(
+        &mut self,
+        center: (f32, f32),
+        radius: f32,
+        width: f32,
+        angles: (f32, f32),
+        color: Color,
+    ) {
+        if color.a > 0 {
+            self.shapes.push(Shape::Arc {
+                center,
+                radius,
+                width,
+                angles,
+                color,
+            });
+        }
+    }
+}
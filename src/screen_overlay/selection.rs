@@ -0,0 +1,103 @@
+//! 哪些显示器需要 overlay
+//!
+//! 默认每个显示器都有一个全屏 overlay. 显示器很多时可以用 `exclude` 跳过用不到的显示器
+//! (比如只用来看视频的电视), 或者打开 `lazy` 只给数位板映射到的显示器创建 overlay.
+//! 没有单独设置的数位板使用 `defaults` 的映射, 所以 `lazy` 也会考虑默认映射
+
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    mapping::{Mapper, MappingConfig, OutputGeometry},
+};
+
+use super::{
+    backend_wayland::discovery::DisplayPolicy, stacking::StackingConfig, strategy::OverlayStrategy,
+};
+
+/// 配置文件中的 `[overlay]`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OverlayConfig {
+    /// 不创建 overlay 的显示器名称
+    pub exclude: Vec<String>,
+    /// 只给数位板映射到的显示器创建 overlay
+    pub lazy: bool,
+    /// 绘制方式, 不设置时按 [`super::strategy::StrategyCache`] 中的测量结果自动选择
+    pub strategy: Option<OverlayStrategy>,
+    /// 没有 `WAYLAND_DISPLAY` 时连接哪个合成器
+    pub display: DisplayPolicy,
+    /// 层级和被其他 layer-shell 客户端遮住时的处理
+    pub stacking: StackingConfig,
+}
+
+/// 从配置得出的 overlay 显示器选择规则
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OutputSelection {
+    exclude: BTreeSet<String>,
+    /// 只考虑这些显示器, `None` 表示所有显示器
+    only: Option<BTreeSet<String>>,
+    /// `lazy` 时所有数位板的映射, `None` 表示不按映射筛选
+    mappings: Option<Vec<MappingConfig>>,
+}
+
+impl OutputSelection {
+    /// 所有显示器都需要 overlay
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        let mappings = config.overlay.lazy.then(|| {
+            std::iter::once(&config.defaults)
+                .chain(config.tablets.iter().map(|tablet| &tablet.profile))
+                .map(|profile| profile.mapping.clone())
+                .collect()
+        });
+        Self {
+            exclude: config.overlay.exclude.iter().cloned().collect(),
+            only: None,
+            mappings,
+        }
+    }
+
+    /// 只有名称在 `names` 中的显示器需要 overlay
+    pub fn only(names: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            only: Some(names.into_iter().map(Into::into).collect()),
+            ..Self::default()
+        }
+    }
+
+    /// 布局中需要 overlay 的显示器名称
+    pub fn select(&self, outputs: &[OutputGeometry]) -> BTreeSet<String> {
+        let targets: Option<Vec<_>> = self.mappings.as_ref().map(|mappings| {
+            mappings
+                .iter()
+                .filter_map(|mapping| {
+                    let mut mapper = Mapper::new(mapping.clone());
+                    mapper.set_outputs(outputs.to_vec());
+                    mapper.target_rect()
+                })
+                .collect()
+        });
+        outputs
+            .iter()
+            .filter(|output| !self.exclude.contains(&output.name))
+            .filter(|output| {
+                self.only
+                    .as_ref()
+                    .is_none_or(|only| only.contains(&output.name))
+            })
+            .filter(|output| {
+                targets.as_ref().is_none_or(|targets| {
+                    let rect = output.logical_rect();
+                    targets.iter().any(|target| target.intersects(&rect))
+                })
+            })
+            .map(|output| output.name.clone())
+            .collect()
+    }
+}
@@ -0,0 +1,51 @@
+//! 绘制函数读取的共享状态
+//!
+//! 每个显示器在自己的线程中绘制, 绘制函数可能同时在多个线程中运行. 状态的所有者(路由器、HUD)
+//! 修改后用 [`Snapshot::publish`] 发布新的快照, 绘制函数用 [`Snapshot::load`] 读取, 两边都不加锁,
+//! 刷新率低的显示器绘制得再慢也不会拖住其他显示器上的光标
+
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+
+/// 可以在任何线程中读取和替换的快照, 克隆后共享同一份状态
+pub struct Snapshot<T> {
+    current: Arc<ArcSwap<T>>,
+}
+
+impl<T> Clone for Snapshot<T> {
+    fn clone(&self) -> Self {
+        Self {
+            current: Arc::clone(&self.current),
+        }
+    }
+}
+
+impl<T: Default> Default for Snapshot<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> Snapshot<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            current: Arc::new(ArcSwap::from_pointee(value)),
+        }
+    }
+
+    /// 替换快照, 正在绘制的帧继续使用旧的快照
+    pub fn publish(&self, value: T) {
+        self.current.store(Arc::new(value));
+    }
+
+    /// 在当前快照的基础上修改
+    pub fn update(&self, f: impl Fn(&T) -> T) {
+        self.current.rcu(|current| f(current));
+    }
+
+    /// 当前的快照
+    pub fn load(&self) -> Arc<T> {
+        self.current.load_full()
+    }
+}
@@ -0,0 +1,148 @@
+//! overlay 和其他 layer-shell 客户端的层叠
+//!
+//! 通知、状态栏、截图工具等也使用 `wlr layer shell`. 同一层中后创建的 surface 通常在上面,
+//! overlay 被不透明的 surface 完全盖住时合成器不再发送帧回调(至少基于 wlroots scene 的合成器
+//! 如此). 所以有新内容等待显示的 surface 超过 `stall_ms` 没有收到帧回调时, 认为它被遮住了.
+//! 只遮住一部分时合成器仍然发送帧回调, 检测不到; 显示器关闭时也会被当作遮住.
+//!
+//! 打开 `reclaim` 时重新创建被遮住的 surface, 回到这一层的最上面. 另一个客户端也这样做时
+//! 两边会轮流重新创建, 所以同一个显示器连续重新创建时等待的时间逐次加倍
+//!
+//! ```toml
+//! [overlay.stacking]
+//! layer = "top"
+//! reclaim = true
+//! stall_ms = 1000
+//! ```
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use super::{builder::OverlayLayer, id::SurfaceId};
+
+/// 第一次重新创建后至少等这么久才再次重新创建, 之后每次加倍
+const RECLAIM_BACKOFF_MIN: Duration = Duration::from_secs(2);
+const RECLAIM_BACKOFF_MAX: Duration = Duration::from_secs(60);
+/// 上次重新创建已经过去这么久时, 重新从 [`RECLAIM_BACKOFF_MIN`] 开始
+const RECLAIM_STABLE_AFTER: Duration = Duration::from_secs(120);
+
+/// overlay 使用的层级
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StackingLayer {
+    /// 和状态栏、通知同一层, 全屏窗口会盖住 overlay
+    Top,
+    /// 在全屏窗口之上
+    #[default]
+    Overlay,
+}
+
+impl From<StackingLayer> for OverlayLayer {
+    fn from(layer: StackingLayer) -> Self {
+        match layer {
+            StackingLayer::Top => OverlayLayer::Top,
+            StackingLayer::Overlay => OverlayLayer::Overlay,
+        }
+    }
+}
+
+/// 配置文件中的 `[overlay.stacking]`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StackingConfig {
+    pub layer: StackingLayer,
+    /// 被遮住时重新创建 surface
+    pub reclaim: bool,
+    /// 新内容等待帧回调超过这么久(毫秒)时认为被遮住
+    pub stall_ms: u64,
+}
+
+impl Default for StackingConfig {
+    fn default() -> Self {
+        Self {
+            layer: StackingLayer::default(),
+            reclaim: false,
+            stall_ms: 1000,
+        }
+    }
+}
+
+/// 一个 surface 被遮住的情况
+#[derive(Debug, Default)]
+struct Obscured {
+    /// 已经报告过被遮住, 还没有恢复
+    reported: bool,
+    last_reclaim: Option<Instant>,
+    backoff: Duration,
+}
+
+/// 按帧回调判断哪些 surface 被遮住, 决定什么时候重新创建
+///
+/// 重新创建的 surface 沿用原来的标识, 等待时间按标识记录, 所以不随 surface 一起删除
+#[derive(Debug, Default)]
+pub struct StackingMonitor {
+    config: StackingConfig,
+    surfaces: HashMap<SurfaceId, Obscured>,
+}
+
+impl StackingMonitor {
+    pub fn new(config: StackingConfig) -> Self {
+        Self {
+            config,
+            surfaces: HashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &StackingConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: StackingConfig) {
+        self.config = config;
+    }
+
+    /// `waiting_since` 是 surface 上的新内容开始等待帧回调的时间, 没有等待的内容时为 `None`.
+    /// 返回是否应该现在重新创建这个 surface
+    pub fn check(&mut self, id: SurfaceId, waiting_since: Option<Instant>, now: Instant) -> bool {
+        let stall = Duration::from_millis(self.config.stall_ms);
+        let obscured = self.surfaces.entry(id).or_default();
+        let Some(since) = waiting_since.filter(|since| now.duration_since(*since) >= stall) else {
+            if waiting_since.is_none() && std::mem::take(&mut obscured.reported) {
+                info!("{id} 重新收到帧回调");
+            }
+            return false;
+        };
+        if !obscured.reported {
+            obscured.reported = true;
+            warn!(
+                "{id} 的新内容等待了 {} ms 仍没有帧回调, overlay 可能被其他 layer-shell 客户端遮住",
+                now.duration_since(since).as_millis()
+            );
+        }
+        if !self.config.reclaim {
+            return false;
+        }
+        if let Some(last) = obscured.last_reclaim {
+            let elapsed = now.duration_since(last);
+            if elapsed < obscured.backoff {
+                return false;
+            }
+            obscured.backoff = if elapsed >= RECLAIM_STABLE_AFTER {
+                RECLAIM_BACKOFF_MIN
+            } else {
+                (obscured.backoff * 2).min(RECLAIM_BACKOFF_MAX)
+            };
+        } else {
+            obscured.backoff = RECLAIM_BACKOFF_MIN;
+        }
+        obscured.last_reclaim = Some(now);
+        obscured.reported = false;
+        info!("重新创建 {id} 以回到最上面");
+        true
+    }
+}
@@ -0,0 +1,238 @@
+//! overlay 的绘制方式
+//!
+//! 同样的光标和 HUD 可以用几种方式交给合成器, 哪种最快取决于合成器和显卡:
+//!
+//! - [`OverlayStrategy::Shm`]: 每帧在 CPU 上画好整个显示器大小的画布，复制到 shm 缓冲区
+//! - [`OverlayStrategy::Dmabuf`]: 同样画整个画布, 但上传到显卡上的 DMA-BUF, 合成器不需要再复制一次
+//! - [`OverlayStrategy::CursorSurface`]: 光标画在单独的小 subsurface 上，移动光标只需要重画这一小块
+//!   并移动它的位置, HUD 所在的全屏 surface 只在 HUD 变化时重画
+//!
+//! `tabletd --bench-overlay` 用一段固定的光标轨迹测量每种方式, 结果按合成器记在
+//! `$XDG_STATE_HOME/tabletd/overlay-strategy.toml` 中. 配置中没有设置 `[overlay] strategy` 时
+//! 使用当前合成器测得最快的方式, 没有测过时使用 shm
+//!
+//! ```toml
+//! [overlay]
+//! strategy = "cursor_surface"
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::units;
+
+/// 把 overlay 内容交给合成器的方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverlayStrategy {
+    #[default]
+    Shm,
+    Dmabuf,
+    CursorSurface,
+}
+
+impl OverlayStrategy {
+    pub const ALL: [OverlayStrategy; 3] = [
+        OverlayStrategy::Shm,
+        OverlayStrategy::Dmabuf,
+        OverlayStrategy::CursorSurface,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            OverlayStrategy::Shm => "shm",
+            OverlayStrategy::Dmabuf => "dmabuf",
+            OverlayStrategy::CursorSurface => "cursor_surface",
+        }
+    }
+}
+
+impl fmt::Display for OverlayStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// 一种方式的测量结果
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StrategyReport {
+    pub strategy: OverlayStrategy,
+    pub frames: u32,
+    /// 每帧的平均耗时
+    pub mean: Duration,
+    pub p95: Duration,
+    pub worst: Duration,
+    /// 每帧平均提交给合成器的损坏区域(像素)
+    pub damage: u64,
+    /// 测量期间消耗的 CPU 时间(用户态 + 内核态)
+    pub cpu: Duration,
+    /// 测量持续的时间
+    pub wall: Duration,
+}
+
+impl StrategyReport {
+    /// CPU 占用率, 1.0 表示占满一个核心
+    pub fn cpu_usage(&self) -> f64 {
+        self.cpu.as_secs_f64() / self.wall.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+impl fmt::Display for StrategyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let units = units::current();
+        write!(
+            f,
+            "{:<14} {:>6} frames  mean {:>10}  p95 {:>10}  max {:>10}  damage {:>9} px  cpu {:>6}",
+            self.strategy.name(),
+            self.frames,
+            units.latency_with(self.mean, 3),
+            units.latency_with(self.p95, 3),
+            units.latency_with(self.worst, 3),
+            self.damage,
+            units.percent(self.cpu_usage(), 1),
+        )
+    }
+}
+
+/// 平均每帧耗时最短的方式
+pub fn fastest(reports: &[StrategyReport]) -> Option<OverlayStrategy> {
+    reports
+        .iter()
+        .filter(|report| report.frames > 0)
+        .min_by_key(|report| report.mean)
+        .map(|report| report.strategy)
+}
+
+/// 当前的合成器, 用来区分不同桌面上的测量结果
+pub fn compositor_name() -> String {
+    ["XDG_CURRENT_DESKTOP", "XDG_SESSION_DESKTOP"]
+        .into_iter()
+        .filter_map(std::env::var_os)
+        .map(|name| name.to_string_lossy().into_owned())
+        .find(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// 一个合成器上的测量结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedChoice {
+    strategy: OverlayStrategy,
+    reports: Vec<StrategyReport>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct CacheFile {
+    #[serde(default)]
+    compositor: BTreeMap<String, CachedChoice>,
+}
+
+/// 按合成器记住的测量结果
+#[derive(Default)]
+pub struct StrategyCache {
+    path: Option<PathBuf>,
+    compositors: BTreeMap<String, CachedChoice>,
+}
+
+impl StrategyCache {
+    /// 只在内存中记录
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 默认路径: `$XDG_STATE_HOME/tabletd/overlay-strategy.toml`
+    pub fn default_path() -> PathBuf {
+        std::env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| Path::new(&home).join(".local").join("state"))
+            })
+            .unwrap_or_else(std::env::temp_dir)
+            .join("tabletd")
+            .join("overlay-strategy.toml")
+    }
+
+    /// 从文件读取，文件不存在时为空. 之后每次记录都会写回文件
+    pub fn load(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let path = path.into();
+        let compositors = match fs::read_to_string(&path) {
+            Ok(text) => {
+                toml::from_str::<CacheFile>(&text)
+                    .with_context(|| format!("{}", path.display()))?
+                    .compositor
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path: Some(path),
+            compositors,
+        })
+    }
+
+    /// 合成器上测得最快的方式
+    pub fn get(&self, compositor: &str) -> Option<OverlayStrategy> {
+        self.compositors
+            .get(compositor)
+            .map(|choice| choice.strategy)
+    }
+
+    /// 合成器上的测量结果
+    pub fn reports(&self, compositor: &str) -> &[StrategyReport] {
+        self.compositors
+            .get(compositor)
+            .map_or(&[], |choice| &choice.reports)
+    }
+
+    /// 记下一次测量, 返回最快的方式. 没有任何方式成功时不修改记录
+    pub fn record(
+        &mut self,
+        compositor: &str,
+        reports: Vec<StrategyReport>,
+    ) -> Option<OverlayStrategy> {
+        let strategy = fastest(&reports)?;
+        self.compositors
+            .insert(compositor.to_string(), CachedChoice { strategy, reports });
+        self.persist();
+        Some(strategy)
+    }
+
+    fn persist(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let file = CacheFile {
+            compositor: self.compositors.clone(),
+        };
+        let result = toml::to_string_pretty(&file)
+            .map_err(anyhow::Error::from)
+            .and_then(|text| {
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                fs::write(path, text)?;
+                Ok(())
+            });
+        if let Err(e) = result {
+            warn!("无法保存 overlay 测量结果 {}: {e}", path.display());
+        }
+    }
+
+    /// 实际使用的方式: 配置指定的优先, 否则使用合成器上测得最快的
+    pub fn resolve(
+        &self,
+        configured: Option<OverlayStrategy>,
+        compositor: &str,
+    ) -> OverlayStrategy {
+        configured
+            .or_else(|| self.get(compositor))
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,136 @@
+//! 文字的排版和绘制
+//!
+//! HUD 和光标标签的绘制函数只返回 [`TextRun`], 最后由 [`TextRenderer`] 统一画到画布上.
+//! 排版使用 cosmic-text: 按系统字体回退, 经过 HarfBuzz 规则整形, 中文、阿拉伯文和 emoji
+//! 混排都能正确显示. 字形的水平位置精确到 1/4 像素(subpixel positioning), 标签跟着光标移动时
+//! 文字不会抖动.
+//!
+//! [`TextRun`] 的坐标和字号已经按画布的缩放比例换算成像素, 字形直接按像素字号光栅化,
+//! 分数缩放下也不会先画小再放大而变模糊
+
+use std::collections::HashMap;
+
+use cosmic_text::{Attrs, Buffer, FontSystem, Metrics, Shaping, SwashCache, SwashContent};
+
+use super::canvas::{Canvas, Color, TextRun};
+
+/// 行高与字号的比例
+const LINE_HEIGHT: f32 = 1.25;
+
+/// 排版好的一段文字
+struct Shaped {
+    buffer: Buffer,
+    /// 上一次 [`TextRenderer::draw`] 之后是否用到
+    used: bool,
+}
+
+/// 文字渲染器, 缓存排版结果和光栅化的字形
+pub struct TextRenderer {
+    fonts: FontSystem,
+    glyphs: SwashCache,
+    /// 按文字和字号缓存
+    shaped: HashMap<(String, u32), Shaped>,
+}
+
+impl Default for TextRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TextRenderer {
+    /// 加载系统字体, 需要几十到几百毫秒, 同一个 overlay 的所有画布应该共用一个
+    pub fn new() -> Self {
+        Self {
+            fonts: FontSystem::new(),
+            glyphs: SwashCache::new(),
+            shaped: HashMap::new(),
+        }
+    }
+
+    /// 文字排版后的宽度和高度(像素), 用于居中和对齐
+    pub fn measure(&mut self, text: &str, size: f32) -> (f32, f32) {
+        if text.is_empty() || size <= 0.0 {
+            return (0.0, 0.0);
+        }
+        let buffer = shape(&mut self.shaped, &mut self.fonts, text, size);
+        buffer
+            .layout_runs()
+            .fold((0.0, 0.0), |(width, height), line| {
+                (width.max(line.line_w), height + line.line_height)
+            })
+    }
+
+    /// 绘制一帧的文字
+    ///
+    /// 上一帧之后没有再用到的排版结果在这里丢弃, 字形的缓存一直保留
+    pub fn draw(&mut self, canvas: &mut Canvas, runs: &[TextRun]) {
+        for run in runs {
+            if run.text.is_empty() || run.size <= 0.0 || run.color.a == 0 {
+                continue;
+            }
+            self.draw_run(canvas, run);
+        }
+        self.shaped.retain(|_, shaped| shaped.used);
+        for shaped in self.shaped.values_mut() {
+            shaped.used = false;
+        }
+    }
+
+    fn draw_run(&mut self, canvas: &mut Canvas, run: &TextRun) {
+        let buffer = shape(&mut self.shaped, &mut self.fonts, &run.text, run.size);
+        for line in buffer.layout_runs() {
+            for glyph in line.glyphs {
+                // 水平方向的小数部分进入缓存键, 垂直方向对齐到整像素
+                let physical = glyph.physical((run.x, run.y + line.line_y), 1.0);
+                let Some(image) = self.glyphs.get_image(&mut self.fonts, physical.cache_key) else {
+                    continue;
+                };
+                let left = physical.x + image.placement.left;
+                let top = physical.y - image.placement.top;
+                let width = image.placement.width as usize;
+                match image.content {
+                    SwashContent::Mask => {
+                        for (i, coverage) in image.data.iter().enumerate() {
+                            let (x, y) = (left + (i % width) as i32, top + (i / width) as i32);
+                            canvas.blend(x, y, run.color, *coverage as f32 / 255.0);
+                        }
+                    }
+                    // 彩色 emoji 保留自己的颜色, 只跟随文字的不透明度
+                    SwashContent::Color => {
+                        let alpha = run.color.a as f32 / 255.0;
+                        for (i, pixel) in image.data.chunks_exact(4).enumerate() {
+                            let (x, y) = (left + (i % width) as i32, top + (i / width) as i32);
+                            let color = Color::rgba(pixel[0], pixel[1], pixel[2], pixel[3]);
+                            canvas.blend(x, y, color.with_alpha(alpha), 1.0);
+                        }
+                    }
+                    // 只请求了灰度的字形
+                    SwashContent::SubpixelMask => {}
+                }
+            }
+        }
+    }
+}
+
+/// 排版 `text`, 已经排版过的直接使用缓存
+fn shape<'a>(
+    shaped: &'a mut HashMap<(String, u32), Shaped>,
+    fonts: &mut FontSystem,
+    text: &str,
+    size: f32,
+) -> &'a Buffer {
+    let entry = shaped
+        .entry((text.to_string(), size.to_bits()))
+        .or_insert_with(|| {
+            // 没有设置宽度, 不会自动换行
+            let mut buffer = Buffer::new(fonts, Metrics::new(size, size * LINE_HEIGHT));
+            buffer.set_text(fonts, text, &Attrs::new(), Shaping::Advanced);
+            Shaped {
+                buffer,
+                used: false,
+            }
+        });
+    entry.used = true;
+    &entry.buffer
+}
@@ -0,0 +1,118 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use crate::mapping::OutputGeometry;
+
+use super::canvas::{Color, Painter};
+
+/// 轨迹的颜色和线宽(逻辑像素)
+const TRAIL_COLOR: Color = Color::rgb(0xff, 0x30, 0x30);
+const TRAIL_WIDTH: f32 = 4.0;
+
+/// 轨迹上的一个点, 逻辑坐标
+#[derive(Debug, Clone, Copy)]
+pub struct TrailPoint {
+    pub x: f64,
+    pub y: f64,
+    pub time: Instant,
+    /// 是否与上一个点相连
+    pub connected: bool,
+}
+
+/// 会逐渐消失的激光笔轨迹
+///
+/// 和 [`super::ink::InkLayer`] 一样使用所有显示器共用的逻辑坐标系, 轨迹可以跨越多个显示器
+#[derive(Debug, Clone)]
+pub struct LaserTrail {
+    points: VecDeque<TrailPoint>,
+    lifetime: Duration,
+    lifted: bool,
+}
+
+impl LaserTrail {
+    /// `lifetime` 是每个点从出现到完全消失的时间
+    pub fn new(lifetime: Duration) -> Self {
+        Self {
+            points: VecDeque::new(),
+            lifetime,
+            lifted: true,
+        }
+    }
+
+    pub fn push(&mut self, x: f64, y: f64, time: Instant) {
+        self.points.push_back(TrailPoint {
+            x,
+            y,
+            time,
+            connected: !self.lifted,
+        });
+        self.lifted = false;
+        self.expire(time);
+    }
+
+    /// 笔离开了，下一个点开始新的一段轨迹
+    pub fn lift(&mut self) {
+        self.lifted = true;
+    }
+
+    /// 移除已经完全消失的点
+    pub fn expire(&mut self, now: Instant) {
+        while let Some(point) = self.points.front() {
+            if now.saturating_duration_since(point.time) < self.lifetime {
+                break;
+            }
+            self.points.pop_front();
+        }
+    }
+
+    /// 仍然可见的点以及它们的不透明度(0.0 ~ 1.0)
+    pub fn visible(&self, now: Instant) -> impl Iterator<Item = (TrailPoint, f32)> + '_ {
+        self.points.iter().filter_map(move |point| {
+            let age = now.saturating_duration_since(point.time);
+            (age < self.lifetime).then(|| {
+                (
+                    *point,
+                    1.0 - age.as_secs_f32() / self.lifetime.as_secs_f32(),
+                )
+            })
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// 绘制落在 `output` 上的轨迹, 越旧的部分越透明
+    pub fn render(&self, output: &OutputGeometry, canvas: &mut impl Painter, now: Instant) {
+        let width = TRAIL_WIDTH * canvas.scale() as f32;
+        let to_pixels = |point: &TrailPoint| {
+            let (x, y) = output.to_pixels(point.x, point.y);
+            (x as f32, y as f32)
+        };
+        let mut previous: Option<TrailPoint> = None;
+        for (point, opacity) in self.visible(now) {
+            let color = TRAIL_COLOR.with_alpha(opacity);
+            match previous.filter(|_| point.connected) {
+                Some(previous) => canvas.stroke_segment(
+                    to_pixels(&previous),
+                    to_pixels(&point),
+                    (width, width),
+                    color,
+                ),
+                None => {
+                    let (x, y) = to_pixels(&point);
+                    canvas.fill_circle(x, y, width / 2.0, color);
+                }
+            }
+            previous = Some(point);
+        }
+    }
+}
+
+impl Default for LaserTrail {
+    fn default() -> Self {
+        Self::new(Duration::from_millis(800))
+    }
+}
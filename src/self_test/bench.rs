@@ -0,0 +1,267 @@
+//! overlay 绘制方式的性能比较
+//!
+//! 在当前合成器上依次用每种 [`OverlayStrategy`] 显示同一段光标轨迹(绕圈、快速横扫、离开后重新进入),
+//! 记录每一帧绘制、上传和提交的耗时、损坏区域和进程的 CPU 占用. 合成器不支持的方式会被跳过.
+//! 结果交给 [`crate::screen_overlay::strategy::StrategyCache::record`] 后, 没有在配置中指定绘制方式时
+//! 自动使用最快的方式
+//!
+//! 测量的是 tabletd 这一侧的开销, 合成器合成一帧的时间不包括在内
+
+use std::{
+    f32::consts::TAU,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use nix::sys::resource::{UsageWho, getrusage};
+use tokio::time::MissedTickBehavior;
+
+use crate::{
+    event_model::{
+        capability::{DEFAULT_MAX_TILT, DeviceCapabilities, DeviceClass},
+        event::{PenLocation, PenState, Tilt, ToolType},
+    },
+    screen_overlay::{
+        backend_wayland::{
+            WaylandOverlay,
+            frame::{CURSOR_SIZE, CursorRenderer, FrameLog, Renderer},
+        },
+        cursor::{Cursor, CursorStyle},
+        snapshot::Snapshot,
+        strategy::{OverlayStrategy, StrategyReport, fastest},
+    },
+};
+
+/// 等待 overlay 创建完成的最长时间
+const OVERLAY_TIMEOUT: Duration = Duration::from_secs(3);
+/// 切换方式后等合成器处理完旧的缓冲区再开始测量
+const SETTLE: Duration = Duration::from_millis(300);
+/// 轨迹的一个周期: 绕圈、横扫、离开
+const SCRIPT_PERIOD: f32 = 3.0;
+
+/// 比较的参数
+#[derive(Debug, Clone)]
+pub struct BenchConfig {
+    /// 每种方式测量的时间
+    pub duration: Duration,
+    /// 光标移动的频率(Hz), 和数位板的报告频率相近
+    pub rate: u32,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        Self {
+            duration: Duration::from_secs(5),
+            rate: 200,
+        }
+    }
+}
+
+/// 比较的结果
+#[derive(Debug, Clone, Default)]
+pub struct BenchReport {
+    pub reports: Vec<StrategyReport>,
+    /// 跳过的方式和原因
+    pub skipped: Vec<(OverlayStrategy, String)>,
+    /// 无法进行比较的原因
+    pub failure: Option<String>,
+}
+
+impl BenchReport {
+    /// 平均每帧耗时最短的方式
+    pub fn fastest(&self) -> Option<OverlayStrategy> {
+        fastest(&self.reports)
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for report in &self.reports {
+            writeln!(f, "{report}")?;
+        }
+        for (strategy, reason) in &self.skipped {
+            writeln!(f, "{:<14} 跳过: {reason}", strategy.name())?;
+        }
+        match (&self.failure, self.fastest()) {
+            (Some(failure), _) => write!(f, "[FAIL] {failure}"),
+            (None, Some(strategy)) => write!(f, "最快的方式: {strategy}"),
+            (None, None) => write!(f, "[FAIL] 没有可用的方式"),
+        }
+    }
+}
+
+/// 依次测量所有方式
+pub async fn run(config: BenchConfig) -> BenchReport {
+    let mut report = BenchReport::default();
+    if let Err(e) = bench(&config, &mut report).await {
+        report.failure = Some(format!("{e:#}"));
+    }
+    report
+}
+
+async fn bench(config: &BenchConfig, report: &mut BenchReport) -> anyhow::Result<()> {
+    if std::env::var_os("WAYLAND_DISPLAY").is_none() {
+        anyhow::bail!("未设置 WAYLAND_DISPLAY");
+    }
+    let overlay = WaylandOverlay::new();
+    wait_for_overlay(&overlay).await?;
+
+    // 脚本中的时间(秒), 由测量循环推进, 光标绘制函数按它计算位置
+    let clock = Snapshot::new(0.0f32);
+    let renderer: Renderer = Arc::new(|_, _| false);
+    overlay
+        .set_renderer(renderer)
+        .await
+        .context("无法设置 overlay 绘制函数")?;
+    overlay
+        .set_cursor_renderer(scripted_cursor(clock.clone()))
+        .await
+        .context("无法设置光标绘制函数")?;
+    let log = FrameLog::default();
+    overlay
+        .set_frame_log(Some(Arc::clone(&log)))
+        .await
+        .context("无法记录帧")?;
+    let redraw = overlay.redraw_handle();
+
+    for strategy in OverlayStrategy::ALL {
+        overlay
+            .set_strategy(strategy)
+            .await
+            .with_context(|| format!("无法切换到 {strategy}"))?;
+        tokio::time::sleep(SETTLE).await;
+        log.lock().unwrap().clear();
+
+        let cpu_start = cpu_time()?;
+        let start = Instant::now();
+        let mut ticker =
+            tokio::time::interval(Duration::from_secs_f64(1.0 / config.rate.max(1) as f64));
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        while start.elapsed() < config.duration {
+            ticker.tick().await;
+            clock.publish(start.elapsed().as_secs_f32());
+            redraw.request_cursor();
+        }
+        let wall = start.elapsed();
+        let cpu = cpu_time()?.saturating_sub(cpu_start);
+
+        let samples = std::mem::take(&mut *log.lock().unwrap());
+        let mut frames: Vec<_> = samples
+            .iter()
+            .filter(|sample| sample.strategy == strategy)
+            .collect();
+        if frames.is_empty() {
+            let reason = if samples.is_empty() {
+                "没有绘制任何一帧".to_string()
+            } else {
+                format!("合成器不支持, 实际使用了 {}", samples[0].strategy)
+            };
+            report.skipped.push((strategy, reason));
+            continue;
+        }
+        frames.sort_unstable_by_key(|sample| sample.elapsed);
+        let count = frames.len();
+        let total: Duration = frames.iter().map(|sample| sample.elapsed).sum();
+        let damage: u64 = frames.iter().map(|sample| sample.damage).sum();
+        let result = StrategyReport {
+            strategy,
+            frames: count as u32,
+            mean: total / count as u32,
+            p95: frames[((count as f64 * 0.95) as usize).min(count - 1)].elapsed,
+            worst: frames[count - 1].elapsed,
+            damage: damage / count as u64,
+            cpu,
+            wall,
+        };
+        println!("{result}");
+        report.reports.push(result);
+    }
+
+    let _ = overlay.set_frame_log(None).await;
+    let _ = overlay.destroy_surfaces().await;
+    Ok(())
+}
+
+/// 等到至少一个显示器上有了 overlay
+async fn wait_for_overlay(overlay: &WaylandOverlay) -> anyhow::Result<()> {
+    let start = Instant::now();
+    while start.elapsed() < OVERLAY_TIMEOUT {
+        if overlay.next_display().await.is_ok() {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    anyhow::bail!("没有创建任何 overlay")
+}
+
+/// 进程到目前为止消耗的 CPU 时间, 包括 Wayland 线程
+fn cpu_time() -> anyhow::Result<Duration> {
+    let usage = getrusage(UsageWho::RUSAGE_SELF).context("无法读取 CPU 时间")?;
+    let seconds = |time: nix::sys::time::TimeVal| {
+        Duration::new(
+            time.tv_sec().max(0) as u64,
+            time.tv_usec().max(0) as u32 * 1000,
+        )
+    };
+    Ok(seconds(usage.user_time()) + seconds(usage.system_time()))
+}
+
+/// 按脚本时间绘制光标: 每个周期先绕圈, 再快速横扫整个显示器, 最后离开感应范围
+fn scripted_cursor(clock: Snapshot<f32>) -> CursorRenderer {
+    let capabilities = DeviceCapabilities {
+        max_x: 32767,
+        max_y: 32767,
+        resolution_x: 200,
+        resolution_y: 200,
+        max_pressure: 8191,
+        tilt: true,
+        rotation: false,
+        eraser: false,
+        max_tilt: DEFAULT_MAX_TILT,
+        class: DeviceClass::Tablet,
+    };
+    // 光标的动画状态在各个显示器的绘制线程之间共享
+    let cursor = Mutex::new(Cursor::new(CursorStyle::default(), capabilities));
+    Arc::new(move |info, canvas| {
+        let t = *clock.load();
+        let phase = t % SCRIPT_PERIOD / SCRIPT_PERIOD;
+        let scale = canvas.scale() as f32;
+        let (width, height) = (info.width as f32 * scale, info.height as f32 * scale);
+        let (x, y, location) = match phase {
+            // 绕圈
+            p if p < 0.6 => {
+                let angle = p / 0.6 * 2.0 * TAU;
+                (
+                    width / 2.0 + angle.cos() * width / 4.0,
+                    height / 2.0 + angle.sin() * height / 4.0,
+                    PenLocation::Floating,
+                )
+            }
+            // 从左到右横扫
+            p if p < 0.9 => ((p - 0.6) / 0.3 * width, height / 3.0, PenLocation::Floating),
+            _ => (0.0, 0.0, PenLocation::Leaved),
+        };
+        let pen = PenState {
+            x: 0,
+            y: 0,
+            pressure: 0,
+            tilt: Tilt {
+                x: ((phase * TAU).cos() * 30.0) as i16,
+                y: ((phase * TAU).sin() * 30.0) as i16,
+            },
+            tool: ToolType::Pen,
+            location,
+        };
+        if matches!(pen.location, PenLocation::Leaved) {
+            return None;
+        }
+        let mut cursor = cursor.lock().unwrap();
+        cursor.update(&pen, Instant::now());
+        let half = (CURSOR_SIZE as f32 * scale / 2.0).round();
+        let origin = ((x - half) as i32, (y - half) as i32);
+        cursor.render_cursor(&pen, (half, half), canvas);
+        Some(origin)
+    })
+}
@@ -0,0 +1,363 @@
+use std::{
+    fmt,
+    io::Read,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    daemon::{
+        DaemonPlan, Subsystem,
+        startup::{self, Backend, ProbePolicy, StartupConfig},
+    },
+    event_dispatcher::api::ApiServer,
+    input_devices::{hidraw, privilege},
+    mapping::geometry::GeometryBus,
+    screen_overlay::{
+        backend_wayland::{
+            WaylandOverlay,
+            discovery::{self, DisplayChooser, DisplayPolicy},
+        },
+        builder::SurfaceOptions,
+        selection::OutputSelection,
+        strategy::OverlayStrategy,
+    },
+    units,
+};
+
+/// overlay 绘制方式的性能比较
+pub mod bench;
+/// 长时间运行的浸泡测试
+pub mod soak;
+
+/// 读取设备报告的时长
+const DEVICE_READ_DURATION: Duration = Duration::from_secs(1);
+/// 等待 overlay 创建完成的最长时间
+const OVERLAY_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// 单项检查的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Pass,
+    Fail,
+    Skip,
+}
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CheckStatus::Pass => "PASS",
+            CheckStatus::Fail => "FAIL",
+            CheckStatus::Skip => "SKIP",
+        })
+    }
+}
+
+/// 一项子系统检查
+#[derive(Debug, Clone)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+    pub elapsed: Duration,
+}
+
+/// 自检报告
+#[derive(Debug, Clone, Default)]
+pub struct SelfTestReport {
+    pub results: Vec<CheckResult>,
+}
+
+impl SelfTestReport {
+    /// 没有任何一项失败
+    pub fn passed(&self) -> bool {
+        self.results
+            .iter()
+            .all(|result| result.status != CheckStatus::Fail)
+    }
+
+    fn count(&self, status: CheckStatus) -> usize {
+        self.results
+            .iter()
+            .filter(|result| result.status == status)
+            .count()
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self
+            .results
+            .iter()
+            .map(|result| result.name.len())
+            .max()
+            .unwrap_or(0);
+        let units = units::current();
+        for result in &self.results {
+            writeln!(
+                f,
+                "[{}] {:width$}  {:>7}  {}",
+                result.status,
+                result.name,
+                units.seconds(result.elapsed, 2),
+                result.detail,
+            )?;
+        }
+        write!(
+            f,
+            "{} passed, {} failed, {} skipped",
+            self.count(CheckStatus::Pass),
+            self.count(CheckStatus::Fail),
+            self.count(CheckStatus::Skip),
+        )
+    }
+}
+
+/// 依次运行 `plan` 中启动的子系统的自检, 不启动的子系统记为跳过.
+/// 可选的后端(蓝牙、DRM、X11)按 `startup` 中的超时探测一次, overlay 按 `display` 寻找合成器
+pub async fn run(
+    plan: &DaemonPlan,
+    startup: &StartupConfig,
+    display: &DisplayPolicy,
+) -> SelfTestReport {
+    let mut report = SelfTestReport::default();
+    let skipped = |name| CheckResult {
+        name,
+        status: CheckStatus::Skip,
+        detail: format!("{} 模式不需要", plan.mode),
+        elapsed: Duration::ZERO,
+    };
+    report.results.push(if plan.runs(Subsystem::Devices) {
+        timed("devices", check_devices()).await
+    } else {
+        skipped("devices")
+    });
+    report.results.push(if plan.runs(Subsystem::Devices) {
+        timed("ble", check_backend(Backend::Ble, &startup.ble)).await
+    } else {
+        skipped("ble")
+    });
+    report.results.push(if plan.runs(Subsystem::Overlay) {
+        timed("overlay", check_overlay(display)).await
+    } else {
+        skipped("overlay")
+    });
+    for (name, backend) in [("drm", Backend::Drm), ("x11", Backend::X11)] {
+        report.results.push(if plan.runs(Subsystem::Overlay) {
+            timed(name, check_backend(backend, startup.policy(backend))).await
+        } else {
+            skipped(name)
+        });
+    }
+    report.results.push(if plan.runs(Subsystem::Dispatch) {
+        timed("uinput", check_uinput()).await
+    } else {
+        skipped("uinput")
+    });
+    report.results.push(if plan.runs(Subsystem::Api) {
+        timed("api-socket", check_api_socket()).await
+    } else {
+        skipped("api-socket")
+    });
+    report
+}
+
+async fn timed(
+    name: &'static str,
+    check: impl Future<Output = (CheckStatus, String)>,
+) -> CheckResult {
+    let start = Instant::now();
+    let (status, detail) = check.await;
+    CheckResult {
+        name,
+        status,
+        detail,
+        elapsed: start.elapsed(),
+    }
+}
+
+/// 打开所有已知数位板的 hidraw 节点，各读取 1 秒的报告
+async fn check_devices() -> (CheckStatus, String) {
+    let nodes: Vec<_> = hidraw::enumerate()
+        .into_iter()
+        .filter(|node| node.is_known_tablet())
+        .collect();
+    if nodes.is_empty() {
+        return (CheckStatus::Skip, "未发现数位板".to_string());
+    }
+
+    let mut status = CheckStatus::Pass;
+    let mut details = Vec::new();
+    for node in nodes {
+        let mut file = match privilege::open_hidraw(&node.path) {
+            Ok(file) => file,
+            Err(e) => {
+                status = CheckStatus::Fail;
+                details.push(format!("{}: {e}", node.path.display()));
+                continue;
+            }
+        };
+
+        // hidraw 的读取是阻塞的，交给单独的线程，超时后不再等待
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 64];
+            while let Ok(len) = file.read(&mut buf) {
+                if len == 0 || tx.send(()).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut reports = 0;
+        let deadline = tokio::time::sleep(DEVICE_READ_DURATION);
+        tokio::pin!(deadline);
+        loop {
+            tokio::select! {
+                _ = &mut deadline => break,
+                report = rx.recv() => match report {
+                    Some(()) => reports += 1,
+                    None => break,
+                },
+            }
+        }
+        details.push(format!(
+            "{} ({}): {reports} 个报告",
+            node.path.display(),
+            node.name
+        ));
+    }
+    (status, details.join("; "))
+}
+
+/// 探测一次可选的后端, 不可用时记为跳过, 超时记为失败
+async fn check_backend(backend: Backend, policy: &ProbePolicy) -> (CheckStatus, String) {
+    match tokio::time::timeout(policy.timeout(), startup::probe(backend)).await {
+        Ok(Ok(detail)) => (CheckStatus::Pass, detail),
+        Ok(Err(e)) => (CheckStatus::Skip, format!("不可用: {e:#}")),
+        Err(_) => (
+            CheckStatus::Fail,
+            format!("{} 毫秒内没有完成", policy.timeout_ms),
+        ),
+    }
+}
+
+/// 和守护进程一样按 `policy` 找到支持 `wlr-layer-shell` 的合成器, 在它的每个显示器上创建一个
+/// overlay，然后全部销毁. 没有任何 Wayland socket 时跳过
+async fn check_overlay(policy: &DisplayPolicy) -> (CheckStatus, String) {
+    let display = DisplayChooser::new(policy.clone());
+    let probe = tokio::time::timeout(OVERLAY_TIMEOUT, async move {
+        tokio::task::spawn_blocking(move || display.probe()).await?
+    });
+    let name = match probe.await {
+        Ok(Ok(name)) => name,
+        Ok(Err(e)) if !has_wayland_socket(policy) => {
+            return (CheckStatus::Skip, format!("{e:#}"));
+        }
+        Ok(Err(e)) => return (CheckStatus::Fail, format!("{e:#}")),
+        Err(_) => return (CheckStatus::Fail, "合成器没有响应".to_string()),
+    };
+
+    // 连接刚才检查过的合成器, 有多个合成器时也不等待选择
+    let overlay = WaylandOverlay::with_surface(
+        GeometryBus::new(),
+        SurfaceOptions::default(),
+        OutputSelection::all(),
+        OverlayStrategy::default(),
+        DisplayChooser::new(DisplayPolicy::Name(name.clone())),
+    );
+    let start = Instant::now();
+    let mut names = Vec::new();
+    let mut displays = Vec::new();
+    while start.elapsed() < OVERLAY_TIMEOUT {
+        match overlay.next_display().await {
+            Ok(display) => {
+                if let Ok(info) = display.get_info().await {
+                    names.push(format!("{} {}x{}", info.name, info.width, info.height));
+                }
+                displays.push(display);
+            }
+            // 已经拿到所有显示器了
+            Err(_) if !displays.is_empty() => break,
+            Err(_) => tokio::time::sleep(Duration::from_millis(100)).await,
+        }
+    }
+    drop(displays);
+    drop(overlay);
+
+    if names.is_empty() {
+        (CheckStatus::Fail, format!("{name} 上没有创建任何 overlay"))
+    } else {
+        (
+            CheckStatus::Pass,
+            format!("{name} 上 {} 个 overlay: {}", names.len(), names.join(", ")),
+        )
+    }
+}
+
+/// 有没有可以尝试连接的 Wayland socket: 指定了名称, 设置了 `WAYLAND_DISPLAY`, 或者
+/// `$XDG_RUNTIME_DIR` 中有 `wayland-*`
+fn has_wayland_socket(policy: &DisplayPolicy) -> bool {
+    matches!(policy, DisplayPolicy::Name(_))
+        || std::env::var_os("WAYLAND_DISPLAY").is_some()
+        || std::env::var_os("XDG_RUNTIME_DIR")
+            .is_some_and(|dir| !discovery::scan(Path::new(&dir)).is_empty())
+}
+
+/// 创建并销毁一个 uinput 虚拟设备
+async fn check_uinput() -> (CheckStatus, String) {
+    use evdev_rs::{
+        AbsInfo, DeviceWrapper, EnableCodeData, UInputDevice, UninitDevice,
+        enums::{EV_ABS, EV_KEY, EventCode},
+    };
+
+    let Some(device) = UninitDevice::new() else {
+        return (CheckStatus::Fail, "无法初始化 libevdev".to_string());
+    };
+    device.set_name("tabletd self-test");
+    let abs = AbsInfo {
+        value: 0,
+        minimum: 0,
+        maximum: 1000,
+        fuzz: 0,
+        flat: 0,
+        resolution: 0,
+    };
+    let enabled = device
+        .enable_event_code(
+            &EventCode::EV_ABS(EV_ABS::ABS_X),
+            Some(EnableCodeData::AbsInfo(abs)),
+        )
+        .and_then(|_| {
+            device.enable_event_code(
+                &EventCode::EV_ABS(EV_ABS::ABS_Y),
+                Some(EnableCodeData::AbsInfo(abs)),
+            )
+        })
+        .and_then(|_| device.enable_event_code(&EventCode::EV_KEY(EV_KEY::BTN_TOOL_PEN), None));
+    if let Err(e) = enabled {
+        return (CheckStatus::Fail, format!("无法设置设备能力: {e}"));
+    }
+
+    match UInputDevice::create_from_device(&device) {
+        Ok(uinput) => (
+            CheckStatus::Pass,
+            format!("已创建 {}", uinput.devnode().unwrap_or("(unknown)")),
+        ),
+        Err(e) => (CheckStatus::Fail, format!("无法创建 uinput 设备: {e}")),
+    }
+}
+
+/// 在运行时目录启动一个 `tabletd API` 服务端
+async fn check_api_socket() -> (CheckStatus, String) {
+    let path = ApiServer::default_socket_path()
+        .with_file_name(format!("tabletd-self-test-{}.sock", std::process::id()));
+    let result = ApiServer::new().serve_unix(&path);
+    let _ = std::fs::remove_file(&path);
+    match result {
+        Ok(handle) => {
+            handle.abort();
+            (CheckStatus::Pass, format!("已绑定 {}", path.display()))
+        }
+        Err(e) => (CheckStatus::Fail, format!("{}: {e}", path.display())),
+    }
+}
@@ -0,0 +1,371 @@
+//! 长时间运行的浸泡测试
+//!
+//! 用合成的笔输入按数位板的频率驱动 [`Router`] 和 uinput 虚拟数位板(有 Wayland 时还会重绘 overlay),
+//! 定期记录常驻内存、打开的文件描述符和延迟. 和第一次采样相比增长超过阈值时立即停止并报告失败,
+//! 用来在发布前发现 Wayland 和 USB 循环中的泄漏.
+//!
+//! 虚拟数位板会移动真实的光标, 合成的笔只悬浮不按下，避免在桌面上点击
+
+use std::{
+    f32::consts::TAU,
+    fmt,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU32, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use tokio::{sync::mpsc, time::MissedTickBehavior};
+
+use crate::{
+    event_dispatcher::uinput::UinputTablet,
+    event_model::{
+        capability::{DEFAULT_MAX_TILT, DeviceCapabilities, DeviceClass},
+        event::{PenLocation, PenState, TabletEvent, Tilt, ToolType},
+        stamp::EventSequence,
+        tablet::TabletId,
+    },
+    event_router::{InputEvent, RoutedEvent, Router, lanes},
+    input_devices::transport::Transport,
+    screen_overlay::{
+        backend_wayland::{
+            WaylandOverlay,
+            frame::{RedrawHandle, Renderer},
+        },
+        canvas::Color,
+    },
+    units,
+};
+
+/// 合成输入使用的数位板标识, 不会和真实设备冲突
+const SOAK_TABLET: TabletId = TabletId(u32::MAX);
+/// 笔绕一圈的时间
+const ORBIT_PERIOD: f32 = 4.0;
+/// 每隔这么久离开感应范围一次
+const PROXIMITY_PERIOD: f32 = 10.0;
+/// 每个周期中离开感应范围的时间
+const PROXIMITY_GAP: f32 = 0.5;
+
+/// 浸泡测试的参数和阈值
+#[derive(Debug, Clone)]
+pub struct SoakConfig {
+    pub duration: Duration,
+    /// 合成事件的频率(Hz)
+    pub rate: u32,
+    /// 采样间隔, 第一次采样作为基准
+    pub sample_interval: Duration,
+    /// 允许的常驻内存增长(字节)
+    pub max_rss_growth: u64,
+    /// 允许增加的文件描述符数量
+    pub max_fd_growth: usize,
+    /// p99 延迟允许比基准增加多少
+    pub max_latency_drift: Duration,
+}
+
+impl Default for SoakConfig {
+    fn default() -> Self {
+        Self {
+            duration: Duration::from_secs(60 * 60),
+            rate: 250,
+            sample_interval: Duration::from_secs(30),
+            max_rss_growth: 64 * 1024 * 1024,
+            max_fd_growth: 16,
+            max_latency_drift: Duration::from_millis(20),
+        }
+    }
+}
+
+/// 一次采样
+#[derive(Debug, Clone)]
+pub struct SoakSample {
+    pub elapsed: Duration,
+    /// 到目前为止发送的事件数量
+    pub events: u64,
+    /// 常驻内存(字节)
+    pub rss: u64,
+    pub fds: usize,
+    /// 上一次采样之后的延迟
+    pub p50: Duration,
+    pub p99: Duration,
+}
+
+impl fmt::Display for SoakSample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let units = units::current();
+        write!(
+            f,
+            "{:>9}  {:>10} events  rss {:>8} MiB  fd {:>4}  p50 {:>9}  p99 {:>9}",
+            units.seconds(self.elapsed, 0),
+            self.events,
+            units.number(self.rss as f64 / (1024.0 * 1024.0), 1),
+            self.fds,
+            units.latency(self.p50),
+            units.latency(self.p99),
+        )
+    }
+}
+
+/// 浸泡测试报告
+#[derive(Debug, Clone, Default)]
+pub struct SoakReport {
+    pub samples: Vec<SoakSample>,
+    /// 超过的阈值或者中途的错误
+    pub failure: Option<String>,
+}
+
+impl SoakReport {
+    pub fn passed(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+impl fmt::Display for SoakReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for sample in &self.samples {
+            writeln!(f, "{sample}")?;
+        }
+        match &self.failure {
+            Some(failure) => write!(f, "[FAIL] {failure}"),
+            None => write!(f, "[PASS] {} 次采样均未超过阈值", self.samples.len()),
+        }
+    }
+}
+
+/// 运行浸泡测试, 每次采样时输出一行进度
+pub async fn run(config: SoakConfig) -> SoakReport {
+    let mut report = SoakReport::default();
+    if let Err(e) = soak(&config, &mut report).await {
+        report.failure = Some(format!("{e:#}"));
+    }
+    report
+}
+
+async fn soak(config: &SoakConfig, report: &mut SoakReport) -> anyhow::Result<()> {
+    let capabilities = DeviceCapabilities {
+        max_x: 32767,
+        max_y: 32767,
+        resolution_x: 200,
+        resolution_y: 200,
+        max_pressure: 8191,
+        tilt: true,
+        rotation: false,
+        eraser: false,
+        max_tilt: DEFAULT_MAX_TILT,
+        class: DeviceClass::Tablet,
+    };
+
+    // 笔在轨道上的角度, 用于绘制 overlay
+    let angle = Arc::new(AtomicU32::new(0));
+    let overlay = std::env::var_os("WAYLAND_DISPLAY").map(|_| WaylandOverlay::new());
+    if let Some(overlay) = overlay.as_ref() {
+        let angle = Arc::clone(&angle);
+        let renderer: Renderer = Arc::new(move |_, canvas| {
+            let angle = f32::from_bits(angle.load(Ordering::Relaxed));
+            let (width, height) = (canvas.width() as f32, canvas.height() as f32);
+            canvas.clear();
+            canvas.fill_circle(
+                width / 2.0 + angle.cos() * width / 4.0,
+                height / 2.0 + angle.sin() * height / 4.0,
+                12.0,
+                Color::rgba(0x4f, 0xc3, 0xf7, 0xc0),
+            );
+            false
+        });
+        overlay
+            .set_renderer(renderer)
+            .await
+            .context("无法设置 overlay 绘制函数")?;
+    }
+    let redraw = overlay.as_ref().map(WaylandOverlay::redraw_handle);
+
+    let (input_tx, input_rx) = lanes::channel(256);
+    let (output_tx, output_rx) = mpsc::channel(256);
+    let mut router = Router::new();
+    router.connect(SOAK_TABLET, Transport::Usb, &capabilities);
+    let router = tokio::spawn(router.run(input_rx, output_tx));
+
+    let latencies = Arc::new(Mutex::new(Vec::new()));
+    let sink = spawn_sink(
+        capabilities.clone(),
+        output_rx,
+        Arc::clone(&latencies),
+        redraw,
+    );
+    let mut sequence = EventSequence::new();
+
+    let start = Instant::now();
+    let mut ticker =
+        tokio::time::interval(Duration::from_secs_f64(1.0 / config.rate.max(1) as f64));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut next_sample = start + config.sample_interval;
+    let mut events = 0u64;
+    loop {
+        ticker.tick().await;
+        let now = Instant::now();
+        let finished = now - start >= config.duration;
+        if now >= next_sample || finished {
+            next_sample += config.sample_interval;
+            let sample = sample(now - start, events, &latencies)?;
+            println!("{sample}");
+            let violation = report
+                .samples
+                .first()
+                .and_then(|baseline| check(config, baseline, &sample));
+            report.samples.push(sample);
+            if violation.is_some() {
+                report.failure = violation;
+                break;
+            }
+            if finished {
+                break;
+            }
+        }
+
+        let t = events as f32 / config.rate.max(1) as f32;
+        let (event, orbit) = synthetic(t, &capabilities);
+        angle.store(orbit.to_bits(), Ordering::Relaxed);
+        let input = InputEvent {
+            tablet: SOAK_TABLET,
+            transport: Transport::Usb,
+            event,
+            stamp: sequence.stamp_now(),
+        };
+        // 虚拟数位板已经停止, 错误在下面报告
+        if input_tx.send(input).await.is_err() {
+            break;
+        }
+        events += 1;
+    }
+
+    drop(input_tx);
+    let _ = router.await;
+    sink.await.context("uinput 线程异常退出")??;
+    if let Some(overlay) = overlay {
+        let _ = overlay.destroy_surfaces().await;
+    }
+    Ok(())
+}
+
+/// 把路由后的事件写入虚拟数位板, 按事件的时间戳记录延迟
+fn spawn_sink(
+    capabilities: DeviceCapabilities,
+    mut events: mpsc::Receiver<RoutedEvent>,
+    latencies: Arc<Mutex<Vec<Duration>>>,
+    redraw: Option<RedrawHandle>,
+) -> tokio::task::JoinHandle<anyhow::Result<()>> {
+    tokio::task::spawn_blocking(move || {
+        let mut tablet = UinputTablet::new("soak test", &capabilities)?;
+        while let Some(routed) = events.blocking_recv() {
+            tablet
+                .dispatch(&routed.event)
+                .context("无法写入 uinput 事件")?;
+            latencies.lock().unwrap().push(routed.stamp.age());
+            if let Some(redraw) = redraw.as_ref() {
+                redraw.request();
+            }
+        }
+        Ok(())
+    })
+}
+
+/// 第 `t` 秒的合成事件和笔在轨道上的角度
+fn synthetic(t: f32, capabilities: &DeviceCapabilities) -> (TabletEvent, f32) {
+    let angle = t / ORBIT_PERIOD * TAU;
+    let location = if t % PROXIMITY_PERIOD < PROXIMITY_PERIOD - PROXIMITY_GAP {
+        PenLocation::Floating
+    } else {
+        PenLocation::Leaved
+    };
+    let (cx, cy) = (
+        capabilities.max_x as f32 / 2.0,
+        capabilities.max_y as f32 / 2.0,
+    );
+    let pen = PenState {
+        x: (cx + angle.cos() * cx / 2.0) as u32,
+        y: (cy + angle.sin() * cy / 2.0) as u32,
+        pressure: 0,
+        tilt: Tilt {
+            x: (angle.cos() * 30.0) as i16,
+            y: (angle.sin() * 30.0) as i16,
+        },
+        tool: ToolType::Pen,
+        location,
+    };
+    (TabletEvent::PenEvent(pen), angle)
+}
+
+/// 采样当前进程的资源占用和上一次采样之后的延迟
+fn sample(
+    elapsed: Duration,
+    events: u64,
+    latencies: &Mutex<Vec<Duration>>,
+) -> anyhow::Result<SoakSample> {
+    let mut window = std::mem::take(&mut *latencies.lock().unwrap());
+    window.sort_unstable();
+    let percentile = |p: f64| {
+        window
+            .get(((window.len() as f64 * p) as usize).min(window.len().saturating_sub(1)))
+            .copied()
+            .unwrap_or_default()
+    };
+    Ok(SoakSample {
+        elapsed,
+        events,
+        rss: resident_memory()?,
+        fds: std::fs::read_dir("/proc/self/fd")
+            .context("无法读取 /proc/self/fd")?
+            .count(),
+        p50: percentile(0.5),
+        p99: percentile(0.99),
+    })
+}
+
+/// `/proc/self/status` 中的 `VmRSS`
+fn resident_memory() -> anyhow::Result<u64> {
+    let status =
+        std::fs::read_to_string("/proc/self/status").context("无法读取 /proc/self/status")?;
+    let kib = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))
+        .and_then(|value| {
+            value
+                .trim()
+                .trim_end_matches("kB")
+                .trim()
+                .parse::<u64>()
+                .ok()
+        })
+        .context("/proc/self/status 中没有 VmRSS")?;
+    Ok(kib * 1024)
+}
+
+/// 和基准比较, 返回超过的阈值
+fn check(config: &SoakConfig, baseline: &SoakSample, sample: &SoakSample) -> Option<String> {
+    let units = units::current();
+    let rss_growth = sample.rss.saturating_sub(baseline.rss);
+    if rss_growth > config.max_rss_growth {
+        return Some(format!(
+            "常驻内存增长了 {} MiB, 超过 {} MiB",
+            units.number(rss_growth as f64 / (1024.0 * 1024.0), 1),
+            units.number(config.max_rss_growth as f64 / (1024.0 * 1024.0), 1),
+        ));
+    }
+    let fd_growth = sample.fds.saturating_sub(baseline.fds);
+    if fd_growth > config.max_fd_growth {
+        return Some(format!(
+            "文件描述符增加了 {fd_growth} 个, 超过 {}",
+            config.max_fd_growth
+        ));
+    }
+    let drift = sample.p99.saturating_sub(baseline.p99);
+    if drift > config.max_latency_drift {
+        return Some(format!(
+            "p99 延迟比基准增加了 {}, 超过 {}",
+            units.latency(drift),
+            units.latency(config.max_latency_drift),
+        ));
+    }
+    None
+}
@@ -0,0 +1,182 @@
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::watch;
+
+/// 退出时的清理阶段，按声明顺序依次执行
+///
+/// 顺序很重要: 先停止接收新事件，再把已经在路上的事件发完，
+/// 然后才能释放设备和虚拟设备，否则可能留下一直按着的键
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ShutdownStage {
+    /// 停止从设备读取新事件
+    StopInput,
+    /// 发出还在队列里的事件，并松开所有按下的按键
+    FlushDispatch,
+    /// 释放对设备的独占(EVIOCGRAB 等)
+    ReleaseGrabs,
+    /// 销毁 uinput 虚拟设备
+    DestroyVirtualDevices,
+    /// 销毁 overlay surface
+    DestroyOverlays,
+    /// 保存设备注册表和日志
+    Persist,
+}
+
+impl ShutdownStage {
+    pub const ALL: [ShutdownStage; 6] = [
+        ShutdownStage::StopInput,
+        ShutdownStage::FlushDispatch,
+        ShutdownStage::ReleaseGrabs,
+        ShutdownStage::DestroyVirtualDevices,
+        ShutdownStage::DestroyOverlays,
+        ShutdownStage::Persist,
+    ];
+}
+
+type HookFuture = Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>;
+
+struct Hook {
+    stage: ShutdownStage,
+    name: String,
+    run: Box<dyn FnOnce() -> HookFuture + Send>,
+}
+
+/// 单个清理步骤的结果
+#[derive(Debug)]
+pub enum HookOutcome {
+    Done,
+    Failed(anyhow::Error),
+    /// 总超时已到，这个步骤没有完成(或没有开始)，已被取消
+    TimedOut,
+}
+
+/// 退出清理的报告
+#[derive(Debug, Default)]
+pub struct ShutdownReport {
+    pub hooks: Vec<(ShutdownStage, String, HookOutcome)>,
+    pub elapsed: Duration,
+}
+
+impl ShutdownReport {
+    /// 所有步骤都已完成
+    pub fn is_clean(&self) -> bool {
+        self.hooks
+            .iter()
+            .all(|(_, _, outcome)| matches!(outcome, HookOutcome::Done))
+    }
+}
+
+impl fmt::Display for ShutdownReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "退出用时 {:.2} 秒", self.elapsed.as_secs_f32())?;
+        for (stage, name, outcome) in &self.hooks {
+            write!(f, "\n  {stage:?} {name}: ")?;
+            match outcome {
+                HookOutcome::Done => write!(f, "完成")?,
+                HookOutcome::Failed(e) => write!(f, "失败: {e:#}")?,
+                HookOutcome::TimedOut => write!(f, "超时")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 退出信号，子系统用它得知需要停止接收新事件
+#[derive(Clone)]
+pub struct ShutdownSignal(watch::Receiver<bool>);
+
+impl ShutdownSignal {
+    /// `receiver` 变为 `true` 时触发
+    pub(crate) fn new(receiver: watch::Receiver<bool>) -> Self {
+        Self(receiver)
+    }
+
+    /// 是否已经开始退出
+    pub fn is_shutting_down(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// 等待退出开始
+    pub async fn wait(&mut self) {
+        let _ = self.0.wait_for(|stopping| *stopping).await;
+    }
+}
+
+/// 协调各子系统的退出清理
+pub struct ShutdownCoordinator {
+    hooks: Vec<Hook>,
+    signal_tx: watch::Sender<bool>,
+    timeout: Duration,
+}
+
+impl ShutdownCoordinator {
+    /// `timeout` 是整个退出过程的上限
+    pub fn new(timeout: Duration) -> Self {
+        let (signal_tx, _) = watch::channel(false);
+        Self {
+            hooks: Vec::new(),
+            signal_tx,
+            timeout,
+        }
+    }
+
+    /// 获取退出信号
+    pub fn signal(&self) -> ShutdownSignal {
+        ShutdownSignal(self.signal_tx.subscribe())
+    }
+
+    /// 注册一个清理步骤，同一阶段的步骤会并发执行
+    pub fn register<F, Fut>(&mut self, stage: ShutdownStage, name: impl Into<String>, run: F)
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        self.hooks.push(Hook {
+            stage,
+            name: name.into(),
+            run: Box::new(move || Box::pin(run())),
+        });
+    }
+
+    /// 通知所有子系统开始退出，并按阶段执行清理
+    pub async fn shutdown(self) -> ShutdownReport {
+        let start = Instant::now();
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        let _ = self.signal_tx.send(true);
+
+        let mut report = ShutdownReport::default();
+        let mut hooks = self.hooks;
+        for stage in ShutdownStage::ALL {
+            let (current, rest): (Vec<_>, Vec<_>) =
+                hooks.into_iter().partition(|hook| hook.stage == stage);
+            hooks = rest;
+
+            let mut tasks = Vec::new();
+            for hook in current {
+                let handle = tokio::spawn((hook.run)());
+                tasks.push((hook.name, handle));
+            }
+            for (name, mut handle) in tasks {
+                let outcome = match tokio::time::timeout_at(deadline, &mut handle).await {
+                    Ok(Ok(Ok(()))) => HookOutcome::Done,
+                    Ok(Ok(Err(e))) => HookOutcome::Failed(e),
+                    Ok(Err(e)) => HookOutcome::Failed(e.into()),
+                    Err(_) => {
+                        // 不取消的话卡住的步骤会在退出之后继续运行
+                        handle.abort();
+                        HookOutcome::TimedOut
+                    }
+                };
+                report.hooks.push((stage, name, outcome));
+            }
+        }
+
+        report.elapsed = start.elapsed();
+        report
+    }
+}
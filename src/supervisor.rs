@@ -0,0 +1,207 @@
+//! 子系统的监督
+//!
+//! 每个子系统由一个启动函数创建. 子系统返回错误或者 panic 时按指数退避重新启动,
+//! [`CRASH_WINDOW`] 内崩溃超过 [`MAX_CRASHES`] 次就放弃, 保持停止直到退出.
+//! 重启也无法恢复的错误(见 [`crate::error::TabletdError::is_recoverable`])不重启.
+//!
+//! 收到 SIGTERM 或 SIGINT 后交给 [`ShutdownCoordinator`] 按阶段退出: 到达子系统注册的
+//! [`ShutdownStage`] 时通知它停止并等它结束, 所以输入先于分发停止, 虚拟设备在事件发完后才释放
+
+use std::{any::Any, collections::VecDeque, future::Future, time::Duration};
+
+use anyhow::anyhow;
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    sync::watch,
+    task::AbortHandle,
+    time::Instant,
+};
+use tracing::{Instrument, error, info, info_span, warn};
+
+use crate::{
+    daemon::Subsystem,
+    error::ErrorReport,
+    event_dispatcher::api::ApiServer,
+    hud_interface::{
+        HudEvent, HudSender,
+        notification::{Notification, NotificationLevel},
+    },
+    shutdown::{ShutdownCoordinator, ShutdownReport, ShutdownSignal, ShutdownStage},
+};
+
+/// 第一次重启前的等待时间, 之后每次崩溃翻倍
+const RESTART_MIN: Duration = Duration::from_millis(500);
+const RESTART_MAX: Duration = Duration::from_secs(30);
+/// 统计崩溃次数的时间范围, 运行超过这个时间后退避重新开始计算
+pub const CRASH_WINDOW: Duration = Duration::from_secs(60);
+/// [`CRASH_WINDOW`] 内最多重启的次数
+pub const MAX_CRASHES: usize = 5;
+
+/// 启动子系统并在崩溃后重启
+pub struct Supervisor {
+    coordinator: ShutdownCoordinator,
+    hud: Option<HudSender>,
+    api: Option<ApiServer>,
+}
+
+impl Supervisor {
+    pub fn new(coordinator: ShutdownCoordinator) -> Self {
+        Self {
+            coordinator,
+            hud: None,
+            api: None,
+        }
+    }
+
+    /// 子系统崩溃和放弃重启时在 HUD 上提示
+    pub fn set_hud(&mut self, hud: HudSender) {
+        self.hud = Some(hud);
+    }
+
+    /// 子系统出错时告诉 `tabletd API` 的客户端
+    pub fn set_api(&mut self, api: ApiServer) {
+        self.api = Some(api);
+    }
+
+    /// 注册子系统以外的清理步骤
+    pub fn coordinator(&mut self) -> &mut ShutdownCoordinator {
+        &mut self.coordinator
+    }
+
+    /// 立刻启动子系统, 退出到达 `stage` 时通知它停止
+    ///
+    /// `start` 每次(重新)启动时调用, 参数在需要停止时触发. 子系统正常返回后不再重启
+    pub fn supervise<F, Fut>(&mut self, subsystem: Subsystem, stage: ShutdownStage, start: F)
+    where
+        F: FnMut(ShutdownSignal) -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let stop = ShutdownSignal::new(stop_rx);
+        let handle = tokio::spawn(
+            keep_running(subsystem, start, stop, self.hud.clone(), self.api.clone())
+                .instrument(info_span!("subsystem", name = subsystem.name())),
+        );
+        self.coordinator
+            .register(stage, subsystem.name(), move || async move {
+                let _abort = AbortOnDrop(handle.abort_handle());
+                let _ = stop_tx.send(true);
+                handle.await?;
+                Ok(())
+            });
+    }
+
+    /// 等待 SIGTERM 或 SIGINT, 然后按阶段停止所有子系统
+    pub async fn run(self) -> anyhow::Result<ShutdownReport> {
+        let mut terminate = signal(SignalKind::terminate())?;
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        tokio::select! {
+            _ = terminate.recv() => info!("收到 SIGTERM, 正在退出"),
+            _ = interrupt.recv() => info!("收到 SIGINT, 正在退出"),
+        }
+        Ok(self.coordinator.shutdown().await)
+    }
+}
+
+/// 丢弃时取消任务: 停止子系统的清理步骤超时被取消时, 子系统的监督任务和运行它的任务也一起取消
+struct AbortOnDrop(AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// 运行子系统直到需要停止, 崩溃时重启
+async fn keep_running<F, Fut>(
+    subsystem: Subsystem,
+    mut start: F,
+    mut stop: ShutdownSignal,
+    hud: Option<HudSender>,
+    api: Option<ApiServer>,
+) where
+    F: FnMut(ShutdownSignal) -> Fut + Send + 'static,
+    Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+{
+    let name = subsystem.name();
+    let notify = |level, text: String| {
+        if let Some(hud) = &hud {
+            let _ = hud.send(HudEvent::Notify(Notification::new(level, text)));
+        }
+    };
+    let mut crashes: VecDeque<Instant> = VecDeque::new();
+    let mut delay = RESTART_MIN;
+    loop {
+        let started = Instant::now();
+        // 放到单独的任务里, panic 不会影响其他子系统. 这个任务被取消时子系统的任务也一起取消
+        let task = tokio::spawn(start(stop.clone()).in_current_span());
+        let _abort = AbortOnDrop(task.abort_handle());
+        let result = match task.await {
+            Ok(result) => result,
+            Err(e) if e.is_panic() => Err(anyhow!("panic: {}", panic_message(&*e.into_panic()))),
+            Err(e) => Err(e.into()),
+        };
+        if stop.is_shutting_down() {
+            if let Err(e) = result {
+                warn!("{name} 停止时出错: {e:#}");
+            }
+            return;
+        }
+        let error = match result {
+            Ok(()) => {
+                info!("{name} 已结束");
+                return;
+            }
+            Err(e) => ErrorReport::from_anyhow(e).with_subsystem(name),
+        };
+        if let Some(api) = &api {
+            api.report_error(error.clone());
+        }
+        if !error.recoverable {
+            error!("{name} 出错, 重启也无法恢复: {}", error.message);
+            notify(NotificationLevel::Error, format!("{name} 出错, 已停止"));
+            return;
+        }
+
+        let now = Instant::now();
+        while crashes
+            .front()
+            .is_some_and(|crash| now - *crash > CRASH_WINDOW)
+        {
+            crashes.pop_front();
+        }
+        crashes.push_back(now);
+        if crashes.len() > MAX_CRASHES {
+            error!(
+                "{name} 在 {} 秒内崩溃了 {} 次, 不再重启: {}",
+                CRASH_WINDOW.as_secs(),
+                crashes.len(),
+                error.message
+            );
+            notify(NotificationLevel::Error, format!("{name} 多次崩溃, 已停止"));
+            return;
+        }
+        if now - started >= CRASH_WINDOW {
+            delay = RESTART_MIN;
+        }
+        warn!(
+            "{name} 崩溃, {:.1} 秒后重启: {}",
+            delay.as_secs_f32(),
+            error.message
+        );
+        notify(NotificationLevel::Warning, format!("{name} 崩溃, 正在重启"));
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = stop.wait() => return,
+        }
+        delay = (delay * 2).min(RESTART_MAX);
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("(无法显示的 panic 信息)")
+}
@@ -0,0 +1,37 @@
+//! 驱动的错误
+
+use std::{io, path::PathBuf};
+
+use thiserror::Error;
+
+use crate::error::ErrorKind;
+
+#[derive(Debug, Error)]
+pub enum DriverError {
+    #[error("无法打开 {}", path.display())]
+    Open {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    /// 读取报告失败, 设备断开时不算错误
+    #[error("无法读取 {}", path.display())]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
+
+impl DriverError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            DriverError::Open { source, .. } | DriverError::Read { source, .. }
+                if source.kind() == io::ErrorKind::PermissionDenied =>
+            {
+                ErrorKind::Permission
+            }
+            _ => ErrorKind::Device,
+        }
+    }
+}
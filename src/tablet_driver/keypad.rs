@@ -0,0 +1,98 @@
+//! 数位板配套的按键设备, 如 Huion Keydial
+//!
+//! 按键设备只有按键和滚轮, 报告格式和数位板的快捷键报告一样简单, 所以同样在设备描述文件中
+//! 用 [`PadLayout`] 描述:
+//!
+//! ```toml
+//! [[keypad]]
+//! name = "Huion Keydial"
+//! # 用 `lsusb` 查看
+//! vendor_id = 0x256c
+//! product_id = 0x0000
+//!
+//! [keypad.pad]
+//! select = [{ byte = 0, value = 0x08 }, { byte = 1, value = 0xe0 }]
+//! buttons = { byte = 4, size = 3 }
+//! button_count = 18
+//! wheel = { byte = 7, signed = true }
+//! ```
+//!
+//! 按键设备有自己的 [`TabletId`](crate::event_model::tablet::TabletId), 默认作为独立的设备,
+//! 使用自己的设置. 在配置文件中用 `[[keypad]]` 和一块数位板编为一组后, 改用那块数位板的
+//! 按键和滚轮绑定, 见 [`crate::config::KeypadConfig`]
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::event_model::{capability::DeviceCapabilities, event::TabletEvent};
+
+use super::spec::{PadLayout, PadState, SpecFile};
+
+/// 一个表驱动支持的按键设备
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeypadSpec {
+    pub name: String,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    /// 只使用这个 USB 接口的 hidraw 节点, 不设置时使用所有接口
+    #[serde(default)]
+    pub interface: Option<u8>,
+    pub pad: PadLayout,
+}
+
+impl KeypadSpec {
+    /// 从设备描述文件读取按键设备列表
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Vec<KeypadSpec>> {
+        Ok(SpecFile::load(path)?.keypad)
+    }
+
+    pub fn is_model(&self, vendor_id: u16, product_id: u16) -> bool {
+        self.vendor_id == vendor_id && self.product_id == product_id
+    }
+}
+
+/// 在 `keypads` 中查找 USB 接口 `interface` 上的按键设备
+pub fn find(
+    vendor_id: u16,
+    product_id: u16,
+    interface: Option<u8>,
+    keypads: &[KeypadSpec],
+) -> Option<&KeypadSpec> {
+    keypads.iter().find(|spec| {
+        spec.is_model(vendor_id, product_id)
+            && spec
+                .interface
+                .is_none_or(|wanted| interface == Some(wanted))
+    })
+}
+
+/// 按 [`KeypadSpec`] 解析报告
+pub struct KeypadParser {
+    spec: KeypadSpec,
+    pad: PadState,
+}
+
+impl KeypadParser {
+    pub fn new(spec: KeypadSpec) -> Self {
+        Self {
+            spec,
+            pad: PadState::default(),
+        }
+    }
+
+    pub fn spec(&self) -> &KeypadSpec {
+        &self.spec
+    }
+
+    pub fn capabilities(&self) -> DeviceCapabilities {
+        DeviceCapabilities::keypad()
+    }
+
+    pub fn parse(&mut self, data: &[u8]) -> Vec<TabletEvent> {
+        if !self.spec.pad.select.iter().all(|s| s.matches(data)) {
+            return Vec::new();
+        }
+        self.pad.parse(&self.spec.pad, data)
+    }
+}
@@ -0,0 +1,42 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// 数位板上的模式指示灯
+pub trait ModeLed {
+    /// 点亮代表 `bank` 的指示灯
+    fn show_bank(&mut self, bank: u8) -> anyhow::Result<()>;
+}
+
+/// 通过 sysfs 控制的状态指示灯(`wacom_led/status_led0_select`)
+pub struct SysfsStatusLed {
+    path: PathBuf,
+}
+
+impl SysfsStatusLed {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        anyhow::ensure!(path.exists(), "指示灯节点不存在: {}", path.display());
+        Ok(Self { path })
+    }
+
+    /// hidraw 节点(比如 `/dev/hidraw3`)所在的 HID 设备的状态指示灯
+    ///
+    /// 这个 sysfs 节点由内核的 wacom 驱动创建. tabletd 直接解析 hidraw 报告, 但不会解绑内核驱动
+    /// (独占的只是 evdev 节点), 所以驱动加载时指示灯照常可用. 驱动没有绑定这个设备(没有加载
+    /// hid-wacom, 或者不是 Wacom 的设备)时返回 `None`, 模式组只显示在 HUD 上
+    pub fn for_hidraw(node: &Path) -> Option<Self> {
+        let path = Path::new("/sys/class/hidraw")
+            .join(node.file_name()?)
+            .join("device/wacom_led/status_led0_select");
+        path.exists().then_some(Self { path })
+    }
+}
+
+impl ModeLed for SysfsStatusLed {
+    fn show_bank(&mut self, bank: u8) -> anyhow::Result<()> {
+        fs::write(&self.path, bank.to_string())?;
+        Ok(())
+    }
+}
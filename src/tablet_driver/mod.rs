@@ -0,0 +1,231 @@
+use nix::libc;
+use tracing::{info, warn};
+
+use crate::{
+    event_model::{
+        capability::DeviceCapabilities, event::TabletEvent, stamp::EventSequence, tablet::TabletId,
+    },
+    event_router::{EventSender, InputEvent},
+    input_devices::{hidraw::HidrawNode, identity::Fingerprint},
+    logging::HidDump,
+};
+
+use error::DriverError;
+use keypad::KeypadParser;
+use quirks::DeviceQuirk;
+use spec::{DeviceSpec, SpecParser};
+use stats::StatsTracker;
+use uclogic::UclogicParser;
+use wacom::IntuosParser;
+
+/// 驱动的错误
+pub mod error;
+/// 配套的按键设备
+pub mod keypad;
+/// 模式指示灯
+pub mod led;
+/// 设备的初始化序列和参数修正
+pub mod quirks;
+/// 表驱动的报告解析
+pub mod spec;
+/// 报告率、抖动和断档的统计
+pub mod stats;
+/// Huion / Gaomon / XP-Pen
+pub mod uclogic;
+/// Wacom Intuos 系列原生协议
+pub mod wacom;
+
+/// 把某种数位板的 HID 报告解析为 [`TabletEvent`]
+pub trait ReportParser: Send {
+    /// 设备名称
+    fn name(&self) -> &str;
+
+    fn capabilities(&self) -> DeviceCapabilities;
+
+    /// 解析一个 HID 报告，一个报告可能对应零个或多个事件
+    fn parse(&mut self, report: &[u8]) -> Vec<TabletEvent>;
+}
+
+impl ReportParser for IntuosParser {
+    fn name(&self) -> &str {
+        self.model().name
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        self.model().capabilities()
+    }
+
+    fn parse(&mut self, report: &[u8]) -> Vec<TabletEvent> {
+        IntuosParser::parse(self, report)
+    }
+}
+
+impl ReportParser for SpecParser {
+    fn name(&self) -> &str {
+        &self.spec().name
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let pen = &self.spec().pen;
+        DeviceCapabilities {
+            tilt: pen.tilt_x.is_some() || pen.tilt_y.is_some(),
+            eraser: pen.eraser.is_some(),
+            ..self.spec().capabilities.clone()
+        }
+    }
+
+    fn parse(&mut self, report: &[u8]) -> Vec<TabletEvent> {
+        SpecParser::parse(self, report)
+    }
+}
+
+impl ReportParser for UclogicParser {
+    fn name(&self) -> &str {
+        UclogicParser::name(self)
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        self.params().capabilities.clone()
+    }
+
+    fn parse(&mut self, report: &[u8]) -> Vec<TabletEvent> {
+        UclogicParser::parse(self, report)
+    }
+}
+
+impl ReportParser for KeypadParser {
+    fn name(&self) -> &str {
+        &self.spec().name
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        KeypadParser::capabilities(self)
+    }
+
+    fn parse(&mut self, report: &[u8]) -> Vec<TabletEvent> {
+        KeypadParser::parse(self, report)
+    }
+}
+
+/// 按 VID/PID 为设备选择解析器
+///
+/// 有原生协议实现的设备优先，其次是 `specs` 中描述的设备.
+/// UC-Logic 系列的设备会先切换到完整数位板模式，没有对应 spec 时使用设备自己报告的参数
+pub fn parser_for(
+    vendor_id: u16,
+    product_id: u16,
+    specs: &[DeviceSpec],
+) -> Option<Box<dyn ReportParser>> {
+    select_parser(vendor_id, product_id, specs, None)
+}
+
+/// 和 [`parser_for`] 相同, 之后按 `quirks` 中匹配的怪癖向节点发送初始化序列、修正设备的参数
+pub fn parser_for_node(
+    node: &HidrawNode,
+    specs: &[DeviceSpec],
+    quirks: &[DeviceQuirk],
+) -> Option<Box<dyn ReportParser>> {
+    select_parser(node.vendor_id, node.product_id, specs, Some((node, quirks)))
+}
+
+fn select_parser(
+    vendor_id: u16,
+    product_id: u16,
+    specs: &[DeviceSpec],
+    node: Option<(&HidrawNode, &[DeviceQuirk])>,
+) -> Option<Box<dyn ReportParser>> {
+    if let Some(model) = wacom::find_model(vendor_id, product_id) {
+        return Some(Box::new(IntuosParser::new(*model)));
+    }
+    let params = if uclogic::is_uclogic(vendor_id) {
+        uclogic::init(vendor_id, product_id)
+            .inspect_err(|e| warn!("初始化 {vendor_id:04x}:{product_id:04x} 失败: {e}"))
+            .ok()
+    } else {
+        None
+    };
+    let firmware = params
+        .as_ref()
+        .and_then(|params| params.firmware.as_deref());
+    let quirk = node.and_then(|(node, quirks)| {
+        let quirk = quirks::find(quirks, vendor_id, product_id, firmware)?;
+        match quirk.init(node) {
+            Ok(()) if quirk.init.is_empty() => {}
+            Ok(()) => info!("已按 {} 的怪癖初始化 {}", quirk.name(), node.path.display()),
+            Err(e) => warn!("{}: {e:#}", quirk.name()),
+        }
+        Some(quirk)
+    });
+    if let Some(spec) = specs
+        .iter()
+        .find(|spec| spec.vendor_id == vendor_id && spec.product_id == product_id)
+    {
+        let mut spec = spec.clone();
+        if let Some(quirk) = quirk {
+            quirk.apply(&mut spec.capabilities);
+            if let (Some(button_count), Some(pad)) = (quirk.button_count, &mut spec.pad) {
+                pad.button_count = button_count;
+            }
+        }
+        return Some(Box::new(SpecParser::new(spec)));
+    }
+    let params = match quirk {
+        Some(quirk) => quirk.params(params),
+        None => params,
+    };
+    params.map(|params| {
+        let name = params
+            .firmware
+            .clone()
+            .or_else(|| quirk.and_then(|quirk| quirk.name.clone()))
+            .unwrap_or_else(|| format!("UC-Logic {vendor_id:04x}:{product_id:04x}"));
+        Box::new(UclogicParser::new(name, params)) as Box<dyn ReportParser>
+    })
+}
+
+/// 从 hidraw 节点读取报告，解析后发往 `event_router`, 每个报告记入 `stats`
+///
+/// 设备断开或者路由器关闭时返回
+pub async fn run(
+    node: HidrawNode,
+    mut parser: Box<dyn ReportParser>,
+    tablet: TabletId,
+    events: EventSender,
+    mut stats: StatsTracker,
+) -> Result<(), DriverError> {
+    let transport = Fingerprint::from_hidraw(&node).transport;
+    let mut reports = node.spawn_reader().map_err(|source| DriverError::Open {
+        path: node.path.clone(),
+        source,
+    })?;
+    let mut sequence = EventSequence::new();
+    let mut dump = HidDump::new();
+    while let Some(report) = reports.recv().await {
+        let (timestamp, report) = match report {
+            Ok(report) => report,
+            // 设备拔出后读取返回 ENODEV 或 EIO
+            Err(e) if matches!(e.raw_os_error(), Some(libc::ENODEV | libc::EIO)) => break,
+            Err(source) => {
+                return Err(DriverError::Read {
+                    path: node.path.clone(),
+                    source,
+                });
+            }
+        };
+        dump.report(&report);
+        let parsed = parser.parse(&report);
+        stats.record(timestamp, &parsed);
+        for event in parsed {
+            let event = InputEvent {
+                tablet,
+                transport,
+                event,
+                stamp: sequence.stamp(timestamp),
+            };
+            if events.send(event).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
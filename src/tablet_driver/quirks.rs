@@ -0,0 +1,197 @@
+//! 设备怪癖: 初始化序列和参数修正
+//!
+//! 有些 Huion / Gaomon 数位板读取参数描述符后仍然停留在"鼠标模拟"模式, 需要再发送厂商自定义的
+//! feature 报告才会切换到完整数位板模式; 有些固件报告的参数不对(压感级数、快捷键数量等), 或者
+//! 根本读不到参数. 这些设备在设备描述文件中写一条 `[[quirk]]`, 接入时依次执行 `init`,
+//! 再用这里的值修正设备报告的参数. 参数描述符读取失败时, 写全了 `protocol`、`max_x`、`max_y`、
+//! `max_pressure`、`lpi` 的设备仍然可以按 UC-Logic 协议使用
+//!
+//! ```toml
+//! [[quirk]]
+//! name = "Gaomon (新固件)"
+//! vendor_id = 0x256c
+//! product_id = 0x006d
+//! firmware = "GM001_"
+//! init = [{ string_descriptor = 200 }, { set_feature = [0x02, 0xb1, 0x04] }, { delay_ms = 50 }]
+//! max_pressure = 8191
+//! button_count = 4
+//! ```
+
+use std::{fs::File, io::Write, os::fd::AsRawFd, path::Path, time::Duration};
+
+use anyhow::{Context, ensure};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::{
+    event_model::capability::DeviceCapabilities,
+    input_devices::{hidraw::HidrawNode, privilege},
+};
+
+use super::{
+    spec::SpecFile,
+    uclogic::{self, Protocol, UclogicParams},
+};
+
+nix::ioctl_readwrite_buf!(hid_set_feature, b'H', 0x06, u8);
+nix::ioctl_readwrite_buf!(hid_get_feature, b'H', 0x07, u8);
+
+/// 初始化序列中的一步
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InitStep {
+    /// 发送 feature 报告, 第一个字节是报告 ID
+    SetFeature(Vec<u8>),
+    /// 读取 feature 报告, 有些固件读取后才切换模式. 读到的内容只记录在日志中
+    GetFeature { report_id: u8, len: usize },
+    /// 发送 output 报告, 第一个字节是报告 ID
+    Output(Vec<u8>),
+    /// 读取 USB 字符串描述符
+    StringDescriptor(u8),
+    /// 等待固件切换模式
+    DelayMs(u64),
+}
+
+impl InitStep {
+    fn run(&self, file: &mut File, node: &HidrawNode) -> anyhow::Result<()> {
+        match self {
+            InitStep::SetFeature(data) => {
+                ensure!(!data.is_empty(), "feature 报告是空的");
+                let mut buf = data.clone();
+                // SAFETY: 缓冲区的长度和 ioctl 编码的长度一致
+                unsafe { hid_set_feature(file.as_raw_fd(), &mut buf) }?;
+            }
+            InitStep::GetFeature { report_id, len } => {
+                let mut buf = vec![0; (*len).max(1)];
+                buf[0] = *report_id;
+                // SAFETY: 同上
+                let read = unsafe { hid_get_feature(file.as_raw_fd(), &mut buf) }?;
+                let read = usize::try_from(read).unwrap_or_default().min(buf.len());
+                debug!("feature 报告 {report_id:#04x}: {:02x?}", &buf[..read]);
+            }
+            InitStep::Output(data) => {
+                ensure!(!data.is_empty(), "output 报告是空的");
+                file.write_all(data)?;
+            }
+            InitStep::StringDescriptor(index) => {
+                let handle = rusb::open_device_with_vid_pid(node.vendor_id, node.product_id)
+                    .context("找不到 USB 设备")?;
+                uclogic::read_string(&handle, *index)?;
+            }
+            InitStep::DelayMs(ms) => std::thread::sleep(Duration::from_millis(*ms)),
+        }
+        Ok(())
+    }
+}
+
+/// 一种设备的怪癖, 在设备描述文件中写作 `[[quirk]]`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceQuirk {
+    /// 写在日志中的名称
+    pub name: Option<String>,
+    pub vendor_id: u16,
+    /// 不设置时匹配这个厂商的所有设备
+    pub product_id: Option<u16>,
+    /// 固件版本的前缀. Huion 的很多型号共用一个 PID, 只能按固件区分
+    pub firmware: Option<String>,
+    /// 打开 hidraw 节点后依次执行
+    #[serde(default)]
+    pub init: Vec<InitStep>,
+    /// 读不到参数描述符时使用的协议
+    pub protocol: Option<Protocol>,
+    pub max_x: Option<u32>,
+    pub max_y: Option<u32>,
+    pub max_pressure: Option<u32>,
+    /// 分辨率, 每英寸的线数
+    pub lpi: Option<u32>,
+    /// 快捷键数量
+    pub button_count: Option<u8>,
+}
+
+impl DeviceQuirk {
+    /// 从设备描述文件读取
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Vec<DeviceQuirk>> {
+        Ok(SpecFile::load(path)?.quirk)
+    }
+
+    /// 设置了 `firmware` 时, 读不到固件版本的设备不匹配
+    pub fn matches(&self, vendor_id: u16, product_id: u16, firmware: Option<&str>) -> bool {
+        self.vendor_id == vendor_id
+            && self.product_id.is_none_or(|id| id == product_id)
+            && self
+                .firmware
+                .as_deref()
+                .is_none_or(|prefix| firmware.is_some_and(|firmware| firmware.starts_with(prefix)))
+    }
+
+    pub fn name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| match self.product_id {
+            Some(product_id) => format!("{:04x}:{product_id:04x}", self.vendor_id),
+            None => format!("{:04x}", self.vendor_id),
+        })
+    }
+
+    /// 依次执行初始化序列, 没有序列时不打开节点
+    pub fn init(&self, node: &HidrawNode) -> anyhow::Result<()> {
+        if self.init.is_empty() {
+            return Ok(());
+        }
+        let mut file = privilege::open_hidraw(&node.path)
+            .with_context(|| format!("无法打开 {}", node.path.display()))?;
+        for (index, step) in self.init.iter().enumerate() {
+            step.run(&mut file, node)
+                .with_context(|| format!("初始化序列第 {} 步 {step:?} 失败", index + 1))?;
+        }
+        Ok(())
+    }
+
+    /// 修正设备报告的参数
+    pub fn apply(&self, capabilities: &mut DeviceCapabilities) {
+        if let Some(max_x) = self.max_x {
+            capabilities.max_x = max_x;
+        }
+        if let Some(max_y) = self.max_y {
+            capabilities.max_y = max_y;
+        }
+        if let Some(max_pressure) = self.max_pressure {
+            capabilities.max_pressure = max_pressure;
+        }
+        if let Some(lpi) = self.lpi {
+            let per_mm = (lpi as f64 / 25.4).round() as u32;
+            capabilities.resolution_x = per_mm;
+            capabilities.resolution_y = per_mm;
+        }
+    }
+
+    /// 修正 UC-Logic 参数. 没有读到参数时, 按这里写的协议和参数构造
+    pub fn params(&self, params: Option<UclogicParams>) -> Option<UclogicParams> {
+        let mut params = match params {
+            Some(params) => params,
+            None => uclogic::params(
+                self.protocol?,
+                self.max_x?,
+                self.max_y?,
+                self.max_pressure?,
+                self.lpi?,
+            ),
+        };
+        self.apply(&mut params.capabilities);
+        if let Some(button_count) = self.button_count {
+            params.button_count = button_count;
+        }
+        Some(params)
+    }
+}
+
+/// 在 `quirks` 中查找设备的怪癖, 后写的优先
+pub fn find<'a>(
+    quirks: &'a [DeviceQuirk],
+    vendor_id: u16,
+    product_id: u16,
+    firmware: Option<&str>,
+) -> Option<&'a DeviceQuirk> {
+    quirks
+        .iter()
+        .rev()
+        .find(|quirk| quirk.matches(vendor_id, product_id, firmware))
+}
@@ -0,0 +1,392 @@
+//! 表驱动的 HID 报告解析
+//!
+//! 大部分便宜的数位板报告格式都很简单: 固定位置的坐标、压力和按键位.
+//! 这类设备不需要写代码，在配置文件里描述报告布局就能支持:
+//!
+//! ```toml
+//! [[device]]
+//! name = "Huion H640P"
+//! vendor_id = 0x256c
+//! product_id = 0x006d
+//! capabilities = { max_x = 32000, max_y = 20000, resolution_x = 200, resolution_y = 200, max_pressure = 8191 }
+//!
+//! [device.pen]
+//! select = [{ byte = 0, value = 0x08 }, { byte = 1, mask = 0xf0, value = 0x80 }]
+//! in_range = { byte = 1, bit = 7 }
+//! tip = { byte = 1, bit = 0 }
+//! barrel = [{ byte = 1, bit = 1 }, { byte = 1, bit = 2 }]
+//! x = { byte = 2, size = 2, high_byte = 8 }
+//! y = { byte = 4, size = 2, high_byte = 9 }
+//! pressure = { byte = 6, size = 2 }
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    event_model::{
+        capability::{DEFAULT_MAX_TILT, DeviceCapabilities, DeviceClass},
+        event::{
+            AuxButtonEvent, PenButton, PenLocation, PenState, TabletEvent, Tilt, ToolType,
+            WheelEvent,
+        },
+    },
+};
+
+use super::{keypad::KeypadSpec, quirks::DeviceQuirk};
+
+/// 用于判断报告类型的字节匹配
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Selector {
+    pub byte: usize,
+    #[serde(default = "default_mask")]
+    pub mask: u8,
+    pub value: u8,
+}
+
+fn default_mask() -> u8 {
+    0xff
+}
+
+impl Selector {
+    pub(super) fn matches(&self, data: &[u8]) -> bool {
+        data.get(self.byte)
+            .is_some_and(|byte| byte & self.mask == self.value)
+    }
+}
+
+/// 报告中的一个标志位
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Flag {
+    pub byte: usize,
+    pub bit: u8,
+}
+
+impl Flag {
+    fn read(&self, data: &[u8]) -> bool {
+        data.get(self.byte)
+            .is_some_and(|byte| byte & (1 << self.bit) != 0)
+    }
+}
+
+/// 报告中的一个小端整数
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Field {
+    pub byte: usize,
+    /// 字节数
+    #[serde(default = "default_size")]
+    pub size: usize,
+    #[serde(default)]
+    pub signed: bool,
+    /// 额外的高位字节(有些大尺寸数位板把坐标的第三个字节放在报告末尾)
+    #[serde(default)]
+    pub high_byte: Option<usize>,
+}
+
+fn default_size() -> usize {
+    1
+}
+
+impl Field {
+    fn read(&self, data: &[u8]) -> Option<i64> {
+        let bytes = data.get(self.byte..self.byte + self.size)?;
+        let mut value = bytes
+            .iter()
+            .rev()
+            .fold(0u64, |acc, byte| acc << 8 | *byte as u64);
+        let mut bits = self.size * 8;
+        if let Some(high) = self.high_byte {
+            value |= (*data.get(high)? as u64) << bits;
+            bits += 8;
+        }
+        if self.signed && bits < 64 && value & (1 << (bits - 1)) != 0 {
+            Some(value as i64 - (1i64 << bits))
+        } else {
+            Some(value as i64)
+        }
+    }
+}
+
+/// 笔报告的布局
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PenLayout {
+    /// 所有条件都满足时才是笔报告
+    pub select: Vec<Selector>,
+    pub in_range: Flag,
+    pub tip: Option<Flag>,
+    /// 笔杆上的按键, 依次是靠近笔尖的按键和上面的按键
+    #[serde(default)]
+    pub barrel: Vec<Flag>,
+    pub eraser: Option<Flag>,
+    pub x: Field,
+    pub y: Field,
+    pub pressure: Field,
+    pub tilt_x: Option<Field>,
+    pub tilt_y: Option<Field>,
+}
+
+/// 快捷键报告的布局
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PadLayout {
+    pub select: Vec<Selector>,
+    /// 按键位图, 第 n 位对应按键 n
+    pub buttons: Field,
+    pub button_count: u8,
+    /// 相对滚轮, 正数为顺时针
+    pub wheel: Option<Field>,
+}
+
+/// 一个表驱动支持的设备
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceSpec {
+    pub name: String,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub capabilities: DeviceCapabilities,
+    pub pen: PenLayout,
+    pub pad: Option<PadLayout>,
+}
+
+/// 设备描述文件, 数位板写作 `[[device]]`, 按键设备写作 `[[keypad]]`, 设备怪癖写作 `[[quirk]]`
+#[derive(Deserialize)]
+pub(super) struct SpecFile {
+    #[serde(default)]
+    pub(super) device: Vec<DeviceSpec>,
+    #[serde(default)]
+    pub(super) keypad: Vec<KeypadSpec>,
+    #[serde(default)]
+    pub(super) quirk: Vec<DeviceQuirk>,
+}
+
+impl SpecFile {
+    pub(super) fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&text)?)
+    }
+}
+
+impl DeviceSpec {
+    /// 默认的设备描述文件: `$XDG_CONFIG_HOME/tabletd/devices.toml`
+    pub fn default_path() -> PathBuf {
+        Config::default_path().with_file_name("devices.toml")
+    }
+
+    /// 从 TOML 文件读取设备列表
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Vec<DeviceSpec>> {
+        Ok(SpecFile::load(path)?.device)
+    }
+}
+
+/// 内置的设备列表
+pub fn builtin_specs() -> Vec<DeviceSpec> {
+    vec![DeviceSpec {
+        // Huion 的很多型号共用这个 PID，切换到完整数位板模式后报告格式相同
+        name: "Huion H640P".to_string(),
+        vendor_id: 0x256c,
+        product_id: 0x006d,
+        capabilities: DeviceCapabilities {
+            max_x: 32000,
+            max_y: 20000,
+            resolution_x: 200,
+            resolution_y: 200,
+            max_pressure: 8191,
+            tilt: false,
+            rotation: false,
+            eraser: false,
+            max_tilt: DEFAULT_MAX_TILT,
+            class: DeviceClass::Tablet,
+        },
+        pen: PenLayout {
+            select: vec![
+                Selector {
+                    byte: 0,
+                    mask: 0xff,
+                    value: 0x08,
+                },
+                Selector {
+                    byte: 1,
+                    mask: 0xf0,
+                    value: 0x80,
+                },
+            ],
+            in_range: Flag { byte: 1, bit: 7 },
+            tip: Some(Flag { byte: 1, bit: 0 }),
+            barrel: vec![Flag { byte: 1, bit: 1 }, Flag { byte: 1, bit: 2 }],
+            eraser: None,
+            x: Field {
+                byte: 2,
+                size: 2,
+                signed: false,
+                high_byte: Some(8),
+            },
+            y: Field {
+                byte: 4,
+                size: 2,
+                signed: false,
+                high_byte: Some(9),
+            },
+            pressure: Field {
+                byte: 6,
+                size: 2,
+                signed: false,
+                high_byte: None,
+            },
+            tilt_x: None,
+            tilt_y: None,
+        },
+        pad: Some(PadLayout {
+            select: vec![
+                Selector {
+                    byte: 0,
+                    mask: 0xff,
+                    value: 0x08,
+                },
+                Selector {
+                    byte: 1,
+                    mask: 0xff,
+                    value: 0xe0,
+                },
+            ],
+            buttons: Field {
+                byte: 4,
+                size: 2,
+                signed: false,
+                high_byte: None,
+            },
+            button_count: 12,
+            wheel: None,
+        }),
+    }]
+}
+
+/// 按 [`DeviceSpec`] 解析报告
+pub struct SpecParser {
+    spec: DeviceSpec,
+    pad: PadState,
+    pen_buttons: PenButton,
+}
+
+impl SpecParser {
+    pub fn new(spec: DeviceSpec) -> Self {
+        Self {
+            spec,
+            pad: PadState::default(),
+            pen_buttons: PenButton::default(),
+        }
+    }
+
+    pub fn spec(&self) -> &DeviceSpec {
+        &self.spec
+    }
+
+    pub fn parse(&mut self, data: &[u8]) -> Vec<TabletEvent> {
+        let pen = &self.spec.pen;
+        if pen.select.iter().all(|s| s.matches(data)) {
+            return self.parse_pen(data);
+        }
+        if let Some(pad) = &self.spec.pad
+            && pad.select.iter().all(|s| s.matches(data))
+        {
+            return self.pad.parse(pad, data);
+        }
+        Vec::new()
+    }
+
+    fn parse_pen(&mut self, data: &[u8]) -> Vec<TabletEvent> {
+        let pen = &self.spec.pen;
+        let caps = &self.spec.capabilities;
+        let tool = match pen.eraser {
+            Some(flag) if flag.read(data) => ToolType::Eraser,
+            _ => ToolType::Pen,
+        };
+        let (Some(x), Some(y), Some(pressure)) =
+            (pen.x.read(data), pen.y.read(data), pen.pressure.read(data))
+        else {
+            return Vec::new();
+        };
+        let pressure = (pressure.max(0) as u32).min(caps.max_pressure);
+        let tip = match pen.tip {
+            Some(flag) => flag.read(data),
+            None => pressure > 0,
+        };
+        let in_range = pen.in_range.read(data);
+        let location = if !in_range {
+            PenLocation::Leaved
+        } else if tip {
+            PenLocation::Pressed
+        } else {
+            PenLocation::Floating
+        };
+        let read_tilt = |field: Option<Field>| {
+            field
+                .and_then(|field| field.read(data))
+                .map_or(0, |value| value as i16)
+        };
+
+        let barrel =
+            |index: usize| in_range && pen.barrel.get(index).is_some_and(|flag| flag.read(data));
+        let buttons = PenButton {
+            lower: barrel(0),
+            upper: barrel(1),
+        };
+        let state = PenState {
+            x: (x.max(0) as u32).min(caps.max_x),
+            y: (y.max(0) as u32).min(caps.max_y),
+            pressure,
+            tilt: Tilt {
+                x: read_tilt(pen.tilt_x),
+                y: read_tilt(pen.tilt_y),
+            },
+            tool,
+            location,
+        };
+
+        let mut events = Vec::new();
+        if buttons != self.pen_buttons {
+            self.pen_buttons = buttons;
+            events.push(TabletEvent::PenButton(buttons));
+        }
+        events.push(TabletEvent::PenEvent(state));
+        events
+    }
+}
+
+/// 快捷键的状态, 报告只有当前按下的按键, 和上一次比较才能得出事件
+#[derive(Debug, Default)]
+pub struct PadState {
+    buttons: u64,
+}
+
+impl PadState {
+    pub fn parse(&mut self, pad: &PadLayout, data: &[u8]) -> Vec<TabletEvent> {
+        let mut events = Vec::new();
+
+        if let Some(buttons) = pad.buttons.read(data) {
+            let buttons = buttons as u64;
+            let changed = buttons ^ self.buttons;
+            for button_id in 0..pad.button_count.min(64) {
+                if changed & (1 << button_id) != 0 {
+                    events.push(TabletEvent::AuxButton(AuxButtonEvent {
+                        button_id,
+                        pressed: buttons & (1 << button_id) != 0,
+                    }));
+                }
+            }
+            self.buttons = buttons;
+        }
+
+        if let Some(wheel) = pad
+            .wheel
+            .and_then(|field| field.read(data))
+            .and_then(|delta| {
+                WheelEvent::from_delta(delta.clamp(i32::MIN.into(), i32::MAX.into()) as i32)
+            })
+        {
+            events.push(TabletEvent::Wheel(wheel));
+        }
+
+        events
+    }
+}
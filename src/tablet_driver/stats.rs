@@ -0,0 +1,191 @@
+//! 报告的统计
+//!
+//! 驱动每读到一个报告记录一次. 报告率比标称的低、间隔忽长忽短(抖动大)、笔在感应范围内时
+//! 突然一段时间没有报告(断档), 都说明报告在到达 tabletd 之前就出了问题, 是硬件或者连接方式
+//! (USB 线、蓝牙干扰)的原因; 统计正常而笔迹仍然有问题时再检查 tabletd 的设置.
+//! 统计可以用 `tabletctl stats` 或 HUD 的诊断面板查看
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::event_model::{
+    event::{PenLocation, TabletEvent},
+    stamp::monotonic_micros,
+    tablet::TabletId,
+};
+
+/// 用最近这么多个报告间隔计算报告率和抖动
+const WINDOW: usize = 128;
+/// 至少有这么多个间隔后才检测断档
+const MIN_SAMPLES: usize = 16;
+/// 间隔超过平均间隔的这个倍数时算作断档
+const GAP_FACTOR: f64 = 4.0;
+/// 超过这个时间(微秒)没有报告时报告率显示为 0
+const IDLE_US: u64 = 500_000;
+
+/// 一块数位板的报告统计
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReportStats {
+    pub tablet: TabletId,
+    pub name: String,
+    /// 这次连接读到的报告数
+    pub reports: u64,
+    /// 最近的报告率(Hz), 一段时间没有报告(比如笔已经离开)时为 0
+    pub rate_hz: f64,
+    /// 最近报告间隔的标准差(毫秒)
+    pub jitter_ms: f64,
+    /// 这次连接中笔在感应范围内时报告断档的次数
+    pub gaps: u64,
+    /// 驱动重新启动的次数, 比如设备断开后重新连接
+    pub resets: u64,
+    /// 最近一个报告的时间, `CLOCK_MONOTONIC` 的微秒数
+    pub last_report: u64,
+}
+
+impl ReportStats {
+    fn new(tablet: TabletId, name: &str) -> Self {
+        Self {
+            tablet,
+            name: name.to_string(),
+            reports: 0,
+            rate_hz: 0.0,
+            jitter_ms: 0.0,
+            gaps: 0,
+            resets: 0,
+            last_report: 0,
+        }
+    }
+}
+
+/// 所有数位板的统计, 驱动写入, 控制接口和 HUD 读取
+#[derive(Debug, Clone, Default)]
+pub struct DeviceStats {
+    devices: Arc<Mutex<HashMap<TabletId, ReportStats>>>,
+}
+
+impl DeviceStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 开始统计数位板的一次连接, 这块数位板之前连接过时算作一次重新启动
+    pub fn track(&self, tablet: TabletId, name: &str) -> StatsTracker {
+        let mut devices = self.devices.lock().unwrap();
+        let stats = match devices.get(&tablet) {
+            Some(previous) => ReportStats {
+                resets: previous.resets + 1,
+                ..ReportStats::new(tablet, name)
+            },
+            None => ReportStats::new(tablet, name),
+        };
+        devices.insert(tablet, stats.clone());
+        StatsTracker {
+            devices: self.clone(),
+            stats,
+            intervals: VecDeque::with_capacity(WINDOW),
+            last: None,
+            in_range: false,
+        }
+    }
+
+    /// 一块数位板当前的统计
+    pub fn get(&self, tablet: TabletId) -> Option<ReportStats> {
+        let stats = self.devices.lock().unwrap().get(&tablet).cloned()?;
+        Some(idle(stats, monotonic_micros()))
+    }
+
+    /// 所有连接过的数位板当前的统计, 按 ID 排序
+    pub fn snapshot(&self) -> Vec<ReportStats> {
+        let now = monotonic_micros();
+        let mut stats: Vec<_> = self
+            .devices
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .map(|stats| idle(stats, now))
+            .collect();
+        stats.sort_by_key(|stats| stats.tablet.0);
+        stats
+    }
+
+    fn publish(&self, stats: &ReportStats) {
+        self.devices
+            .lock()
+            .unwrap()
+            .insert(stats.tablet, stats.clone());
+    }
+}
+
+/// 很久没有报告时报告率为 0
+fn idle(mut stats: ReportStats, now: u64) -> ReportStats {
+    if now.saturating_sub(stats.last_report) > IDLE_US {
+        stats.rate_hz = 0.0;
+    }
+    stats
+}
+
+/// 一次连接的统计, 由驱动持有
+pub struct StatsTracker {
+    devices: DeviceStats,
+    stats: ReportStats,
+    /// 最近的报告间隔(微秒)
+    intervals: VecDeque<u64>,
+    last: Option<u64>,
+    /// 上一个报告之后笔是否在感应范围内, 不在时下一个间隔不计入
+    in_range: bool,
+}
+
+impl StatsTracker {
+    /// 记录在 `timestamp` 读到的报告和从它解析出的事件
+    pub fn record(&mut self, timestamp: u64, events: &[TabletEvent]) {
+        self.stats.reports += 1;
+        self.stats.last_report = timestamp;
+        if let Some(last) = self.last
+            && self.in_range
+        {
+            let interval = timestamp.saturating_sub(last);
+            match self.mean() {
+                Some(mean) if interval as f64 > mean * GAP_FACTOR => self.stats.gaps += 1,
+                _ => {
+                    if self.intervals.len() == WINDOW {
+                        self.intervals.pop_front();
+                    }
+                    self.intervals.push_back(interval);
+                }
+            }
+        }
+        self.last = Some(timestamp);
+        for event in events {
+            match event {
+                TabletEvent::PenEvent(pen) => self.in_range = pen.location != PenLocation::Leaved,
+                TabletEvent::ToolOut(_) => self.in_range = false,
+                _ => {}
+            }
+        }
+
+        if let Some(mean) = self.mean().filter(|mean| *mean > 0.0) {
+            let variance = self
+                .intervals
+                .iter()
+                .map(|interval| (*interval as f64 - mean).powi(2))
+                .sum::<f64>()
+                / self.intervals.len() as f64;
+            self.stats.rate_hz = 1e6 / mean;
+            self.stats.jitter_ms = variance.sqrt() / 1000.0;
+        }
+        self.devices.publish(&self.stats);
+    }
+
+    /// 平均间隔(微秒), 间隔太少时为 `None`
+    fn mean(&self) -> Option<f64> {
+        if self.intervals.len() < MIN_SAMPLES {
+            return None;
+        }
+        Some(self.intervals.iter().sum::<u64>() as f64 / self.intervals.len() as f64)
+    }
+}
@@ -0,0 +1,284 @@
+//! Wacom Intuos4 / Intuos5 / Intuos Pro (PTH-x51) 原生协议
+//!
+//! 直接解析 hidraw 报告，不依赖内核的 `wacom` 驱动.
+//! 报告格式参考 linux `drivers/hid/wacom_wac.c` 中的 `wacom_intuos_irq`
+
+use crate::event_model::{
+    capability::{DeviceCapabilities, DeviceClass},
+    event::{
+        AuxButtonEvent, PenButton, PenLocation, PenState, RingEvent, TabletEvent, Tilt, ToolId,
+        ToolType, WheelEvent,
+    },
+};
+
+/// Wacom 的 USB Vendor ID
+pub const WACOM_VENDOR_ID: u16 = 0x056a;
+
+/// 笔的报告
+const REPORT_PEN: u8 = 0x02;
+/// 快捷键和触控环的报告
+const REPORT_PAD: u8 = 0x0c;
+
+/// 触控环上的位置数量
+const RING_POSITIONS: i16 = 72;
+
+/// 快捷键报告的布局
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PadLayout {
+    Intuos4,
+    /// Intuos5 和 Intuos Pro
+    Intuos5,
+}
+
+/// 支持的型号
+#[derive(Debug, Clone, Copy)]
+pub struct IntuosModel {
+    pub product_id: u16,
+    pub name: &'static str,
+    pub max_x: u32,
+    pub max_y: u32,
+    pub max_pressure: u32,
+    pub pad: PadLayout,
+}
+
+impl IntuosModel {
+    pub fn capabilities(&self) -> DeviceCapabilities {
+        DeviceCapabilities {
+            max_x: self.max_x,
+            max_y: self.max_y,
+            // 5080 lpi
+            resolution_x: 200,
+            resolution_y: 200,
+            max_pressure: self.max_pressure,
+            tilt: true,
+            rotation: true,
+            eraser: true,
+            // 倾斜报告为 -64 ~ 63 度
+            max_tilt: 64,
+            class: DeviceClass::Tablet,
+        }
+    }
+}
+
+#[rustfmt::skip]
+pub const MODELS: &[IntuosModel] = &[
+    IntuosModel { product_id: 0x00b8, name: "Intuos4 S (PTK-440)", max_x: 31496, max_y: 19685, max_pressure: 2047, pad: PadLayout::Intuos4 },
+    IntuosModel { product_id: 0x00b9, name: "Intuos4 M (PTK-640)", max_x: 44704, max_y: 27940, max_pressure: 2047, pad: PadLayout::Intuos4 },
+    IntuosModel { product_id: 0x00ba, name: "Intuos4 L (PTK-840)", max_x: 65024, max_y: 40640, max_pressure: 2047, pad: PadLayout::Intuos4 },
+    IntuosModel { product_id: 0x0026, name: "Intuos5 touch S (PTH-450)", max_x: 31496, max_y: 19685, max_pressure: 2047, pad: PadLayout::Intuos5 },
+    IntuosModel { product_id: 0x0027, name: "Intuos5 touch M (PTH-650)", max_x: 44704, max_y: 27940, max_pressure: 2047, pad: PadLayout::Intuos5 },
+    IntuosModel { product_id: 0x0028, name: "Intuos5 touch L (PTH-850)", max_x: 65024, max_y: 40640, max_pressure: 2047, pad: PadLayout::Intuos5 },
+    IntuosModel { product_id: 0x0029, name: "Intuos5 S (PTK-450)", max_x: 31496, max_y: 19685, max_pressure: 2047, pad: PadLayout::Intuos5 },
+    IntuosModel { product_id: 0x002a, name: "Intuos5 M (PTK-650)", max_x: 44704, max_y: 27940, max_pressure: 2047, pad: PadLayout::Intuos5 },
+    IntuosModel { product_id: 0x0314, name: "Intuos Pro S (PTH-451)", max_x: 31496, max_y: 19685, max_pressure: 2047, pad: PadLayout::Intuos5 },
+    IntuosModel { product_id: 0x0315, name: "Intuos Pro M (PTH-651)", max_x: 44704, max_y: 27940, max_pressure: 2047, pad: PadLayout::Intuos5 },
+    IntuosModel { product_id: 0x0317, name: "Intuos Pro L (PTH-851)", max_x: 65024, max_y: 40640, max_pressure: 2047, pad: PadLayout::Intuos5 },
+];
+
+/// 查找支持的型号
+pub fn find_model(vendor_id: u16, product_id: u16) -> Option<&'static IntuosModel> {
+    if vendor_id != WACOM_VENDOR_ID {
+        return None;
+    }
+    MODELS.iter().find(|model| model.product_id == product_id)
+}
+
+/// 进入感应范围的笔
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IntuosTool {
+    /// 笔的硬件序列号
+    pub serial: u32,
+    /// 工具类型 ID，例如 Pro Pen 为 `0x802`，橡皮擦为 `0x80a`
+    pub tool_id: u32,
+}
+
+impl IntuosTool {
+    pub fn tool_type(&self) -> ToolType {
+        if self.tool_id & 0x8 != 0 {
+            ToolType::Eraser
+        } else {
+            ToolType::Pen
+        }
+    }
+}
+
+/// Intuos 协议解析器
+///
+/// 协议是有状态的: 笔进入感应范围时才报告工具类型，快捷键只报告当前状态，
+/// 所以需要记住上一次的状态才能得出事件
+pub struct IntuosParser {
+    model: IntuosModel,
+    tool: Option<IntuosTool>,
+    /// Art Pen 的旋转角度或喷枪的滚轮(0 ~ 1023)
+    rotation: Option<u16>,
+    pen_buttons: PenButton,
+    buttons: u16,
+    ring: Option<i16>,
+}
+
+impl IntuosParser {
+    pub fn new(model: IntuosModel) -> Self {
+        Self {
+            model,
+            tool: None,
+            rotation: None,
+            pen_buttons: PenButton::default(),
+            buttons: 0,
+            ring: None,
+        }
+    }
+
+    pub fn model(&self) -> &IntuosModel {
+        &self.model
+    }
+
+    /// 当前在感应范围内的笔
+    pub fn tool(&self) -> Option<IntuosTool> {
+        self.tool
+    }
+
+    /// Art Pen 的旋转角度或喷枪的滚轮
+    pub fn rotation(&self) -> Option<u16> {
+        self.rotation
+    }
+
+    /// 解析一个 HID 报告
+    pub fn parse(&mut self, report: &[u8]) -> Vec<TabletEvent> {
+        match report.first() {
+            Some(&REPORT_PEN) if report.len() >= 10 => self.parse_pen(report),
+            Some(&REPORT_PAD) if report.len() >= 6 => self.parse_pad(report),
+            _ => Vec::new(),
+        }
+    }
+
+    fn parse_pen(&mut self, data: &[u8]) -> Vec<TabletEvent> {
+        // 笔进入感应范围，报告序列号和工具类型
+        if data[1] & 0xfc == 0xc0 {
+            let serial = (data[3] as u32 & 0x0f) << 28
+                | (data[4] as u32) << 20
+                | (data[5] as u32) << 12
+                | (data[6] as u32) << 4
+                | (data[7] as u32) >> 4;
+            let tool_id = (data[2] as u32) << 4
+                | (data[3] as u32) >> 4
+                | (data[7] as u32 & 0x0f) << 16
+                | (data[8] as u32 & 0xf0) << 8;
+            self.tool = Some(IntuosTool { serial, tool_id });
+            return vec![TabletEvent::ToolIn(ToolId(serial))];
+        }
+
+        // 笔离开感应范围
+        if data[1] & 0xfe == 0x80 {
+            self.rotation = None;
+            let Some(tool) = self.tool.take() else {
+                return Vec::new();
+            };
+            let mut events = self.set_pen_buttons(PenButton::default());
+            events.push(TabletEvent::PenEvent(PenState {
+                x: 0,
+                y: 0,
+                pressure: 0,
+                tilt: Tilt { x: 0, y: 0 },
+                tool: tool.tool_type(),
+                location: PenLocation::Leaved,
+            }));
+            events.push(TabletEvent::ToolOut(ToolId(tool.serial)));
+            return events;
+        }
+
+        let Some(tool) = self.tool else {
+            // 没有收到进入感应范围的报告，不知道是什么工具
+            return Vec::new();
+        };
+
+        // Art Pen 旋转 / 喷枪滚轮
+        if data[1] & 0xbc == 0xb4 {
+            self.rotation = Some((data[6] as u16) << 2 | (data[7] as u16 >> 6) & 0x03);
+            return Vec::new();
+        }
+
+        // 普通的笔报告
+        if data[1] & 0xb8 != 0xa0 {
+            return Vec::new();
+        }
+        let x = (data[2] as u32) << 9 | (data[3] as u32) << 1 | (data[9] as u32 >> 1) & 1;
+        let y = (data[4] as u32) << 9 | (data[5] as u32) << 1 | data[9] as u32 & 1;
+        let pressure = (data[6] as u32) << 3 | (data[7] as u32 & 0xc0) >> 5 | data[1] as u32 & 0x01;
+        let tilt = Tilt {
+            x: (((data[7] as i16) << 1) & 0x7e | (data[8] as i16) >> 7) - 64,
+            y: (data[8] as i16 & 0x7f) - 64,
+        };
+
+        // 笔杆按键, 对应内核驱动的 BTN_STYLUS 和 BTN_STYLUS2
+        let mut events = self.set_pen_buttons(PenButton {
+            lower: data[1] & 0x02 != 0,
+            upper: data[1] & 0x04 != 0,
+        });
+        events.push(TabletEvent::PenEvent(PenState {
+            x: x.min(self.model.max_x),
+            y: y.min(self.model.max_y),
+            pressure: pressure.min(self.model.max_pressure),
+            tilt,
+            tool: tool.tool_type(),
+            location: if pressure > 0 {
+                PenLocation::Pressed
+            } else {
+                PenLocation::Floating
+            },
+        }));
+        events
+    }
+
+    /// 笔杆按键变化时产生事件
+    fn set_pen_buttons(&mut self, buttons: PenButton) -> Vec<TabletEvent> {
+        if buttons == self.pen_buttons {
+            return Vec::new();
+        }
+        self.pen_buttons = buttons;
+        vec![TabletEvent::PenButton(buttons)]
+    }
+
+    fn parse_pad(&mut self, data: &[u8]) -> Vec<TabletEvent> {
+        // 按键 0 是触控环中间的模式切换键，之后是 ExpressKey
+        let (buttons, ring) = match self.model.pad {
+            PadLayout::Intuos4 => ((data[3] as u16) << 1 | data[2] as u16 & 0x01, data[1]),
+            PadLayout::Intuos5 => ((data[4] as u16) << 1 | data[3] as u16 & 0x01, data[2]),
+        };
+
+        let mut events = Vec::new();
+        let changed = buttons ^ self.buttons;
+        for button_id in 0..9u8 {
+            if changed & (1 << button_id) != 0 {
+                events.push(TabletEvent::AuxButton(AuxButtonEvent {
+                    button_id,
+                    pressed: buttons & (1 << button_id) != 0,
+                }));
+            }
+        }
+        self.buttons = buttons;
+
+        // 最高位表示手指在触控环上
+        let ring = (ring & 0x80 != 0).then_some((ring & 0x7f) as i16);
+        if ring != self.ring {
+            events.push(TabletEvent::Ring(RingEvent {
+                ring: 0,
+                position: ring.map(|ring| ring as f32 / RING_POSITIONS as f32),
+            }));
+        }
+        if let (Some(previous), Some(current)) = (self.ring, ring) {
+            // 触控环是循环的，取最短的方向
+            let mut delta = current - previous;
+            if delta > RING_POSITIONS / 2 {
+                delta -= RING_POSITIONS;
+            } else if delta < -RING_POSITIONS / 2 {
+                delta += RING_POSITIONS;
+            }
+            if let Some(wheel) = WheelEvent::from_delta(delta as i32) {
+                events.push(TabletEvent::Wheel(wheel));
+            }
+        }
+        self.ring = ring;
+
+        events
+    }
+}
@@ -0,0 +1,263 @@
+//! 数值和单位的格式化
+//!
+//! HUD、命令行输出和 `tabletd API` 用同一套规则显示数值: 长度和面积按设置使用毫米或英寸,
+//! 延迟使用毫秒, 压力和音量使用百分比, 小数点按地区设置使用 `.` 或 `,`.
+//!
+//! 配置文件中没有设置时按环境变量(`LC_ALL`、`LC_NUMERIC`/`LC_MEASUREMENT`、`LANG`)推断,
+//! 美国等使用英制的地区默认使用英寸
+//!
+//! ```toml
+//! [units]
+//! locale = "de_DE"
+//! length = "inch"
+//! ```
+
+use std::{fmt, sync::RwLock, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Config, ConfigChange, transaction::ConfigStage};
+
+/// 每英寸的毫米数
+const MM_PER_INCH: f64 = 25.4;
+
+/// 长度的单位
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LengthUnit {
+    #[default]
+    Millimeter,
+    Inch,
+}
+
+impl LengthUnit {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            LengthUnit::Millimeter => "mm",
+            LengthUnit::Inch => "in",
+        }
+    }
+
+    /// 把毫米转换为这个单位
+    pub fn from_mm(self, mm: f64) -> f64 {
+        match self {
+            LengthUnit::Millimeter => mm,
+            LengthUnit::Inch => mm / MM_PER_INCH,
+        }
+    }
+
+    /// 显示时保留的小数位数
+    fn decimals(self) -> usize {
+        match self {
+            LengthUnit::Millimeter => 1,
+            LengthUnit::Inch => 2,
+        }
+    }
+}
+
+impl fmt::Display for LengthUnit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.symbol())
+    }
+}
+
+/// 配置文件中的单位设置, 没有设置的项按环境变量推断
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UnitConfig {
+    /// 地区, 例如 `zh_CN`、`de_DE`, 决定小数点的写法
+    pub locale: Option<String>,
+    /// 长度和面积的单位
+    pub length: Option<LengthUnit>,
+}
+
+/// 实际使用的单位
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Units {
+    /// 地区, 没有设置时为 `C`
+    pub locale: String,
+    pub length: LengthUnit,
+}
+
+impl Default for Units {
+    fn default() -> Self {
+        Self {
+            locale: "C".to_string(),
+            length: LengthUnit::Millimeter,
+        }
+    }
+}
+
+/// 小数点使用逗号的语言
+const COMMA_LANGUAGES: &[&str] = &[
+    "af", "az", "be", "bg", "ca", "cs", "da", "de", "el", "es", "et", "eu", "fi", "fo", "fr", "gl",
+    "hr", "hu", "hy", "id", "is", "it", "ka", "kk", "lt", "lv", "mk", "nb", "nl", "nn", "no", "pl",
+    "pt", "ro", "ru", "sk", "sl", "sq", "sr", "sv", "tr", "uk", "uz", "vi",
+];
+/// 上面的语言中小数点仍然使用 `.` 的地区
+const POINT_REGIONS: &[&str] = &[
+    "de_CH", "it_CH", "es_MX", "es_US", "es_PR", "es_DO", "es_GT", "es_HN", "es_NI", "es_PA",
+    "es_SV",
+];
+/// 使用英制单位的地区
+const IMPERIAL_REGIONS: &[&str] = &["US", "LR", "MM"];
+
+/// 去掉 `.UTF-8`、`@euro` 等后缀
+fn normalize(locale: &str) -> &str {
+    locale.split(['.', '@']).next().unwrap_or(locale)
+}
+
+/// 按顺序读取第一个非空的环境变量
+fn env_locale(names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .map(|value| normalize(&value).to_string())
+        .find(|value| !value.is_empty())
+}
+
+fn is_imperial(locale: &str) -> bool {
+    locale
+        .split_once('_')
+        .is_some_and(|(_, region)| IMPERIAL_REGIONS.contains(&region))
+}
+
+impl Units {
+    /// 按配置决定单位, 没有设置的项按环境变量推断
+    pub fn from_config(config: &UnitConfig) -> Self {
+        let locale = config
+            .locale
+            .as_deref()
+            .map(|locale| normalize(locale).to_string())
+            .or_else(|| env_locale(&["LC_ALL", "LC_NUMERIC", "LANG"]))
+            .unwrap_or_else(|| "C".to_string());
+        let length = config.length.unwrap_or_else(|| {
+            let measurement = config
+                .locale
+                .clone()
+                .or_else(|| env_locale(&["LC_ALL", "LC_MEASUREMENT", "LANG"]))
+                .unwrap_or_default();
+            if is_imperial(normalize(&measurement)) {
+                LengthUnit::Inch
+            } else {
+                LengthUnit::Millimeter
+            }
+        });
+        Self { locale, length }
+    }
+
+    /// 小数点
+    pub fn decimal_separator(&self) -> char {
+        let language = self.locale.split('_').next().unwrap_or_default();
+        if COMMA_LANGUAGES.contains(&language) && !POINT_REGIONS.contains(&self.locale.as_str()) {
+            ','
+        } else {
+            '.'
+        }
+    }
+
+    /// 保留 `decimals` 位小数
+    pub fn number(&self, value: f64, decimals: usize) -> String {
+        let text = format!("{value:.decimals$}");
+        match self.decimal_separator() {
+            '.' => text,
+            separator => text.replace('.', &separator.to_string()),
+        }
+    }
+
+    /// 长度, 参数为毫米
+    pub fn length(&self, mm: f64) -> String {
+        format!(
+            "{} {}",
+            self.number(self.length.from_mm(mm), self.length.decimals()),
+            self.length
+        )
+    }
+
+    /// 面积(宽 × 高), 参数为毫米
+    pub fn area(&self, (width, height): (f64, f64)) -> String {
+        let decimals = self.length.decimals();
+        format!(
+            "{} × {} {}",
+            self.number(self.length.from_mm(width), decimals),
+            self.number(self.length.from_mm(height), decimals),
+            self.length
+        )
+    }
+
+    /// 分辨率, 参数为每毫米的设备单位
+    pub fn resolution(&self, per_mm: f64) -> String {
+        match self.length {
+            LengthUnit::Millimeter => format!("{} lines/mm", self.number(per_mm, 0)),
+            LengthUnit::Inch => format!("{} lpi", self.number(per_mm * MM_PER_INCH, 0)),
+        }
+    }
+
+    /// 延迟或耗时, 以毫秒显示
+    pub fn latency(&self, duration: Duration) -> String {
+        self.latency_with(duration, 2)
+    }
+
+    /// 以毫秒显示, 保留 `decimals` 位小数
+    pub fn latency_with(&self, duration: Duration, decimals: usize) -> String {
+        format!(
+            "{} ms",
+            self.number(duration.as_secs_f64() * 1000.0, decimals)
+        )
+    }
+
+    /// 以秒显示
+    pub fn seconds(&self, duration: Duration, decimals: usize) -> String {
+        format!("{} s", self.number(duration.as_secs_f64(), decimals))
+    }
+
+    /// 比例(1.0 为 100%)
+    pub fn percent(&self, fraction: f64, decimals: usize) -> String {
+        format!("{}%", self.number(fraction * 100.0, decimals))
+    }
+
+    /// 设备单位的压力, `max` 为 0 时显示原始值
+    pub fn pressure(&self, pressure: u32, max: u32) -> String {
+        if max == 0 {
+            return pressure.to_string();
+        }
+        self.percent(pressure as f64 / max as f64, 0)
+    }
+}
+
+/// 进程中所有输出使用的单位, 由 [`set`] 修改
+static CURRENT: RwLock<Option<Units>> = RwLock::new(None);
+
+/// 当前使用的单位, 还没有设置时按环境变量推断
+pub fn current() -> Units {
+    if let Some(units) = CURRENT.read().unwrap().as_ref() {
+        return units.clone();
+    }
+    CURRENT
+        .write()
+        .unwrap()
+        .get_or_insert_with(|| Units::from_config(&UnitConfig::default()))
+        .clone()
+}
+
+/// 修改当前使用的单位
+pub fn set(units: Units) {
+    *CURRENT.write().unwrap() = Some(units);
+}
+
+/// 配置变化时更新 [`current`]
+#[derive(Debug, Default)]
+pub struct UnitStage;
+
+impl ConfigStage for UnitStage {
+    fn name(&self) -> &str {
+        "units"
+    }
+
+    fn apply(&mut self, config: &Config, change: &ConfigChange) -> anyhow::Result<()> {
+        if change.units {
+            set(Units::from_config(&config.units));
+        }
+        Ok(())
+    }
+}
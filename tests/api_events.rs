@@ -0,0 +1,282 @@
+//! 守护进程通过 `tabletd API` 发出的事件
+//!
+//! 和守护进程一样用 [`ApiServer::follow_devices`] 跟踪接入的数位板, 事件来自 [`pipeline::router`],
+//! 检查客户端收到了每块数位板的能力和按订阅转换的坐标, 以及另一台 tabletd 在只看模式下把笔迹
+//! 画在了 overlay 上. 订阅的过滤条件不会丢掉笔的状态变化
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tabletd::{
+    config::Config,
+    daemon::pipeline::{self, PipelineParts},
+    event_dispatcher::api::{
+        ApiServer, codec,
+        filter::{EventFilter, FilterState},
+        protocol::{ClientMessage, ServerMessage, Subscription},
+    },
+    event_model::{
+        capability::{DeviceCapabilities, DeviceClass},
+        coordinate::{CoordinateFormat, CoordinateSpace, Origin},
+        event::{PenLocation, PenState, TabletEvent, Tilt, ToolType},
+        stamp::EventStamp,
+        tablet::TabletId,
+    },
+    event_router::{InputEvent, RoutedEvent, Router},
+    hud_interface::{HudEvent, HudState},
+    input_devices::{
+        hotplug::{ConnectedDevice, DeviceEvent},
+        remote::{RemoteLink, RemoteMode, RemoteViewer},
+        transport::Transport,
+    },
+    mapping::{Mapper, OutputGeometry, geometry::GeometryBus},
+    screen_overlay::{canvas::Canvas, ink::InkLayer, layers::OverlayLayers},
+};
+use tokio::{
+    io::{DuplexStream, duplex},
+    sync::{broadcast, mpsc},
+};
+
+const TABLET: TabletId = TabletId(7);
+
+fn capabilities() -> DeviceCapabilities {
+    DeviceCapabilities {
+        max_x: 32767,
+        max_y: 32767,
+        resolution_x: 200,
+        resolution_y: 200,
+        max_pressure: 8191,
+        tilt: true,
+        rotation: false,
+        eraser: true,
+        max_tilt: 64,
+        class: DeviceClass::Tablet,
+    }
+}
+
+fn outputs() -> Vec<OutputGeometry> {
+    ["DP-1", "HDMI-A-1"]
+        .into_iter()
+        .enumerate()
+        .map(|(index, name)| OutputGeometry {
+            id: None,
+            name: name.to_string(),
+            x: index as f64 * 1920.0,
+            y: 0.0,
+            width: 1920,
+            height: 1080,
+            scale: 1.0,
+        })
+        .collect()
+}
+
+fn device() -> ConnectedDevice {
+    ConnectedDevice {
+        tablet: TABLET,
+        name: "Deco 01".to_string(),
+        transport: Transport::Usb,
+        capabilities: capabilities(),
+        path: PathBuf::new(),
+    }
+}
+
+/// 守护进程的路由器, 数位板映射到所有显示器上
+fn router(geometry: &GeometryBus) -> Router {
+    let (actions, _) = mpsc::unbounded_channel();
+    let mut router = pipeline::router(&PipelineParts::new(actions));
+    router.reconfigure(Arc::new(Config::default())).unwrap();
+    router.apply_geometry(&geometry.current());
+    router.connect(TABLET, Transport::Usb, &capabilities());
+    router
+}
+
+fn pressed(router: &mut Router, sequence: u64, x: u32, y: u32) -> RoutedEvent {
+    router
+        .route(InputEvent {
+            tablet: TABLET,
+            transport: Transport::Usb,
+            event: TabletEvent::PenEvent(PenState {
+                x,
+                y,
+                pressure: 4000,
+                tilt: Tilt { x: 0, y: 0 },
+                tool: ToolType::Pen,
+                location: PenLocation::Pressed,
+            }),
+            stamp: EventStamp {
+                timestamp: 1_000_000 + sequence * 5_000,
+                sequence,
+            },
+        })
+        .expect("数位板已接入")
+}
+
+/// 和守护进程一样接好的服务端和设备接入通知
+fn server() -> (ApiServer, GeometryBus, broadcast::Sender<DeviceEvent>) {
+    let geometry = GeometryBus::new();
+    geometry.publish(outputs());
+    let api = ApiServer::with_geometry(geometry.clone());
+    let (lifecycle, _) = broadcast::channel(16);
+    api.follow_devices(lifecycle.subscribe());
+    (api, geometry, lifecycle)
+}
+
+async fn recv(client: &mut DuplexStream) -> ServerMessage {
+    codec::read_frame(client)
+        .await
+        .unwrap()
+        .expect("服务端关闭了连接")
+}
+
+#[tokio::test]
+async fn subscriber_gets_capabilities_and_screen_position() {
+    let (api, geometry, lifecycle) = server();
+    let (mut client, stream) = duplex(64 * 1024);
+    api.spawn_client(stream);
+    assert!(matches!(recv(&mut client).await, ServerMessage::Hello(_)));
+
+    lifecycle.send(DeviceEvent::Connected(device())).unwrap();
+    match recv(&mut client).await {
+        ServerMessage::TabletAdded(info) => assert_eq!(info.id, TABLET),
+        other => panic!("应该先收到接入通知: {other:?}"),
+    }
+
+    let subscription = Subscription {
+        coordinates: CoordinateFormat {
+            space: CoordinateSpace::Screen {
+                output: "HDMI-A-1".to_string(),
+            },
+            origin: Origin::TopLeft,
+        },
+        ..Subscription::default()
+    };
+    codec::write_frame(&mut client, &ClientMessage::Subscribe(subscription))
+        .await
+        .unwrap();
+    codec::write_frame(&mut client, &ClientMessage::Ping(1))
+        .await
+        .unwrap();
+    let mut tablets = Vec::new();
+    loop {
+        match recv(&mut client).await {
+            ServerMessage::Capabilities(tablet, capabilities) => {
+                tablets.push((tablet, capabilities))
+            }
+            ServerMessage::Pong(1) => break,
+            _ => {}
+        }
+    }
+    assert_eq!(tablets, vec![(TABLET, capabilities())]);
+
+    // 右边四分之一落在第二个显示器中间
+    let routed = pressed(&mut router(&geometry), 1, 32767 * 3 / 4, 32767 / 2);
+    let point = routed.position.clone().expect("映射到了屏幕上");
+    assert_eq!(point.output, "HDMI-A-1");
+    api.publish(routed);
+    let event = loop {
+        if let ServerMessage::Event(event) = recv(&mut client).await {
+            break event;
+        }
+    };
+    assert_eq!(event.tablet, TABLET);
+    assert_eq!(event.position, Some((point.x, point.y)));
+}
+
+#[tokio::test]
+async fn view_only_link_draws_remote_ink() {
+    let (api, geometry, lifecycle) = server();
+    let address = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap()
+    };
+    api.serve_tcp(address).unwrap();
+    lifecycle.send(DeviceEvent::Connected(device())).unwrap();
+
+    // 和守护进程的 Remote 子系统一样接好
+    let ink = Arc::new(Mutex::new(InkLayer::new(None)));
+    let (hud, mut hud_rx) = mpsc::unbounded_channel();
+    let mut viewer =
+        RemoteViewer::new(Mapper::new(Config::default().defaults.mapping), ink.clone());
+    viewer.set_hud(hud.clone());
+    let mut link = RemoteLink::new(
+        address.to_string(),
+        RemoteMode::ViewOnly(Arc::new(Mutex::new(viewer))),
+    );
+    link.set_hud(hud);
+    link.follow_geometry(&geometry);
+    tokio::spawn(link.run(None));
+
+    // 订阅生效前发出的事件会被丢弃, 一直画到墨迹出现
+    let mut router = router(&geometry);
+    let drawn = tokio::time::timeout(Duration::from_secs(5), async {
+        for sequence in 1.. {
+            api.publish(pressed(&mut router, sequence, 32767 * 3 / 4, 32767 / 2));
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            if !ink.lock().unwrap().is_empty() {
+                break;
+            }
+        }
+    })
+    .await;
+    assert!(drawn.is_ok(), "只看模式没有画出远程的笔迹");
+    let mut redraws = 0;
+    while let Ok(event) = hud_rx.try_recv() {
+        if matches!(event, HudEvent::RemoteInkChanged) {
+            redraws += 1;
+        }
+    }
+    assert!(redraws > 0, "墨迹变化后需要请求重绘");
+
+    let mut state = HudState::new(Arc::default());
+    state.geometry = geometry.current();
+    let mut layers = OverlayLayers::new(Arc::new(Mutex::new(state)));
+    layers.set_ink(ink);
+    let drawn = outputs().iter().any(|output| {
+        let mut canvas = Canvas::for_output(output);
+        layers.render(output, &mut canvas);
+        canvas.data().iter().any(|byte| *byte > 0)
+    });
+    assert!(drawn, "overlay 上没有画出墨迹");
+}
+
+#[test]
+fn min_pressure_keeps_location_transitions() {
+    let filter = EventFilter {
+        min_pressure: Some(1000),
+        ..EventFilter::default()
+    };
+    let mut state = FilterState::default();
+    let now = Instant::now();
+    let pen = |location, pressure| RoutedEvent {
+        tablet: TABLET,
+        event: TabletEvent::PenEvent(PenState {
+            x: 100,
+            y: 100,
+            pressure,
+            tilt: Tilt { x: 0, y: 0 },
+            tool: ToolType::Pen,
+            location,
+        }),
+        bank: 0,
+        position: None,
+        consumed_by: None,
+        stamp: EventStamp::default(),
+        hud: None,
+        routed_at: 0,
+    };
+    let accepted: Vec<_> = [
+        (PenLocation::Pressed, 4000),
+        (PenLocation::Pressed, 200),
+        (PenLocation::Floating, 0),
+        (PenLocation::Floating, 0),
+        (PenLocation::Leaved, 0),
+    ]
+    .into_iter()
+    .map(|(location, pressure)| filter.accepts(&pen(location, pressure), &mut state, now))
+    .collect();
+    // 压力不够的移动被过滤, 抬笔和离开照常发送
+    assert_eq!(accepted, [true, false, true, false, true]);
+}
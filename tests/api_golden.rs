@@ -0,0 +1,424 @@
+//! `tabletd API` 的线上格式测试
+//!
+//! 把一组固定的消息编码后和 `tests/golden/v{PROTOCOL_VERSION}/` 中保存的字节比较，
+//! 再把保存的字节解码回来重新编码. 改变了线上格式的修改会让这些测试失败:
+//! 这时应该增加 `PROTOCOL_VERSION`, 把目录改成新版本的名字, 再用
+//! `UPDATE_GOLDEN=1 cargo test --test api_golden --test protocol_spec` 重新生成. 只保留当前版本的目录,
+//! 旧版本的格式可以从 git 历史中取得
+
+use std::{fmt::Write as _, fs, path::PathBuf};
+
+use serde::{Serialize, de::DeserializeOwned};
+use tabletd::{
+    error::{ErrorKind, ErrorReport},
+    event_dispatcher::api::{
+        codec::{self, PROTOCOL_VERSION},
+        filter::{EventFilter, EventKind},
+        protocol::{ApiEvent, ClientMessage, Handshake, ServerMessage, Subscription, TabletInfo},
+    },
+    event_model::{
+        capability::{DeviceCapabilities, DeviceClass},
+        coordinate::{CoordinateFormat, CoordinateSpace, Origin},
+        event::{
+            AuxButtonEvent, PenButton, PenLocation, PenState, TabletEvent, Tilt, ToolId, ToolType,
+            WheelDirection, WheelEvent,
+        },
+        stamp::EventStamp,
+        tablet::TabletId,
+        wire::{Envelope, SCHEMA_VERSION},
+    },
+    mapping::{OutputGeometry, geometry::GeometryChanged},
+    profile::sync::{ProfileStamp, SyncMessage, SyncedProfile},
+    units::{LengthUnit, Units},
+};
+
+fn golden_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(format!("v{PROTOCOL_VERSION}"))
+}
+
+/// 每行 16 个字节的十六进制文本, 方便在代码审查中看出哪里变了
+fn to_hex(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    for line in bytes.chunks(16) {
+        let line: Vec<_> = line.iter().map(|byte| format!("{byte:02x}")).collect();
+        writeln!(text, "{}", line.join(" ")).unwrap();
+    }
+    text
+}
+
+fn from_hex(text: &str) -> Vec<u8> {
+    text.split_whitespace()
+        .map(|byte| u8::from_str_radix(byte, 16).expect("无效的十六进制字节"))
+        .collect()
+}
+
+/// 编码 `message` 并和保存的字节比较, 然后检查保存的字节能解码并重新编码为相同的字节
+fn check<T: Serialize + DeserializeOwned>(name: &str, message: &T) {
+    let path = golden_dir().join(format!("{name}.hex"));
+    let encoded = codec::encode_frame(message).unwrap();
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(golden_dir()).unwrap();
+        fs::write(&path, to_hex(&encoded)).unwrap();
+    }
+
+    let text = fs::read_to_string(&path).unwrap_or_else(|e| {
+        panic!(
+            "无法读取 {}: {e}. 协议版本变化后需要用 UPDATE_GOLDEN=1 生成新的文件",
+            path.display()
+        )
+    });
+    let golden = from_hex(&text);
+    assert_eq!(
+        to_hex(&encoded),
+        to_hex(&golden),
+        "{name} 的编码和 {} 不一致, 修改线上格式时需要增加 PROTOCOL_VERSION",
+        path.display()
+    );
+
+    let decoded: T = codec::decode_frame(&golden).unwrap();
+    assert_eq!(codec::encode_frame(&decoded).unwrap(), golden, "{name}");
+}
+
+fn capabilities() -> DeviceCapabilities {
+    DeviceCapabilities {
+        max_x: 32767,
+        max_y: 32767,
+        resolution_x: 200,
+        resolution_y: 200,
+        max_pressure: 8191,
+        tilt: true,
+        rotation: false,
+        eraser: true,
+        max_tilt: 64,
+        class: DeviceClass::Tablet,
+    }
+}
+
+fn pen(location: PenLocation, pressure: u32) -> PenState {
+    PenState {
+        x: 12345,
+        y: 23456,
+        pressure,
+        tilt: Tilt { x: -12, y: 34 },
+        tool: ToolType::Pen,
+        location,
+    }
+}
+
+fn stamp() -> EventStamp {
+    EventStamp {
+        timestamp: 86_400_123_456,
+        sequence: 4321,
+    }
+}
+
+#[test]
+fn client_subscribe_default() {
+    check(
+        "client_subscribe_default",
+        &ClientMessage::Subscribe(Subscription::default()),
+    );
+}
+
+#[test]
+fn client_subscribe_filtered() {
+    let subscription = Subscription {
+        coordinates: CoordinateFormat {
+            space: CoordinateSpace::Screen {
+                output: "DP-1".to_string(),
+            },
+            origin: Origin::BottomLeft,
+        },
+        filter: EventFilter {
+            tablets: vec![TabletId(1), TabletId(300)],
+            kinds: vec![EventKind::Pen, EventKind::Wheel],
+            min_pressure: Some(100),
+            max_rate: Some(120),
+            skip_consumed: true,
+        },
+    };
+    check(
+        "client_subscribe_filtered",
+        &ClientMessage::Subscribe(subscription),
+    );
+}
+
+#[test]
+fn client_unsubscribe() {
+    check("client_unsubscribe", &ClientMessage::Unsubscribe);
+}
+
+#[test]
+fn server_hello() {
+    let hello = ServerMessage::Hello(Handshake {
+        version: PROTOCOL_VERSION,
+        tablets: vec![TabletInfo {
+            id: TabletId(1),
+            name: "Huion H640P".to_string(),
+            capabilities: capabilities(),
+        }],
+        units: Units {
+            locale: "de_DE".to_string(),
+            length: LengthUnit::Inch,
+        },
+    });
+    check("server_hello", &hello);
+}
+
+#[test]
+fn server_capabilities() {
+    check(
+        "server_capabilities",
+        &ServerMessage::Capabilities(TabletId(1), capabilities()),
+    );
+    check(
+        "server_capabilities_keypad",
+        &ServerMessage::Capabilities(TabletId(2), DeviceCapabilities::keypad()),
+    );
+}
+
+#[test]
+fn server_geometry() {
+    let geometry = GeometryChanged {
+        outputs: vec![
+            OutputGeometry {
+                id: None,
+                name: "eDP-1".to_string(),
+                x: 0.0,
+                y: 0.0,
+                width: 2880,
+                height: 1800,
+                scale: 1.5,
+            },
+            OutputGeometry {
+                id: None,
+                name: "DP-1".to_string(),
+                x: 1920.0,
+                y: -120.0,
+                width: 2560,
+                height: 1440,
+                scale: 1.0,
+            },
+        ],
+    };
+    check("server_geometry", &ServerMessage::Geometry(geometry));
+}
+
+#[test]
+fn server_event_pen() {
+    let event = ApiEvent {
+        tablet: TabletId(1),
+        event: TabletEvent::PenEvent(pen(PenLocation::Pressed, 4096)),
+        position: Some((0.25, 0.75)),
+        consumed: false,
+        stamp: stamp(),
+    };
+    check("server_event_pen", &ServerMessage::Event(event));
+}
+
+#[test]
+fn server_event_pen_consumed() {
+    let event = ApiEvent {
+        tablet: TabletId(2),
+        event: TabletEvent::PenEvent(pen(PenLocation::Floating, 0)),
+        position: None,
+        consumed: true,
+        stamp: stamp(),
+    };
+    check("server_event_pen_consumed", &ServerMessage::Event(event));
+}
+
+#[test]
+fn server_event_aux_button() {
+    let event = ApiEvent {
+        tablet: TabletId(1),
+        event: TabletEvent::AuxButton(AuxButtonEvent {
+            button_id: 3,
+            pressed: true,
+        }),
+        position: None,
+        consumed: false,
+        stamp: stamp(),
+    };
+    check("server_event_aux_button", &ServerMessage::Event(event));
+}
+
+#[test]
+fn server_event_wheel() {
+    let event = ApiEvent {
+        tablet: TabletId(1),
+        event: TabletEvent::Wheel(WheelEvent {
+            direction: WheelDirection::CounterClockwise,
+            steps: 3,
+        }),
+        position: None,
+        consumed: false,
+        stamp: stamp(),
+    };
+    check("server_event_wheel", &ServerMessage::Event(event));
+}
+
+#[test]
+fn server_event_tool_in() {
+    let event = ApiEvent {
+        tablet: TabletId(1),
+        event: TabletEvent::ToolIn(ToolId(0x0a1b2c3d)),
+        position: None,
+        consumed: false,
+        stamp: stamp(),
+    };
+    check("server_event_tool_in", &ServerMessage::Event(event));
+}
+
+#[test]
+fn server_event_tool_out() {
+    let event = ApiEvent {
+        tablet: TabletId(1),
+        event: TabletEvent::ToolOut(ToolId(0x0a1b2c3d)),
+        position: None,
+        consumed: false,
+        stamp: stamp(),
+    };
+    check("server_event_tool_out", &ServerMessage::Event(event));
+}
+
+#[test]
+fn server_event_pen_button() {
+    let event = ApiEvent {
+        tablet: TabletId(1),
+        event: TabletEvent::PenButton(PenButton {
+            upper: true,
+            lower: false,
+        }),
+        position: None,
+        consumed: true,
+        stamp: stamp(),
+    };
+    check("server_event_pen_button", &ServerMessage::Event(event));
+}
+
+#[test]
+fn client_ping() {
+    check("client_ping", &ClientMessage::Ping(70000));
+}
+
+#[test]
+fn server_pong() {
+    check("server_pong", &ServerMessage::Pong(70000));
+}
+
+#[test]
+fn server_tablet_added() {
+    let added = ServerMessage::TabletAdded(TabletInfo {
+        id: TabletId(2),
+        name: "Huion H640P".to_string(),
+        capabilities: capabilities(),
+    });
+    check("server_tablet_added", &added);
+}
+
+#[test]
+fn server_tablet_removed() {
+    check(
+        "server_tablet_removed",
+        &ServerMessage::TabletRemoved(TabletId(2)),
+    );
+}
+
+#[test]
+fn server_error() {
+    let report = ErrorReport {
+        kind: ErrorKind::Permission,
+        recoverable: false,
+        tablet: Some(TabletId(2)),
+        subsystem: Some("devices".to_string()),
+        message: "无法打开 /dev/hidraw3: Permission denied (os error 13)".to_string(),
+    };
+    check("server_error", &ServerMessage::Error(report));
+}
+
+#[test]
+fn client_auth() {
+    check(
+        "client_auth",
+        &ClientMessage::Auth("s3cret-token".to_string()),
+    );
+}
+
+#[test]
+fn client_sync_manifest() {
+    let manifest = SyncMessage::Manifest {
+        stamps: vec![
+            ProfileStamp {
+                name: "krita".to_string(),
+                modified: 1_760_000_000_000,
+                deleted: false,
+            },
+            ProfileStamp {
+                name: "old".to_string(),
+                modified: 1_750_000_000_000,
+                deleted: true,
+            },
+        ],
+        reply: true,
+    };
+    check("client_sync_manifest", &ClientMessage::Sync(manifest));
+}
+
+#[test]
+fn server_sync_profiles() {
+    let profiles = SyncMessage::Profiles(vec![SyncedProfile {
+        stamp: ProfileStamp {
+            name: "krita".to_string(),
+            modified: 1_760_000_000_000,
+            deleted: false,
+        },
+        content: Some("pen_up_delay_ms = 20\n".to_string()),
+    }]);
+    check("server_sync_profiles", &ServerMessage::Sync(profiles));
+}
+
+/// 新版本增加的事件种类和追加的字段不影响旧版本解码
+#[test]
+fn wire_tolerates_newer_events() {
+    let unknown = Envelope {
+        schema: SCHEMA_VERSION,
+        kind: 999,
+        payload: vec![1, 2, 3],
+    };
+    let decoded: TabletEvent =
+        postcard::from_bytes(&postcard::to_stdvec(&unknown).unwrap()).unwrap();
+    assert!(matches!(decoded, TabletEvent::Unknown));
+
+    let mut extended = TabletEvent::PenEvent(pen(PenLocation::Pressed, 100))
+        .to_envelope()
+        .unwrap();
+    extended.payload.extend([7, 7]);
+    let decoded: TabletEvent =
+        postcard::from_bytes(&postcard::to_stdvec(&extended).unwrap()).unwrap();
+    assert!(matches!(decoded, TabletEvent::PenEvent(state) if state.pressure == 100));
+
+    let future = Envelope {
+        schema: SCHEMA_VERSION + 1,
+        ..extended
+    };
+    assert!(postcard::from_bytes::<TabletEvent>(&postcard::to_stdvec(&future).unwrap()).is_err());
+}
+
+/// 只有方向的旧滚轮事件解码为一格
+#[test]
+fn wire_reads_wheel_without_steps() {
+    let old = Envelope::new(2, &WheelDirection::Clockwise).unwrap();
+    let decoded: TabletEvent = postcard::from_bytes(&postcard::to_stdvec(&old).unwrap()).unwrap();
+    assert!(matches!(
+        decoded,
+        TabletEvent::Wheel(WheelEvent {
+            direction: WheelDirection::Clockwise,
+            steps: 1,
+        })
+    ));
+}
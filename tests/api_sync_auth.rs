@@ -0,0 +1,87 @@
+//! `tabletd API` 上的设置同步需要先认证
+//!
+//! 客户端发送 [`ClientMessage::Auth`] 之前的同步请求被忽略, 口令错误时服务端断开连接
+
+use std::sync::Arc;
+
+use tabletd::{
+    event_dispatcher::api::{
+        ApiServer, codec,
+        protocol::{ClientMessage, ServerMessage},
+    },
+    profile::{
+        storage::FileStorage,
+        sync::{ProfileSync, SyncMessage},
+    },
+};
+use tokio::io::{DuplexStream, duplex};
+
+const TOKEN: &str = "s3cret-token";
+
+/// 开启了同步的服务端和一个已经读完握手的客户端连接. 服务端丢弃后连接随之关闭
+async fn connect(dir: &tempfile::TempDir) -> (ApiServer, DuplexStream) {
+    let mut api = ApiServer::new();
+    api.set_sync(
+        ProfileSync::new(Arc::new(FileStorage::new(dir.path()))),
+        TOKEN,
+    );
+    let (mut client, server) = duplex(64 * 1024);
+    api.spawn_client(server);
+    let hello = codec::read_frame::<_, ServerMessage>(&mut client)
+        .await
+        .unwrap();
+    assert!(matches!(hello, Some(ServerMessage::Hello(_))));
+    (api, client)
+}
+
+async fn send(client: &mut DuplexStream, message: ClientMessage) {
+    codec::write_frame(client, &message).await.unwrap();
+}
+
+fn manifest() -> ClientMessage {
+    ClientMessage::Sync(SyncMessage::Manifest {
+        stamps: Vec::new(),
+        reply: true,
+    })
+}
+
+#[tokio::test]
+async fn sync_is_ignored_before_auth() {
+    let dir = tempfile::tempdir().unwrap();
+    let (_api, mut client) = connect(&dir).await;
+    send(&mut client, manifest()).await;
+    send(&mut client, ClientMessage::Ping(1)).await;
+    // 同步请求没有回复, 下一条就是心跳的回应
+    let reply = codec::read_frame::<_, ServerMessage>(&mut client)
+        .await
+        .unwrap();
+    assert!(matches!(reply, Some(ServerMessage::Pong(1))));
+}
+
+#[tokio::test]
+async fn sync_is_answered_after_auth() {
+    let dir = tempfile::tempdir().unwrap();
+    let (_api, mut client) = connect(&dir).await;
+    send(&mut client, ClientMessage::Auth(TOKEN.to_string())).await;
+    send(&mut client, manifest()).await;
+    let reply = codec::read_frame::<_, ServerMessage>(&mut client)
+        .await
+        .unwrap();
+    assert!(matches!(
+        reply,
+        Some(ServerMessage::Sync(SyncMessage::Manifest {
+            reply: false,
+            ..
+        }))
+    ));
+}
+
+#[tokio::test]
+async fn wrong_token_closes_connection() {
+    let dir = tempfile::tempdir().unwrap();
+    let (_api, mut client) = connect(&dir).await;
+    send(&mut client, ClientMessage::Auth("guess".to_string())).await;
+    send(&mut client, manifest()).await;
+    let reply = codec::read_frame::<_, ServerMessage>(&mut client).await;
+    assert!(matches!(reply, Ok(None) | Err(_)));
+}
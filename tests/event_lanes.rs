@@ -0,0 +1,138 @@
+//! 驱动到路由器的变化通道和移动通道
+//!
+//! 移动通道满了以后丢弃的只能是移动, 状态变化(比如手指离开触控环)必须送到;
+//! 合并排着的移动时笔的位置和触控环的位置互不覆盖
+
+use tabletd::{
+    event_model::{
+        event::{AuxButtonEvent, PenLocation, PenState, RingEvent, TabletEvent, Tilt, ToolType},
+        stamp::EventStamp,
+        tablet::TabletId,
+    },
+    event_router::{
+        InputEvent,
+        fair::FairQueue,
+        lanes::{self, Lane},
+    },
+    input_devices::transport::Transport,
+};
+
+const TABLET: TabletId = TabletId(3);
+
+fn input(event: TabletEvent) -> InputEvent {
+    InputEvent {
+        tablet: TABLET,
+        transport: Transport::Usb,
+        event,
+        stamp: EventStamp::default(),
+    }
+}
+
+fn hover(x: u32) -> TabletEvent {
+    TabletEvent::PenEvent(PenState {
+        x,
+        y: 100,
+        pressure: 0,
+        tilt: Tilt { x: 0, y: 0 },
+        tool: ToolType::Pen,
+        location: PenLocation::Floating,
+    })
+}
+
+fn ring(position: Option<f32>) -> TabletEvent {
+    TabletEvent::Ring(RingEvent { ring: 0, position })
+}
+
+fn button() -> TabletEvent {
+    TabletEvent::AuxButton(AuxButtonEvent {
+        button_id: 1,
+        pressed: true,
+    })
+}
+
+/// 简短地描述事件, 方便比较顺序
+fn describe(event: &TabletEvent) -> String {
+    match event {
+        TabletEvent::PenEvent(pen) => format!("pen {}", pen.x),
+        TabletEvent::Ring(ring) => format!("ring {:?}", ring.position),
+        TabletEvent::AuxButton(button) => format!("button {}", button.button_id),
+        other => format!("{other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn ring_lift_survives_full_motion_lane() {
+    let (sender, mut receiver) = lanes::channel(4);
+    // 第一个悬浮是变化, 之后的都是移动, 移动通道很快就满了
+    for x in 0..16 {
+        sender.send(input(hover(x))).await.unwrap();
+    }
+    for step in 0..16 {
+        sender
+            .send(input(ring(Some(step as f32 / 16.0))))
+            .await
+            .unwrap();
+    }
+    sender.send(input(ring(None))).await.unwrap();
+
+    let mut received = Vec::new();
+    while let Some((lane, event)) = receiver.try_recv_lane() {
+        received.push((lane, event.event));
+    }
+    assert!(
+        matches!(
+            received.last(),
+            Some((
+                Lane::Transition,
+                TabletEvent::Ring(RingEvent { position: None, .. })
+            ))
+        ),
+        "手指离开触控环的事件丢失了: {:?}",
+        received
+            .iter()
+            .map(|(_, event)| describe(event))
+            .collect::<Vec<_>>()
+    );
+}
+
+#[tokio::test]
+async fn coalescing_keeps_pen_and_ring_motions() {
+    let (sender, mut receiver) = lanes::channel(16);
+    let events = [
+        hover(0),
+        hover(1),
+        ring(Some(0.25)),
+        hover(2),
+        ring(Some(0.5)),
+        hover(3),
+        button(),
+    ];
+    for event in events.iter().cloned() {
+        sender.send(input(event)).await.unwrap();
+    }
+    let mut received = Vec::new();
+    while let Some((_, event)) = receiver.try_recv_lane() {
+        received.push(describe(&event.event));
+    }
+    // 按钮之前的移动每种只留最后一个, 顺序不变
+    assert_eq!(received, ["pen 0", "ring Some(0.5)", "pen 3", "button 1"]);
+
+    let mut queue = FairQueue::new();
+    let lanes = [
+        Lane::Transition,
+        Lane::Motion,
+        Lane::Motion,
+        Lane::Motion,
+        Lane::Motion,
+        Lane::Motion,
+        Lane::Transition,
+    ];
+    for (lane, event) in lanes.into_iter().zip(events) {
+        queue.push(lane, input(event));
+    }
+    assert_eq!(queue.len(), 4);
+    let popped: Vec<_> = std::iter::from_fn(|| queue.pop())
+        .map(|event| describe(&event.event))
+        .collect();
+    assert_eq!(popped, ["pen 0", "ring Some(0.5)", "pen 3", "button 1"]);
+}
@@ -0,0 +1,171 @@
+//! 守护进程的 overlay 绘制
+//!
+//! 用 [`OverlayLayers`] 画出和守护进程相同的一帧, 检查 HUD 的提示画在了 HUD 所在的显示器上,
+//! 以及出口送来的笔事件移动了光标、演示模式的轨迹画在了 overlay 上
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tabletd::{
+    event_model::{
+        capability::{DeviceCapabilities, DeviceClass},
+        event::{PenLocation, PenState, TabletEvent, Tilt, ToolType},
+        stamp::EventStamp,
+        tablet::TabletId,
+    },
+    event_router::RoutedEvent,
+    hud_interface::{HudEvent, HudState, toast::ToastQueue},
+    input_devices::transport::Transport,
+    mapping::{OutputGeometry, ScreenPoint, geometry::GeometryChanged},
+    screen_overlay::{canvas::Canvas, layers::OverlayLayers, trail::LaserTrail},
+};
+
+fn output(name: &str, x: f64) -> OutputGeometry {
+    OutputGeometry {
+        id: None,
+        name: name.to_string(),
+        x,
+        y: 0.0,
+        width: 1280,
+        height: 720,
+        scale: 1.0,
+    }
+}
+
+/// 像素的不透明度, 画布是预乘 alpha 的 B, G, R, A
+fn alpha(canvas: &Canvas, x: u32, y: u32) -> u8 {
+    canvas.data()[(y * canvas.width() + x) as usize * 4 + 3]
+}
+
+/// (`x`, `y`) 周围 32 像素内画了东西, 悬浮的光标是空心的圆环
+fn drawn_near(canvas: &Canvas, x: u32, y: u32) -> bool {
+    (y - 32..y + 32).any(|py| (x - 32..x + 32).any(|px| alpha(canvas, px, py) > 0))
+}
+
+#[test]
+fn toast_is_drawn_on_hud_output() {
+    let mut state = HudState::new(Arc::default());
+    // 不淡入, 第一帧就完全显示
+    state.toasts = ToastQueue::new(3, Duration::from_secs(3), Duration::ZERO);
+    state.apply(HudEvent::GeometryChanged(GeometryChanged {
+        outputs: vec![output("DP-1", 0.0), output("HDMI-A-1", 1280.0)],
+    }));
+    state.apply(HudEvent::TabletConnected {
+        name: "Deco 01".to_string(),
+        transport: Transport::Usb,
+    });
+    let layers = OverlayLayers::new(Arc::new(Mutex::new(state)));
+
+    let primary = output("DP-1", 0.0);
+    let mut canvas = Canvas::for_output(&primary);
+    assert!(
+        layers.render(&primary, &mut canvas),
+        "提示显示期间需要继续绘制"
+    );
+    // 提示在右上角
+    assert!(alpha(&canvas, primary.width - 200, 40) > 0);
+    assert_eq!(alpha(&canvas, 200, 400), 0);
+
+    let secondary = output("HDMI-A-1", 1280.0);
+    let mut canvas = Canvas::for_output(&secondary);
+    assert!(!layers.render(&secondary, &mut canvas));
+    assert!(canvas.data().iter().all(|byte| *byte == 0));
+}
+
+fn capabilities() -> DeviceCapabilities {
+    DeviceCapabilities {
+        max_x: 32767,
+        max_y: 32767,
+        resolution_x: 200,
+        resolution_y: 200,
+        max_pressure: 8191,
+        tilt: true,
+        rotation: false,
+        eraser: true,
+        max_tilt: 64,
+        class: DeviceClass::Tablet,
+    }
+}
+
+/// 笔悬浮在 DP-1 的 (`x`, `y`) 像素处
+fn hover(tablet: TabletId, x: f64, y: f64) -> RoutedEvent {
+    RoutedEvent {
+        tablet,
+        event: TabletEvent::PenEvent(PenState {
+            x: 0,
+            y: 0,
+            pressure: 0,
+            tilt: Tilt { x: 0, y: 0 },
+            tool: ToolType::Pen,
+            location: PenLocation::Floating,
+        }),
+        bank: 0,
+        position: Some(ScreenPoint {
+            output: "DP-1".to_string(),
+            output_id: None,
+            x,
+            y,
+            logical_x: x,
+            logical_y: y,
+        }),
+        consumed_by: None,
+        stamp: EventStamp::default(),
+        hud: None,
+        routed_at: 0,
+    }
+}
+
+#[test]
+fn second_cursor_is_drawn_with_content() {
+    let mut state = HudState::new(Arc::default());
+    state.apply(HudEvent::GeometryChanged(GeometryChanged {
+        outputs: vec![output("DP-1", 0.0)],
+    }));
+    let layers = OverlayLayers::new(Arc::new(Mutex::new(state)));
+    {
+        let mut cursors = layers.cursors().lock().unwrap();
+        cursors.add(TabletId(1), capabilities(), None, None);
+        cursors.add(TabletId(2), capabilities(), None, None);
+    }
+    assert!(!layers.update_cursor(&hover(TabletId(1), 100.0, 100.0)));
+    assert!(!layers.update_cursor(&hover(TabletId(2), 600.0, 400.0)));
+
+    let primary = output("DP-1", 0.0);
+    let mut canvas = Canvas::for_output(&primary);
+    layers.render(&primary, &mut canvas);
+    // 第一个光标画在光标画布上, 内容上只有第二个
+    assert!(!drawn_near(&canvas, 100, 100));
+    assert!(drawn_near(&canvas, 600, 400));
+}
+
+#[test]
+fn laser_trail_is_drawn() {
+    let mut state = HudState::new(Arc::default());
+    state.apply(HudEvent::GeometryChanged(GeometryChanged {
+        outputs: vec![output("DP-1", 0.0), output("HDMI-A-1", 1280.0)],
+    }));
+    let trail = Arc::new(Mutex::new(LaserTrail::default()));
+    let mut layers = OverlayLayers::new(Arc::new(Mutex::new(state)));
+    layers.set_trail(trail.clone());
+    {
+        let mut trail = trail.lock().unwrap();
+        let now = Instant::now();
+        trail.push(1200.0, 300.0, now);
+        trail.push(1360.0, 300.0, now);
+    }
+
+    // 轨迹跨过两个显示器
+    let primary = output("DP-1", 0.0);
+    let mut canvas = Canvas::for_output(&primary);
+    assert!(
+        layers.render(&primary, &mut canvas),
+        "轨迹消失前需要继续绘制"
+    );
+    assert!(alpha(&canvas, 1250, 300) > 0);
+    let secondary = output("HDMI-A-1", 1280.0);
+    let mut canvas = Canvas::for_output(&secondary);
+    layers.render(&secondary, &mut canvas);
+    assert!(alpha(&canvas, 40, 300) > 0);
+}
@@ -0,0 +1,270 @@
+//! `tabletd API` 的协议说明
+//!
+//! 用 serde-reflection 从消息类型推导出协议说明, 写成 `tests/golden/v{PROTOCOL_VERSION}/` 中的
+//! `protocol.json`(给程序读)和 `protocol.md`(给人读), 再和保存的文件比较. 说明和编译出的
+//! 守护进程不一致时测试失败, 用 `UPDATE_GOLDEN=1 cargo test --test protocol_spec` 重新生成.
+//! 客户端的作者可以直接从这个目录中取用
+
+use std::{collections::BTreeMap, fmt::Write as _, fs, path::PathBuf};
+
+use serde::Serialize;
+use serde_reflection::{
+    ContainerFormat, Format, Named, Registry, Samples, Tracer, TracerConfig, VariantFormat,
+};
+use tabletd::{
+    error::ErrorKind,
+    event_dispatcher::api::{
+        codec::{MAX_FRAME_LEN, PROTOCOL_VERSION},
+        filter::EventKind,
+        protocol::{ClientMessage, ServerMessage},
+    },
+    event_model::{
+        capability::DeviceClass,
+        coordinate::{CoordinateSpace, Origin},
+        event::{PenLocation, TabletEvent, ToolType, WheelDirection},
+        wire::SCHEMA_VERSION,
+    },
+    profile::sync::SyncMessage,
+    units::LengthUnit,
+};
+
+fn golden_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(format!("v{PROTOCOL_VERSION}"))
+}
+
+/// 用 [`wire::Envelope`](tabletd::event_model::wire::Envelope) 包装的类型中, 每种 `kind` 的内容
+#[derive(Serialize)]
+struct EnvelopeKind {
+    kind: u16,
+    /// 对应的枚举成员, 内容的格式是这个成员的字段
+    variant: String,
+}
+
+/// 协议说明
+#[derive(Serialize)]
+struct Spec {
+    protocol_version: u16,
+    schema_version: u16,
+    max_frame_len: usize,
+    /// 客户端发送的消息
+    client: &'static str,
+    /// 服务端发送的消息
+    server: &'static str,
+    /// 在线上编码为 `Envelope` 的类型
+    envelopes: BTreeMap<&'static str, Vec<EnvelopeKind>>,
+    /// 所有用到的类型
+    types: Registry,
+}
+
+/// 追踪所有消息类型. 新增的枚举没有追踪时 `registry()` 会报告缺少成员, 需要加在这里
+fn trace() -> Spec {
+    // `Envelope` 的内容是各成员的默认表示, 所以追踪默认表示, 再单独列出 `kind`
+    let mut tracer = Tracer::new(TracerConfig::default().is_human_readable(true));
+    let samples = Samples::new();
+    tracer.trace_type::<ClientMessage>(&samples).unwrap();
+    tracer.trace_type::<ServerMessage>(&samples).unwrap();
+    tracer.trace_simple_type::<DeviceClass>().unwrap();
+    tracer.trace_simple_type::<CoordinateSpace>().unwrap();
+    tracer.trace_simple_type::<Origin>().unwrap();
+    tracer.trace_simple_type::<EventKind>().unwrap();
+    tracer.trace_simple_type::<LengthUnit>().unwrap();
+    tracer.trace_simple_type::<PenLocation>().unwrap();
+    tracer.trace_simple_type::<ToolType>().unwrap();
+    tracer.trace_simple_type::<WheelDirection>().unwrap();
+    tracer.trace_simple_type::<SyncMessage>().unwrap();
+    tracer.trace_simple_type::<ErrorKind>().unwrap();
+    let (_, events) = tracer.trace_simple_type::<TabletEvent>().unwrap();
+
+    let event_kinds = events
+        .iter()
+        .map(|event| {
+            let json = serde_json::to_value(event).unwrap();
+            let variant = match json {
+                serde_json::Value::String(name) => name,
+                serde_json::Value::Object(fields) => fields.keys().next().unwrap().clone(),
+                other => panic!("意外的事件表示 {other}"),
+            };
+            EnvelopeKind {
+                kind: event.kind(),
+                variant,
+            }
+        })
+        .collect();
+    let envelopes = BTreeMap::from([
+        ("TabletEvent", event_kinds),
+        (
+            "DeviceCapabilities",
+            vec![EnvelopeKind {
+                kind: 0,
+                variant: "DeviceCapabilities".to_string(),
+            }],
+        ),
+    ]);
+
+    Spec {
+        protocol_version: PROTOCOL_VERSION,
+        schema_version: SCHEMA_VERSION,
+        max_frame_len: MAX_FRAME_LEN,
+        client: "ClientMessage",
+        server: "ServerMessage",
+        envelopes,
+        types: tracer.registry().unwrap(),
+    }
+}
+
+fn format_name(format: &Format) -> String {
+    match format {
+        Format::Variable(_) => "?".to_string(),
+        Format::TypeName(name) => format!("[{name}](#{})", name.to_lowercase()),
+        Format::Unit => "()".to_string(),
+        Format::Bool => "bool".to_string(),
+        Format::I8 => "i8".to_string(),
+        Format::I16 => "i16".to_string(),
+        Format::I32 => "i32".to_string(),
+        Format::I64 => "i64".to_string(),
+        Format::I128 => "i128".to_string(),
+        Format::U8 => "u8".to_string(),
+        Format::U16 => "u16".to_string(),
+        Format::U32 => "u32".to_string(),
+        Format::U64 => "u64".to_string(),
+        Format::U128 => "u128".to_string(),
+        Format::F32 => "f32".to_string(),
+        Format::F64 => "f64".to_string(),
+        Format::Char => "char".to_string(),
+        Format::Str => "string".to_string(),
+        Format::Bytes => "bytes".to_string(),
+        Format::Option(inner) => format!("option<{}>", format_name(inner)),
+        Format::Seq(inner) => format!("seq<{}>", format_name(inner)),
+        Format::Map { key, value } => {
+            format!("map<{}, {}>", format_name(key), format_name(value))
+        }
+        Format::Tuple(formats) => format!("({})", format_list(formats)),
+        Format::TupleArray { content, size } => format!("[{}; {size}]", format_name(content)),
+    }
+}
+
+fn format_list(formats: &[Format]) -> String {
+    formats
+        .iter()
+        .map(format_name)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn field_table(text: &mut String, fields: &[Named<Format>]) {
+    writeln!(text, "| 字段 | 类型 |\n| --- | --- |").unwrap();
+    for field in fields {
+        writeln!(text, "| `{}` | {} |", field.name, format_name(&field.value)).unwrap();
+    }
+}
+
+/// 给人读的说明
+fn render(spec: &Spec) -> String {
+    let mut text = String::new();
+    writeln!(
+        text,
+        "# tabletd API 协议 v{}\n\n\
+         由 `tests/protocol_spec.rs` 从消息类型生成, 不要手动修改.\n\n\
+         每条消息是一帧: 4 字节大端长度, 之后是 [postcard](https://postcard.jamesmunns.com/wire-format) \
+         编码的消息, 一帧最长 {} 字节. 客户端发送 [{}](#{}), 服务端发送 [{}](#{}), \
+         连接建立后服务端先发送 `Hello`, 其中的 `version` 和 v{} 不一致时客户端应该断开.\n",
+        spec.protocol_version,
+        spec.max_frame_len,
+        spec.client,
+        spec.client.to_lowercase(),
+        spec.server,
+        spec.server.to_lowercase(),
+        spec.protocol_version,
+    )
+    .unwrap();
+
+    writeln!(
+        text,
+        "## Envelope\n\n\
+         下面的类型在线上编码为 `Envelope {{ schema: u16, kind: u16, payload: bytes }}`, \
+         `schema` 为 {}. `payload` 是 `kind` 对应成员的字段单独用 postcard 编码的结果, \
+         末尾可能有新版本追加的字段, 应该忽略; 不认识的 `kind` 当作 `Unknown`.\n",
+        spec.schema_version
+    )
+    .unwrap();
+    for (name, kinds) in &spec.envelopes {
+        writeln!(text, "### {name} 的 kind\n\n| kind | 成员 |\n| --- | --- |").unwrap();
+        for kind in kinds {
+            writeln!(text, "| {} | `{}` |", kind.kind, kind.variant).unwrap();
+        }
+        writeln!(text).unwrap();
+    }
+
+    writeln!(text, "## 类型\n").unwrap();
+    for (name, container) in &spec.types {
+        writeln!(text, "### {name}\n").unwrap();
+        match container {
+            ContainerFormat::UnitStruct => writeln!(text, "空结构体").unwrap(),
+            ContainerFormat::NewTypeStruct(inner) => {
+                writeln!(text, "等同于 {}", format_name(inner)).unwrap()
+            }
+            ContainerFormat::TupleStruct(formats) => {
+                writeln!(text, "元组 ({})", format_list(formats)).unwrap()
+            }
+            ContainerFormat::Struct(fields) => field_table(&mut text, fields),
+            ContainerFormat::Enum(variants) => {
+                writeln!(
+                    text,
+                    "枚举, 先编码成员序号(varint)\n\n| 序号 | 成员 | 内容 |\n| --- | --- | --- |"
+                )
+                .unwrap();
+                for (index, variant) in variants {
+                    let content = match &variant.value {
+                        VariantFormat::Variable(_) => "?".to_string(),
+                        VariantFormat::Unit => String::new(),
+                        VariantFormat::NewType(inner) => format_name(inner),
+                        VariantFormat::Tuple(formats) => format!("({})", format_list(formats)),
+                        VariantFormat::Struct(fields) => {
+                            let fields: Vec<_> = fields
+                                .iter()
+                                .map(|field| {
+                                    format!("`{}`: {}", field.name, format_name(&field.value))
+                                })
+                                .collect();
+                            format!("{{ {} }}", fields.join(", "))
+                        }
+                    };
+                    writeln!(text, "| {index} | `{}` | {content} |", variant.name).unwrap();
+                }
+            }
+        }
+        writeln!(text).unwrap();
+    }
+    text
+}
+
+/// 和保存的文件比较, 设置了 `UPDATE_GOLDEN` 时先写入
+fn check(name: &str, generated: &str) {
+    let path = golden_dir().join(name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(golden_dir()).unwrap();
+        fs::write(&path, generated).unwrap();
+    }
+    let saved = fs::read_to_string(&path).unwrap_or_else(|e| {
+        panic!(
+            "无法读取 {}: {e}. 用 UPDATE_GOLDEN=1 cargo test --test protocol_spec 生成",
+            path.display()
+        )
+    });
+    assert!(
+        saved == generated,
+        "{} 和消息类型不一致, 用 UPDATE_GOLDEN=1 重新生成. 线上格式变化时还需要增加 PROTOCOL_VERSION",
+        path.display()
+    );
+}
+
+#[test]
+fn protocol_spec_matches_types() {
+    let spec = trace();
+    let json = serde_json::to_string_pretty(&spec).unwrap() + "\n";
+    check("protocol.json", &json);
+    check("protocol.md", &render(&spec));
+}
@@ -0,0 +1,314 @@
+//! 守护进程的路由器
+//!
+//! 用 [`pipeline::router`] 创建和守护进程相同的路由器, 检查事件经过了映射、模式组、绑定和
+//! 演示模式, 同时通过 USB 和蓝牙接入时只用一个连接, 断开时松开按着的笔和按键, 以及新接入的
+//! 数位板的第一个事件不会丢失
+
+use std::{path::PathBuf, sync::Arc};
+
+use tabletd::{
+    config::Config,
+    daemon::pipeline::{self, PipelineParts},
+    event_model::{
+        capability::{DeviceCapabilities, DeviceClass},
+        event::{AuxButtonEvent, PenButton, PenLocation, PenState, TabletEvent, Tilt, ToolType},
+        stamp::EventStamp,
+        tablet::TabletId,
+    },
+    event_router::{
+        InputEvent, RoutedEvent, Router, bindings::Triggered, lanes, mode_bank::ModeBankConfig,
+    },
+    input_devices::{
+        hotplug::{ConnectedDevice, DeviceEvent},
+        transport::Transport,
+    },
+    mapping::{OutputGeometry, geometry::GeometryChanged},
+    profile::binding::{Action, Binding},
+};
+use tokio::sync::{broadcast, mpsc};
+
+const TABLET: TabletId = TabletId(7);
+
+fn capabilities() -> DeviceCapabilities {
+    DeviceCapabilities {
+        max_x: 32767,
+        max_y: 32767,
+        resolution_x: 200,
+        resolution_y: 200,
+        max_pressure: 8191,
+        tilt: true,
+        rotation: false,
+        eraser: true,
+        max_tilt: 64,
+        class: DeviceClass::Tablet,
+    }
+}
+
+fn config() -> Config {
+    let mut config = Config::default();
+    config.defaults.mode_bank = Some(ModeBankConfig {
+        switch_button: 0,
+        bank_count: 2,
+    });
+    config.defaults.bindings = vec![
+        Binding {
+            button: 1,
+            bank: None,
+            action: Action::ToggleHistory,
+            confirm: false,
+        },
+        Binding {
+            button: 2,
+            bank: Some(1),
+            action: Action::ToggleDiagnostics,
+            confirm: false,
+        },
+    ];
+    config
+}
+
+/// 接入了一块数位板和一个显示器的路由器
+fn router() -> (Router, mpsc::UnboundedReceiver<Triggered>) {
+    let (actions, rx) = mpsc::unbounded_channel();
+    let mut router = pipeline::router(&PipelineParts::new(actions));
+    router.reconfigure(Arc::new(config())).unwrap();
+    router.apply_geometry(&GeometryChanged {
+        outputs: vec![OutputGeometry {
+            id: None,
+            name: "DP-1".to_string(),
+            x: 0.0,
+            y: 0.0,
+            width: 1920,
+            height: 1080,
+            scale: 1.0,
+        }],
+    });
+    router.connect(TABLET, Transport::Usb, &capabilities());
+    (router, rx)
+}
+
+fn route_from(
+    router: &mut Router,
+    transport: Transport,
+    sequence: u64,
+    event: TabletEvent,
+) -> Option<RoutedEvent> {
+    router.route(InputEvent {
+        tablet: TABLET,
+        transport,
+        event,
+        stamp: EventStamp {
+            timestamp: 1_000_000 + sequence * 5_000,
+            sequence,
+        },
+    })
+}
+
+fn route(router: &mut Router, sequence: u64, event: TabletEvent) -> RoutedEvent {
+    route_from(router, Transport::Usb, sequence, event).expect("数位板已接入")
+}
+
+fn button(button_id: u8, pressed: bool) -> TabletEvent {
+    TabletEvent::AuxButton(AuxButtonEvent { button_id, pressed })
+}
+
+fn pen(location: PenLocation) -> TabletEvent {
+    TabletEvent::PenEvent(PenState {
+        x: 32767 / 2,
+        y: 32767 / 2,
+        pressure: 0,
+        tilt: Tilt { x: 0, y: 0 },
+        tool: ToolType::Pen,
+        location,
+    })
+}
+
+#[test]
+fn pen_is_mapped_to_screen() {
+    let (mut router, _actions) = router();
+    let routed = route(&mut router, 1, pen(PenLocation::Floating));
+    assert!(!routed.is_consumed());
+    let position = routed.position.expect("笔的位置应该映射到屏幕");
+    assert_eq!(position.output, "DP-1");
+}
+
+#[test]
+fn binding_triggers_action() {
+    let (mut router, mut actions) = router();
+    let routed = route(&mut router, 1, button(1, true));
+    assert_eq!(routed.consumed_by.as_deref(), Some("bindings"));
+    assert_eq!(actions.try_recv().unwrap().action, Action::ToggleHistory);
+    let routed = route(&mut router, 2, button(1, false));
+    assert_eq!(routed.consumed_by.as_deref(), Some("bindings"));
+    assert!(actions.try_recv().is_err());
+}
+
+#[test]
+fn mode_bank_selects_bindings() {
+    let (mut router, mut actions) = router();
+    // 第 0 组没有按键 2 的绑定, 按键照常传给系统
+    let routed = route(&mut router, 1, button(2, true));
+    assert_eq!(routed.bank, 0);
+    assert!(!routed.is_consumed());
+    route(&mut router, 2, button(2, false));
+
+    let routed = route(&mut router, 3, button(0, true));
+    assert_eq!(routed.consumed_by.as_deref(), Some("mode-bank"));
+    route(&mut router, 4, button(0, false));
+
+    let routed = route(&mut router, 5, button(2, true));
+    assert_eq!(routed.bank, 1);
+    assert_eq!(routed.consumed_by.as_deref(), Some("bindings"));
+    assert_eq!(
+        actions.try_recv().unwrap().action,
+        Action::ToggleDiagnostics
+    );
+}
+
+#[test]
+fn bluetooth_is_used_after_usb_unplugged() {
+    let (mut router, _actions) = router();
+    router.connect(TABLET, Transport::Bluetooth, &capabilities());
+    // USB 优先, 同一块数位板从蓝牙来的事件丢弃
+    assert!(
+        route_from(
+            &mut router,
+            Transport::Bluetooth,
+            1,
+            pen(PenLocation::Floating)
+        )
+        .is_none()
+    );
+    assert!(route_from(&mut router, Transport::Usb, 2, pen(PenLocation::Floating)).is_some());
+
+    assert!(router.disconnect(TABLET, Transport::Usb).is_empty());
+    let routed = route_from(
+        &mut router,
+        Transport::Bluetooth,
+        3,
+        pen(PenLocation::Floating),
+    )
+    .expect("切换到蓝牙");
+    assert_eq!(routed.position.expect("蓝牙的事件同样映射").output, "DP-1");
+}
+
+#[test]
+fn lost_connection_releases_held_buttons() {
+    let (mut router, _actions) = router();
+    route(&mut router, 1, pen(PenLocation::Pressed));
+    route(
+        &mut router,
+        2,
+        TabletEvent::PenButton(PenButton {
+            upper: false,
+            lower: true,
+        }),
+    );
+    assert!(!route(&mut router, 3, button(3, true)).is_consumed());
+
+    let releases = router.disconnect(TABLET, Transport::Usb);
+    let events: Vec<_> = releases.iter().map(|routed| &routed.event).collect();
+    assert!(
+        matches!(
+            events[..],
+            [
+                TabletEvent::PenEvent(PenState {
+                    location: PenLocation::Leaved,
+                    ..
+                }),
+                TabletEvent::PenButton(PenButton {
+                    upper: false,
+                    lower: false,
+                }),
+                TabletEvent::AuxButton(AuxButtonEvent {
+                    button_id: 3,
+                    pressed: false,
+                }),
+            ]
+        ),
+        "断开时应该松开笔和所有按键: {events:?}"
+    );
+    assert!(
+        releases
+            .windows(2)
+            .all(|pair| pair[0].stamp.sequence < pair[1].stamp.sequence)
+    );
+}
+
+#[test]
+fn hover_only_records_trail_on_screen() {
+    let (actions, _actions) = mpsc::unbounded_channel();
+    let parts = PipelineParts::new(actions);
+    let mut router = pipeline::router(&parts);
+    let mut config = config();
+    config.defaults.hover_only = true;
+    router.reconfigure(Arc::new(config)).unwrap();
+    router.apply_geometry(&GeometryChanged {
+        outputs: vec![OutputGeometry {
+            id: None,
+            name: "DP-1".to_string(),
+            x: 0.0,
+            y: 0.0,
+            width: 1920,
+            height: 1080,
+            scale: 1.0,
+        }],
+    });
+    router.connect(TABLET, Transport::Usb, &capabilities());
+
+    let routed = route(&mut router, 1, pen(PenLocation::Pressed));
+    let TabletEvent::PenEvent(pen) = &routed.event else {
+        panic!("笔的事件");
+    };
+    assert!(matches!(pen.location, PenLocation::Floating));
+    let trail = parts.trail.lock().unwrap();
+    let (point, _) = trail
+        .visible(std::time::Instant::now())
+        .next()
+        .expect("按下的位置记录为轨迹");
+    // 数位板中心映射到屏幕中心
+    assert!((point.x - 960.0).abs() < 1.0 && (point.y - 540.0).abs() < 1.0);
+}
+
+#[tokio::test]
+async fn new_tablet_is_connected_before_its_first_event() {
+    let (actions, _actions) = mpsc::unbounded_channel();
+    let mut router = pipeline::router(&PipelineParts::new(actions));
+    router.reconfigure(Arc::new(config())).unwrap();
+    let (lifecycle, devices) = broadcast::channel(128);
+    router.follow_devices(devices);
+
+    // 和驱动一样先通知接入再发送事件, 路由器开始运行时两边都已经到达
+    let (events, mut input) = lanes::channel(128);
+    for id in 0..64 {
+        let tablet = TabletId(100 + id);
+        lifecycle
+            .send(DeviceEvent::Connected(ConnectedDevice {
+                tablet,
+                name: format!("tablet {id}"),
+                transport: Transport::Usb,
+                capabilities: capabilities(),
+                path: PathBuf::new(),
+            }))
+            .unwrap();
+        events
+            .send(InputEvent {
+                tablet,
+                transport: Transport::Usb,
+                event: button(5, true),
+                stamp: EventStamp::default(),
+            })
+            .await
+            .unwrap();
+    }
+    drop(events);
+
+    let (output, mut routed) = mpsc::channel(128);
+    router.run_with(&mut input, output).await;
+    let mut tablets = Vec::new();
+    while let Some(event) = routed.recv().await {
+        tablets.push(event.tablet.0);
+    }
+    tablets.sort();
+    assert_eq!(tablets, (100..164).collect::<Vec<_>>());
+}